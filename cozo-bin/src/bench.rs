@@ -0,0 +1,415 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use clap::{Args, ValueEnum};
+use itertools::Itertools;
+use miette::{bail, IntoDiagnostic};
+use rand::Rng;
+use serde_json::{json, Value};
+
+use cozo::{DataValue, DbInstance, ScriptMutability};
+
+#[derive(Args, Debug)]
+pub(crate) struct BenchArgs {
+    /// A replay log to run, as written by the server with `--replay-log`.
+    /// If not given, a synthetic workload is generated instead.
+    log: Option<String>,
+
+    /// The synthetic workload to generate when no log is given
+    #[clap(short, long, value_enum, default_value_t = Workload::Graph)]
+    workload: Workload,
+
+    /// The number of nodes or vectors in the synthetic workload
+    #[clap(long, default_value_t = 10000)]
+    scale: usize,
+
+    /// The number of queries of the synthetic workload
+    #[clap(short, long, default_value_t = 10000)]
+    queries: usize,
+
+    /// The number of queries run first and left out of the report
+    #[clap(long, default_value_t = 100)]
+    warmup: usize,
+
+    /// The number of threads running queries. With more than one thread, the scripts
+    /// of a log no longer run in the order they were recorded.
+    #[clap(short, long, default_value_t = 1)]
+    threads: usize,
+
+    /// Database engine, can be `mem`, `sqlite`, `rocksdb` and others.
+    #[clap(short, long, default_value_t = String::from("mem"))]
+    engine: String,
+
+    /// Path to the directory to store the database
+    #[clap(short, long, default_value_t = String::from("cozo.db"))]
+    path: String,
+
+    /// Restore from the specified backup before running a log, usually one taken when
+    /// recording started
+    #[clap(long)]
+    restore: Option<String>,
+
+    /// Extra config in JSON format
+    #[clap(short, long, default_value_t = String::from("{}"))]
+    config: String,
+
+    /// Print the report as JSON, for comparing runs with scripts
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Workload {
+    /// Point lookups, neighbours and two-hop traversals over a random graph
+    Graph,
+    /// Nearest neighbour searches over random vectors with an HNSW index
+    Vector,
+}
+
+/// A script to time, with the kind it is reported under
+struct BenchQuery {
+    kind: String,
+    script: String,
+    params: BTreeMap<String, DataValue>,
+    read_only: bool,
+}
+
+/// The fields of a replay log entry needed to run it again
+#[derive(serde_derive::Deserialize)]
+struct LoggedScript {
+    script: String,
+    params: BTreeMap<String, DataValue>,
+    read_only: bool,
+}
+
+const VECTOR_DIM: usize = 32;
+
+/// Run a workload against a database, printing latency percentiles and throughput
+/// for each kind of query
+pub(crate) fn bench_main(args: BenchArgs) -> miette::Result<()> {
+    if args.threads == 0 {
+        bail!("at least one thread is needed")
+    }
+    if args.log.is_none() && args.scale == 0 {
+        bail!("the scale of a synthetic workload must be positive")
+    }
+    let db = DbInstance::new(&args.engine, &args.path, &args.config)?;
+    let queries = match &args.log {
+        Some(log) => {
+            if let Some(p) = &args.restore {
+                db.restore_backup(p)?;
+            }
+            load_log(log)?
+        }
+        None => {
+            eprintln!(
+                "Loading the {:?} workload at scale {}",
+                args.workload, args.scale
+            );
+            match args.workload {
+                Workload::Graph => graph_workload(&db, args.scale, args.queries)?,
+                Workload::Vector => vector_workload(&db, args.scale, args.queries)?,
+            }
+        }
+    };
+    let warmup = args.warmup.min(queries.len());
+    run_queries(&db, &queries[..warmup], args.threads);
+    let started = Instant::now();
+    let timings = run_queries(&db, &queries[warmup..], args.threads);
+    let report = Report::new(timings, started.elapsed());
+    if args.json {
+        println!("{}", report.to_json());
+    } else {
+        report.print();
+    }
+    Ok(())
+}
+
+/// The scripts of a replay log, reported by their first line
+fn load_log(path: &str) -> miette::Result<Vec<BenchQuery>> {
+    let reader = BufReader::new(File::open(path).into_diagnostic()?);
+    let mut queries = vec![];
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.into_diagnostic()?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: LoggedScript = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(err) => bail!("line {} of {path} is not a recorded script: {err}", idx + 1),
+        };
+        let kind = entry
+            .script
+            .lines()
+            .map(|l| l.trim())
+            .find(|l| !l.is_empty())
+            .unwrap_or_default()
+            .chars()
+            .take(40)
+            .collect();
+        queries.push(BenchQuery {
+            kind,
+            script: entry.script,
+            params: entry.params,
+            read_only: entry.read_only,
+        });
+    }
+    Ok(queries)
+}
+
+fn graph_workload(db: &DbInstance, scale: usize, n: usize) -> miette::Result<Vec<BenchQuery>> {
+    let mut rng = rand::thread_rng();
+    db.run_default(":create node {id: Int => name: String}")?;
+    db.run_default(":create edge {fr: Int, to: Int}")?;
+    let nodes = (0..scale)
+        .map(|i| {
+            DataValue::List(vec![
+                DataValue::from(i as i64),
+                DataValue::from(format!("n{i}")),
+            ])
+        })
+        .collect_vec();
+    put_rows(db, "?[id, name] <- $rows :put node {id => name}", nodes)?;
+    let edges = (0..scale * 8)
+        .map(|_| {
+            DataValue::List(vec![
+                DataValue::from(rng.gen_range(0..scale) as i64),
+                DataValue::from(rng.gen_range(0..scale) as i64),
+            ])
+        })
+        .collect_vec();
+    put_rows(db, "?[fr, to] <- $rows :put edge {fr, to}", edges)?;
+
+    let kinds = [
+        ("point", "?[name] := *node{id: $id, name}"),
+        ("neighbours", "?[to] := *edge{fr: $id, to}"),
+        (
+            "two_hop",
+            "?[count(c)] := *edge{fr: $id, to: b}, *edge{fr: b, to: c}",
+        ),
+    ];
+    Ok((0..n)
+        .map(|i| {
+            let (kind, script) = kinds[i % kinds.len()];
+            BenchQuery {
+                kind: kind.to_string(),
+                script: script.to_string(),
+                params: BTreeMap::from([(
+                    "id".to_string(),
+                    DataValue::from(rng.gen_range(0..scale) as i64),
+                )]),
+                read_only: true,
+            }
+        })
+        .collect())
+}
+
+fn vector_workload(db: &DbInstance, scale: usize, n: usize) -> miette::Result<Vec<BenchQuery>> {
+    let mut rng = rand::thread_rng();
+    let mut random_vec = || {
+        DataValue::List(
+            (0..VECTOR_DIM)
+                .map(|_| DataValue::from(rng.gen::<f64>()))
+                .collect(),
+        )
+    };
+    db.run_default(&format!(
+        ":create item {{id: Int => v: <F32; {VECTOR_DIM}>}}"
+    ))?;
+    db.run_default(&format!(
+        "::hnsw create item:vec {{dim: {VECTOR_DIM}, m: 16, dtype: F32, fields: [v], \
+         distance: L2, ef_construction: 50}}"
+    ))?;
+    let items = (0..scale)
+        .map(|i| DataValue::List(vec![DataValue::from(i as i64), random_vec()]))
+        .collect_vec();
+    put_rows(db, "?[id, v] <- $rows :put item {id => v}", items)?;
+
+    Ok((0..n)
+        .map(|_| BenchQuery {
+            kind: "knn".to_string(),
+            script: "?[id, dist] := ~item:vec{id | query: q, k: 10, ef: 50, bind_distance: dist}, \
+                     q = vec($q)"
+                .to_string(),
+            params: BTreeMap::from([("q".to_string(), random_vec())]),
+            read_only: true,
+        })
+        .collect())
+}
+
+/// Insert rows in batches, so that loading a large workload does not build one huge transaction
+fn put_rows(db: &DbInstance, script: &str, rows: Vec<DataValue>) -> miette::Result<()> {
+    for chunk in rows.chunks(10000) {
+        let params = BTreeMap::from([("rows".to_string(), DataValue::List(chunk.to_vec()))]);
+        db.run_script(script, params, ScriptMutability::Mutable)?;
+    }
+    Ok(())
+}
+
+/// How long a query took, and whether it failed
+struct Timing {
+    kind: String,
+    elapsed: Duration,
+    failed: bool,
+}
+
+/// Run the queries on worker threads taking them in order, returning their timings
+fn run_queries(db: &DbInstance, queries: &[BenchQuery], threads: usize) -> Vec<Timing> {
+    let next = AtomicUsize::new(0);
+    let timings = Mutex::new(Vec::with_capacity(queries.len()));
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| loop {
+                let Some(query) = queries.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                    break;
+                };
+                let started = Instant::now();
+                let res = db.run_script(
+                    &query.script,
+                    query.params.clone(),
+                    if query.read_only {
+                        ScriptMutability::Immutable
+                    } else {
+                        ScriptMutability::Mutable
+                    },
+                );
+                let timing = Timing {
+                    kind: query.kind.clone(),
+                    elapsed: started.elapsed(),
+                    failed: res.is_err(),
+                };
+                timings.lock().unwrap().push(timing);
+            });
+        }
+    });
+    timings.into_inner().unwrap()
+}
+
+/// Latencies of one kind of query, in milliseconds
+struct KindReport {
+    kind: String,
+    count: usize,
+    errors: usize,
+    mean: f64,
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+}
+
+struct Report {
+    total: usize,
+    errors: usize,
+    secs: f64,
+    kinds: Vec<KindReport>,
+}
+
+/// The value at the percentile `p` of sorted values, by the nearest rank
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.;
+    }
+    let rank = ((p / 100.) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl Report {
+    fn new(timings: Vec<Timing>, elapsed: Duration) -> Self {
+        let total = timings.len();
+        let errors = timings.iter().filter(|t| t.failed).count();
+        let kinds = timings
+            .into_iter()
+            .into_group_map_by(|t| t.kind.clone())
+            .into_iter()
+            .map(|(kind, timings)| {
+                let mut millis = timings
+                    .iter()
+                    .map(|t| t.elapsed.as_secs_f64() * 1000.)
+                    .collect_vec();
+                millis.sort_by(|a, b| a.total_cmp(b));
+                KindReport {
+                    kind,
+                    count: millis.len(),
+                    errors: timings.iter().filter(|t| t.failed).count(),
+                    mean: millis.iter().sum::<f64>() / millis.len() as f64,
+                    p50: percentile(&millis, 50.),
+                    p90: percentile(&millis, 90.),
+                    p99: percentile(&millis, 99.),
+                    max: millis.last().copied().unwrap_or_default(),
+                }
+            })
+            .sorted_by(|a, b| a.kind.cmp(&b.kind))
+            .collect();
+        Self {
+            total,
+            errors,
+            secs: elapsed.as_secs_f64(),
+            kinds,
+        }
+    }
+    fn throughput(&self) -> f64 {
+        if self.secs > 0. {
+            self.total as f64 / self.secs
+        } else {
+            0.
+        }
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "queries": self.total,
+            "errors": self.errors,
+            "secs": self.secs,
+            "throughput": self.throughput(),
+            "kinds": self.kinds.iter().map(|k| json!({
+                "kind": k.kind,
+                "count": k.count,
+                "errors": k.errors,
+                "mean_ms": k.mean,
+                "p50_ms": k.p50,
+                "p90_ms": k.p90,
+                "p99_ms": k.p99,
+                "max_ms": k.max,
+            })).collect_vec(),
+        })
+    }
+    fn print(&self) {
+        use prettytable::format;
+        let mut table = prettytable::Table::new();
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+        table.set_titles(prettytable::row![
+            "kind", "count", "errors", "mean ms", "p50 ms", "p90 ms", "p99 ms", "max ms"
+        ]);
+        for k in &self.kinds {
+            table.add_row(prettytable::row![
+                k.kind,
+                k.count,
+                k.errors,
+                format!("{:.3}", k.mean),
+                format!("{:.3}", k.p50),
+                format!("{:.3}", k.p90),
+                format!("{:.3}", k.p99),
+                format!("{:.3}", k.max)
+            ]);
+        }
+        table.printstd();
+        println!(
+            "{} queries, {} errors in {:.3}s: {:.1} queries/s",
+            self.total,
+            self.errors,
+            self.secs,
+            self.throughput()
+        );
+    }
+}
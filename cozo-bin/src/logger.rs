@@ -0,0 +1,164 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+use log::kv::{Key, Value, VisitSource};
+use log::{Log, Metadata, Record};
+use miette::{bail, IntoDiagnostic, WrapErr};
+use serde_json::{json, Map};
+
+/// How the records are written, one per line
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum LogFormat {
+    /// The message followed by the key-values as `key=value`
+    Text,
+    /// An object with the fields `ts`, `level`, `target` and `msg`, and the key-values
+    Json,
+}
+
+pub(crate) fn parse_log_format(format: &str) -> miette::Result<LogFormat> {
+    match format {
+        "text" => Ok(LogFormat::Text),
+        "json" => Ok(LogFormat::Json),
+        _ => bail!("invalid log format '{format}', expected text or json"),
+    }
+}
+
+/// The logger of the server, writing to stderr or to a file that can be reopened,
+/// for the file to be rotated while the server runs
+pub(crate) struct ServerLogger {
+    format: LogFormat,
+    path: Option<String>,
+    file: Mutex<Option<File>>,
+}
+
+fn open_log_file(path: &str) -> miette::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("cannot open the log file {path}"))
+}
+
+impl ServerLogger {
+    /// Install the logger, with records of every level let through until the level is set
+    pub(crate) fn install(
+        format: LogFormat,
+        path: Option<String>,
+    ) -> miette::Result<&'static ServerLogger> {
+        let file = match &path {
+            None => None,
+            Some(path) => Some(open_log_file(path)?),
+        };
+        let logger: &'static ServerLogger = Box::leak(Box::new(ServerLogger {
+            format,
+            path,
+            file: Mutex::new(file),
+        }));
+        log::set_logger(logger).into_diagnostic()?;
+        log::set_max_level(log::LevelFilter::Trace);
+        Ok(logger)
+    }
+    /// Open the log file again, after it has been moved away
+    pub(crate) fn reopen(&self) -> miette::Result<()> {
+        if let Some(path) = &self.path {
+            *self.file.lock().unwrap() = Some(open_log_file(path)?);
+        }
+        Ok(())
+    }
+    fn format(&self, record: &Record<'_>) -> String {
+        let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        match self.format {
+            LogFormat::Text => {
+                let mut kvs = TextKvs(String::new());
+                let _ = record.key_values().visit(&mut kvs);
+                format!(
+                    "[{ts} {} {}] {}{}\n",
+                    record.level(),
+                    record.target(),
+                    record.args(),
+                    kvs.0
+                )
+            }
+            LogFormat::Json => {
+                let mut kvs = JsonKvs(Map::new());
+                let _ = record.key_values().visit(&mut kvs);
+                let mut obj = json!({
+                    "ts": ts,
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "msg": record.args().to_string(),
+                });
+                obj.as_object_mut().unwrap().append(&mut kvs.0);
+                format!("{obj}\n")
+            }
+        }
+    }
+}
+
+impl Log for ServerLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format(record);
+        // there is nowhere left to report failing to log to
+        match &mut *self.file.lock().unwrap() {
+            Some(file) => {
+                let _ = file.write_all(line.as_bytes());
+            }
+            None => {
+                let _ = std::io::stderr().write_all(line.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &mut *self.file.lock().unwrap() {
+            let _ = file.flush();
+        }
+    }
+}
+
+struct TextKvs(String);
+
+impl<'kvs> VisitSource<'kvs> for TextKvs {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.push_str(&format!(" {key}={value}"));
+        Ok(())
+    }
+}
+
+struct JsonKvs(Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonKvs {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = if let Some(b) = value.to_bool() {
+            json!(b)
+        } else if let Some(i) = value.to_i64() {
+            json!(i)
+        } else if let Some(u) = value.to_u64() {
+            json!(u)
+        } else if let Some(f) = value.to_f64() {
+            json!(f)
+        } else {
+            json!(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
@@ -11,14 +11,23 @@ extern crate core;
 use std::process::exit;
 
 use clap::{Parser, Subcommand};
-use env_logger::Env;
 
+use crate::bench::{bench_main, BenchArgs};
+#[cfg(feature = "neo4j")]
+use crate::neo4j::{neo4j_main, Neo4jArgs};
 use crate::repl::{repl_main, ReplArgs};
+use crate::replay::{replay_main, ReplayArgs};
 use crate::server::{server_main, ServerArgs};
 
+mod bench;
 mod client;
+mod logger;
+#[cfg(feature = "neo4j")]
+mod neo4j;
 mod repl;
+mod replay;
 mod server;
+mod settings;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -32,24 +41,46 @@ struct AppArgs {
 enum Commands {
     Server(ServerArgs),
     Repl(ReplArgs),
+    Replay(ReplayArgs),
+    /// Time a replay log or a synthetic workload
+    Bench(BenchArgs),
+    /// Migrate from Neo4j
+    #[cfg(feature = "neo4j")]
+    Neo4j(Neo4jArgs),
 }
 
 fn main() {
     match AppArgs::parse().command {
-        Commands::Server(args) => {
-            env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-            tokio::runtime::Builder::new_multi_thread()
-                .enable_all()
-                .build()
-                .unwrap()
-                .block_on(server_main(args))
-        }
+        Commands::Server(args) => tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(server_main(args)),
         Commands::Repl(args) => {
             if let Err(e) = repl_main(args) {
                 eprintln!("{e}");
                 exit(-1);
             }
         }
+        Commands::Replay(args) => {
+            if let Err(e) = replay_main(args) {
+                eprintln!("{e:?}");
+                exit(-1);
+            }
+        }
+        Commands::Bench(args) => {
+            if let Err(e) = bench_main(args) {
+                eprintln!("{e:?}");
+                exit(-1);
+            }
+        }
+        #[cfg(feature = "neo4j")]
+        Commands::Neo4j(args) => {
+            if let Err(e) = neo4j_main(args) {
+                eprintln!("{e:?}");
+                exit(-1);
+            }
+        }
     };
 
     // if args.repl {
@@ -0,0 +1,740 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use clap::{Args, Subcommand};
+use itertools::Itertools;
+use miette::{bail, miette, IntoDiagnostic, Result};
+use serde_derive::Deserialize;
+
+use cozo::{DataValue, DbInstance, NamedRows, Num, ScriptMutability};
+
+#[derive(Args, Debug)]
+pub(crate) struct Neo4jArgs {
+    #[command(subcommand)]
+    command: Neo4jCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum Neo4jCommand {
+    /// Import the nodes and relationships of CSV files exported from Neo4j into stored relations
+    Import(ImportArgs),
+    /// Print the CozoScript equivalents of common Cypher patterns, using the mapped schema
+    Cheatsheet(CheatsheetArgs),
+}
+
+#[derive(Args, Debug)]
+struct ImportArgs {
+    /// CSV files written by `apoc.export.csv.all` or in the `neo4j-admin import` format.
+    /// Labels or the relationship type can be given as `Label=file.csv`
+    #[clap(required = true)]
+    files: Vec<String>,
+
+    /// JSON file mapping labels and relationship types to relations
+    #[clap(short, long)]
+    mapping: Option<String>,
+
+    /// Print the schema that would be created, without importing anything
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Database engine, can be `mem`, `sqlite`, `rocksdb` and others.
+    #[clap(short, long, default_value_t = String::from("mem"))]
+    engine: String,
+
+    /// Path to the directory to store the database
+    #[clap(short, long, default_value_t = String::from("cozo.db"))]
+    path: String,
+
+    /// Extra config in JSON format
+    #[clap(short, long, default_value_t = String::from("{}"))]
+    config: String,
+}
+
+#[derive(Args, Debug)]
+struct CheatsheetArgs {
+    /// CSV files to take the schema from, as for `import`
+    files: Vec<String>,
+
+    /// JSON file mapping labels and relationship types to relations
+    #[clap(short, long)]
+    mapping: Option<String>,
+}
+
+/// How the labels and relationship types are stored, e.g.
+/// `{"nodes": {"Person": {"relation": "people", "properties": {"born": "birth_year"}}},
+///   "relationships": {"KNOWS": {"skip": true}}}`
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Mapping {
+    nodes: BTreeMap<String, LabelMapping>,
+    relationships: BTreeMap<String, LabelMapping>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct LabelMapping {
+    /// The relation to store into, by default the label in snake case
+    relation: Option<String>,
+    /// Renamed properties, the others keep their names
+    properties: BTreeMap<String, String>,
+    /// Properties left out
+    exclude: Vec<String>,
+    /// Leaves out the label or relationship type entirely
+    skip: bool,
+}
+
+/// A node or a relationship read from the files
+struct Element {
+    /// The labels of a node, or the type of a relationship
+    labels: Vec<String>,
+    /// `[id]` for a node and `[from, to]` for a relationship
+    keys: Vec<DataValue>,
+    properties: BTreeMap<String, DataValue>,
+}
+
+#[derive(Default)]
+struct Elements {
+    nodes: Vec<Element>,
+    relationships: Vec<Element>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Kind {
+    Int,
+    Float,
+    Bool,
+    String,
+    List,
+    Any,
+}
+
+impl Kind {
+    fn of(val: &DataValue) -> Self {
+        match val {
+            DataValue::Num(Num::Int(_)) => Kind::Int,
+            DataValue::Num(Num::Float(_)) => Kind::Float,
+            DataValue::Bool(_) => Kind::Bool,
+            DataValue::Str(_) => Kind::String,
+            DataValue::List(_) => Kind::List,
+            _ => Kind::Any,
+        }
+    }
+    fn widen(self, other: Kind) -> Self {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Kind::Int, Kind::Float) | (Kind::Float, Kind::Int) => Kind::Float,
+            _ => Kind::Any,
+        }
+    }
+    fn col_type(self) -> &'static str {
+        match self {
+            Kind::Int => "Int",
+            Kind::Float => "Float",
+            Kind::Bool => "Bool",
+            Kind::String => "String",
+            Kind::List => "[Any]",
+            Kind::Any => "Any",
+        }
+    }
+}
+
+/// A stored relation the elements of a label or relationship type go into
+struct Table {
+    name: String,
+    label: String,
+    keys: Vec<(String, Kind)>,
+    /// Columns as `(neo4j name, column name, kind)`
+    columns: Vec<(String, String, Kind)>,
+    rows: Vec<Vec<DataValue>>,
+}
+
+impl Table {
+    fn create_script(&self) -> String {
+        let keys = self
+            .keys
+            .iter()
+            .map(|(name, kind)| format!("{name}: {}", kind.col_type()))
+            .join(", ");
+        let cols = self
+            .columns
+            .iter()
+            .map(|(_, name, kind)| format!("{name}: {}?", kind.col_type()))
+            .join(", ");
+        if cols.is_empty() {
+            format!(":create {} {{{keys}}}", self.name)
+        } else {
+            format!(":create {} {{{keys} => {cols}}}", self.name)
+        }
+    }
+    fn headers(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .map(|(name, _)| name.clone())
+            .chain(self.columns.iter().map(|(_, name, _)| name.clone()))
+            .collect()
+    }
+}
+
+/// Run the Neo4j migration helpers
+pub(crate) fn neo4j_main(args: Neo4jArgs) -> Result<()> {
+    match args.command {
+        Neo4jCommand::Import(args) => import_main(args),
+        Neo4jCommand::Cheatsheet(args) => cheatsheet_main(args),
+    }
+}
+
+/// Import Neo4j CSV exports, creating a relation for every label and relationship type
+/// not already in the database
+fn import_main(args: ImportArgs) -> Result<()> {
+    let mapping = read_mapping(&args.mapping)?;
+    let (node_tables, rel_tables) = build_tables(read_files(&args.files)?, &mapping)?;
+    let tables = node_tables.into_iter().chain(rel_tables).collect_vec();
+    if args.dry_run {
+        for table in &tables {
+            println!("{}", table.create_script());
+        }
+        return Ok(());
+    }
+
+    let db = DbInstance::new(&args.engine, &args.path, &args.config)?;
+    let mut data = BTreeMap::new();
+    for table in tables {
+        let exists = db
+            .run_script(
+                &format!("::columns {}", table.name),
+                Default::default(),
+                ScriptMutability::Immutable,
+            )
+            .is_ok();
+        if !exists {
+            db.run_script(
+                &table.create_script(),
+                Default::default(),
+                ScriptMutability::Mutable,
+            )?;
+        }
+        eprintln!(
+            "{} ({}): {} rows",
+            table.name,
+            table.label,
+            table.rows.len()
+        );
+        data.insert(
+            table.name.clone(),
+            NamedRows::new(table.headers(), table.rows),
+        );
+    }
+    db.import_relations(data)?;
+    Ok(())
+}
+
+fn cheatsheet_main(args: CheatsheetArgs) -> Result<()> {
+    let mapping = read_mapping(&args.mapping)?;
+    let (node_tables, rel_tables) = build_tables(read_files(&args.files)?, &mapping)?;
+    let label_relation = |label: &str, set: &BTreeMap<String, LabelMapping>| match set.get(label) {
+        Some(LabelMapping {
+            relation: Some(rel),
+            ..
+        }) => rel.clone(),
+        _ => to_snake_case(label),
+    };
+    let (label, node, prop) = match node_tables.first() {
+        Some(table) => (
+            table.label.clone(),
+            table.name.clone(),
+            table
+                .columns
+                .first()
+                .map(|(_, name, _)| name.clone())
+                .unwrap_or_else(|| "id".to_string()),
+        ),
+        None => {
+            let label = mapping
+                .nodes
+                .keys()
+                .next()
+                .cloned()
+                .unwrap_or_else(|| "Person".to_string());
+            let node = label_relation(&label, &mapping.nodes);
+            (label, node, "name".to_string())
+        }
+    };
+    let (rel_type, rel) = match rel_tables.first() {
+        Some(table) => (table.label.clone(), table.name.clone()),
+        None => {
+            let rel_type = mapping
+                .relationships
+                .keys()
+                .next()
+                .cloned()
+                .unwrap_or_else(|| "KNOWS".to_string());
+            let rel = label_relation(&rel_type, &mapping.relationships);
+            (rel_type, rel)
+        }
+    };
+    print!("{}", cheatsheet(&label, &node, &prop, &rel_type, &rel));
+    Ok(())
+}
+
+fn read_mapping(path: &Option<String>) -> Result<Mapping> {
+    match path {
+        None => Ok(Mapping::default()),
+        Some(path) => {
+            let content = fs::read_to_string(path).into_diagnostic()?;
+            serde_json::from_str(&content)
+                .map_err(|err| miette!("cannot read the mapping {path}: {err}"))
+        }
+    }
+}
+
+fn read_files(files: &[String]) -> Result<Elements> {
+    let mut elements = Elements::default();
+    for arg in files {
+        let (labels, path) = match arg.split_once('=') {
+            Some((labels, path)) if !Path::new(arg).exists() => (
+                labels
+                    .split(':')
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_string())
+                    .collect_vec(),
+                path,
+            ),
+            _ => (vec![], arg.as_str()),
+        };
+        let mut reader = csv::ReaderBuilder::new()
+            .from_path(path)
+            .map_err(|err| miette!("cannot read {path}: {err}"))?;
+        let headers = reader
+            .headers()
+            .map_err(|err| miette!("cannot read {path}: {err}"))?
+            .iter()
+            .map(|h| h.to_string())
+            .collect_vec();
+        let read_file = if headers.iter().any(|h| h == "_start") {
+            read_apoc_record
+        } else if headers
+            .iter()
+            .any(|h| h.contains(":START_ID") || h.contains(":ID"))
+        {
+            read_admin_record
+        } else {
+            bail!("{path} has neither the headers of an APOC export nor of `neo4j-admin import`")
+        };
+        for (i, record) in reader.records().enumerate() {
+            let record = record.map_err(|err| miette!("cannot read {path}: {err}"))?;
+            let fields = record.iter().collect_vec();
+            read_file(&headers, &fields, &labels, &mut elements)
+                .map_err(|err| miette!("{path}, line {}: {err}", i + 2))?;
+        }
+    }
+    Ok(elements)
+}
+
+/// A row of `apoc.export.csv.all`, with the columns `_id`, `_labels`, `_start`, `_end` and `_type`
+/// besides the properties, nodes leaving the relationship columns empty
+fn read_apoc_record(
+    headers: &[String],
+    fields: &[&str],
+    _labels: &[String],
+    elements: &mut Elements,
+) -> Result<()> {
+    let mut special = BTreeMap::new();
+    let mut properties = BTreeMap::new();
+    for (header, field) in headers.iter().zip(fields) {
+        if field.is_empty() {
+            continue;
+        }
+        if header.starts_with('_') {
+            special.insert(header.as_str(), *field);
+        } else {
+            properties.insert(header.clone(), infer_value(field));
+        }
+    }
+    let key = |name: &str| -> Result<DataValue> {
+        special
+            .get(name)
+            .map(|v| infer_value(v))
+            .ok_or_else(|| miette!("the column `{name}` is empty"))
+    };
+    if special.contains_key("_start") {
+        let rel_type = special.get("_type").map(|t| t.to_string());
+        elements.relationships.push(Element {
+            labels: rel_type.into_iter().collect(),
+            keys: vec![key("_start")?, key("_end")?],
+            properties,
+        });
+    } else {
+        let labels = special
+            .get("_labels")
+            .map(|l| {
+                l.split(':')
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_string())
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+        elements.nodes.push(Element {
+            labels,
+            keys: vec![key("_id")?],
+            properties,
+        });
+    }
+    Ok(())
+}
+
+/// A row in the `neo4j-admin import` format, with headers such as `name:string`, `:ID`,
+/// `:LABEL` for nodes and `:START_ID`, `:END_ID`, `:TYPE` for relationships
+fn read_admin_record(
+    headers: &[String],
+    fields: &[&str],
+    labels: &[String],
+    elements: &mut Elements,
+) -> Result<()> {
+    let mut labels = labels.to_vec();
+    let mut id = None;
+    let mut start = None;
+    let mut end = None;
+    let mut properties = BTreeMap::new();
+    for (header, field) in headers.iter().zip(fields) {
+        let (name, kind) = header.split_once(':').unwrap_or((header, "string"));
+        // ID groups such as `:ID(Person)` are not kept apart
+        let kind = kind.split('(').next().unwrap();
+        match kind {
+            "ID" => {
+                id = Some(DataValue::from(*field));
+                if !name.is_empty() {
+                    properties.insert(name.to_string(), DataValue::from(*field));
+                }
+            }
+            "START_ID" => start = Some(DataValue::from(*field)),
+            "END_ID" => end = Some(DataValue::from(*field)),
+            "LABEL" | "TYPE" => labels.extend(
+                field
+                    .split(';')
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_string()),
+            ),
+            "IGNORE" => {}
+            _ if field.is_empty() => {}
+            _ => {
+                properties.insert(name.to_string(), typed_value(field, kind)?);
+            }
+        }
+    }
+    match (id, start, end) {
+        (_, Some(start), Some(end)) => elements.relationships.push(Element {
+            labels,
+            keys: vec![start, end],
+            properties,
+        }),
+        (Some(id), None, None) => elements.nodes.push(Element {
+            labels,
+            keys: vec![id],
+            properties,
+        }),
+        _ => bail!("the row is neither a node with an ID nor a relationship with both ends"),
+    }
+    Ok(())
+}
+
+/// A value of an untyped export: numbers, booleans and JSON lists are recognised
+fn infer_value(field: &str) -> DataValue {
+    if let Ok(i) = field.parse::<i64>() {
+        return DataValue::from(i);
+    }
+    if field.bytes().any(|b| b.is_ascii_digit()) {
+        if let Ok(f) = field.parse::<f64>() {
+            return DataValue::from(f);
+        }
+    }
+    match field {
+        "true" => return DataValue::Bool(true),
+        "false" => return DataValue::Bool(false),
+        _ => {}
+    }
+    if field.starts_with('[') {
+        if let Ok(val @ serde_json::Value::Array(_)) = serde_json::from_str(field) {
+            return DataValue::from(val);
+        }
+    }
+    DataValue::from(field)
+}
+
+/// A value with the type of its `neo4j-admin import` header, arrays being separated by `;`
+fn typed_value(field: &str, kind: &str) -> Result<DataValue> {
+    if let Some(elem_kind) = kind.strip_suffix("[]") {
+        let elems: Vec<_> = field
+            .split(';')
+            .map(|elem| typed_value(elem, elem_kind))
+            .try_collect()?;
+        return Ok(DataValue::List(elems));
+    }
+    Ok(match kind {
+        "int" | "long" | "short" | "byte" => DataValue::from(
+            field
+                .parse::<i64>()
+                .map_err(|_| miette!("`{field}` is not an integer"))?,
+        ),
+        "float" | "double" => DataValue::from(
+            field
+                .parse::<f64>()
+                .map_err(|_| miette!("`{field}` is not a number"))?,
+        ),
+        "boolean" => DataValue::Bool(field.eq_ignore_ascii_case("true")),
+        // strings, chars, and temporal and spatial values are kept as text
+        _ => DataValue::from(field),
+    })
+}
+
+/// Group the elements into a table per label or relationship type, a node with several labels
+/// going into the table of each of them
+fn build_tables(elements: Elements, mapping: &Mapping) -> Result<(Vec<Table>, Vec<Table>)> {
+    let node_tables = group_tables(elements.nodes, &mapping.nodes, &["id"])?;
+    let rel_tables = group_tables(
+        elements.relationships,
+        &mapping.relationships,
+        &["from", "to"],
+    )?;
+    let mut names = BTreeMap::new();
+    for table in node_tables.iter().chain(rel_tables.iter()) {
+        if let Some(other) = names.insert(table.name.clone(), table.label.clone()) {
+            bail!(
+                "`{other}` and `{}` are both mapped to the relation `{}`",
+                table.label,
+                table.name
+            )
+        }
+    }
+    Ok((node_tables, rel_tables))
+}
+
+fn group_tables(
+    elements: Vec<Element>,
+    mapping: &BTreeMap<String, LabelMapping>,
+    key_names: &[&str],
+) -> Result<Vec<Table>> {
+    let default_mapping = LabelMapping::default();
+    let mut grouped: BTreeMap<String, Vec<&Element>> = BTreeMap::new();
+    for element in &elements {
+        for label in &element.labels {
+            grouped.entry(label.clone()).or_default().push(element);
+        }
+    }
+    let mut tables = vec![];
+    for (label, elements) in grouped {
+        let label_mapping = mapping.get(&label).unwrap_or(&default_mapping);
+        if label_mapping.skip {
+            continue;
+        }
+        let name = label_mapping
+            .relation
+            .clone()
+            .unwrap_or_else(|| to_snake_case(&label));
+        let mut key_kinds: Vec<Option<Kind>> = vec![None; key_names.len()];
+        let mut kinds: BTreeMap<&str, Kind> = BTreeMap::new();
+        for element in &elements {
+            for (kind, val) in key_kinds.iter_mut().zip(&element.keys) {
+                let k = Kind::of(val);
+                *kind = Some(kind.map_or(k, |kind| kind.widen(k)));
+            }
+            for (prop, val) in &element.properties {
+                if label_mapping.exclude.contains(prop) {
+                    continue;
+                }
+                let k = Kind::of(val);
+                kinds
+                    .entry(prop)
+                    .and_modify(|kind| *kind = kind.widen(k))
+                    .or_insert(k);
+            }
+        }
+        let keys = key_names
+            .iter()
+            .zip(key_kinds)
+            .map(|(name, kind)| (name.to_string(), kind.unwrap_or(Kind::Any)))
+            .collect_vec();
+        let columns = kinds
+            .into_iter()
+            .map(|(prop, kind)| {
+                let col = match label_mapping.properties.get(prop) {
+                    Some(col) => col.clone(),
+                    None => to_ident(prop),
+                };
+                (prop.to_string(), col, kind)
+            })
+            .collect_vec();
+        for (_, col, _) in &columns {
+            if key_names.contains(&col.as_str()) {
+                bail!("the property `{col}` of `{label}` clashes with a key column, rename it in the mapping")
+            }
+        }
+        let rows = elements
+            .iter()
+            .map(|element| {
+                element
+                    .keys
+                    .iter()
+                    .cloned()
+                    .chain(columns.iter().map(|(prop, _, _)| {
+                        element
+                            .properties
+                            .get(prop)
+                            .cloned()
+                            .unwrap_or(DataValue::Null)
+                    }))
+                    .collect()
+            })
+            .collect();
+        tables.push(Table {
+            name,
+            label,
+            keys,
+            columns,
+            rows,
+        });
+    }
+    Ok(tables)
+}
+
+/// `MovieGenre` and `ACTED_IN` become `movie_genre` and `acted_in`
+fn to_snake_case(label: &str) -> String {
+    let mut out = String::new();
+    let mut prev_lower = false;
+    for c in label.chars() {
+        if c.is_uppercase() && prev_lower {
+            out.push('_');
+        }
+        prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        out.extend(c.to_lowercase());
+    }
+    to_ident(&out)
+}
+
+/// A valid column or relation name for a property or label
+fn to_ident(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    if !out.starts_with(|c: char| c.is_alphabetic()) {
+        out.insert(0, 'p');
+    }
+    out
+}
+
+fn cheatsheet(label: &str, node: &str, prop: &str, rel_type: &str, rel: &str) -> String {
+    let patterns = [
+        (
+            "Scan a label",
+            format!("MATCH (n:{label}) RETURN n.{prop}"),
+            format!("?[{prop}] := *{node}{{{prop}}}"),
+        ),
+        (
+            "Filter on a property",
+            format!("MATCH (n:{label}) WHERE n.{prop} = $value RETURN n"),
+            format!("?[id, {prop}] := *{node}{{id, {prop}}}, {prop} == $value"),
+        ),
+        (
+            "Follow a relationship",
+            format!("MATCH (a:{label})-[:{rel_type}]->(b) RETURN a.{prop}, b"),
+            format!("?[{prop}, b] := *{node}{{id: a, {prop}}}, *{rel}{{from: a, to: b}}"),
+        ),
+        (
+            "Ignore the direction",
+            format!("MATCH (a)-[:{rel_type}]-(b) RETURN a, b"),
+            format!("?[a, b] := *{rel}{{from: a, to: b}}\n?[a, b] := *{rel}{{from: b, to: a}}"),
+        ),
+        (
+            "Variable length paths",
+            format!("MATCH (a {{id: $start}})-[:{rel_type}*1..]->(b) RETURN DISTINCT b"),
+            format!(
+                "reach[b] := *{rel}{{from: $start, to: b}}\n\
+                 reach[b] := reach[a], *{rel}{{from: a, to: b}}\n\
+                 ?[b] := reach[b]"
+            ),
+        ),
+        (
+            "Shortest path (with the `graph-algo` feature)",
+            format!(
+                "MATCH p = shortestPath((a {{id: $start}})-[:{rel_type}*]->(b {{id: $end}})) RETURN p"
+            ),
+            format!(
+                "start[] <- [[$start]]\n\
+                 end[] <- [[$end]]\n\
+                 ?[start, end, path] <~ ShortestPathBFS(*{rel}[], start[], end[])"
+            ),
+        ),
+        (
+            "Pattern absent",
+            format!("MATCH (a:{label}) WHERE NOT (a)-[:{rel_type}]->() RETURN a"),
+            format!(
+                "has[a] := *{rel}{{from: a}}\n\
+                 ?[a] := *{node}{{id: a}}, not has[a]"
+            ),
+        ),
+        (
+            "Optional match",
+            format!("MATCH (a:{label}) OPTIONAL MATCH (a)-[:{rel_type}]->(b) RETURN a, b"),
+            format!(
+                "?[a, b] := *{node}{{id: a}}, *{rel}{{from: a, to: b}}\n\
+                 ?[a, b] := *{node}{{id: a}}, not *{rel}{{from: a}}, b = null"
+            ),
+        ),
+        (
+            "Aggregation",
+            format!("MATCH (a)-[:{rel_type}]->(b) RETURN a, count(b) AS n"),
+            format!("?[a, count(b)] := *{rel}{{from: a, to: b}}"),
+        ),
+        (
+            "Order and limit",
+            format!("MATCH (n:{label}) RETURN n.{prop} ORDER BY n.{prop} DESC LIMIT 10"),
+            format!("?[{prop}] := *{node}{{{prop}}}\n:order -{prop}\n:limit 10"),
+        ),
+        (
+            "Create or merge a node",
+            format!("MERGE (n:{label} {{id: $id}}) SET n.{prop} = $value"),
+            format!("?[id, {prop}] <- [[$id, $value]]\n:put {node} {{id => {prop}}}"),
+        ),
+        (
+            "Create a relationship",
+            format!(
+                "MATCH (a {{id: $from}}), (b {{id: $to}}) CREATE (a)-[:{rel_type}]->(b)"
+            ),
+            format!("?[from, to] <- [[$from, $to]]\n:put {rel} {{from, to}}"),
+        ),
+        (
+            "Update a property",
+            format!("MATCH (n:{label} {{id: $id}}) SET n.{prop} = $value"),
+            format!("?[id, {prop}] <- [[$id, $value]]\n:update {node} {{id => {prop}}}"),
+        ),
+        (
+            "Delete a node with its relationships",
+            format!("MATCH (n:{label} {{id: $id}}) DETACH DELETE n"),
+            format!(
+                "{{?[id] <- [[$id]] :rm {node} {{id}}}}\n\
+                 {{?[from, to] := *{rel}{{from, to}}, from == $id || to == $id\n :rm {rel} {{from, to}}}}"
+            ),
+        ),
+    ];
+    let mut out = format!(
+        "# Cypher to CozoScript\n\n\
+         Nodes labelled `{label}` are stored in `{node}`, keyed by `id`,\n\
+         and relationships of type `{rel_type}` in `{rel}`, keyed by `from` and `to`.\n"
+    );
+    for (title, cypher, cozo) in patterns {
+        out.push_str(&format!(
+            "\n## {title}\n\n```cypher\n{cypher}\n```\n\n```\n{cozo}\n```\n"
+        ));
+    }
+    out
+}
@@ -141,6 +141,9 @@ pub(crate) fn repl_main(args: ReplArgs) -> Result<(), Box<dyn Error>> {
     if rl.save_history(history_file).is_ok() {
         eprintln!("Query history saved in {history_file}");
     }
+    if let Err(err) = db.close() {
+        eprintln!("{err:?}");
+    }
     Ok(())
 }
 
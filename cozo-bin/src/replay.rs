@@ -0,0 +1,65 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use clap::Args;
+use serde_json::Value;
+
+use cozo::DbInstance;
+
+#[derive(Args, Debug)]
+pub(crate) struct ReplayArgs {
+    /// The replay log to run, as written by the server with `--replay-log`
+    log: String,
+
+    /// Database engine, can be `mem`, `sqlite`, `rocksdb` and others.
+    #[clap(short, long, default_value_t = String::from("mem"))]
+    engine: String,
+
+    /// Path to the directory to store the database
+    #[clap(short, long, default_value_t = String::from("cozo.db"))]
+    path: String,
+
+    /// Restore from the specified backup before replaying, usually one taken when recording started
+    #[clap(long)]
+    restore: Option<String>,
+
+    /// Extra config in JSON format
+    #[clap(short, long, default_value_t = String::from("{}"))]
+    config: String,
+}
+
+/// Run the scripts of a replay log, printing a JSON object per script that compares
+/// the replay with the recording
+pub(crate) fn replay_main(args: ReplayArgs) -> miette::Result<()> {
+    let db = DbInstance::new(&args.engine, &args.path, &args.config)?;
+    if let Some(p) = &args.restore {
+        db.restore_backup(p)?;
+    }
+    let out = db.replay(&args.log)?;
+    let mut n_changed = 0;
+    for row in &out.rows {
+        let obj: serde_json::Map<String, Value> = out
+            .headers
+            .iter()
+            .zip(row)
+            .map(|(k, v)| (k.to_string(), Value::from(v.clone())))
+            .collect();
+        if obj["recorded_rows"] != obj["replayed_rows"]
+            || obj["recorded_error"] != obj["replayed_error"]
+        {
+            n_changed += 1;
+        }
+        println!("{}", Value::Object(obj));
+    }
+    eprintln!(
+        "Replayed {} scripts, {} with a different outcome",
+        out.rows.len(),
+        n_changed
+    );
+    Ok(())
+}
@@ -9,18 +9,21 @@
 use std::collections::BTreeMap;
 use std::convert::Infallible;
 use std::net::{Ipv6Addr, SocketAddr};
+use std::process::exit;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
 
 use axum::body::Body;
 use axum::extract::{DefaultBodyLimit, Path, Query, State};
 use axum::http::{header, HeaderName, Method, Request, Response, StatusCode};
 use axum::response::sse::{Event, KeepAlive};
 use axum::response::{Html, Sse};
-use axum::routing::{get, post, put};
+use axum::routing::{get, post};
 use axum::{Extension, Json, Router};
 use clap::Args;
+use crossbeam::channel::RecvTimeoutError;
 use futures::future::BoxFuture;
 use futures::stream::Stream;
 use itertools::Itertools;
@@ -30,12 +33,19 @@ use miette::miette;
 use rand::Rng;
 use serde_json::json;
 use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::task::spawn_blocking;
 use tower_http::auth::{AsyncAuthorizeRequest, AsyncRequireAuthorizationLayer};
 use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::decompression::RequestDecompressionLayer;
 
-use cozo::{DataValue, DbInstance, format_error_as_json, MultiTransaction, NamedRows, ScriptMutability, SimpleFixedRule};
+use cozo::{protocol_routes, CallbackOp, DataValue, DbInstance, NamedRows, ScriptMutability, ServeRole, ServeSession, ServeState, SimpleFixedRule, IDEMPOTENCY_KEY};
+
+use crate::logger::{parse_log_format, ServerLogger};
+use crate::settings::{env_log_level, parse_log_level, AuthConfig, Reloader, Settings};
 
 #[derive(Args, Debug)]
 pub(crate) struct ServerArgs {
@@ -66,6 +76,49 @@ pub(crate) struct ServerArgs {
     /// When set, the content of the named table will be used as a token table
     #[clap(long)]
     token_table: Option<String>,
+
+    /// Record schema changes and mutations in the `cozo.audit` relation
+    #[clap(long)]
+    audit: bool,
+
+    /// Append every script run to this file, to be replayed later with `cozo replay`
+    #[clap(long)]
+    replay_log: Option<String>,
+
+    /// Return the work done by the storage engine with the results of each script
+    #[clap(long)]
+    storage_stats: bool,
+
+    /// Scan relations in place of indices found inconsistent, rebuilding them in the
+    /// background, instead of failing the queries reading them
+    #[clap(long)]
+    index_fallback: bool,
+
+    /// Serve queries only: every client is restricted to immutable scripts
+    #[clap(long)]
+    read_only: bool,
+
+    /// The level of the messages logged: off, error, warn, info, debug or trace.
+    /// Defaults to `RUST_LOG` if it is one of those levels, as filters by module are not
+    /// supported, and to info otherwise.
+    #[clap(long)]
+    log_level: Option<String>,
+
+    /// How the messages are logged: `text`, or `json` with one object per line
+    #[clap(long, default_value_t = String::from("text"))]
+    log_format: String,
+
+    /// Log to this file instead of stderr. It is opened again when the settings are
+    /// reloaded, for it to be rotated.
+    #[clap(long)]
+    log_file: Option<String>,
+
+    /// A JSON file of settings taking precedence over the flags, read again on SIGHUP
+    /// or `::config reload`: `log_level`, `read_only`, `audit`, `storage_stats`,
+    /// `index_fallback`, `replay_log`, `token_table`, `max_rows` and `max_bytes`.
+    /// The auth token file is read again at the same time.
+    #[clap(long)]
+    settings: Option<String>,
 }
 
 #[derive(Clone)]
@@ -73,15 +126,14 @@ struct DbState {
     db: DbInstance,
     rule_senders: Arc<Mutex<BTreeMap<u32, crossbeam::channel::Sender<miette::Result<NamedRows>>>>>,
     rule_counter: Arc<AtomicU32>,
-    tx_counter: Arc<AtomicU32>,
-    txs: Arc<Mutex<BTreeMap<u32, Arc<MultiTransaction>>>>,
 }
 
 #[derive(Clone)]
 struct MyAuth {
-    skip_auth: bool,
-    auth_guard: String,
-    token_table: Option<Arc<(String, DbInstance)>>,
+    /// The database tokens are looked up in
+    db: DbInstance,
+    config: Arc<RwLock<Arc<AuthConfig>>>,
+    session_counter: Arc<AtomicU64>,
 }
 
 impl AsyncAuthorizeRequest<Body> for MyAuth
@@ -91,23 +143,42 @@ impl AsyncAuthorizeRequest<Body> for MyAuth
     type Future = BoxFuture<'static, Result<Request<Body>, Response<Self::ResponseBody>>>;
 
     fn authorize(&mut self, mut request: Request<Body>) -> Self::Future {
-        let skip_auth = self.skip_auth;
-        let auth_guard = self.auth_guard.clone();
-        let token_table = self.token_table.clone();
+        let session = match request
+            .headers()
+            .get("x-cozo-session")
+            .and_then(|s| s.to_str().ok())
+        {
+            Some(s) => Arc::from(s),
+            None => {
+                let id = self.session_counter.fetch_add(1, Ordering::Relaxed);
+                Arc::from(id.to_string())
+            }
+        };
+        request.extensions_mut().insert(ServeSession(session));
+        let config = self.config.read().unwrap().clone();
+        let db = self.db.clone();
+        let read_only = config.read_only;
+        let cap = move |role: ServeRole| {
+            if read_only {
+                role.min(ServeRole::Reader)
+            } else {
+                role
+            }
+        };
         Box::pin(async move {
-            if skip_auth {
-                request.extensions_mut().insert(ScriptMutability::Mutable);
+            if config.skip_auth {
+                request.extensions_mut().insert(cap(ServeRole::Admin));
                 return Ok(request);
             }
 
-            let mutability = match request.headers().get("x-cozo-auth") {
+            let role = match request.headers().get("x-cozo-auth") {
                 None => match request.uri().query() {
                     Some(q_str) => {
                         let mut bingo = false;
                         for pair in q_str.split('&') {
                             if let Some((k, v)) = pair.split_once('=') {
                                 if k == "auth" {
-                                    if v == auth_guard.as_str() {
+                                    if v == config.auth_guard.as_str() {
                                         bingo = true
                                     }
                                     break;
@@ -115,35 +186,36 @@ impl AsyncAuthorizeRequest<Body> for MyAuth
                             }
                         }
                         if bingo {
-                            Some(ScriptMutability::Mutable)
+                            Some(ServeRole::Admin)
                         } else {
                             None
                         }
                     }
-                    None => match token_table {
+                    None => match config.token_query(&db) {
                         None => None,
-                        Some(tt) => {
-                            let (name, db) = tt.as_ref();
+                        Some(query) => {
                             if let Some(auth_header) = request.headers().get("Authorization") {
                                 if let Ok(auth_str) = auth_header.to_str() {
                                     if let Some(token) = auth_str.strip_prefix("Bearer ") {
                                         match db.run_script(
-                                            &format!("?[mutable] := *{name} {{ token: $token, mutable }}"),
+                                            query,
                                             BTreeMap::from([(String::from("token"), DataValue::from(token))]),
                                             ScriptMutability::Immutable,
                                         ) {
                                             Ok(rows) => match rows.rows.first() {
                                                 None => None,
                                                 Some(val) => {
-                                                    if val[0].get_bool() == Some(true) {
-                                                        Some(ScriptMutability::Mutable)
+                                                    if val[1].get_bool() == Some(true) {
+                                                        Some(ServeRole::Admin)
+                                                    } else if val[0].get_bool() == Some(true) {
+                                                        Some(ServeRole::Writer)
                                                     } else {
-                                                        Some(ScriptMutability::Immutable)
+                                                        Some(ServeRole::Reader)
                                                     }
                                                 }
                                             },
                                             Err(err) => {
-                                                eprintln!("Error: {}", err);
+                                                error!("Cannot look up the token: {}", err);
                                                 None
                                             }
                                         }
@@ -161,8 +233,8 @@ impl AsyncAuthorizeRequest<Body> for MyAuth
                 },
                 Some(data) => match data.to_str() {
                     Ok(s) => {
-                        if s == auth_guard.as_str() {
-                            Some(ScriptMutability::Mutable)
+                        if s == config.auth_guard.as_str() {
+                            Some(ServeRole::Admin)
                         } else {
                             None
                         }
@@ -170,8 +242,8 @@ impl AsyncAuthorizeRequest<Body> for MyAuth
                     Err(_) => None,
                 },
             };
-            if let Some(mutability) = mutability {
-                request.extensions_mut().insert(mutability);
+            if let Some(role) = role {
+                request.extensions_mut().insert(cap(role));
                 Ok(request)
             } else {
                 let unauthorized_response = Response::builder()
@@ -189,6 +261,15 @@ impl AsyncAuthorizeRequest<Body> for MyAuth
 fn x() {}
 
 pub(crate) async fn server_main(args: ServerArgs) {
+    let logger = match parse_log_format(&args.log_format)
+        .and_then(|format| ServerLogger::install(format, args.log_file.clone()))
+    {
+        Ok(logger) => logger,
+        Err(err) => {
+            eprintln!("{err:?}");
+            exit(-1)
+        }
+    };
     let db = DbInstance::new(&args.engine, &args.path, &args.config).unwrap();
     if let Some(p) = &args.restore {
         if let Err(err) = db.restore_backup(p) {
@@ -197,7 +278,6 @@ pub(crate) async fn server_main(args: ServerArgs) {
             panic!()
         }
     }
-
     let skip_auth = args.bind == "127.0.0.1";
 
     let conf_path = if skip_auth {
@@ -205,60 +285,113 @@ pub(crate) async fn server_main(args: ServerArgs) {
     } else {
         format!("{}.{}.cozo_auth", args.path, args.engine)
     };
-    let auth_guard = if skip_auth {
-        "".to_string()
-    } else {
-        match tokio::fs::read_to_string(&conf_path).await {
-            Ok(s) => s.trim().to_string(),
-            Err(_) => {
-                let s = rand::thread_rng()
-                    .sample_iter(&rand::distributions::Alphanumeric)
-                    .take(64)
-                    .map(char::from)
-                    .collect();
-                tokio::fs::write(&conf_path, &s).await.unwrap();
-                s
-            }
+    if !skip_auth && tokio::fs::metadata(&conf_path).await.is_err() {
+        let s: String = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(64)
+            .map(char::from)
+            .collect();
+        tokio::fs::write(&conf_path, &s).await.unwrap();
+    }
+
+    let log_level = match args.log_level.as_deref() {
+        None => Ok(env_log_level()),
+        Some(level) => parse_log_level(level),
+    };
+    let log_level = match log_level {
+        Ok(level) => level,
+        Err(err) => {
+            error!("{}", err);
+            panic!()
         }
     };
+    let reloader = Arc::new(Reloader::new(
+        db.clone(),
+        Settings {
+            log_level,
+            read_only: args.read_only,
+            audit: args.audit,
+            storage_stats: args.storage_stats,
+            index_fallback: args.index_fallback,
+            replay_log: args.replay_log.clone(),
+            token_table: args.token_table.clone(),
+            max_rows: None,
+            max_bytes: None,
+        },
+        args.settings.clone(),
+        (!skip_auth).then(|| conf_path.clone()),
+        logger,
+    ));
+    if let Err(err) = reloader.reload() {
+        error!("{:?}", err);
+        error!("Cannot apply the settings, terminate");
+        panic!()
+    }
+    {
+        let reloader = reloader.clone();
+        db.set_config_reloader(Some(Arc::new(move || reloader.reload())));
+    }
+    #[cfg(unix)]
+    {
+        let reloader = reloader.clone();
+        tokio::spawn(async move {
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(err) => {
+                    error!("Cannot listen for SIGHUP: {err}");
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                let reloader = reloader.clone();
+                match spawn_blocking(move || reloader.reload()).await {
+                    Ok(Ok(_)) => info!("Settings reloaded on SIGHUP"),
+                    Ok(Err(err)) => error!("Cannot reload the settings: {err:?}"),
+                    Err(err) => error!("Cannot reload the settings: {err}"),
+                }
+            }
+        });
+    }
 
     let auth_obj = MyAuth {
-        skip_auth,
-        auth_guard,
-        token_table: args.token_table.map(|t| Arc::new((t, db.clone()))),
+        db: db.clone(),
+        config: reloader.auth.clone(),
+        session_counter: Default::default(),
     };
 
     let state = DbState {
-        db,
+        db: db.clone(),
         rule_senders: Default::default(),
         rule_counter: Default::default(),
-        tx_counter: Default::default(),
-        txs: Default::default(),
     };
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_origin(Any)
-        .allow_headers([header::CONTENT_TYPE, HeaderName::from_static("x-cozo-auth")]);
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::CONTENT_ENCODING,
+            HeaderName::from_static("x-cozo-auth"),
+            HeaderName::from_static(IDEMPOTENCY_KEY),
+        ]);
 
     let app = Router::new()
-        .route("/text-query", post(text_query))
-        .route("/export/:relations", get(export_relations))
-        .route("/import", put(import_relations))
         .route("/backup", post(backup))
         .route("/import-from-backup", post(import_from_backup))
         .route("/changes/:relation", get(observe_changes))
+        .route("/live", get(live_query))
+        .route("/jobs/:id", get(observe_job))
         .route("/rules/:name", get(register_rule))
         .route(
             "/rule-result/:id",
             post(post_rule_result).delete(post_rule_err),
         ) // +keep alive
-        .route("/transact", post(start_transact))
-        .route("/transact/:id", post(transact_query).put(finish_query))
         .with_state(state)
+        .merge(protocol_routes(ServeState::new(db.clone())))
         .layer(AsyncRequireAuthorizationLayer::new(auth_obj))
         .fallback(not_found)
         .route("/", get(root))
         .layer(cors)
+        .layer(RequestDecompressionLayer::new())
         .layer(CompressionLayer::new())
         .layer(DefaultBodyLimit::disable());
 
@@ -272,6 +405,9 @@ pub(crate) async fn server_main(args: ServerArgs) {
         warn!("{}", include_str!("./security.txt"));
         info!("The auth token is in the file: {conf_path}");
     }
+    if reloader.auth.read().unwrap().read_only {
+        info!("Serving in read-only mode");
+    }
 
     info!(
         "Starting Cozo ({}-backed) API at http://{}",
@@ -279,187 +415,18 @@ pub(crate) async fn server_main(args: ServerArgs) {
     );
 
     let listener = TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app.into_make_service()).await.unwrap();
-}
-
-#[derive(serde_derive::Deserialize)]
-struct StartTransactPayload {
-    write: bool,
-}
-
-async fn start_transact(
-    State(st): State<DbState>,
-    Query(payload): Query<StartTransactPayload>,
-) -> (StatusCode, Json<serde_json::Value>) {
-    let tx = st.db.multi_transaction(payload.write);
-    let id = st.tx_counter.fetch_add(1, Ordering::SeqCst);
-    st.txs.lock().unwrap().insert(id, Arc::new(tx));
-    (StatusCode::OK, json!({"ok": true, "id": id}).into())
-}
-
-async fn transact_query(
-    State(st): State<DbState>,
-    Path(id): Path<u32>,
-    Json(payload): Json<QueryPayload>,
-) -> (StatusCode, Json<serde_json::Value>) {
-    let tx = match st.txs.lock().unwrap().get(&id) {
-        None => return (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
-        Some(tx) => tx.clone(),
-    };
-    let src = payload.script.clone();
-    let result = spawn_blocking(move || {
-        let params = payload
-            .params
-            .into_iter()
-            .map(|(k, v)| (k, DataValue::from(v)))
-            .collect();
-        let query = payload.script;
-        tx.run_script(&query, params)
-    })
-        .await;
-    match result {
-        Ok(Ok(res)) => (StatusCode::OK, res.into_json().into()),
-        Ok(Err(err)) => (
-            StatusCode::BAD_REQUEST,
-            format_error_as_json(err, Some(&src)).into(),
-        ),
-        Err(err) => internal_error(err),
-    }
-}
-
-#[derive(serde_derive::Deserialize)]
-struct FinishTransactPayload {
-    abort: bool,
-}
-
-async fn finish_query(
-    State(st): State<DbState>,
-    Path(id): Path<u32>,
-    Json(payload): Json<FinishTransactPayload>,
-) -> (StatusCode, Json<serde_json::Value>) {
-    let tx = match st.txs.lock().unwrap().remove(&id) {
-        None => return (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
-        Some(tx) => tx,
-    };
-    let res = if payload.abort {
-        tx.abort()
-    } else {
-        tx.commit()
-    };
-    match res {
-        Ok(_) => (StatusCode::OK, json!({"ok": true}).into()),
-        Err(err) => (
-            StatusCode::BAD_REQUEST,
-            json!({"ok": false, "message": err.to_string()}).into(),
-        ),
-    }
-}
-
-#[derive(serde_derive::Deserialize)]
-struct QueryPayload {
-    script: String,
-    params: BTreeMap<String, serde_json::Value>,
-    immutable: Option<bool>,
-}
-
-async fn text_query(
-    Extension(mutability): Extension<ScriptMutability>,
-    State(st): State<DbState>,
-    Json(payload): Json<QueryPayload>,
-) -> (StatusCode, Json<serde_json::Value>) {
-    let params = payload
-        .params
-        .into_iter()
-        .map(|(k, v)| (k, DataValue::from(v)))
-        .collect();
-    let immutable = match mutability {
-        ScriptMutability::Mutable => payload.immutable.unwrap_or(false),
-        ScriptMutability::Immutable => true,
-    };
-    let result = spawn_blocking(move || {
-        st.db.run_script_fold_err(
-            &payload.script,
-            params,
-            if immutable {
-                ScriptMutability::Immutable
-            } else {
-                ScriptMutability::Mutable
-            },
-        )
-    })
-        .await;
-    match result {
-        Ok(res) => wrap_json(res),
-        Err(err) => internal_error(err),
-    }
-}
-
-async fn export_relations(
-    State(st): State<DbState>,
-    Path(relations): Path<String>,
-) -> (StatusCode, Json<serde_json::Value>) {
-    let relations = relations
-        .split(',')
-        .filter_map(|t| {
-            if t.is_empty() {
-                None
-            } else {
-                Some(t.to_string())
-            }
+    axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
         })
-        .collect_vec();
-    let result = spawn_blocking(move || st.db.export_relations(relations.iter())).await;
-    match result {
-        Ok(Ok(s)) => {
-            let s: serde_json::Map<_, _> = s.into_iter().map(|(k, v)| (k, v.into_json())).collect();
-            let ret = json!({"ok": true, "data": s});
-            (StatusCode::OK, ret.into())
-        }
-        Ok(Err(err)) => {
-            let ret = json!({"ok": false, "message": err.to_string()});
-            (StatusCode::BAD_REQUEST, ret.into())
-        }
-        Err(err) => internal_error(err),
-    }
-}
-
-async fn import_relations(
-    State(st): State<DbState>,
-    Json(payload): Json<serde_json::Value>,
-) -> (StatusCode, Json<serde_json::Value>) {
-    let payload = match payload.as_object() {
-        None => {
-            return (
-                StatusCode::BAD_REQUEST,
-                json!({"ok": false, "message": "payload must be a JSON object"}).into(),
-            );
-        }
-        Some(pl) => {
-            let mut ret = BTreeMap::new();
-            for (k, v) in pl {
-                let nr = match NamedRows::from_json(v) {
-                    Ok(p) => p,
-                    Err(err) => {
-                        return (
-                            StatusCode::BAD_REQUEST,
-                            json!({"ok": false, "message": err.to_string()}).into(),
-                        );
-                    }
-                };
-                ret.insert(k.to_string(), nr);
-            }
-            ret
-        }
-    };
-
-    let result = spawn_blocking(move || st.db.import_relations(payload)).await;
-    match result {
-        Ok(Ok(_)) => (StatusCode::OK, json!({"ok": true}).into()),
-        Ok(Err(err)) => {
-            let ret = json!({"ok": false, "message": err.to_string()});
-            (StatusCode::BAD_REQUEST, ret.into())
-        }
-        Err(err) => internal_error(err),
+        .await
+        .unwrap();
+
+    info!("Shutting down");
+    // the reloader holds a clone of the database
+    db.set_config_reloader(None);
+    if let Err(err) = db.close() {
+        eprintln!("Error closing database: {err:?}");
     }
 }
 
@@ -469,9 +436,13 @@ struct BackupPayload {
 }
 
 async fn backup(
+    Extension(role): Extension<ServeRole>,
     State(st): State<DbState>,
     Json(payload): Json<BackupPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
+    if role < ServeRole::Admin {
+        return forbidden(ADMIN_REQUIRED);
+    }
     let result = spawn_blocking(move || st.db.backup_db(payload.path)).await;
 
     match result {
@@ -494,9 +465,13 @@ struct BackupImportPayload {
 }
 
 async fn import_from_backup(
+    Extension(role): Extension<ServeRole>,
     State(st): State<DbState>,
     Json(payload): Json<BackupImportPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
+    if role < ServeRole::Admin {
+        return forbidden(ADMIN_REQUIRED);
+    }
     let result =
         spawn_blocking(move || st.db.import_from_backup(&payload.path, &payload.relations)).await;
 
@@ -626,12 +601,77 @@ async fn register_rule(
     Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
+/// Events buffered for a client of `/changes` by default, beyond which they are dropped
+const DEFAULT_CHANGES_BUFFER: usize = 1024;
+
+/// Interval at which a change stream checks whether its client is gone
+/// or can be told about dropped events
+const CHANGES_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(serde_derive::Deserialize)]
+struct ChangesOptions {
+    /// Comma-separated operations to send, `put` and `rm`; all of them if absent
+    ops: Option<String>,
+    /// A JSON object of column values: only rows having these values are sent
+    filter: Option<String>,
+    /// The number of events buffered for a slow client
+    buffer: Option<usize>,
+}
+
+/// Keep the rows having the values given for their columns
+fn filter_rows(mut rows: NamedRows, filter: &BTreeMap<String, DataValue>) -> NamedRows {
+    if filter.is_empty() {
+        return rows;
+    }
+    let positions = filter
+        .iter()
+        .map(|(col, val)| (rows.headers.iter().position(|h| h == col), val))
+        .collect_vec();
+    rows.rows.retain(|row| {
+        positions
+            .iter()
+            .all(|(pos, val)| matches!(pos, Some(i) if row[*i] == **val))
+    });
+    rows
+}
+
+/// Parse the JSON object given in a query string into values by name
+fn parse_json_object(
+    what: &str,
+    s: Option<&str>,
+) -> Result<BTreeMap<String, DataValue>, String> {
+    match s {
+        None => Ok(BTreeMap::new()),
+        Some(s) => match serde_json::from_str::<serde_json::Value>(s) {
+            Ok(serde_json::Value::Object(obj)) => Ok(obj
+                .into_iter()
+                .map(|(k, v)| (k, DataValue::from(v)))
+                .collect()),
+            _ => Err(format!("{what} must be a JSON object")),
+        },
+    }
+}
+
 async fn observe_changes(
     State(st): State<DbState>,
     Path(relation): Path<String>,
+    Query(opts): Query<ChangesOptions>,
 ) -> Sse<impl Stream<Item=Result<Event, Infallible>>> {
+    let ops = match &opts.ops {
+        None => vec![CallbackOp::Put, CallbackOp::Rm],
+        Some(ops) => ops
+            .split(',')
+            .filter_map(|op| match op.trim() {
+                "put" => Some(CallbackOp::Put),
+                "rm" => Some(CallbackOp::Rm),
+                _ => None,
+            })
+            .collect(),
+    };
+    let filter = parse_json_object("filter", opts.filter.as_deref());
     let (id, recv) = st.db.register_callback(&relation, None);
-    let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+    let (sender, mut receiver) =
+        tokio::sync::mpsc::channel(opts.buffer.unwrap_or(DEFAULT_CHANGES_BUFFER).max(1));
     struct Guard {
         id: u32,
         db: DbInstance,
@@ -645,17 +685,247 @@ async fn observe_changes(
         }
     }
 
+    // Writers are never held back by a slow client: when its buffer is full, events are
+    // dropped, and the client is told how many with a `Lagged` event once there is room.
+    let forward_filter = filter.clone().unwrap_or_default();
     spawn_blocking(move || {
-        for data in recv {
-            sender.blocking_send(data).unwrap();
+        let mut dropped = 0;
+        loop {
+            let item = match recv.recv_timeout(CHANGES_POLL_INTERVAL) {
+                Ok((op, new, old)) => {
+                    if !ops.contains(&op) {
+                        continue;
+                    }
+                    let new = filter_rows(new, &forward_filter);
+                    let old = filter_rows(old, &forward_filter);
+                    if new.rows.is_empty() && old.rows.is_empty() {
+                        continue;
+                    }
+                    Some(json!({"op": op.to_string(), "new_rows": new.into_json(), "old_rows": old.into_json()}))
+                }
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            if dropped > 0 {
+                match sender.try_send(json!({"op": "Lagged", "dropped": dropped})) {
+                    Ok(()) => dropped = 0,
+                    Err(TrySendError::Full(_)) => {}
+                    Err(TrySendError::Closed(_)) => break,
+                }
+            }
+            if let Some(item) = item {
+                if dropped > 0 {
+                    dropped += 1;
+                    continue;
+                }
+                match sender.try_send(item) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => dropped += 1,
+                    Err(TrySendError::Closed(_)) => break,
+                }
+            } else if sender.is_closed() {
+                break;
+            }
         }
     });
     let stream = async_stream::stream! {
         info!("starting changes SSE {}: {}", relation, id);
         let _guard = Guard {id, db: st.db, relation};
-        while let Some((op, new, old)) = receiver.recv().await {
-            let item = json!({"op": op.to_string(), "new_rows": new.into_json(), "old_rows": old.into_json()});
+        if let Err(err) = filter {
+            let item = json!({"op": "Error", "error": err});
             yield Ok(Event::default().json_data(item).unwrap());
+        } else {
+            while let Some(item) = receiver.recv().await {
+                yield Ok(Event::default().json_data(item).unwrap());
+            }
+        }
+    };
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[derive(serde_derive::Deserialize)]
+struct LiveQueryOptions {
+    script: String,
+    /// The parameters of the script as a JSON object
+    params: Option<String>,
+    /// Comma-separated stored relations whose changes make the query run again
+    relations: String,
+}
+
+/// The rows of a result with the number of times each occurs
+fn count_rows(rows: Vec<Vec<DataValue>>) -> BTreeMap<Vec<DataValue>, usize> {
+    let mut counts = BTreeMap::new();
+    for row in rows {
+        *counts.entry(row).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// The rows of `new` not in `old`, as a JSON array, each as many times as it is missing
+fn missing_rows(
+    new: &BTreeMap<Vec<DataValue>, usize>,
+    old: &BTreeMap<Vec<DataValue>, usize>,
+) -> Vec<serde_json::Value> {
+    let mut ret = vec![];
+    for (row, n) in new {
+        let m = old.get(row).copied().unwrap_or(0);
+        for _ in m..*n {
+            ret.push(row.iter().cloned().map(serde_json::Value::from).collect());
+        }
+    }
+    ret
+}
+
+/// A standing query: its result is sent first, then the rows added to and removed from it
+/// whenever one of the relations it reads changes. Changes arriving while the client is
+/// catching up are coalesced into one run of the query.
+async fn live_query(
+    Extension(role): Extension<ServeRole>,
+    State(st): State<DbState>,
+    Query(opts): Query<LiveQueryOptions>,
+) -> Sse<impl Stream<Item=Result<Event, Infallible>>> {
+    let params = parse_json_object("params", opts.params.as_deref());
+    let (notify_send, mut notify_recv) = tokio::sync::mpsc::channel(1);
+    let mut ids = vec![];
+    for relation in opts.relations.split(',').filter(|r| !r.is_empty()) {
+        let (id, recv) = st.db.register_callback(relation, None);
+        ids.push(id);
+        let notify_send = notify_send.clone();
+        spawn_blocking(move || {
+            for _ in recv {
+                if let Err(TrySendError::Closed(_)) = notify_send.try_send(()) {
+                    break;
+                }
+            }
+        });
+    }
+    drop(notify_send);
+    struct Guard {
+        ids: Vec<u32>,
+        db: DbInstance,
+    }
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            info!("dropping live query SSE {:?}", self.ids);
+            for id in &self.ids {
+                self.db.unregister_callback(*id);
+            }
+        }
+    }
+
+    let stream = async_stream::stream! {
+        info!("starting live query SSE {:?}", ids);
+        let _guard = Guard {ids, db: st.db.clone()};
+        let params = match params {
+            Ok(params) => params,
+            Err(err) => {
+                let item = json!({"type": "error", "error": err});
+                yield Ok(Event::default().json_data(item).unwrap());
+                return;
+            }
+        };
+        let db = st.db.with_unmask(role.unmask());
+        let mut last = None;
+        loop {
+            let db = db.clone();
+            let script = opts.script.clone();
+            let params = params.clone();
+            let result = spawn_blocking(move || {
+                if role < ServeRole::Admin && db.script_requires_admin(&script, &params).unwrap_or(false) {
+                    return Err(ADMIN_REQUIRED.to_string());
+                }
+                db.run_script(&script, params, ScriptMutability::Immutable)
+                    .map_err(|err| err.to_string())
+            })
+            .await;
+            let res = match result.map_err(|err| err.to_string()) {
+                Ok(Ok(res)) => res,
+                Ok(Err(err)) | Err(err) => {
+                    let item = json!({"type": "error", "error": err});
+                    yield Ok(Event::default().json_data(item).unwrap());
+                    break;
+                }
+            };
+            let headers = res.headers;
+            let rows = count_rows(res.rows);
+            match &last {
+                None => {
+                    let all = missing_rows(&rows, &BTreeMap::new());
+                    let item = json!({"type": "snapshot", "headers": headers, "rows": all});
+                    yield Ok(Event::default().json_data(item).unwrap());
+                }
+                Some(last) => {
+                    let added = missing_rows(&rows, last);
+                    let removed = missing_rows(last, &rows);
+                    if !added.is_empty() || !removed.is_empty() {
+                        let item = json!({"type": "delta", "added": added, "removed": removed});
+                        yield Ok(Event::default().json_data(item).unwrap());
+                    }
+                }
+            }
+            last = Some(rows);
+            if notify_recv.recv().await.is_none() {
+                break;
+            }
+        }
+    };
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Interval at which the state of a job is polled for changes
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+async fn observe_job(
+    State(st): State<DbState>,
+    Path(id): Path<i64>,
+) -> Sse<impl Stream<Item=Result<Event, Infallible>>> {
+    let stream = async_stream::stream! {
+        info!("starting job SSE {}", id);
+        let mut last = None;
+        loop {
+            let db = st.db.clone();
+            let jobs = spawn_blocking(move || {
+                db.run_script("::jobs", Default::default(), ScriptMutability::Immutable)
+            })
+            .await;
+            let jobs = match jobs.map_err(|err| err.to_string()) {
+                Ok(Ok(jobs)) => jobs,
+                Ok(Err(err)) => {
+                    let item = json!({"type": "error", "error": err.to_string()});
+                    yield Ok(Event::default().json_data(item).unwrap());
+                    break;
+                }
+                Err(err) => {
+                    let item = json!({"type": "error", "error": err});
+                    yield Ok(Event::default().json_data(item).unwrap());
+                    break;
+                }
+            };
+            let row = jobs.rows.iter().find(|row| row[0] == DataValue::from(id));
+            let row = match row {
+                None => {
+                    let item = json!({"type": "not-found", "id": id});
+                    yield Ok(Event::default().json_data(item).unwrap());
+                    break;
+                }
+                Some(row) => row,
+            };
+            let item: serde_json::Map<_, _> = jobs
+                .headers
+                .iter()
+                .cloned()
+                .zip(row.iter().map(|v| serde_json::Value::from(v.clone())))
+                .collect();
+            let finished = !matches!(row[1].get_str(), Some("queued" | "running" | "killing"));
+            if last.as_ref() != Some(&item) {
+                yield Ok(Event::default().json_data(&item).unwrap());
+                last = Some(item);
+            }
+            if finished {
+                break;
+            }
+            tokio::time::sleep(JOB_POLL_INTERVAL).await;
         }
     };
     Sse::new(stream).keep_alive(KeepAlive::default())
@@ -675,13 +945,13 @@ fn internal_error<E>(err: E) -> (StatusCode, Json<serde_json::Value>)
     )
 }
 
-fn wrap_json(json: serde_json::Value) -> (StatusCode, Json<serde_json::Value>) {
-    let code = if let Some(serde_json::Value::Bool(true)) = json.get("ok") {
-        StatusCode::OK
-    } else {
-        StatusCode::BAD_REQUEST
-    };
-    (code, json.into())
+const ADMIN_REQUIRED: &str = "this operation requires an admin token";
+
+fn forbidden(message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::FORBIDDEN,
+        json!({"ok": false, "message": message}).into(),
+    )
 }
 
 pub async fn not_found(uri: axum::http::Uri) -> (StatusCode, Json<serde_json::Value>) {
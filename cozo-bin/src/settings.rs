@@ -0,0 +1,241 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use log::{info, LevelFilter};
+use miette::{miette, IntoDiagnostic, WrapErr};
+
+use cozo::{DataValue, DbInstance, NamedRows, ScriptMutability};
+
+use crate::logger::ServerLogger;
+
+/// The settings of the server that can be changed while it runs, by sending it SIGHUP
+/// or running `::config reload`. Settings left out of the settings file keep the values
+/// given on the command line.
+#[derive(Clone, Debug)]
+pub(crate) struct Settings {
+    pub(crate) log_level: LevelFilter,
+    pub(crate) read_only: bool,
+    pub(crate) audit: bool,
+    pub(crate) storage_stats: bool,
+    pub(crate) index_fallback: bool,
+    pub(crate) replay_log: Option<String>,
+    pub(crate) token_table: Option<String>,
+    /// The most rows a query may return, see [DbInstance::set_result_limits]
+    pub(crate) max_rows: Option<usize>,
+    /// The most bytes a query may return, see [DbInstance::set_result_limits]
+    pub(crate) max_bytes: Option<usize>,
+}
+
+/// The content of the settings file, in JSON
+#[derive(serde_derive::Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct SettingsFile {
+    log_level: Option<String>,
+    read_only: Option<bool>,
+    audit: Option<bool>,
+    storage_stats: Option<bool>,
+    index_fallback: Option<bool>,
+    /// An empty string stops recording
+    replay_log: Option<String>,
+    /// An empty string stops looking tokens up in a table
+    token_table: Option<String>,
+    max_rows: Option<usize>,
+    max_bytes: Option<usize>,
+}
+
+fn non_empty(s: String) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+impl Settings {
+    /// The settings with those in the file at `path` taking precedence
+    fn overlay(&self, path: &str) -> miette::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("cannot read the settings file {path}"))?;
+        let file: SettingsFile = serde_json::from_str(&text)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("invalid settings file {path}"))?;
+        let log_level = match file.log_level {
+            None => self.log_level,
+            Some(level) => parse_log_level(&level)?,
+        };
+        Ok(Self {
+            log_level,
+            read_only: file.read_only.unwrap_or(self.read_only),
+            audit: file.audit.unwrap_or(self.audit),
+            storage_stats: file.storage_stats.unwrap_or(self.storage_stats),
+            index_fallback: file.index_fallback.unwrap_or(self.index_fallback),
+            replay_log: file
+                .replay_log
+                .map_or_else(|| self.replay_log.clone(), non_empty),
+            token_table: file
+                .token_table
+                .map_or_else(|| self.token_table.clone(), non_empty),
+            max_rows: file.max_rows.or(self.max_rows),
+            max_bytes: file.max_bytes.or(self.max_bytes),
+        })
+    }
+    fn to_rows(&self) -> NamedRows {
+        let opt_str = |s: &Option<String>| match s {
+            None => DataValue::Null,
+            Some(s) => DataValue::from(s.as_str()),
+        };
+        let opt_int = |n: Option<usize>| match n {
+            None => DataValue::Null,
+            Some(n) => DataValue::from(n as i64),
+        };
+        NamedRows::new(
+            vec!["setting".to_string(), "value".to_string()],
+            vec![
+                ("log_level", DataValue::from(self.log_level.as_str())),
+                ("read_only", DataValue::from(self.read_only)),
+                ("audit", DataValue::from(self.audit)),
+                ("storage_stats", DataValue::from(self.storage_stats)),
+                ("index_fallback", DataValue::from(self.index_fallback)),
+                ("replay_log", opt_str(&self.replay_log)),
+                ("token_table", opt_str(&self.token_table)),
+                ("max_rows", opt_int(self.max_rows)),
+                ("max_bytes", opt_int(self.max_bytes)),
+            ]
+            .into_iter()
+            .map(|(k, v)| vec![DataValue::from(k), v])
+            .collect(),
+        )
+    }
+}
+
+pub(crate) fn parse_log_level(level: &str) -> miette::Result<LevelFilter> {
+    LevelFilter::from_str(level).map_err(|_| {
+        miette!("invalid log level '{level}', expected one of off, error, warn, info, debug, trace")
+    })
+}
+
+/// The level set by `RUST_LOG`, if it is a plain level, info otherwise
+pub(crate) fn env_log_level() -> LevelFilter {
+    std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|level| LevelFilter::from_str(&level).ok())
+        .unwrap_or(LevelFilter::Info)
+}
+
+/// What requests are authorized against, replaced as a whole when the settings are reloaded
+pub(crate) struct AuthConfig {
+    pub(crate) skip_auth: bool,
+    pub(crate) read_only: bool,
+    pub(crate) auth_guard: String,
+    token_table: Option<String>,
+    /// The query looking up a token in `token_table`, built on first use
+    token_query: OnceLock<String>,
+}
+
+impl AuthConfig {
+    /// The query looking up a token, returning whether it may mutate and whether it is an admin
+    pub(crate) fn token_query(&self, db: &DbInstance) -> Option<&str> {
+        let name = self.token_table.as_ref()?;
+        Some(self.token_query.get_or_init(|| {
+            // the `admin` column is optional, so that existing token tables keep working
+            let has_admin_col = db
+                .run_script(
+                    &format!("::columns {name}"),
+                    Default::default(),
+                    ScriptMutability::Immutable,
+                )
+                .map(|cols| {
+                    cols.rows
+                        .iter()
+                        .any(|row| row[0].get_str() == Some("admin"))
+                })
+                .unwrap_or(false);
+            if has_admin_col {
+                format!("?[mutable, admin] := *{name} {{ token: $token, mutable, admin }}")
+            } else {
+                format!("?[mutable, admin] := *{name} {{ token: $token, mutable }}, admin = false")
+            }
+        }))
+    }
+}
+
+/// Applies the settings to the database and the authorization layer, at startup and on
+/// every reload
+pub(crate) struct Reloader {
+    db: DbInstance,
+    /// The settings given on the command line
+    defaults: Settings,
+    /// The settings file, if any
+    settings_path: Option<String>,
+    /// The file holding the auth token, unless bound to localhost
+    auth_path: Option<String>,
+    logger: &'static ServerLogger,
+    pub(crate) auth: Arc<RwLock<Arc<AuthConfig>>>,
+}
+
+impl Reloader {
+    pub(crate) fn new(
+        db: DbInstance,
+        defaults: Settings,
+        settings_path: Option<String>,
+        auth_path: Option<String>,
+        logger: &'static ServerLogger,
+    ) -> Self {
+        Self {
+            db,
+            defaults,
+            settings_path,
+            auth_path,
+            logger,
+            auth: Arc::new(RwLock::new(Arc::new(AuthConfig {
+                skip_auth: true,
+                read_only: true,
+                auth_guard: String::new(),
+                token_table: None,
+                token_query: OnceLock::new(),
+            }))),
+        }
+    }
+    /// Read the settings file and the auth token again, and apply them, and open the
+    /// log file again. Nothing is changed if either file cannot be read.
+    pub(crate) fn reload(&self) -> miette::Result<NamedRows> {
+        let settings = match &self.settings_path {
+            None => self.defaults.clone(),
+            Some(path) => self.defaults.overlay(path)?,
+        };
+        let auth_guard = match &self.auth_path {
+            None => String::new(),
+            Some(path) => std::fs::read_to_string(path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("cannot read the auth token file {path}"))?
+                .trim()
+                .to_string(),
+        };
+        self.logger.reopen()?;
+        self.db.set_replay_log(settings.replay_log.as_deref())?;
+        self.db.set_audit_log(settings.audit);
+        self.db.set_storage_stats(settings.storage_stats);
+        self.db.set_index_fallback(settings.index_fallback);
+        self.db
+            .set_result_limits(settings.max_rows, settings.max_bytes);
+        log::set_max_level(settings.log_level);
+        *self.auth.write().unwrap() = Arc::new(AuthConfig {
+            skip_auth: self.auth_path.is_none(),
+            read_only: settings.read_only,
+            auth_guard,
+            token_table: settings.token_table.clone(),
+            token_query: OnceLock::new(),
+        });
+        info!("Settings applied: {settings:?}");
+        Ok(settings.to_rows())
+    }
+}
@@ -0,0 +1,328 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::{Error, Params, Prepared, Result, RetryPolicy, Rows};
+
+#[derive(Clone)]
+enum Auth {
+    None,
+    /// The token generated by the server, sent as `x-cozo-auth`
+    Guard(String),
+    /// A token from the server's token table, sent as a bearer token
+    Bearer(String),
+}
+
+/// Builder for [Client]
+pub struct ClientBuilder {
+    base_url: String,
+    auth: Auth,
+    retry: RetryPolicy,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+}
+
+impl ClientBuilder {
+    /// Authenticate with the token the server generated at startup
+    pub fn auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth = Auth::Guard(token.into());
+        self
+    }
+    /// Authenticate with a token from the server's token table
+    pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.auth = Auth::Bearer(token.into());
+        self
+    }
+    /// How failed requests are retried, the default is [RetryPolicy::default]
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+    /// Maximum number of idle connections kept open in the pool, the default is 32
+    pub fn pool_max_idle(mut self, n: usize) -> Self {
+        self.pool_max_idle_per_host = n;
+        self
+    }
+    /// How long idle connections are kept in the pool, the default is 90 seconds
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+    /// Timeout of each request, including reading the response. No timeout by default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Timeout for establishing connections. No timeout by default
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+    /// Build the client
+    pub fn build(self) -> Result<Client> {
+        let mut http = reqwest::Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(
+                self.pool_idle_timeout
+                    .unwrap_or_else(|| Duration::from_secs(90)),
+            );
+        if let Some(timeout) = self.timeout {
+            http = http.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            http = http.connect_timeout(timeout);
+        }
+        Ok(Client {
+            inner: Arc::new(ClientInner {
+                http: http.build()?,
+                base_url: self.base_url.trim_end_matches('/').to_string(),
+                auth: self.auth,
+                retry: self.retry,
+            }),
+        })
+    }
+}
+
+struct ClientInner {
+    http: reqwest::Client,
+    base_url: String,
+    auth: Auth,
+    retry: RetryPolicy,
+}
+
+/// An async client for the Cozo server.
+///
+/// Connections are pooled and reused across requests. Cloning is cheap and clones share
+/// the pool, so a single client should be created and shared by the whole application.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<ClientInner>,
+}
+
+impl Debug for Client {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Client({})", self.inner.base_url)
+    }
+}
+
+impl Client {
+    /// Create a client with the default settings, e.g. `Client::new("http://127.0.0.1:9070")`
+    pub fn new(base_url: impl Into<String>) -> Result<Self> {
+        Self::builder(base_url).build()
+    }
+
+    /// Create a builder for a client connecting to `base_url`
+    pub fn builder(base_url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            base_url: base_url.into(),
+            auth: Auth::None,
+            retry: RetryPolicy::default(),
+            pool_max_idle_per_host: 32,
+            pool_idle_timeout: None,
+            timeout: None,
+            connect_timeout: None,
+        }
+    }
+
+    /// Run a script that may mutate the database
+    pub async fn run(&self, script: &str, params: Params) -> Result<Rows> {
+        self.text_query(script, params, false).await
+    }
+
+    /// Run a script that may not mutate the database. Such scripts are safe to retry.
+    pub async fn query(&self, script: &str, params: Params) -> Result<Rows> {
+        self.text_query(script, params, true).await
+    }
+
+    /// Run an immutable script and convert the resulting rows into `T`, see [Rows::to_typed]
+    pub async fn query_as<T: DeserializeOwned>(
+        &self,
+        script: &str,
+        params: Params,
+    ) -> Result<Vec<T>> {
+        self.query(script, params).await?.to_typed()
+    }
+
+    /// Prepare a script that may mutate the database, for repeated execution
+    pub fn prepare(&self, script: &str) -> Prepared {
+        Prepared::new(self.clone(), script, false)
+    }
+
+    /// Prepare a script that may not mutate the database, for repeated execution
+    pub fn prepare_query(&self, script: &str) -> Prepared {
+        Prepared::new(self.clone(), script, true)
+    }
+
+    /// Start a multi-statement transaction
+    pub async fn transaction(&self, write: bool) -> Result<Transaction> {
+        let url = format!("{}/transact?write={write}", self.inner.base_url);
+        let res = self
+            .send(!write, || self.inner.http.post(&url))
+            .await?;
+        let id = res
+            .get("id")
+            .and_then(|id| id.as_u64())
+            .ok_or_else(|| Error::Protocol("missing transaction id".to_string()))?;
+        Ok(Transaction {
+            client: self.clone(),
+            id,
+            write,
+            finished: false,
+        })
+    }
+
+    pub(crate) async fn text_query(
+        &self,
+        script: &str,
+        params: Params,
+        immutable: bool,
+    ) -> Result<Rows> {
+        let url = format!("{}/text-query", self.inner.base_url);
+        let body = json!({"script": script, "params": params, "immutable": immutable});
+        let res = self
+            .send(immutable, || self.inner.http.post(&url).json(&body))
+            .await?;
+        serde_json::from_value(res).map_err(|e| Error::Protocol(e.to_string()))
+    }
+
+    async fn send(&self, idempotent: bool, make: impl Fn() -> RequestBuilder) -> Result<Value> {
+        let mut attempt = 0;
+        loop {
+            match self.send_once(make()).await {
+                Ok(v) => return Ok(v),
+                Err(err) => {
+                    if !self.inner.retry.should_retry(&err, attempt, !idempotent) {
+                        return Err(err);
+                    }
+                    tokio::time::sleep(self.inner.retry.backoff(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn send_once(&self, req: RequestBuilder) -> Result<Value> {
+        let req = match &self.inner.auth {
+            Auth::None => req,
+            Auth::Guard(token) => req.header("x-cozo-auth", token),
+            Auth::Bearer(token) => req.bearer_auth(token),
+        };
+        let resp = req.send().await?;
+        let status = resp.status();
+        if status == StatusCode::UNAUTHORIZED {
+            return Err(Error::Unauthorized);
+        }
+        let text = resp.text().await?;
+        let body: Value = match serde_json::from_str(&text) {
+            Ok(body) => body,
+            // e.g. a proxy in front of the server reporting an error
+            Err(_) if !status.is_success() => {
+                return Err(Error::Query {
+                    status: status.as_u16(),
+                    message: text,
+                    display: None,
+                });
+            }
+            Err(err) => return Err(Error::Protocol(err.to_string())),
+        };
+        // results of queries in transactions carry no `ok` field
+        if status.is_success() && body.get("ok") != Some(&Value::Bool(false)) {
+            return Ok(body);
+        }
+        let message = body
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or_default()
+            .to_string();
+        if status == StatusCode::FORBIDDEN {
+            return Err(Error::Forbidden(message));
+        }
+        Err(Error::Query {
+            status: status.as_u16(),
+            message,
+            display: body
+                .get("display")
+                .and_then(|d| d.as_str())
+                .map(|d| d.to_string()),
+        })
+    }
+}
+
+/// A multi-statement transaction running on the server.
+///
+/// Finish it with [Transaction::commit] or [Transaction::abort]. A transaction
+/// dropped without being finished is aborted in the background.
+pub struct Transaction {
+    client: Client,
+    id: u64,
+    write: bool,
+    finished: bool,
+}
+
+impl Transaction {
+    /// Run a script within the transaction
+    pub async fn run(&self, script: &str, params: Params) -> Result<Rows> {
+        let url = format!("{}/transact/{}", self.client.inner.base_url, self.id);
+        let body = json!({"script": script, "params": params});
+        let res = self
+            .client
+            .send(!self.write, || self.client.inner.http.post(&url).json(&body))
+            .await?;
+        serde_json::from_value(res).map_err(|e| Error::Protocol(e.to_string()))
+    }
+
+    /// Run a script within the transaction and convert the resulting rows into `T`
+    pub async fn run_as<T: DeserializeOwned>(&self, script: &str, params: Params) -> Result<Vec<T>> {
+        self.run(script, params).await?.to_typed()
+    }
+
+    /// Commit the transaction
+    pub async fn commit(mut self) -> Result<()> {
+        self.finished = true;
+        finish(&self.client, self.id, false).await
+    }
+
+    /// Abort the transaction
+    pub async fn abort(mut self) -> Result<()> {
+        self.finished = true;
+        finish(&self.client, self.id, true).await
+    }
+}
+
+async fn finish(client: &Client, id: u64, abort: bool) -> Result<()> {
+    let url = format!("{}/transact/{id}", client.inner.base_url);
+    let body = json!({ "abort": abort });
+    client
+        .send(false, || client.inner.http.put(&url).json(&body))
+        .await?;
+    Ok(())
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let client = self.client.clone();
+            let id = self.id;
+            handle.spawn(async move {
+                let _ = finish(&client, id, true).await;
+            });
+        }
+    }
+}
@@ -0,0 +1,50 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use thiserror::Error;
+
+/// Errors returned by the client
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The request could not be sent, or the response could not be read
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The server rejected the query
+    #[error("{message}")]
+    Query {
+        /// HTTP status code of the response
+        status: u16,
+        /// The error message
+        message: String,
+        /// A formatted diagnostic, if the server provided one
+        display: Option<String>,
+    },
+    /// The auth token was missing or not accepted
+    #[error("unauthorized")]
+    Unauthorized,
+    /// The token is valid, but does not allow the operation
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    /// A prepared statement was run without one of its parameters
+    #[error("missing parameter '${0}'")]
+    MissingParam(String),
+    /// A row could not be converted into the requested type
+    #[error("cannot deserialize row {row}: {source}")]
+    Deserialize {
+        /// Index of the offending row
+        row: usize,
+        /// The underlying error
+        source: serde_json::Error,
+    },
+    /// The server responded with something that is not a Cozo response
+    #[error("unexpected response from server: {0}")]
+    Protocol(String),
+}
+
+/// Result type of the client
+pub type Result<T> = std::result::Result<T, Error>;
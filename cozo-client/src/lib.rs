@@ -0,0 +1,61 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! An async client for the standalone [CozoDB](https://cozodb.org) server (`cozo server`).
+//!
+//! ```no_run
+//! use std::collections::BTreeMap;
+//!
+//! use cozo_client::Client;
+//! use serde_json::json;
+//!
+//! #[derive(serde_derive::Deserialize)]
+//! struct Person {
+//!     name: String,
+//!     age: i64,
+//! }
+//!
+//! # async fn example() -> cozo_client::Result<()> {
+//! let client = Client::builder("http://127.0.0.1:9070").build()?;
+//! client
+//!     .run(":create person {name: String => age: Int}", Default::default())
+//!     .await?;
+//!
+//! let insert = client.prepare("?[name, age] <- [[$name, $age]] :put person {name => age}");
+//! insert
+//!     .run(BTreeMap::from([
+//!         ("name".to_string(), json!("alice")),
+//!         ("age".to_string(), json!(30)),
+//!     ]))
+//!     .await?;
+//!
+//! let people: Vec<Person> = client
+//!     .query_as("?[name, age] := *person{name, age}", Default::default())
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+#![warn(rust_2018_idioms, future_incompatible)]
+#![warn(missing_docs)]
+
+use std::collections::BTreeMap;
+
+pub use client::{Client, ClientBuilder, Transaction};
+pub use error::{Error, Result};
+pub use prepared::Prepared;
+pub use retry::RetryPolicy;
+pub use rows::{Column, Rows};
+
+mod client;
+mod error;
+mod prepared;
+mod retry;
+mod rows;
+
+/// Named parameters of a script, referenced as `$name` in the script
+pub type Params = BTreeMap<String, serde_json::Value>;
@@ -0,0 +1,159 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+
+use crate::{Client, Error, Params, Result, Rows};
+
+/// A script prepared for repeated execution with different parameters.
+///
+/// The parameters referenced by the script are collected once, when it is prepared,
+/// and every execution is checked against them before anything is sent.
+/// Cloning is cheap.
+#[derive(Debug, Clone)]
+pub struct Prepared {
+    client: Client,
+    script: Arc<str>,
+    params: Arc<[String]>,
+    immutable: bool,
+}
+
+impl Prepared {
+    pub(crate) fn new(client: Client, script: &str, immutable: bool) -> Self {
+        Self {
+            client,
+            script: script.into(),
+            params: script_params(script).into_iter().collect(),
+            immutable,
+        }
+    }
+
+    /// The script
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+
+    /// Names of the parameters referenced by the script, without the leading `$`
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    /// Run the script
+    pub async fn run(&self, params: Params) -> Result<Rows> {
+        if let Some(missing) = self.params.iter().find(|p| !params.contains_key(*p)) {
+            return Err(Error::MissingParam(missing.clone()));
+        }
+        self.client
+            .text_query(&self.script, params, self.immutable)
+            .await
+    }
+
+    /// Run the script and convert the resulting rows into `T`, see [Rows::to_typed]
+    pub async fn run_as<T: DeserializeOwned>(&self, params: Params) -> Result<Vec<T>> {
+        self.run(params).await?.to_typed()
+    }
+}
+
+/// Collect the names of the parameters (`$name`) in a script, skipping strings and comments
+pub(crate) fn script_params(script: &str) -> BTreeSet<String> {
+    let mut ret = BTreeSet::new();
+    let chars: Vec<char> = script.chars().collect();
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '$' => {
+                let start = i + 1;
+                i = start;
+                while i < chars.len() && is_ident(chars[i]) {
+                    i += 1;
+                }
+                if i > start {
+                    ret.insert(chars[start..i].iter().collect());
+                }
+                continue;
+            }
+            q @ ('"' | '\'') => {
+                i += 1;
+                while i < chars.len() && chars[i] != q {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            '_' if i == 0 || !is_ident(chars[i - 1]) => {
+                // raw strings are delimited by `_"` and `"_`, with any number of underscores
+                let start = i;
+                while i < chars.len() && chars[i] == '_' {
+                    i += 1;
+                }
+                if i < chars.len() && chars[i] == '"' {
+                    let n = i - start;
+                    i += 1;
+                    while i < chars.len()
+                        && !(chars[i] == '"'
+                            && chars[i + 1..].iter().take(n).filter(|c| **c == '_').count() == n)
+                    {
+                        i += 1;
+                    }
+                    i += n;
+                } else {
+                    continue;
+                }
+            }
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let mut depth = 0;
+                while i < chars.len() {
+                    if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                        depth += 1;
+                        i += 1;
+                    } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                        depth -= 1;
+                        i += 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_params() {
+        let script = r#"
+            # $commented
+            /* $block /* $nested */ $still_block */
+            ?[a, b] := a = $first, b = concat("$quoted", '$single', __"$raw "_"__, $second)
+            :limit $lim
+        "#;
+        assert_eq!(
+            script_params(script).into_iter().collect::<Vec<_>>(),
+            vec!["first", "lim", "second"]
+        );
+        assert!(script_params("?[a] := a = get($obj.inner, 0)").contains("obj.inner"));
+    }
+}
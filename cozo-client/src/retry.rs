@@ -0,0 +1,100 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::time::Duration;
+
+use crate::Error;
+
+/// When and how often failed requests are retried.
+///
+/// Only transient failures are retried: connection failures, timeouts, and
+/// `502`, `503` and `504` responses. A mutating request that may have reached
+/// the server is retried only if `retry_mutations` is set, since it is not
+/// necessarily idempotent.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every further retry
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between retries
+    pub max_backoff: Duration,
+    /// Whether mutating requests are retried after they may have reached the server
+    pub retry_mutations: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retry_mutations: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    pub(crate) fn should_retry(&self, err: &Error, attempt: u32, mutating: bool) -> bool {
+        if attempt >= self.max_retries {
+            return false;
+        }
+        match err {
+            // the connection was never established, so the server has not seen the request
+            Error::Http(e) if e.is_connect() => true,
+            Error::Http(e) if e.is_timeout() => !mutating || self.retry_mutations,
+            Error::Query { status, .. } if matches!(status, 502..=504) => {
+                !mutating || self.retry_mutations
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(3), Duration::from_millis(800));
+        assert_eq!(policy.backoff(10), Duration::from_secs(5));
+        assert_eq!(policy.backoff(64), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn mutations_are_not_retried_by_default() {
+        let policy = RetryPolicy::default();
+        let unavailable = Error::Query {
+            status: 503,
+            message: String::new(),
+            display: None,
+        };
+        assert!(policy.should_retry(&unavailable, 0, false));
+        assert!(!policy.should_retry(&unavailable, 0, true));
+        assert!(!policy.should_retry(&unavailable, 3, false));
+        assert!(!RetryPolicy::none().should_retry(&unavailable, 0, false));
+    }
+}
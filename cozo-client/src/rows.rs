@@ -0,0 +1,135 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{Error, Result};
+
+/// Rows returned by a query
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Rows {
+    /// Names of the columns
+    pub headers: Vec<String>,
+    /// The rows, each with one value per header
+    pub rows: Vec<Vec<Value>>,
+    /// Result of the next query of a chain, if any
+    #[serde(default)]
+    pub next: Option<Box<Rows>>,
+    /// Seconds taken by the server to run the query
+    #[serde(default)]
+    pub took: Option<f64>,
+    /// Type and origin of each column, in the order of the headers.
+    /// Empty if not sent by the server.
+    #[serde(default)]
+    pub columns: Vec<Column>,
+}
+
+/// Type and origin of a column of [Rows]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Column {
+    /// The type of the values, written as in schemas, e.g. `Int`, `Bytes?` or `<F32;128>`
+    #[serde(rename = "type")]
+    pub typing: String,
+    /// The stored relation the values are read from, if known
+    #[serde(default)]
+    pub relation: Option<String>,
+    /// The column of `relation` the values are read from
+    #[serde(default)]
+    pub column: Option<String>,
+}
+
+impl Rows {
+    /// Convert each row into `T`.
+    ///
+    /// Every row is presented to `T` as a map from headers to values, so a struct
+    /// deserializes from rows whose headers match its field names.
+    pub fn to_typed<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                let obj: Map<String, Value> = self
+                    .headers
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().cloned())
+                    .collect();
+                serde_json::from_value(Value::Object(obj))
+                    .map_err(|source| Error::Deserialize { row: i, source })
+            })
+            .collect()
+    }
+
+    /// Convert each row into `T`, presenting rows as sequences, e.g. for tuples
+    pub fn to_tuples<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                serde_json::from_value(Value::Array(row.clone()))
+                    .map_err(|source| Error::Deserialize { row: i, source })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_derive::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Person {
+        name: String,
+        age: Option<u32>,
+    }
+
+    #[test]
+    fn typed_rows() {
+        let rows: Rows = serde_json::from_value(json!({
+            "ok": true,
+            "headers": ["name", "age"],
+            "rows": [["alice", 30], ["bob", null]],
+            "next": null,
+            "took": 0.001,
+            "columns": [
+                {"type": "String", "relation": "person", "column": "name"},
+                {"type": "Int?", "relation": null, "column": null}
+            ]
+        }))
+        .unwrap();
+        assert_eq!(rows.columns[0].typing, "String");
+        assert_eq!(rows.columns[0].relation.as_deref(), Some("person"));
+        assert_eq!(rows.columns[1].column, None);
+        assert_eq!(
+            rows.to_typed::<Person>().unwrap(),
+            vec![
+                Person {
+                    name: "alice".to_string(),
+                    age: Some(30)
+                },
+                Person {
+                    name: "bob".to_string(),
+                    age: None
+                }
+            ]
+        );
+        assert_eq!(
+            rows.to_tuples::<(String, Option<u32>)>().unwrap(),
+            vec![("alice".to_string(), Some(30)), ("bob".to_string(), None)]
+        );
+        match rows.to_tuples::<(u32, u32)>() {
+            Err(Error::Deserialize { row: 0, .. }) => {}
+            r => panic!("unexpected {r:?}"),
+        }
+    }
+}
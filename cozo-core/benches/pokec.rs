@@ -105,16 +105,15 @@ lazy_static! {
                     mem::swap(&mut new_rows, &mut users);
                     db.import_relations(BTreeMap::from([(
                         "user".to_string(),
-                        NamedRows {
-                            headers: vec![
+                        NamedRows::new(
+                            vec![
                                 "uid".to_string(),
                                 "cmpl_pct".to_string(),
                                 "gender".to_string(),
                                 "age".to_string(),
                             ],
-                            rows: new_rows,
-                            next: None
-                        },
+                            new_rows,
+                        ),
                     )]))
                     .unwrap();
                 }
@@ -130,19 +129,17 @@ lazy_static! {
                     db.import_relations(BTreeMap::from([
                         (
                             "friends".to_string(),
-                            NamedRows {
-                                headers: vec!["fr".to_string(), "to".to_string()],
-                                rows: new_rows.clone(),
-                                next: None,
-                            },
+                            NamedRows::new(
+                                vec!["fr".to_string(), "to".to_string()],
+                                new_rows.clone(),
+                            ),
                         ),
                         (
                             "friends.rev".to_string(),
-                            NamedRows {
-                                headers: vec!["fr".to_string(), "to".to_string()],
-                                rows: new_rows,
-                                next: None,
-                            },
+                            NamedRows::new(
+                                vec!["fr".to_string(), "to".to_string()],
+                                new_rows,
+                            ),
                         ),
                     ]))
                     .unwrap();
@@ -25,11 +25,10 @@ fn insert_data(db: &DbInstance) {
     let mut to_import = BTreeMap::new();
     to_import.insert(
         "plain".to_string(),
-        NamedRows {
-            headers: vec!["k".to_string(), "v".to_string()],
-            rows: (0..10000).map(|i| vec![DataValue::from(i as i64), DataValue::from(i as i64)]).collect_vec(),
-            next: None,
-        },
+        NamedRows::new(
+            vec!["k".to_string(), "v".to_string()],
+            (0..10000).map(|i| vec![DataValue::from(i as i64), DataValue::from(i as i64)]).collect_vec(),
+        ),
     );
     db.import_relations(to_import).unwrap();
     dbg!(insert_plain_time.elapsed());
@@ -38,17 +37,16 @@ fn insert_data(db: &DbInstance) {
     let mut to_import = BTreeMap::new();
     to_import.insert(
         "tt1".to_string(),
-        NamedRows {
-            headers: vec!["k".to_string(), "vld".to_string(), "v".to_string()],
-            rows: (0..10000)
+        NamedRows::new(
+            vec!["k".to_string(), "vld".to_string(), "v".to_string()],
+            (0..10000)
                 .map(|i| vec![
                     DataValue::from(i as i64),
                     DataValue::Validity(Validity::from((0, true))),
                     DataValue::from(i as i64),
                 ])
                 .collect_vec(),
-            next: None,
-        },
+        ),
     );
     db.import_relations(to_import).unwrap();
     dbg!(insert_tt1_time.elapsed());
@@ -57,17 +55,16 @@ fn insert_data(db: &DbInstance) {
     let mut to_import = BTreeMap::new();
     to_import.insert(
         "tt10".to_string(),
-        NamedRows {
-            headers: vec!["k".to_string(), "vld".to_string(), "v".to_string()],
-            rows: (0..10000)
+        NamedRows::new(
+            vec!["k".to_string(), "vld".to_string(), "v".to_string()],
+            (0..10000)
                 .flat_map(|i| (0..10).map(move |vld| vec![
                     DataValue::from(i as i64),
                     DataValue::Validity(Validity::from((vld, true))),
                     DataValue::from(i as i64),
                 ]))
                 .collect_vec(),
-            next: None,
-        },
+        ),
     );
     db.import_relations(to_import).unwrap();
     dbg!(insert_tt10_time.elapsed());
@@ -76,17 +73,16 @@ fn insert_data(db: &DbInstance) {
     let mut to_import = BTreeMap::new();
     to_import.insert(
         "tt100".to_string(),
-        NamedRows {
-            headers: vec!["k".to_string(), "vld".to_string(), "v".to_string()],
-            rows: (0..10000)
+        NamedRows::new(
+            vec!["k".to_string(), "vld".to_string(), "v".to_string()],
+            (0..10000)
                 .flat_map(|i| (0..100).map(move |vld| vec![
                     DataValue::from(i as i64),
                     DataValue::Validity(Validity::from((vld, true))),
                     DataValue::from(i as i64),
                 ]))
                 .collect_vec(),
-            next: None,
-        },
+        ),
     );
     db.import_relations(to_import).unwrap();
     dbg!(insert_tt100_time.elapsed());
@@ -95,9 +91,9 @@ fn insert_data(db: &DbInstance) {
     let mut to_import = BTreeMap::new();
     to_import.insert(
         "tt1000".to_string(),
-        NamedRows {
-            headers: vec!["k".to_string(), "vld".to_string(), "v".to_string()],
-            rows: (0..10000)
+        NamedRows::new(
+            vec!["k".to_string(), "vld".to_string(), "v".to_string()],
+            (0..10000)
                 .flat_map(|i| {
                     (0..1000).map(move |vld| vec![
                         DataValue::from(i as i64),
@@ -106,8 +102,7 @@ fn insert_data(db: &DbInstance) {
                     ])
                 })
                 .collect_vec(),
-            next: None,
-        },
+        ),
     );
     db.import_relations(to_import).unwrap();
     dbg!(insert_tt1000_time.elapsed());
@@ -54,14 +54,13 @@ lazy_static! {
             let to = splits.next().unwrap();
             articles.push(vec![DataValue::from(fr.parse::<i64>().unwrap()), DataValue::from(to.parse::<i64>().unwrap())])
         }
-        db.import_relations(BTreeMap::from([("article".to_string(), NamedRows {
-            headers: vec![
+        db.import_relations(BTreeMap::from([("article".to_string(), NamedRows::new(
+            vec![
                 "fr".to_string(),
                 "to".to_string(),
             ],
-            rows: articles,
-            next: None,
-        })])).unwrap();
+            articles,
+        ))])).unwrap();
         dbg!(import_time.elapsed());
         db
     };
@@ -0,0 +1,646 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A stable API for embedding CozoDB in Rust applications.
+//!
+//! The items of this module only change in major releases: none is removed, deprecated
+//! or has its signature changed in between. They are kept apart from the internals of
+//! the database, which the rest of the crate exposes as they are, and which may change
+//! in any release. [Value] is marked non-exhaustive so that kinds of values may be added
+//! without breaking code matching on it.
+//!
+//! ```
+//! use cozo::api::{DbInstance, Value};
+//!
+//! let db = DbInstance::open_mem().unwrap();
+//! let rows = db
+//!     .query("?[x, y] := x = $a, y = x * 2")
+//!     .param("a", 21)
+//!     .immutable()
+//!     .run()
+//!     .unwrap();
+//! assert_eq!(rows.headers(), ["x", "y"]);
+//! let y: i64 = rows[0].get_as("y").unwrap();
+//! assert_eq!(y, 42);
+//! assert_eq!(rows[0][0], Value::Int(21));
+//! ```
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Index;
+use std::path::Path;
+use std::sync::Arc;
+
+use miette::{Diagnostic, Report};
+use ndarray::{Array1, Array2};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::data::value::{
+    DataValue, JsonData, Matrix, Num, SparseVector, UuidWrapper, Validity, ValidityTs, Vector,
+};
+use crate::{NamedRows, ScriptMutability};
+
+pub use crate::runtime::columns::ColumnInfo;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Value {0} cannot be read as {1}")]
+#[diagnostic(code(api::type_mismatch))]
+struct TypeMismatch(String, &'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The result has no column '{0}'")]
+#[diagnostic(code(api::no_such_column))]
+struct NoSuchColumn(String);
+
+/// The result type of this module
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An error raised by the database
+pub struct Error {
+    report: Report,
+}
+
+impl Error {
+    /// The message of the error, without its source or help
+    pub fn message(&self) -> String {
+        self.report.to_string()
+    }
+    /// The code of the error, e.g. `parser::pest`, if it has one
+    pub fn code(&self) -> Option<String> {
+        self.report.code().map(|code| code.to_string())
+    }
+    /// The help given for the error, if any
+    pub fn help(&self) -> Option<String> {
+        self.report.help().map(|help| help.to_string())
+    }
+}
+
+impl From<Report> for Error {
+    fn from(report: Report) -> Self {
+        Self { report }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.report, f)
+    }
+}
+
+impl Debug for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.report, f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A value stored in, or returned by, the database
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Value {
+    /// null
+    Null,
+    /// boolean
+    Bool(bool),
+    /// integer
+    Int(i64),
+    /// float
+    Float(f64),
+    /// string
+    Str(String),
+    /// bytes
+    Bytes(Vec<u8>),
+    /// UUID, as its 128 bits
+    Uuid(u128),
+    /// list
+    List(Vec<Value>),
+    /// vector, as used by proximity indices
+    Vector(Vec<f64>),
+    /// matrix, as its rows
+    Matrix(Vec<Vec<f64>>),
+    /// sparse vector, as pairs of index and value of its non-zero elements
+    SparseVector(Vec<(u32, f32)>),
+    /// JSON
+    Json(serde_json::Value),
+    /// validity for time travel
+    Validity {
+        /// microseconds since the UNIX epoch
+        timestamp: i64,
+        /// whether the fact is asserted, as opposed to retracted
+        asserted: bool,
+    },
+}
+
+impl Value {
+    /// Whether the value is null
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+    /// The boolean, if the value is one
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+    /// The integer, if the value is one
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+    /// The value as a float, if it is a number
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+    /// The string, if the value is one
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+    /// The bytes, if the value is some
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+    /// The elements, if the value is a list
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(l) => Some(l),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&DataValue::from(self.clone()), f)
+    }
+}
+
+impl From<DataValue> for Value {
+    fn from(value: DataValue) -> Self {
+        match value {
+            DataValue::Null | DataValue::Bot => Value::Null,
+            DataValue::Bool(b) => Value::Bool(b),
+            DataValue::Num(Num::Int(i)) => Value::Int(i),
+            DataValue::Num(Num::Float(f)) => Value::Float(f),
+            DataValue::Str(s) => Value::Str(s.into()),
+            DataValue::Bytes(b) => Value::Bytes(b),
+            DataValue::Uuid(UuidWrapper(u)) => Value::Uuid(u.as_u128()),
+            DataValue::Regex(r) => Value::Str(r.0.as_str().to_string()),
+            DataValue::List(l) => Value::List(l.into_iter().map(Value::from).collect()),
+            DataValue::Set(s) => Value::List(s.into_iter().map(Value::from).collect()),
+            DataValue::Vec(Vector::F32(v)) => Value::Vector(v.iter().map(|x| *x as f64).collect()),
+            DataValue::Vec(Vector::F64(v)) => Value::Vector(v.to_vec()),
+            DataValue::Mat(Matrix::F32(m)) => Value::Matrix(
+                m.outer_iter()
+                    .map(|r| r.iter().map(|x| *x as f64).collect())
+                    .collect(),
+            ),
+            DataValue::Mat(Matrix::F64(m)) => {
+                Value::Matrix(m.outer_iter().map(|r| r.to_vec()).collect())
+            }
+            DataValue::Sparse(s) => Value::SparseVector(s.iter().collect()),
+            DataValue::Json(JsonData(j)) => Value::Json(j),
+            DataValue::Validity(v) => Value::Validity {
+                timestamp: v.timestamp.0 .0,
+                asserted: v.is_assert.0,
+            },
+        }
+    }
+}
+
+impl From<Value> for DataValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => DataValue::Null,
+            Value::Bool(b) => DataValue::Bool(b),
+            Value::Int(i) => DataValue::from(i),
+            Value::Float(f) => DataValue::from(f),
+            Value::Str(s) => DataValue::from(s),
+            Value::Bytes(b) => DataValue::Bytes(b),
+            Value::Uuid(u) => DataValue::Uuid(UuidWrapper(Uuid::from_u128(u))),
+            Value::List(l) => DataValue::List(l.into_iter().map(DataValue::from).collect()),
+            Value::Vector(v) => DataValue::Vec(Vector::F64(Array1::from(v))),
+            Value::Matrix(m) => {
+                let cols = m.first().map(|r| r.len()).unwrap_or(0);
+                if m.iter().all(|r| r.len() == cols) {
+                    let shape = (m.len(), cols);
+                    let els = m.into_iter().flatten().collect();
+                    DataValue::Mat(Matrix::F64(Array2::from_shape_vec(shape, els).unwrap()))
+                } else {
+                    // ragged rows cannot make a matrix, keep them as vectors
+                    DataValue::List(
+                        m.into_iter()
+                            .map(|r| DataValue::Vec(Vector::F64(Array1::from(r))))
+                            .collect(),
+                    )
+                }
+            }
+            Value::SparseVector(s) => DataValue::Sparse(SparseVector::from_pairs(s)),
+            Value::Json(j) => DataValue::Json(JsonData(j)),
+            Value::Validity {
+                timestamp,
+                asserted,
+            } => DataValue::Validity(Validity {
+                timestamp: ValidityTs(Reverse(timestamp)),
+                is_assert: Reverse(asserted),
+            }),
+        }
+    }
+}
+
+macro_rules! value_from {
+    ($($t:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$t> for Value {
+                fn from(v: $t) -> Self {
+                    Value::$variant(v.into())
+                }
+            }
+        )*
+    };
+}
+
+value_from!(
+    bool => Bool,
+    i64 => Int,
+    i32 => Int,
+    u32 => Int,
+    f64 => Float,
+    f32 => Float,
+    String => Str,
+    &str => Str,
+    Vec<u8> => Bytes,
+    Vec<Value> => List,
+    serde_json::Value => Json,
+);
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+/// Types a [Value] can be read as, see [Row::get_as]
+pub trait FromValue: Sized {
+    /// The name of the type, for errors
+    const TYPE_NAME: &'static str;
+    /// Read the value, `None` if it is not of this type
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+macro_rules! from_value {
+    ($($t:ty, $name:expr, |$v:ident| $read:expr);* $(;)?) => {
+        $(
+            impl FromValue for $t {
+                const TYPE_NAME: &'static str = $name;
+                fn from_value($v: &Value) -> Option<Self> {
+                    $read
+                }
+            }
+        )*
+    };
+}
+
+from_value!(
+    Value, "any value", |v| Some(v.clone());
+    bool, "a boolean", |v| v.as_bool();
+    i64, "an integer", |v| v.as_int();
+    f64, "a number", |v| v.as_float();
+    String, "a string", |v| v.as_str().map(|s| s.to_string());
+    Vec<u8>, "bytes", |v| v.as_bytes().map(|b| b.to_vec());
+    Vec<Value>, "a list", |v| v.as_list().map(|l| l.to_vec());
+);
+
+impl<T: FromValue> FromValue for Option<T> {
+    const TYPE_NAME: &'static str = T::TYPE_NAME;
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(None),
+            v => T::from_value(v).map(Some),
+        }
+    }
+}
+
+/// A row of a result, with the headers of its columns
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    headers: Arc<[String]>,
+    values: Vec<Value>,
+}
+
+impl Row {
+    /// The names of the columns
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+    /// The values of the columns, in order
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+    /// Take the values of the columns
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+    /// The value at the column with the given index
+    pub fn get(&self, idx: usize) -> Option<&Value> {
+        self.values.get(idx)
+    }
+    /// The value at the column with the given name
+    pub fn by_name(&self, name: &str) -> Option<&Value> {
+        let idx = self.headers.iter().position(|h| h == name)?;
+        self.values.get(idx)
+    }
+    /// The value at the column with the given name, read as `T`
+    pub fn get_as<T: FromValue>(&self, name: &str) -> Result<T> {
+        let value = self
+            .by_name(name)
+            .ok_or_else(|| Report::new(NoSuchColumn(name.to_string())))?;
+        T::from_value(value)
+            .ok_or_else(|| Report::new(TypeMismatch(value.to_string(), T::TYPE_NAME)).into())
+    }
+}
+
+impl Index<usize> for Row {
+    type Output = Value;
+
+    fn index(&self, idx: usize) -> &Value {
+        &self.values[idx]
+    }
+}
+
+/// The rows returned by a script
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rows {
+    headers: Arc<[String]>,
+    rows: Vec<Row>,
+    truncated: bool,
+    incomplete: bool,
+    next: Option<Box<Rows>>,
+    columns: Vec<ColumnInfo>,
+}
+
+impl Rows {
+    /// Rows with the given headers, e.g. to import into a relation
+    pub fn new(headers: Vec<String>, rows: Vec<Vec<Value>>) -> Self {
+        let headers: Arc<[String]> = headers.into();
+        let rows = rows
+            .into_iter()
+            .map(|values| Row {
+                headers: headers.clone(),
+                values,
+            })
+            .collect();
+        Self {
+            headers,
+            rows,
+            truncated: false,
+            incomplete: false,
+            next: None,
+            columns: vec![],
+        }
+    }
+    /// The names of the columns
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+    /// The type and origin of each column, in the order of the headers.
+    /// Empty unless the rows are the result of a query.
+    pub fn columns(&self) -> &[ColumnInfo] {
+        &self.columns
+    }
+    /// The number of rows
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+    /// Whether there are no rows
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+    /// The rows, in order
+    pub fn iter(&self) -> std::slice::Iter<'_, Row> {
+        self.rows.iter()
+    }
+    /// Whether rows were left out because the result exceeded the limits of the database
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+    /// Whether recursive rules were stopped by `:max_iterations` before reaching their fixpoint
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+    /// The result of the next query of a script made of several queries
+    pub fn next_result(&self) -> Option<&Rows> {
+        self.next.as_deref()
+    }
+}
+
+impl Index<usize> for Rows {
+    type Output = Row;
+
+    fn index(&self, idx: usize) -> &Row {
+        &self.rows[idx]
+    }
+}
+
+impl IntoIterator for Rows {
+    type Item = Row;
+    type IntoIter = std::vec::IntoIter<Row>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Rows {
+    type Item = &'a Row;
+    type IntoIter = std::slice::Iter<'a, Row>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.iter()
+    }
+}
+
+impl From<NamedRows> for Rows {
+    fn from(named: NamedRows) -> Self {
+        let mut rows = Rows::new(
+            named.headers,
+            named
+                .rows
+                .into_iter()
+                .map(|row| row.into_iter().map(Value::from).collect())
+                .collect(),
+        );
+        rows.truncated = named.truncated;
+        rows.incomplete = named.incomplete;
+        rows.columns = named.columns;
+        rows.next = named.next.map(|next| Box::new(Rows::from(*next)));
+        rows
+    }
+}
+
+impl From<Rows> for NamedRows {
+    fn from(rows: Rows) -> Self {
+        NamedRows::new(
+            rows.headers.to_vec(),
+            rows.rows
+                .into_iter()
+                .map(|row| row.values.into_iter().map(DataValue::from).collect())
+                .collect(),
+        )
+    }
+}
+
+/// A database
+#[derive(Clone, Default)]
+pub struct DbInstance {
+    inner: crate::DbInstance,
+}
+
+impl DbInstance {
+    /// Open a database with the given storage engine, see [crate::DbInstance::new]
+    /// for the engines and their options
+    pub fn open(engine: &str, path: impl AsRef<Path>, options: &str) -> Result<Self> {
+        Ok(Self {
+            inner: crate::DbInstance::new(engine, path, options)?,
+        })
+    }
+    /// Open a database kept in memory only
+    pub fn open_mem() -> Result<Self> {
+        Self::open("mem", "", "")
+    }
+    /// Start building a query running the script
+    pub fn query<'a>(&'a self, script: &'a str) -> QueryBuilder<'a> {
+        QueryBuilder {
+            db: self,
+            script,
+            params: BTreeMap::new(),
+            mutability: ScriptMutability::Mutable,
+            actor: None,
+        }
+    }
+    /// Run a script without parameters
+    pub fn run(&self, script: &str) -> Result<Rows> {
+        self.query(script).run()
+    }
+    /// Run a script without parameters, refusing it unless it is guaranteed not to change
+    /// the database, see [`QueryBuilder::run_readonly`]
+    pub fn run_readonly(&self, script: &str) -> Result<Rows> {
+        self.query(script).run_readonly()
+    }
+    /// The rows of the given stored relations, by relation
+    pub fn export_relations(&self, relations: &[&str]) -> Result<BTreeMap<String, Rows>> {
+        let exported = self.inner.export_relations(relations.iter())?;
+        Ok(exported
+            .into_iter()
+            .map(|(name, rows)| (name, Rows::from(rows)))
+            .collect())
+    }
+    /// Put rows into existing stored relations, by relation
+    pub fn import_relations(&self, data: BTreeMap<String, Rows>) -> Result<()> {
+        let data = data
+            .into_iter()
+            .map(|(name, rows)| (name, NamedRows::from(rows)))
+            .collect();
+        Ok(self.inner.import_relations(data)?)
+    }
+    /// Back the database up into a file
+    pub fn backup(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(self.inner.backup_db(path)?)
+    }
+    /// Restore the database from a backup, into a database with no stored relations
+    pub fn restore(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(self.inner.restore_backup(path)?)
+    }
+    /// Close the database, waiting for running work to finish
+    pub fn close(&self) -> Result<()> {
+        Ok(self.inner.close()?)
+    }
+}
+
+impl From<crate::DbInstance> for DbInstance {
+    fn from(inner: crate::DbInstance) -> Self {
+        Self { inner }
+    }
+}
+
+/// A query to run, with its parameters and options, built with [DbInstance::query]
+pub struct QueryBuilder<'a> {
+    db: &'a DbInstance,
+    script: &'a str,
+    params: BTreeMap<String, DataValue>,
+    mutability: ScriptMutability,
+    actor: Option<&'a str>,
+}
+
+impl<'a> QueryBuilder<'a> {
+    /// Give the parameter `$name` a value
+    pub fn param(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.params
+            .insert(name.to_string(), DataValue::from(value.into()));
+        self
+    }
+    /// Give several parameters values
+    pub fn params<K: Into<String>, V: Into<Value>>(
+        mut self,
+        params: impl IntoIterator<Item = (K, V)>,
+    ) -> Self {
+        for (name, value) in params {
+            self.params
+                .insert(name.into(), DataValue::from(value.into()));
+        }
+        self
+    }
+    /// Refuse to run the script if it would change the database
+    pub fn immutable(mut self) -> Self {
+        self.mutability = ScriptMutability::Immutable;
+        self
+    }
+    /// Run the script as the given user, whose access is checked
+    pub fn as_user(mut self, user: &'a str) -> Self {
+        self.actor = Some(user);
+        self
+    }
+    /// Run the script
+    pub fn run(self) -> Result<Rows> {
+        let res = match self.actor {
+            None => self
+                .db
+                .inner
+                .run_script(self.script, self.params, self.mutability),
+            Some(actor) => {
+                self.db
+                    .inner
+                    .run_script_as(actor, self.script, self.params, self.mutability)
+            }
+        };
+        Ok(Rows::from(res?))
+    }
+    /// Run the script after checking that it cannot mutate stored relations, run system
+    /// ops or use nondeterministic functions, failing before running anything otherwise.
+    /// Suitable for running untrusted scripts.
+    pub fn run_readonly(self) -> Result<Rows> {
+        Ok(Rows::from(
+            self.db.inner.run_readonly(self.script, self.params)?,
+        ))
+    }
+}
@@ -0,0 +1,560 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Building CozoScript queries from Rust, instead of by concatenating strings.
+//!
+//! Names of variables, rules, relations and columns are checked against the grammar,
+//! and values are written as escaped literals, or passed as parameters when they have
+//! no literal form, so that nothing given to the builder can change the structure of
+//! the script. The script built is parsed before it is returned.
+//!
+//! ```
+//! use cozo::builder::{Atom, Comparison, QueryBuilder, RuleBuilder, Term};
+//! use cozo::{DataValue, DbInstance, ScriptMutability};
+//!
+//! let (script, params) = QueryBuilder::new()
+//!     .rule(
+//!         RuleBuilder::entry(["x"])
+//!             .atom(Atom::member("x", Term::value(vec![1, 2, 3])))
+//!             .atom(Atom::compare(Term::var("x"), Comparison::Gt, Term::value(1))),
+//!     )
+//!     .limit(1)
+//!     .build()
+//!     .unwrap();
+//! let db = DbInstance::default();
+//! let res = db.run_script(&script, params, ScriptMutability::Immutable).unwrap();
+//! assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use pest::Parser;
+use thiserror::Error;
+
+use crate::data::value::{DataValue, Num};
+use crate::parse::{matches_rule, CozoScriptParser, Rule};
+use crate::runtime::db::Payload;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("'{0}' is not a valid name for {1}")]
+#[diagnostic(code(builder::invalid_name))]
+struct InvalidName(String, &'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The query built has no entry rule")]
+#[diagnostic(code(builder::no_entry))]
+#[diagnostic(help("Add a rule made with 'RuleBuilder::entry'"))]
+struct NoEntryRule;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The query built cannot be parsed: {0}")]
+#[diagnostic(code(builder::unparsable))]
+struct UnparsableScript(String);
+
+/// Names a variable cannot take, as they are read as literals
+const RESERVED: [&str; 3] = ["null", "true", "false"];
+
+/// Parameters made by the builder for values without literals are prefixed by this,
+/// and parameters given by the user may not be
+const GENERATED_PARAM_PREFIX: &str = "__";
+
+/// Check that `name` is matched in full by the grammar rule `rule`
+fn check_name(name: &str, rule: Rule, what: &'static str) -> Result<()> {
+    ensure!(
+        matches_rule(name, rule) && !RESERVED.contains(&name),
+        InvalidName(name.to_string(), what)
+    );
+    Ok(())
+}
+
+/// A term of an atom: a variable, a parameter of the script, or a value
+#[derive(Clone, Debug)]
+pub enum Term {
+    /// a variable, bound in the rule
+    Var(String),
+    /// a parameter, given when the script is run
+    Param(String),
+    /// a value
+    Value(DataValue),
+}
+
+impl Term {
+    /// A variable
+    pub fn var(name: impl Into<String>) -> Self {
+        Term::Var(name.into())
+    }
+    /// A parameter of the script, without the `$`
+    pub fn param(name: impl Into<String>) -> Self {
+        Term::Param(name.into())
+    }
+    /// A value
+    pub fn value(value: impl Into<DataValue>) -> Self {
+        Term::Value(value.into())
+    }
+}
+
+/// The comparisons of [Atom::compare]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Comparison {
+    /// `==`
+    Eq,
+    /// `!=`
+    Neq,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+impl Comparison {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Neq => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+}
+
+/// An atom of the body of a rule
+#[derive(Clone, Debug)]
+pub enum Atom {
+    /// `rule[args]`
+    Rule(String, Vec<Term>),
+    /// `*relation{column: term, ...}`
+    Stored(String, Vec<(String, Term)>),
+    /// `left op right`
+    Compare(Term, Comparison, Term),
+    /// `var = term`
+    Unify(String, Term),
+    /// `var in term`
+    Member(String, Term),
+    /// `not atom`
+    Not(Box<Atom>),
+    /// `(atoms or atoms ...)`, true if all atoms of some alternative are
+    Or(Vec<Vec<Atom>>),
+}
+
+impl Atom {
+    /// Apply a rule to the terms, by position
+    pub fn rule(name: impl Into<String>, args: impl IntoIterator<Item = Term>) -> Self {
+        Atom::Rule(name.into(), args.into_iter().collect())
+    }
+    /// Apply a stored relation, binding its columns with [Atom::bind]
+    pub fn stored(relation: impl Into<String>) -> Self {
+        Atom::Stored(relation.into(), vec![])
+    }
+    /// Bind a column of a stored relation to a term. Does nothing to other atoms.
+    pub fn bind(mut self, column: impl Into<String>, term: Term) -> Self {
+        if let Atom::Stored(_, bindings) = &mut self {
+            bindings.push((column.into(), term));
+        }
+        self
+    }
+    /// Compare two terms
+    pub fn compare(left: Term, op: Comparison, right: Term) -> Self {
+        Atom::Compare(left, op, right)
+    }
+    /// Bind a variable to a term
+    pub fn unify(var: impl Into<String>, term: Term) -> Self {
+        Atom::Unify(var.into(), term)
+    }
+    /// Bind a variable to each element of a list
+    pub fn member(var: impl Into<String>, term: Term) -> Self {
+        Atom::Member(var.into(), term)
+    }
+    /// Negate an atom
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(atom: Atom) -> Self {
+        Atom::Not(Box::new(atom))
+    }
+    /// Any of the alternatives, each a conjunction of atoms
+    pub fn or(alternatives: impl IntoIterator<Item = Vec<Atom>>) -> Self {
+        Atom::Or(alternatives.into_iter().collect())
+    }
+}
+
+/// A rule of a query
+#[derive(Clone, Debug)]
+pub struct RuleBuilder {
+    name: Option<String>,
+    head: Vec<(Option<String>, String)>,
+    body: Vec<Atom>,
+    rows: Option<Vec<Vec<DataValue>>>,
+}
+
+impl RuleBuilder {
+    /// A rule with the given name
+    pub fn new(name: impl Into<String>, head: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            name: Some(name.into()),
+            head: head.into_iter().map(|v| (None, v.into())).collect(),
+            body: vec![],
+            rows: None,
+        }
+    }
+    /// The entry rule `?`, whose rows are returned
+    pub fn entry(head: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let mut ret = Self::new("", head);
+        ret.name = None;
+        ret
+    }
+    /// Add an aggregated variable to the head, e.g. `count(x)`
+    pub fn aggr(mut self, op: impl Into<String>, var: impl Into<String>) -> Self {
+        self.head.push((Some(op.into()), var.into()));
+        self
+    }
+    /// Add an atom to the body, which is the conjunction of its atoms
+    pub fn atom(mut self, atom: Atom) -> Self {
+        self.body.push(atom);
+        self
+    }
+    /// Make this a constant rule with the given rows, instead of its body
+    pub fn rows(mut self, rows: Vec<Vec<DataValue>>) -> Self {
+        self.rows = Some(rows);
+        self
+    }
+}
+
+/// The mutations of stored relations of [QueryBuilder::mutate]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mutation {
+    /// `:put`
+    Put,
+    /// `:insert`
+    Insert,
+    /// `:update`
+    Update,
+    /// `:rm`
+    Rm,
+    /// `:delete`
+    Delete,
+    /// `:ensure`
+    Ensure,
+    /// `:ensure_not`
+    EnsureNot,
+}
+
+impl Mutation {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Mutation::Put => ":put",
+            Mutation::Insert => ":insert",
+            Mutation::Update => ":update",
+            Mutation::Rm => ":rm",
+            Mutation::Delete => ":delete",
+            Mutation::Ensure => ":ensure",
+            Mutation::EnsureNot => ":ensure_not",
+        }
+    }
+}
+
+/// Builds a query script, see the [module docs](self)
+#[derive(Clone, Debug, Default)]
+pub struct QueryBuilder {
+    rules: Vec<RuleBuilder>,
+    params: BTreeMap<String, DataValue>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    sort: Vec<(String, bool)>,
+    timeout: Option<f64>,
+    mutation: Option<(Mutation, String, Vec<String>, Vec<String>)>,
+}
+
+/// The text of a script being written, with the parameters it needs
+struct ScriptWriter {
+    out: String,
+    params: BTreeMap<String, DataValue>,
+    n_generated: usize,
+}
+
+impl ScriptWriter {
+    fn bind(&mut self, value: DataValue) {
+        let name = format!("{GENERATED_PARAM_PREFIX}{}", self.n_generated);
+        self.n_generated += 1;
+        write!(self.out, "${name}").unwrap();
+        self.params.insert(name, value);
+    }
+    fn value(&mut self, value: &DataValue) {
+        match value {
+            DataValue::Null => self.out.push_str("null"),
+            DataValue::Bool(b) => write!(self.out, "{b}").unwrap(),
+            DataValue::Num(Num::Int(i)) if *i != i64::MIN => write!(self.out, "{i}").unwrap(),
+            DataValue::Num(Num::Float(f)) if f.is_finite() => write!(self.out, "{f:?}").unwrap(),
+            // quoted strings of CozoScript are escaped as strings of JSON
+            DataValue::Str(s) => self
+                .out
+                .push_str(&serde_json::to_string(s.as_str()).unwrap()),
+            DataValue::List(l) => {
+                self.out.push('[');
+                for (i, v) in l.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.value(v);
+                }
+                self.out.push(']');
+            }
+            v => self.bind(v.clone()),
+        }
+    }
+    fn term(&mut self, term: &Term) -> Result<()> {
+        match term {
+            Term::Var(v) => {
+                check_name(v, Rule::var, "a variable")?;
+                self.out.push_str(v);
+            }
+            Term::Param(p) => {
+                check_name(&format!("${p}"), Rule::param, "a parameter")?;
+                ensure!(
+                    !p.starts_with(GENERATED_PARAM_PREFIX),
+                    InvalidName(p.to_string(), "a parameter")
+                );
+                write!(self.out, "${p}").unwrap();
+            }
+            Term::Value(v) => self.value(v),
+        }
+        Ok(())
+    }
+    fn atoms(&mut self, atoms: &[Atom]) -> Result<()> {
+        for (i, atom) in atoms.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.atom(atom)?;
+        }
+        Ok(())
+    }
+    fn atom(&mut self, atom: &Atom) -> Result<()> {
+        match atom {
+            Atom::Rule(name, args) => {
+                check_name(name, Rule::underscore_ident, "a rule")?;
+                write!(self.out, "{name}[").unwrap();
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.term(arg)?;
+                }
+                self.out.push(']');
+            }
+            Atom::Stored(relation, bindings) => {
+                check_name(relation, Rule::compound_ident, "a stored relation")?;
+                write!(self.out, "*{relation}{{").unwrap();
+                for (i, (col, term)) in bindings.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    check_name(col, Rule::underscore_ident, "a column")?;
+                    write!(self.out, "{col}: ").unwrap();
+                    self.term(term)?;
+                }
+                self.out.push('}');
+            }
+            Atom::Compare(left, op, right) => {
+                self.term(left)?;
+                write!(self.out, " {} ", op.as_str()).unwrap();
+                self.term(right)?;
+            }
+            Atom::Unify(var, term) => {
+                check_name(var, Rule::var, "a variable")?;
+                write!(self.out, "{var} = ").unwrap();
+                self.term(term)?;
+            }
+            Atom::Member(var, term) => {
+                check_name(var, Rule::var, "a variable")?;
+                write!(self.out, "{var} in ").unwrap();
+                self.term(term)?;
+            }
+            Atom::Not(atom) => {
+                self.out.push_str("not ");
+                self.atom(atom)?;
+            }
+            Atom::Or(alternatives) => {
+                self.out.push('(');
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(" or ");
+                    }
+                    self.out.push('(');
+                    self.atoms(alternative)?;
+                    self.out.push(')');
+                }
+                self.out.push(')');
+            }
+        }
+        Ok(())
+    }
+    fn rule(&mut self, rule: &RuleBuilder) -> Result<()> {
+        match &rule.name {
+            None => self.out.push('?'),
+            Some(name) => {
+                check_name(name, Rule::ident, "a rule")?;
+                self.out.push_str(name);
+            }
+        }
+        self.out.push('[');
+        for (i, (aggr, var)) in rule.head.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            check_name(var, Rule::var, "a variable")?;
+            match aggr {
+                None => self.out.push_str(var),
+                Some(aggr) => {
+                    check_name(aggr, Rule::ident, "an aggregation")?;
+                    write!(self.out, "{aggr}({var})").unwrap();
+                }
+            }
+        }
+        self.out.push(']');
+        match &rule.rows {
+            Some(rows) => {
+                self.out.push_str(" <- ");
+                self.bind(DataValue::List(
+                    rows.iter().cloned().map(DataValue::List).collect(),
+                ));
+            }
+            None => {
+                self.out.push_str(" := ");
+                self.atoms(&rule.body)?;
+            }
+        }
+        self.out.push('\n');
+        Ok(())
+    }
+}
+
+impl QueryBuilder {
+    /// An empty query
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Add a rule
+    pub fn rule(mut self, rule: RuleBuilder) -> Self {
+        self.rules.push(rule);
+        self
+    }
+    /// Give a parameter of the script a value
+    pub fn param(mut self, name: impl Into<String>, value: impl Into<DataValue>) -> Self {
+        self.params.insert(name.into(), value.into());
+        self
+    }
+    /// Return at most `n` rows
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+    /// Skip the first `n` rows
+    pub fn offset(mut self, n: usize) -> Self {
+        self.offset = Some(n);
+        self
+    }
+    /// Sort the rows by a column of the entry rule, after the columns given before
+    pub fn sort(mut self, column: impl Into<String>, descending: bool) -> Self {
+        self.sort.push((column.into(), descending));
+        self
+    }
+    /// Stop the query after the given number of seconds
+    pub fn timeout(mut self, seconds: f64) -> Self {
+        self.timeout = Some(seconds);
+        self
+    }
+    /// Write the rows of the entry rule into a stored relation, with the given key and
+    /// value columns
+    pub fn mutate(
+        mut self,
+        mutation: Mutation,
+        relation: impl Into<String>,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+        values: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.mutation = Some((
+            mutation,
+            relation.into(),
+            keys.into_iter().map(|k| k.into()).collect(),
+            values.into_iter().map(|v| v.into()).collect(),
+        ));
+        self
+    }
+    /// The text of the script and its parameters, to run with e.g.
+    /// [DbInstance::run_script](crate::DbInstance::run_script)
+    pub fn build(self) -> Result<Payload> {
+        if !self.rules.iter().any(|r| r.name.is_none()) {
+            bail!(NoEntryRule)
+        }
+        for name in self.params.keys() {
+            check_name(&format!("${name}"), Rule::param, "a parameter")?;
+            ensure!(
+                !name.starts_with(GENERATED_PARAM_PREFIX),
+                InvalidName(name.to_string(), "a parameter")
+            );
+        }
+        let mut writer = ScriptWriter {
+            out: String::new(),
+            params: self.params,
+            n_generated: 0,
+        };
+        for rule in &self.rules {
+            writer.rule(rule)?;
+        }
+        if let Some(n) = self.limit {
+            writeln!(writer.out, ":limit {n}").unwrap();
+        }
+        if let Some(n) = self.offset {
+            writeln!(writer.out, ":offset {n}").unwrap();
+        }
+        if !self.sort.is_empty() {
+            for (col, _) in &self.sort {
+                check_name(col, Rule::var, "a column")?;
+            }
+            let args = self
+                .sort
+                .iter()
+                .map(|(col, desc)| format!("{}{col}", if *desc { "-" } else { "" }))
+                .join(", ");
+            writeln!(writer.out, ":sort {args}").unwrap();
+        }
+        if let Some(seconds) = self.timeout {
+            writer.out.push_str(":timeout ");
+            writer.value(&DataValue::from(seconds));
+            writer.out.push('\n');
+        }
+        if let Some((mutation, relation, keys, values)) = &self.mutation {
+            check_name(relation, Rule::compound_ident, "a stored relation")?;
+            for col in keys.iter().chain(values) {
+                check_name(col, Rule::underscore_ident, "a column")?;
+            }
+            write!(
+                writer.out,
+                "{} {relation} {{{}",
+                mutation.as_str(),
+                keys.join(", ")
+            )
+            .unwrap();
+            if !values.is_empty() {
+                write!(writer.out, " => {}", values.join(", ")).unwrap();
+            }
+            writer.out.push_str("}\n");
+        }
+        if let Err(err) = CozoScriptParser::parse(Rule::query_script, &writer.out) {
+            bail!(UnparsableScript(err.to_string()))
+        }
+        Ok((writer.out, writer.params))
+    }
+}
@@ -1204,6 +1204,9 @@ impl Aggregation {
         });
         Ok(())
     }
+    pub(crate) fn is_count(&self) -> bool {
+        self.name == AGGR_COUNT.name
+    }
     pub(crate) fn normal_init(&mut self, args: &[DataValue]) -> Result<()> {
         #[allow(clippy::box_default)]
         self.normal_op.replace(match self.name {
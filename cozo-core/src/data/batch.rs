@@ -0,0 +1,522 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Columnar batches of tuples.
+//!
+//! Large scans move rows between operators in batches of up to [BATCH_SIZE] rows stored
+//! column by column: booleans, integers, floats and strings live in typed vectors with a
+//! validity bitmap marking nulls, instead of one `DataValue` per cell. Batches are turned
+//! back into tuples at the edges, after filters have thrown away the rows not needed.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Num};
+
+/// Maximum number of rows in a batch
+pub(crate) const BATCH_SIZE: usize = 1024;
+
+/// A fixed-length sequence of bits
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Bitmap {
+    /// A bitmap of `len` bits, all set to `value`
+    pub(crate) fn new(len: usize, value: bool) -> Self {
+        let mut words = vec![if value { u64::MAX } else { 0 }; len.div_ceil(64)];
+        let extra = words.len() * 64 - len;
+        if value && extra > 0 {
+            *words.last_mut().unwrap() >>= extra;
+        }
+        Self { words, len }
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+    pub(crate) fn push(&mut self, bit: bool) {
+        if self.len % 64 == 0 {
+            self.words.push(0);
+        }
+        if bit {
+            self.words[self.len / 64] |= 1 << (self.len % 64);
+        }
+        self.len += 1;
+    }
+    pub(crate) fn get(&self, i: usize) -> bool {
+        debug_assert!(i < self.len);
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+    pub(crate) fn set(&mut self, i: usize, bit: bool) {
+        debug_assert!(i < self.len);
+        if bit {
+            self.words[i / 64] |= 1 << (i % 64);
+        } else {
+            self.words[i / 64] &= !(1 << (i % 64));
+        }
+    }
+    pub(crate) fn all(&self) -> bool {
+        self.count_ones() == self.len
+    }
+    pub(crate) fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+    /// Positions of the set bits, in increasing order
+    pub(crate) fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(n, word)| {
+            let mut word = *word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    None
+                } else {
+                    let bit = word.trailing_zeros() as usize;
+                    word &= word - 1;
+                    Some(n * 64 + bit)
+                }
+            })
+        })
+    }
+}
+
+/// The values of one column of a [TupleBatch]. Nulls in typed columns are marked
+/// as invalid in `validity`, and hold a placeholder in `values`.
+#[derive(Clone, Debug)]
+pub(crate) enum Column {
+    /// A column that has only seen nulls so far
+    Null(usize),
+    Bool { values: Bitmap, validity: Bitmap },
+    Int { values: Vec<i64>, validity: Bitmap },
+    Float { values: Vec<f64>, validity: Bitmap },
+    Str {
+        values: Vec<SmartString<LazyCompact>>,
+        validity: Bitmap,
+    },
+    /// Values of other types, or of more than one type
+    Mixed(Vec<DataValue>),
+}
+
+impl Column {
+    pub(crate) fn push(&mut self, v: DataValue) {
+        let v = match (&mut *self, v) {
+            (Column::Null(n), DataValue::Null) => {
+                *n += 1;
+                return;
+            }
+            (Column::Bool { values, validity }, DataValue::Bool(b)) => {
+                values.push(b);
+                validity.push(true);
+                return;
+            }
+            (Column::Bool { values, validity }, DataValue::Null) => {
+                values.push(false);
+                validity.push(false);
+                return;
+            }
+            (Column::Int { values, validity }, DataValue::Num(Num::Int(i))) => {
+                values.push(i);
+                validity.push(true);
+                return;
+            }
+            (Column::Int { values, validity }, DataValue::Null) => {
+                values.push(0);
+                validity.push(false);
+                return;
+            }
+            (Column::Float { values, validity }, DataValue::Num(Num::Float(f))) => {
+                values.push(f);
+                validity.push(true);
+                return;
+            }
+            (Column::Float { values, validity }, DataValue::Null) => {
+                values.push(0.);
+                validity.push(false);
+                return;
+            }
+            (Column::Str { values, validity }, DataValue::Str(s)) => {
+                values.push(s);
+                validity.push(true);
+                return;
+            }
+            (Column::Str { values, validity }, DataValue::Null) => {
+                values.push(SmartString::new());
+                validity.push(false);
+                return;
+            }
+            (Column::Mixed(values), v) => {
+                values.push(v);
+                return;
+            }
+            (_, v) => v,
+        };
+        // either the first non-null value, or a value not matching the type of the column
+        *self = match std::mem::replace(self, Column::Null(0)) {
+            Column::Null(n) => Column::with_nulls(&v, n),
+            col => Column::Mixed(col.into_values()),
+        };
+        self.push(v)
+    }
+
+    /// A column of booleans without nulls
+    pub(crate) fn from_bools(bools: impl IntoIterator<Item = bool>) -> Self {
+        let mut values = Bitmap::default();
+        for b in bools {
+            values.push(b);
+        }
+        let validity = Bitmap::new(values.len(), true);
+        Column::Bool { values, validity }
+    }
+
+    /// A column of numbers without nulls, typed if all numbers are of the same kind
+    pub(crate) fn from_nums(nums: impl IntoIterator<Item = Num>) -> Self {
+        let mut ret = Column::Null(0);
+        for n in nums {
+            ret.push(DataValue::Num(n));
+        }
+        ret
+    }
+
+    /// A column of the type suitable for `v`, starting with `n` nulls
+    fn with_nulls(v: &DataValue, n: usize) -> Self {
+        let validity = Bitmap::new(n, false);
+        match v {
+            DataValue::Bool(_) => Column::Bool {
+                values: Bitmap::new(n, false),
+                validity,
+            },
+            DataValue::Num(Num::Int(_)) => Column::Int {
+                values: vec![0; n],
+                validity,
+            },
+            DataValue::Num(Num::Float(_)) => Column::Float {
+                values: vec![0.; n],
+                validity,
+            },
+            DataValue::Str(_) => Column::Str {
+                values: vec![SmartString::new(); n],
+                validity,
+            },
+            _ => Column::Mixed(vec![DataValue::Null; n]),
+        }
+    }
+
+    /// The value at row `i`
+    pub(crate) fn get(&self, i: usize) -> DataValue {
+        match self {
+            Column::Null(_) => DataValue::Null,
+            Column::Bool { validity, .. }
+            | Column::Int { validity, .. }
+            | Column::Float { validity, .. }
+            | Column::Str { validity, .. }
+                if !validity.get(i) =>
+            {
+                DataValue::Null
+            }
+            Column::Bool { values, .. } => DataValue::Bool(values.get(i)),
+            Column::Int { values, .. } => DataValue::from(values[i]),
+            Column::Float { values, .. } => DataValue::from(values[i]),
+            Column::Str { values, .. } => DataValue::Str(values[i].clone()),
+            Column::Mixed(values) => values[i].clone(),
+        }
+    }
+
+    pub(crate) fn into_values(self) -> Vec<DataValue> {
+        fn with_validity<T>(
+            values: impl IntoIterator<Item = T>,
+            validity: &Bitmap,
+            f: impl Fn(T) -> DataValue,
+        ) -> Vec<DataValue> {
+            values
+                .into_iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    if validity.get(i) {
+                        f(v)
+                    } else {
+                        DataValue::Null
+                    }
+                })
+                .collect()
+        }
+
+        match self {
+            Column::Null(n) => vec![DataValue::Null; n],
+            Column::Bool { values, validity } => with_validity(
+                (0..values.len()).map(|i| values.get(i)),
+                &validity,
+                DataValue::Bool,
+            ),
+            Column::Int { values, validity } => with_validity(values, &validity, DataValue::from),
+            Column::Float { values, validity } => {
+                with_validity(values, &validity, DataValue::from)
+            }
+            Column::Str { values, validity } => with_validity(values, &validity, DataValue::Str),
+            Column::Mixed(values) => values,
+        }
+    }
+}
+
+/// A batch of tuples of the same arity, stored column by column
+#[derive(Clone, Debug)]
+pub(crate) struct TupleBatch {
+    columns: Vec<Column>,
+    len: usize,
+}
+
+impl TupleBatch {
+    pub(crate) fn new(arity: usize) -> Self {
+        Self {
+            columns: (0..arity).map(|_| Column::Null(0)).collect(),
+            len: 0,
+        }
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+    pub(crate) fn arity(&self) -> usize {
+        self.columns.len()
+    }
+    pub(crate) fn column(&self, i: usize) -> &Column {
+        &self.columns[i]
+    }
+    /// The tuple at row `i`
+    pub(crate) fn row(&self, i: usize) -> Tuple {
+        self.columns.iter().map(|col| col.get(i)).collect()
+    }
+    pub(crate) fn push(&mut self, tuple: Tuple) {
+        debug_assert_eq!(tuple.len(), self.columns.len());
+        for (col, v) in self.columns.iter_mut().zip(tuple) {
+            col.push(v);
+        }
+        self.len += 1;
+    }
+    /// The tuples of the rows set in `selection`, in order
+    pub(crate) fn into_selected(self, selection: &Bitmap) -> Vec<Tuple> {
+        debug_assert_eq!(selection.len(), self.len);
+        let arity = self.columns.len();
+        let mut rows = (0..selection.count_ones())
+            .map(|_| Vec::with_capacity(arity))
+            .collect_vec();
+        for col in self.columns {
+            let mut out = rows.iter_mut();
+            for (i, v) in col.into_values().into_iter().enumerate() {
+                if selection.get(i) {
+                    out.next().unwrap().push(v);
+                }
+            }
+        }
+        rows
+    }
+}
+
+/// Group the tuples of `it` into batches. The arity is taken from the first tuple.
+///
+/// An error ends the batch being built, and the rows gathered so far are dropped with it.
+pub(crate) fn batched(
+    mut it: impl Iterator<Item = Result<Tuple>>,
+) -> impl Iterator<Item = Result<TupleBatch>> {
+    std::iter::from_fn(move || {
+        let mut batch: Option<TupleBatch> = None;
+        for res in it.by_ref() {
+            match res {
+                Err(err) => return Some(Err(err)),
+                Ok(tuple) => {
+                    let batch = batch.get_or_insert_with(|| TupleBatch::new(tuple.len()));
+                    batch.push(tuple);
+                    if batch.len() >= BATCH_SIZE {
+                        break;
+                    }
+                }
+            }
+        }
+        batch.map(Ok)
+    })
+}
+
+/// An argument to, or the result of, an op evaluated over a whole batch
+#[derive(Debug, Clone)]
+pub(crate) enum BatchValue<'a> {
+    /// One value per row
+    Column(Cow<'a, Column>),
+    /// The same value for all rows
+    Scalar(DataValue),
+}
+
+impl BatchValue<'_> {
+    /// The value at row `i`
+    pub(crate) fn get(&self, i: usize) -> DataValue {
+        match self {
+            BatchValue::Column(col) => col.get(i),
+            BatchValue::Scalar(v) => v.clone(),
+        }
+    }
+    /// A view of the values as numbers, if they are all non-null numbers
+    /// and the column is typed
+    pub(crate) fn nums(&self) -> Option<Nums<'_>> {
+        Some(match self {
+            BatchValue::Column(col) => match col.as_ref() {
+                Column::Int { values, validity } if validity.all() => Nums::Ints(values),
+                Column::Float { values, validity } if validity.all() => Nums::Floats(values),
+                _ => return None,
+            },
+            BatchValue::Scalar(DataValue::Num(n)) => Nums::Scalar(*n),
+            _ => return None,
+        })
+    }
+    /// A view of the values as strings, if they are all non-null strings
+    pub(crate) fn strs(&self) -> Option<Strs<'_>> {
+        Some(match self {
+            BatchValue::Column(col) => match col.as_ref() {
+                Column::Str { values, validity } if validity.all() => Strs::Column(values),
+                _ => return None,
+            },
+            BatchValue::Scalar(DataValue::Str(s)) => Strs::Scalar(s),
+            _ => return None,
+        })
+    }
+}
+
+/// See [BatchValue::nums]
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Nums<'a> {
+    Ints(&'a [i64]),
+    Floats(&'a [f64]),
+    Scalar(Num),
+}
+
+impl Nums<'_> {
+    pub(crate) fn get(&self, i: usize) -> Num {
+        match self {
+            Nums::Ints(values) => Num::Int(values[i]),
+            Nums::Floats(values) => Num::Float(values[i]),
+            Nums::Scalar(n) => *n,
+        }
+    }
+}
+
+/// See [BatchValue::strs]
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Strs<'a> {
+    Column(&'a [SmartString<LazyCompact>]),
+    Scalar(&'a str),
+}
+
+impl Strs<'_> {
+    pub(crate) fn get(&self, i: usize) -> &str {
+        match self {
+            Strs::Column(values) => &values[i],
+            Strs::Scalar(s) => s,
+        }
+    }
+}
+
+/// Whether all arguments are scalars, in which case batched implementations have nothing to gain
+pub(crate) fn all_scalars(args: &[BatchValue<'_>]) -> bool {
+    args.iter().all(|arg| matches!(arg, BatchValue::Scalar(_)))
+}
+
+/// A comparison, as done by the comparison ops
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum CmpOp {
+    Eq,
+    Neq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    /// `None` stands for unordered floats, which are only unequal
+    pub(crate) fn test(self, ord: Option<Ordering>) -> bool {
+        match ord {
+            None => self == CmpOp::Neq,
+            Some(ord) => match self {
+                CmpOp::Eq => ord == Ordering::Equal,
+                CmpOp::Neq => ord != Ordering::Equal,
+                CmpOp::Lt => ord == Ordering::Less,
+                CmpOp::Le => ord != Ordering::Greater,
+                CmpOp::Gt => ord == Ordering::Greater,
+                CmpOp::Ge => ord != Ordering::Less,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tuples() -> Vec<Tuple> {
+        vec![
+            vec![DataValue::from(1), DataValue::Null, DataValue::from("a")],
+            vec![DataValue::from(2), DataValue::from(1.5), DataValue::from("b")],
+            vec![DataValue::Null, DataValue::from(2.5), DataValue::from(3)],
+            vec![DataValue::from(4), DataValue::Null, DataValue::from("d")],
+        ]
+    }
+
+    fn batch(tuples: Vec<Tuple>) -> TupleBatch {
+        let mut batch = TupleBatch::new(3);
+        for tuple in tuples {
+            batch.push(tuple);
+        }
+        batch
+    }
+
+    #[test]
+    fn bitmap() {
+        let mut bm = Bitmap::new(130, true);
+        assert_eq!(bm.count_ones(), 130);
+        bm.set(0, false);
+        bm.set(64, false);
+        assert!(!bm.get(64));
+        assert_eq!(bm.count_ones(), 128);
+        assert_eq!(bm.ones().take(2).collect_vec(), vec![1, 2]);
+        assert_eq!(bm.ones().last(), Some(129));
+        bm.push(true);
+        assert_eq!(bm.len(), 131);
+        assert_eq!(bm.count_ones(), 129);
+    }
+
+    #[test]
+    fn round_trip() {
+        let batch = batch(tuples());
+        assert_eq!(batch.len(), 4);
+        assert!(matches!(batch.column(0), Column::Int { .. }));
+        assert!(matches!(batch.column(1), Column::Float { .. }));
+        assert!(matches!(batch.column(2), Column::Mixed(_)));
+        assert_eq!(batch.row(2), tuples()[2]);
+        assert_eq!(
+            batch.clone().into_selected(&Bitmap::new(4, true)),
+            tuples()
+        );
+
+        let mut selection = Bitmap::new(4, false);
+        selection.set(1, true);
+        selection.set(3, true);
+        assert_eq!(
+            batch.into_selected(&selection),
+            vec![tuples()[1].clone(), tuples()[3].clone()]
+        );
+
+        let batches: Vec<_> = batched((0..2500).map(|i| Ok(vec![DataValue::from(i)])))
+            .try_collect()
+            .unwrap();
+        assert_eq!(
+            batches.iter().map(|b| b.len()).collect_vec(),
+            vec![1024, 1024, 452]
+        );
+    }
+}
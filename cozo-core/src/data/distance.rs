@@ -0,0 +1,526 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Distance kernels for vectors, used by the distance functions and by HNSW search.
+//!
+//! On x86_64 the kernels use AVX2 and FMA when the CPU supports them, detected at runtime.
+//! On aarch64 they use NEON, which is always available. Elsewhere, and as a fallback,
+//! portable code with several independent accumulators lets the compiler vectorize.
+
+use std::borrow::Cow;
+
+use ndarray::Array1;
+
+use crate::data::value::Vector;
+
+macro_rules! kernel {
+    ($(#[$meta:meta])* $name:ident, $t:ty, $ret:ty) => {
+        $(#[$meta])*
+        pub(crate) fn $name(a: &[$t], b: &[$t]) -> $ret {
+            assert_eq!(a.len(), b.len());
+            #[cfg(target_arch = "x86_64")]
+            {
+                if x86::available() {
+                    // SAFETY: the required CPU features have been detected
+                    return unsafe { x86::$name(a, b) };
+                }
+                portable::$name(a, b)
+            }
+            #[cfg(target_arch = "aarch64")]
+            {
+                // SAFETY: NEON is part of the aarch64 baseline
+                unsafe { arm::$name(a, b) }
+            }
+            #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+            {
+                portable::$name(a, b)
+            }
+        }
+    };
+}
+
+kernel!(
+    /// Inner product
+    dot_f32, f32, f32
+);
+kernel!(
+    /// Inner product
+    dot_f64, f64, f64
+);
+kernel!(
+    /// Squared euclidean distance
+    l2_sq_f32, f32, f32
+);
+kernel!(
+    /// Squared euclidean distance
+    l2_sq_f64, f64, f64
+);
+kernel!(
+    /// The inner product, and the squared norms of both vectors, in one pass
+    cos_parts_f32, f32, (f32, f32, f32)
+);
+kernel!(
+    /// The inner product, and the squared norms of both vectors, in one pass
+    cos_parts_f64, f64, (f64, f64, f64)
+);
+
+fn as_slice<T: Clone>(a: &Array1<T>) -> Cow<'_, [T]> {
+    match a.as_slice() {
+        Some(s) => Cow::Borrowed(s),
+        None => Cow::Owned(a.to_vec()),
+    }
+}
+
+fn cos_from_parts(dot: f64, a_norm: f64, b_norm: f64) -> f64 {
+    1. - dot / (a_norm * b_norm).sqrt()
+}
+
+/// Squared euclidean distance. `None` if the vectors are of different types.
+/// Panics if the lengths differ.
+pub(crate) fn l2_dist(a: &Vector, b: &Vector) -> Option<f64> {
+    Some(match (a, b) {
+        (Vector::F32(a), Vector::F32(b)) => l2_sq_f32(&as_slice(a), &as_slice(b)) as f64,
+        (Vector::F64(a), Vector::F64(b)) => l2_sq_f64(&as_slice(a), &as_slice(b)),
+        _ => return None,
+    })
+}
+
+/// One minus the inner product. `None` if the vectors are of different types.
+/// Panics if the lengths differ.
+pub(crate) fn ip_dist(a: &Vector, b: &Vector) -> Option<f64> {
+    Some(match (a, b) {
+        (Vector::F32(a), Vector::F32(b)) => 1. - dot_f32(&as_slice(a), &as_slice(b)) as f64,
+        (Vector::F64(a), Vector::F64(b)) => 1. - dot_f64(&as_slice(a), &as_slice(b)),
+        _ => return None,
+    })
+}
+
+/// One minus the cosine similarity. `None` if the vectors are of different types.
+/// Panics if the lengths differ.
+pub(crate) fn cos_dist(a: &Vector, b: &Vector) -> Option<f64> {
+    Some(match (a, b) {
+        (Vector::F32(a), Vector::F32(b)) => {
+            let (dot, a_norm, b_norm) = cos_parts_f32(&as_slice(a), &as_slice(b));
+            cos_from_parts(dot as f64, a_norm as f64, b_norm as f64)
+        }
+        (Vector::F64(a), Vector::F64(b)) => {
+            let (dot, a_norm, b_norm) = cos_parts_f64(&as_slice(a), &as_slice(b));
+            cos_from_parts(dot, a_norm, b_norm)
+        }
+        _ => return None,
+    })
+}
+
+/// The euclidean norm
+pub(crate) fn norm(a: &Vector) -> f64 {
+    match a {
+        Vector::F32(a) => {
+            let a = as_slice(a);
+            (dot_f32(&a, &a) as f64).sqrt()
+        }
+        Vector::F64(a) => {
+            let a = as_slice(a);
+            dot_f64(&a, &a).sqrt()
+        }
+    }
+}
+
+#[cfg_attr(
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    allow(dead_code)
+)]
+mod portable {
+    use std::ops::{Add, AddAssign, Mul, Sub};
+
+    const LANES: usize = 8;
+
+    trait Float:
+        Copy + Default + Add<Output = Self> + AddAssign + Sub<Output = Self> + Mul<Output = Self>
+    {
+    }
+
+    impl Float for f32 {}
+    impl Float for f64 {}
+
+    fn sum<T: Float>(acc: [T; LANES]) -> T {
+        acc.into_iter().fold(T::default(), |a, b| a + b)
+    }
+
+    fn dot<T: Float>(a: &[T], b: &[T]) -> T {
+        let mut acc = [T::default(); LANES];
+        let (a_chunks, b_chunks) = (a.chunks_exact(LANES), b.chunks_exact(LANES));
+        let (a_rem, b_rem) = (a_chunks.remainder(), b_chunks.remainder());
+        for (x, y) in a_chunks.zip(b_chunks) {
+            for ((acc, x), y) in acc.iter_mut().zip(x).zip(y) {
+                *acc += *x * *y;
+            }
+        }
+        for (x, y) in a_rem.iter().zip(b_rem) {
+            acc[0] += *x * *y;
+        }
+        sum(acc)
+    }
+
+    fn l2_sq<T: Float>(a: &[T], b: &[T]) -> T {
+        let mut acc = [T::default(); LANES];
+        let (a_chunks, b_chunks) = (a.chunks_exact(LANES), b.chunks_exact(LANES));
+        let (a_rem, b_rem) = (a_chunks.remainder(), b_chunks.remainder());
+        for (x, y) in a_chunks.zip(b_chunks) {
+            for ((acc, x), y) in acc.iter_mut().zip(x).zip(y) {
+                let d = *x - *y;
+                *acc += d * d;
+            }
+        }
+        for (x, y) in a_rem.iter().zip(b_rem) {
+            let d = *x - *y;
+            acc[0] += d * d;
+        }
+        sum(acc)
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    fn cos_parts<T: Float>(a: &[T], b: &[T]) -> (T, T, T) {
+        let mut dot = [T::default(); LANES];
+        let mut aa = [T::default(); LANES];
+        let mut bb = [T::default(); LANES];
+        let (a_chunks, b_chunks) = (a.chunks_exact(LANES), b.chunks_exact(LANES));
+        let (a_rem, b_rem) = (a_chunks.remainder(), b_chunks.remainder());
+        for (x, y) in a_chunks.zip(b_chunks) {
+            for i in 0..LANES {
+                let (x, y) = (x[i], y[i]);
+                dot[i] += x * y;
+                aa[i] += x * x;
+                bb[i] += y * y;
+            }
+        }
+        for (x, y) in a_rem.iter().zip(b_rem) {
+            dot[0] += *x * *y;
+            aa[0] += *x * *x;
+            bb[0] += *y * *y;
+        }
+        (sum(dot), sum(aa), sum(bb))
+    }
+
+    pub(super) fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+        dot(a, b)
+    }
+    pub(super) fn dot_f64(a: &[f64], b: &[f64]) -> f64 {
+        dot(a, b)
+    }
+    pub(super) fn l2_sq_f32(a: &[f32], b: &[f32]) -> f32 {
+        l2_sq(a, b)
+    }
+    pub(super) fn l2_sq_f64(a: &[f64], b: &[f64]) -> f64 {
+        l2_sq(a, b)
+    }
+    pub(super) fn cos_parts_f32(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+        cos_parts(a, b)
+    }
+    pub(super) fn cos_parts_f64(a: &[f64], b: &[f64]) -> (f64, f64, f64) {
+        cos_parts(a, b)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    pub(super) fn available() -> bool {
+        is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn hsum_ps(v: __m256) -> f32 {
+        let mut buf = [0f32; 8];
+        _mm256_storeu_ps(buf.as_mut_ptr(), v);
+        buf.iter().sum()
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn hsum_pd(v: __m256d) -> f64 {
+        let mut buf = [0f64; 4];
+        _mm256_storeu_pd(buf.as_mut_ptr(), v);
+        buf.iter().sum()
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len() / 8 * 8;
+        let mut acc = _mm256_setzero_ps();
+        for i in (0..n).step_by(8) {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i));
+            acc = _mm256_fmadd_ps(x, y, acc);
+        }
+        let mut ret = hsum_ps(acc);
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            ret += x * y;
+        }
+        ret
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn dot_f64(a: &[f64], b: &[f64]) -> f64 {
+        let n = a.len() / 4 * 4;
+        let mut acc = _mm256_setzero_pd();
+        for i in (0..n).step_by(4) {
+            let x = _mm256_loadu_pd(a.as_ptr().add(i));
+            let y = _mm256_loadu_pd(b.as_ptr().add(i));
+            acc = _mm256_fmadd_pd(x, y, acc);
+        }
+        let mut ret = hsum_pd(acc);
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            ret += x * y;
+        }
+        ret
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn l2_sq_f32(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len() / 8 * 8;
+        let mut acc = _mm256_setzero_ps();
+        for i in (0..n).step_by(8) {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i));
+            let d = _mm256_sub_ps(x, y);
+            acc = _mm256_fmadd_ps(d, d, acc);
+        }
+        let mut ret = hsum_ps(acc);
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            let d = x - y;
+            ret += d * d;
+        }
+        ret
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn l2_sq_f64(a: &[f64], b: &[f64]) -> f64 {
+        let n = a.len() / 4 * 4;
+        let mut acc = _mm256_setzero_pd();
+        for i in (0..n).step_by(4) {
+            let x = _mm256_loadu_pd(a.as_ptr().add(i));
+            let y = _mm256_loadu_pd(b.as_ptr().add(i));
+            let d = _mm256_sub_pd(x, y);
+            acc = _mm256_fmadd_pd(d, d, acc);
+        }
+        let mut ret = hsum_pd(acc);
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            let d = x - y;
+            ret += d * d;
+        }
+        ret
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn cos_parts_f32(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+        let n = a.len() / 8 * 8;
+        let mut dot = _mm256_setzero_ps();
+        let mut aa = _mm256_setzero_ps();
+        let mut bb = _mm256_setzero_ps();
+        for i in (0..n).step_by(8) {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i));
+            dot = _mm256_fmadd_ps(x, y, dot);
+            aa = _mm256_fmadd_ps(x, x, aa);
+            bb = _mm256_fmadd_ps(y, y, bb);
+        }
+        let (mut dot, mut aa, mut bb) = (hsum_ps(dot), hsum_ps(aa), hsum_ps(bb));
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            dot += x * y;
+            aa += x * x;
+            bb += y * y;
+        }
+        (dot, aa, bb)
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn cos_parts_f64(a: &[f64], b: &[f64]) -> (f64, f64, f64) {
+        let n = a.len() / 4 * 4;
+        let mut dot = _mm256_setzero_pd();
+        let mut aa = _mm256_setzero_pd();
+        let mut bb = _mm256_setzero_pd();
+        for i in (0..n).step_by(4) {
+            let x = _mm256_loadu_pd(a.as_ptr().add(i));
+            let y = _mm256_loadu_pd(b.as_ptr().add(i));
+            dot = _mm256_fmadd_pd(x, y, dot);
+            aa = _mm256_fmadd_pd(x, x, aa);
+            bb = _mm256_fmadd_pd(y, y, bb);
+        }
+        let (mut dot, mut aa, mut bb) = (hsum_pd(dot), hsum_pd(aa), hsum_pd(bb));
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            dot += x * y;
+            aa += x * x;
+            bb += y * y;
+        }
+        (dot, aa, bb)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm {
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len() / 4 * 4;
+        let mut acc = vdupq_n_f32(0.);
+        for i in (0..n).step_by(4) {
+            let x = vld1q_f32(a.as_ptr().add(i));
+            let y = vld1q_f32(b.as_ptr().add(i));
+            acc = vfmaq_f32(acc, x, y);
+        }
+        let mut ret = vaddvq_f32(acc);
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            ret += x * y;
+        }
+        ret
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn dot_f64(a: &[f64], b: &[f64]) -> f64 {
+        let n = a.len() / 2 * 2;
+        let mut acc = vdupq_n_f64(0.);
+        for i in (0..n).step_by(2) {
+            let x = vld1q_f64(a.as_ptr().add(i));
+            let y = vld1q_f64(b.as_ptr().add(i));
+            acc = vfmaq_f64(acc, x, y);
+        }
+        let mut ret = vaddvq_f64(acc);
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            ret += x * y;
+        }
+        ret
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn l2_sq_f32(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len() / 4 * 4;
+        let mut acc = vdupq_n_f32(0.);
+        for i in (0..n).step_by(4) {
+            let d = vsubq_f32(vld1q_f32(a.as_ptr().add(i)), vld1q_f32(b.as_ptr().add(i)));
+            acc = vfmaq_f32(acc, d, d);
+        }
+        let mut ret = vaddvq_f32(acc);
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            let d = x - y;
+            ret += d * d;
+        }
+        ret
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn l2_sq_f64(a: &[f64], b: &[f64]) -> f64 {
+        let n = a.len() / 2 * 2;
+        let mut acc = vdupq_n_f64(0.);
+        for i in (0..n).step_by(2) {
+            let d = vsubq_f64(vld1q_f64(a.as_ptr().add(i)), vld1q_f64(b.as_ptr().add(i)));
+            acc = vfmaq_f64(acc, d, d);
+        }
+        let mut ret = vaddvq_f64(acc);
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            let d = x - y;
+            ret += d * d;
+        }
+        ret
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn cos_parts_f32(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+        let n = a.len() / 4 * 4;
+        let mut dot = vdupq_n_f32(0.);
+        let mut aa = vdupq_n_f32(0.);
+        let mut bb = vdupq_n_f32(0.);
+        for i in (0..n).step_by(4) {
+            let x = vld1q_f32(a.as_ptr().add(i));
+            let y = vld1q_f32(b.as_ptr().add(i));
+            dot = vfmaq_f32(dot, x, y);
+            aa = vfmaq_f32(aa, x, x);
+            bb = vfmaq_f32(bb, y, y);
+        }
+        let (mut dot, mut aa, mut bb) = (vaddvq_f32(dot), vaddvq_f32(aa), vaddvq_f32(bb));
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            dot += x * y;
+            aa += x * x;
+            bb += y * y;
+        }
+        (dot, aa, bb)
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn cos_parts_f64(a: &[f64], b: &[f64]) -> (f64, f64, f64) {
+        let n = a.len() / 2 * 2;
+        let mut dot = vdupq_n_f64(0.);
+        let mut aa = vdupq_n_f64(0.);
+        let mut bb = vdupq_n_f64(0.);
+        for i in (0..n).step_by(2) {
+            let x = vld1q_f64(a.as_ptr().add(i));
+            let y = vld1q_f64(b.as_ptr().add(i));
+            dot = vfmaq_f64(dot, x, y);
+            aa = vfmaq_f64(aa, x, x);
+            bb = vfmaq_f64(bb, y, y);
+        }
+        let (mut dot, mut aa, mut bb) = (vaddvq_f64(dot), vaddvq_f64(aa), vaddvq_f64(bb));
+        for (x, y) in a[n..].iter().zip(&b[n..]) {
+            dot += x * y;
+            aa += x * x;
+            bb += y * y;
+        }
+        (dot, aa, bb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(n: usize, seed: f64) -> Vec<f64> {
+        (0..n).map(|i| ((i as f64 + seed) * 0.37).sin()).collect()
+    }
+
+    #[test]
+    fn kernels_match_portable() {
+        for n in [0, 1, 3, 4, 7, 8, 9, 16, 31, 100, 1000] {
+            let a = sample(n, 0.);
+            let b = sample(n, 1.5);
+            let a32 = a.iter().map(|x| *x as f32).collect::<Vec<_>>();
+            let b32 = b.iter().map(|x| *x as f32).collect::<Vec<_>>();
+
+            let close64 = |x: f64, y: f64| (x - y).abs() <= 1e-9 * (1. + y.abs());
+            let close32 = |x: f32, y: f32| (x - y).abs() <= 1e-4 * (1. + y.abs());
+
+            assert!(close64(dot_f64(&a, &b), portable::dot_f64(&a, &b)));
+            assert!(close64(l2_sq_f64(&a, &b), portable::l2_sq_f64(&a, &b)));
+            let (x, y) = (cos_parts_f64(&a, &b), portable::cos_parts_f64(&a, &b));
+            assert!(close64(x.0, y.0) && close64(x.1, y.1) && close64(x.2, y.2));
+
+            assert!(close32(dot_f32(&a32, &b32), portable::dot_f32(&a32, &b32)));
+            assert!(close32(l2_sq_f32(&a32, &b32), portable::l2_sq_f32(&a32, &b32)));
+            let (x, y) = (
+                cos_parts_f32(&a32, &b32),
+                portable::cos_parts_f32(&a32, &b32),
+            );
+            assert!(close32(x.0, y.0) && close32(x.1, y.1) && close32(x.2, y.2));
+
+            let naive: f64 = a.iter().zip(&b).map(|(x, y)| (x - y) * (x - y)).sum();
+            assert!(close64(l2_sq_f64(&a, &b), naive));
+        }
+    }
+
+    #[test]
+    fn vector_distances() {
+        let a = Vector::F64(Array1::from(vec![1., 0., 0.]));
+        let b = Vector::F64(Array1::from(vec![0., 2., 0.]));
+        assert_eq!(l2_dist(&a, &b), Some(5.));
+        assert_eq!(ip_dist(&a, &b), Some(1.));
+        assert_eq!(cos_dist(&a, &b), Some(1.));
+        assert_eq!(cos_dist(&a, &a), Some(0.));
+        assert_eq!(norm(&b), 2.);
+        let c = Vector::F32(Array1::from(vec![1., 0., 0.]));
+        assert_eq!(l2_dist(&a, &c), None);
+    }
+}
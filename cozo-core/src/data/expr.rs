@@ -6,6 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::borrow::Cow;
 use std::cmp::{max, min};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
@@ -18,6 +19,7 @@ use serde::{Deserializer, Serializer};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::batch::{all_scalars, BatchValue, Bitmap, Column, TupleBatch};
 use crate::data::functions::*;
 use crate::data::relation::NullableColType;
 use crate::data::symb::Symbol;
@@ -406,13 +408,17 @@ impl Expr {
         self.partial_eval()?;
         match self {
             Expr::Const { val, .. } => Ok(val),
+            // calls to nondeterministic functions are left in place by partial evaluation
+            e if e.bindings()?.is_empty() => e.eval(&vec![]),
             _ => bail!(NotConstError),
         }
     }
+    /// Evaluate the subexpressions that do not depend on any binding.
+    /// Calls to nondeterministic functions such as `rand_float()` are not evaluated.
     pub(crate) fn partial_eval(&mut self) -> Result<()> {
-        if let Expr::Apply { args, span, .. } = self {
+        if let Expr::Apply { op, args, span } = self {
             let span = *span;
-            let mut all_evaluated = true;
+            let mut all_evaluated = !op.is_nondeterministic();
             for arg in args.iter_mut() {
                 arg.partial_eval()?;
                 all_evaluated = all_evaluated && matches!(arg, Expr::Const { .. });
@@ -445,6 +451,62 @@ impl Expr {
         }
         Ok(())
     }
+    /// Replace the calls to `now()` by the given time, in seconds since the epoch
+    pub(crate) fn fix_now(&mut self, now: f64) {
+        match self {
+            Expr::Apply { op, span, .. } if op.name == OP_NOW.name => {
+                let span = *span;
+                *self = Expr::Const {
+                    val: DataValue::from(now),
+                    span,
+                };
+            }
+            Expr::Apply { args, .. } | Expr::UnboundApply { args, .. } => {
+                for arg in args.iter_mut() {
+                    arg.fix_now(now);
+                }
+            }
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in clauses {
+                    cond.fix_now(now);
+                    val.fix_now(now);
+                }
+            }
+            Expr::Binding { .. } | Expr::Const { .. } => {}
+        }
+    }
+    /// Call `f` on every constant, with its span
+    pub(crate) fn for_each_const(&mut self, f: &mut impl FnMut(&mut DataValue, SourceSpan)) {
+        match self {
+            Expr::Const { val, span } => f(val, *span),
+            Expr::Apply { args, .. } | Expr::UnboundApply { args, .. } => {
+                for arg in args.iter_mut() {
+                    arg.for_each_const(f);
+                }
+            }
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in clauses {
+                    cond.for_each_const(f);
+                    val.for_each_const(f);
+                }
+            }
+            Expr::Binding { .. } => {}
+        }
+    }
+    /// The name and span of the first call to a nondeterministic function, if any
+    pub(crate) fn nondeterministic_call(&self) -> Option<(&'static str, SourceSpan)> {
+        match self {
+            Expr::Apply { op, span, .. } if op.is_nondeterministic() => Some((op.name, *span)),
+            Expr::Apply { args, .. } | Expr::UnboundApply { args, .. } => {
+                args.iter().find_map(|arg| arg.nondeterministic_call())
+            }
+            Expr::Cond { clauses, .. } => clauses.iter().find_map(|(cond, val)| {
+                cond.nondeterministic_call()
+                    .or_else(|| val.nondeterministic_call())
+            }),
+            Expr::Binding { .. } | Expr::Const { .. } => None,
+        }
+    }
     pub(crate) fn bindings(&self) -> Result<BTreeSet<Symbol>> {
         let mut ret = BTreeSet::new();
         self.collect_bindings(&mut ret)?;
@@ -519,6 +581,117 @@ impl Expr {
             }
         }
     }
+    /// Whether the expression contains an op with a batched implementation
+    pub(crate) fn has_batched_op(&self) -> bool {
+        match self {
+            Expr::Apply { op, args, .. } => {
+                op.batch.is_some() || args.iter().any(|arg| arg.has_batched_op())
+            }
+            Expr::Cond { clauses, .. } => clauses
+                .iter()
+                .any(|(cond, val)| cond.has_batched_op() || val.has_batched_op()),
+            Expr::Binding { .. } | Expr::Const { .. } | Expr::UnboundApply { .. } => false,
+        }
+    }
+    /// Evaluate the expression over the rows of `batch` set in `selection`.
+    /// The results for the other rows are unspecified, but errors are only raised for selected rows.
+    ///
+    /// Ops with a batched implementation run over whole columns, the others row by row.
+    pub(crate) fn eval_batch<'a>(
+        &self,
+        batch: &'a TupleBatch,
+        selection: &Bitmap,
+    ) -> Result<BatchValue<'a>> {
+        match self {
+            Expr::Binding { var, tuple_pos, .. } => match tuple_pos {
+                None => {
+                    bail!(UnboundVariableError(var.name.to_string(), var.span))
+                }
+                Some(i) => {
+                    if *i >= batch.arity() {
+                        bail!(TupleTooShortError(
+                            var.name.to_string(),
+                            *i,
+                            batch.arity(),
+                            var.span
+                        ))
+                    }
+                    Ok(BatchValue::Column(Cow::Borrowed(batch.column(*i))))
+                }
+            },
+            Expr::Const { val, .. } => Ok(BatchValue::Scalar(val.clone())),
+            Expr::Apply { op, args, .. } => {
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|arg| arg.eval_batch(batch, selection))
+                    .try_collect()?;
+                if all_scalars(&args) {
+                    let args = args.iter().map(|arg| arg.get(0)).collect_vec();
+                    return Ok(BatchValue::Scalar(
+                        (op.inner)(&args)
+                            .map_err(|err| EvalRaisedError(self.span(), err.to_string()))?,
+                    ));
+                }
+                if let Some(batched) = op.batch {
+                    if let Some(col) = batched(&args, batch.len()) {
+                        return Ok(BatchValue::Column(Cow::Owned(col)));
+                    }
+                }
+                let mut ret = Column::Null(0);
+                let mut row = Vec::with_capacity(args.len());
+                for i in 0..batch.len() {
+                    if selection.get(i) {
+                        row.clear();
+                        row.extend(args.iter().map(|arg| arg.get(i)));
+                        ret.push(
+                            (op.inner)(&row)
+                                .map_err(|err| EvalRaisedError(self.span(), err.to_string()))?,
+                        );
+                    } else {
+                        ret.push(DataValue::Null);
+                    }
+                }
+                Ok(BatchValue::Column(Cow::Owned(ret)))
+            }
+            Expr::Cond { .. } | Expr::UnboundApply { .. } => {
+                let mut ret = Column::Null(0);
+                for i in 0..batch.len() {
+                    ret.push(if selection.get(i) {
+                        self.eval(batch.row(i))?
+                    } else {
+                        DataValue::Null
+                    });
+                }
+                Ok(BatchValue::Column(Cow::Owned(ret)))
+            }
+        }
+    }
+    /// Evaluate the expression as a predicate over the rows of `batch`, and unset
+    /// in `selection` the rows for which it is false
+    pub(crate) fn eval_batch_pred(&self, batch: &TupleBatch, selection: &mut Bitmap) -> Result<()> {
+        let res = self.eval_batch(batch, selection)?;
+        if let BatchValue::Column(col) = &res {
+            if let Column::Bool { values, validity } = col.as_ref() {
+                if validity.all() {
+                    for i in 0..batch.len() {
+                        if !values.get(i) {
+                            selection.set(i, false);
+                        }
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        let selected = selection.ones().collect_vec();
+        for i in selected {
+            match res.get(i) {
+                DataValue::Bool(true) => {}
+                DataValue::Bool(false) => selection.set(i, false),
+                v => bail!(PredicateTypeError(self.span(), v)),
+            }
+        }
+        Ok(())
+    }
     pub(crate) fn extract_bound(&self, target: &Symbol) -> Result<ValueRange> {
         Ok(match self {
             Expr::Binding { .. } | Expr::Const { .. } | Expr::Cond { .. } => ValueRange::default(),
@@ -730,8 +903,17 @@ pub struct Op {
     pub(crate) min_arity: usize,
     pub(crate) vararg: bool,
     pub(crate) inner: fn(&[DataValue]) -> Result<DataValue>,
+    /// Optional implementation over whole batches, see [Expr::eval_batch]
+    pub(crate) batch: Option<BatchOpFn>,
 }
 
+/// A batched implementation of an op, called with at least one column among the arguments.
+///
+/// It must give the same results as the row-wise implementation for all rows, and return
+/// `None` to decline any input it does not handle, or on which the row-wise implementation
+/// could fail: declined inputs are evaluated row by row, on selected rows only.
+pub(crate) type BatchOpFn = fn(&[BatchValue<'_>], usize) -> Option<Column>;
+
 /// Used as `Arc<dyn CustomOp>`
 pub trait CustomOp {
     fn name(&self) -> &'static str;
@@ -897,6 +1079,8 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "t2s" => &OP_T2S,
         "encode_base64" => &OP_ENCODE_BASE64,
         "decode_base64" => &OP_DECODE_BASE64,
+        "to_hex" => &OP_TO_HEX,
+        "from_hex" => &OP_FROM_HEX,
         "first" => &OP_FIRST,
         "last" => &OP_LAST,
         "chunks" => &OP_CHUNKS,
@@ -905,6 +1089,7 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "to_int" => &OP_TO_INT,
         "to_float" => &OP_TO_FLOAT,
         "to_string" => &OP_TO_STRING,
+        "format_number" => &OP_FORMAT_NUMBER,
         "l2_dist" => &OP_L2_DIST,
         "l2_normalize" => &OP_L2_NORMALIZE,
         "ip_dist" => &OP_IP_DIST,
@@ -928,13 +1113,52 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "now" => &OP_NOW,
         "format_timestamp" => &OP_FORMAT_TIMESTAMP,
         "parse_timestamp" => &OP_PARSE_TIMESTAMP,
+        "format_timestamp_fmt" => &OP_FORMAT_TIMESTAMP_FMT,
+        "parse_timestamp_fmt" => &OP_PARSE_TIMESTAMP_FMT,
+        "add_business_days" => &OP_ADD_BUSINESS_DAYS,
+        "business_minutes_between" => &OP_BUSINESS_MINUTES_BETWEEN,
+        "iso_week" => &OP_ISO_WEEK,
+        "iso_year" => &OP_ISO_YEAR,
+        "quarter" => &OP_QUARTER,
+        "start_of_week" => &OP_START_OF_WEEK,
+        "start_of_month" => &OP_START_OF_MONTH,
+        "start_of_quarter" => &OP_START_OF_QUARTER,
+        "start_of_year" => &OP_START_OF_YEAR,
+        "date_trunc" => &OP_DATE_TRUNC,
+        "date_diff" => &OP_DATE_DIFF,
         "vec" => &OP_VEC,
         "rand_vec" => &OP_RAND_VEC,
+        "vec_get" => &OP_VEC_GET,
+        "vec_slice" => &OP_VEC_SLICE,
+        "vec_concat" => &OP_VEC_CONCAT,
+        "vec_min" => &OP_VEC_MIN,
+        "vec_max" => &OP_VEC_MAX,
+        "vec_clamp" => &OP_VEC_CLAMP,
+        "vec_to_bytes" => &OP_VEC_TO_BYTES,
+        "vec_from_bytes" => &OP_VEC_FROM_BYTES,
+        "mat" => &OP_MAT,
+        "mat_shape" => &OP_MAT_SHAPE,
+        "transpose" => &OP_TRANSPOSE,
+        "matmul" => &OP_MATMUL,
+        "l2_normalize_rows" => &OP_L2_NORMALIZE_ROWS,
+        "sparse_vec" => &OP_SPARSE_VEC,
+        "sparse_dot" => &OP_SPARSE_DOT,
+        "sparse_cos_dist" => &OP_SPARSE_COS_DIST,
+        "sparse_to_dense" => &OP_SPARSE_TO_DENSE,
+        "chunk_text" => &OP_CHUNK_TEXT,
+        "soundex" => &OP_SOUNDEX,
+        "metaphone" => &OP_METAPHONE,
+        "trigram_similarity" => &OP_TRIGRAM_SIMILARITY,
         _ => return None,
     })
 }
 
 impl Op {
+    /// Whether the op may return different results for the same arguments,
+    /// in which case calls to it must be neither evaluated ahead of time nor shared
+    pub(crate) fn is_nondeterministic(&self) -> bool {
+        NONDETERMINISTIC_OPS.contains(&self.name)
+    }
     pub(crate) fn post_process_args(&self, args: &mut [Expr]) {
         if self.name.starts_with("OP_REGEX_") {
             args[1] = Expr::Apply {
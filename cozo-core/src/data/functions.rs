@@ -8,6 +8,7 @@
 
 use std::cmp::Reverse;
 use std::collections::BTreeSet;
+use std::fmt::Write;
 use std::mem;
 use std::ops::{Div, Rem};
 use std::str::FromStr;
@@ -15,7 +16,12 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::format::{Item, Parsed, StrftimeItems};
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc,
+    Weekday,
+};
+use chrono_tz::Tz;
 use itertools::Itertools;
 #[cfg(target_arch = "wasm32")]
 use js_sys::Date;
@@ -27,11 +33,14 @@ use smartstring::SmartString;
 use unicode_normalization::UnicodeNormalization;
 use uuid::v1::Timestamp;
 
+use crate::data::batch::{BatchValue, CmpOp, Column};
+use crate::data::distance;
 use crate::data::expr::Op;
 use crate::data::json::JsonValue;
 use crate::data::relation::VecElementType;
 use crate::data::value::{
-    DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector,
+    DataValue, JsonData, Matrix, Num, RegexWrapper, SparseVector, UuidWrapper, Validity,
+    ValidityTs, Vector,
 };
 
 macro_rules! define_op {
@@ -41,10 +50,32 @@ macro_rules! define_op {
             min_arity: $min_arity,
             vararg: $vararg,
             inner: ::casey::lower!($name),
+            batch: None,
+        };
+    };
+    ($name:ident, $min_arity:expr, $vararg:expr, batch = $batch:expr) => {
+        pub(crate) const $name: Op = Op {
+            name: stringify!($name),
+            min_arity: $min_arity,
+            vararg: $vararg,
+            inner: ::casey::lower!($name),
+            batch: Some($batch),
         };
     };
 }
 
+/// Names of the ops whose results may differ between calls with the same arguments
+pub(crate) const NONDETERMINISTIC_OPS: &[&str] = &[
+    OP_RAND_VEC.name,
+    OP_RAND_FLOAT.name,
+    OP_RAND_BERNOULLI.name,
+    OP_RAND_INT.name,
+    OP_RAND_CHOOSE.name,
+    OP_NOW.name,
+    OP_RAND_UUID_V1.name,
+    OP_RAND_UUID_V4.name,
+];
+
 fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
     use DataValue::*;
     if !matches!(
@@ -259,6 +290,23 @@ fn to_json(d: &DataValue) -> JsonValue {
         DataValue::Validity(vld) => {
             json!([vld.timestamp.0, vld.is_assert.0])
         }
+        DataValue::Mat(m) => match m {
+            Matrix::F32(m) => m
+                .outer_iter()
+                .map(|row| row.iter().map(|el| json!(el)).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+                .into(),
+            Matrix::F64(m) => m
+                .outer_iter()
+                .map(|row| row.iter().map(|el| json!(el)).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+                .into(),
+        },
+        DataValue::Sparse(s) => s
+            .iter()
+            .map(|(idx, val)| (idx.to_string(), json!(val)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
         DataValue::Bot => {
             json!(null)
         }
@@ -294,7 +342,61 @@ pub(crate) fn op_coalesce(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Null)
 }
 
-define_op!(OP_EQ, 2, false);
+/// Batched comparison of numbers or strings, with the semantics of the row-wise comparison ops
+fn batch_cmp(args: &[BatchValue<'_>], len: usize, op: CmpOp) -> Option<Column> {
+    if let (Some(l), Some(r)) = (args[0].nums(), args[1].nums()) {
+        return Some(Column::from_bools((0..len).map(|i| {
+            op.test(match (l.get(i), r.get(i)) {
+                (Num::Int(l), Num::Int(r)) => Some(l.cmp(&r)),
+                (Num::Float(l), Num::Float(r)) => Some(l.total_cmp(&r)),
+                (Num::Int(l), Num::Float(r)) => (l as f64).partial_cmp(&r),
+                (Num::Float(l), Num::Int(r)) => l.partial_cmp(&(r as f64)),
+            })
+        })));
+    }
+    if let (Some(l), Some(r)) = (args[0].strs(), args[1].strs()) {
+        return Some(Column::from_bools(
+            (0..len).map(|i| op.test(Some(l.get(i).cmp(r.get(i))))),
+        ));
+    }
+    None
+}
+
+fn batch_eq(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    batch_cmp(args, len, CmpOp::Eq)
+}
+fn batch_neq(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    batch_cmp(args, len, CmpOp::Neq)
+}
+fn batch_gt(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    batch_cmp(args, len, CmpOp::Gt)
+}
+fn batch_ge(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    batch_cmp(args, len, CmpOp::Ge)
+}
+fn batch_lt(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    batch_cmp(args, len, CmpOp::Lt)
+}
+fn batch_le(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    batch_cmp(args, len, CmpOp::Le)
+}
+
+/// Apply `f` to pairs of numbers row by row. `f` returns `None` on overflow,
+/// to leave it to the row-wise implementation.
+fn batch_nums(
+    args: &[BatchValue<'_>],
+    len: usize,
+    f: impl Fn(Num, Num) -> Option<Num>,
+) -> Option<Column> {
+    if args.len() != 2 {
+        return None;
+    }
+    let (l, r) = (args[0].nums()?, args[1].nums()?);
+    let nums: Vec<_> = (0..len).map(|i| f(l.get(i), r.get(i))).collect::<Option<_>>()?;
+    Some(Column::from_nums(nums))
+}
+
+define_op!(OP_EQ, 2, false, batch = batch_eq);
 pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
@@ -330,7 +432,7 @@ pub(crate) fn op_is_in(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(right.contains(left)))
 }
 
-define_op!(OP_NEQ, 2, false);
+define_op!(OP_NEQ, 2, false, batch = batch_neq);
 pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
@@ -339,7 +441,7 @@ pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
     }))
 }
 
-define_op!(OP_GT, 2, false);
+define_op!(OP_GT, 2, false, batch = batch_gt);
 pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
     Ok(DataValue::from(match (&args[0], &args[1]) {
@@ -349,7 +451,7 @@ pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
     }))
 }
 
-define_op!(OP_GE, 2, false);
+define_op!(OP_GE, 2, false, batch = batch_ge);
 pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
     Ok(DataValue::from(match (&args[0], &args[1]) {
@@ -359,7 +461,7 @@ pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
     }))
 }
 
-define_op!(OP_LT, 2, false);
+define_op!(OP_LT, 2, false, batch = batch_lt);
 pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
     Ok(DataValue::from(match (&args[0], &args[1]) {
@@ -369,7 +471,7 @@ pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
     }))
 }
 
-define_op!(OP_LE, 2, false);
+define_op!(OP_LE, 2, false, batch = batch_le);
 pub(crate) fn op_le(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
     Ok(DataValue::from(match (&args[0], &args[1]) {
@@ -379,7 +481,25 @@ pub(crate) fn op_le(args: &[DataValue]) -> Result<DataValue> {
     }))
 }
 
-define_op!(OP_ADD, 0, true);
+fn batch_add(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    batch_nums(args, len, |l, r| {
+        let mut i_accum = 0i64;
+        let mut f_accum = 0.0f64;
+        for n in [l, r] {
+            match n {
+                Num::Int(i) => i_accum = i_accum.checked_add(i)?,
+                Num::Float(f) => f_accum += f,
+            }
+        }
+        Some(if f_accum == 0.0f64 {
+            Num::Int(i_accum)
+        } else {
+            Num::Float(i_accum as f64 + f_accum)
+        })
+    })
+}
+
+define_op!(OP_ADD, 0, true, batch = batch_add);
 pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
     let mut i_accum = 0i64;
     let mut f_accum = 0.0f64;
@@ -480,7 +600,16 @@ pub(crate) fn op_min(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_SUB, 2, false);
+fn batch_sub(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    batch_nums(args, len, |l, r| {
+        Some(match (l, r) {
+            (Num::Int(a), Num::Int(b)) => Num::Int(a.checked_sub(b)?),
+            (a, b) => Num::Float(a.get_float() - b.get_float()),
+        })
+    })
+}
+
+define_op!(OP_SUB, 2, false, batch = batch_sub);
 pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
@@ -541,7 +670,25 @@ pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
-define_op!(OP_MUL, 0, true);
+fn batch_mul(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    batch_nums(args, len, |l, r| {
+        let mut i_accum = 1i64;
+        let mut f_accum = 1.0f64;
+        for n in [l, r] {
+            match n {
+                Num::Int(i) => i_accum = i_accum.checked_mul(i)?,
+                Num::Float(f) => f_accum *= f,
+            }
+        }
+        Some(if f_accum == 1.0f64 {
+            Num::Int(i_accum)
+        } else {
+            Num::Float(i_accum as f64 * f_accum)
+        })
+    })
+}
+
+define_op!(OP_MUL, 0, true, batch = batch_mul);
 pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
     let mut i_accum = 1i64;
     let mut f_accum = 1.0f64;
@@ -612,7 +759,11 @@ fn mul_vecs(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_DIV, 2, false);
+fn batch_div(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    batch_nums(args, len, |l, r| Some(Num::Float(l.get_float() / r.get_float())))
+}
+
+define_op!(OP_DIV, 2, false, batch = batch_div);
 pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
@@ -1390,7 +1541,16 @@ pub(crate) fn op_regex(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
-define_op!(OP_REGEX_MATCHES, 2, false);
+fn batch_regex_matches(args: &[BatchValue<'_>], len: usize) -> Option<Column> {
+    match (args[0].strs()?, &args[1]) {
+        (s, BatchValue::Scalar(DataValue::Regex(r))) => {
+            Some(Column::from_bools((0..len).map(|i| r.0.is_match(s.get(i)))))
+        }
+        _ => None,
+    }
+}
+
+define_op!(OP_REGEX_MATCHES, 2, false, batch = batch_regex_matches);
 pub(crate) fn op_regex_matches(args: &[DataValue]) -> Result<DataValue> {
     match (&args[0], &args[1]) {
         (DataValue::Str(s), DataValue::Regex(r)) => Ok(DataValue::from(r.0.is_match(s))),
@@ -1916,6 +2076,38 @@ pub(crate) fn op_decode_base64(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_TO_HEX, 1, false);
+pub(crate) fn op_to_hex(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(match &args[0] {
+        DataValue::Num(Num::Int(i)) if *i < 0 => format!("-{:x}", i.unsigned_abs()),
+        DataValue::Num(Num::Int(i)) => format!("{:x}", i),
+        DataValue::Bytes(b) => b.iter().map(|x| format!("{:02x}", x)).collect(),
+        _ => bail!("'to_hex' requires an integer or bytes"),
+    }))
+}
+
+define_op!(OP_FROM_HEX, 1, false);
+pub(crate) fn op_from_hex(args: &[DataValue]) -> Result<DataValue> {
+    let s = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("'from_hex' requires a string"))?
+        .trim();
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let digits = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+        .unwrap_or(digits);
+    let i = i128::from_str_radix(digits, 16)
+        .ok()
+        .filter(|_| !digits.starts_with(|c: char| c == '+' || c == '-'))
+        .and_then(|i| i64::try_from(if negative { -i } else { i }).ok())
+        .ok_or_else(|| miette!("'from_hex' cannot read '{}' as a hexadecimal integer", s))?;
+    Ok(DataValue::from(i))
+}
+
 define_op!(OP_TO_BOOL, 1, false);
 pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
@@ -1929,6 +2121,8 @@ pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
         DataValue::List(l) => !l.is_empty(),
         DataValue::Set(s) => !s.is_empty(),
         DataValue::Vec(_) => true,
+        DataValue::Mat(_) => true,
+        DataValue::Sparse(s) => s.nnz() != 0,
         DataValue::Validity(vld) => vld.is_assert.0,
         DataValue::Bot => false,
         DataValue::Json(json) => match &json.0 {
@@ -1955,6 +2149,8 @@ pub(crate) fn op_to_unity(args: &[DataValue]) -> Result<DataValue> {
         DataValue::List(l) => i64::from(!l.is_empty()),
         DataValue::Set(s) => i64::from(!s.is_empty()),
         DataValue::Vec(_) => 1,
+        DataValue::Mat(_) => 1,
+        DataValue::Sparse(s) => i64::from(s.nnz() != 0),
         DataValue::Validity(vld) => i64::from(vld.is_assert.0),
         DataValue::Bot => 0,
         DataValue::Json(json) => match &json.0 {
@@ -2027,6 +2223,166 @@ fn val2str(arg: &DataValue) -> String {
     }
 }
 
+/// A number pattern such as `#,##0.00`, `0.0%` or `0.00E+00`, with literal text around it
+struct NumberPattern {
+    prefix: String,
+    suffix: String,
+    grouping: bool,
+    min_int: usize,
+    min_frac: usize,
+    max_frac: usize,
+    /// the minimal number of digits of the exponent, and whether its sign is always shown
+    exponent: Option<(usize, bool)>,
+    percent: bool,
+}
+
+impl NumberPattern {
+    fn parse(pattern: &str) -> Result<Self> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let start = chars
+            .iter()
+            .position(|c| matches!(c, '0' | '#'))
+            .ok_or_else(|| miette!("bad number pattern, no digits in '{}'", pattern))?;
+        let start = if start > 0 && chars[start - 1] == '.' {
+            start - 1
+        } else {
+            start
+        };
+        let mut ret = NumberPattern {
+            prefix: chars[..start].iter().collect(),
+            suffix: String::new(),
+            grouping: false,
+            min_int: 0,
+            min_frac: 0,
+            max_frac: 0,
+            exponent: None,
+            percent: false,
+        };
+        let mut in_frac = false;
+        let mut pos = start;
+        while pos < chars.len() {
+            match chars[pos] {
+                '0' if in_frac => {
+                    ret.min_frac += 1;
+                    ret.max_frac += 1;
+                }
+                '#' if in_frac => ret.max_frac += 1,
+                '0' => ret.min_int += 1,
+                '#' => {}
+                ',' if !in_frac => ret.grouping = true,
+                '.' if !in_frac => in_frac = true,
+                'E' => {
+                    let always_sign = chars.get(pos + 1) == Some(&'+');
+                    let first_digit = if always_sign { pos + 2 } else { pos + 1 };
+                    let digits = chars[first_digit..]
+                        .iter()
+                        .take_while(|c| **c == '0')
+                        .count();
+                    ensure!(
+                        digits > 0,
+                        "bad number pattern, no digits for the exponent in '{}'",
+                        pattern
+                    );
+                    ret.exponent = Some((digits, always_sign));
+                    pos = first_digit + digits;
+                    break;
+                }
+                _ => break,
+            }
+            pos += 1;
+        }
+        ret.suffix = chars[pos..].iter().collect();
+        ret.percent = ret.prefix.contains('%') || ret.suffix.contains('%');
+        Ok(ret)
+    }
+
+    fn format(&self, n: Num, group_sep: char, decimal_sep: char) -> String {
+        let f = if self.percent {
+            n.get_float() * 100.
+        } else {
+            n.get_float()
+        };
+        if !f.is_finite() {
+            return f.to_string();
+        }
+        let (mantissa, exponent) = match (n, self.exponent) {
+            // integers are written exactly, even when too large for floats
+            (Num::Int(i), None) if !self.percent => (i.unsigned_abs().to_string(), None),
+            (_, None) => (format!("{:.*}", self.max_frac, f.abs()), None),
+            (_, Some(_)) => {
+                let s = format!("{:.*e}", self.max_frac, f.abs());
+                let (mantissa, exponent) = s.split_once('e').unwrap();
+                (mantissa.to_string(), Some(exponent.parse::<i32>().unwrap()))
+            }
+        };
+        let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((&mantissa, ""));
+        let int_part = int_part.trim_start_matches('0');
+        let mut int_digits = "0".repeat(self.min_int.saturating_sub(int_part.len()));
+        int_digits.push_str(int_part);
+        let mut frac_digits = frac_part.trim_end_matches('0').to_string();
+        if frac_digits.len() < self.min_frac {
+            frac_digits.push_str(&"0".repeat(self.min_frac - frac_digits.len()));
+        }
+
+        let mut ret = String::new();
+        if f < 0. && format!("{}{}", int_digits, frac_digits).contains(|c: char| c != '0') {
+            ret.push('-');
+        }
+        ret.push_str(&self.prefix);
+        if self.grouping && exponent.is_none() {
+            let len = int_digits.len();
+            for (i, c) in int_digits.chars().enumerate() {
+                if i > 0 && (len - i) % 3 == 0 {
+                    ret.push(group_sep);
+                }
+                ret.push(c);
+            }
+        } else {
+            ret.push_str(&int_digits);
+        }
+        if !frac_digits.is_empty() {
+            ret.push(decimal_sep);
+            ret.push_str(&frac_digits);
+        }
+        if let (Some(e), Some((min_digits, always_sign))) = (exponent, self.exponent) {
+            ret.push('E');
+            if e < 0 {
+                ret.push('-');
+            } else if always_sign {
+                ret.push('+');
+            }
+            ret.push_str(&format!("{:0>1$}", e.unsigned_abs(), min_digits));
+        }
+        ret.push_str(&self.suffix);
+        ret
+    }
+}
+
+define_op!(OP_FORMAT_NUMBER, 2, true);
+pub(crate) fn op_format_number(args: &[DataValue]) -> Result<DataValue> {
+    let n = match &args[0] {
+        DataValue::Num(n) => *n,
+        _ => bail!("'format_number' requires a number"),
+    };
+    let pattern = args[1]
+        .get_str()
+        .ok_or_else(|| miette!("'format_number' requires a pattern string"))?;
+    let pattern = NumberPattern::parse(pattern)?;
+    let (group_sep, decimal_sep) = match args.get(2) {
+        None | Some(DataValue::Null) => (',', '.'),
+        Some(v) => {
+            let mut seps = v.get_str().unwrap_or_default().chars();
+            match (seps.next(), seps.next(), seps.next()) {
+                (Some(group), Some(decimal), None) => (group, decimal),
+                _ => bail!(
+                    "'format_number' expects the grouping and decimal separators as a string of two characters, such as '.,'"
+                ),
+            }
+        }
+    };
+    Ok(DataValue::from(pattern.format(n, group_sep, decimal_sep)))
+}
+
 define_op!(OP_VEC, 1, true);
 pub(crate) fn op_vec(args: &[DataValue]) -> Result<DataValue> {
     let t = match args.get(1) {
@@ -2167,91 +2523,811 @@ pub(crate) fn op_rand_vec(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_L2_NORMALIZE, 1, false);
 pub(crate) fn op_l2_normalize(args: &[DataValue]) -> Result<DataValue> {
-    let a = &args[0];
-    match a {
-        DataValue::Vec(Vector::F32(a)) => {
-            let norm = a.dot(a).sqrt();
-            Ok(DataValue::Vec(Vector::F32(a / norm)))
-        }
-        DataValue::Vec(Vector::F64(a)) => {
-            let norm = a.dot(a).sqrt();
-            Ok(DataValue::Vec(Vector::F64(a / norm)))
+    match &args[0] {
+        DataValue::Vec(v) => {
+            let norm = distance::norm(v);
+            Ok(DataValue::Vec(match v {
+                Vector::F32(a) => Vector::F32(a / norm as f32),
+                Vector::F64(a) => Vector::F64(a / norm),
+            }))
         }
         _ => bail!("'l2_normalize' requires a vector"),
     }
 }
 
+fn vector_dist(
+    name: &str,
+    args: &[DataValue],
+    f: fn(&Vector, &Vector) -> Option<f64>,
+) -> Result<DataValue> {
+    let (a, b) = match (&args[0], &args[1]) {
+        (DataValue::Vec(a @ Vector::F32(_)), DataValue::Vec(b @ Vector::F32(_)))
+        | (DataValue::Vec(a @ Vector::F64(_)), DataValue::Vec(b @ Vector::F64(_))) => (a, b),
+        _ => bail!("'{name}' requires two vectors of the same type"),
+    };
+    ensure!(
+        a.len() == b.len(),
+        "'{name}' requires two vectors of the same length"
+    );
+    // the types have been checked above
+    Ok(DataValue::from(f(a, b).unwrap()))
+}
+
 define_op!(OP_L2_DIST, 2, false);
 pub(crate) fn op_l2_dist(args: &[DataValue]) -> Result<DataValue> {
-    let a = &args[0];
-    let b = &args[1];
-    match (a, b) {
+    vector_dist("l2_dist", args, distance::l2_dist)
+}
+
+define_op!(OP_IP_DIST, 2, false);
+pub(crate) fn op_ip_dist(args: &[DataValue]) -> Result<DataValue> {
+    vector_dist("ip_dist", args, distance::ip_dist)
+}
+
+define_op!(OP_COS_DIST, 2, false);
+pub(crate) fn op_cos_dist(args: &[DataValue]) -> Result<DataValue> {
+    vector_dist("cos_dist", args, distance::cos_dist)
+}
+
+define_op!(OP_VEC_GET, 2, false);
+pub(crate) fn op_vec_get(args: &[DataValue]) -> Result<DataValue> {
+    let i = args[1]
+        .get_int()
+        .ok_or_else(|| miette!("second argument to 'vec_get' must be an integer"))?;
+    match &args[0] {
+        DataValue::Vec(Vector::F32(a)) => {
+            Ok(DataValue::from(a[get_index(i, a.len(), false)?] as f64))
+        }
+        DataValue::Vec(Vector::F64(a)) => Ok(DataValue::from(a[get_index(i, a.len(), false)?])),
+        _ => bail!("first argument to 'vec_get' must be a vector"),
+    }
+}
+
+define_op!(OP_VEC_SLICE, 3, false);
+pub(crate) fn op_vec_slice(args: &[DataValue]) -> Result<DataValue> {
+    let v = match &args[0] {
+        DataValue::Vec(v) => v,
+        _ => bail!("first argument to 'vec_slice' must be a vector"),
+    };
+    let m = args[1]
+        .get_int()
+        .ok_or_else(|| miette!("second argument to 'vec_slice' must be an integer"))?;
+    let n = args[2]
+        .get_int()
+        .ok_or_else(|| miette!("third argument to 'vec_slice' must be an integer"))?;
+    let m = get_index(m, v.len(), true)?;
+    let n = get_index(n, v.len(), true)?;
+    ensure!(
+        m <= n,
+        "the end of the slice in 'vec_slice' must not come before its start"
+    );
+    Ok(DataValue::Vec(match v {
+        Vector::F32(a) => Vector::F32(a.slice(ndarray::s![m..n]).to_owned()),
+        Vector::F64(a) => Vector::F64(a.slice(ndarray::s![m..n]).to_owned()),
+    }))
+}
+
+define_op!(OP_VEC_CONCAT, 1, true);
+pub(crate) fn op_vec_concat(args: &[DataValue]) -> Result<DataValue> {
+    let mut f32s = vec![];
+    let mut f64s = vec![];
+    for arg in args {
+        match arg {
+            DataValue::Vec(Vector::F32(a)) => f32s.extend(a.iter()),
+            DataValue::Vec(Vector::F64(a)) => f64s.extend(a.iter()),
+            _ => bail!("'vec_concat' requires vectors"),
+        }
+    }
+    Ok(DataValue::Vec(match &args[0] {
+        DataValue::Vec(Vector::F32(_)) if f64s.is_empty() => {
+            Vector::F32(ndarray::Array1::from(f32s))
+        }
+        DataValue::Vec(Vector::F64(_)) if f32s.is_empty() => {
+            Vector::F64(ndarray::Array1::from(f64s))
+        }
+        _ => bail!("'vec_concat' requires vectors of the same type"),
+    }))
+}
+
+/// Apply `f` to the elements of the vector `v` and to the matching elements of `other`,
+/// either a vector of the same type and length or a number
+fn vector_elementwise(
+    name: &str,
+    v: &DataValue,
+    other: &DataValue,
+    f: fn(f64, f64) -> f64,
+) -> Result<DataValue> {
+    Ok(DataValue::Vec(match (v, other) {
         (DataValue::Vec(Vector::F32(a)), DataValue::Vec(Vector::F32(b))) => {
-            if a.len() != b.len() {
-                bail!("'l2_dist' requires two vectors of the same length");
-            }
-            let diff = a - b;
-            Ok(DataValue::from(diff.dot(&diff) as f64))
+            ensure!(
+                a.len() == b.len(),
+                "'{name}' requires vectors of the same length"
+            );
+            Vector::F32(
+                a.iter()
+                    .zip(b.iter())
+                    .map(|(x, y)| f(*x as f64, *y as f64) as f32)
+                    .collect(),
+            )
         }
         (DataValue::Vec(Vector::F64(a)), DataValue::Vec(Vector::F64(b))) => {
-            if a.len() != b.len() {
-                bail!("'l2_dist' requires two vectors of the same length");
+            ensure!(
+                a.len() == b.len(),
+                "'{name}' requires vectors of the same length"
+            );
+            Vector::F64(a.iter().zip(b.iter()).map(|(x, y)| f(*x, *y)).collect())
+        }
+        (DataValue::Vec(Vector::F32(a)), DataValue::Num(n)) => {
+            let y = n.get_float();
+            Vector::F32(a.mapv(|x| f(x as f64, y) as f32))
+        }
+        (DataValue::Vec(Vector::F64(a)), DataValue::Num(n)) => {
+            let y = n.get_float();
+            Vector::F64(a.mapv(|x| f(x, y)))
+        }
+        (DataValue::Vec(_), _) => {
+            bail!("'{name}' requires a vector of the same type or a number after the vector")
+        }
+        _ => bail!("first argument to '{name}' must be a vector"),
+    }))
+}
+
+define_op!(OP_VEC_MIN, 2, false);
+pub(crate) fn op_vec_min(args: &[DataValue]) -> Result<DataValue> {
+    vector_elementwise("vec_min", &args[0], &args[1], f64::min)
+}
+
+define_op!(OP_VEC_MAX, 2, false);
+pub(crate) fn op_vec_max(args: &[DataValue]) -> Result<DataValue> {
+    vector_elementwise("vec_max", &args[0], &args[1], f64::max)
+}
+
+define_op!(OP_VEC_CLAMP, 3, false);
+pub(crate) fn op_vec_clamp(args: &[DataValue]) -> Result<DataValue> {
+    let lower_bounded = vector_elementwise("vec_clamp", &args[0], &args[1], f64::max)?;
+    vector_elementwise("vec_clamp", &lower_bounded, &args[2], f64::min)
+}
+
+define_op!(OP_VEC_TO_BYTES, 1, false);
+pub(crate) fn op_vec_to_bytes(args: &[DataValue]) -> Result<DataValue> {
+    // little-endian, whatever the platform, so that the bytes can be stored and exchanged
+    Ok(DataValue::Bytes(match &args[0] {
+        DataValue::Vec(Vector::F32(a)) => a.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        DataValue::Vec(Vector::F64(a)) => a.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        _ => bail!("'vec_to_bytes' requires a vector"),
+    }))
+}
+
+define_op!(OP_VEC_FROM_BYTES, 1, true);
+pub(crate) fn op_vec_from_bytes(args: &[DataValue]) -> Result<DataValue> {
+    let bytes = args[0]
+        .get_bytes()
+        .ok_or_else(|| miette!("first argument to 'vec_from_bytes' must be bytes"))?;
+    let t = match args.get(1) {
+        Some(DataValue::Str(s)) => match s as &str {
+            "F32" | "Float" => VecElementType::F32,
+            "F64" | "Double" => VecElementType::F64,
+            _ => bail!("'vec_from_bytes' does not recognize type {}", s),
+        },
+        None => VecElementType::F32,
+        _ => bail!("'vec_from_bytes' requires a string as second argument"),
+    };
+    Ok(DataValue::Vec(match t {
+        VecElementType::F32 => {
+            ensure!(
+                bytes.len() % 4 == 0,
+                "'vec_from_bytes' requires a multiple of 4 bytes for F32"
+            );
+            Vector::F32(
+                bytes
+                    .chunks_exact(4)
+                    .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+                    .collect(),
+            )
+        }
+        VecElementType::F64 => {
+            ensure!(
+                bytes.len() % 8 == 0,
+                "'vec_from_bytes' requires a multiple of 8 bytes for F64"
+            );
+            Vector::F64(
+                bytes
+                    .chunks_exact(8)
+                    .map(|c| f64::from_le_bytes(c.try_into().unwrap()))
+                    .collect(),
+            )
+        }
+    }))
+}
+
+define_op!(OP_MAT, 1, true);
+pub(crate) fn op_mat(args: &[DataValue]) -> Result<DataValue> {
+    let t = match args.get(1) {
+        Some(DataValue::Str(s)) => match s as &str {
+            "F32" | "Float" => VecElementType::F32,
+            "F64" | "Double" => VecElementType::F64,
+            _ => bail!("'mat' does not recognize type {}", s),
+        },
+        None => VecElementType::F32,
+        _ => bail!("'mat' requires a string as second argument"),
+    };
+    if let DataValue::Mat(m) = &args[0] {
+        return Ok(DataValue::Mat(match (t, m) {
+            (VecElementType::F32, Matrix::F32(m)) => Matrix::F32(m.clone()),
+            (VecElementType::F64, Matrix::F64(m)) => Matrix::F64(m.clone()),
+            (VecElementType::F32, Matrix::F64(m)) => Matrix::F32(m.mapv(|x| x as f32)),
+            (VecElementType::F64, Matrix::F32(m)) => Matrix::F64(m.mapv(|x| x as f64)),
+        }));
+    }
+    let rows = match &args[0] {
+        DataValue::List(l) => l,
+        _ => bail!("'mat' requires a list of rows"),
+    };
+    let mut els = vec![];
+    let mut ncols = None;
+    for row in rows {
+        let start = els.len();
+        match row {
+            DataValue::List(r) => {
+                for el in r {
+                    els.push(
+                        el.get_float()
+                            .ok_or_else(|| miette!("'mat' requires rows of numbers"))?,
+                    );
+                }
             }
-            let diff = a - b;
-            Ok(DataValue::from(diff.dot(&diff)))
+            DataValue::Vec(Vector::F32(r)) => els.extend(r.iter().map(|x| *x as f64)),
+            DataValue::Vec(Vector::F64(r)) => els.extend(r.iter()),
+            _ => bail!("'mat' requires rows to be lists or vectors"),
+        }
+        let len = els.len() - start;
+        ensure!(
+            *ncols.get_or_insert(len) == len,
+            "'mat' requires all rows to have the same length"
+        );
+    }
+    let shape = (rows.len(), ncols.unwrap_or(0));
+    let m = ndarray::Array2::from_shape_vec(shape, els).unwrap();
+    Ok(DataValue::Mat(match t {
+        VecElementType::F32 => Matrix::F32(m.mapv(|x| x as f32)),
+        VecElementType::F64 => Matrix::F64(m),
+    }))
+}
+
+define_op!(OP_MAT_SHAPE, 1, false);
+pub(crate) fn op_mat_shape(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Mat(m) => {
+            let (rows, cols) = m.shape();
+            Ok(DataValue::List(vec![
+                DataValue::from(rows as i64),
+                DataValue::from(cols as i64),
+            ]))
+        }
+        _ => bail!("'mat_shape' requires a matrix"),
+    }
+}
+
+define_op!(OP_TRANSPOSE, 1, false);
+pub(crate) fn op_transpose(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Mat(Matrix::F32(m)) => Ok(DataValue::Mat(Matrix::F32(
+            m.t().as_standard_layout().into_owned(),
+        ))),
+        DataValue::Mat(Matrix::F64(m)) => Ok(DataValue::Mat(Matrix::F64(
+            m.t().as_standard_layout().into_owned(),
+        ))),
+        _ => bail!("'transpose' requires a matrix"),
+    }
+}
+
+define_op!(OP_MATMUL, 2, false);
+pub(crate) fn op_matmul(args: &[DataValue]) -> Result<DataValue> {
+    let inner_dims = match (&args[0], &args[1]) {
+        (DataValue::Mat(a), DataValue::Mat(b)) => (a.shape().1, b.shape().0),
+        (DataValue::Mat(a), DataValue::Vec(b)) => (a.shape().1, b.len()),
+        (DataValue::Vec(a), DataValue::Mat(b)) => (a.len(), b.shape().0),
+        _ => bail!("'matmul' requires two matrices, or a matrix and a vector"),
+    };
+    ensure!(
+        inner_dims.0 == inner_dims.1,
+        "'matmul' requires compatible dimensions, got {} and {}",
+        inner_dims.0,
+        inner_dims.1
+    );
+    Ok(match (&args[0], &args[1]) {
+        (DataValue::Mat(Matrix::F32(a)), DataValue::Mat(Matrix::F32(b))) => {
+            DataValue::Mat(Matrix::F32(a.dot(b)))
+        }
+        (DataValue::Mat(Matrix::F64(a)), DataValue::Mat(Matrix::F64(b))) => {
+            DataValue::Mat(Matrix::F64(a.dot(b)))
+        }
+        (DataValue::Mat(Matrix::F32(a)), DataValue::Vec(Vector::F32(b))) => {
+            DataValue::Vec(Vector::F32(a.dot(b)))
+        }
+        (DataValue::Mat(Matrix::F64(a)), DataValue::Vec(Vector::F64(b))) => {
+            DataValue::Vec(Vector::F64(a.dot(b)))
+        }
+        (DataValue::Vec(Vector::F32(a)), DataValue::Mat(Matrix::F32(b))) => {
+            DataValue::Vec(Vector::F32(a.dot(b)))
+        }
+        (DataValue::Vec(Vector::F64(a)), DataValue::Mat(Matrix::F64(b))) => {
+            DataValue::Vec(Vector::F64(a.dot(b)))
+        }
+        _ => bail!("'matmul' requires operands of the same element type"),
+    })
+}
+
+fn normalize_rows<T: num_traits::Float>(m: &ndarray::Array2<T>) -> ndarray::Array2<T> {
+    let mut res = m.clone();
+    for mut row in res.rows_mut() {
+        let norm = row.iter().fold(T::zero(), |acc, x| acc + *x * *x).sqrt();
+        if norm > T::zero() {
+            row.mapv_inplace(|x| x / norm);
         }
-        _ => bail!("'l2_dist' requires two vectors of the same type"),
     }
+    res
 }
 
-define_op!(OP_IP_DIST, 2, false);
-pub(crate) fn op_ip_dist(args: &[DataValue]) -> Result<DataValue> {
-    let a = &args[0];
-    let b = &args[1];
-    match (a, b) {
-        (DataValue::Vec(Vector::F32(a)), DataValue::Vec(Vector::F32(b))) => {
-            if a.len() != b.len() {
-                bail!("'ip_dist' requires two vectors of the same length");
+define_op!(OP_L2_NORMALIZE_ROWS, 1, false);
+pub(crate) fn op_l2_normalize_rows(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Mat(Matrix::F32(m)) => Ok(DataValue::Mat(Matrix::F32(normalize_rows(m)))),
+        DataValue::Mat(Matrix::F64(m)) => Ok(DataValue::Mat(Matrix::F64(normalize_rows(m)))),
+        _ => bail!("'l2_normalize_rows' requires a matrix"),
+    }
+}
+
+fn sparse_index(name: &str, idx: &DataValue) -> Result<u32> {
+    idx.get_int()
+        .and_then(|i| u32::try_from(i).ok())
+        .ok_or_else(|| miette!("'{}' requires indices to be non-negative integers", name))
+}
+
+define_op!(OP_SPARSE_VEC, 1, false);
+pub(crate) fn op_sparse_vec(args: &[DataValue]) -> Result<DataValue> {
+    let mut pairs = vec![];
+    match &args[0] {
+        DataValue::Sparse(s) => return Ok(DataValue::Sparse(s.clone())),
+        DataValue::Vec(Vector::F32(a)) => {
+            for (i, x) in a.iter().enumerate() {
+                pairs.push((i as u32, *x));
             }
-            let dot = a.dot(b);
-            Ok(DataValue::from(1. - dot as f64))
         }
-        (DataValue::Vec(Vector::F64(a)), DataValue::Vec(Vector::F64(b))) => {
-            if a.len() != b.len() {
-                bail!("'ip_dist' requires two vectors of the same length");
+        DataValue::Vec(Vector::F64(a)) => {
+            for (i, x) in a.iter().enumerate() {
+                pairs.push((i as u32, *x as f32));
+            }
+        }
+        DataValue::List(l) => {
+            for (i, el) in l.iter().enumerate() {
+                match el {
+                    DataValue::List(pair) if pair.len() == 2 => {
+                        let idx = sparse_index("sparse_vec", &pair[0])?;
+                        let val = pair[1]
+                            .get_float()
+                            .ok_or_else(|| miette!("'sparse_vec' requires numbers as values"))?;
+                        pairs.push((idx, val as f32));
+                    }
+                    el => {
+                        let val = el.get_float().ok_or_else(|| {
+                            miette!(
+                                "'sparse_vec' requires a list of numbers, or of index-value pairs"
+                            )
+                        })?;
+                        pairs.push((i as u32, val as f32));
+                    }
+                }
+            }
+        }
+        DataValue::Json(JsonData(Value::Object(o))) => {
+            for (k, v) in o {
+                let idx = u32::from_str(k)
+                    .map_err(|_| miette!("'sparse_vec' requires keys to be indices"))?;
+                let val = v
+                    .as_f64()
+                    .ok_or_else(|| miette!("'sparse_vec' requires numbers as values"))?;
+                pairs.push((idx, val as f32));
             }
-            let dot = a.dot(b);
-            Ok(DataValue::from(1. - dot))
         }
-        _ => bail!("'ip_dist' requires two vectors of the same type"),
+        _ => bail!("'sparse_vec' requires a list, a vector or a JSON object"),
     }
+    Ok(DataValue::Sparse(SparseVector::from_pairs(pairs)))
 }
 
-define_op!(OP_COS_DIST, 2, false);
-pub(crate) fn op_cos_dist(args: &[DataValue]) -> Result<DataValue> {
-    let a = &args[0];
-    let b = &args[1];
-    match (a, b) {
-        (DataValue::Vec(Vector::F32(a)), DataValue::Vec(Vector::F32(b))) => {
-            if a.len() != b.len() {
-                bail!("'cos_dist' requires two vectors of the same length");
+fn sparse_args<'a>(
+    name: &str,
+    args: &'a [DataValue],
+) -> Result<(&'a SparseVector, &'a SparseVector)> {
+    match (&args[0], &args[1]) {
+        (DataValue::Sparse(a), DataValue::Sparse(b)) => Ok((a, b)),
+        _ => bail!("'{}' requires two sparse vectors", name),
+    }
+}
+
+define_op!(OP_SPARSE_DOT, 2, false);
+pub(crate) fn op_sparse_dot(args: &[DataValue]) -> Result<DataValue> {
+    let (a, b) = sparse_args("sparse_dot", args)?;
+    Ok(DataValue::from(a.dot(b)))
+}
+
+define_op!(OP_SPARSE_COS_DIST, 2, false);
+pub(crate) fn op_sparse_cos_dist(args: &[DataValue]) -> Result<DataValue> {
+    let (a, b) = sparse_args("sparse_cos_dist", args)?;
+    let norms = a.norm() * b.norm();
+    // a zero vector is not similar to anything
+    if norms == 0. {
+        return Ok(DataValue::from(1.));
+    }
+    Ok(DataValue::from(1. - a.dot(b) / norms))
+}
+
+define_op!(OP_SPARSE_TO_DENSE, 2, true);
+pub(crate) fn op_sparse_to_dense(args: &[DataValue]) -> Result<DataValue> {
+    let s = match &args[0] {
+        DataValue::Sparse(s) => s,
+        _ => bail!("first argument to 'sparse_to_dense' must be a sparse vector"),
+    };
+    let dim = args[1]
+        .get_non_neg_int()
+        .ok_or_else(|| miette!("second argument to 'sparse_to_dense' must be the dimension"))?
+        as usize;
+    let t = match args.get(2) {
+        Some(DataValue::Str(s)) => match s as &str {
+            "F32" | "Float" => VecElementType::F32,
+            "F64" | "Double" => VecElementType::F64,
+            _ => bail!("'sparse_to_dense' does not recognize type {}", s),
+        },
+        None => VecElementType::F32,
+        _ => bail!("'sparse_to_dense' requires a string as third argument"),
+    };
+    if let Some(last) = s.indices.last() {
+        ensure!(
+            (*last as usize) < dim,
+            "'sparse_to_dense' got index {} beyond the dimension {}",
+            last,
+            dim
+        );
+    }
+    let mut res = ndarray::Array1::<f32>::zeros(dim);
+    for (idx, val) in s.iter() {
+        res[idx as usize] = val;
+    }
+    Ok(DataValue::Vec(match t {
+        VecElementType::F32 => Vector::F32(res),
+        VecElementType::F64 => Vector::F64(res.mapv(|x| x as f64)),
+    }))
+}
+
+/// Splits `chars` into chunks of at most `max_tokens` whitespace-separated tokens,
+/// consecutive chunks sharing up to `overlap` tokens. With `by_sentences`, chunks only
+/// break between sentences, unless a sentence is longer than `max_tokens` by itself.
+/// Returns the character offsets of the chunks, the end being exclusive.
+pub(crate) fn text_chunks(
+    chars: &[char],
+    max_tokens: usize,
+    overlap: usize,
+    by_sentences: bool,
+) -> Vec<(usize, usize)> {
+    let mut tokens = vec![];
+    let mut token_start = None;
+    for (i, c) in chars.iter().enumerate() {
+        match (c.is_whitespace(), token_start) {
+            (true, Some(start)) => {
+                tokens.push((start, i));
+                token_start = None;
             }
-            let a_norm = a.dot(a) as f64;
-            let b_norm = b.dot(b) as f64;
-            let dot = a.dot(b) as f64;
-            Ok(DataValue::from(1. - dot / (a_norm * b_norm).sqrt()))
+            (false, None) => token_start = Some(i),
+            _ => {}
         }
-        (DataValue::Vec(Vector::F64(a)), DataValue::Vec(Vector::F64(b))) => {
-            if a.len() != b.len() {
-                bail!("'cos_dist' requires two vectors of the same length");
+    }
+    if let Some(start) = token_start {
+        tokens.push((start, chars.len()));
+    }
+
+    // ranges of tokens that are never split, single tokens unless chunking by sentences
+    let mut units = vec![];
+    if by_sentences {
+        let mut sentence_start = 0;
+        for (i, (start, end)) in tokens.iter().enumerate() {
+            let ends_sentence = chars[*start..*end]
+                .iter()
+                .rev()
+                .find(|c| !matches!(c, '"' | '\'' | ')' | ']' | '»' | '”' | '’'))
+                .map(|c| matches!(c, '.' | '!' | '?' | '。' | '！' | '？'))
+                .unwrap_or(false);
+            if ends_sentence || i + 1 == tokens.len() {
+                let mut piece_start = sentence_start;
+                while piece_start <= i {
+                    let piece_end = (piece_start + max_tokens).min(i + 1);
+                    units.push((piece_start, piece_end));
+                    piece_start = piece_end;
+                }
+                sentence_start = i + 1;
+            }
+        }
+    } else {
+        units.extend((0..tokens.len()).map(|i| (i, i + 1)));
+    }
+
+    let unit_len = |i: usize| units[i].1 - units[i].0;
+    let mut ret = vec![];
+    let mut start = 0;
+    while start < units.len() {
+        let mut end = start;
+        let mut count = 0;
+        while end < units.len() && (end == start || count + unit_len(end) <= max_tokens) {
+            count += unit_len(end);
+            end += 1;
+        }
+        ret.push((tokens[units[start].0].0, tokens[units[end - 1].1 - 1].1));
+        if end == units.len() {
+            break;
+        }
+        let mut next = end;
+        let mut shared = 0;
+        while next > start + 1 && shared + unit_len(next - 1) <= overlap {
+            shared += unit_len(next - 1);
+            next -= 1;
+        }
+        start = next;
+    }
+    ret
+}
+
+define_op!(OP_CHUNK_TEXT, 3, true);
+pub(crate) fn op_chunk_text(args: &[DataValue]) -> Result<DataValue> {
+    let s = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("first argument to 'chunk_text' must be a string"))?;
+    let max_tokens = match args[1].get_int() {
+        Some(i) if i > 0 => i as usize,
+        _ => bail!("second argument to 'chunk_text' must be a positive integer"),
+    };
+    let overlap = args[2]
+        .get_non_neg_int()
+        .ok_or_else(|| miette!("third argument to 'chunk_text' must be a non-negative integer"))?
+        as usize;
+    ensure!(
+        overlap < max_tokens,
+        "the overlap given to 'chunk_text' must be less than the maximum number of tokens"
+    );
+    let by_sentences = match args.get(3) {
+        None => false,
+        Some(DataValue::Str(s)) => match s as &str {
+            "tokens" => false,
+            "sentences" => true,
+            _ => bail!(
+                "'chunk_text' can only split by 'tokens' or 'sentences', got {}",
+                s
+            ),
+        },
+        _ => bail!("'chunk_text' requires a string as fourth argument"),
+    };
+    let chars = s.chars().collect_vec();
+    Ok(DataValue::List(
+        text_chunks(&chars, max_tokens, overlap, by_sentences)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (start, end))| {
+                DataValue::List(vec![
+                    DataValue::from(i as i64),
+                    DataValue::from(start as i64),
+                    DataValue::from(end as i64),
+                    DataValue::Str(chars[start..end].iter().collect()),
+                ])
+            })
+            .collect_vec(),
+    ))
+}
+
+define_op!(OP_SOUNDEX, 1, false);
+pub(crate) fn op_soundex(args: &[DataValue]) -> Result<DataValue> {
+    let s = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("'soundex' requires strings"))?;
+    fn code(c: char) -> Option<char> {
+        Some(match c {
+            'B' | 'F' | 'P' | 'V' => '1',
+            'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => '2',
+            'D' | 'T' => '3',
+            'L' => '4',
+            'M' | 'N' => '5',
+            'R' => '6',
+            _ => return None,
+        })
+    }
+    let mut letters = s
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase());
+    let first = match letters.next() {
+        None => return Ok(DataValue::from("")),
+        Some(c) => c,
+    };
+    let mut ret = SmartString::new();
+    ret.push(first);
+    let mut last = code(first);
+    for c in letters {
+        if ret.len() == 4 {
+            break;
+        }
+        match c {
+            // H and W do not separate letters with the same code, vowels do
+            'H' | 'W' => {}
+            c => {
+                let cur = code(c);
+                if let Some(d) = cur {
+                    if cur != last {
+                        ret.push(d);
+                    }
+                }
+                last = cur;
+            }
+        }
+    }
+    while ret.len() < 4 {
+        ret.push('0');
+    }
+    Ok(DataValue::Str(ret))
+}
+
+define_op!(OP_METAPHONE, 1, false);
+pub(crate) fn op_metaphone(args: &[DataValue]) -> Result<DataValue> {
+    let s = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("'metaphone' requires strings"))?;
+    let mut w = s
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase())
+        .collect_vec();
+    match w.as_slice() {
+        ['A', 'E', ..] | ['G', 'N', ..] | ['K', 'N', ..] | ['P', 'N', ..] | ['W', 'R', ..] => {
+            w.remove(0);
+        }
+        ['X', ..] => w[0] = 'S',
+        ['W', 'H', ..] => {
+            w.remove(1);
+        }
+        _ => {}
+    }
+    let is_vowel = |c: Option<&char>| matches!(c, Some('A' | 'E' | 'I' | 'O' | 'U'));
+    let is_front = |c: Option<&char>| matches!(c, Some('E' | 'I' | 'Y'));
+    let mut ret = SmartString::new();
+    for (i, &c) in w.iter().enumerate() {
+        let prev = if i > 0 { w.get(i - 1) } else { None };
+        let next = w.get(i + 1);
+        let after_next = w.get(i + 2);
+        if prev == Some(&c) && c != 'C' {
+            continue;
+        }
+        match c {
+            'A' | 'E' | 'I' | 'O' | 'U' => {
+                if i == 0 {
+                    ret.push(c)
+                }
+            }
+            'B' => {
+                if !(prev == Some(&'M') && next.is_none()) {
+                    ret.push('B')
+                }
             }
-            let a_norm = a.dot(a);
-            let b_norm = b.dot(b);
-            let dot = a.dot(b);
-            Ok(DataValue::from(1. - dot / (a_norm * b_norm).sqrt()))
+            'C' => {
+                if next == Some(&'I') && after_next == Some(&'A') {
+                    ret.push('X')
+                } else if next == Some(&'H') {
+                    ret.push(if prev == Some(&'S') { 'K' } else { 'X' })
+                } else if is_front(next) {
+                    if prev != Some(&'S') {
+                        ret.push('S')
+                    }
+                } else {
+                    ret.push('K')
+                }
+            }
+            'D' => {
+                if next == Some(&'G') && is_front(after_next) {
+                    ret.push('J')
+                } else {
+                    ret.push('T')
+                }
+            }
+            'G' => {
+                let silent = (next == Some(&'H') && after_next.is_some() && !is_vowel(after_next))
+                    || (next == Some(&'N') && (after_next.is_none() || w[i + 2..] == ['E', 'D']));
+                if !silent {
+                    if is_front(next) && prev != Some(&'G') {
+                        ret.push('J')
+                    } else {
+                        ret.push('K')
+                    }
+                }
+            }
+            'H' => {
+                let after_vowel = is_vowel(prev) && !is_vowel(next);
+                let in_digraph = matches!(prev, Some('C' | 'S' | 'P' | 'T' | 'G'));
+                if !after_vowel && !in_digraph {
+                    ret.push('H')
+                }
+            }
+            'K' => {
+                if prev != Some(&'C') {
+                    ret.push('K')
+                }
+            }
+            'P' => ret.push(if next == Some(&'H') { 'F' } else { 'P' }),
+            'Q' => ret.push('K'),
+            'S' => {
+                if next == Some(&'H')
+                    || (next == Some(&'I') && matches!(after_next, Some('O' | 'A')))
+                {
+                    ret.push('X')
+                } else {
+                    ret.push('S')
+                }
+            }
+            'T' => {
+                if next == Some(&'I') && matches!(after_next, Some('O' | 'A')) {
+                    ret.push('X')
+                } else if next == Some(&'H') {
+                    ret.push('0')
+                } else if !(next == Some(&'C') && after_next == Some(&'H')) {
+                    ret.push('T')
+                }
+            }
+            'V' => ret.push('F'),
+            'W' | 'Y' => {
+                if is_vowel(next) {
+                    ret.push(c)
+                }
+            }
+            'X' => ret.push_str("KS"),
+            'Z' => ret.push('S'),
+            c => ret.push(c),
         }
-        _ => bail!("'cos_dist' requires two vectors of the same type"),
     }
+    Ok(DataValue::Str(ret))
+}
+
+/// The trigrams of the words of `s`, lowercased and padded as in PostgreSQL's `pg_trgm`
+pub(crate) fn trigrams(s: &str) -> BTreeSet<[char; 3]> {
+    let mut ret = BTreeSet::new();
+    for word in s.split(|c: char| !c.is_alphanumeric()) {
+        if word.is_empty() {
+            continue;
+        }
+        let padded = [' ', ' ']
+            .into_iter()
+            .chain(word.chars().flat_map(|c| c.to_lowercase()))
+            .chain([' '])
+            .collect_vec();
+        for w in padded.windows(3) {
+            ret.insert([w[0], w[1], w[2]]);
+        }
+    }
+    ret
+}
+
+/// The number of shared trigrams over the number of distinct trigrams of both sets
+pub(crate) fn trigram_jaccard(a: &BTreeSet<[char; 3]>, b: &BTreeSet<[char; 3]>) -> f64 {
+    let shared = a.intersection(b).count();
+    let all = a.len() + b.len() - shared;
+    if all == 0 {
+        0.
+    } else {
+        shared as f64 / all as f64
+    }
+}
+
+define_op!(OP_TRIGRAM_SIMILARITY, 2, false);
+pub(crate) fn op_trigram_similarity(args: &[DataValue]) -> Result<DataValue> {
+    let a = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("'trigram_similarity' requires strings"))?;
+    let b = args[1]
+        .get_str()
+        .ok_or_else(|| miette!("'trigram_similarity' requires strings"))?;
+    Ok(DataValue::from(trigram_jaccard(&trigrams(a), &trigrams(b))))
 }
 
 define_op!(OP_INT_RANGE, 1, true);
@@ -2517,6 +3593,60 @@ pub(crate) fn op_parse_timestamp(args: &[DataValue]) -> Result<DataValue> {
     ))
 }
 
+define_op!(OP_FORMAT_TIMESTAMP_FMT, 2, true);
+pub(crate) fn op_format_timestamp_fmt(args: &[DataValue]) -> Result<DataValue> {
+    let micros = timestamp_micros_arg(&args[0], "format_timestamp_fmt")?;
+    let fmt = args[1]
+        .get_str()
+        .ok_or_else(|| miette!("'format_timestamp_fmt' expects a format string"))?;
+    let tz = timezone_arg(args.get(2), "format_timestamp_fmt")?;
+    let items: Vec<_> = StrftimeItems::new(fmt).collect();
+    ensure!(
+        !items.contains(&Item::Error),
+        "bad format string for 'format_timestamp_fmt': {}",
+        fmt
+    );
+    let dt = micros_to_datetime(micros)
+        .ok_or_else(|| miette!("bad time: {}", &args[0]))?
+        .with_timezone(&tz);
+    let mut ret = String::new();
+    write!(ret, "{}", dt.format_with_items(items.iter()))
+        .map_err(|_| miette!("cannot format {} with '{}'", &args[0], fmt))?;
+    Ok(DataValue::from(ret))
+}
+
+define_op!(OP_PARSE_TIMESTAMP_FMT, 2, true);
+pub(crate) fn op_parse_timestamp_fmt(args: &[DataValue]) -> Result<DataValue> {
+    let s = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("'parse_timestamp_fmt' expects a string"))?;
+    let fmt = args[1]
+        .get_str()
+        .ok_or_else(|| miette!("'parse_timestamp_fmt' expects a format string"))?;
+    let tz = timezone_arg(args.get(2), "parse_timestamp_fmt")?;
+    let mut parsed = Parsed::new();
+    chrono::format::parse(&mut parsed, s, StrftimeItems::new(fmt))
+        .map_err(|e| miette!("cannot parse '{}' with format '{}': {}", s, fmt, e))?;
+    // with an offset in the string, the timezone is not needed
+    let micros = match parsed.to_datetime() {
+        Ok(dt) => dt.timestamp_micros(),
+        Err(_) => match parsed.to_datetime_with_timezone(&tz) {
+            Ok(dt) => dt.timestamp_micros(),
+            // times of day that are missing, ambiguous or skipped in the timezone
+            Err(_) => {
+                let date = parsed
+                    .to_naive_date()
+                    .map_err(|e| miette!("cannot parse '{}' with format '{}': {}", s, fmt, e))?;
+                let time = parsed.to_naive_time().unwrap_or(NaiveTime::MIN);
+                resolve_local_time(&tz, date.and_time(time))
+                    .ok_or_else(|| miette!("bad time: {}", s))?
+                    .timestamp_micros()
+            }
+        },
+    };
+    Ok(DataValue::from(micros as f64 / 1e6))
+}
+
 pub(crate) fn str2vld(s: &str) -> Result<ValidityTs> {
     let dt = DateTime::parse_from_rfc3339(s).map_err(|_| miette!("bad datetime: {}", s))?;
     let st: SystemTime = dt.into();
@@ -2524,6 +3654,443 @@ pub(crate) fn str2vld(s: &str) -> Result<ValidityTs> {
     Ok(ValidityTs(Reverse(microseconds as i64)))
 }
 
+pub(crate) fn micros_to_datetime(micros: i64) -> Option<DateTime<Utc>> {
+    let nanos = (micros.rem_euclid(1_000_000) * 1000) as u32;
+    Utc.timestamp_opt(micros.div_euclid(1_000_000), nanos)
+        .single()
+}
+
+/// The time at the given wall clock time. In a gap, such as when clocks are
+/// put forward, the time an hour later is taken, in an overlap the earliest one.
+pub(crate) fn resolve_local_time(tz: &Tz, local: NaiveDateTime) -> Option<DateTime<Tz>> {
+    tz.from_local_datetime(&local).earliest().or_else(|| {
+        tz.from_local_datetime(&(local + Duration::hours(1)))
+            .earliest()
+    })
+}
+
+/// Parse a day of the week written as in iCalendar, such as `MO` or `SU`
+pub(crate) fn parse_weekday(s: &str) -> Option<Weekday> {
+    Some(match s.trim().to_ascii_uppercase().as_str() {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn timestamp_micros_arg(v: &DataValue, op: &str) -> Result<i64> {
+    match v {
+        DataValue::Validity(vld) => Ok(vld.timestamp.0 .0),
+        v => v
+            .get_float()
+            .map(|f| (f * 1e6).round() as i64)
+            .ok_or_else(|| miette!("'{}' expects a timestamp", op)),
+    }
+}
+
+/// Weekends, holidays and working hours, given as a JSON object such as
+/// `{"tz": "Europe/Paris", "weekend": ["SA", "SU"], "holidays": ["2024-12-25"],
+/// "hours": [["09:00", "12:00"], ["13:00", "17:00"]]}`.
+/// Every field can be left out: by default the calendar is in UTC,
+/// with Saturdays and Sundays off and whole working days.
+struct BusinessCalendar {
+    tz: Tz,
+    /// days off, counted from Monday
+    weekend: BTreeSet<u32>,
+    holidays: BTreeSet<NaiveDate>,
+    /// working hours as seconds from midnight, sorted
+    hours: Vec<(i64, i64)>,
+}
+
+impl BusinessCalendar {
+    fn parse(v: &DataValue, op: &str) -> Result<Self> {
+        let json = to_json(v);
+        let obj = json
+            .as_object()
+            .ok_or_else(|| miette!("'{}' expects a calendar given as a JSON object", op))?;
+        let strings = |field: &str| -> Result<Option<Vec<String>>> {
+            match obj.get(field) {
+                None | Some(JsonValue::Null) => Ok(None),
+                Some(JsonValue::Array(items)) => items
+                    .iter()
+                    .map(|item| {
+                        item.as_str().map(|s| s.to_string()).ok_or_else(|| {
+                            miette!(
+                                "'{}' expects the calendar field '{}' to hold strings",
+                                op,
+                                field
+                            )
+                        })
+                    })
+                    .collect::<Result<_>>()
+                    .map(Some),
+                Some(_) => bail!(
+                    "'{}' expects the calendar field '{}' to be a list",
+                    op,
+                    field
+                ),
+            }
+        };
+
+        let tz = match obj.get("tz") {
+            None | Some(JsonValue::Null) => Tz::UTC,
+            Some(JsonValue::String(s)) => {
+                Tz::from_str(s).map_err(|_| miette!("bad timezone specification: {}", s))?
+            }
+            Some(v) => bail!("bad timezone specification: {}", v),
+        };
+        let weekend = match strings("weekend")? {
+            None => vec![Weekday::Sat, Weekday::Sun],
+            Some(days) => days
+                .into_iter()
+                .map(|d| parse_weekday(&d).ok_or_else(|| miette!("bad day of the week: {}", d)))
+                .collect::<Result<_>>()?,
+        };
+        let weekend: BTreeSet<_> = weekend.iter().map(|d| d.num_days_from_monday()).collect();
+        ensure!(
+            weekend.len() < 7,
+            "'{}' expects a calendar with at least one working day in the week",
+            op
+        );
+        let holidays = strings("holidays")?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|d| {
+                NaiveDate::parse_from_str(&d, "%Y-%m-%d").map_err(|_| miette!("bad date: {}", d))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut hours = vec![];
+        match obj.get("hours") {
+            None | Some(JsonValue::Null) => hours.push((0, 86400)),
+            Some(JsonValue::Array(windows)) => {
+                for window in windows {
+                    let bounds = window
+                        .as_array()
+                        .filter(|b| b.len() == 2)
+                        .and_then(|b| Some((b[0].as_str()?, b[1].as_str()?)))
+                        .ok_or_else(|| miette!("bad working hours: {}", window))?;
+                    let start = parse_time_of_day(bounds.0)?;
+                    let end = parse_time_of_day(bounds.1)?;
+                    ensure!(
+                        start < end,
+                        "working hours end before they start: {}",
+                        window
+                    );
+                    hours.push((start, end));
+                }
+            }
+            Some(v) => bail!("bad working hours: {}", v),
+        }
+        hours.sort_unstable();
+        ensure!(
+            hours.windows(2).all(|w| w[0].1 <= w[1].0),
+            "'{}' expects working hours that do not overlap",
+            op
+        );
+
+        Ok(Self {
+            tz,
+            weekend,
+            holidays,
+            hours,
+        })
+    }
+    fn is_business_day(&self, date: NaiveDate) -> bool {
+        !self
+            .weekend
+            .contains(&date.weekday().num_days_from_monday())
+            && !self.holidays.contains(&date)
+    }
+}
+
+/// Seconds from midnight of a time written like `09:30` or `09:30:15`, up to `24:00`
+fn parse_time_of_day(s: &str) -> Result<i64> {
+    let parts: Vec<_> = s.split(':').map(|p| p.trim().parse::<i64>().ok()).collect();
+    let secs = match parts[..] {
+        [Some(h), Some(m)] if m < 60 => h * 3600 + m * 60,
+        [Some(h), Some(m), Some(s)] if m < 60 && s < 60 => h * 3600 + m * 60 + s,
+        _ => bail!("bad time of day: {}", s),
+    };
+    ensure!((0..=86400).contains(&secs), "bad time of day: {}", s);
+    Ok(secs)
+}
+
+define_op!(OP_ADD_BUSINESS_DAYS, 3, false);
+pub(crate) fn op_add_business_days(args: &[DataValue]) -> Result<DataValue> {
+    let micros = timestamp_micros_arg(&args[0], "add_business_days")?;
+    let n = args[1]
+        .get_int()
+        .ok_or_else(|| miette!("'add_business_days' expects an integer number of days"))?;
+    let calendar = BusinessCalendar::parse(&args[2], "add_business_days")?;
+    if n == 0 {
+        return Ok(DataValue::from(micros as f64 / 1e6));
+    }
+    let local = micros_to_datetime(micros)
+        .ok_or_else(|| miette!("bad time: {}", &args[0]))?
+        .with_timezone(&calendar.tz)
+        .naive_local();
+    let step = Duration::days(n.signum());
+    let mut date = local.date();
+    for _ in 0..n.unsigned_abs() {
+        date += step;
+        while !calendar.is_business_day(date) {
+            date += step;
+        }
+    }
+    let dt = resolve_local_time(&calendar.tz, date.and_time(local.time()))
+        .ok_or_else(|| miette!("bad time: {}", date))?;
+    Ok(DataValue::from(dt.timestamp_micros() as f64 / 1e6))
+}
+
+define_op!(OP_BUSINESS_MINUTES_BETWEEN, 3, false);
+pub(crate) fn op_business_minutes_between(args: &[DataValue]) -> Result<DataValue> {
+    let a = timestamp_micros_arg(&args[0], "business_minutes_between")?;
+    let b = timestamp_micros_arg(&args[1], "business_minutes_between")?;
+    let calendar = BusinessCalendar::parse(&args[2], "business_minutes_between")?;
+    let (lo, hi, sign) = if a <= b { (a, b, 1.) } else { (b, a, -1.) };
+    let local_date = |micros: i64| -> Result<NaiveDate> {
+        Ok(micros_to_datetime(micros)
+            .ok_or_else(|| miette!("bad time: {}", micros as f64 / 1e6))?
+            .with_timezone(&calendar.tz)
+            .date_naive())
+    };
+    let last = local_date(hi)?;
+    let mut date = local_date(lo)?;
+    let mut total = 0;
+    while date <= last {
+        if calendar.is_business_day(date) {
+            let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+            for (start, end) in &calendar.hours {
+                let at = |secs: i64| {
+                    resolve_local_time(&calendar.tz, midnight + Duration::seconds(secs))
+                        .map(|dt| dt.timestamp_micros())
+                        .ok_or_else(|| miette!("bad time: {}", midnight))
+                };
+                let (start, end) = (at(*start)?.max(lo), at(*end)?.min(hi));
+                total += (end - start).max(0);
+            }
+        }
+        date += Duration::days(1);
+    }
+    Ok(DataValue::from(sign * total as f64 / 60e6))
+}
+
+fn timezone_arg(arg: Option<&DataValue>, op: &str) -> Result<Tz> {
+    Ok(match arg {
+        None | Some(DataValue::Null) => Tz::UTC,
+        Some(v) => {
+            let s = v
+                .get_str()
+                .ok_or_else(|| miette!("'{}' timezone specification requires a string", op))?;
+            Tz::from_str(s).map_err(|_| miette!("bad timezone specification: {}", s))?
+        }
+    })
+}
+
+/// The timestamp in the first argument, in the timezone in the second one, UTC if not given
+fn zoned_timestamp_arg(args: &[DataValue], op: &str) -> Result<DateTime<Tz>> {
+    let micros = timestamp_micros_arg(&args[0], op)?;
+    let tz = timezone_arg(args.get(1), op)?;
+    Ok(micros_to_datetime(micros)
+        .ok_or_else(|| miette!("bad time: {}", &args[0]))?
+        .with_timezone(&tz))
+}
+
+fn start_of_day(date: NaiveDate, tz: &Tz) -> Result<DataValue> {
+    let dt = resolve_local_time(tz, date.and_hms_opt(0, 0, 0).unwrap())
+        .ok_or_else(|| miette!("bad time: {}", date))?;
+    Ok(DataValue::from(dt.timestamp_micros() as f64 / 1e6))
+}
+
+define_op!(OP_ISO_WEEK, 1, true);
+pub(crate) fn op_iso_week(args: &[DataValue]) -> Result<DataValue> {
+    let dt = zoned_timestamp_arg(args, "iso_week")?;
+    Ok(DataValue::from(dt.iso_week().week() as i64))
+}
+
+define_op!(OP_ISO_YEAR, 1, true);
+pub(crate) fn op_iso_year(args: &[DataValue]) -> Result<DataValue> {
+    let dt = zoned_timestamp_arg(args, "iso_year")?;
+    Ok(DataValue::from(dt.iso_week().year() as i64))
+}
+
+define_op!(OP_QUARTER, 1, true);
+pub(crate) fn op_quarter(args: &[DataValue]) -> Result<DataValue> {
+    let dt = zoned_timestamp_arg(args, "quarter")?;
+    Ok(DataValue::from((dt.month0() / 3 + 1) as i64))
+}
+
+define_op!(OP_START_OF_WEEK, 1, true);
+pub(crate) fn op_start_of_week(args: &[DataValue]) -> Result<DataValue> {
+    let dt = zoned_timestamp_arg(args, "start_of_week")?;
+    let week_start = match args.get(2) {
+        None | Some(DataValue::Null) => Weekday::Mon,
+        Some(v) => v
+            .get_str()
+            .and_then(parse_weekday)
+            .ok_or_else(|| miette!("'start_of_week' expects a day such as 'MO' or 'SU'"))?,
+    };
+    let date = dt.date_naive();
+    let days_back =
+        (date.weekday().num_days_from_monday() + 7 - week_start.num_days_from_monday()) % 7;
+    start_of_day(date - Duration::days(days_back as i64), &dt.timezone())
+}
+
+define_op!(OP_START_OF_MONTH, 1, true);
+pub(crate) fn op_start_of_month(args: &[DataValue]) -> Result<DataValue> {
+    let dt = zoned_timestamp_arg(args, "start_of_month")?;
+    start_of_day(trunc_date(dt.date_naive(), DateUnit::Month), &dt.timezone())
+}
+
+define_op!(OP_START_OF_QUARTER, 1, true);
+pub(crate) fn op_start_of_quarter(args: &[DataValue]) -> Result<DataValue> {
+    let dt = zoned_timestamp_arg(args, "start_of_quarter")?;
+    start_of_day(
+        trunc_date(dt.date_naive(), DateUnit::Quarter),
+        &dt.timezone(),
+    )
+}
+
+define_op!(OP_START_OF_YEAR, 1, true);
+pub(crate) fn op_start_of_year(args: &[DataValue]) -> Result<DataValue> {
+    let dt = zoned_timestamp_arg(args, "start_of_year")?;
+    start_of_day(trunc_date(dt.date_naive(), DateUnit::Year), &dt.timezone())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DateUnit {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl DateUnit {
+    fn parse(v: &DataValue, op: &str) -> Result<Self> {
+        let s = v
+            .get_str()
+            .ok_or_else(|| miette!("'{}' expects a unit given as a string", op))?;
+        Ok(match s.trim().to_ascii_lowercase().trim_end_matches('s') {
+            "second" => DateUnit::Second,
+            "minute" => DateUnit::Minute,
+            "hour" => DateUnit::Hour,
+            "day" => DateUnit::Day,
+            "week" => DateUnit::Week,
+            "month" => DateUnit::Month,
+            "quarter" => DateUnit::Quarter,
+            "year" => DateUnit::Year,
+            _ => bail!(
+                "'{}' expects a unit from 'second' to 'year', got '{}'",
+                op,
+                s
+            ),
+        })
+    }
+    /// The length in microseconds of the units shorter than a day,
+    /// which are counted in elapsed time rather than on the calendar
+    fn fixed_micros(self) -> Option<i64> {
+        match self {
+            DateUnit::Second => Some(1_000_000),
+            DateUnit::Minute => Some(60_000_000),
+            DateUnit::Hour => Some(3_600_000_000),
+            _ => None,
+        }
+    }
+}
+
+/// The first day of the period holding the date, for units of a day or longer.
+/// Weeks start on Mondays.
+fn trunc_date(date: NaiveDate, unit: DateUnit) -> NaiveDate {
+    match unit {
+        DateUnit::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        DateUnit::Month => date.with_day(1).unwrap(),
+        DateUnit::Quarter => {
+            NaiveDate::from_ymd_opt(date.year(), date.month0() / 3 * 3 + 1, 1).unwrap()
+        }
+        DateUnit::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+        _ => date,
+    }
+}
+
+define_op!(OP_DATE_TRUNC, 2, true);
+pub(crate) fn op_date_trunc(args: &[DataValue]) -> Result<DataValue> {
+    let unit = DateUnit::parse(&args[0], "date_trunc")?;
+    let dt = zoned_timestamp_arg(&args[1..], "date_trunc")?;
+    let local = dt.naive_local();
+    let truncated = match unit {
+        DateUnit::Second => local
+            .date()
+            .and_hms_opt(local.hour(), local.minute(), local.second()),
+        DateUnit::Minute => local.date().and_hms_opt(local.hour(), local.minute(), 0),
+        DateUnit::Hour => local.date().and_hms_opt(local.hour(), 0, 0),
+        _ => return start_of_day(trunc_date(local.date(), unit), &dt.timezone()),
+    }
+    .unwrap();
+    // within the hour the offset stays the same, even when the wall clock is ambiguous
+    let micros = dt.timestamp_micros() - (local - truncated).num_microseconds().unwrap();
+    Ok(DataValue::from(micros as f64 / 1e6))
+}
+
+/// Count the whole units between two times, given the units between them in the calendar
+/// and what is left over at each end, such as the time of the day for days
+fn whole_units<T: PartialOrd>(n: i64, rest_a: T, rest_b: T) -> i64 {
+    if n > 0 && rest_b < rest_a {
+        n - 1
+    } else if n < 0 && rest_b > rest_a {
+        n + 1
+    } else {
+        n
+    }
+}
+
+define_op!(OP_DATE_DIFF, 3, true);
+pub(crate) fn op_date_diff(args: &[DataValue]) -> Result<DataValue> {
+    let unit = DateUnit::parse(&args[0], "date_diff")?;
+    let a = timestamp_micros_arg(&args[1], "date_diff")?;
+    let b = timestamp_micros_arg(&args[2], "date_diff")?;
+    if let Some(len) = unit.fixed_micros() {
+        return Ok(DataValue::from((b - a) / len));
+    }
+    let tz = timezone_arg(args.get(3), "date_diff")?;
+    let local = |micros: i64| -> Result<NaiveDateTime> {
+        Ok(micros_to_datetime(micros)
+            .ok_or_else(|| miette!("bad time: {}", micros as f64 / 1e6))?
+            .with_timezone(&tz)
+            .naive_local())
+    };
+    let (a, b) = (local(a)?, local(b)?);
+    let n = match unit {
+        DateUnit::Day | DateUnit::Week => {
+            let days = whole_units((b.date() - a.date()).num_days(), a.time(), b.time());
+            if unit == DateUnit::Week {
+                days / 7
+            } else {
+                days
+            }
+        }
+        _ => {
+            let months = (b.year() - a.year()) as i64 * 12 + b.month() as i64 - a.month() as i64;
+            let months = whole_units(months, (a.day(), a.time()), (b.day(), b.time()));
+            match unit {
+                DateUnit::Quarter => months / 3,
+                DateUnit::Year => months / 12,
+                _ => months,
+            }
+        }
+    };
+    Ok(DataValue::from(n))
+}
+
 define_op!(OP_RAND_UUID_V1, 0, false);
 pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
     let mut rng = rand::thread_rng();
@@ -11,7 +11,7 @@ use base64::Engine;
 use serde_json::json;
 pub(crate) use serde_json::Value as JsonValue;
 
-use crate::data::value::{DataValue, Num, Vector};
+use crate::data::value::{DataValue, Matrix, Num, Vector};
 use crate::JsonData;
 
 impl From<JsonValue> for DataValue {
@@ -92,6 +92,15 @@ impl From<DataValue> for JsonValue {
                 Vector::F32(a) => json!(a.as_slice().unwrap()),
                 Vector::F64(a) => json!(a.as_slice().unwrap()),
             },
+            DataValue::Mat(m) => match m {
+                Matrix::F32(a) => json!(a.outer_iter().map(|r| r.to_vec()).collect::<Vec<_>>()),
+                Matrix::F64(a) => json!(a.outer_iter().map(|r| r.to_vec()).collect::<Vec<_>>()),
+            },
+            DataValue::Sparse(s) => JsonValue::Object(
+                s.iter()
+                    .map(|(idx, val)| (idx.to_string(), json!(val)))
+                    .collect(),
+            ),
             DataValue::Validity(v) => {
                 json!([v.timestamp.0, v.is_assert])
             }
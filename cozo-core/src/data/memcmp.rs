@@ -15,7 +15,8 @@ use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use regex::Regex;
 
 use crate::data::value::{
-    DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector,
+    DataValue, JsonData, Matrix, Num, RegexWrapper, SparseVector, UuidWrapper, Validity,
+    ValidityTs, Vector,
 };
 
 const INIT_TAG: u8 = 0x00;
@@ -32,6 +33,8 @@ const LIST_TAG: u8 = 0x0A;
 const SET_TAG: u8 = 0x0B;
 const VLD_TAG: u8 = 0x0C;
 const JSON_TAG: u8 = 0x0D;
+const MAT_TAG: u8 = 0x0E;
+const SPARSE_TAG: u8 = 0x0F;
 const BOT_TAG: u8 = 0xFF;
 
 const VEC_F32: u8 = 0x01;
@@ -121,6 +124,36 @@ pub(crate) trait MemCmpEncoder: Write {
                 self.write_u64::<BigEndian>(ts_flipped).unwrap();
                 self.write_u8(!vld.is_assert.0 as u8).unwrap();
             }
+            DataValue::Mat(m) => {
+                self.write_u8(MAT_TAG).unwrap();
+                let (rows, cols) = m.shape();
+                match m {
+                    Matrix::F32(a) => {
+                        self.write_u8(VEC_F32).unwrap();
+                        self.write_u64::<BigEndian>(rows as u64).unwrap();
+                        self.write_u64::<BigEndian>(cols as u64).unwrap();
+                        for el in a {
+                            self.write_f32::<BigEndian>(*el).unwrap();
+                        }
+                    }
+                    Matrix::F64(a) => {
+                        self.write_u8(VEC_F64).unwrap();
+                        self.write_u64::<BigEndian>(rows as u64).unwrap();
+                        self.write_u64::<BigEndian>(cols as u64).unwrap();
+                        for el in a {
+                            self.write_f64::<BigEndian>(*el).unwrap();
+                        }
+                    }
+                }
+            }
+            DataValue::Sparse(s) => {
+                self.write_u8(SPARSE_TAG).unwrap();
+                self.write_u64::<BigEndian>(s.nnz() as u64).unwrap();
+                for (idx, val) in s.iter() {
+                    self.write_u32::<BigEndian>(idx).unwrap();
+                    self.write_f32::<BigEndian>(val).unwrap();
+                }
+            }
             DataValue::Bot => self.write_u8(BOT_TAG).unwrap(),
         }
     }
@@ -363,6 +396,47 @@ impl DataValue {
                     _ => unreachable!(),
                 }
             }
+            MAT_TAG => {
+                let (t_tag, remaining) = remaining.split_first().unwrap();
+                let (rows_bytes, remaining) = remaining.split_at(8);
+                let (cols_bytes, mut rest) = remaining.split_at(8);
+                let rows = BigEndian::read_u64(rows_bytes) as usize;
+                let cols = BigEndian::read_u64(cols_bytes) as usize;
+                match *t_tag {
+                    VEC_F32 => {
+                        let mut res_arr = ndarray::Array2::zeros((rows, cols));
+                        for el in res_arr.iter_mut() {
+                            let (f_bytes, next_chunk) = rest.split_at(4);
+                            rest = next_chunk;
+                            *el = BigEndian::read_f32(f_bytes);
+                        }
+                        (DataValue::Mat(Matrix::F32(res_arr)), rest)
+                    }
+                    VEC_F64 => {
+                        let mut res_arr = ndarray::Array2::zeros((rows, cols));
+                        for el in res_arr.iter_mut() {
+                            let (f_bytes, next_chunk) = rest.split_at(8);
+                            rest = next_chunk;
+                            *el = BigEndian::read_f64(f_bytes);
+                        }
+                        (DataValue::Mat(Matrix::F64(res_arr)), rest)
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            SPARSE_TAG => {
+                let (len_bytes, mut rest) = remaining.split_at(8);
+                let len = BigEndian::read_u64(len_bytes) as usize;
+                let mut res = SparseVector::default();
+                for _ in 0..len {
+                    let (idx_bytes, next_chunk) = rest.split_at(4);
+                    let (f_bytes, next_chunk) = next_chunk.split_at(4);
+                    rest = next_chunk;
+                    res.indices.push(BigEndian::read_u32(idx_bytes));
+                    res.values.push(BigEndian::read_f32(f_bytes));
+                }
+                (DataValue::Sparse(res), rest)
+            }
             _ => unreachable!("{:?}", bs),
         }
     }
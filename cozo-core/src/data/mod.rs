@@ -7,6 +7,8 @@
  */
 
 pub(crate) mod aggr;
+pub(crate) mod batch;
+pub(crate) mod distance;
 pub(crate) mod expr;
 pub(crate) mod functions;
 pub(crate) mod json;
@@ -9,6 +9,7 @@
 use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
+use std::mem;
 use std::sync::Arc;
 
 use miette::{bail, ensure, miette, Diagnostic, Result};
@@ -21,16 +22,18 @@ use crate::data::expr::Expr;
 use crate::data::relation::StoredRelationMetadata;
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::value::{DataValue, ValidityTs};
-use crate::fixed_rule::{FixedRule, FixedRuleHandle};
+use crate::fixed_rule::{FixedRule, FixedRuleHandle, NONDETERMINISTIC_FIXED_RULES};
 use crate::fts::FtsIndexManifest;
 use crate::parse::SourceSpan;
 use crate::query::compile::ContainedRuleMultiplicity;
 use crate::query::logical::{Disjunction, NamedFieldNotFound};
+use crate::runtime::fixed_rule_cache::{FixedRuleCacheSpec, CACHE_OPTION};
 use crate::runtime::hnsw::HnswIndexManifest;
 use crate::runtime::minhash_lsh::{LshSearch, MinHashLshIndexManifest};
 use crate::runtime::relation::{
     AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
 };
+use crate::runtime::rerank::RerankStage;
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
 
@@ -46,6 +49,16 @@ pub(crate) enum ReturnMutation {
     Returning,
 }
 
+/// Set by `:max_iterations`: how many times recursive rules may be iterated
+/// without reaching their fixpoint
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct IterationLimit {
+    pub(crate) max: usize,
+    /// Set by `:on_max_iterations 'partial'`: return what has been derived so far
+    /// instead of failing
+    pub(crate) partial: bool,
+}
+
 #[derive(Clone, PartialEq, Default)]
 pub(crate) struct QueryOutOptions {
     pub(crate) limit: Option<usize>,
@@ -53,8 +66,14 @@ pub(crate) struct QueryOutOptions {
     pub(crate) timeout: Option<f64>,
     pub(crate) sleep: Option<f64>,
     pub(crate) sorters: Vec<(Symbol, SortDir)>,
+    /// Set by `:fixed_now`, the time taken as the current one by the query, except for
+    /// transaction time, see [crate::Db::set_fixed_now]
+    pub(crate) fixed_now: Option<ValidityTs>,
     pub(crate) store_relation: Option<(InputRelationHandle, RelationOp, ReturnMutation)>,
     pub(crate) assertion: Option<QueryAssertion>,
+    pub(crate) max_iterations: Option<IterationLimit>,
+    /// Set by `:deterministic`, overriding [crate::Db::set_deterministic] for the query
+    pub(crate) deterministic: Option<bool>,
 }
 
 impl Debug for QueryOutOptions {
@@ -74,6 +93,18 @@ impl Display for QueryOutOptions {
         if let Some(l) = self.timeout {
             writeln!(f, ":timeout {l};")?;
         }
+        if let Some(l) = self.fixed_now {
+            writeln!(f, ":fixed_now {};", l.0 .0 as f64 / 1e6)?;
+        }
+        if let Some(d) = self.deterministic {
+            writeln!(f, ":deterministic {d};")?;
+        }
+        if let Some(l) = self.max_iterations {
+            writeln!(f, ":max_iterations {};", l.max)?;
+            if l.partial {
+                writeln!(f, ":on_max_iterations 'partial';")?;
+            }
+        }
         for (symb, dir) in &self.sorters {
             write!(f, ":order ")?;
             if *dir == SortDir::Dsc {
@@ -87,6 +118,7 @@ impl Display for QueryOutOptions {
                             metadata: StoredRelationMetadata { keys, non_keys },
                             key_bindings,
                             dep_bindings,
+                            expected_version,
                             ..
                         },
                         op,
@@ -155,7 +187,11 @@ impl Display for QueryOutOptions {
                     write!(f, " = {bind}")?;
                 }
             }
-            writeln!(f, "}};")?;
+            write!(f, "}}")?;
+            if let Some((col, expr)) = expected_version {
+                write!(f, " expecting {col} = {expr}")?;
+            }
+            writeln!(f, ";")?;
         }
 
         if let Some(a) = &self.assertion {
@@ -257,6 +293,8 @@ pub(crate) struct FixedRuleApply {
     pub(crate) arity: usize,
     pub(crate) span: SourceSpan,
     pub(crate) fixed_impl: Arc<Box<dyn FixedRule>>,
+    /// Set if the output is to be cached in the database
+    pub(crate) cache: Option<FixedRuleCacheSpec>,
 }
 
 impl FixedRuleApply {
@@ -279,11 +317,13 @@ impl Debug for FixedRuleApply {
 
 pub(crate) struct MagicFixedRuleApply {
     pub(crate) fixed_handle: FixedRuleHandle,
+    pub(crate) head: Vec<Symbol>,
     pub(crate) rule_args: Vec<MagicFixedRuleRuleArg>,
     pub(crate) options: Arc<BTreeMap<SmartString<LazyCompact>, Expr>>,
     pub(crate) span: SourceSpan,
     pub(crate) arity: usize,
     pub(crate) fixed_impl: Arc<Box<dyn FixedRule>>,
+    pub(crate) cache: Option<FixedRuleCacheSpec>,
 }
 
 #[derive(Error, Diagnostic, Debug)]
@@ -465,6 +505,8 @@ pub(crate) struct InputProgram {
     pub(crate) prog: BTreeMap<Symbol, InputInlineRulesOrFixed>,
     pub(crate) out_opts: QueryOutOptions,
     pub(crate) disable_magic_rewrite: bool,
+    /// Also apply the magic set rewrite to rules with aggregations
+    pub(crate) force_magic_rewrite: bool,
 }
 
 impl Display for InputProgram {
@@ -509,6 +551,7 @@ impl Display for InputProgram {
                         rule_args,
                         options,
                         head,
+                        cache,
                         ..
                     },
                 } => {
@@ -533,6 +576,12 @@ impl Display for InputProgram {
                         }
                         write!(f, "{k}: {v}")?;
                     }
+                    if cache.is_some() {
+                        if !first {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{CACHE_OPTION}: true")?;
+                    }
                     writeln!(f, ");")?;
                 }
             }
@@ -554,6 +603,24 @@ struct EntryHeadNotExplicitlyDefinedError(#[label] SourceSpan);
 #[diagnostic(help("You need to have one rule named '?'"))]
 pub(crate) struct NoEntryError;
 
+#[derive(Debug, Diagnostic, Error)]
+#[error("{0} is not allowed in a read-only script")]
+#[diagnostic(code(eval::sandbox_violation))]
+#[diagnostic(help(
+    "Read-only scripts cannot mutate stored relations, run system ops or use nondeterministic functions"
+))]
+pub(crate) struct SandboxViolation(pub(crate) String, #[label] pub(crate) SourceSpan);
+
+impl SandboxViolation {
+    fn function(op_name: &str, span: SourceSpan) -> Self {
+        let name = op_name
+            .strip_prefix("OP_")
+            .unwrap_or(op_name)
+            .to_lowercase();
+        Self(format!("The nondeterministic function '{name}'"), span)
+    }
+}
+
 impl InputProgram {
     pub(crate) fn needs_write_lock(&self) -> Option<SmartString<LazyCompact>> {
         if let Some((h, _, _)) = &self.out_opts.store_relation {
@@ -567,6 +634,94 @@ impl InputProgram {
         }
     }
 
+    /// Replace the calls to `now()` in the rules and in the options of fixed rules
+    /// by the given time
+    pub(crate) fn fix_now(&mut self, now: ValidityTs) {
+        let now = now.0 .0 as f64 / 1e6;
+        for rules_or_fixed in self.prog.values_mut() {
+            match rules_or_fixed {
+                InputInlineRulesOrFixed::Rules { rules } => {
+                    for rule in rules {
+                        rule.body.iter_mut().for_each(|a| a.fix_now(now));
+                    }
+                }
+                InputInlineRulesOrFixed::Fixed { fixed } => {
+                    Arc::make_mut(&mut fixed.options)
+                        .values_mut()
+                        .for_each(|e| e.fix_now(now));
+                }
+            }
+        }
+    }
+
+    /// Call `f` on every constant in the bodies of the rules, with its span.
+    /// The options of fixed rules are left alone, as they may be used when parsing.
+    pub(crate) fn for_each_rule_const(&mut self, f: &mut impl FnMut(&mut DataValue, SourceSpan)) {
+        for rules_or_fixed in self.prog.values_mut() {
+            if let InputInlineRulesOrFixed::Rules { rules } = rules_or_fixed {
+                for rule in rules {
+                    rule.body.iter_mut().for_each(|a| a.for_each_const(f));
+                }
+            }
+        }
+    }
+
+    /// Reject everything that could mutate the database or make the result depend on
+    /// more than the stored data. Storing into temp relations is allowed.
+    pub(crate) fn check_sandboxed(&self) -> Result<()> {
+        if let Some((h, _, _)) = &self.out_opts.store_relation {
+            if !h.name.is_temp_store_name() {
+                bail!(SandboxViolation(
+                    format!("Mutating the stored relation '{}'", h.name),
+                    h.span
+                ))
+            }
+        }
+        for rules_or_fixed in self.prog.values() {
+            match rules_or_fixed {
+                InputInlineRulesOrFixed::Rules { rules } => {
+                    for rule in rules {
+                        if let Some((name, span)) =
+                            rule.body.iter().find_map(|a| a.nondeterministic_call())
+                        {
+                            bail!(SandboxViolation::function(name, span))
+                        }
+                    }
+                }
+                InputInlineRulesOrFixed::Fixed { fixed } => {
+                    let name = &fixed.fixed_handle.name;
+                    ensure!(
+                        !NONDETERMINISTIC_FIXED_RULES.contains(&name.name.as_str()),
+                        SandboxViolation(
+                            format!("The nondeterministic fixed rule '{name}'"),
+                            fixed.span
+                        )
+                    );
+                    ensure!(
+                        fixed.cache.is_none(),
+                        SandboxViolation("Caching fixed rule output".to_string(), fixed.span)
+                    );
+                    if let Some((name, span)) = fixed
+                        .options
+                        .values()
+                        .find_map(|e| e.nondeterministic_call())
+                    {
+                        bail!(SandboxViolation::function(name, span))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replacing a stored relation drops all of its data
+    pub(crate) fn requires_admin(&self) -> bool {
+        matches!(
+            &self.out_opts.store_relation,
+            Some((h, RelationOp::Replace, _)) if !h.name.is_temp_store_name()
+        )
+    }
+
     pub(crate) fn get_entry_arity(&self) -> Result<usize> {
         if let Some(entry) = self.prog.get(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0))) {
             return match entry {
@@ -681,7 +836,7 @@ impl InputProgram {
                                 aggr: rule.aggr.clone(),
                                 body,
                             };
-                            collected_rules.push(normalized_rule.convert_to_well_ordered_rule()?);
+                            collected_rules.push(normalized_rule);
                         }
                     }
                     prog.insert(
@@ -696,13 +851,21 @@ impl InputProgram {
                 }
             }
         }
-        Ok((
-            NormalFormProgram {
-                prog,
-                disable_magic_rewrite: self.disable_magic_rewrite,
-            },
-            self.out_opts,
-        ))
+        let mut prog = NormalFormProgram {
+            prog,
+            disable_magic_rewrite: self.disable_magic_rewrite,
+            force_magic_rewrite: self.force_magic_rewrite,
+        };
+        prog.optimize_rules();
+        for rules_or_fixed in prog.prog.values_mut() {
+            if let NormalFormRulesOrFixed::Rules { rules } = rules_or_fixed {
+                *rules = mem::take(rules)
+                    .into_iter()
+                    .map(|rule| rule.optimize_exprs().convert_to_well_ordered_rule())
+                    .collect::<Result<_>>()?;
+            }
+        }
+        Ok((prog, self.out_opts))
     }
 }
 
@@ -728,6 +891,7 @@ impl NormalFormRulesOrFixed {
 pub(crate) struct NormalFormProgram {
     pub(crate) prog: BTreeMap<Symbol, NormalFormRulesOrFixed>,
     pub(crate) disable_magic_rewrite: bool,
+    pub(crate) force_magic_rewrite: bool,
 }
 
 #[derive(Debug)]
@@ -901,6 +1065,18 @@ pub(crate) struct MagicInlineRule {
 }
 
 impl MagicInlineRule {
+    /// The hints given to the stored relations of the rule, as shown by `::explain`
+    pub(crate) fn hints(&self) -> Vec<String> {
+        self.body
+            .iter()
+            .filter_map(|atom| match atom {
+                MagicAtom::Relation(r) | MagicAtom::NegatedRelation(r) if !r.hints.is_empty() => {
+                    Some(format!(":{} {}", r.name, r.hints))
+                }
+                _ => None,
+            })
+            .collect()
+    }
     pub(crate) fn contained_rules(&self) -> BTreeMap<MagicSymbol, ContainedRuleMultiplicity> {
         let mut coll = BTreeMap::new();
         for atom in self.body.iter() {
@@ -980,6 +1156,10 @@ pub(crate) struct HnswSearch {
     pub(crate) bind_vector: Option<Symbol>,
     pub(crate) radius: Option<f64>,
     pub(crate) filter: Option<Expr>,
+    pub(crate) rerank: Option<RerankStage>,
+    /// Set when the index is read around, the relation is scanned instead,
+    /// see [crate::Db::set_index_fallback]
+    pub(crate) scan_base: bool,
     pub(crate) span: SourceSpan,
 }
 
@@ -1003,6 +1183,10 @@ pub(crate) struct FtsSearch {
     pub(crate) bind_score: Option<Symbol>,
     // pub(crate) lax_mode: bool,
     pub(crate) filter: Option<Expr>,
+    pub(crate) rerank: Option<RerankStage>,
+    /// Set when the index is read around, the relation is scanned instead,
+    /// see [crate::Db::set_index_fallback]
+    pub(crate) scan_base: bool,
     pub(crate) span: SourceSpan,
 }
 
@@ -1014,12 +1198,16 @@ impl HnswSearch {
             .chain(self.bind_field_idx.iter())
             .chain(self.bind_distance.iter())
             .chain(self.bind_vector.iter())
+            .chain(self.rerank.iter().flat_map(|r| r.bind_score.iter()))
     }
 }
 
 impl FtsSearch {
     pub(crate) fn all_bindings(&self) -> impl Iterator<Item=&Symbol> {
-        self.bindings.iter().chain(self.bind_score.iter())
+        self.bindings
+            .iter()
+            .chain(self.bind_score.iter())
+            .chain(self.rerank.iter().flat_map(|r| r.bind_score.iter()))
     }
 }
 
@@ -1164,6 +1352,7 @@ impl SearchInput {
             query,
             span: self.span,
             filter,
+            scan_base: false,
         }));
 
         Ok(Disjunction::conj(conj))
@@ -1174,6 +1363,7 @@ impl SearchInput {
         idx_handle: RelationHandle,
         manifest: FtsIndexManifest,
         gen: &mut TempSymbGen,
+        tx: &SessionTx<'_>,
     ) -> Result<Disjunction> {
         let mut conj = Vec::with_capacity(self.bindings.len() + 8);
         let mut bindings = Vec::with_capacity(self.bindings.len());
@@ -1309,6 +1499,11 @@ impl SearchInput {
             }
         };
 
+        let rerank = RerankStage::take_from_parameters(&mut self.parameters, tx, gen, &mut conj)?;
+        if let Some(rerank) = &rerank {
+            rerank.check_filter(&filter)?;
+        }
+
         if !self.parameters.is_empty() {
             bail!("Unknown parameters for FTS: {:?}", self.parameters.keys());
         }
@@ -1326,6 +1521,8 @@ impl SearchInput {
             // k1,
             // b,
             filter,
+            rerank,
+            scan_base: false,
             span: self.span,
         }));
 
@@ -1337,6 +1534,7 @@ impl SearchInput {
         idx_handle: RelationHandle,
         manifest: HnswIndexManifest,
         gen: &mut TempSymbGen,
+        tx: &SessionTx<'_>,
     ) -> Result<Disjunction> {
         let mut conj = Vec::with_capacity(self.bindings.len() + 8);
         let mut bindings = Vec::with_capacity(self.bindings.len());
@@ -1537,6 +1735,11 @@ impl SearchInput {
             }
         };
 
+        let rerank = RerankStage::take_from_parameters(&mut self.parameters, tx, gen, &mut conj)?;
+        if let Some(rerank) = &rerank {
+            rerank.check_filter(&filter)?;
+        }
+
         if !self.parameters.is_empty() {
             bail!("Unexpected parameters for HNSW: {:?}", self.parameters);
         }
@@ -1555,6 +1758,8 @@ impl SearchInput {
             bind_vector,
             radius,
             filter,
+            rerank,
+            scan_base: false,
             span: self.span,
         }));
 
@@ -1576,11 +1781,11 @@ impl SearchInput {
         if let Some((idx_handle, manifest)) =
             base_handle.hnsw_indices.get(&self.index.name).cloned()
         {
-            return self.normalize_hnsw(base_handle, idx_handle, manifest, gen);
+            return self.normalize_hnsw(base_handle, idx_handle, manifest, gen, tx);
         }
         if let Some((idx_handle, manifest)) = base_handle.fts_indices.get(&self.index.name).cloned()
         {
-            return self.normalize_fts(base_handle, idx_handle, manifest, gen);
+            return self.normalize_fts(base_handle, idx_handle, manifest, gen, tx);
         }
         if let Some((idx_handle, _, manifest)) =
             base_handle.lsh_indices.get(&self.index.name).cloned()
@@ -1620,7 +1825,10 @@ impl Display for InputAtom {
                 f.debug_list().entries(args).finish()?;
             }
             InputAtom::NamedFieldRelation {
-                inner: InputNamedFieldRelationApplyAtom { name, args, .. },
+                inner:
+                    InputNamedFieldRelationApplyAtom {
+                        name, args, hints, ..
+                    },
             } => {
                 f.write_str("*")?;
                 let mut sf = f.debug_struct(name);
@@ -1628,12 +1836,21 @@ impl Display for InputAtom {
                     sf.field(k, v);
                 }
                 sf.finish()?;
+                if !hints.is_empty() {
+                    write!(f, " /*+ {hints} */")?;
+                }
             }
             InputAtom::Relation {
-                inner: InputRelationApplyAtom { name, args, .. },
+                inner:
+                    InputRelationApplyAtom {
+                        name, args, hints, ..
+                    },
             } => {
                 write!(f, ":{name}")?;
                 f.debug_list().entries(args).finish()?;
+                if !hints.is_empty() {
+                    write!(f, " /*+ {hints} */")?;
+                }
             }
             InputAtom::Search { inner } => {
                 write!(f, "~{}:{}{{", inner.relation, inner.index)?;
@@ -1714,6 +1931,106 @@ impl InputAtom {
             InputAtom::Search { inner, .. } => inner.span,
         }
     }
+    pub(crate) fn fix_now(&mut self, now: f64) {
+        match self {
+            InputAtom::Rule { inner } => inner.args.iter_mut().for_each(|e| e.fix_now(now)),
+            InputAtom::NamedFieldRelation { inner } => {
+                inner.args.values_mut().for_each(|e| e.fix_now(now))
+            }
+            InputAtom::Relation { inner } => inner.args.iter_mut().for_each(|e| e.fix_now(now)),
+            InputAtom::Predicate { inner } => inner.fix_now(now),
+            InputAtom::Negation { inner, .. } => inner.fix_now(now),
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                inner.iter_mut().for_each(|a| a.fix_now(now))
+            }
+            InputAtom::Unification { inner } => inner.expr.fix_now(now),
+            InputAtom::Search { inner } => inner
+                .bindings
+                .values_mut()
+                .chain(inner.parameters.values_mut())
+                .for_each(|e| e.fix_now(now)),
+        }
+    }
+    pub(crate) fn for_each_const(&mut self, f: &mut impl FnMut(&mut DataValue, SourceSpan)) {
+        match self {
+            InputAtom::Rule { inner } => inner.args.iter_mut().for_each(|e| e.for_each_const(f)),
+            InputAtom::NamedFieldRelation { inner } => {
+                inner.args.values_mut().for_each(|e| e.for_each_const(f))
+            }
+            InputAtom::Relation { inner } => {
+                inner.args.iter_mut().for_each(|e| e.for_each_const(f))
+            }
+            InputAtom::Predicate { inner } => inner.for_each_const(f),
+            InputAtom::Negation { inner, .. } => inner.for_each_const(f),
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                inner.iter_mut().for_each(|a| a.for_each_const(f))
+            }
+            InputAtom::Unification { inner } => inner.expr.for_each_const(f),
+            InputAtom::Search { inner } => inner
+                .bindings
+                .values_mut()
+                .chain(inner.parameters.values_mut())
+                .for_each(|e| e.for_each_const(f)),
+        }
+    }
+    pub(crate) fn collect_bindings(&self, coll: &mut BTreeSet<Symbol>) -> Result<()> {
+        match self {
+            InputAtom::Rule { inner } => {
+                for arg in &inner.args {
+                    arg.collect_bindings(coll)?;
+                }
+            }
+            InputAtom::NamedFieldRelation { inner } => {
+                for arg in inner.args.values() {
+                    arg.collect_bindings(coll)?;
+                }
+            }
+            InputAtom::Relation { inner } => {
+                for arg in &inner.args {
+                    arg.collect_bindings(coll)?;
+                }
+            }
+            InputAtom::Predicate { inner } => inner.collect_bindings(coll)?,
+            InputAtom::Negation { inner, .. } => inner.collect_bindings(coll)?,
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                for atom in inner {
+                    atom.collect_bindings(coll)?;
+                }
+            }
+            InputAtom::Unification { inner } => {
+                coll.insert(inner.binding.clone());
+                inner.expr.collect_bindings(coll)?;
+            }
+            InputAtom::Search { inner } => {
+                for arg in inner.bindings.values().chain(inner.parameters.values()) {
+                    arg.collect_bindings(coll)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    pub(crate) fn nondeterministic_call(&self) -> Option<(&'static str, SourceSpan)> {
+        match self {
+            InputAtom::Rule { inner } => inner.args.iter().find_map(|e| e.nondeterministic_call()),
+            InputAtom::NamedFieldRelation { inner } => {
+                inner.args.values().find_map(|e| e.nondeterministic_call())
+            }
+            InputAtom::Relation { inner } => {
+                inner.args.iter().find_map(|e| e.nondeterministic_call())
+            }
+            InputAtom::Predicate { inner } => inner.nondeterministic_call(),
+            InputAtom::Negation { inner, .. } => inner.nondeterministic_call(),
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                inner.iter().find_map(|a| a.nondeterministic_call())
+            }
+            InputAtom::Unification { inner } => inner.expr.nondeterministic_call(),
+            InputAtom::Search { inner } => inner
+                .bindings
+                .values()
+                .chain(inner.parameters.values())
+                .find_map(|e| e.nondeterministic_call()),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1749,11 +2066,55 @@ pub(crate) struct InputRuleApplyAtom {
     pub(crate) span: SourceSpan,
 }
 
+/// How a stored relation application is read, when the planner is told
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) enum IndexHint {
+    /// The planner chooses
+    #[default]
+    Auto,
+    /// Read through the relation itself
+    NoIndex,
+    /// Read through the index of the relation with this name
+    UseIndex(Symbol),
+}
+
+/// Hints given to a stored relation application with `/*+ ... */`, overriding
+/// the choices of the planner
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct RelationHints {
+    pub(crate) index: IndexHint,
+    /// Read the relation once, and join it in memory instead of looking it up for each row
+    pub(crate) broadcast: bool,
+}
+
+impl RelationHints {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Display for RelationHints {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut hints = vec![];
+        match &self.index {
+            IndexHint::Auto => {}
+            IndexHint::NoIndex => hints.push("no_index".to_string()),
+            IndexHint::UseIndex(idx) => hints.push(format!("use_index({idx})")),
+        }
+        if self.broadcast {
+            hints.push("broadcast".to_string());
+        }
+        write!(f, "{}", hints.join(", "))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct InputNamedFieldRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: BTreeMap<SmartString<LazyCompact>, Expr>,
     pub(crate) valid_at: Option<ValidityTs>,
+    pub(crate) tx_at: Option<ValidityTs>,
+    pub(crate) hints: RelationHints,
     pub(crate) span: SourceSpan,
 }
 
@@ -1762,6 +2123,8 @@ pub(crate) struct InputRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Expr>,
     pub(crate) valid_at: Option<ValidityTs>,
+    pub(crate) tx_at: Option<ValidityTs>,
+    pub(crate) hints: RelationHints,
     pub(crate) span: SourceSpan,
 }
 
@@ -1777,6 +2140,8 @@ pub(crate) struct NormalFormRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Symbol>,
     pub(crate) valid_at: Option<ValidityTs>,
+    pub(crate) tx_at: Option<ValidityTs>,
+    pub(crate) hints: RelationHints,
     pub(crate) span: SourceSpan,
 }
 
@@ -1792,6 +2157,8 @@ pub(crate) struct MagicRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Symbol>,
     pub(crate) valid_at: Option<ValidityTs>,
+    pub(crate) tx_at: Option<ValidityTs>,
+    pub(crate) hints: RelationHints,
     pub(crate) span: SourceSpan,
 }
 
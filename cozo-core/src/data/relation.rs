@@ -21,6 +21,7 @@ use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::expr::Expr;
+use crate::data::json::JsonValue;
 use crate::data::value::{DataValue, JsonData, UuidWrapper, Validity, ValidityTs, Vector};
 use crate::Num;
 
@@ -41,6 +42,7 @@ impl Display for NullableColType {
             ColType::Bytes => f.write_str("Bytes")?,
             ColType::Uuid => f.write_str("Uuid")?,
             ColType::Validity => f.write_str("Validity")?,
+            ColType::TxTime => f.write_str("TxTime")?,
             ColType::List { eltype, len } => {
                 f.write_str("[")?;
                 write!(f, "{eltype}")?;
@@ -100,6 +102,8 @@ pub enum ColType {
     Tuple(Vec<NullableColType>),
     Validity,
     Json,
+    /// Transaction time, maintained by the database on every write
+    TxTime,
 }
 
 #[derive(
@@ -330,7 +334,7 @@ impl NullableColType {
                     bail!(make_err())
                 }
             }
-            ColType::Validity => {
+            ColType::Validity | ColType::TxTime => {
                 #[derive(Debug, Error, Diagnostic)]
                 #[error("{0} cannot be coerced into validity")]
                 #[diagnostic(code(eval::invalid_validity))]
@@ -445,6 +449,7 @@ impl NullableColType {
                     arr.into()
                 }
                 DataValue::Json(j) => j.0,
+                v @ (DataValue::Mat(_) | DataValue::Sparse(_)) => JsonValue::from(v),
                 DataValue::Validity(vld) => {
                     json!([vld.timestamp.0, vld.is_assert.0])
                 }
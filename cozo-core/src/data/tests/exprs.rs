@@ -30,3 +30,80 @@ fn expression_eval() {
         .unwrap();
     assert_eq!(res.rows[0][0].get_bool().unwrap(), true);
 }
+
+#[test]
+fn batched_ops_match_row_wise() {
+    use crate::data::batch::{Bitmap, TupleBatch};
+    use crate::data::expr::{Expr, Op};
+    use crate::data::functions::*;
+    use crate::data::symb::Symbol;
+    use crate::data::value::RegexWrapper;
+    use crate::parse::SourceSpan;
+
+    let binding = |i: usize| Expr::Binding {
+        var: Symbol::new(format!("v{i}"), SourceSpan::default()),
+        tuple_pos: Some(i),
+    };
+    let constant = |val: DataValue| Expr::Const {
+        val,
+        span: SourceSpan::default(),
+    };
+    let apply = |op: &'static Op, args: Vec<Expr>| Expr::Apply {
+        op,
+        args: args.into(),
+        span: SourceSpan::default(),
+    };
+
+    // int, float, string, int with a null
+    let mut batch = TupleBatch::new(4);
+    for i in 0..100i64 {
+        batch.push(vec![
+            DataValue::from(i - 50),
+            DataValue::from(i as f64 / 3.),
+            DataValue::from(format!("s{}", i % 7)),
+            if i == 42 {
+                DataValue::Null
+            } else {
+                DataValue::from(i)
+            },
+        ]);
+    }
+    let regex = DataValue::Regex(RegexWrapper(regex::Regex::new("^s[0-3]$").unwrap()));
+
+    let mut exprs = vec![];
+    for op in [&OP_EQ, &OP_NEQ, &OP_LT, &OP_LE, &OP_GT, &OP_GE] {
+        exprs.push(apply(op, vec![binding(0), binding(1)]));
+        exprs.push(apply(op, vec![binding(1), constant(DataValue::from(10))]));
+        exprs.push(apply(op, vec![constant(DataValue::from(0.5)), binding(0)]));
+        exprs.push(apply(op, vec![binding(2), constant(DataValue::from("s3"))]));
+    }
+    for op in [&OP_ADD, &OP_SUB, &OP_MUL, &OP_DIV] {
+        exprs.push(apply(op, vec![binding(0), binding(1)]));
+        exprs.push(apply(op, vec![binding(0), constant(DataValue::from(3))]));
+        exprs.push(apply(op, vec![binding(1), constant(DataValue::from(3))]));
+    }
+    exprs.push(apply(&OP_REGEX_MATCHES, vec![binding(2), constant(regex)]));
+    exprs.push(apply(
+        &OP_GT,
+        vec![
+            apply(&OP_MUL, vec![binding(0), constant(DataValue::from(2))]),
+            binding(1),
+        ],
+    ));
+
+    let all = Bitmap::new(batch.len(), true);
+    for expr in exprs {
+        let res = expr.eval_batch(&batch, &all).unwrap();
+        for i in 0..batch.len() {
+            assert_eq!(res.get(i), expr.eval(batch.row(i)).unwrap(), "{expr:?}, row {i}");
+        }
+    }
+
+    // errors are only raised for selected rows
+    let pred = apply(&OP_GT, vec![binding(3), constant(DataValue::from(10))]);
+    let mut selection = Bitmap::new(batch.len(), true);
+    assert!(pred.eval_batch_pred(&batch, &mut selection).is_err());
+    selection.set(42, false);
+    pred.eval_batch_pred(&batch, &mut selection).unwrap();
+    assert_eq!(selection.count_ones(), 88);
+}
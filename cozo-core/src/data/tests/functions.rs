@@ -12,7 +12,7 @@ use regex::Regex;
 use serde_json::json;
 
 use crate::data::functions::*;
-use crate::data::value::{DataValue, RegexWrapper};
+use crate::data::value::{DataValue, JsonData, Matrix, RegexWrapper, SparseVector, Vector};
 use crate::DbInstance;
 
 #[test]
@@ -1487,3 +1487,591 @@ fn test_range() {
         .into_json();
     assert_eq!(res["rows"][0][0], json!([15, 13, 11, 9, 7, 5]));
 }
+
+#[test]
+fn test_business_time() {
+    let ts = |s: &str| op_parse_timestamp(&[DataValue::from(s)]).unwrap();
+    let calendar = DataValue::Json(JsonData(json!({
+        "holidays": ["2024-12-25"],
+        "hours": [["13:00", "17:00"], ["09:00", "12:00"]]
+    })));
+    let add = |t: &str, n: i64| {
+        op_add_business_days(&[ts(t), DataValue::from(n), calendar.clone()]).unwrap()
+    };
+    // from a Friday, over the weekend and Christmas
+    assert_eq!(add("2024-12-20T10:00:00Z", 2), ts("2024-12-24T10:00:00Z"));
+    assert_eq!(add("2024-12-20T10:00:00Z", 3), ts("2024-12-26T10:00:00Z"));
+    assert_eq!(add("2024-12-23T10:00:00Z", -1), ts("2024-12-20T10:00:00Z"));
+    assert_eq!(add("2024-12-21T10:00:00Z", 0), ts("2024-12-21T10:00:00Z"));
+
+    let between =
+        |a: &str, b: &str| op_business_minutes_between(&[ts(a), ts(b), calendar.clone()]).unwrap();
+    assert_eq!(
+        between("2024-12-20T16:00:00Z", "2024-12-23T10:00:00Z"),
+        DataValue::from(120.)
+    );
+    assert_eq!(
+        between("2024-12-23T10:00:00Z", "2024-12-20T16:00:00Z"),
+        DataValue::from(-120.)
+    );
+    // over lunch
+    assert_eq!(
+        between("2024-12-23T11:30:00Z", "2024-12-23T13:30:00Z"),
+        DataValue::from(60.)
+    );
+
+    let bad = DataValue::Json(JsonData(json!({"weekend": ["MO", "XX"]})));
+    assert!(op_add_business_days(&[ts("2024-12-20T10:00:00Z"), DataValue::from(1), bad]).is_err());
+
+    // calendars are usually kept in a stored relation
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[name, spec] <- [['support', parse_json('{"tz": "Europe/Paris", "holidays": ["2024-12-25"], "hours": [["09:00", "17:00"]]}')]]
+        :create calendars {name => spec}
+    "#,
+    )
+    .unwrap();
+    let res = db
+        .run_default(
+            r#"
+        tickets[id, opened, closed] <- [[
+            1,
+            parse_timestamp('2024-12-24T16:00:00+01:00'),
+            parse_timestamp('2024-12-26T10:00:00+01:00')
+        ]]
+        ?[id, due, minutes] := tickets[id, opened, closed],
+                               *calendars{name: 'support', spec},
+                               due = format_timestamp(add_business_days(opened, 1, spec), 'Europe/Paris'),
+                               minutes = business_minutes_between(opened, closed, spec)
+    "#,
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([[1, "2024-12-26T16:00:00+01:00", 120.0]])
+    );
+}
+
+#[test]
+fn test_calendar_buckets() {
+    let ts = |s: &str| op_parse_timestamp(&[DataValue::from(s)]).unwrap();
+    // a Monday, in the first ISO week of 2025
+    let t = ts("2024-12-30T10:00:00Z");
+    assert_eq!(op_iso_week(&[t.clone()]).unwrap(), DataValue::from(1));
+    assert_eq!(op_iso_year(&[t.clone()]).unwrap(), DataValue::from(2025));
+    assert_eq!(op_quarter(&[t.clone()]).unwrap(), DataValue::from(4));
+    assert_eq!(
+        op_start_of_week(&[t.clone()]).unwrap(),
+        ts("2024-12-30T00:00:00Z")
+    );
+    assert_eq!(
+        op_start_of_week(&[t.clone(), DataValue::Null, DataValue::from("SU")]).unwrap(),
+        ts("2024-12-29T00:00:00Z")
+    );
+    assert_eq!(
+        op_start_of_month(&[t.clone()]).unwrap(),
+        ts("2024-12-01T00:00:00Z")
+    );
+    assert_eq!(
+        op_start_of_quarter(&[t.clone()]).unwrap(),
+        ts("2024-10-01T00:00:00Z")
+    );
+    assert_eq!(op_start_of_year(&[t]).unwrap(), ts("2024-01-01T00:00:00Z"));
+
+    // already the new year in Tokyo
+    let t = ts("2024-12-31T23:30:00Z");
+    let tz = DataValue::from("Asia/Tokyo");
+    assert_eq!(
+        op_quarter(&[t.clone(), tz.clone()]).unwrap(),
+        DataValue::from(1)
+    );
+    assert_eq!(
+        op_start_of_year(&[t.clone(), tz]).unwrap(),
+        ts("2025-01-01T00:00:00+09:00")
+    );
+    assert!(op_start_of_week(&[t, DataValue::Null, DataValue::from("XX")]).is_err());
+}
+
+#[test]
+fn test_date_trunc_diff() {
+    let ts = |s: &str| op_parse_timestamp(&[DataValue::from(s)]).unwrap();
+    let ny = DataValue::from("America/New_York");
+    let trunc =
+        |unit: &str, t: &str| op_date_trunc(&[DataValue::from(unit), ts(t), ny.clone()]).unwrap();
+    assert_eq!(
+        trunc("minute", "2024-05-17T10:42:31-04:00"),
+        ts("2024-05-17T10:42:00-04:00")
+    );
+    assert_eq!(
+        trunc("days", "2024-03-10T12:00:00-04:00"),
+        ts("2024-03-10T00:00:00-05:00")
+    );
+    assert_eq!(
+        trunc("week", "2024-05-17T10:00:00-04:00"),
+        ts("2024-05-13T00:00:00-04:00")
+    );
+    assert_eq!(
+        trunc("quarter", "2024-05-17T10:00:00-04:00"),
+        ts("2024-04-01T00:00:00-04:00")
+    );
+    // the second time the clock shows 01:30 when it is put back
+    assert_eq!(
+        trunc("hour", "2024-11-03T01:30:00-05:00"),
+        ts("2024-11-03T01:00:00-05:00")
+    );
+
+    let diff = |unit: &str, a: &str, b: &str| {
+        op_date_diff(&[DataValue::from(unit), ts(a), ts(b), ny.clone()]).unwrap()
+    };
+    // a day on the calendar, but only 23 hours when the clock is put forward
+    let (a, b) = ("2024-03-09T09:00:00-05:00", "2024-03-10T09:00:00-04:00");
+    assert_eq!(diff("day", a, b), DataValue::from(1));
+    assert_eq!(diff("hour", a, b), DataValue::from(23));
+    assert_eq!(diff("day", b, a), DataValue::from(-1));
+    assert_eq!(
+        diff("month", "2024-01-31T00:00:00Z", "2024-02-29T00:00:00Z"),
+        DataValue::from(0)
+    );
+    assert_eq!(
+        diff("month", "2024-01-15T12:00:00Z", "2024-03-15T12:00:00Z"),
+        DataValue::from(2)
+    );
+    assert_eq!(
+        diff("month", "2024-03-15T12:00:00Z", "2024-01-16T12:00:00Z"),
+        DataValue::from(-1)
+    );
+    assert_eq!(
+        diff("year", "2020-02-29T00:00:00Z", "2024-02-28T00:00:00Z"),
+        DataValue::from(3)
+    );
+    assert!(op_date_diff(&[DataValue::from("fortnight"), ts(a), ts(b)]).is_err());
+}
+
+#[test]
+fn test_timestamp_fmt() {
+    let ts = |s: &str| op_parse_timestamp(&[DataValue::from(s)]).unwrap();
+    let fmt = |t: &str, f: &str, tz: &str| {
+        op_format_timestamp_fmt(&[ts(t), DataValue::from(f), DataValue::from(tz)]).unwrap()
+    };
+    assert_eq!(
+        fmt(
+            "2024-05-17T10:42:31Z",
+            "%d/%b/%Y:%H:%M:%S %z",
+            "Europe/Paris"
+        ),
+        DataValue::from("17/May/2024:12:42:31 +0200")
+    );
+    assert_eq!(
+        op_format_timestamp_fmt(&[ts("2024-05-17T10:42:31Z"), DataValue::from("%F %T")]).unwrap(),
+        DataValue::from("2024-05-17 10:42:31")
+    );
+    assert!(op_format_timestamp_fmt(&[ts("2024-05-17T10:42:31Z"), DataValue::from("%Q")]).is_err());
+
+    let parse = |s: &str, f: &str, tz: &str| {
+        op_parse_timestamp_fmt(&[DataValue::from(s), DataValue::from(f), DataValue::from(tz)])
+            .unwrap()
+    };
+    // an access log line, with its own offset
+    assert_eq!(
+        parse("17/May/2024:12:42:31 +0200", "%d/%b/%Y:%H:%M:%S %z", "UTC"),
+        ts("2024-05-17T10:42:31Z")
+    );
+    // a syslog-like time without offset, taken in the timezone given
+    assert_eq!(
+        parse("2024-05-17 12:42:31", "%Y-%m-%d %H:%M:%S", "Europe/Paris"),
+        ts("2024-05-17T10:42:31Z")
+    );
+    // only a date
+    assert_eq!(
+        parse("05/17/2024", "%m/%d/%Y", "America/New_York"),
+        ts("2024-05-17T00:00:00-04:00")
+    );
+    assert!(
+        op_parse_timestamp_fmt(&[DataValue::from("yesterday"), DataValue::from("%F")]).is_err()
+    );
+}
+
+#[test]
+fn test_format_number() {
+    let fmt = |x: DataValue, pattern: &str| {
+        op_format_number(&[x, DataValue::from(pattern)])
+            .unwrap()
+            .get_str()
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(
+        fmt(DataValue::from(1234567.891), "#,##0.00"),
+        "1,234,567.89"
+    );
+    assert_eq!(fmt(DataValue::from(-1234), "$#,##0.00"), "-$1,234.00");
+    assert_eq!(fmt(DataValue::from(0.5), "#.##"), ".5");
+    assert_eq!(fmt(DataValue::from(7), "000"), "007");
+    assert_eq!(fmt(DataValue::from(0.1234), "0.0%"), "12.3%");
+    assert_eq!(fmt(DataValue::from(-0.0001), "0.00"), "0.00");
+    assert_eq!(fmt(DataValue::from(123456.0), "0.00E+00"), "1.23E+05");
+    assert_eq!(fmt(DataValue::from(0.00012), "0.0#E0"), "1.2E-4");
+    assert_eq!(
+        fmt(DataValue::from(i64::MAX), "#,##0"),
+        "9,223,372,036,854,775,807"
+    );
+    assert_eq!(
+        op_format_number(&[
+            DataValue::from(1234567.891),
+            DataValue::from("#,##0.00 €"),
+            DataValue::from(".,")
+        ])
+        .unwrap(),
+        DataValue::from("1.234.567,89 €")
+    );
+    assert!(op_format_number(&[DataValue::from(1), DataValue::from("abc")]).is_err());
+
+    assert_eq!(
+        op_to_hex(&[DataValue::from(255)]).unwrap(),
+        DataValue::from("ff")
+    );
+    assert_eq!(
+        op_to_hex(&[DataValue::from(-255)]).unwrap(),
+        DataValue::from("-ff")
+    );
+    assert_eq!(
+        op_to_hex(&[DataValue::Bytes(vec![0, 15, 255])]).unwrap(),
+        DataValue::from("000fff")
+    );
+    assert_eq!(
+        op_from_hex(&[DataValue::from("0xFF")]).unwrap(),
+        DataValue::from(255)
+    );
+    assert_eq!(
+        op_from_hex(&[DataValue::from("-ff")]).unwrap(),
+        DataValue::from(-255)
+    );
+    assert!(op_from_hex(&[DataValue::from("xyz")]).is_err());
+    assert!(op_from_hex(&[DataValue::from("10000000000000000")]).is_err());
+}
+
+#[test]
+fn test_vec_functions() {
+    let f32s = |xs: &[f32]| DataValue::Vec(Vector::F32(ndarray::Array1::from(xs.to_vec())));
+    let f64s = |xs: &[f64]| DataValue::Vec(Vector::F64(ndarray::Array1::from(xs.to_vec())));
+    let v = f32s(&[1., 2., 3., 4.]);
+
+    assert_eq!(
+        op_vec_get(&[v.clone(), DataValue::from(1)]).unwrap(),
+        DataValue::from(2.)
+    );
+    assert_eq!(
+        op_vec_get(&[v.clone(), DataValue::from(-1)]).unwrap(),
+        DataValue::from(4.)
+    );
+    assert!(op_vec_get(&[v.clone(), DataValue::from(4)]).is_err());
+
+    assert_eq!(
+        op_vec_slice(&[v.clone(), DataValue::from(1), DataValue::from(-1)]).unwrap(),
+        f32s(&[2., 3.])
+    );
+    assert_eq!(
+        op_vec_slice(&[v.clone(), DataValue::from(4), DataValue::from(4)]).unwrap(),
+        f32s(&[])
+    );
+    assert!(op_vec_slice(&[v.clone(), DataValue::from(3), DataValue::from(1)]).is_err());
+
+    assert_eq!(
+        op_vec_concat(&[f64s(&[1.]), f64s(&[]), f64s(&[2., 3.])]).unwrap(),
+        f64s(&[1., 2., 3.])
+    );
+    assert!(op_vec_concat(&[f64s(&[1.]), f32s(&[2.])]).is_err());
+
+    assert_eq!(
+        op_vec_min(&[v.clone(), f32s(&[0., 5., 0., 5.])]).unwrap(),
+        f32s(&[0., 2., 0., 4.])
+    );
+    assert_eq!(
+        op_vec_max(&[v.clone(), DataValue::from(2.5)]).unwrap(),
+        f32s(&[2.5, 2.5, 3., 4.])
+    );
+    assert_eq!(
+        op_vec_clamp(&[v.clone(), DataValue::from(2), DataValue::from(3)]).unwrap(),
+        f32s(&[2., 2., 3., 3.])
+    );
+    assert!(op_vec_min(&[v.clone(), f32s(&[1.])]).is_err());
+    assert!(op_vec_min(&[v.clone(), f64s(&[1., 2., 3., 4.])]).is_err());
+
+    let bytes = op_vec_to_bytes(&[f32s(&[1., -2.])]).unwrap();
+    assert_eq!(
+        bytes,
+        DataValue::Bytes(vec![0, 0, 0x80, 0x3f, 0, 0, 0, 0xc0])
+    );
+    assert_eq!(
+        op_vec_from_bytes(&[bytes.clone()]).unwrap(),
+        f32s(&[1., -2.])
+    );
+    assert_eq!(
+        op_vec_from_bytes(&[
+            op_vec_to_bytes(&[f64s(&[0.5])]).unwrap(),
+            DataValue::from("F64")
+        ])
+        .unwrap(),
+        f64s(&[0.5])
+    );
+    assert!(op_vec_from_bytes(&[bytes, DataValue::from("F64")]).is_err());
+}
+
+#[test]
+fn test_matrix_functions() {
+    let rows = |xs: &[&[f64]]| {
+        DataValue::List(
+            xs.iter()
+                .map(|r| DataValue::List(r.iter().map(|x| DataValue::from(*x)).collect()))
+                .collect(),
+        )
+    };
+    let mat64 = |xs: &[&[f64]]| op_mat(&[rows(xs), DataValue::from("F64")]).unwrap();
+    let m = mat64(&[&[1., 2., 3.], &[4., 5., 6.]]);
+
+    assert_eq!(
+        op_mat_shape(&[m.clone()]).unwrap(),
+        DataValue::List(vec![DataValue::from(2), DataValue::from(3)])
+    );
+    assert!(op_mat(&[rows(&[&[1., 2.], &[3.]])]).is_err());
+    assert!(matches!(
+        op_mat(&[rows(&[&[1., 2.]])]).unwrap(),
+        DataValue::Mat(Matrix::F32(_))
+    ));
+
+    let t = op_transpose(&[m.clone()]).unwrap();
+    assert_eq!(t, mat64(&[&[1., 4.], &[2., 5.], &[3., 6.]]));
+
+    assert_eq!(
+        op_matmul(&[m.clone(), t]).unwrap(),
+        mat64(&[&[14., 32.], &[32., 77.]])
+    );
+    let v = DataValue::Vec(Vector::F64(ndarray::Array1::from(vec![1., 0., -1.])));
+    assert_eq!(
+        op_matmul(&[m.clone(), v.clone()]).unwrap(),
+        DataValue::Vec(Vector::F64(ndarray::Array1::from(vec![-2., -2.])))
+    );
+    assert!(op_matmul(&[v, m.clone()]).is_err());
+    assert!(op_matmul(&[m.clone(), m.clone()]).is_err());
+    assert!(op_matmul(&[m, op_mat(&[rows(&[&[1.], &[1.], &[1.]])]).unwrap()]).is_err());
+
+    assert_eq!(
+        op_l2_normalize_rows(&[mat64(&[&[3., 4.], &[0., 0.]])]).unwrap(),
+        mat64(&[&[0.6, 0.8], &[0., 0.]])
+    );
+
+    let db = DbInstance::default();
+    db.run_default(":create weights {k: Int => w: Any}")
+        .unwrap();
+    db.run_default(r#"?[k, w] <- [[0, mat([[1, 2], [3, 4]], "F64")]] :put weights {k => w}"#)
+        .unwrap();
+    let res = db
+        .run_default(r#"?[s] := *weights{w}, s = matmul(w, vec([1, 1], "F64"))"#)
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"][0][0], json!([3.0, 7.0]));
+    let res = db.run_default("?[w] := *weights{w}").unwrap().into_json();
+    assert_eq!(res["rows"][0][0], json!([[1.0, 2.0], [3.0, 4.0]]));
+}
+
+#[test]
+fn test_sparse_vec_functions() {
+    let pairs = |xs: &[(i64, f64)]| {
+        DataValue::List(
+            xs.iter()
+                .map(|(i, x)| DataValue::List(vec![DataValue::from(*i), DataValue::from(*x)]))
+                .collect(),
+        )
+    };
+    let a = op_sparse_vec(&[pairs(&[(7, 2.), (1, 1.), (3, 0.), (1, 1.)])]).unwrap();
+    assert_eq!(
+        a,
+        DataValue::Sparse(SparseVector {
+            indices: vec![1, 7],
+            values: vec![2., 2.],
+        })
+    );
+    assert_eq!(a.to_string(), "sparse_vec([[1, 2.0], [7, 2.0]])");
+    assert_eq!(
+        op_sparse_vec(&[DataValue::List(vec![
+            DataValue::from(0.),
+            DataValue::from(2.),
+            DataValue::from(0.),
+            DataValue::from(2.),
+        ])])
+        .unwrap(),
+        op_sparse_vec(&[DataValue::Json(JsonData(json!({"1": 2.0, "3": 2.0})))]).unwrap()
+    );
+    assert!(op_sparse_vec(&[pairs(&[(-1, 1.)])]).is_err());
+
+    let b = op_sparse_vec(&[pairs(&[(1, 3.), (2, 5.)])]).unwrap();
+    assert_eq!(
+        op_sparse_dot(&[a.clone(), b.clone()]).unwrap(),
+        DataValue::from(6.)
+    );
+    let d = op_sparse_cos_dist(&[a.clone(), a.clone()])
+        .unwrap()
+        .get_float()
+        .unwrap();
+    assert!(d.abs() < 1e-6);
+    assert_eq!(
+        op_sparse_cos_dist(&[a.clone(), op_sparse_vec(&[pairs(&[])]).unwrap()]).unwrap(),
+        DataValue::from(1.)
+    );
+    assert!(op_sparse_dot(&[a.clone(), DataValue::from(1)]).is_err());
+
+    assert_eq!(
+        op_sparse_to_dense(&[b.clone(), DataValue::from(4), DataValue::from("F64")]).unwrap(),
+        DataValue::Vec(Vector::F64(ndarray::Array1::from(vec![0., 3., 5., 0.])))
+    );
+    assert!(op_sparse_to_dense(&[b, DataValue::from(2)]).is_err());
+
+    let db = DbInstance::default();
+    db.run_default(":create docs {k: Int => v: Any}").unwrap();
+    db.run_default("?[k, v] <- [[1, sparse_vec([[0, 1], [5, 2]])]] :put docs {k => v}")
+        .unwrap();
+    let res = db
+        .run_default("?[s] := *docs{v}, s = sparse_dot(v, sparse_vec([[5, 3]]))")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"][0][0], json!(6.0));
+    let res = db.run_default("?[v] := *docs{v}").unwrap().into_json();
+    assert_eq!(res["rows"][0][0], json!({"0": 1.0, "5": 2.0}));
+}
+
+#[test]
+fn test_chunk_text() {
+    let chunk = |s: &str, max_tokens: i64, overlap: i64, by: Option<&str>| {
+        let mut args = vec![
+            DataValue::from(s),
+            DataValue::from(max_tokens),
+            DataValue::from(overlap),
+        ];
+        if let Some(by) = by {
+            args.push(DataValue::from(by));
+        }
+        op_chunk_text(&args)
+    };
+    assert_eq!(
+        chunk("a b  c d e ", 3, 1, None).unwrap(),
+        DataValue::List(vec![
+            DataValue::List(vec![
+                DataValue::from(0),
+                DataValue::from(0),
+                DataValue::from(6),
+                DataValue::from("a b  c"),
+            ]),
+            DataValue::List(vec![
+                DataValue::from(1),
+                DataValue::from(5),
+                DataValue::from(10),
+                DataValue::from("c d e"),
+            ]),
+        ])
+    );
+    assert_eq!(
+        chunk("héllo wörld", 1, 0, Some("tokens")).unwrap(),
+        DataValue::List(vec![
+            DataValue::List(vec![
+                DataValue::from(0),
+                DataValue::from(0),
+                DataValue::from(5),
+                DataValue::from("héllo"),
+            ]),
+            DataValue::List(vec![
+                DataValue::from(1),
+                DataValue::from(6),
+                DataValue::from(11),
+                DataValue::from("wörld"),
+            ]),
+        ])
+    );
+    assert_eq!(chunk("  ", 3, 1, None).unwrap(), DataValue::List(vec![]));
+
+    let texts = |v: DataValue| match v {
+        DataValue::List(l) => l
+            .into_iter()
+            .map(|c| match c {
+                DataValue::List(mut c) => c.pop().unwrap(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>(),
+        _ => unreachable!(),
+    };
+    let s = "Hi there. How are you? Fine.";
+    assert_eq!(
+        texts(chunk(s, 5, 2, Some("sentences")).unwrap()),
+        vec![
+            DataValue::from("Hi there. How are you?"),
+            DataValue::from("Fine.")
+        ]
+    );
+    assert_eq!(
+        texts(chunk(s, 5, 3, Some("sentences")).unwrap()),
+        vec![
+            DataValue::from("Hi there. How are you?"),
+            DataValue::from("How are you? Fine.")
+        ]
+    );
+    assert_eq!(
+        texts(chunk("a b c d e.", 2, 0, Some("sentences")).unwrap()),
+        vec![
+            DataValue::from("a b"),
+            DataValue::from("c d"),
+            DataValue::from("e.")
+        ]
+    );
+
+    assert!(chunk(s, 0, 0, None).is_err());
+    assert!(chunk(s, 2, 2, None).is_err());
+    assert!(chunk(s, 2, 1, Some("words")).is_err());
+    assert!(op_chunk_text(&[DataValue::from(1), DataValue::from(2), DataValue::from(0)]).is_err());
+}
+
+#[test]
+fn test_phonetic_and_fuzzy_matching() {
+    for (name, code) in [
+        ("Robert", "R163"),
+        ("Rupert", "R163"),
+        ("Ashcraft", "A261"),
+        ("Tymczak", "T522"),
+        ("Pfister", "P236"),
+        ("Honeyman", "H555"),
+        ("Lee", "L000"),
+        ("", ""),
+    ] {
+        assert_eq!(
+            op_soundex(&[DataValue::from(name)]).unwrap(),
+            DataValue::from(code),
+            "{name}"
+        );
+    }
+    for (name, code) in [
+        ("Smith", "SM0"),
+        ("Knight", "NT"),
+        ("Phillip", "FLP"),
+        ("Wright", "RT"),
+        ("Xavier", "SFR"),
+    ] {
+        assert_eq!(
+            op_metaphone(&[DataValue::from(name)]).unwrap(),
+            DataValue::from(code),
+            "{name}"
+        );
+    }
+    assert!(op_soundex(&[DataValue::from(1)]).is_err());
+    assert!(op_metaphone(&[DataValue::Null]).is_err());
+
+    let sim = |a: &str, b: &str| {
+        op_trigram_similarity(&[DataValue::from(a), DataValue::from(b)])
+            .unwrap()
+            .get_float()
+            .unwrap()
+    };
+    assert!((sim("word", "two words") - 4. / 11.).abs() < 1e-9);
+    assert_eq!(sim("cat", "CAT"), 1.);
+    assert_eq!(sim("", ""), 0.);
+    assert!(op_trigram_similarity(&[DataValue::from("a"), DataValue::from(1)]).is_err());
+}
@@ -8,9 +8,9 @@
 
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
-use ndarray::Array1;
+use ndarray::{Array1, Array2};
 use std::cmp::{Ordering, Reverse};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
@@ -169,6 +169,10 @@ pub enum DataValue {
     Json(JsonData),
     /// validity,
     Validity(Validity),
+    /// Matrix, for linear algebra
+    Mat(Matrix),
+    /// Sparse vector
+    Sparse(SparseVector),
     /// bottom type, used internally only
     Bot,
 }
@@ -438,6 +442,261 @@ impl Hash for Vector {
     }
 }
 
+/// Matrix of floating numbers, stored in row-major order
+#[derive(Debug, Clone)]
+pub enum Matrix {
+    /// 32-bit float matrix
+    F32(Array2<f32>),
+    /// 64-bit float matrix
+    F64(Array2<f64>),
+}
+
+impl Matrix {
+    /// Get the number of rows and of columns of the matrix
+    pub fn shape(&self) -> (usize, usize) {
+        match self {
+            Matrix::F32(m) => m.dim(),
+            Matrix::F64(m) => m.dim(),
+        }
+    }
+    pub(crate) fn el_type(&self) -> VecElementType {
+        match self {
+            Matrix::F32(_) => VecElementType::F32,
+            Matrix::F64(_) => VecElementType::F64,
+        }
+    }
+    /// The elements, row after row, in little-endian bytes
+    fn to_le_bytes(&self) -> Vec<u8> {
+        match self {
+            Matrix::F32(m) => m.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            Matrix::F64(m) => m.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        }
+    }
+}
+
+fn cmp_float_arrays<T: num_traits::Float>(l: &Array2<T>, r: &Array2<T>) -> Ordering {
+    l.dim().cmp(&r.dim()).then_with(|| {
+        l.iter()
+            .map(|x| OrderedFloat(*x))
+            .cmp(r.iter().map(|x| OrderedFloat(*x)))
+    })
+}
+
+impl PartialEq<Self> for Matrix {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Matrix {}
+
+impl PartialOrd for Matrix {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Matrix {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Matrix::F32(l), Matrix::F32(r)) => cmp_float_arrays(l, r),
+            (Matrix::F32(_), Matrix::F64(_)) => Ordering::Less,
+            (Matrix::F64(l), Matrix::F64(r)) => cmp_float_arrays(l, r),
+            (Matrix::F64(_), Matrix::F32(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl Hash for Matrix {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.shape().hash(state);
+        match self {
+            Matrix::F32(m) => {
+                for el in m {
+                    OrderedFloat(*el).hash(state)
+                }
+            }
+            Matrix::F64(m) => {
+                for el in m {
+                    OrderedFloat(*el).hash(state)
+                }
+            }
+        }
+    }
+}
+
+impl serde::Serialize for Matrix {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let (rows, cols) = self.shape();
+        let mut state = serializer.serialize_tuple(4)?;
+        state.serialize_element(&match self {
+            Matrix::F32(_) => 0u8,
+            Matrix::F64(_) => 1u8,
+        })?;
+        state.serialize_element(&(rows as u64))?;
+        state.serialize_element(&(cols as u64))?;
+        state.serialize_element(&VecBytes(&self.to_le_bytes()))?;
+        state.end()
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Matrix {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(4, MatrixVisitor)
+    }
+}
+
+struct MatrixVisitor;
+
+impl<'de> Visitor<'de> for MatrixVisitor {
+    type Value = Matrix;
+
+    fn expecting(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("matrix representation")
+    }
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let tag: u8 = seq
+            .next_element()?
+            .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+        let rows: u64 = seq
+            .next_element()?
+            .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
+        let cols: u64 = seq
+            .next_element()?
+            .ok_or_else(|| serde::de::Error::invalid_length(2, &self))?;
+        let bytes: &[u8] = seq
+            .next_element()?
+            .ok_or_else(|| serde::de::Error::invalid_length(3, &self))?;
+        let shape = (rows as usize, cols as usize);
+        let bad_len = || serde::de::Error::invalid_length(bytes.len(), &self);
+        match tag {
+            0u8 => {
+                let els = bytes
+                    .chunks_exact(4)
+                    .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+                    .collect();
+                Ok(Matrix::F32(
+                    Array2::from_shape_vec(shape, els).map_err(|_| bad_len())?,
+                ))
+            }
+            1u8 => {
+                let els = bytes
+                    .chunks_exact(8)
+                    .map(|c| f64::from_le_bytes(c.try_into().unwrap()))
+                    .collect();
+                Ok(Matrix::F64(
+                    Array2::from_shape_vec(shape, els).map_err(|_| bad_len())?,
+                ))
+            }
+            _ => Err(serde::de::Error::invalid_value(
+                serde::de::Unexpected::Unsigned(tag as u64),
+                &self,
+            )),
+        }
+    }
+}
+
+/// Sparse vector of 32-bit floats, holding its non-zero elements only
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, serde_derive::Serialize)]
+pub struct SparseVector {
+    /// Indices of the non-zero elements, strictly increasing
+    pub indices: Vec<u32>,
+    /// Values of the non-zero elements, in the order of `indices`
+    pub values: Vec<f32>,
+}
+
+impl SparseVector {
+    /// Build a sparse vector from pairs of index and value in any order.
+    /// Zeros are dropped, and values at the same index are summed.
+    pub fn from_pairs(pairs: impl IntoIterator<Item = (u32, f32)>) -> Self {
+        let mut collected: BTreeMap<u32, f32> = BTreeMap::new();
+        for (idx, val) in pairs {
+            *collected.entry(idx).or_default() += val;
+        }
+        let mut ret = SparseVector::default();
+        for (idx, val) in collected {
+            if val != 0. {
+                ret.indices.push(idx);
+                ret.values.push(val);
+            }
+        }
+        ret
+    }
+    /// The pairs of index and value of the non-zero elements
+    pub fn iter(&self) -> impl Iterator<Item = (u32, f32)> + '_ {
+        self.indices.iter().copied().zip(self.values.iter().copied())
+    }
+    /// The number of non-zero elements
+    pub fn nnz(&self) -> usize {
+        self.indices.len()
+    }
+    /// The dot product with another sparse vector
+    pub fn dot(&self, other: &Self) -> f64 {
+        let mut ret = 0.;
+        let (mut i, mut j) = (0, 0);
+        while i < self.nnz() && j < other.nnz() {
+            match self.indices[i].cmp(&other.indices[j]) {
+                Ordering::Less => i += 1,
+                Ordering::Greater => j += 1,
+                Ordering::Equal => {
+                    ret += self.values[i] as f64 * other.values[j] as f64;
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        ret
+    }
+    /// The Euclidean norm
+    pub fn norm(&self) -> f64 {
+        self.values
+            .iter()
+            .map(|x| *x as f64 * *x as f64)
+            .sum::<f64>()
+            .sqrt()
+    }
+}
+
+impl PartialEq<Self> for SparseVector {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SparseVector {}
+
+impl PartialOrd for SparseVector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SparseVector {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter()
+            .map(|(i, v)| (i, OrderedFloat(v)))
+            .cmp(other.iter().map(|(i, v)| (i, OrderedFloat(v))))
+    }
+}
+
+impl Hash for SparseVector {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for (idx, val) in self.iter() {
+            idx.hash(state);
+            OrderedFloat(val).hash(state);
+        }
+    }
+}
+
 impl From<bool> for DataValue {
     fn from(value: bool) -> Self {
         DataValue::Bool(value)
@@ -644,6 +903,32 @@ impl Display for DataValue {
                     write!(f, "json({})", j.0)
                 }
             }
+            DataValue::Mat(m) => match m {
+                Matrix::F32(m) => {
+                    write!(
+                        f,
+                        "mat({:?})",
+                        m.outer_iter().map(|r| r.to_vec()).collect::<Vec<_>>()
+                    )
+                }
+                Matrix::F64(m) => {
+                    write!(
+                        f,
+                        "mat({:?}, \"F64\")",
+                        m.outer_iter().map(|r| r.to_vec()).collect::<Vec<_>>()
+                    )
+                }
+            },
+            DataValue::Sparse(s) => {
+                write!(f, "sparse_vec([")?;
+                for (i, (idx, val)) in s.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "[{idx}, {val:?}]")?;
+                }
+                write!(f, "])")
+            }
         }
     }
 }
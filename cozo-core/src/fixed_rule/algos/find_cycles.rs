@@ -0,0 +1,150 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, VecDeque};
+
+use graph::prelude::{DirectedNeighbors, Graph};
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::algos::strongly_connected_components::TarjanSccG;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Finds the cycles of the directed graph given by the edges `[from, to]`,
+/// outputting one witness cycle for each strongly connected component that has one,
+/// as rows of `[component, cycle]`.
+///
+/// The cycle is the shortest one through the first node of the component,
+/// listed as a path starting and ending at that node.
+pub(crate) struct FindCycles;
+
+impl FixedRule for FindCycles {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(2)?;
+
+        let (graph, indices, _) = edges.as_directed_graph(false)?;
+        let adjacency: Vec<Vec<u32>> = (0..graph.node_count())
+            .map(|node| graph.out_neighbors(node).cloned().collect())
+            .collect();
+
+        let components = TarjanSccG::new(graph).run(poison.clone())?;
+        let mut component_of = vec![0; adjacency.len()];
+        for (id, component) in components.iter().enumerate() {
+            for node in component {
+                component_of[*node as usize] = id;
+            }
+        }
+
+        let mut counter: i64 = 0;
+        for (id, component) in components.iter().enumerate() {
+            let start = component[0];
+            if let Some(cycle) =
+                shortest_cycle_through(start, &adjacency, |node| component_of[node as usize] == id)
+            {
+                let cycle = cycle
+                    .into_iter()
+                    .map(|node| indices[node as usize].clone())
+                    .collect();
+                out.put(vec![DataValue::from(counter), DataValue::List(cycle)]);
+                counter += 1;
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(vec!["component".to_string(), "cycle".to_string()])
+    }
+}
+
+/// Breadth-first search from `start` back to itself, only visiting the nodes allowed by `within`
+fn shortest_cycle_through(
+    start: u32,
+    adjacency: &[Vec<u32>],
+    within: impl Fn(u32) -> bool,
+) -> Option<Vec<u32>> {
+    let mut parent: BTreeMap<u32, u32> = BTreeMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(node) = queue.pop_front() {
+        for &next in &adjacency[node as usize] {
+            if next == start {
+                let mut cycle = vec![start, node];
+                let mut current = node;
+                while current != start {
+                    current = parent[&current];
+                    cycle.push(current);
+                }
+                cycle.reverse();
+                return Some(cycle);
+            }
+            if within(next) && !parent.contains_key(&next) {
+                parent.insert(next, node);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::DbInstance;
+
+    #[test]
+    fn test_find_cycles() {
+        let db = DbInstance::default();
+        let res = db
+            .run_default(
+                r#"
+        deps[a, b] <- [['a', 'b'],
+                       ['b', 'c'],
+                       ['c', 'a'],
+                       ['c', 'd'],
+                       ['d', 'e'],
+                       ['f', 'f']]
+        ?[component, cycle] <~ FindCycles(deps[])
+        "#,
+            )
+            .unwrap()
+            .into_json();
+        let mut cycles = res["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row[1].clone())
+            .collect::<Vec<_>>();
+        cycles.sort_by_key(|c| c.to_string());
+        assert_eq!(cycles, vec![json!(["a", "b", "c", "a"]), json!(["f", "f"])]);
+    }
+}
@@ -62,7 +62,11 @@ fn label_propagation(
     let mut labels = (0..n_nodes).collect_vec();
     let mut rng = thread_rng();
     let mut iter_order = (0..n_nodes).collect_vec();
-    for _ in 0..max_iter {
+    for i in 0..max_iter {
+        poison.report_progress(
+            i as f64 / max_iter as f64,
+            format!("iteration {} of at most {max_iter}", i + 1),
+        );
         iter_order.shuffle(&mut rng);
         let mut changed = false;
         for node in &iter_order {
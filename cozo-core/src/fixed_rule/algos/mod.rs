@@ -11,6 +11,7 @@ pub(crate) mod astar;
 pub(crate) mod bfs;
 pub(crate) mod degree_centrality;
 pub(crate) mod dfs;
+pub(crate) mod find_cycles;
 pub(crate) mod kruskal;
 pub(crate) mod label_propagation;
 pub(crate) mod louvain;
@@ -19,8 +20,11 @@ pub(crate) mod prim;
 pub(crate) mod random_walk;
 pub(crate) mod shortest_path_bfs;
 pub(crate) mod shortest_path_dijkstra;
+pub(crate) mod simple_paths;
 pub(crate) mod strongly_connected_components;
 pub(crate) mod top_sort;
+pub(crate) mod topological_layers;
+pub(crate) mod transitive_closure;
 pub(crate) mod triangles;
 pub(crate) mod yen;
 
@@ -29,6 +33,7 @@ pub(crate) use astar::ShortestPathAStar;
 pub(crate) use bfs::Bfs;
 pub(crate) use degree_centrality::DegreeCentrality;
 pub(crate) use dfs::Dfs;
+pub(crate) use find_cycles::FindCycles;
 pub(crate) use kruskal::MinimumSpanningForestKruskal;
 pub(crate) use label_propagation::LabelPropagation;
 pub(crate) use louvain::CommunityDetectionLouvain;
@@ -37,7 +42,10 @@ pub(crate) use prim::MinimumSpanningTreePrim;
 pub(crate) use random_walk::RandomWalk;
 pub(crate) use shortest_path_bfs::ShortestPathBFS;
 pub(crate) use shortest_path_dijkstra::ShortestPathDijkstra;
+pub(crate) use simple_paths::AllSimplePaths;
 pub(crate) use strongly_connected_components::StronglyConnectedComponent;
 pub(crate) use top_sort::TopSort;
+pub(crate) use topological_layers::TopologicalLayers;
+pub(crate) use transitive_closure::TransitiveClosure;
 pub(crate) use triangles::ClusteringCoefficients;
 pub(crate) use yen::KShortestPathYen;
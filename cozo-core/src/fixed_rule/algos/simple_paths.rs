@@ -0,0 +1,179 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use graph::prelude::{DirectedNeighbors, Graph};
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Enumerates the simple paths of the graph given by the edges `[from, to]`
+/// from the starting nodes to the ending nodes, outputting rows of `[start, end, path]`,
+/// the path listing its nodes from `start` to `end`.
+///
+/// Only paths of at most `max_length` nodes are followed.
+/// Without the ending nodes, the paths to every node reached are output.
+pub(crate) struct AllSimplePaths;
+
+impl FixedRule for AllSimplePaths {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(2)?;
+        let starting_nodes: Vec<_> = payload
+            .get_input(1)?
+            .ensure_min_len(1)?
+            .iter()?
+            .map_ok(|n| n.into_iter().next().unwrap())
+            .try_collect()?;
+        let ending_nodes: Option<BTreeSet<_>> = match payload.get_input(2) {
+            Ok(rel) => Some(
+                rel.ensure_min_len(1)?
+                    .iter()?
+                    .map_ok(|n| n.into_iter().next().unwrap())
+                    .try_collect()?,
+            ),
+            Err(_) => None,
+        };
+        let max_length = payload.pos_integer_option("max_length", Some(10))?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+
+        let (graph, indices, inv_indices) = edges.as_directed_graph(undirected)?;
+        let is_end = |node: u32| match &ending_nodes {
+            None => true,
+            Some(ends) => ends.contains(&indices[node as usize]),
+        };
+
+        for start in starting_nodes.iter() {
+            let start_idx = match inv_indices.get(start) {
+                Some(idx) => *idx,
+                None => continue,
+            };
+            let mut path = vec![start_idx];
+            let mut on_path = vec![false; graph.node_count() as usize];
+            on_path[start_idx as usize] = true;
+            let mut pending = vec![graph.out_neighbors(start_idx).cloned().collect_vec()];
+            while let Some(candidates) = pending.last_mut() {
+                match candidates.pop() {
+                    None => {
+                        pending.pop();
+                        let node = path.pop().unwrap();
+                        on_path[node as usize] = false;
+                    }
+                    Some(next) => {
+                        if on_path[next as usize] {
+                            continue;
+                        }
+                        path.push(next);
+                        if is_end(next) {
+                            let nodes = path
+                                .iter()
+                                .map(|node| indices[*node as usize].clone())
+                                .collect();
+                            out.put(vec![
+                                start.clone(),
+                                indices[next as usize].clone(),
+                                DataValue::List(nodes),
+                            ]);
+                        }
+                        if path.len() < max_length {
+                            on_path[next as usize] = true;
+                            pending.push(graph.out_neighbors(next).cloned().collect_vec());
+                        } else {
+                            path.pop();
+                        }
+                        poison.check()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(vec![
+            "start".to_string(),
+            "end".to_string(),
+            "path".to_string(),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::DbInstance;
+
+    #[test]
+    fn test_all_simple_paths() {
+        let db = DbInstance::default();
+        let query = r#"
+        deps[a, b] <- [['a', 'b'],
+                       ['a', 'c'],
+                       ['b', 'd'],
+                       ['c', 'd'],
+                       ['d', 'a'],
+                       ['d', 'e']]
+        start[] <- [['a']]
+        end[] <- [['e']]
+        "#;
+        let res = db
+            .run_default(&format!(
+                "{query} ?[start, end, path] <~ AllSimplePaths(deps[], start[], end[])"
+            ))
+            .unwrap()
+            .into_json();
+        assert_eq!(
+            res["rows"],
+            json!([
+                ["a", "e", ["a", "b", "d", "e"]],
+                ["a", "e", ["a", "c", "d", "e"]]
+            ])
+        );
+        let res = db
+            .run_default(&format!(
+                "{query} ?[start, end, path] <~ AllSimplePaths(deps[], start[], max_length: 3)"
+            ))
+            .unwrap()
+            .into_json();
+        assert_eq!(
+            res["rows"],
+            json!([
+                ["a", "b", ["a", "b"]],
+                ["a", "c", ["a", "c"]],
+                ["a", "d", ["a", "b", "d"]],
+                ["a", "d", ["a", "c", "d"]]
+            ])
+        );
+    }
+}
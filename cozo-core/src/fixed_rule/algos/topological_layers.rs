@@ -0,0 +1,196 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use graph::prelude::{DirectedNeighbors, Graph};
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Sorts the nodes of the DAG given by the edges `[from, to]` topologically, outputting rows
+/// of `[node, order, layer]`. Nodes without edges can be given as the second input.
+///
+/// Among the nodes whose predecessors all come before them, the smallest comes first.
+/// The layer of a node is the length of the longest path reaching it,
+/// so that nodes of the same layer do not depend on each other.
+/// A graph with a cycle is an error reporting one of its cycles.
+pub(crate) struct TopologicalLayers;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The graph is not acyclic, it has the cycle {0}")]
+#[diagnostic(code(algo::graph_has_cycle))]
+#[diagnostic(help("Use FindCycles to list the cycles of the graph"))]
+struct GraphHasCycleError(String, #[label] SourceSpan);
+
+impl FixedRule for TopologicalLayers {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(2)?;
+        let (graph, mut indices, mut inv_indices) = edges.as_directed_graph(false)?;
+        let mut adjacency: Vec<Vec<u32>> = (0..indices.len() as u32)
+            .map(|node| graph.out_neighbors(node).cloned().collect())
+            .collect();
+        if let Ok(nodes) = payload.get_input(1) {
+            for tuple in nodes.ensure_min_len(1)?.iter()? {
+                let node = tuple?.into_iter().next().unwrap();
+                if !inv_indices.contains_key(&node) {
+                    inv_indices.insert(node.clone(), indices.len() as u32);
+                    indices.push(node);
+                    adjacency.push(vec![]);
+                }
+            }
+        }
+
+        let mut in_degree = vec![0; indices.len()];
+        for tos in &adjacency {
+            for to in tos {
+                in_degree[*to as usize] += 1;
+            }
+        }
+        let mut layer = vec![0i64; indices.len()];
+        let mut ready: BTreeSet<(&DataValue, u32)> = in_degree
+            .iter()
+            .enumerate()
+            .filter(|(_, degree)| **degree == 0)
+            .map(|(node, _)| (&indices[node], node as u32))
+            .collect();
+        let mut order: i64 = 0;
+        while let Some((val, node)) = ready.pop_first() {
+            out.put(vec![
+                val.clone(),
+                DataValue::from(order),
+                DataValue::from(layer[node as usize]),
+            ]);
+            order += 1;
+            for to in &adjacency[node as usize] {
+                let to = *to as usize;
+                layer[to] = layer[to].max(layer[node as usize] + 1);
+                in_degree[to] -= 1;
+                if in_degree[to] == 0 {
+                    ready.insert((&indices[to], to as u32));
+                }
+            }
+            poison.check()?;
+        }
+
+        if order < indices.len() as i64 {
+            let cycle = find_remaining_cycle(&adjacency, &in_degree)
+                .into_iter()
+                .map(|node| indices[node as usize].to_string())
+                .join(" -> ");
+            bail!(GraphHasCycleError(cycle, edges.span()))
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(vec![
+            "node".to_string(),
+            "order".to_string(),
+            "layer".to_string(),
+        ])
+    }
+}
+
+/// A cycle among the nodes left over by the sort, which all have a predecessor left over:
+/// following predecessors from any of them must come back to a node already seen
+fn find_remaining_cycle(adjacency: &[Vec<u32>], in_degree: &[usize]) -> Vec<u32> {
+    let mut predecessor = vec![None; adjacency.len()];
+    for (from, tos) in adjacency.iter().enumerate() {
+        if in_degree[from] == 0 {
+            continue;
+        }
+        for to in tos {
+            predecessor[*to as usize] = Some(from as u32);
+        }
+    }
+    let mut node = in_degree.iter().position(|d| *d > 0).unwrap() as u32;
+    let mut seen = BTreeMap::new();
+    let mut walk = vec![];
+    while !seen.contains_key(&node) {
+        seen.insert(node, walk.len());
+        walk.push(node);
+        node = predecessor[node as usize].unwrap();
+    }
+    let mut cycle = walk.split_off(seen[&node]);
+    cycle.push(node);
+    cycle.reverse();
+    cycle
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::DbInstance;
+
+    #[test]
+    fn test_topological_layers() {
+        let db = DbInstance::default();
+        let res = db
+            .run_default(
+                r#"
+        deps[a, b] <- [['lib', 'app'],
+                       ['core', 'lib'],
+                       ['core', 'cli'],
+                       ['cli', 'app']]
+        tasks[] <- [['docs'], ['core']]
+        ?[node, order, layer] <~ TopologicalLayers(deps[], tasks[])
+        :order order
+        "#,
+            )
+            .unwrap()
+            .into_json();
+        assert_eq!(
+            res["rows"],
+            json!([
+                ["core", 0, 0],
+                ["cli", 1, 1],
+                ["docs", 2, 0],
+                ["lib", 3, 1],
+                ["app", 4, 2]
+            ])
+        );
+
+        let err = db
+            .run_default(
+                r#"
+        deps[a, b] <- [['a', 'b'], ['b', 'c'], ['c', 'b'], ['c', 'd']]
+        ?[node, order, layer] <~ TopologicalLayers(deps[])
+        "#,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains(r#""b" -> "c" -> "b""#));
+    }
+}
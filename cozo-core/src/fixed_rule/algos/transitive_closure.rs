@@ -0,0 +1,253 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use graph::prelude::{DirectedNeighbors, Graph};
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::algos::strongly_connected_components::TarjanSccG;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Computes the transitive closure of the graph given by the edges `[from, to]`,
+/// outputting a row `[from, to]` for each node `to` reachable from `from` by a non-empty path.
+/// With the starting nodes given as the second input, only the rows of these nodes are output.
+///
+/// The strongly connected components are collapsed first, and the reachable components are
+/// then merged along the resulting DAG, which is much faster than the recursive rule
+/// `tc[a, c] := tc[a, b], edge[b, c]`. Use `cache: true` on stored relations to keep the
+/// closure in the database until the edges are written to.
+pub(crate) struct TransitiveClosure;
+
+impl FixedRule for TransitiveClosure {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(2)?;
+        let (graph, indices, inv_indices) = edges.as_directed_graph(false)?;
+        let starting_nodes: Option<Vec<u32>> = match payload.get_input(1) {
+            Ok(rel) => Some(
+                rel.ensure_min_len(1)?
+                    .iter()?
+                    .filter_map_ok(|n| inv_indices.get(&n[0]).cloned())
+                    .try_collect()?,
+            ),
+            Err(_) => None,
+        };
+
+        let adjacency: Vec<Vec<u32>> = (0..graph.node_count())
+            .map(|node| graph.out_neighbors(node).cloned().collect())
+            .collect();
+        let components = TarjanSccG::new(graph).run(poison.clone())?;
+        let mut component_of = vec![0; adjacency.len()];
+        for (id, component) in components.iter().enumerate() {
+            for node in component {
+                component_of[*node as usize] = id;
+            }
+        }
+        // a component reaches itself if it has a cycle
+        let mut cyclic = vec![false; components.len()];
+        let mut successors = vec![vec![]; components.len()];
+        for (from, tos) in adjacency.iter().enumerate() {
+            let from_component = component_of[from];
+            for to in tos {
+                let to_component = component_of[*to as usize];
+                if to_component == from_component {
+                    cyclic[from_component] = true;
+                } else {
+                    successors[from_component].push(to_component);
+                }
+            }
+        }
+        for succ in successors.iter_mut() {
+            succ.sort_unstable();
+            succ.dedup();
+        }
+
+        let reached: BTreeMap<usize, Vec<usize>> = match &starting_nodes {
+            Some(starting_nodes) => {
+                let mut reached = BTreeMap::new();
+                for node in starting_nodes {
+                    let component = component_of[*node as usize];
+                    if !reached.contains_key(&component) {
+                        let found = reachable_from(component, &successors, &cyclic);
+                        reached.insert(component, found);
+                    }
+                    poison.check()?;
+                }
+                reached
+            }
+            None => all_reachable(&successors, &cyclic, &poison)?
+                .into_iter()
+                .enumerate()
+                .collect(),
+        };
+
+        let sources = match starting_nodes {
+            Some(nodes) => nodes,
+            None => (0..adjacency.len() as u32).collect(),
+        };
+        for from in sources {
+            let from_val = &indices[from as usize];
+            for component in &reached[&component_of[from as usize]] {
+                for to in &components[*component] {
+                    out.put(vec![from_val.clone(), indices[*to as usize].clone()]);
+                }
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(vec!["from".to_string(), "to".to_string()])
+    }
+}
+
+/// The components reachable from `start` by a non-empty path, by depth-first search
+fn reachable_from(start: usize, successors: &[Vec<usize>], cyclic: &[bool]) -> Vec<usize> {
+    let mut visited = vec![false; successors.len()];
+    let mut found = vec![];
+    if cyclic[start] {
+        found.push(start);
+    }
+    let mut stack = successors[start].clone();
+    while let Some(component) = stack.pop() {
+        if visited[component] {
+            continue;
+        }
+        visited[component] = true;
+        found.push(component);
+        stack.extend(successors[component].iter().filter(|c| !visited[**c]));
+    }
+    found
+}
+
+/// The components reachable from every component, merged in reverse topological order
+fn all_reachable(
+    successors: &[Vec<usize>],
+    cyclic: &[bool],
+    poison: &Poison,
+) -> Result<Vec<Vec<usize>>> {
+    let mut in_degree = vec![0; successors.len()];
+    for succ in successors {
+        for to in succ {
+            in_degree[*to] += 1;
+        }
+    }
+    let mut pending = (0..successors.len())
+        .filter(|c| in_degree[*c] == 0)
+        .collect_vec();
+    let mut sorted = Vec::with_capacity(successors.len());
+    while let Some(component) = pending.pop() {
+        sorted.push(component);
+        for to in &successors[component] {
+            in_degree[*to] -= 1;
+            if in_degree[*to] == 0 {
+                pending.push(*to);
+            }
+        }
+    }
+
+    let mut reached: Vec<Vec<usize>> = vec![vec![]; successors.len()];
+    for component in sorted.into_iter().rev() {
+        let mut found = successors[component].clone();
+        for to in &successors[component] {
+            found.extend_from_slice(&reached[*to]);
+        }
+        found.sort_unstable();
+        found.dedup();
+        reached[component] = found;
+        poison.check()?;
+    }
+    for (component, found) in reached.iter_mut().enumerate() {
+        if cyclic[component] {
+            found.push(component);
+        }
+    }
+    Ok(reached)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::DbInstance;
+
+    #[test]
+    fn test_transitive_closure() {
+        let db = DbInstance::default();
+        let edges = r#"
+        edge[a, b] <- [[1, 2], [2, 3], [3, 2], [3, 4], [5, 5]]
+        "#;
+        let res = db
+            .run_default(&format!("{edges} ?[a, b] <~ TransitiveClosure(edge[])"))
+            .unwrap()
+            .into_json();
+        assert_eq!(
+            res["rows"],
+            json!([
+                [1, 2],
+                [1, 3],
+                [1, 4],
+                [2, 2],
+                [2, 3],
+                [2, 4],
+                [3, 2],
+                [3, 3],
+                [3, 4],
+                [5, 5]
+            ])
+        );
+        let res = db
+            .run_default(&format!(
+                "{edges} start[] <- [[3], [4]] ?[a, b] <~ TransitiveClosure(edge[], start[])"
+            ))
+            .unwrap()
+            .into_json();
+        assert_eq!(res["rows"], json!([[3, 2], [3, 3], [3, 4]]));
+
+        db.run_default(":create dep {a: Int, b: Int}").unwrap();
+        db.run_default("?[a, b] <- [[1, 2], [2, 3]] :put dep {a, b}")
+            .unwrap();
+        let query = "?[a, b] <~ TransitiveClosure(*dep[a, b], cache: true)";
+        let res = db.run_default(query).unwrap().into_json();
+        assert_eq!(res["rows"], json!([[1, 2], [1, 3], [2, 3]]));
+        assert_eq!(db.run_default("::cache").unwrap().rows.len(), 1);
+        db.run_default("?[a, b] <- [[3, 4]] :put dep {a, b}")
+            .unwrap();
+        let res = db.run_default(query).unwrap().into_json();
+        assert_eq!(
+            res["rows"],
+            json!([[1, 2], [1, 3], [1, 4], [2, 3], [2, 4], [3, 4]])
+        );
+    }
+}
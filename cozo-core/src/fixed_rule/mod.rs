@@ -92,7 +92,7 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
                 Box::new(store.all_iter().map(|t| Ok(t.into_tuple())))
             }
             MagicFixedRuleRuleArg::Stored { name, valid_at, .. } => {
-                let relation = self.tx.get_relation(name, false)?;
+                let relation = self.tx.get_relation_for_read(name)?;
                 if let Some(valid_at) = valid_at {
                     Box::new(relation.skip_scan_all(self.tx, *valid_at))
                 } else {
@@ -112,7 +112,7 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
                 Box::new(store.prefix_iter(&t).map(|t| Ok(t.into_tuple())))
             }
             MagicFixedRuleRuleArg::Stored { name, valid_at, .. } => {
-                let relation = self.tx.get_relation(name, false)?;
+                let relation = self.tx.get_relation_for_read(name)?;
                 let t = vec![prefix.clone()];
                 if let Some(valid_at) = valid_at {
                     Box::new(relation.skip_scan_prefix(self.tx, &t, *valid_at))
@@ -555,6 +555,16 @@ pub trait FixedRule: Send + Sync {
         rule_head: &[Symbol],
         span: SourceSpan,
     ) -> Result<usize>;
+    /// The names of the output columns, used as the head of applications that do not give one,
+    /// so that results and relations stored from them do not rely on column positions.
+    /// If names are returned, there must be as many as the arity.
+    /// The default implementation returns none, and columns are named by their positions.
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        None
+    }
     /// You should implement the logic of your algorithm/utility in this function.
     /// The outputs are written to `out`. You should check `poison` periodically
     /// for user-initiated termination.
@@ -570,6 +580,7 @@ pub trait FixedRule: Send + Sync {
 /// but implementation is simpler.
 pub struct SimpleFixedRule {
     return_arity: usize,
+    output_columns: Option<Vec<String>>,
     rule: Box<
         dyn Fn(Vec<NamedRows>, BTreeMap<String, DataValue>) -> Result<NamedRows>
             + Send
@@ -596,9 +607,16 @@ impl SimpleFixedRule {
     {
         Self {
             return_arity,
+            output_columns: None,
             rule: Box::new(rule),
         }
     }
+    /// Name the output columns, see [FixedRule::output_columns].
+    /// There must be as many names as `return_arity`.
+    pub fn with_output_columns(mut self, names: Vec<String>) -> Self {
+        self.output_columns = Some(names);
+        self
+    }
     /// Construct a SimpleFixedRule that uses channels for communication.
     pub fn rule_with_channel(
         return_arity: usize,
@@ -614,6 +632,7 @@ impl SimpleFixedRule {
         (
             Self {
                 return_arity,
+                output_columns: None,
                 rule: Box::new(move |inputs, options| -> Result<NamedRows> {
                     let (app2db_sender, app2db_receiver) = bounded(0);
                     db2app_sender
@@ -637,6 +656,13 @@ impl FixedRule for SimpleFixedRule {
         Ok(self.return_arity)
     }
 
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        self.output_columns.clone()
+    }
+
     fn run(
         &self,
         payload: FixedRulePayload<'_, '_>,
@@ -702,6 +728,15 @@ pub(crate) struct FixedRuleHandle {
     pub(crate) name: Symbol,
 }
 
+/// Names of the default fixed rules whose output may differ between runs on the same data
+pub(crate) const NONDETERMINISTIC_FIXED_RULES: &[&str] = &[
+    "LabelPropagation",
+    "RandomWalk",
+    "JsonReader",
+    "CsvReader",
+    "OnnxScore",
+];
+
 lazy_static! {
     pub(crate) static ref DEFAULT_FIXED_RULES: BTreeMap<String, Arc<Box<dyn FixedRule>>> = {
         BTreeMap::from([
@@ -781,6 +816,26 @@ lazy_static! {
                 Arc::<Box<dyn FixedRule>>::new(Box::new(TopSort)),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "TopologicalLayers".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(TopologicalLayers)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "FindCycles".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(FindCycles)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "AllSimplePaths".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(AllSimplePaths)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "TransitiveClosure".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(TransitiveClosure)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "ConnectedComponents".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(StronglyConnectedComponent::new(false))),
@@ -827,10 +882,51 @@ lazy_static! {
                 "CsvReader".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(CsvReader)),
             ),
+            (
+                "ChunkText".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(ChunkText)),
+            ),
             (
                 "Constant".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(Constant)),
             ),
+            (
+                "ConvertOffsets".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(ConvertOffsets)),
+            ),
+            (
+                "ExpandRecurrences".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(ExpandRecurrences)),
+            ),
+            (
+                "HybridSearch".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(HybridSearch)),
+            ),
+            (
+                "Pivot".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Pivot)),
+            ),
+            (
+                "Unpivot".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Unpivot)),
+            ),
+            (
+                "RdfMatch".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(RdfMatch)),
+            ),
+            (
+                "SparseSearch".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(SparseSearch)),
+            ),
+            (
+                "TrigramSearch".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(TrigramSearch)),
+            ),
+            #[cfg(feature = "onnx")]
+            (
+                "OnnxScore".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(OnnxScore)),
+            ),
         ])
     };
 }
@@ -0,0 +1,114 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::functions::text_chunks;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Splits the texts of the input rows `[key, text]` into chunks of at most `max_tokens`
+/// whitespace-separated tokens, consecutive chunks sharing up to `overlap` tokens.
+/// With `by: 'sentences'` chunks only break between sentences, unless a single sentence
+/// is too long. Outputs rows of `[key, chunk_idx, start, end, chunk]`, where `start` and `end`
+/// are the character offsets of the chunk in the text, as taken by `slice_string`.
+pub(crate) struct ChunkText;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The value {0:?} to be chunked is not a string")]
+#[diagnostic(code(fixed_rule::chunk_not_a_string))]
+#[diagnostic(help("The last column of the input of ChunkText must hold the texts"))]
+struct NotTextError(DataValue, #[label] SourceSpan);
+
+impl FixedRule for ChunkText {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let texts = payload.get_input(0)?.ensure_min_len(2)?;
+        let max_tokens = payload.pos_integer_option("max_tokens", Some(256))?;
+        let overlap = payload.non_neg_integer_option("overlap", Some(0))?;
+        if overlap >= max_tokens {
+            bail!(WrongFixedRuleOptionError {
+                name: "overlap".to_string(),
+                span: payload.option_span("overlap")?,
+                rule_name: "ChunkText".to_string(),
+                help: "'overlap' must be less than 'max_tokens'".to_string()
+            })
+        }
+        let by_sentences = match payload.string_option("by", Some("tokens"))?.as_str() {
+            "tokens" => false,
+            "sentences" => true,
+            _ => bail!(WrongFixedRuleOptionError {
+                name: "by".to_string(),
+                span: payload.option_span("by")?,
+                rule_name: "ChunkText".to_string(),
+                help: "'by' must be 'tokens' or 'sentences'".to_string()
+            }),
+        };
+
+        for tuple in texts.iter()? {
+            let mut tuple = tuple?;
+            let text = tuple.pop().unwrap();
+            let chars = match &text {
+                DataValue::Str(s) => s.chars().collect_vec(),
+                _ => bail!(NotTextError(text, texts.span())),
+            };
+            let key = tuple.swap_remove(0);
+            for (i, (start, end)) in text_chunks(&chars, max_tokens, overlap, by_sentences)
+                .into_iter()
+                .enumerate()
+            {
+                out.put(vec![
+                    key.clone(),
+                    DataValue::from(i as i64),
+                    DataValue::from(start as i64),
+                    DataValue::from(end as i64),
+                    DataValue::Str(chars[start..end].iter().collect()),
+                ]);
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(5)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(vec![
+            "key".to_string(),
+            "chunk_idx".to_string(),
+            "start".to_string(),
+            "end".to_string(),
+            "chunk".to_string(),
+        ])
+    }
+}
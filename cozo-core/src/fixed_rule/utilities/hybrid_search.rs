@@ -0,0 +1,142 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Fuses the hits of a vector search `[key, distance]` and of a full-text search `[key, score]`
+/// into a single ranking, outputting the `k` best keys as rows of `[key, score]`.
+///
+/// With `method: 'rrf'` (the default) the score of a key is the reciprocal rank fusion
+/// `sum(weight / (rrf_k + rank))` over both searches, ranks starting at 1.
+/// With `method: 'weighted'` it is the weighted sum of the distances and scores,
+/// each rescaled to `[0, 1]` with 1 for the best hit.
+/// Keys missing from one of the searches get nothing from it.
+pub(crate) struct HybridSearch;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The value {0:?} in the search results is not a number")]
+#[diagnostic(code(fixed_rule::bad_search_score))]
+#[diagnostic(help("The last column of the inputs of HybridSearch must hold distances or scores"))]
+struct BadSearchScoreError(DataValue, #[label] SourceSpan);
+
+/// The hits of a search, best first, keeping the best hit for each key
+fn ranked_hits(
+    rel: FixedRuleInputRelation<'_, '_>,
+    lower_is_better: bool,
+    poison: &Poison,
+) -> Result<Vec<(DataValue, f64)>> {
+    let mut best: BTreeMap<DataValue, f64> = BTreeMap::new();
+    for tuple in rel.iter()? {
+        let mut tuple = tuple?;
+        let metric = tuple.pop().unwrap();
+        let metric = match metric.get_float() {
+            Some(f) if !f.is_nan() => f,
+            _ => bail!(BadSearchScoreError(metric, rel.span())),
+        };
+        let metric = if lower_is_better { -metric } else { metric };
+        let key = tuple.swap_remove(0);
+        let entry = best.entry(key).or_insert(metric);
+        if metric > *entry {
+            *entry = metric;
+        }
+        poison.check()?;
+    }
+    let mut ret = best.into_iter().collect_vec();
+    ret.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    Ok(ret)
+}
+
+impl FixedRule for HybridSearch {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let vector_hits = payload.get_input(0)?.ensure_min_len(2)?;
+        let text_hits = payload.get_input(1)?.ensure_min_len(2)?;
+        let method = payload.string_option("method", Some("rrf"))?;
+        let k = payload.pos_integer_option("k", Some(10))?;
+        let rrf_k = payload.float_option("rrf_k", Some(60.))?;
+        let vector_weight = payload.float_option("vector_weight", Some(1.))?;
+        let text_weight = payload.float_option("text_weight", Some(1.))?;
+
+        let vector_hits = ranked_hits(vector_hits, true, &poison)?;
+        let text_hits = ranked_hits(text_hits, false, &poison)?;
+
+        let mut fused: BTreeMap<DataValue, f64> = BTreeMap::new();
+        for (hits, weight) in [(vector_hits, vector_weight), (text_hits, text_weight)] {
+            match &method as &str {
+                "rrf" => {
+                    for (rank, (key, _)) in hits.into_iter().enumerate() {
+                        *fused.entry(key).or_default() += weight / (rrf_k + (rank + 1) as f64);
+                    }
+                }
+                "weighted" => {
+                    let (best, worst) = match (hits.first(), hits.last()) {
+                        (Some((_, best)), Some((_, worst))) => (*best, *worst),
+                        _ => continue,
+                    };
+                    for (key, metric) in hits {
+                        let rescaled = if best > worst {
+                            (metric - worst) / (best - worst)
+                        } else {
+                            1.
+                        };
+                        *fused.entry(key).or_default() += weight * rescaled;
+                    }
+                }
+                _ => bail!(WrongFixedRuleOptionError {
+                    name: "method".to_string(),
+                    span: payload.option_span("method")?,
+                    rule_name: "HybridSearch".to_string(),
+                    help: "'method' must be 'rrf' or 'weighted'".to_string()
+                }),
+            }
+        }
+
+        for (key, score) in fused
+            .into_iter()
+            .sorted_by(|(_, a), (_, b)| b.total_cmp(a))
+            .take(k)
+        {
+            out.put(vec![key, DataValue::from(score)]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(vec!["key".to_string(), "score".to_string()])
+    }
+}
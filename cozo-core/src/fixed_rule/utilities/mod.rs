@@ -6,12 +6,32 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub(crate) mod chunk_text;
 pub(crate) mod constant;
 pub(crate) mod csv;
+pub(crate) mod hybrid_search;
 pub(crate) mod jlines;
+pub(crate) mod offsets;
+#[cfg(feature = "onnx")]
+pub(crate) mod onnx;
+pub(crate) mod pivot;
+pub(crate) mod rdf_match;
+pub(crate) mod recurrence;
 pub(crate) mod reorder_sort;
+pub(crate) mod sparse_search;
+pub(crate) mod trigram_search;
 
 pub(crate) use self::csv::CsvReader;
+pub(crate) use chunk_text::ChunkText;
 pub(crate) use constant::Constant;
+pub(crate) use hybrid_search::HybridSearch;
 pub(crate) use jlines::JsonReader;
+pub(crate) use offsets::ConvertOffsets;
+#[cfg(feature = "onnx")]
+pub(crate) use onnx::OnnxScore;
+pub(crate) use pivot::{Pivot, Unpivot};
+pub(crate) use rdf_match::RdfMatch;
+pub(crate) use recurrence::ExpandRecurrences;
 pub(crate) use reorder_sort::ReorderSort;
+pub(crate) use sparse_search::SparseSearch;
+pub(crate) use trigram_search::TrigramSearch;
@@ -0,0 +1,165 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, miette, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Offset {1} in {0} is not at a character boundary")]
+#[diagnostic(code(algo::offset_not_at_char_boundary))]
+#[diagnostic(help("Check that the offset is in the encoding given by the option 'from'"))]
+struct OffsetNotAtCharBoundary(String, usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Offset {1} is past the end of {0}")]
+#[diagnostic(code(algo::offset_out_of_range))]
+struct OffsetOutOfRange(String, usize);
+
+/// Converts offsets into files between UTF-8 and UTF-16, and into lines and columns.
+///
+/// The first input holds rows of file path and offset, the second one rows of file path
+/// and content, as a string or UTF-8 encoded bytes. Offsets are counted in the encoding
+/// given by the option `from`: bytes for `'utf8'` (the default), code units for `'utf16'`.
+///
+/// For each offset, the output row holds the path, the offset as given, the UTF-8 and
+/// UTF-16 offsets, the line, and the UTF-8 and UTF-16 columns within the line.
+/// Lines and columns start at zero.
+pub(crate) struct ConvertOffsets;
+
+impl FixedRule for ConvertOffsets {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let positions = payload.get_input(0)?.ensure_min_len(2)?;
+        let files = payload.get_input(1)?.ensure_min_len(2)?;
+        let from_utf16 = match payload.string_option("from", Some("utf8"))?.as_str() {
+            "utf8" => false,
+            "utf16" => true,
+            _ => bail!(WrongFixedRuleOptionError {
+                name: "from".to_string(),
+                span: payload.option_span("from")?,
+                rule_name: payload.name().to_string(),
+                help: "must be 'utf8' or 'utf16'".to_string(),
+            }),
+        };
+
+        let mut offsets: BTreeMap<DataValue, Vec<usize>> = BTreeMap::new();
+        for tuple in positions.iter()? {
+            let tuple = tuple?;
+            let offset = tuple[1].get_non_neg_int().ok_or_else(|| {
+                miette!("Offset must be a non-negative integer, got {}", tuple[1])
+            })?;
+            offsets
+                .entry(tuple[0].clone())
+                .or_default()
+                .push(offset as usize);
+        }
+
+        for tuple in files.iter()? {
+            let tuple = tuple?;
+            let path = &tuple[0];
+            let mut targets = match offsets.remove(path) {
+                None => continue,
+                Some(targets) => targets,
+            };
+            targets.sort_unstable();
+            targets.dedup();
+            let content = match &tuple[1] {
+                DataValue::Str(s) => s.as_str(),
+                DataValue::Bytes(b) => std::str::from_utf8(b)
+                    .map_err(|_| miette!("The content of {path} is not valid UTF-8"))?,
+                v => bail!("The content of {path} must be a string or bytes, got {v}"),
+            };
+
+            let mut targets = targets.into_iter().peekable();
+            let mut chars = content.chars();
+            let (mut utf8, mut utf16) = (0, 0);
+            let (mut line, mut line_utf8, mut line_utf16) = (0i64, 0, 0);
+            loop {
+                let here = if from_utf16 { utf16 } else { utf8 };
+                while let Some(target) = targets.next_if(|t| *t <= here) {
+                    ensure!(
+                        target == here,
+                        OffsetNotAtCharBoundary(path.to_string(), target)
+                    );
+                    out.put(vec![
+                        path.clone(),
+                        DataValue::from(target as i64),
+                        DataValue::from(utf8 as i64),
+                        DataValue::from(utf16 as i64),
+                        DataValue::from(line),
+                        DataValue::from((utf8 - line_utf8) as i64),
+                        DataValue::from((utf16 - line_utf16) as i64),
+                    ]);
+                }
+                let c = match chars.next() {
+                    None => break,
+                    Some(c) => c,
+                };
+                utf8 += c.len_utf8();
+                utf16 += c.len_utf16();
+                if c == '\n' {
+                    line += 1;
+                    line_utf8 = utf8;
+                    line_utf16 = utf16;
+                    poison.check()?;
+                }
+            }
+            if let Some(target) = targets.next() {
+                bail!(OffsetOutOfRange(path.to_string(), target))
+            }
+        }
+        if let Some(path) = offsets.into_keys().next() {
+            bail!("No content given for {path}")
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(7)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(
+            [
+                "path",
+                "offset",
+                "utf8",
+                "utf16",
+                "line",
+                "col_utf8",
+                "col_utf16",
+            ]
+            .map(|s| s.to_string())
+            .to_vec(),
+        )
+    }
+}
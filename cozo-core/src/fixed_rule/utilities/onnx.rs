@@ -0,0 +1,212 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::mem;
+
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+use tract_onnx::prelude::*;
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::{DataValue, Vector};
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Scores rows of `[key, features]` with an ONNX model, outputting rows of `[key, prediction]`.
+///
+/// The model is given by the option `model`, either as a path or as bytes,
+/// or as the last column of the first row of a second input relation.
+/// Rows are fed to the model in batches of `batch_size` (default 256),
+/// as a `[batch_size, n_features]` tensor of 32-bit floats.
+pub(crate) struct OnnxScore;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot score with the ONNX model: {0}")]
+#[diagnostic(code(fixed_rule::onnx_error))]
+struct OnnxError(String, #[label] SourceSpan);
+
+type OnnxPlan = TypedRunnableModel<TypedModel>;
+
+impl FixedRule for OnnxScore {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?.ensure_min_len(2)?;
+        let batch_size = payload.pos_integer_option("batch_size", Some(256))?;
+        let model = model_bytes(&payload)?;
+        let span = payload.span();
+
+        let mut plan = None;
+        let mut n_features = None;
+        let mut keys = Vec::with_capacity(batch_size);
+        let mut features = vec![];
+        for tuple in in_rel.iter()? {
+            let tuple = tuple?;
+            let start = features.len();
+            match tuple.last().unwrap() {
+                DataValue::Vec(Vector::F32(v)) => features.extend(v.iter()),
+                DataValue::Vec(Vector::F64(v)) => features.extend(v.iter().map(|x| *x as f32)),
+                DataValue::List(l) => {
+                    for el in l {
+                        let f = el.get_float().ok_or_else(|| {
+                            OnnxError("features must be numbers".to_string(), span)
+                        })?;
+                        features.push(f as f32);
+                    }
+                }
+                v => bail!(OnnxError(
+                    format!("features must be a vector or a list, got {v}"),
+                    span
+                )),
+            }
+            let len = features.len() - start;
+            ensure!(
+                *n_features.get_or_insert(len) == len,
+                OnnxError(
+                    "all rows must have the same number of features".to_string(),
+                    span
+                )
+            );
+            keys.push(tuple.into_iter().next().unwrap());
+            if keys.len() == batch_size {
+                if plan.is_none() {
+                    plan = Some(build_plan(&model, batch_size, len, span)?);
+                }
+                score_batch(
+                    plan.as_ref().unwrap(),
+                    &mut keys,
+                    &mut features,
+                    batch_size,
+                    out,
+                    span,
+                )?;
+            }
+            poison.check()?;
+        }
+        if let Some(n_features) = n_features {
+            if !keys.is_empty() {
+                if plan.is_none() {
+                    plan = Some(build_plan(&model, batch_size, n_features, span)?);
+                }
+                // the last batch is padded with zeros, the padding scores are dropped
+                features.resize(batch_size * n_features, 0.);
+                score_batch(
+                    plan.as_ref().unwrap(),
+                    &mut keys,
+                    &mut features,
+                    batch_size,
+                    out,
+                    span,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(vec!["key".to_string(), "prediction".to_string()])
+    }
+}
+
+fn model_bytes(payload: &FixedRulePayload<'_, '_>) -> Result<Vec<u8>> {
+    if payload.inputs_count() > 1 {
+        let model_rel = payload.get_input(1)?;
+        let first = model_rel.iter()?.next().transpose()?;
+        return match first.as_ref().and_then(|t| t.last()) {
+            Some(DataValue::Bytes(b)) => Ok(b.clone()),
+            _ => bail!(OnnxError(
+                "the model relation must contain the model as bytes in its last column".to_string(),
+                model_rel.span()
+            )),
+        };
+    }
+    match payload.expr_option("model", None)?.eval_to_const()? {
+        DataValue::Str(path) => std::fs::read(path.as_str()).into_diagnostic(),
+        DataValue::Bytes(b) => Ok(b),
+        _ => bail!(WrongFixedRuleOptionError {
+            name: "model".to_string(),
+            span: payload.option_span("model")?,
+            rule_name: "OnnxScore".to_string(),
+            help: "'model' must be the path to the model, or the model as bytes".to_string()
+        }),
+    }
+}
+
+fn build_plan(
+    model: &[u8],
+    batch_size: usize,
+    n_features: usize,
+    span: SourceSpan,
+) -> Result<OnnxPlan> {
+    tract_onnx::onnx()
+        .model_for_read(&mut &model[..])
+        .and_then(|m| m.with_input_fact(0, f32::fact([batch_size, n_features]).into()))
+        .and_then(|m| m.into_optimized())
+        .and_then(|m| m.into_runnable())
+        .map_err(|err| OnnxError(err.to_string(), span).into())
+}
+
+fn score_batch(
+    plan: &OnnxPlan,
+    keys: &mut Vec<DataValue>,
+    features: &mut Vec<f32>,
+    batch_size: usize,
+    out: &mut RegularTempStore,
+    span: SourceSpan,
+) -> Result<()> {
+    let n_features = features.len() / batch_size;
+    let input = Tensor::from_shape(&[batch_size, n_features], &features[..])
+        .map_err(|err| OnnxError(err.to_string(), span))?;
+    features.clear();
+    let outputs = plan
+        .run(tvec!(input.into()))
+        .map_err(|err| OnnxError(err.to_string(), span))?;
+    let preds = outputs[0]
+        .cast_to::<f32>()
+        .map_err(|err| OnnxError(err.to_string(), span))?;
+    let preds = preds
+        .to_array_view::<f32>()
+        .map_err(|err| OnnxError(err.to_string(), span))?;
+    ensure!(
+        preds.ndim() >= 1 && preds.shape()[0] == batch_size,
+        OnnxError(
+            "the model must output one prediction per row".to_string(),
+            span
+        )
+    );
+    for (key, pred) in mem::take(keys).into_iter().zip(preds.outer_iter()) {
+        let pred = if pred.len() == 1 {
+            DataValue::from(*pred.iter().next().unwrap() as f64)
+        } else {
+            DataValue::Vec(Vector::F32(pred.iter().copied().collect()))
+        };
+        out.put(vec![key, pred]);
+    }
+    Ok(())
+}
@@ -0,0 +1,195 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::functions::OP_LIST;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Key {0} has different values for attribute {1}: {2} and {3}")]
+#[diagnostic(code(algo::pivot_conflict))]
+#[diagnostic(help("Aggregate the values of each key and attribute before pivoting"))]
+struct PivotConflict(String, DataValue, DataValue, DataValue);
+
+/// The constant values of the option `attributes`
+fn attributes(
+    rule_name: &str,
+    options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    span: SourceSpan,
+) -> Result<Vec<DataValue>> {
+    let bad = |help: &str| CannotDetermineArity(rule_name.to_string(), help.to_string(), span);
+    match options.get("attributes") {
+        None => bail!(bad("option 'attributes' not provided")),
+        Some(Expr::Const {
+            val: DataValue::List(l),
+            ..
+        }) => Ok(l.clone()),
+        Some(Expr::Apply { op, args, .. }) if **op == OP_LIST => args
+            .iter()
+            .map(|arg| {
+                arg.clone()
+                    .eval_to_const()
+                    .map_err(|_| bad("option 'attributes' must be a list of constants").into())
+            })
+            .try_collect(),
+        Some(_) => bail!(bad("option 'attributes' must be a list")),
+    }
+}
+
+/// How many columns at the start of the rows are the key, given by the option `keys`
+fn key_len(
+    rule_name: &str,
+    options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    span: SourceSpan,
+) -> Result<usize> {
+    match options.get("keys") {
+        None => Ok(1),
+        Some(expr) => match expr.clone().eval_to_const()?.get_non_neg_int() {
+            Some(n) => Ok(n as usize),
+            None => bail!(WrongFixedRuleOptionError {
+                name: "keys".to_string(),
+                span,
+                rule_name: rule_name.to_string(),
+                help: "must be a non-negative integer".to_string(),
+            }),
+        },
+    }
+}
+
+/// Turns rows of key, attribute and value into one wide row per key, with a column for
+/// each of the attributes given by the option `attributes`, in that order.
+///
+/// The key is made of the first `keys` columns (one by default). Rows whose attribute is
+/// not listed are ignored. Attributes a key has no row for take the value of the option
+/// `default`, null by default. Different values for the same key and attribute are an error.
+pub(crate) struct Pivot;
+
+impl FixedRule for Pivot {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let options = &payload.manifest.options;
+        let attrs = attributes(payload.name(), options, payload.span())?;
+        let keys = key_len(payload.name(), options, payload.span())?;
+        let default = payload
+            .expr_option(
+                "default",
+                Some(Expr::Const {
+                    val: DataValue::Null,
+                    span: payload.span(),
+                }),
+            )?
+            .eval_to_const()?;
+        let positions: BTreeMap<_, _> = attrs.iter().enumerate().map(|(i, a)| (a, i)).collect();
+
+        let input = payload.get_input(0)?.ensure_min_len(keys + 2)?;
+        let mut rows: BTreeMap<Vec<DataValue>, Vec<Option<DataValue>>> = BTreeMap::new();
+        for tuple in input.iter()? {
+            let tuple = tuple?;
+            let pos = match positions.get(&tuple[keys]) {
+                None => continue,
+                Some(pos) => *pos,
+            };
+            let row = rows
+                .entry(tuple[..keys].to_vec())
+                .or_insert_with(|| vec![None; attrs.len()]);
+            let value = &tuple[keys + 1];
+            match &row[pos] {
+                Some(existing) if existing != value => bail!(PivotConflict(
+                    tuple[..keys].iter().join(", "),
+                    tuple[keys].clone(),
+                    existing.clone(),
+                    value.clone()
+                )),
+                _ => row[pos] = Some(value.clone()),
+            }
+            poison.check()?;
+        }
+        for (key, values) in rows {
+            let mut tuple = key;
+            tuple.extend(
+                values
+                    .into_iter()
+                    .map(|v| v.unwrap_or_else(|| default.clone())),
+            );
+            out.put(tuple);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(key_len("Pivot", options, span)? + attributes("Pivot", options, span)?.len())
+    }
+}
+
+/// The reverse of [Pivot]: turns wide rows into rows of key, attribute and value.
+///
+/// The key is made of the first `keys` columns (one by default), followed by one column
+/// for each of the attributes given by the option `attributes`, in that order.
+/// Null values are left out, unless the option `keep_nulls` is true.
+pub(crate) struct Unpivot;
+
+impl FixedRule for Unpivot {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let options = &payload.manifest.options;
+        let attrs = attributes(payload.name(), options, payload.span())?;
+        let keys = key_len(payload.name(), options, payload.span())?;
+        let keep_nulls = payload.bool_option("keep_nulls", Some(false))?;
+
+        let input = payload.get_input(0)?.ensure_min_len(keys + attrs.len())?;
+        for tuple in input.iter()? {
+            let tuple = tuple?;
+            for (attr, value) in attrs.iter().zip(&tuple[keys..]) {
+                if *value == DataValue::Null && !keep_nulls {
+                    continue;
+                }
+                let mut row = tuple[..keys].to_vec();
+                row.push(attr.clone());
+                row.push(value.clone());
+                out.put(row);
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(key_len("Unpivot", options, span)? + 2)
+    }
+}
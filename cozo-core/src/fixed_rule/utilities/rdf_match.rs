@@ -0,0 +1,243 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::rdf::{Term, TurtleParser};
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Matches a basic graph pattern against RDF data loaded with `::rdf import`.
+///
+/// The inputs are the relation of triples and the relation of terms, and the `pattern` option
+/// holds triples in Turtle syntax with SPARQL variables, such as
+/// `?p a foaf:Person ; foaf:name ?name`. `PREFIX` declarations may precede the triples.
+/// A row is output for each match, holding the values of the variables in the order
+/// they first appear. Blank nodes in the pattern match anything and are not output.
+pub(crate) struct RdfMatch;
+
+enum Slot {
+    /// A term, by its columns in the relation of terms
+    Const(Vec<DataValue>),
+    Var(usize),
+}
+
+struct Pattern {
+    /// The variables output, by name
+    outputs: Vec<String>,
+    /// All variables, output ones first
+    n_vars: usize,
+    triples: Vec<[Slot; 3]>,
+    /// The constants of the pattern, by their columns in the relation of terms
+    constants: BTreeMap<Vec<DataValue>, DataValue>,
+}
+
+fn parse_pattern(src: &str) -> Result<Pattern> {
+    let triples = TurtleParser::new(src, true).parse()?;
+    let mut vars: Vec<String> = vec![];
+    for term in triples.iter().flatten() {
+        if let Term::Var(name) = term {
+            if !name.starts_with("_:") && !vars.contains(name) {
+                vars.push(name.clone());
+            }
+        }
+    }
+    let outputs = vars.clone();
+    for term in triples.iter().flatten() {
+        match term {
+            Term::Var(name) | Term::Blank(name) if !vars.contains(name) => vars.push(name.clone()),
+            _ => {}
+        }
+    }
+    let mut constants = BTreeMap::new();
+    let triples = triples
+        .into_iter()
+        .map(|triple| {
+            triple.map(|term| match term {
+                // blank nodes made for `[]` and collections are variables as well
+                Term::Var(name) | Term::Blank(name) => {
+                    Slot::Var(vars.iter().position(|v| *v == name).unwrap())
+                }
+                term => {
+                    let columns = term.columns();
+                    constants.entry(columns.clone()).or_insert(DataValue::Null);
+                    Slot::Const(columns)
+                }
+            })
+        })
+        .collect();
+    Ok(Pattern {
+        outputs,
+        n_vars: vars.len(),
+        triples,
+        constants,
+    })
+}
+
+fn pattern_option(options: &BTreeMap<SmartString<LazyCompact>, Expr>) -> Option<String> {
+    match options.get("pattern")?.clone().eval_to_const() {
+        Ok(DataValue::Str(s)) => Some(s.to_string()),
+        _ => None,
+    }
+}
+
+impl FixedRule for RdfMatch {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let triples = payload.get_input(0)?.ensure_min_len(3)?;
+        let terms = payload.get_input(1)?.ensure_min_len(3)?;
+        let mut pattern = parse_pattern(&payload.string_option("pattern", None)?)?;
+
+        if !pattern.constants.is_empty() {
+            for tuple in terms.iter()? {
+                let tuple = tuple?;
+                if let Some(id) = pattern.constants.get_mut(&tuple[1..]) {
+                    *id = tuple[0].clone();
+                }
+            }
+            if pattern.constants.values().any(|id| *id == DataValue::Null) {
+                return Ok(());
+            }
+        }
+        let resolve = |slot: &Slot, row: &[Option<DataValue>]| -> Option<DataValue> {
+            match slot {
+                Slot::Const(columns) => Some(pattern.constants[columns].clone()),
+                Slot::Var(idx) => row[*idx].clone(),
+            }
+        };
+
+        // matching the most constrained triple next, those with a known subject first
+        let mut bound = vec![false; pattern.n_vars];
+        let mut remaining = (0..pattern.triples.len()).collect_vec();
+        let mut rows: Vec<Vec<Option<DataValue>>> = vec![vec![None; pattern.n_vars]];
+        let mut scanned: BTreeMap<usize, Vec<Tuple>> = BTreeMap::new();
+        while !remaining.is_empty() {
+            let is_bound = |slot: &Slot| match slot {
+                Slot::Const(_) => true,
+                Slot::Var(idx) => bound[*idx],
+            };
+            let (pos, &next) = remaining
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, idx)| {
+                    let triple = &pattern.triples[**idx];
+                    (
+                        is_bound(&triple[0]),
+                        triple.iter().filter(|s| is_bound(s)).count(),
+                    )
+                })
+                .unwrap();
+            remaining.remove(pos);
+            let triple = &pattern.triples[next];
+            let subject_bound = is_bound(&triple[0]);
+
+            let mut new_rows = vec![];
+            for row in rows {
+                let candidates: Vec<Tuple> = if subject_bound {
+                    let subject = resolve(&triple[0], &row).unwrap();
+                    triples.prefix_iter(&subject)?.try_collect()?
+                } else {
+                    if let Entry::Vacant(entry) = scanned.entry(next) {
+                        let mut found = vec![];
+                        for tuple in triples.iter()? {
+                            let tuple = tuple?;
+                            let matches = triple.iter().zip(&tuple).all(|(slot, val)| match slot {
+                                Slot::Const(_) => resolve(slot, &row).as_ref() == Some(val),
+                                Slot::Var(_) => true,
+                            });
+                            if matches {
+                                found.push(tuple);
+                            }
+                        }
+                        entry.insert(found);
+                    }
+                    scanned[&next].clone()
+                };
+                'candidates: for tuple in candidates {
+                    let mut new_row = row.clone();
+                    for (slot, val) in triple.iter().zip(&tuple) {
+                        match slot {
+                            Slot::Var(idx) if new_row[*idx].is_none() => {
+                                new_row[*idx] = Some(val.clone())
+                            }
+                            slot => {
+                                if resolve(slot, &new_row).as_ref() != Some(val) {
+                                    continue 'candidates;
+                                }
+                            }
+                        }
+                    }
+                    new_rows.push(new_row);
+                }
+                poison.check()?;
+            }
+            rows = new_rows;
+            for slot in triple {
+                if let Slot::Var(idx) = slot {
+                    bound[*idx] = true;
+                }
+            }
+        }
+
+        let mut values: BTreeMap<DataValue, DataValue> = BTreeMap::new();
+        for row in rows {
+            let mut tuple = Vec::with_capacity(pattern.outputs.len());
+            for id in row.into_iter().take(pattern.outputs.len()) {
+                let id = id.unwrap();
+                if !values.contains_key(&id) {
+                    let value = match terms.prefix_iter(&id)?.next() {
+                        Some(term) => term?[2].clone(),
+                        None => bail!("The term {id} is missing from the relation of terms"),
+                    };
+                    values.insert(id.clone(), value);
+                }
+                tuple.push(values[&id].clone());
+            }
+            out.put(tuple);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        let pattern = pattern_option(options).ok_or_else(|| {
+            CannotDetermineArity(
+                "RdfMatch".to_string(),
+                "the option 'pattern' must be a string".to_string(),
+                span,
+            )
+        })?;
+        Ok(parse_pattern(&pattern)?.outputs.len())
+    }
+
+    fn output_columns(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(parse_pattern(&pattern_option(options)?).ok()?.outputs)
+    }
+}
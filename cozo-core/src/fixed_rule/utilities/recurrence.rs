@@ -0,0 +1,285 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, Duration, Weekday};
+use chrono_tz::Tz;
+use miette::{bail, ensure, miette, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::functions::{micros_to_datetime, parse_weekday, resolve_local_time};
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bad recurrence rule '{0}': {1}")]
+#[diagnostic(code(algo::bad_recurrence_rule))]
+#[diagnostic(help("Rules are written like 'FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE;COUNT=10'"))]
+struct BadRecurrenceRule(String, String);
+
+/// A recurrence rule, written as a subset of the `RRULE` of RFC 5545
+struct Recurrence {
+    weekly: bool,
+    interval: i64,
+    by_day: Vec<Weekday>,
+    count: Option<u64>,
+}
+
+impl Recurrence {
+    fn parse(rule: &str) -> Result<Self> {
+        let bad = |msg: &str| BadRecurrenceRule(rule.to_string(), msg.to_string());
+        let mut weekly = None;
+        let mut ret = Recurrence {
+            weekly: false,
+            interval: 1,
+            by_day: vec![],
+            count: None,
+        };
+        let parts = rule.trim().trim_start_matches("RRULE:").split(';');
+        for part in parts.filter(|p| !p.trim().is_empty()) {
+            let (key, val) = part
+                .split_once('=')
+                .ok_or_else(|| bad("parts must be written as KEY=VALUE"))?;
+            let val = val.trim();
+            match key.trim().to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    weekly = Some(match val.to_ascii_uppercase().as_str() {
+                        "DAILY" => false,
+                        "WEEKLY" => true,
+                        _ => bail!(bad("only DAILY and WEEKLY frequencies are supported")),
+                    })
+                }
+                "INTERVAL" => {
+                    ret.interval = val
+                        .parse()
+                        .ok()
+                        .filter(|i| *i > 0)
+                        .ok_or_else(|| bad("INTERVAL must be a positive integer"))?
+                }
+                "COUNT" => {
+                    ret.count = Some(
+                        val.parse()
+                            .map_err(|_| bad("COUNT must be a non-negative integer"))?,
+                    )
+                }
+                "BYDAY" => {
+                    for day in val.split(',') {
+                        let day = parse_weekday(day).ok_or_else(|| {
+                            bad("days must be written as MO, TU, WE, TH, FR, SA or SU")
+                        })?;
+                        ret.by_day.push(day);
+                    }
+                }
+                _ => bail!(bad("only FREQ, INTERVAL, COUNT and BYDAY are supported")),
+            }
+        }
+        ret.weekly = weekly.ok_or_else(|| bad("FREQ is required"))?;
+        ret.by_day.sort_by_key(|d| d.num_days_from_monday());
+        ret.by_day.dedup();
+        Ok(ret)
+    }
+
+    /// Call `emit` with the start of every occurrence overlapping `from..to`, in microseconds.
+    /// The occurrences keep the wall clock time of `first` in its timezone,
+    /// and none starts at or after `until`.
+    fn expand(
+        &self,
+        first: DateTime<Tz>,
+        duration: i64,
+        until: i64,
+        from: i64,
+        to: i64,
+        poison: &Poison,
+        mut emit: impl FnMut(i64),
+    ) -> Result<()> {
+        let tz = first.timezone();
+        let local = first.naive_local();
+        let (first_date, time) = (local.date(), local.time());
+        let (base, period_days, offsets) = if self.weekly {
+            let monday = first_date.weekday().num_days_from_monday();
+            let offsets = if self.by_day.is_empty() {
+                vec![monday]
+            } else {
+                self.by_day
+                    .iter()
+                    .map(|d| d.num_days_from_monday())
+                    .collect()
+            };
+            let base = first_date - Duration::days(monday as i64);
+            (base, 7 * self.interval, offsets)
+        } else {
+            (first_date, self.interval, vec![0])
+        };
+        let end = until.min(to);
+        let last_date = to_datetime(end, &tz)?.date_naive() + Duration::days(1);
+
+        let mut period = 0;
+        if self.count.is_none() {
+            // periods ending well before the window hold no occurrence reaching into it
+            let skip_to = to_datetime(from.saturating_sub(duration), &tz)?.date_naive();
+            period = ((skip_to - base).num_days() - 1).max(0) / period_days;
+        }
+        let mut seen = 0;
+        loop {
+            let period_start = base + Duration::days(period * period_days);
+            if period_start > last_date {
+                return Ok(());
+            }
+            for offset in &offsets {
+                let date = period_start + Duration::days(*offset as i64);
+                if date < first_date
+                    || (!self.weekly
+                        && !self.by_day.is_empty()
+                        && !self.by_day.contains(&date.weekday()))
+                {
+                    continue;
+                }
+                let start = match resolve_local_time(&tz, date.and_time(time)) {
+                    None => continue,
+                    Some(dt) => dt.timestamp_micros(),
+                };
+                if start >= end {
+                    return Ok(());
+                }
+                if let Some(count) = self.count {
+                    if seen >= count {
+                        return Ok(());
+                    }
+                    seen += 1;
+                }
+                if start >= from || start + duration > from {
+                    emit(start);
+                }
+            }
+            period += 1;
+            poison.check()?;
+        }
+    }
+}
+
+fn to_datetime(micros: i64, tz: &Tz) -> Result<DateTime<Tz>> {
+    micros_to_datetime(micros)
+        .map(|dt| dt.with_timezone(tz))
+        .ok_or_else(|| miette!("Timestamp {} is out of range", micros as f64 / 1e6))
+}
+
+fn secs_to_micros(secs: f64) -> i64 {
+    (secs * 1e6).round() as i64
+}
+
+fn to_micros(v: &DataValue) -> Result<i64> {
+    let secs = v
+        .get_float()
+        .ok_or_else(|| miette!("A timestamp must be a number, got {}", v))?;
+    Ok(secs_to_micros(secs))
+}
+
+/// Expands recurrence definitions into the occurrences falling in a window.
+///
+/// The input holds rows of id, rule, timezone, and the start and end of the first occurrence,
+/// optionally followed by a timestamp at or after which no occurrence starts.
+/// Rules are written as a subset of the `RRULE` of RFC 5545, for example
+/// `'FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE;COUNT=10'`. Occurrences keep the wall clock time
+/// of the first one in the timezone, which is UTC when null.
+///
+/// The options `from` and `to` give the window. For each occurrence overlapping it,
+/// the output row holds the id, and the start and end of the occurrence.
+pub(crate) struct ExpandRecurrences;
+
+impl FixedRule for ExpandRecurrences {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let defs = payload.get_input(0)?.ensure_min_len(5)?;
+        let from = secs_to_micros(payload.float_option("from", None)?);
+        let to = secs_to_micros(payload.float_option("to", None)?);
+
+        let mut rules: BTreeMap<String, Recurrence> = BTreeMap::new();
+        let mut zones: BTreeMap<String, Tz> = BTreeMap::new();
+        for tuple in defs.iter()? {
+            let tuple = tuple?;
+            let id = &tuple[0];
+            let rule = tuple[1]
+                .get_str()
+                .ok_or_else(|| miette!("The rule of {} must be a string", id))?;
+            let rule = match rules.entry(rule.to_string()) {
+                Entry::Occupied(ent) => ent.into_mut(),
+                Entry::Vacant(ent) => ent.insert(Recurrence::parse(rule)?),
+            };
+            let tz = match &tuple[2] {
+                DataValue::Null => Tz::UTC,
+                DataValue::Str(name) => match zones.entry(name.to_string()) {
+                    Entry::Occupied(ent) => *ent.get(),
+                    Entry::Vacant(ent) => *ent.insert(
+                        name.parse::<Tz>()
+                            .map_err(|_| miette!("bad timezone specification: {}", name))?,
+                    ),
+                },
+                v => bail!("The timezone of {} must be a string, got {}", id, v),
+            };
+            let start = to_micros(&tuple[3])?;
+            let duration = to_micros(&tuple[4])? - start;
+            ensure!(
+                duration >= 0,
+                "The occurrences of {} end before they start",
+                id
+            );
+            let until = match tuple.get(5) {
+                None | Some(DataValue::Null) => i64::MAX,
+                Some(v) => to_micros(v)?,
+            };
+            rule.expand(
+                to_datetime(start, &tz)?,
+                duration,
+                until,
+                from,
+                to,
+                &poison,
+                |start| {
+                    out.put(vec![
+                        id.clone(),
+                        DataValue::from(start as f64 / 1e6),
+                        DataValue::from((start + duration) as f64 / 1e6),
+                    ])
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(vec![
+            "id".to_string(),
+            "start".to_string(),
+            "end".to_string(),
+        ])
+    }
+}
@@ -0,0 +1,118 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::functions::op_sparse_vec;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, SparseVector};
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Scores the queries `[query, vector]` of the second input relation against the documents
+/// `[key, vector]` of the first one by dot product of their sparse vectors,
+/// outputting the `k` best documents of each query as rows of `[query, key, score]`.
+///
+/// The documents are put in an inverted index first, so that each query
+/// only visits the documents sharing a non-zero dimension with it.
+pub(crate) struct SparseSearch;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot interpret {0} as a sparse vector")]
+#[diagnostic(code(fixed_rule::not_a_sparse_vector))]
+#[diagnostic(help("The last column must be a sparse vector, or a value accepted by 'sparse_vec'"))]
+struct NotSparseVectorError(DataValue, #[label] SourceSpan);
+
+fn sparse_of(tuple: &Tuple, span: SourceSpan) -> Result<SparseVector> {
+    let last = tuple.last().unwrap();
+    match op_sparse_vec(&[last.clone()]) {
+        Ok(DataValue::Sparse(s)) => Ok(s),
+        _ => Err(NotSparseVectorError(last.clone(), span).into()),
+    }
+}
+
+impl FixedRule for SparseSearch {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let docs = payload.get_input(0)?.ensure_min_len(2)?;
+        let queries = payload.get_input(1)?.ensure_min_len(2)?;
+        let k = payload.pos_integer_option("k", Some(10))?;
+
+        let mut keys = vec![];
+        let mut postings: BTreeMap<u32, Vec<(usize, f32)>> = BTreeMap::new();
+        for tuple in docs.iter()? {
+            let tuple = tuple?;
+            let v = sparse_of(&tuple, docs.span())?;
+            for (idx, val) in v.iter() {
+                postings.entry(idx).or_default().push((keys.len(), val));
+            }
+            keys.push(tuple.into_iter().next().unwrap());
+            poison.check()?;
+        }
+
+        for tuple in queries.iter()? {
+            let tuple = tuple?;
+            let q = sparse_of(&tuple, queries.span())?;
+            let mut scores: BTreeMap<usize, f64> = BTreeMap::new();
+            for (idx, val) in q.iter() {
+                if let Some(docs) = postings.get(&idx) {
+                    for (doc, doc_val) in docs {
+                        *scores.entry(*doc).or_default() += val as f64 * *doc_val as f64;
+                    }
+                }
+            }
+            let query = tuple.into_iter().next().unwrap();
+            let best = scores
+                .into_iter()
+                .sorted_by(|(_, a), (_, b)| b.total_cmp(a))
+                .take(k);
+            for (doc, score) in best {
+                out.put(vec![
+                    query.clone(),
+                    keys[doc].clone(),
+                    DataValue::from(score),
+                ]);
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(vec![
+            "query".to_string(),
+            "key".to_string(),
+            "score".to_string(),
+        ])
+    }
+}
@@ -0,0 +1,123 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::functions::trigrams;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Fuzzy matches the queries `[query, text]` of the second input relation against the
+/// names `[key, text]` of the first one, outputting for each query the `k` best names
+/// whose `trigram_similarity` is at least `threshold` as rows of `[query, key, similarity]`.
+///
+/// The trigrams of the names are put in an inverted index first, so that each query
+/// only visits the names sharing a trigram with it.
+pub(crate) struct TrigramSearch;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The value {0:?} to be matched is not a string")]
+#[diagnostic(code(fixed_rule::trigram_not_a_string))]
+#[diagnostic(help("The last column of the inputs of TrigramSearch must hold strings"))]
+struct NotTextError(DataValue, #[label] SourceSpan);
+
+fn text_of(tuple: &Tuple, span: SourceSpan) -> Result<&str> {
+    let last = tuple.last().unwrap();
+    match last.get_str() {
+        Some(s) => Ok(s),
+        None => bail!(NotTextError(last.clone(), span)),
+    }
+}
+
+impl FixedRule for TrigramSearch {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let names = payload.get_input(0)?.ensure_min_len(2)?;
+        let queries = payload.get_input(1)?.ensure_min_len(2)?;
+        let k = payload.pos_integer_option("k", Some(10))?;
+        let threshold = payload.unit_interval_option("threshold", Some(0.3))?;
+
+        let mut keys = vec![];
+        let mut sizes = vec![];
+        let mut postings: BTreeMap<[char; 3], Vec<usize>> = BTreeMap::new();
+        for tuple in names.iter()? {
+            let tuple = tuple?;
+            let grams = trigrams(text_of(&tuple, names.span())?);
+            for gram in &grams {
+                postings.entry(*gram).or_default().push(keys.len());
+            }
+            sizes.push(grams.len());
+            keys.push(tuple.into_iter().next().unwrap());
+            poison.check()?;
+        }
+
+        for tuple in queries.iter()? {
+            let tuple = tuple?;
+            let grams = trigrams(text_of(&tuple, queries.span())?);
+            let mut shared: BTreeMap<usize, usize> = BTreeMap::new();
+            for gram in &grams {
+                if let Some(names) = postings.get(gram) {
+                    for name in names {
+                        *shared.entry(*name).or_default() += 1;
+                    }
+                }
+            }
+            let query = tuple.into_iter().next().unwrap();
+            let best = shared
+                .into_iter()
+                .map(|(name, n)| (name, n as f64 / (grams.len() + sizes[name] - n) as f64))
+                .filter(|(_, sim)| *sim >= threshold)
+                .sorted_by(|(_, a), (_, b)| b.total_cmp(a))
+                .take(k);
+            for (name, sim) in best {
+                out.put(vec![
+                    query.clone(),
+                    keys[name].clone(),
+                    DataValue::from(sim),
+                ]);
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+
+    fn output_columns(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    ) -> Option<Vec<String>> {
+        Some(vec![
+            "query".to_string(),
+            "key".to_string(),
+            "similarity".to_string(),
+        ])
+    }
+}
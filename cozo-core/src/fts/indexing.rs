@@ -13,22 +13,28 @@ use crate::data::value::LARGEST_UTF_CHAR;
 use crate::fts::ast::{FtsExpr, FtsLiteral, FtsNear};
 use crate::fts::tokenizer::TextAnalyzer;
 use crate::parse::fts::parse_fts_query;
+use crate::runtime::degraded::{compile_index_expr, InconsistentIndex};
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, SourceSpan};
 use itertools::Itertools;
-use miette::{bail, miette, Diagnostic, Result};
+use miette::{bail, Diagnostic, Result};
 use ordered_float::OrderedFloat;
 use rustc_hash::{FxHashMap, FxHashSet};
 use smartstring::{LazyCompact, SmartString};
 use std::cmp::Reverse;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use thiserror::Error;
 
+/// The positions of every token in every row of a relation, by token and then by key
+type ScannedPostings = BTreeMap<SmartString<LazyCompact>, Vec<(Tuple, Vec<u32>)>>;
+
 #[derive(Default)]
 pub(crate) struct FtsCache {
     total_n_cache: FxHashMap<SmartString<LazyCompact>, usize>,
+    /// Built for an index that cannot be read, see [FtsSearch::scan_base]
+    scanned: Option<ScannedPostings>,
 }
 
 impl FtsCache {
@@ -59,11 +65,68 @@ struct LiteralStats {
 }
 
 impl<'a> SessionTx<'a> {
+    /// Tokenize the rows of the relation as they would be indexed
+    fn fts_scan_postings(
+        &self,
+        config: &FtsSearch,
+        tokenizer: &TextAnalyzer,
+    ) -> Result<ScannedPostings> {
+        let extractor = compile_index_expr(&config.base_handle, &config.manifest.extractor)?;
+        let n_keys = config.base_handle.metadata.keys.len();
+        let mut stack = vec![];
+        let mut postings: ScannedPostings = BTreeMap::new();
+        for tuple in config.base_handle.scan_all(self) {
+            let tuple = tuple?;
+            let to_index = match eval_bytecode(&extractor, &tuple, &mut stack)? {
+                DataValue::Str(s) => s,
+                _ => continue,
+            };
+            let mut collector: FxHashMap<_, Vec<u32>> = FxHashMap::default();
+            let mut token_stream = tokenizer.token_stream(&to_index);
+            while let Some(token) = token_stream.next() {
+                let text = SmartString::<LazyCompact>::from(&token.text);
+                collector
+                    .entry(text)
+                    .or_default()
+                    .push(token.position as u32);
+            }
+            for (text, positions) in collector {
+                postings
+                    .entry(text)
+                    .or_default()
+                    .push((tuple[..n_keys].to_vec(), positions));
+            }
+        }
+        Ok(postings)
+    }
     fn fts_search_literal(
         &self,
         literal: &FtsLiteral,
         idx_handle: &RelationHandle,
+        scanned: Option<&ScannedPostings>,
     ) -> Result<Vec<LiteralStats>> {
+        if let Some(postings) = scanned {
+            let mut results = vec![];
+            for (text, found) in postings.range(literal.value.clone()..) {
+                if literal.is_prefix {
+                    if !text.starts_with(&literal.value as &str) {
+                        break;
+                    }
+                } else if *text != literal.value {
+                    break;
+                }
+                for (key, positions) in found {
+                    results.push(LiteralStats {
+                        key: key.clone(),
+                        position_info: positions
+                            .iter()
+                            .map(|p| PositionInfo { position: *p })
+                            .collect_vec(),
+                    });
+                }
+            }
+            return Ok(results);
+        }
         let start_key_str = &literal.value as &str;
         let start_key = vec![DataValue::Str(SmartString::from(start_key_str))];
         let mut end_key_str = literal.value.clone();
@@ -112,11 +175,12 @@ impl<'a> SessionTx<'a> {
         ast: &FtsExpr,
         config: &FtsSearch,
         n: usize,
+        scanned: Option<&ScannedPostings>,
     ) -> Result<FxHashMap<Tuple, f64>> {
         Ok(match ast {
             FtsExpr::Literal(l) => {
                 let mut res = FxHashMap::default();
-                let found_docs = self.fts_search_literal(l, &config.idx_handle)?;
+                let found_docs = self.fts_search_literal(l, &config.idx_handle, scanned)?;
                 let found_docs_len = found_docs.len();
                 for el in found_docs {
                     let score = Self::fts_compute_score(
@@ -132,13 +196,9 @@ impl<'a> SessionTx<'a> {
             }
             FtsExpr::And(ls) => {
                 let mut l_iter = ls.iter();
-                let mut res = self.fts_search_impl(
-                    l_iter.next().unwrap(),
-                    config,
-                    n,
-                )?;
+                let mut res = self.fts_search_impl(l_iter.next().unwrap(), config, n, scanned)?;
                 for nxt in l_iter {
-                    let nxt_res = self.fts_search_impl(nxt, config, n)?;
+                    let nxt_res = self.fts_search_impl(nxt, config, n, scanned)?;
                     res = res
                         .into_iter()
                         .filter_map(|(k, v)| nxt_res.get(&k).map(|nxt_v| (k, v + nxt_v)))
@@ -149,7 +209,7 @@ impl<'a> SessionTx<'a> {
             FtsExpr::Or(ls) => {
                 let mut res: FxHashMap<Tuple, f64> = FxHashMap::default();
                 for nxt in ls {
-                    let nxt_res = self.fts_search_impl(nxt, config, n)?;
+                    let nxt_res = self.fts_search_impl(nxt, config, n, scanned)?;
                     for (k, v) in nxt_res {
                         if let Some(old_v) = res.get_mut(&k) {
                             *old_v = (*old_v).max(v);
@@ -163,7 +223,9 @@ impl<'a> SessionTx<'a> {
             FtsExpr::Near(FtsNear { literals, distance }) => {
                 let mut l_it = literals.iter();
                 let mut coll: FxHashMap<_, _> = FxHashMap::default();
-                for first_el in self.fts_search_literal(l_it.next().unwrap(), &config.idx_handle)? {
+                for first_el in
+                    self.fts_search_literal(l_it.next().unwrap(), &config.idx_handle, scanned)?
+                {
                     coll.insert(
                         first_el.key,
                         first_el
@@ -174,7 +236,7 @@ impl<'a> SessionTx<'a> {
                     );
                 }
                 for lit_nxt in literals {
-                    let el_res = self.fts_search_literal(lit_nxt, &config.idx_handle)?;
+                    let el_res = self.fts_search_literal(lit_nxt, &config.idx_handle, scanned)?;
                     coll = el_res
                         .into_iter()
                         .filter_map(|x| match coll.remove(&x.key) {
@@ -217,11 +279,8 @@ impl<'a> SessionTx<'a> {
                     .collect()
             }
             FtsExpr::Not(fst, snd) => {
-                let mut res = self.fts_search_impl(fst, config, n)?;
-                for el in self
-                    .fts_search_impl(snd, config, n)?
-                    .keys()
-                {
+                let mut res = self.fts_search_impl(fst, config, n, scanned)?;
+                for el in self.fts_search_impl(snd, config, n, scanned)?.keys() {
                     res.remove(el);
                 }
                 res
@@ -263,8 +322,11 @@ impl<'a> SessionTx<'a> {
         } else {
             0
         };
+        if config.scan_base && cache.scanned.is_none() {
+            cache.scanned = Some(self.fts_scan_postings(config, tokenizer)?);
+        }
         let mut result: Vec<_> = self
-            .fts_search_impl(&ast, config, n)?
+            .fts_search_impl(&ast, config, n, cache.scanned.as_ref())?
             .into_iter()
             .collect();
         result.sort_by_key(|(_, score)| Reverse(OrderedFloat(*score)));
@@ -274,10 +336,12 @@ impl<'a> SessionTx<'a> {
 
         let mut ret = Vec::with_capacity(config.k);
         for (found_key, score) in result {
-            let mut cand_tuple = config
-                .base_handle
-                .get(self, &found_key)?
-                .ok_or_else(|| miette!("corrupted index"))?;
+            let mut cand_tuple = config.base_handle.get(self, &found_key)?.ok_or_else(|| {
+                InconsistentIndex(
+                    config.idx_handle.name.to_string(),
+                    format!("cannot find the indexed row {:?}", found_key),
+                )
+            })?;
 
             if config.bind_score.is_some() {
                 cand_tuple.push(DataValue::from(score));
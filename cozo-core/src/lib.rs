@@ -33,9 +33,14 @@
 
 use std::collections::BTreeMap;
 use std::path::Path;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
 #[allow(unused_imports)]
 use std::time::Instant;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crossbeam::channel::RecvTimeoutError;
 use crossbeam::channel::{bounded, Receiver, Sender};
 use lazy_static::lazy_static;
 pub use miette::Error;
@@ -48,12 +53,21 @@ use miette::{
 use serde_json::json;
 
 pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+#[cfg(feature = "arrow")]
+pub use runtime::arrow::ArrowFormat;
 pub use fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
+pub use runtime::columns::ColumnInfo;
 pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
+pub use runtime::prepared::PreparedQuery;
+pub use runtime::profile::ResourceProfile;
 pub use runtime::relation::decode_tuple_from_kv;
+pub use runtime::stream::QueryStream;
 pub use runtime::temp_store::RegularTempStore;
-pub use storage::mem::{new_cozo_mem, MemStorage};
+#[cfg(not(target_arch = "wasm32"))]
+pub use runtime::worker_pool::DbFuture;
+pub use storage::conformance;
+pub use storage::mem::{new_cozo_mem, new_cozo_mem_journaled, MemChanges, MemStorage};
 #[cfg(feature = "storage-rocksdb")]
 pub use storage::rocks::{new_cozo_rocksdb, RocksDbStorage};
 #[cfg(feature = "storage-sled")]
@@ -62,28 +76,44 @@ pub use storage::sled::{new_cozo_sled, SledStorage};
 pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
 #[cfg(feature = "storage-tikv")]
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
-pub use storage::{Storage, StoreTx};
+#[cfg(feature = "serve")]
+pub use serve::{
+    protocol_routes, ServeAddr, ServeOptions, ServeRole, ServeSession, ServeState, ServerHandle,
+    IDEMPOTENCY_KEY,
+};
+pub use storage::{PartitionScheme, Storage, StorageStats, StoreTx};
 
 pub use crate::data::expr::Expr;
 use crate::data::json::JsonValue;
 pub use crate::data::symb::Symbol;
-pub use crate::data::value::{JsonData, Vector};
+pub use crate::data::value::{JsonData, Matrix, SparseVector, Vector};
 pub use crate::fixed_rule::SimpleFixedRule;
 pub use crate::parse::SourceSpan;
 pub use crate::runtime::callback::CallbackOp;
 pub use crate::runtime::db::evaluate_expressions;
+pub use crate::runtime::db::ConfigReloader;
 pub use crate::runtime::db::get_variables;
+pub use crate::runtime::db::to_params;
 pub use crate::runtime::db::Poison;
+pub use crate::runtime::db::ProgressReport;
 pub use crate::runtime::db::ScriptMutability;
 pub use crate::runtime::db::Payload;
 pub use crate::runtime::db::TransactionPayload;
+#[cfg(feature = "requests")]
+pub use crate::runtime::encryption::HttpKeyProvider;
+pub use crate::runtime::encryption::KeyProvider;
+pub use crate::runtime::rerank::Reranker;
 
+pub mod api;
+pub mod builder;
 pub(crate) mod data;
 pub(crate) mod fixed_rule;
 pub(crate) mod fts;
 pub(crate) mod parse;
 pub(crate) mod query;
 pub(crate) mod runtime;
+#[cfg(feature = "serve")]
+pub(crate) mod serve;
 pub(crate) mod storage;
 pub(crate) mod utils;
 
@@ -134,18 +164,58 @@ impl DbInstance {
     /// some of the engines are available. The `mem` engine is always available.
     ///
     /// `path` is ignored for `mem` and `tikv` engines.
-    /// `options` is ignored for every engine except `tikv`.
+    ///
+    /// The `sqlite`, `rocksdb` and `sled` engines lock the database against being opened
+    /// by another process at the same time. Pass the options `{"read_only": true}`
+    /// to open an existing database without writing to it: the lock is skipped and all
+    /// writes are refused. Only `sqlite` databases can then be read while another process
+    /// has them open. The RocksDB and Sled libraries lock the database themselves, so
+    /// opening one of their databases read-only still fails while it is in use.
+    /// `tikv` requires its own options, and ignores the above.
+    ///
+    /// For all engines, `max_result_rows` and `max_result_bytes` may be given in the options
+    /// to limit the size of query results, see [Db::set_result_limits], and `profile`
+    /// may be `"full"` or `"lite"`, see [ResourceProfile].
+    /// With the `requests` feature, `key_url` and optionally `key_token` give the
+    /// key management service the keys of encrypted columns are fetched from,
+    /// see [HttpKeyProvider].
     #[allow(unused_variables)]
     pub fn new(engine: &str, path: impl AsRef<Path>, options: &str) -> Result<Self> {
         let options = if options.is_empty() { "{}" } else { options };
-        Ok(match engine {
+        #[derive(serde_derive::Deserialize)]
+        struct CommonOpts {
+            max_result_rows: Option<usize>,
+            max_result_bytes: Option<usize>,
+            profile: Option<String>,
+            #[cfg(feature = "requests")]
+            key_url: Option<String>,
+            #[cfg(feature = "requests")]
+            key_token: Option<String>,
+        }
+        let common_opts: CommonOpts = serde_json::from_str(options).into_diagnostic()?;
+        #[derive(serde_derive::Deserialize)]
+        struct FileOpts {
+            #[serde(default)]
+            read_only: bool,
+        }
+        let file_opts = || -> Result<FileOpts> { serde_json::from_str(options).into_diagnostic() };
+        let db = match engine {
             "mem" => Self::Mem(new_cozo_mem()?),
             #[cfg(feature = "storage-sqlite")]
-            "sqlite" => Self::Sqlite(new_cozo_sqlite(path)?),
+            "sqlite" => Self::Sqlite(storage::sqlite::open_cozo_sqlite(
+                path,
+                file_opts()?.read_only,
+            )?),
             #[cfg(feature = "storage-rocksdb")]
-            "rocksdb" => Self::RocksDb(new_cozo_rocksdb(path)?),
+            "rocksdb" => Self::RocksDb(storage::rocks::open_cozo_rocksdb(
+                path,
+                file_opts()?.read_only,
+            )?),
             #[cfg(feature = "storage-sled")]
-            "sled" => Self::Sled(new_cozo_sled(path)?),
+            "sled" => Self::Sled(storage::sled::open_cozo_sled(
+                path,
+                file_opts()?.read_only,
+            )?),
             #[cfg(feature = "storage-tikv")]
             "tikv" => {
                 #[derive(serde_derive::Deserialize)]
@@ -160,7 +230,19 @@ impl DbInstance {
                 "database engine '{}' not supported (maybe not compiled in)",
                 k
             ),
-        })
+        };
+        db.set_result_limits(common_opts.max_result_rows, common_opts.max_result_bytes);
+        if let Some(name) = &common_opts.profile {
+            db.set_resource_profile(ResourceProfile::by_name(name)?);
+        }
+        #[cfg(feature = "requests")]
+        if let Some(url) = &common_opts.key_url {
+            db.set_key_provider(Some(Arc::new(HttpKeyProvider::new(
+                url,
+                common_opts.key_token.as_deref(),
+            ))));
+        }
+        Ok(db)
     }
     /// Same as [Self::new], but inputs and error messages are all in strings
     pub fn new_with_str(
@@ -177,7 +259,9 @@ impl DbInstance {
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
     ) -> Result<NamedRows> {
-        match self {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.accept_jobs();
+        let res = match self {
             DbInstance::Mem(db) => db.run_script(payload, params, mutability),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.run_script(payload, params, mutability),
@@ -187,6 +271,329 @@ impl DbInstance {
             DbInstance::Sled(db) => db.run_script(payload, params, mutability),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.run_script(payload, params, mutability),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        self.spawn_job_worker();
+        res
+    }
+    /// Dispatcher method. See [crate::Db::run_script_as].
+    pub fn run_script_as(
+        &self,
+        actor: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.accept_jobs();
+        let res = match self {
+            DbInstance::Mem(db) => db.run_script_as(actor, payload, params, mutability),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_as(actor, payload, params, mutability),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_as(actor, payload, params, mutability),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_as(actor, payload, params, mutability),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_as(actor, payload, params, mutability),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        self.spawn_job_worker();
+        res
+    }
+    /// Dispatcher method. See [crate::Db::prepare].
+    pub fn prepare(&self, payload: &str) -> Result<PreparedQuery> {
+        match self {
+            DbInstance::Mem(db) => db.prepare(payload),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.prepare(payload),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.prepare(payload),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.prepare(payload),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.prepare(payload),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_prepared].
+    pub fn run_prepared(
+        &self,
+        prepared: &PreparedQuery,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.accept_jobs();
+        let res = match self {
+            DbInstance::Mem(db) => db.run_prepared(prepared, params, mutability),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_prepared(prepared, params, mutability),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_prepared(prepared, params, mutability),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_prepared(prepared, params, mutability),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_prepared(prepared, params, mutability),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        self.spawn_job_worker();
+        res
+    }
+    /// Dispatcher method. See [crate::Db::run_readonly].
+    pub fn run_readonly(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.run_readonly(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_readonly(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_readonly(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_readonly(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_readonly(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_script_streaming].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run_script_streaming(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        buffer: usize,
+    ) -> Result<QueryStream> {
+        match self {
+            DbInstance::Mem(db) => db.run_script_streaming(payload, params, mutability, buffer),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_streaming(payload, params, mutability, buffer),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_script_streaming(payload, params, mutability, buffer)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_streaming(payload, params, mutability, buffer),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_streaming(payload, params, mutability, buffer),
+        }
+    }
+    /// A clone whose scripts are killed along with `poison`
+    #[cfg(not(target_arch = "wasm32"))]
+    fn with_job(&self, poison: Poison) -> Self {
+        match self {
+            DbInstance::Mem(db) => DbInstance::Mem(db.with_job(poison)),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => DbInstance::Sqlite(db.with_job(poison)),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => DbInstance::RocksDb(db.with_job(poison)),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => DbInstance::Sled(db.with_job(poison)),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => DbInstance::TiKv(db.with_job(poison)),
+        }
+    }
+    /// Make the call `f` on the worker pool of the async methods
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn_async<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(DbInstance) -> Result<T> + Send + 'static,
+    ) -> DbFuture<T> {
+        let poison = Poison::default();
+        let db = self.with_job(poison.clone());
+        runtime::worker_pool::spawn_on_pool(poison, move || f(db))
+    }
+    /// [DbInstance::run_script] made on a pool of worker threads, for async code.
+    /// See [DbFuture] for how the script is killed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run_script_async(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> DbFuture<NamedRows> {
+        let payload = payload.to_string();
+        self.spawn_async(move |db| db.run_script(&payload, params, mutability))
+    }
+    /// [DbInstance::run_prepared] made on a pool of worker threads, for async code.
+    /// See [DbFuture] for how the script is killed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run_prepared_async(
+        &self,
+        prepared: &PreparedQuery,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> DbFuture<NamedRows> {
+        let prepared = prepared.clone();
+        self.spawn_async(move |db| db.run_prepared(&prepared, params, mutability))
+    }
+    /// [DbInstance::export_relations] made on a pool of worker threads, for async code
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn export_relations_async<I, T>(
+        &self,
+        relations: I,
+    ) -> DbFuture<BTreeMap<String, NamedRows>>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        let relations = relations
+            .map(|r| r.as_ref().to_string())
+            .collect::<Vec<_>>();
+        self.spawn_async(move |db| db.export_relations(relations.iter()))
+    }
+    /// [DbInstance::import_relations] made on a pool of worker threads, for async code.
+    /// The import cannot be cancelled once started.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn import_relations_async(&self, data: BTreeMap<String, NamedRows>) -> DbFuture<()> {
+        self.spawn_async(move |db| db.import_relations(data))
+    }
+    /// [DbInstance::backup_db] made on a pool of worker threads, for async code.
+    /// The backup cannot be cancelled once started.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn backup_db_async(&self, out_file: impl AsRef<Path>) -> DbFuture<()> {
+        let out_file = out_file.as_ref().to_path_buf();
+        self.spawn_async(move |db| db.backup_db(out_file))
+    }
+    /// [DbInstance::restore_backup] made on a pool of worker threads, for async code.
+    /// The restoration cannot be cancelled once started.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn restore_backup_async(&self, in_file: impl AsRef<Path>) -> DbFuture<()> {
+        let in_file = in_file.as_ref().to_path_buf();
+        self.spawn_async(move |db| db.restore_backup(in_file))
+    }
+    /// Let scripts submit jobs, as they are run by [DbInstance::spawn_job_worker]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn accept_jobs(&self) {
+        match self {
+            DbInstance::Mem(db) => db.accept_jobs(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.accept_jobs(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.accept_jobs(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.accept_jobs(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.accept_jobs(),
+        }
+    }
+    /// Run the jobs submitted by a script on a background thread, unless one is already running
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn_job_worker(&self) {
+        match self {
+            DbInstance::Mem(db) => db.spawn_job_worker(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.spawn_job_worker(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.spawn_job_worker(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.spawn_job_worker(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.spawn_job_worker(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::script_requires_admin].
+    pub fn script_requires_admin(
+        &self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+    ) -> Result<bool> {
+        match self {
+            DbInstance::Mem(db) => db.script_requires_admin(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.script_requires_admin(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.script_requires_admin(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.script_requires_admin(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.script_requires_admin(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_audit_log].
+    pub fn set_audit_log(&self, enabled: bool) {
+        match self {
+            DbInstance::Mem(db) => db.set_audit_log(enabled),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_audit_log(enabled),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_audit_log(enabled),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_audit_log(enabled),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_audit_log(enabled),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_replay_log].
+    pub fn set_replay_log(&self, path: Option<&str>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.set_replay_log(path),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_replay_log(path),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_replay_log(path),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_replay_log(path),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_replay_log(path),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::replay].
+    pub fn replay(&self, path: impl AsRef<Path>) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.replay(path),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.replay(path),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.replay(path),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.replay(path),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.replay(path),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_storage_stats].
+    pub fn set_storage_stats(&self, enabled: bool) {
+        match self {
+            DbInstance::Mem(db) => db.set_storage_stats(enabled),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_storage_stats(enabled),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_storage_stats(enabled),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_storage_stats(enabled),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_storage_stats(enabled),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_index_fallback].
+    pub fn set_index_fallback(&self, enabled: bool) {
+        match self {
+            DbInstance::Mem(db) => db.set_index_fallback(enabled),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_index_fallback(enabled),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_index_fallback(enabled),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_index_fallback(enabled),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_index_fallback(enabled),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_deterministic].
+    pub fn set_deterministic(&self, enabled: bool) {
+        match self {
+            DbInstance::Mem(db) => db.set_deterministic(enabled),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_deterministic(enabled),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_deterministic(enabled),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_deterministic(enabled),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_deterministic(enabled),
         }
     }
     /// `run_script` with mutable script and no parameters
@@ -266,6 +673,29 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.export_relations(relations),
         }
     }
+    /// Dispatcher method. See [crate::Db::export_relations_arrow].
+    #[cfg(feature = "arrow")]
+    pub fn export_relations_arrow<I, T>(
+        &self,
+        relations: I,
+        format: ArrowFormat,
+    ) -> Result<BTreeMap<String, Vec<u8>>>
+        where
+            T: AsRef<str>,
+            I: Iterator<Item=T>,
+    {
+        match self {
+            DbInstance::Mem(db) => db.export_relations_arrow(relations, format),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_relations_arrow(relations, format),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_relations_arrow(relations, format),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relations_arrow(relations, format),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relations_arrow(relations, format),
+        }
+    }
     /// Export relations to JSON-encoded string.
     /// See [crate::Db::export_relations]
     pub fn export_relations_str(&self, data: &str) -> String {
@@ -292,6 +722,25 @@ impl DbInstance {
             .map(|(k, v)| (k, v.into_json()))
             .collect())
     }
+    /// Dispatcher method. See [crate::Db::mutate_relation].
+    pub fn mutate_relation(
+        &self,
+        relation: &str,
+        mutation: builder::Mutation,
+        rows: NamedRows,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.mutate_relation(relation, mutation, rows),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.mutate_relation(relation, mutation, rows),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.mutate_relation(relation, mutation, rows),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.mutate_relation(relation, mutation, rows),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.mutate_relation(relation, mutation, rows),
+        }
+    }
     /// Dispatcher method. See [crate::Db::import_relations].
     pub fn import_relations(&self, data: BTreeMap<String, NamedRows>) -> Result<()> {
         match self {
@@ -321,17 +770,35 @@ impl DbInstance {
     /// Import a relation, the data is given as a JSON string.
     /// See [crate::Db::import_relations].
     pub fn import_relations_str_with_err(&self, data: &str) -> Result<()> {
-        let json_data: JsonValue = serde_json::from_str(data).into_diagnostic()?;
-        let json_object = json_data
-            .as_object()
-            .ok_or_else(|| miette!("A JSON object is requried"))?;
-        let mapping = json_object
-            .iter()
-            .map(|(k, v)| -> Result<(String, NamedRows)> {
-                Ok((k.to_string(), NamedRows::from_json(v)?))
-            })
-            .collect::<Result<_>>()?;
-        self.import_relations(mapping)
+        self.import_relations(relations_from_json(data)?)
+    }
+    /// Dispatcher method. See [crate::Db::ingest_relations].
+    pub fn ingest_relations(
+        &self,
+        idempotency_key: &str,
+        data: BTreeMap<String, NamedRows>,
+    ) -> Result<bool> {
+        match self {
+            DbInstance::Mem(db) => db.ingest_relations(idempotency_key, data),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.ingest_relations(idempotency_key, data),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.ingest_relations(idempotency_key, data),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.ingest_relations(idempotency_key, data),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.ingest_relations(idempotency_key, data),
+        }
+    }
+    /// Ingest a batch of relations, the data is given as a JSON string, and the returned result
+    /// is converted into a string. See [crate::Db::ingest_relations].
+    pub fn ingest_relations_str(&self, idempotency_key: &str, data: &str) -> String {
+        match relations_from_json(data)
+            .and_then(|data| self.ingest_relations(idempotency_key, data))
+        {
+            Ok(ingested) => json!({"ok": true, "ingested": ingested}).to_string(),
+            Err(err) => json!({"ok": false, "message": err.to_string()}).to_string(),
+        }
     }
     /// Dispatcher method. See [crate::Db::backup_db].
     pub fn backup_db(&self, out_file: impl AsRef<Path>) -> Result<()> {
@@ -480,6 +947,247 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.unregister_fixed_rule(name),
         }
     }
+    /// Dispatcher method. See [crate::Db::register_reranker].
+    pub fn register_reranker<R>(&self, name: String, reranker: R) -> Result<()>
+        where
+            R: Reranker + 'static,
+    {
+        match self {
+            DbInstance::Mem(db) => db.register_reranker(name, reranker),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_reranker(name, reranker),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_reranker(name, reranker),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_reranker(name, reranker),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_reranker(name, reranker),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::unregister_reranker]
+    pub fn unregister_reranker(&self, name: &str) -> Result<bool> {
+        match self {
+            DbInstance::Mem(db) => db.unregister_reranker(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_reranker(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_reranker(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_reranker(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_reranker(name),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::with_unmask]
+    pub fn with_unmask(&self, unmask: bool) -> Self {
+        match self {
+            DbInstance::Mem(db) => DbInstance::Mem(db.with_unmask(unmask)),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => DbInstance::Sqlite(db.with_unmask(unmask)),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => DbInstance::RocksDb(db.with_unmask(unmask)),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => DbInstance::Sled(db.with_unmask(unmask)),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => DbInstance::TiKv(db.with_unmask(unmask)),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::with_session].
+    pub fn with_session(&self, id: &str) -> Self {
+        match self {
+            DbInstance::Mem(db) => DbInstance::Mem(db.with_session(id)),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => DbInstance::Sqlite(db.with_session(id)),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => DbInstance::RocksDb(db.with_session(id)),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => DbInstance::Sled(db.with_session(id)),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => DbInstance::TiKv(db.with_session(id)),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_config_reloader]
+    pub fn set_config_reloader(&self, reloader: Option<ConfigReloader>) {
+        match self {
+            DbInstance::Mem(db) => db.set_config_reloader(reloader),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_config_reloader(reloader),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_config_reloader(reloader),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_config_reloader(reloader),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_config_reloader(reloader),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_key_provider]
+    pub fn set_key_provider(&self, provider: Option<Arc<dyn KeyProvider>>) {
+        match self {
+            DbInstance::Mem(db) => db.set_key_provider(provider),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_key_provider(provider),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_key_provider(provider),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_key_provider(provider),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_key_provider(provider),
+        }
+    }
+
+    /// The database of a branch made with `::branch create`, see [crate::Db::branch]
+    pub fn branch(&self, name: &str) -> Result<DbInstance> {
+        Ok(DbInstance::Mem(match self {
+            DbInstance::Mem(db) => db.branch(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.branch(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.branch(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.branch(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.branch(name),
+        }?))
+    }
+
+    /// Dispatcher method. See [crate::Db::prune_history].
+    pub fn prune_history(&self) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.prune_history(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.prune_history(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.prune_history(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.prune_history(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.prune_history(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::scrub].
+    pub fn scrub(&self) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.scrub(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.scrub(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.scrub(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.scrub(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.scrub(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_result_limits].
+    pub fn set_result_limits(&self, max_rows: Option<usize>, max_bytes: Option<usize>) {
+        match self {
+            DbInstance::Mem(db) => db.set_result_limits(max_rows, max_bytes),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_result_limits(max_rows, max_bytes),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_result_limits(max_rows, max_bytes),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_result_limits(max_rows, max_bytes),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_result_limits(max_rows, max_bytes),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_resource_profile].
+    pub fn set_resource_profile(&self, profile: ResourceProfile) {
+        match self {
+            DbInstance::Mem(db) => db.set_resource_profile(profile),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_resource_profile(profile),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_resource_profile(profile),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_resource_profile(profile),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_resource_profile(profile),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_fixed_now].
+    pub fn set_fixed_now(&self, now: Option<f64>) {
+        match self {
+            DbInstance::Mem(db) => db.set_fixed_now(now),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_fixed_now(now),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_fixed_now(now),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_fixed_now(now),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_fixed_now(now),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::close].
+    pub fn close(&self) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.close(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.close(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.close(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.close(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.close(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::is_closed].
+    pub fn is_closed(&self) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.is_closed(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.is_closed(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.is_closed(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.is_closed(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.is_closed(),
+        }
+    }
+    /// Start a background job enforcing the retention policies of relations,
+    /// running [crate::Db::prune_history] every `interval`.
+    /// The job stops when the returned handle is dropped or the database is closed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawn_history_retention(&self, interval: Duration) -> HistoryRetentionJob {
+        let (stop_send, stop_recv) = bounded::<()>(1);
+        let db = self.clone();
+        std::thread::spawn(move || loop {
+            match stop_recv.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) if db.is_closed() => break,
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Err(err) = db.prune_history() {
+                        log::error!("history retention job failed: {err:?}");
+                    }
+                }
+                _ => break,
+            }
+        });
+        HistoryRetentionJob { _stop: stop_send }
+    }
+    /// Start a background job checking the stored data for corruption,
+    /// running [crate::Db::scrub] every `interval`.
+    /// The job stops when the returned handle is dropped or the database is closed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawn_scrubbing(&self, interval: Duration) -> ScrubbingJob {
+        let (stop_send, stop_recv) = bounded::<()>(1);
+        let db = self.clone();
+        std::thread::spawn(move || loop {
+            match stop_recv.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) if db.is_closed() => break,
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Err(err) = db.scrub() {
+                        log::error!("scrubbing job failed: {err:?}");
+                    }
+                }
+                _ => break,
+            }
+        });
+        ScrubbingJob { _stop: stop_send }
+    }
 
     /// Dispatcher method. See [crate::Db::run_multi_transaction]
     pub fn run_multi_transaction(
@@ -517,6 +1225,20 @@ impl DbInstance {
     }
 }
 
+/// Handle of the background history retention job started by
+/// [DbInstance::spawn_history_retention]. Dropping it stops the job.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct HistoryRetentionJob {
+    _stop: Sender<()>,
+}
+
+/// Handle of the background scrubbing job started by [DbInstance::spawn_scrubbing].
+/// Dropping it stops the job.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ScrubbingJob {
+    _stop: Sender<()>,
+}
+
 /// A multi-transaction handle.
 /// You should use either the fields directly, or the associated functions.
 pub struct MultiTransaction {
@@ -564,6 +1286,42 @@ impl MultiTransaction {
             Err(err) => bail!(err),
         }
     }
+    /// Takes a savepoint in the multi-transaction
+    pub fn savepoint(&self, name: &str) -> Result<()> {
+        self.run_savepoint_op(TransactionPayload::Savepoint(name.to_string()))
+    }
+    /// Undoes what was done in the multi-transaction after the savepoint was taken.
+    /// The savepoint is kept, and can be rolled back to again.
+    pub fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        self.run_savepoint_op(TransactionPayload::RollbackToSavepoint(name.to_string()))
+    }
+    /// Releases the savepoint and the ones taken after it, keeping what was done since
+    pub fn release_savepoint(&self, name: &str) -> Result<()> {
+        self.run_savepoint_op(TransactionPayload::ReleaseSavepoint(name.to_string()))
+    }
+    fn run_savepoint_op(&self, payload: TransactionPayload) -> Result<()> {
+        if let Err(err) = self.sender.send(payload) {
+            bail!(err);
+        }
+        match self.receiver.recv() {
+            Ok(r) => r.map(|_| ()),
+            Err(err) => bail!(err),
+        }
+    }
+}
+
+/// Parse relations given as a JSON object, in the shape accepted by [DbInstance::import_relations]
+fn relations_from_json(data: &str) -> Result<BTreeMap<String, NamedRows>> {
+    let json_data: JsonValue = serde_json::from_str(data).into_diagnostic()?;
+    let json_object = json_data
+        .as_object()
+        .ok_or_else(|| miette!("A JSON object is requried"))?;
+    json_object
+        .iter()
+        .map(|(k, v)| -> Result<(String, NamedRows)> {
+            Ok((k.to_string(), NamedRows::from_json(v)?))
+        })
+        .collect()
 }
 
 /// Convert error raised by the database into friendly JSON format
@@ -175,6 +175,11 @@ fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Resul
     })
 }
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Required parameter {0} not found")]
+#[diagnostic(code(parser::param_not_found))]
+pub(crate) struct ParamNotFoundError(pub(crate) String, #[label] pub(crate) SourceSpan);
+
 fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Expr> {
     let span = pair.extract_span();
     let op = pair.as_rule();
@@ -184,11 +189,6 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             tuple_pos: None,
         },
         Rule::param => {
-            #[derive(Error, Diagnostic, Debug)]
-            #[error("Required parameter {0} not found")]
-            #[diagnostic(code(parser::param_not_found))]
-            struct ParamNotFoundError(String, #[label] SourceSpan);
-
             let param_str = pair.as_str().strip_prefix('$').unwrap();
             Expr::Const {
                 val: param_pool
@@ -18,14 +18,15 @@ use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::program::InputProgram;
+use crate::data::program::{InputProgram, SandboxViolation};
 use crate::data::relation::NullableColType;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::expr::build_expr;
 use crate::parse::imperative::parse_imperative_block;
-use crate::parse::query::parse_query;
+use crate::parse::query::{param_decls, parse_query, parse_query_with_params, ParamDecl};
 use crate::parse::schema::parse_nullable_type;
 use crate::parse::sys::{parse_sys, SysOp};
+use crate::runtime::scrub::SCRUB_RELATION;
 use crate::{Expr, FixedRule};
 
 pub(crate) mod expr;
@@ -161,7 +162,11 @@ impl ImperativeStmt {
                             collector.insert(new.name.clone());
                         }
                     }
-                    SysOp::CreateIndex(symb, subs, _) => {
+                    SysOp::CloneRelation(base, new) => {
+                        collector.insert(base.name.clone());
+                        collector.insert(new.name.clone());
+                    }
+                    SysOp::CreateIndex(symb, subs, _) | SysOp::RebuildIndex(symb, subs) => {
                         collector.insert(symb.name.clone());
                         collector.insert(SmartString::from(format!("{}:{}", symb.name, subs.name)));
                     }
@@ -180,14 +185,129 @@ impl ImperativeStmt {
                     SysOp::RemoveIndex(rel, idx) => {
                         collector.insert(SmartString::from(format!("{}:{}", rel.name, idx.name)));
                     }
+                    SysOp::PruneHistory(rel, policy) => {
+                        collector.insert(rel.name.clone());
+                        if let Some(archive) = policy.as_ref().and_then(|p| p.expire_to.as_ref()) {
+                            collector.insert(archive.clone());
+                        }
+                    }
+                    SysOp::CreateMaterialized(rel, _)
+                    | SysOp::RefreshMaterialized(rel)
+                    | SysOp::SetRetention(rel, _)
+                    | SysOp::SetPartitions(rel, _)
+                    | SysOp::EncryptColumns(rel, _)
+                    | SysOp::SetMasks(rel, _)
+                    | SysOp::DescribeColumn(rel, _, _) => {
+                        collector.insert(rel.name.clone());
+                    }
+                    SysOp::InferImport(config) if config.create => {
+                        collector.insert(config.relation.clone());
+                    }
+                    SysOp::ImportGraph(config) => {
+                        collector.insert(config.relation.clone());
+                        collector.extend(config.nodes.iter().cloned());
+                    }
+                    SysOp::ImportRdf(config) => {
+                        collector.insert(config.relation.clone());
+                        collector.insert(config.terms.clone());
+                    }
+                    SysOp::Scrub => {
+                        collector.insert(SmartString::from(SCRUB_RELATION));
+                    }
                     _ => {}
                 }
             }
         }
     }
+    pub(crate) fn requires_admin(&self) -> bool {
+        match self {
+            ImperativeStmt::Program { prog }
+            | ImperativeStmt::IgnoreErrorProgram { prog } => prog.prog.requires_admin(),
+            ImperativeStmt::Return { returns } => returns.iter().any(|ret| match ret {
+                Left(prog) => prog.prog.requires_admin(),
+                Right(_) => false,
+            }),
+            ImperativeStmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                matches!(condition, ImperativeCondition::Right(prog) if prog.prog.requires_admin())
+                    || then_branch
+                        .iter()
+                        .chain(else_branch.iter())
+                        .any(|p| p.requires_admin())
+            }
+            ImperativeStmt::Loop { body, .. } => body.iter().any(|p| p.requires_admin()),
+            ImperativeStmt::SysOp { sysop } => sysop.sysop.requires_admin(),
+            ImperativeStmt::TempDebug { .. }
+            | ImperativeStmt::Break { .. }
+            | ImperativeStmt::Continue { .. }
+            | ImperativeStmt::TempSwap { .. } => false,
+        }
+    }
+    pub(crate) fn check_sandboxed(&self) -> Result<()> {
+        match self {
+            ImperativeStmt::Program { prog } | ImperativeStmt::IgnoreErrorProgram { prog } => {
+                prog.prog.check_sandboxed()
+            }
+            ImperativeStmt::Return { returns } => {
+                for ret in returns {
+                    if let Left(prog) = ret {
+                        prog.prog.check_sandboxed()?;
+                    }
+                }
+                Ok(())
+            }
+            ImperativeStmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                if let ImperativeCondition::Right(prog) = condition {
+                    prog.prog.check_sandboxed()?;
+                }
+                then_branch
+                    .iter()
+                    .chain(else_branch.iter())
+                    .try_for_each(|p| p.check_sandboxed())
+            }
+            ImperativeStmt::Loop { body, .. } => body.iter().try_for_each(|p| p.check_sandboxed()),
+            ImperativeStmt::SysOp { .. } => bail!(SandboxViolation(
+                "A system op".to_string(),
+                SourceSpan(0, 0)
+            )),
+            ImperativeStmt::TempDebug { .. }
+            | ImperativeStmt::Break { .. }
+            | ImperativeStmt::Continue { .. }
+            | ImperativeStmt::TempSwap { .. } => Ok(()),
+        }
+    }
 }
 
 impl CozoScript {
+    /// Whether the script contains destructive operations reserved for administrators
+    pub(crate) fn requires_admin(&self) -> bool {
+        match self {
+            CozoScript::Single(p) => p.requires_admin(),
+            CozoScript::Imperative(ps) => ps.iter().any(|p| p.requires_admin()),
+            CozoScript::Sys(op) => op.requires_admin(),
+        }
+    }
+    /// Reject scripts that could mutate the database or whose results may change between
+    /// runs on the same data, see [`InputProgram::check_sandboxed`]
+    pub(crate) fn check_sandboxed(&self) -> Result<()> {
+        match self {
+            CozoScript::Single(p) => p.check_sandboxed(),
+            CozoScript::Imperative(ps) => ps.iter().try_for_each(|p| p.check_sandboxed()),
+            CozoScript::Sys(_) => bail!(SandboxViolation(
+                "A system op".to_string(),
+                SourceSpan(0, 0)
+            )),
+        }
+    }
     pub(crate) fn get_single_program(self) -> Result<InputProgram> {
         #[derive(Debug, Error, Diagnostic)]
         #[error("expect script to contain only a single program")]
@@ -246,6 +366,14 @@ pub(crate) struct ParseError {
     pub(crate) span: SourceSpan,
 }
 
+/// Whether the grammar rule `rule` matches all of `src`
+pub(crate) fn matches_rule(src: &str, rule: Rule) -> bool {
+    match CozoScriptParser::parse(rule, src) {
+        Ok(mut pairs) => pairs.next().map(|p| p.as_str()) == Some(src),
+        Err(_) => false,
+    }
+}
+
 pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
     let parsed = CozoScriptParser::parse(Rule::col_type_with_term, src)
         .into_diagnostic()?
@@ -308,6 +436,84 @@ pub(crate) fn parse_script(
     })
 }
 
+/// A query parsed with placeholders for its parameters, see [crate::Db::prepare]
+#[derive(Clone)]
+pub(crate) struct QueryTemplate {
+    pub(crate) program: InputProgram,
+    /// The name of the parameter used at each span
+    pub(crate) params: Vec<(SourceSpan, String)>,
+    /// The parameters declared with `:params`
+    pub(crate) declared: Vec<ParamDecl>,
+}
+
+/// Parse a script consisting of a single query, with nulls in place of its parameters.
+///
+/// `None` is returned when the parameters cannot all be substituted into the parsed query
+/// afterwards, e.g. when they are used in options, and for other kinds of scripts.
+/// Only syntax errors are reported, the others are when the script is parsed again to be run.
+pub(crate) fn parse_query_template(
+    src: &str,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
+) -> Result<Option<QueryTemplate>> {
+    let parsed = CozoScriptParser::parse(Rule::script, src)
+        .map_err(|err| {
+            let span = match err.location {
+                InputLocation::Pos(p) => SourceSpan(p, 0),
+                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+            };
+            ParseError { span }
+        })?
+        .next()
+        .unwrap();
+    if parsed.as_rule() != Rule::query_script {
+        return Ok(None);
+    }
+    let mut params = vec![];
+    let mut declared = vec![];
+    for pair in parsed.clone().into_inner().flatten() {
+        match pair.as_rule() {
+            Rule::param => params.push((
+                pair.extract_span(),
+                pair.as_str().strip_prefix('$').unwrap().to_string(),
+            )),
+            Rule::params_option => declared = param_decls(pair)?,
+            // time travel clauses are evaluated when parsing, against the clock of the time
+            Rule::validity_clause | Rule::tx_time_clause => return Ok(None),
+            _ => {}
+        }
+    }
+    let placeholders = params
+        .iter()
+        .map(|(_, name)| (name.clone(), DataValue::Null))
+        .collect();
+    let mut program = match parse_query_with_params(
+        parsed.into_inner(),
+        &placeholders,
+        fixed_rules,
+        cur_vld,
+        false,
+    ) {
+        Ok(program) => program,
+        Err(_) => return Ok(None),
+    };
+    let mut substituted = vec![false; params.len()];
+    program.for_each_rule_const(&mut |_, span| {
+        if let Some(i) = params.iter().position(|(s, _)| *s == span) {
+            substituted[i] = true;
+        }
+    });
+    Ok(if substituted.into_iter().all(|s| s) {
+        Some(QueryTemplate {
+            program,
+            params,
+            declared,
+        })
+    } else {
+        None
+    })
+}
+
 trait ExtractSpan {
     fn extract_span(&self) -> SourceSpan;
 }
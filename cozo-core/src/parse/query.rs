@@ -22,11 +22,12 @@ use thiserror::Error;
 
 use crate::data::aggr::{parse_aggr, Aggregation};
 use crate::data::expr::Expr;
-use crate::data::functions::{str2vld, MAX_VALIDITY_TS};
+use crate::data::functions::{str2vld, MAX_VALIDITY_TS, OP_ADD, OP_LE};
 use crate::data::program::{
-    FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
+    FixedRuleApply, FixedRuleArg, IndexHint, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
     InputNamedFieldRelationApplyAtom, InputProgram, InputRelationApplyAtom, InputRuleApplyAtom,
-    QueryAssertion, QueryOutOptions, RelationOp, ReturnMutation, SearchInput, SortDir, Unification,
+    IterationLimit, QueryAssertion, QueryOutOptions, RelationHints, RelationOp, ReturnMutation, SearchInput,
+    SortDir, Unification,
 };
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::{Symbol, PROG_ENTRY};
@@ -34,8 +35,9 @@ use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::{FixedRuleHandle, FixedRuleNotFoundError};
 use crate::parse::expr::build_expr;
-use crate::parse::schema::parse_schema;
-use crate::parse::{CozoScriptParser, ExtractSpan, Pair, Pairs, Rule, SourceSpan};
+use crate::parse::schema::{parse_nullable_type, parse_schema};
+use crate::parse::{matches_rule, CozoScriptParser, ExtractSpan, Pair, Pairs, Rule, SourceSpan};
+use crate::runtime::fixed_rule_cache::{FixedRuleCacheSpec, CACHE_OPTION};
 use crate::runtime::relation::InputRelationHandle;
 use crate::FixedRule;
 
@@ -59,6 +61,21 @@ struct OptionNotPosIntError(&'static str, #[label] SourceSpan);
 #[diagnostic(code(parser::option_not_bool))]
 struct OptionNotBoolError(&'static str, #[label] SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Query options disable_magic_rewrite and force_magic_rewrite cannot both be set")]
+#[diagnostic(code(parser::conflicting_magic_rewrite_options))]
+struct ConflictingMagicRewriteOptions(#[label] SourceSpan);
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Query option on_max_iterations requires 'error' or 'partial'")]
+#[diagnostic(code(parser::bad_on_max_iterations))]
+struct BadOnMaxIterations(#[label] SourceSpan);
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Query option on_max_iterations requires max_iterations to be set")]
+#[diagnostic(code(parser::on_max_iterations_without_max))]
+struct OnMaxIterationsWithoutMax(#[label] SourceSpan);
+
 #[derive(Debug)]
 struct MultipleRuleDefinitionError(String, Vec<SourceSpan>);
 
@@ -67,6 +84,11 @@ struct MultipleRuleDefinitionError(String, Vec<SourceSpan>);
 #[diagnostic(code(parser::multiple_out_assert))]
 struct DuplicateQueryAssertion(#[label] SourceSpan);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Expected versions can only be given to `:update`")]
+#[diagnostic(code(parser::expecting_without_update))]
+struct ExpectingWithoutUpdate(#[label] SourceSpan);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Multiple query yields defined")]
 #[diagnostic(code(parser::multiple_yields))]
@@ -95,6 +117,75 @@ impl Diagnostic for MultipleRuleDefinitionError {
     }
 }
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Declared parameter ${0} not given")]
+#[diagnostic(code(parser::declared_param_not_given))]
+#[diagnostic(help("Parameters declared with a nullable type, e.g. 'Int?', default to null"))]
+struct DeclaredParamNotGiven(String, #[label] SourceSpan);
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Parameter ${0} is not of its declared type {1}")]
+#[diagnostic(code(parser::param_type_mismatch))]
+struct ParamTypeMismatch(
+    String,
+    NullableColType,
+    #[label] SourceSpan,
+    #[related] [Report; 1],
+);
+
+/// A parameter declared with `:params`
+#[derive(Debug, Clone)]
+pub(crate) struct ParamDecl {
+    pub(crate) name: String,
+    pub(crate) typ: NullableColType,
+    pub(crate) span: SourceSpan,
+}
+
+impl ParamDecl {
+    /// The value given for the parameter, coerced to its declared type
+    pub(crate) fn check(
+        &self,
+        param_pool: &BTreeMap<String, DataValue>,
+        cur_vld: ValidityTs,
+    ) -> Result<DataValue> {
+        let val = match param_pool.get(&self.name) {
+            Some(val) => val.clone(),
+            None if self.typ.nullable => DataValue::Null,
+            None => bail!(DeclaredParamNotGiven(self.name.clone(), self.span)),
+        };
+        Ok(self.typ.coerce(val, cur_vld).map_err(|err| {
+            ParamTypeMismatch(self.name.clone(), self.typ.clone(), self.span, [err])
+        })?)
+    }
+}
+
+/// The declarations of a `:params` option
+pub(crate) fn param_decls(src: Pair<'_>) -> Result<Vec<ParamDecl>> {
+    src.into_inner()
+        .map(|decl| {
+            let span = decl.extract_span();
+            let mut decl = decl.into_inner();
+            let name = decl.next().unwrap().as_str().to_string();
+            let typ = parse_nullable_type(decl.next().unwrap())?;
+            Ok(ParamDecl { name, typ, span })
+        })
+        .collect()
+}
+
+/// The parameters with those declared by `:params` coerced to their types
+fn check_params(
+    src: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    cur_vld: ValidityTs,
+) -> Result<BTreeMap<String, DataValue>> {
+    let mut checked = param_pool.clone();
+    for decl in param_decls(src)? {
+        let val = decl.check(param_pool, cur_vld)?;
+        checked.insert(decl.name, val);
+    }
+    Ok(checked)
+}
+
 fn merge_spans(symbs: &[Symbol]) -> SourceSpan {
     let mut fst = symbs.first().unwrap().span;
     for nxt in symbs.iter().skip(1) {
@@ -108,14 +199,60 @@ pub(crate) fn parse_query(
     param_pool: &BTreeMap<String, DataValue>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
+) -> Result<InputProgram> {
+    parse_query_with_params(src, param_pool, fixed_rules, cur_vld, true)
+}
+
+/// Parse a query, checking the parameters declared with `:params` only if `check_declared`
+/// is set, as [crate::Db::prepare] does when the query is run instead
+pub(crate) fn parse_query_with_params(
+    src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
+    check_declared: bool,
 ) -> Result<InputProgram> {
     let mut progs: BTreeMap<Symbol, InputInlineRulesOrFixed> = Default::default();
     let mut out_opts: QueryOutOptions = Default::default();
     let mut disable_magic_rewrite = false;
+    let mut force_magic_rewrite = None;
+    let mut max_iterations = None;
+    let mut on_max_iterations = None;
 
     let mut stored_relation = None;
     let mut returning_mutation = ReturnMutation::NotReturning;
 
+    // declared parameters are checked before anything else is parsed, as everything uses them
+    let checked_params;
+    let param_pool = match src.clone().find(|p| p.as_rule() == Rule::params_option) {
+        Some(pair) if check_declared => {
+            checked_params = check_params(pair, param_pool, cur_vld)?;
+            &checked_params
+        }
+        _ => param_pool,
+    };
+
+    // the clock is fixed before anything else is parsed, as validity specifications use it
+    let cur_vld = match src.clone().find(|p| p.as_rule() == Rule::fixed_now_option) {
+        None => cur_vld,
+        Some(pair) => {
+            let pair = pair.into_inner().next().unwrap();
+            let span = pair.extract_span();
+            let now = match build_expr(pair, param_pool)?
+                .eval_to_const()
+                .map_err(|err| OptionNotConstantError("fixed_now", span, [err]))?
+            {
+                DataValue::Str(s) => str2vld(&s).map_err(|_| BadValiditySpecification(span))?,
+                v => {
+                    let secs = v.get_float().ok_or(BadValiditySpecification(span))?;
+                    ValidityTs(Reverse((secs * 1e6).round() as i64))
+                }
+            };
+            out_opts.fixed_now = Some(now);
+            now
+        }
+    };
+
     for pair in src {
         match pair.as_rule() {
             Rule::rule => {
@@ -161,6 +298,25 @@ pub(crate) fn parse_query(
                     }
                 }
             }
+            Rule::closure_rule => {
+                let span = pair.extract_span();
+                for (name, rules) in parse_closure_rule(pair, param_pool, cur_vld)? {
+                    if let Some(found) = progs.get(&name) {
+                        let mut found_span = match found {
+                            InputInlineRulesOrFixed::Rules { rules } => {
+                                rules.iter().map(|r| r.span).collect_vec()
+                            }
+                            InputInlineRulesOrFixed::Fixed { fixed } => vec![fixed.span],
+                        };
+                        found_span.push(span);
+                        bail!(MultipleRuleDefinitionError(
+                            name.name.to_string(),
+                            found_span
+                        ));
+                    }
+                    progs.insert(name, InputInlineRulesOrFixed::Rules { rules });
+                }
+            }
             Rule::fixed_rule => {
                 let rule_span = pair.extract_span();
                 let (name, apply) = parse_fixed_rule(pair, param_pool, fixed_rules, cur_vld)?;
@@ -253,6 +409,7 @@ pub(crate) fn parse_query(
                             arity,
                             span,
                             fixed_impl: Arc::new(fixed_impl),
+                            cache: None,
                         },
                     },
                 );
@@ -346,10 +503,33 @@ pub(crate) fn parse_query(
                     _ => unreachable!(),
                 };
 
-                let name_p = args.next().unwrap();
-                let name = Symbol::new(name_p.as_str(), name_p.extract_span());
-                match args.next() {
-                    None => stored_relation = Some(Left((name, span, op))),
+                let name = relation_name(
+                    &args.next().unwrap(),
+                    None,
+                    &[Rule::compound_ident, Rule::underscore_ident],
+                    param_pool,
+                )?;
+                let mut schema_p = None;
+                let mut expected_version = None;
+                for arg in args {
+                    match arg.as_rule() {
+                        Rule::table_schema => schema_p = Some(arg),
+                        Rule::expecting_clause => {
+                            ensure!(
+                                op == RelationOp::Update,
+                                ExpectingWithoutUpdate(arg.extract_span())
+                            );
+                            let mut inner = arg.into_inner();
+                            let col = inner.next().unwrap();
+                            let col = Symbol::new(col.as_str(), col.extract_span());
+                            let expr = build_expr(inner.next().unwrap(), param_pool)?;
+                            expected_version = Some((col, expr));
+                        }
+                        r => unreachable!("{:?}", r),
+                    }
+                }
+                match schema_p {
+                    None => stored_relation = Some(Left((name, span, op, expected_version))),
                     Some(schema_p) => {
                         let (mut metadata, mut key_bindings, mut dep_bindings) =
                             parse_schema(schema_p)?;
@@ -365,6 +545,7 @@ pub(crate) fn parse_query(
                                 metadata,
                                 key_bindings,
                                 dep_bindings,
+                                expected_version,
                                 span,
                             },
                             op,
@@ -396,15 +577,77 @@ pub(crate) fn parse_query(
                     .ok_or(OptionNotBoolError("disable_magic_rewrite", span))?;
                 disable_magic_rewrite = val;
             }
+            Rule::force_magic_rewrite_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let val = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("force_magic_rewrite", span, [err]))?
+                    .get_bool()
+                    .ok_or(OptionNotBoolError("force_magic_rewrite", span))?;
+                force_magic_rewrite = val.then_some(span);
+            }
+            Rule::max_iterations_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let max = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("max_iterations", span, [err]))?
+                    .get_int()
+                    .ok_or(OptionNotPosIntError("max_iterations", span))?;
+                ensure!(max > 0, OptionNotPosIntError("max_iterations", span));
+                max_iterations = Some(max as usize);
+            }
+            Rule::on_max_iterations_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let partial = match build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("on_max_iterations", span, [err]))?
+                    .get_str()
+                {
+                    Some("error") => false,
+                    Some("partial") => true,
+                    _ => bail!(BadOnMaxIterations(span)),
+                };
+                on_max_iterations = Some((partial, span));
+            }
+            Rule::deterministic_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let val = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("deterministic", span, [err]))?
+                    .get_bool()
+                    .ok_or(OptionNotBoolError("deterministic", span))?;
+                out_opts.deterministic = Some(val);
+            }
+            Rule::fixed_now_option | Rule::params_option => {}
             Rule::EOI => break,
             r => unreachable!("{:?}", r),
         }
     }
 
+    match (max_iterations, on_max_iterations) {
+        (Some(max), partial) => {
+            out_opts.max_iterations = Some(IterationLimit {
+                max,
+                partial: matches!(partial, Some((true, _))),
+            })
+        }
+        (None, Some((_, span))) => bail!(OnMaxIterationsWithoutMax(span)),
+        (None, None) => {}
+    }
+
+    if let Some(span) = force_magic_rewrite {
+        ensure!(!disable_magic_rewrite, ConflictingMagicRewriteOptions(span));
+    }
+
     let mut prog = InputProgram {
         prog: progs,
         out_opts,
         disable_magic_rewrite,
+        force_magic_rewrite: force_magic_rewrite.is_some(),
     };
 
     if prog.prog.is_empty() {
@@ -428,7 +671,7 @@ pub(crate) fn parse_query(
 
     match stored_relation {
         None => {}
-        Some(Left((name, span, op))) => {
+        Some(Left((name, span, op, expected_version))) => {
             let head = prog.get_entry_out_head()?;
             for symb in &head {
                 symb.ensure_valid_field()?;
@@ -454,6 +697,7 @@ pub(crate) fn parse_query(
                 metadata,
                 key_bindings: head,
                 dep_bindings: vec![],
+                expected_version,
                 span,
             };
             prog.out_opts.store_relation = Some((handle, op, returning_mutation))
@@ -550,14 +794,35 @@ fn parse_rule(
     ensure!(!head.is_empty(), EmptyRuleHead(head_span));
     let body = src.next().unwrap();
     let mut body_clauses = vec![];
+    let mut optionals = vec![];
     let mut ignored_counter = 0;
     for atom_src in body.into_inner() {
-        body_clauses.push(parse_disjunction(
-            atom_src,
-            param_pool,
-            cur_vld,
-            &mut ignored_counter,
-        )?)
+        let mut inner = atom_src.clone().into_inner();
+        match (inner.next(), inner.next()) {
+            (Some(opt), None) if opt.as_rule() == Rule::optional_atom => {
+                let optional = parse_optional_atom(opt, param_pool, cur_vld, &mut ignored_counter)?;
+                optionals.push((body_clauses.len(), optional));
+                // replaced below, once the clauses before it are known
+                body_clauses.push(InputAtom::Conjunction {
+                    inner: vec![],
+                    span,
+                });
+            }
+            _ => body_clauses.push(parse_disjunction(
+                atom_src,
+                param_pool,
+                cur_vld,
+                &mut ignored_counter,
+            )?),
+        }
+    }
+    // like a left join, an optional atom joins with the clauses before it
+    for (idx, optional) in optionals {
+        let mut before = BTreeSet::new();
+        for clause in &body_clauses[..idx] {
+            clause.collect_bindings(&mut before)?;
+        }
+        body_clauses[idx] = optional.expand(&before)?;
     }
 
     Ok((
@@ -571,6 +836,455 @@ fn parse_rule(
     ))
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("'optional' can only be used for a whole clause of a rule body")]
+#[diagnostic(code(parser::nested_optional))]
+#[diagnostic(help("Move the optional atom out of the disjunction, negation or group"))]
+struct NestedOptional(#[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The default of '{0}' is not for a variable first bound by the optional atom")]
+#[diagnostic(code(parser::bad_optional_default))]
+#[diagnostic(help(
+    "Defaults can only be given for variables that appear in no clause before the optional atom"
+))]
+struct BadOptionalDefault(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Variable '{0}' is first bound by the optional atom, but not directly")]
+#[diagnostic(code(parser::optional_binding_in_expr))]
+#[diagnostic(help(
+    "Bind the variable directly, e.g. 'optional *r{a, b}', and compute with it in the rule body"
+))]
+struct OptionalBindingInExpr(String, #[label] SourceSpan);
+
+/// An atom made optional by `optional`, with the defaults of its variables
+struct OptionalAtom {
+    atom: InputAtom,
+    defaults: BTreeMap<SmartString<LazyCompact>, Unification>,
+    span: SourceSpan,
+}
+
+fn parse_optional_atom(
+    src: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    cur_vld: ValidityTs,
+    ignored_counter: &mut u32,
+) -> Result<OptionalAtom> {
+    let span = src.extract_span();
+    let mut src = src.into_inner();
+    src.next().unwrap();
+    let atom = parse_atom(src.next().unwrap(), param_pool, cur_vld, ignored_counter)?;
+    let mut defaults = BTreeMap::new();
+    if let Some(defaults_p) = src.next() {
+        for pair in defaults_p.into_inner().next().unwrap().into_inner() {
+            let pair_span = pair.extract_span();
+            let (name, expr) = extract_named_apply_arg(pair, param_pool)?;
+            let unification = Unification {
+                binding: Symbol::new(name.clone(), pair_span),
+                expr,
+                one_many_unif: false,
+                span: pair_span,
+            };
+            defaults.insert(name, unification);
+        }
+    }
+    Ok(OptionalAtom {
+        atom,
+        defaults,
+        span,
+    })
+}
+
+impl OptionalAtom {
+    /// Expand into a left join: either the atom holds, or it holds for no values of its
+    /// variables not bound `before` it, and these take their defaults, or null.
+    fn expand(&self, before: &BTreeSet<Symbol>) -> Result<InputAtom> {
+        let mut new_vars = BTreeSet::new();
+        self.atom.collect_bindings(&mut new_vars)?;
+        new_vars.retain(|v| !v.is_ignored_symbol() && !before.contains(v));
+        for (name, default) in &self.defaults {
+            ensure!(
+                new_vars.iter().any(|v| v.name == *name),
+                BadOptionalDefault(name.to_string(), default.span)
+            );
+        }
+
+        // the atom with its new variables ignored
+        let is_new = |e: &Expr| matches!(e, Expr::Binding { var, .. } if new_vars.contains(var));
+        let check_arg = |e: &Expr| -> Result<()> {
+            for var in e.bindings()? {
+                ensure!(
+                    !new_vars.contains(&var) || is_new(e),
+                    OptionalBindingInExpr(var.to_string(), e.span())
+                );
+            }
+            Ok(())
+        };
+        let ignore_new = |args: &mut Vec<Expr>| -> Result<()> {
+            for arg in args.iter_mut() {
+                check_arg(arg)?;
+                if is_new(arg) {
+                    *arg = Expr::Binding {
+                        var: Symbol::new("_", arg.span()),
+                        tuple_pos: None,
+                    };
+                }
+            }
+            Ok(())
+        };
+        let mut negated = self.atom.clone();
+        match &mut negated {
+            InputAtom::NamedFieldRelation { inner } => {
+                for arg in inner.args.values() {
+                    check_arg(arg)?;
+                }
+                inner.args.retain(|_, arg| !is_new(arg));
+            }
+            InputAtom::Relation { inner } => ignore_new(&mut inner.args)?,
+            InputAtom::Rule { inner } => ignore_new(&mut inner.args)?,
+            _ => unreachable!(),
+        }
+
+        let mut absent = vec![InputAtom::Negation {
+            inner: Box::new(negated),
+            span: self.span,
+        }];
+        for var in new_vars {
+            let unification = match self.defaults.get(&var.name) {
+                Some(unification) => unification.clone(),
+                None => Unification {
+                    binding: var.clone(),
+                    expr: Expr::Const {
+                        val: DataValue::Null,
+                        span: var.span,
+                    },
+                    one_many_unif: false,
+                    span: self.span,
+                },
+            };
+            absent.push(InputAtom::Unification { inner: unification });
+        }
+        Ok(InputAtom::Disjunction {
+            inner: vec![
+                self.atom.clone(),
+                InputAtom::Conjunction {
+                    inner: absent,
+                    span: self.span,
+                },
+            ],
+            span: self.span,
+        })
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The operands of '{0}' bind different variables")]
+#[diagnostic(code(parser::set_op_mismatch))]
+#[diagnostic(help("The first operand binds {1:?}, this one binds {2:?}"))]
+struct SetOpMismatch(&'static str, Vec<String>, Vec<String>, #[label] SourceSpan);
+
+/// `a intersect b except c`: the operands must bind the same variables, and the result is
+/// what is in `a` and `b` but not in `c`. Rules and relations are sets, so is the result.
+fn parse_set_op_atom(
+    src: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    cur_vld: ValidityTs,
+    ignored_counter: &mut u32,
+) -> Result<InputAtom> {
+    let span = src.extract_span();
+    let mut src = src.into_inner();
+    let first = parse_atom(src.next().unwrap(), param_pool, cur_vld, ignored_counter)?;
+    let bound = |atom: &InputAtom| -> Result<Vec<String>> {
+        let mut vars = BTreeSet::new();
+        atom.collect_bindings(&mut vars)?;
+        Ok(vars
+            .into_iter()
+            .filter(|v| !v.is_ignored_symbol())
+            .map(|v| v.name.to_string())
+            .collect())
+    };
+    let first_vars = bound(&first)?;
+    let mut inner = vec![first];
+    while let Some(op) = src.next() {
+        let operand = parse_atom(src.next().unwrap(), param_pool, cur_vld, ignored_counter)?;
+        let intersect = op.as_rule() == Rule::intersect_op;
+        let vars = bound(&operand)?;
+        ensure!(
+            vars == first_vars,
+            SetOpMismatch(
+                if intersect { "intersect" } else { "except" },
+                first_vars.clone(),
+                vars,
+                operand.span()
+            )
+        );
+        inner.push(if intersect {
+            operand
+        } else {
+            InputAtom::Negation {
+                span: operand.span(),
+                inner: Box::new(operand),
+            }
+        });
+    }
+    Ok(InputAtom::Conjunction { inner, span })
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The edges of a closure must bind exactly two variables, found {0:?}")]
+#[diagnostic(code(parser::bad_closure_edge))]
+#[diagnostic(help(
+    "Bind the source and the target of the edges directly, in that order, \
+    e.g. 'closure(*follows{src: a, dst: b}, ...)', and ignore other columns with '_'"
+))]
+struct BadClosureEdge(Vec<String>, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The head of this closure rule must have {0}, found {1} columns")]
+#[diagnostic(code(parser::bad_closure_head))]
+#[diagnostic(help("Aggregations cannot be applied: the depth is always the shortest one"))]
+struct BadClosureHead(&'static str, usize, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unknown option '{0}' for closure")]
+#[diagnostic(code(parser::unknown_closure_option))]
+#[diagnostic(help("The options are 'from' and 'max_depth'"))]
+struct UnknownClosureOption(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The option 'max_depth' of closure requires a non-negative integer")]
+#[diagnostic(code(parser::bad_closure_max_depth))]
+struct BadClosureMaxDepth(#[label] SourceSpan);
+
+/// `r[node, depth] := closure(edge[a, b], from: $start, max_depth: 5)` is expanded into the
+/// recursive rules for the nodes reachable along the edges from `a` to `b`, with the length
+/// of the shortest path to each of them. Without `from`, the start node is the first column
+/// and every node is a start. The depth column is optional.
+fn parse_closure_rule(
+    src: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    cur_vld: ValidityTs,
+) -> Result<Vec<(Symbol, Vec<InputInlineRule>)>> {
+    let span = src.extract_span();
+    let mut src = src.into_inner();
+    let head_p = src.next().unwrap();
+    let head_span = head_p.extract_span();
+    let (name, head, aggr) = parse_rule_head(head_p, param_pool)?;
+    let edge_p = src.next().unwrap();
+    let edge_span = edge_p.extract_span();
+    let mut edge = parse_atom(edge_p, param_pool, cur_vld, &mut 0)?;
+
+    let mut from = None;
+    let mut max_depth = None;
+    for opt in src {
+        let mut inner = opt.into_inner();
+        let opt_name = inner.next().unwrap();
+        let expr = build_expr(inner.next().unwrap(), param_pool)?;
+        match opt_name.as_str() {
+            "from" => from = Some(expr),
+            "max_depth" => {
+                let span = expr.span();
+                let depth = expr
+                    .eval_to_const()
+                    .map_err(|_| BadClosureMaxDepth(span))?
+                    .get_non_neg_int()
+                    .ok_or(BadClosureMaxDepth(span))?;
+                max_depth = Some(depth as i64);
+            }
+            n => bail!(UnknownClosureOption(n.to_string(), opt_name.extract_span())),
+        }
+    }
+
+    let (min_len, expected) = if from.is_some() {
+        (1, "one or two columns: the node reached and its depth")
+    } else {
+        (
+            2,
+            "two or three columns: the start, the node reached and its depth",
+        )
+    };
+    ensure!(
+        (head.len() == min_len || head.len() == min_len + 1) && aggr.iter().all(|a| a.is_none()),
+        BadClosureHead(expected, head.len(), head_span)
+    );
+
+    let gen_symb = |name: &str| Symbol::new(format!("*^*{name}"), span);
+    let bind = |var: &Symbol| Expr::Binding {
+        var: var.clone(),
+        tuple_pos: None,
+    };
+    let unify = |var: &Symbol, expr: Expr| InputAtom::Unification {
+        inner: Unification {
+            binding: var.clone(),
+            expr,
+            one_many_unif: false,
+            span,
+        },
+    };
+
+    // the source and the target are the variables of the edge atom, in the order written
+    let edge_vars = |edge: &InputAtom| -> Result<Vec<Symbol>> {
+        let mut vars = BTreeSet::new();
+        edge.collect_bindings(&mut vars)?;
+        Ok(vars
+            .into_iter()
+            .filter(|v| !v.is_ignored_symbol())
+            .sorted_by_key(|v| v.span.0)
+            .collect_vec())
+    };
+    let ends = edge_vars(&edge)?;
+    let src_var = gen_symb("src");
+    let dst_var = gen_symb("dst");
+    let rename = |arg: &mut Expr| {
+        if let Expr::Binding { var, .. } = arg {
+            if ends.len() == 2 && *var == ends[0] {
+                *var = src_var.clone();
+            } else if ends.len() == 2 && *var == ends[1] {
+                *var = dst_var.clone();
+            }
+        }
+    };
+    match &mut edge {
+        InputAtom::Rule { inner } => inner.args.iter_mut().for_each(rename),
+        InputAtom::Relation { inner } => inner.args.iter_mut().for_each(rename),
+        InputAtom::NamedFieldRelation { inner } => inner.args.values_mut().for_each(rename),
+        _ => unreachable!(),
+    }
+    // only variables bound directly are renamed, not those in expressions
+    let renamed = edge_vars(&edge)?;
+    ensure!(
+        renamed.len() == 2 && renamed.contains(&src_var) && renamed.contains(&dst_var),
+        BadClosureEdge(ends.iter().map(|v| v.name.to_string()).collect(), edge_span)
+    );
+
+    // the depth is needed to stop at `max_depth` even if it is not asked for,
+    // in which case the closure is computed by a helper rule
+    let mut core_head = head.clone();
+    if max_depth.is_some() && head.len() == min_len {
+        core_head.push(gen_symb("depth"));
+    }
+    let core_name = if core_head.len() > head.len() {
+        Symbol::new(format!("*^*{}", name.name), name.span)
+    } else {
+        name.clone()
+    };
+    let node = &core_head[min_len - 1];
+    let depth = core_head.get(min_len);
+    let mut core_aggr = vec![None; core_head.len()];
+    if depth.is_some() {
+        core_aggr[min_len] = Some((parse_aggr("min").unwrap().clone(), vec![]));
+    }
+    let depth_bound = |depth: &Symbol| {
+        max_depth.map(|max| InputAtom::Predicate {
+            inner: Expr::Apply {
+                op: &OP_LE,
+                args: [
+                    bind(depth),
+                    Expr::Const {
+                        val: DataValue::from(max),
+                        span,
+                    },
+                ]
+                .into(),
+                span,
+            },
+        })
+    };
+
+    let mut base = vec![edge.clone()];
+    match &from {
+        Some(start) => base.push(InputAtom::Predicate {
+            inner: Expr::build_equate(vec![bind(&src_var), start.clone()], span),
+        }),
+        None => base.push(unify(&core_head[0], bind(&src_var))),
+    }
+    base.push(unify(node, bind(&dst_var)));
+
+    let prev_depth = gen_symb("prev_depth");
+    let mut prev_args = vec![];
+    if from.is_none() {
+        prev_args.push(bind(&core_head[0]));
+    }
+    prev_args.push(bind(&src_var));
+    let mut step = vec![];
+    if let Some(depth) = depth {
+        prev_args.push(bind(&prev_depth));
+        base.push(unify(
+            depth,
+            Expr::Const {
+                val: DataValue::from(1),
+                span,
+            },
+        ));
+        base.extend(depth_bound(depth));
+        step.push(unify(
+            depth,
+            Expr::Apply {
+                op: &OP_ADD,
+                args: [
+                    bind(&prev_depth),
+                    Expr::Const {
+                        val: DataValue::from(1),
+                        span,
+                    },
+                ]
+                .into(),
+                span,
+            },
+        ));
+        step.extend(depth_bound(depth));
+    }
+    let mut recursive = vec![
+        InputAtom::Rule {
+            inner: InputRuleApplyAtom {
+                name: core_name.clone(),
+                args: prev_args,
+                span,
+            },
+        },
+        edge,
+        unify(node, bind(&dst_var)),
+    ];
+    recursive.extend(step);
+
+    let mut ret = vec![(
+        core_name.clone(),
+        vec![
+            InputInlineRule {
+                head: core_head.clone(),
+                aggr: core_aggr.clone(),
+                body: base,
+                span,
+            },
+            InputInlineRule {
+                head: core_head.clone(),
+                aggr: core_aggr,
+                body: recursive,
+                span,
+            },
+        ],
+    )];
+    if core_name != name {
+        ret.push((
+            name,
+            vec![InputInlineRule {
+                aggr: vec![None; head.len()],
+                body: vec![InputAtom::Rule {
+                    inner: InputRuleApplyAtom {
+                        name: core_name,
+                        args: core_head.iter().map(bind).collect(),
+                        span,
+                    },
+                }],
+                head,
+                span,
+            }],
+        ));
+    }
+    Ok(ret)
+}
+
 fn parse_disjunction(
     pair: Pair<'_>,
     param_pool: &BTreeMap<String, DataValue>,
@@ -611,6 +1325,8 @@ fn parse_atom(
             }
         }
         Rule::disjunction => parse_disjunction(src, param_pool, cur_vld, ignored_counter)?,
+        Rule::optional_atom => bail!(NestedOptional(src.extract_span())),
+        Rule::set_op_atom => parse_set_op_atom(src, param_pool, cur_vld, ignored_counter)?,
         Rule::negation => {
             let span = src.extract_span();
             let mut src = src.into_inner();
@@ -685,25 +1401,26 @@ fn parse_atom(
         Rule::relation_apply => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            let name = src.next().unwrap();
+            let name = relation_name(
+                &src.next().unwrap(),
+                Some('*'),
+                &[Rule::compound_or_index_ident, Rule::underscore_ident],
+                param_pool,
+            )?;
             let args: Vec<_> = src
                 .next()
                 .unwrap()
                 .into_inner()
                 .map(|v| build_expr(v, param_pool))
                 .try_collect()?;
-            let valid_at = match src.next() {
-                None => None,
-                Some(vld_clause) => {
-                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
-                    Some(expr2vld_spec(vld_expr, cur_vld)?)
-                }
-            };
+            let (valid_at, tx_at, hints) = parse_relation_clauses(src, param_pool, cur_vld)?;
             InputAtom::Relation {
                 inner: InputRelationApplyAtom {
-                    name: Symbol::new(&name.as_str()[1..], name.extract_span()),
+                    name,
                     args,
                     valid_at,
+                    tx_at,
+                    hints,
                     span,
                 },
             }
@@ -712,7 +1429,8 @@ fn parse_atom(
             let span = src.extract_span();
             let mut src = src.into_inner();
             let name_p = src.next().unwrap();
-            let name_segs = name_p.as_str().split(':').collect_vec();
+            let name = relation_name(&name_p, None, &[Rule::compound_or_index_ident], param_pool)?;
+            let name_segs = name.name.split(':').collect_vec();
 
             #[derive(Debug, Error, Diagnostic)]
             #[error("Search head must be of the form `relation_name:index_name`")]
@@ -748,27 +1466,27 @@ fn parse_atom(
         Rule::relation_named_apply => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            let name_p = src.next().unwrap();
-            let name = Symbol::new(&name_p.as_str()[1..], name_p.extract_span());
+            let name = relation_name(
+                &src.next().unwrap(),
+                Some('*'),
+                &[Rule::compound_or_index_ident, Rule::underscore_ident],
+                param_pool,
+            )?;
             let args = src
                 .next()
                 .unwrap()
                 .into_inner()
                 .map(|arg| extract_named_apply_arg(arg, param_pool))
                 .try_collect()?;
-            let valid_at = match src.next() {
-                None => None,
-                Some(vld_clause) => {
-                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
-                    Some(expr2vld_spec(vld_expr, cur_vld)?)
-                }
-            };
+            let (valid_at, tx_at, hints) = parse_relation_clauses(src, param_pool, cur_vld)?;
             InputAtom::NamedFieldRelation {
                 inner: InputNamedFieldRelationApplyAtom {
                     name,
                     args,
                     span,
                     valid_at,
+                    tx_at,
+                    hints,
                 },
             }
         }
@@ -866,6 +1584,11 @@ fn parse_fixed_rule(
     #[diagnostic(code(parser::fixed_aggr_conflict))]
     struct AggrInfixedError(#[label] SourceSpan);
 
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("The 'cache' option of fixed rules must be a boolean")]
+    #[diagnostic(code(parser::bad_cache_option))]
+    struct BadCacheOption(#[label] SourceSpan);
+
     #[derive(Debug, Error, Diagnostic)]
     #[error("fixed rule cannot have duplicate bindings")]
     #[diagnostic(code(parser::duplicate_bindings_for_fixed_rule))]
@@ -882,6 +1605,7 @@ fn parse_fixed_rule(
     let fixed_name = &name_pair.as_str();
     let mut rule_args: Vec<FixedRuleArg> = vec![];
     let mut options: BTreeMap<SmartString<LazyCompact>, Expr> = Default::default();
+    let mut cache = false;
     let args_list = src.next().unwrap();
     let args_list_span = args_list.extract_span();
 
@@ -948,10 +1672,12 @@ fn parse_fixed_rule(
                             }
                         }
                         rule_args.push(FixedRuleArg::Stored {
-                            name: Symbol::new(
-                                name.as_str().strip_prefix('*').unwrap(),
-                                name.extract_span(),
-                            ),
+                            name: relation_name(
+                                &name,
+                                Some('*'),
+                                &[Rule::compound_or_index_ident, Rule::underscore_ident],
+                                param_pool,
+                            )?,
                             bindings,
                             valid_at,
                             span,
@@ -994,10 +1720,12 @@ fn parse_fixed_rule(
                         }
 
                         rule_args.push(FixedRuleArg::NamedStored {
-                            name: Symbol::new(
-                                name.as_str().strip_prefix(':').unwrap(),
-                                name.extract_span(),
-                            ),
+                            name: relation_name(
+                                &name,
+                                Some('*'),
+                                &[Rule::compound_or_index_ident, Rule::underscore_ident],
+                                param_pool,
+                            )?,
                             bindings,
                             valid_at,
                             span,
@@ -1011,7 +1739,15 @@ fn parse_fixed_rule(
                 let name = inner.next().unwrap().as_str();
                 let val = inner.next().unwrap();
                 let val = build_expr(val, param_pool)?;
-                options.insert(SmartString::from(name), val);
+                if name == CACHE_OPTION {
+                    let span = val.span();
+                    cache = val
+                        .eval_to_const()?
+                        .get_bool()
+                        .ok_or(BadCacheOption(span))?;
+                } else {
+                    options.insert(SmartString::from(name), val);
+                }
             }
             _ => unreachable!(),
         }
@@ -1029,6 +1765,30 @@ fn parse_fixed_rule(
         head.is_empty() || arity == head.len(),
         FixedRuleHeadArityMismatch(arity, head.len(), args_list_span)
     );
+    let head = match fixed_impl.output_columns(&options) {
+        Some(names) if head.is_empty() => {
+            ensure!(
+                names.len() == arity,
+                FixedRuleHeadArityMismatch(arity, names.len(), args_list_span)
+            );
+            names
+                .into_iter()
+                .map(|name| Symbol::new(name, name_pair.extract_span()))
+                .collect()
+        }
+        _ => head,
+    };
+
+    let cache = if cache {
+        Some(FixedRuleCacheSpec::new(
+            &fixed.name,
+            &rule_args,
+            &options,
+            args_list_span,
+        )?)
+    } else {
+        None
+    };
 
     Ok((
         out_symbol,
@@ -1040,6 +1800,7 @@ fn parse_fixed_rule(
             arity,
             span: args_list_span,
             fixed_impl: fixed_impl.clone(),
+            cache,
         },
     ))
 }
@@ -1078,11 +1839,127 @@ fn make_empty_const_rule(prog: &mut InputProgram, bindings: &[Symbol]) {
                 arity: bindings.len(),
                 span: Default::default(),
                 fixed_impl: Arc::new(Box::new(Constant)),
+                cache: None,
             },
         },
     );
 }
 
+/// Parse the optional valid time (`@`) and transaction time (`@@`) clauses of a relation
+/// application, and the hints following it
+fn parse_relation_clauses(
+    src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    cur_vld: ValidityTs,
+) -> Result<(Option<ValidityTs>, Option<ValidityTs>, RelationHints)> {
+    let mut valid_at = None;
+    let mut tx_at = None;
+    let mut hints = RelationHints::default();
+    for clause in src {
+        let rule = clause.as_rule();
+        if rule == Rule::atom_hints {
+            hints = parse_hints(clause)?;
+            continue;
+        }
+        let expr = build_expr(clause.into_inner().next().unwrap(), param_pool)?;
+        let ts = expr2vld_spec(expr, cur_vld)?;
+        match rule {
+            Rule::validity_clause => valid_at = Some(ts),
+            Rule::tx_time_clause => tx_at = Some(ts),
+            r => unreachable!("{:?}", r),
+        }
+    }
+    Ok((valid_at, tx_at, hints))
+}
+
+/// The name of a relation as written, without its leading `sigil` if any. A name written
+/// as a parameter is replaced by the value of the parameter, which must be a string matched
+/// by one of `rules`, so that parameters cannot put anything but a name into the script.
+fn relation_name(
+    name_p: &Pair<'_>,
+    sigil: Option<char>,
+    rules: &[Rule],
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<Symbol> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Required parameter {0} not found")]
+    #[diagnostic(code(parser::param_not_found))]
+    struct RelationParamNotFound(String, #[label] SourceSpan);
+
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Parameter {0} does not hold the name of a relation: {1}")]
+    #[diagnostic(code(parser::bad_relation_param))]
+    #[diagnostic(help("Give the name as a string, such as 'friends' or 'friends:rev'"))]
+    struct BadRelationParam(String, DataValue, #[label] SourceSpan);
+
+    let span = name_p.extract_span();
+    let name = name_p.as_str();
+    let name = match sigil {
+        Some(c) => name.strip_prefix(c).unwrap_or(name),
+        None => name,
+    };
+    let param = match name.strip_prefix('$') {
+        None => return Ok(Symbol::new(name, span)),
+        Some(param) => param,
+    };
+    let value = param_pool
+        .get(param)
+        .ok_or_else(|| RelationParamNotFound(param.to_string(), span))?;
+    match value {
+        DataValue::Str(s) if rules.iter().any(|rule| matches_rule(s, *rule)) => {
+            Ok(Symbol::new(s.clone(), span))
+        }
+        v => bail!(BadRelationParam(param.to_string(), v.clone(), span)),
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid hint '{0}'")]
+#[diagnostic(code(parser::invalid_hint))]
+#[diagnostic(help("Hints are 'use_index(<index>)', 'no_index' and 'broadcast'"))]
+struct InvalidHint(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Conflicting hints: {0}")]
+#[diagnostic(code(parser::conflicting_hints))]
+struct ConflictingHints(String, #[label] SourceSpan);
+
+fn parse_hints(src: Pair<'_>) -> Result<RelationHints> {
+    let span = src.extract_span();
+    let mut hints = RelationHints::default();
+    for hint in src.into_inner() {
+        let hint_span = hint.extract_span();
+        let hint_str = hint.as_str();
+        let mut inner = hint.into_inner();
+        let name = inner.next().unwrap().as_str();
+        let arg = inner.next();
+        let index = match (name, arg) {
+            ("use_index", Some(idx)) => {
+                IndexHint::UseIndex(Symbol::new(idx.as_str(), idx.extract_span()))
+            }
+            ("no_index", None) => IndexHint::NoIndex,
+            ("broadcast", None) => {
+                hints.broadcast = true;
+                continue;
+            }
+            _ => bail!(InvalidHint(hint_str.to_string(), hint_span)),
+        };
+        ensure!(
+            hints.index == IndexHint::Auto,
+            ConflictingHints("more than one index is hinted".to_string(), span)
+        );
+        hints.index = index;
+    }
+    ensure!(
+        !(hints.broadcast && matches!(hints.index, IndexHint::UseIndex(_))),
+        ConflictingHints(
+            "a broadcast relation is read in full, not through an index".to_string(),
+            span
+        )
+    );
+    Ok(hints)
+}
+
 fn expr2vld_spec(expr: Expr, cur_vld: ValidityTs) -> Result<ValidityTs> {
     let vld_span = expr.span();
     match expr.eval_to_const()? {
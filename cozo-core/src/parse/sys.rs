@@ -7,10 +7,13 @@
  */
 
 use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use itertools::Itertools;
-use miette::{bail, ensure, miette, Diagnostic, Result};
+use log::LevelFilter;
+use miette::{bail, ensure, miette, Diagnostic, IntoDiagnostic, Result};
 use ordered_float::OrderedFloat;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
@@ -18,35 +21,288 @@ use thiserror::Error;
 use crate::data::program::InputProgram;
 use crate::data::relation::VecElementType;
 use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fts::TokenizerConfig;
 use crate::parse::expr::{build_expr, parse_string};
 use crate::parse::query::parse_query;
-use crate::parse::{ExtractSpan, Pairs, Rule, SourceSpan};
+use crate::parse::{parse_script, ExtractSpan, Pairs, Rule, SourceSpan};
+use crate::runtime::history::{RetentionPolicy, MICROS_PER_DAY};
+use crate::runtime::import::sanitize_name;
+use crate::runtime::masking::MaskPolicy;
+use crate::runtime::pinned::DEFAULT_MAX_PINNED_BYTES;
 use crate::runtime::relation::AccessLevel;
+use crate::runtime::trigger::{TriggerDef, TriggerErrorPolicy, TriggerKind, TriggerOptions};
+use crate::storage::PartitionScheme;
 use crate::{Expr, FixedRule};
 
 #[derive(Debug)]
 pub(crate) enum SysOp {
     Compact,
+    /// Check the stored data for corruption now
+    Scrub,
+    ListConfig,
+    SetConfig(SmartString<LazyCompact>, DataValue),
+    ReloadConfig,
+    /// Show the level of the messages logged, or set it
+    LogLevel(Option<LevelFilter>),
+    ListPartitions,
+    /// Partition the relation, or put its partitions back together with `None`
+    SetPartitions(Symbol, Option<PartitionScheme>),
+    ListPinned,
+    /// Keep the rows of the relation in memory, up to the number of bytes given
+    PinRelation(Symbol, usize),
+    UnpinRelation(Symbol),
     ListColumns(Symbol),
     ListIndices(Symbol),
     ListRelations,
+    ListUsage,
+    /// Suggest indices for the workload in the replay log at the path, or the current one
+    AdviseIndexes(Option<String>),
     ListRunning,
     ListFixedRules,
     KillRunning(u64),
     Explain(Box<InputProgram>),
     RemoveRelation(Vec<Symbol>),
     RenameRelation(Vec<(Symbol, Symbol)>),
+    /// Copy the first relation into a new one named by the second. All rows are copied, in
+    /// time and space proportional to the relation: it is not a copy-on-write branch
+    CloneRelation(Symbol, Symbol),
+    ListBranches,
+    /// Copy the database into a new branch with the name
+    CreateBranch(SmartString<LazyCompact>),
+    MergeBranch(SmartString<LazyCompact>),
+    DropBranch(SmartString<LazyCompact>),
     ShowTrigger(Symbol),
-    SetTriggers(Symbol, Vec<String>, Vec<String>, Vec<String>),
+    ListTriggers,
+    SetTriggers(Symbol, Vec<TriggerDef>),
+    /// The relation, the name of the trigger, and whether it is enabled
+    SetTriggerEnabled(Symbol, SmartString<LazyCompact>, bool),
     SetAccessLevel(Vec<Symbol>, AccessLevel),
     CreateIndex(Symbol, Symbol, Vec<Symbol>),
     CreateVectorIndex(HnswIndexConfig),
     CreateFtsIndex(FtsIndexConfig),
     CreateMinHashLshIndex(MinHashLshConfig),
     RemoveIndex(Symbol, Symbol),
-    DescribeRelation(Symbol, SmartString<LazyCompact>)
+    /// Fill an index of any kind again from the rows of its relation
+    RebuildIndex(Symbol, Symbol),
+    DescribeRelation(Symbol, SmartString<LazyCompact>),
+    /// The relation, the column and its description
+    DescribeColumn(Symbol, Symbol, SmartString<LazyCompact>),
+    SetRetention(Symbol, Option<RetentionPolicy>),
+    PruneHistory(Symbol, Option<RetentionPolicy>),
+    ListFixedRuleCache,
+    DropFixedRuleCache,
+    ListJobs,
+    /// The script, the parameters it is run with, and whether it requires admin
+    SubmitJob(String, BTreeMap<String, DataValue>, bool),
+    JobResult(u64),
+    KillJob(u64),
+    InferImport(ImportInferConfig),
+    ExportChunked(ChunkedExportConfig),
+    ExportGraph(GraphExportConfig),
+    ImportGraph(GraphImportConfig),
+    ImportRdf(RdfImportConfig),
+    /// Encrypt the columns given with the named keys, or decrypt those given with `None`
+    EncryptColumns(
+        Symbol,
+        BTreeMap<SmartString<LazyCompact>, Option<SmartString<LazyCompact>>>,
+    ),
+    ListMasks(Symbol),
+    /// Mask the columns given, or unmask those given with `None`
+    SetMasks(
+        Symbol,
+        BTreeMap<SmartString<LazyCompact>, Option<MaskPolicy>>,
+    ),
+    Diff(DiffConfig),
+    Test(Vec<TestStmt>),
+    /// The view and its query
+    CreateMaterialized(Symbol, String),
+    /// Evaluate the query of the view again, and write the rows that changed
+    RefreshMaterialized(Symbol),
+}
+
+impl SysOp {
+    /// Destructive operations that drop data or schema wholesale or affect other sessions,
+    /// and all operations reading or writing files on the server
+    pub(crate) fn requires_admin(&self) -> bool {
+        match self {
+            SysOp::Compact
+            | SysOp::Scrub
+            | SysOp::SetConfig(..)
+            | SysOp::ReloadConfig
+            | SysOp::LogLevel(Some(_))
+            | SysOp::SetPartitions(..)
+            | SysOp::PinRelation(..)
+            | SysOp::UnpinRelation(_)
+            | SysOp::KillRunning(_)
+            | SysOp::KillJob(_)
+            | SysOp::RemoveRelation(_)
+            | SysOp::RenameRelation(_)
+            | SysOp::SetAccessLevel(..)
+            | SysOp::RemoveIndex(..)
+            | SysOp::PruneHistory(..)
+            | SysOp::CreateBranch(_)
+            | SysOp::MergeBranch(_)
+            | SysOp::DropBranch(_)
+            | SysOp::ExportChunked(_)
+            | SysOp::ExportGraph(_)
+            | SysOp::InferImport(_)
+            | SysOp::ImportGraph(_)
+            | SysOp::ImportRdf(_)
+            | SysOp::Diff(_)
+            | SysOp::AdviseIndexes(_)
+            | SysOp::EncryptColumns(..)
+            | SysOp::SetMasks(..) => true,
+            SysOp::SubmitJob(_, _, requires_admin) => *requires_admin,
+            SysOp::Explain(_)
+            | SysOp::CloneRelation(..)
+            | SysOp::ListBranches
+            | SysOp::ListConfig
+            | SysOp::LogLevel(None)
+            | SysOp::ListPartitions
+            | SysOp::ListPinned
+            | SysOp::ListColumns(_)
+            | SysOp::ListIndices(_)
+            | SysOp::ListRelations
+            | SysOp::ListUsage
+            | SysOp::ListRunning
+            | SysOp::ListFixedRules
+            | SysOp::ListFixedRuleCache
+            | SysOp::DropFixedRuleCache
+            | SysOp::ListJobs
+            | SysOp::JobResult(_)
+            | SysOp::ListMasks(_)
+            | SysOp::Test(_)
+            | SysOp::ShowTrigger(_)
+            | SysOp::ListTriggers
+            | SysOp::SetTriggers(..)
+            | SysOp::SetTriggerEnabled(..)
+            | SysOp::CreateIndex(..)
+            | SysOp::CreateVectorIndex(_)
+            | SysOp::CreateFtsIndex(_)
+            | SysOp::CreateMinHashLshIndex(_)
+            | SysOp::RebuildIndex(..)
+            | SysOp::DescribeRelation(..)
+            | SysOp::DescribeColumn(..)
+            | SysOp::SetRetention(..)
+            | SysOp::CreateMaterialized(..)
+            | SysOp::RefreshMaterialized(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ImportFormat {
+    /// Delimited text with a header line, with the delimiter
+    Csv(u8),
+    /// One JSON object on each line
+    Ndjson,
+    /// A Parquet file, read with the `arrow` feature
+    Parquet,
+}
+
+/// A statement of a `::test` script. Assertions are named by the optional string.
+#[derive(Debug)]
+pub(crate) enum TestStmt {
+    /// A query run for its effects, which are discarded with all others at the end
+    Setup(InputProgram),
+    /// The query must return exactly these rows
+    AssertRows(Option<String>, InputProgram, Vec<Tuple>),
+    /// The two queries must return the same rows
+    AssertEq(Option<String>, InputProgram, InputProgram),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ImportInferConfig {
+    pub(crate) path: String,
+    /// Detected from the file when not given
+    pub(crate) format: Option<ImportFormat>,
+    pub(crate) relation: SmartString<LazyCompact>,
+    /// The key columns, chosen among the columns with unique values when not given
+    pub(crate) keys: Option<Vec<SmartString<LazyCompact>>>,
+    /// How many records the schema is inferred from
+    pub(crate) sample: usize,
+    /// Whether to create the relation and load the file into it
+    pub(crate) create: bool,
+}
+
+/// The file formats of graphs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum GraphFormat {
+    GraphMl,
+    Dot,
+    /// The JSON Graph Format
+    Json,
+    /// One edge on each line, the nodes separated by whitespace or commas, then the weight
+    EdgeList,
+}
+
+impl GraphFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "graphml" => GraphFormat::GraphMl,
+            "dot" | "gv" => GraphFormat::Dot,
+            "json" => GraphFormat::Json,
+            "edges" | "edgelist" | "txt" | "csv" | "tsv" => GraphFormat::EdgeList,
+            _ => return None,
+        })
+    }
+}
+
+/// The number of rows in each chunk of `::export chunked` when not given
+pub(crate) const DEFAULT_ROWS_PER_CHUNK: usize = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ChunkedExportConfig {
+    pub(crate) relation: SmartString<LazyCompact>,
+    /// The directory the chunks and the manifest are written into
+    pub(crate) dir: String,
+    pub(crate) rows_per_chunk: usize,
+    /// Whether to start over instead of resuming the export already in the directory
+    pub(crate) restart: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct GraphExportConfig {
+    /// The relation of edges: its first two columns are the nodes, the others the attributes
+    pub(crate) relation: SmartString<LazyCompact>,
+    pub(crate) path: String,
+    pub(crate) format: GraphFormat,
+    /// The relation of nodes: its first column is the node, the others the attributes
+    pub(crate) nodes: Option<SmartString<LazyCompact>>,
+    pub(crate) directed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct GraphImportConfig {
+    pub(crate) path: String,
+    /// Either GraphML or an edge list
+    pub(crate) format: GraphFormat,
+    /// The relation of edges to create
+    pub(crate) relation: SmartString<LazyCompact>,
+    /// The relation of nodes to create, with the attributes of nodes in GraphML
+    pub(crate) nodes: Option<SmartString<LazyCompact>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct RdfImportConfig {
+    /// An N-Triples or Turtle file
+    pub(crate) path: String,
+    /// The relation of triples `{s, p, o}`, holding the ids of terms
+    pub(crate) relation: SmartString<LazyCompact>,
+    /// The relation of terms `{id => kind, value, datatype, lang}`
+    pub(crate) terms: SmartString<LazyCompact>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct DiffConfig {
+    /// The file of the other database, in the format of backups
+    pub(crate) path: String,
+    /// How many differing rows are given for each relation, none by default
+    pub(crate) rows: usize,
+    /// The relations compared, all of them when not given
+    pub(crate) relations: Option<Vec<SmartString<LazyCompact>>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -101,6 +357,17 @@ pub(crate) enum HnswDistance {
 #[diagnostic(code(parser::not_proc_id))]
 struct ProcessIdError(String, #[label] SourceSpan);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The rows expected by an assertion must be given as a list of lists")]
+#[diagnostic(code(parser::bad_expected_rows))]
+struct ExpectedRowsError(#[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid log level '{0}'")]
+#[diagnostic(code(parser::invalid_log_level))]
+#[diagnostic(help("The level is one of off, error, warn, info, debug and trace"))]
+struct InvalidLogLevel(String, #[label] SourceSpan);
+
 pub(crate) fn parse_sys(
     mut src: Pairs<'_>,
     param_pool: &BTreeMap<String, DataValue>,
@@ -110,7 +377,102 @@ pub(crate) fn parse_sys(
     let inner = src.next().unwrap();
     Ok(match inner.as_rule() {
         Rule::compact_op => SysOp::Compact,
+        Rule::scrub_op => SysOp::Scrub,
+        Rule::config_op => match inner.into_inner().next() {
+            None => SysOp::ListConfig,
+            Some(reload) if reload.as_rule() == Rule::config_reload => SysOp::ReloadConfig,
+            Some(set) => {
+                let mut src = set.into_inner();
+                let name = SmartString::from(src.next().unwrap().as_str());
+                let value = build_expr(src.next().unwrap(), param_pool)?.eval_to_const()?;
+                SysOp::SetConfig(name, value)
+            }
+        },
+        Rule::log_op => match inner.into_inner().next() {
+            None => SysOp::LogLevel(None),
+            Some(level_p) => match LevelFilter::from_str(level_p.as_str()) {
+                Ok(level) => SysOp::LogLevel(Some(level)),
+                Err(_) => bail!(InvalidLogLevel(
+                    level_p.as_str().to_string(),
+                    level_p.extract_span()
+                )),
+            },
+        },
+        Rule::partition_op => match inner.into_inner().next() {
+            None => SysOp::ListPartitions,
+            Some(op) => {
+                let is_create = op.as_rule() == Rule::partition_create;
+                let mut src = op.into_inner();
+                let rel_p = src.next().unwrap();
+                let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+                let scheme = if is_create {
+                    Some(parse_partition_opts(
+                        src.next().unwrap().into_inner(),
+                        param_pool,
+                    )?)
+                } else {
+                    None
+                };
+                SysOp::SetPartitions(rel, scheme)
+            }
+        },
+        Rule::pin_op => {
+            let mut src = inner.into_inner();
+            match src.next() {
+                None => SysOp::ListPinned,
+                Some(rel_p) => {
+                    let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+                    let max_bytes = match src.next() {
+                        None => DEFAULT_MAX_PINNED_BYTES,
+                        Some(opts) => parse_pin_opts(opts.into_inner(), param_pool)?,
+                    };
+                    SysOp::PinRelation(rel, max_bytes)
+                }
+            }
+        }
+        Rule::unpin_op => {
+            let rel_p = inner.into_inner().next().unwrap();
+            SysOp::UnpinRelation(Symbol::new(rel_p.as_str(), rel_p.extract_span()))
+        }
+        Rule::cache_op => {
+            if inner.into_inner().next().is_some() {
+                SysOp::DropFixedRuleCache
+            } else {
+                SysOp::ListFixedRuleCache
+            }
+        }
         Rule::running_op => SysOp::ListRunning,
+        Rule::jobs_op => SysOp::ListJobs,
+        Rule::job_op => {
+            let inner = inner.into_inner().next().unwrap();
+            let rule = inner.as_rule();
+            let expr = build_expr(inner.into_inner().next().unwrap(), param_pool)?;
+            let span = expr.span();
+            let val = expr.eval_to_const()?;
+            match rule {
+                Rule::job_submit => {
+                    let script = val
+                        .get_str()
+                        .ok_or_else(|| miette!("The script of a job must be a string"))?
+                        .to_string();
+                    // fail early on syntax errors, and find out what the job will be allowed to do
+                    let requires_admin =
+                        parse_script(&script, param_pool, algorithms, cur_vld)?.requires_admin();
+                    SysOp::SubmitJob(script, param_pool.clone(), requires_admin)
+                }
+                rule => {
+                    let id = match val.get_int() {
+                        Some(id) if id >= 0 => id as u64,
+                        _ => bail!(ProcessIdError(val.to_string(), span)),
+                    };
+                    if rule == Rule::job_kill {
+                        SysOp::KillJob(id)
+                    } else {
+                        SysOp::JobResult(id)
+                    }
+                }
+            }
+        }
         Rule::kill_op => {
             let i_expr = inner.into_inner().next().unwrap();
             let i_val = build_expr(i_expr, param_pool)?;
@@ -139,7 +501,36 @@ pub(crate) fn parse_sys(
             };
             SysOp::DescribeRelation(rel, description)
         }
+        Rule::describe_column_op => {
+            let mut inner = inner.into_inner().skip(1);
+            let rel_p = inner.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let col_p = inner.next().unwrap();
+            let col = Symbol::new(col_p.as_str(), col_p.extract_span());
+            let description = match inner.next() {
+                None => Default::default(),
+                Some(desc_p) => parse_string(desc_p)?,
+            };
+            SysOp::DescribeColumn(rel, col, description)
+        }
         Rule::list_relations_op => SysOp::ListRelations,
+        Rule::list_usage_op => SysOp::ListUsage,
+        Rule::advise_op => {
+            let path = match inner.into_inner().next() {
+                None => None,
+                Some(path_p) => Some(parse_string(path_p)?.to_string()),
+            };
+            SysOp::AdviseIndexes(path)
+        }
+        Rule::list_triggers_op => SysOp::ListTriggers,
+        Rule::trigger_toggle_op => {
+            let mut src = inner.into_inner();
+            let enabled = src.next().unwrap().as_rule() == Rule::trigger_enable;
+            let rel_p = src.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let name = SmartString::from(src.next().unwrap().as_str());
+            SysOp::SetTriggerEnabled(rel, name, enabled)
+        }
         Rule::remove_relations_op => {
             let rel = inner
                 .into_inner()
@@ -172,6 +563,27 @@ pub(crate) fn parse_sys(
                 .collect_vec();
             SysOp::RenameRelation(rename_pairs)
         }
+        Rule::clone_relation_op => {
+            let mut src = inner.into_inner();
+            let rels_p = src.next().unwrap();
+            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let rels_p = src.next().unwrap();
+            let new_rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            SysOp::CloneRelation(rel, new_rel)
+        }
+        Rule::branch_op => match inner.into_inner().next() {
+            None => SysOp::ListBranches,
+            Some(op) => {
+                let rule = op.as_rule();
+                let name = SmartString::from(op.into_inner().next().unwrap().as_str());
+                match rule {
+                    Rule::branch_create => SysOp::CreateBranch(name),
+                    Rule::branch_merge => SysOp::MergeBranch(name),
+                    Rule::branch_drop => SysOp::DropBranch(name),
+                    _ => unreachable!(),
+                }
+            }
+        },
         Rule::access_level_op => {
             let mut ps = inner.into_inner();
             let access_level = match ps.next().unwrap().as_str() {
@@ -197,28 +609,71 @@ pub(crate) fn parse_sys(
             let mut src = inner.into_inner();
             let rels_p = src.next().unwrap();
             let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
-            let mut puts = vec![];
-            let mut rms = vec![];
-            let mut replaces = vec![];
+            let mut triggers = vec![];
             for clause in src {
                 let mut clause_inner = clause.into_inner();
-                let op = clause_inner.next().unwrap();
-                let script = clause_inner.next().unwrap();
-                let script_str = script.as_str();
+                let kind = match clause_inner.next().unwrap().as_rule() {
+                    Rule::trigger_put => TriggerKind::Put,
+                    Rule::trigger_rm => TriggerKind::Rm,
+                    Rule::trigger_replace => TriggerKind::Replace,
+                    r => unreachable!("{:?}", r),
+                };
+                let mut options = TriggerOptions::default();
+                let mut script = None;
+                for p in clause_inner {
+                    match p.as_rule() {
+                        Rule::trigger_name => options.name = Some(SmartString::from(p.as_str())),
+                        Rule::trigger_order => {
+                            let order = p.into_inner().next().unwrap();
+                            options.order = order
+                                .as_str()
+                                .replace('_', "")
+                                .parse::<i64>()
+                                .into_diagnostic()?;
+                        }
+                        Rule::trigger_on_error => {
+                            options.on_error = match p.into_inner().next().unwrap().as_rule() {
+                                Rule::trigger_abort => TriggerErrorPolicy::Abort,
+                                Rule::trigger_log => TriggerErrorPolicy::Log,
+                                r => unreachable!("{:?}", r),
+                            }
+                        }
+                        _ => script = Some(p),
+                    }
+                }
+                let script = script.unwrap();
+                let script_str = script.as_str().to_string();
                 parse_query(
                     script.into_inner(),
                     &Default::default(),
                     algorithms,
                     cur_vld,
                 )?;
-                match op.as_rule() {
-                    Rule::trigger_put => puts.push(script_str.to_string()),
-                    Rule::trigger_rm => rms.push(script_str.to_string()),
-                    Rule::trigger_replace => replaces.push(script_str.to_string()),
-                    r => unreachable!("{:?}", r),
-                }
+                triggers.push(TriggerDef {
+                    kind,
+                    script: script_str,
+                    options,
+                });
             }
-            SysOp::SetTriggers(rel, puts, rms, replaces)
+            SysOp::SetTriggers(rel, triggers)
+        }
+        Rule::create_materialized_op => {
+            let mut src = inner.into_inner();
+            let name_p = src.next().unwrap();
+            let name = Symbol::new(name_p.as_str(), name_p.extract_span());
+            let script = src.next().unwrap();
+            let script_str = script.as_str().to_string();
+            parse_query(
+                script.into_inner(),
+                &Default::default(),
+                algorithms,
+                cur_vld,
+            )?;
+            SysOp::CreateMaterialized(name, script_str)
+        }
+        Rule::refresh_materialized_op => {
+            let name_p = inner.into_inner().next().unwrap();
+            SysOp::RefreshMaterialized(Symbol::new(name_p.as_str(), name_p.extract_span()))
         }
         Rule::lsh_idx_op => {
             let inner = inner.into_inner().next().unwrap();
@@ -666,10 +1121,609 @@ pub(crate) fn parse_sys(
                         Symbol::new(name.as_str(), name.extract_span()),
                     )
                 }
+                Rule::index_rebuild => {
+                    let mut inner = inner.into_inner();
+                    let rel = inner.next().unwrap();
+                    let name = inner.next().unwrap();
+                    SysOp::RebuildIndex(
+                        Symbol::new(rel.as_str(), rel.extract_span()),
+                        Symbol::new(name.as_str(), name.extract_span()),
+                    )
+                }
                 _ => unreachable!(),
             }
         }
         Rule::list_fixed_rules => SysOp::ListFixedRules,
+        Rule::history_op => {
+            let inner = inner.into_inner().next().unwrap();
+            let is_prune = inner.as_rule() == Rule::history_prune;
+            let mut inner = inner.into_inner();
+            let rel_p = inner.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let policy = match inner.next() {
+                None => None,
+                Some(opts) => Some(parse_retention_opts(opts.into_inner(), param_pool)?),
+            };
+            if is_prune {
+                SysOp::PruneHistory(rel, policy)
+            } else {
+                SysOp::SetRetention(rel, policy)
+            }
+        }
+        Rule::import_op => {
+            let mut inner = inner.into_inner();
+            let path_expr = build_expr(inner.next().unwrap(), param_pool)?;
+            let path = match path_expr.eval_to_const()? {
+                DataValue::Str(s) => s,
+                v => bail!("The path of the file to import must be a string, got {v}"),
+            };
+            let path = path
+                .strip_prefix("file://")
+                .unwrap_or(path.as_str())
+                .to_string();
+            let mut config = ImportInferConfig {
+                relation: SmartString::from(sanitize_name(
+                    Path::new(&path)
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .unwrap_or_default(),
+                    'r',
+                )),
+                path,
+                format: None,
+                keys: None,
+                sample: 1000,
+                create: false,
+            };
+            if let Some(opts) = inner.next() {
+                parse_import_opts(opts.into_inner(), param_pool, &mut config)?;
+            }
+            SysOp::InferImport(config)
+        }
+        Rule::export_op => {
+            let mut inner = inner.into_inner();
+            let relation = SmartString::from(inner.next().unwrap().as_str());
+            let dir = match build_expr(inner.next().unwrap(), param_pool)?.eval_to_const()? {
+                DataValue::Str(s) => s,
+                v => bail!("The directory to export into must be a string, got {v}"),
+            };
+            let mut config = ChunkedExportConfig {
+                relation,
+                dir: dir
+                    .strip_prefix("file://")
+                    .unwrap_or(dir.as_str())
+                    .to_string(),
+                rows_per_chunk: DEFAULT_ROWS_PER_CHUNK,
+                restart: false,
+            };
+            if let Some(opts) = inner.next() {
+                parse_export_opts(opts.into_inner(), param_pool, &mut config)?;
+            }
+            SysOp::ExportChunked(config)
+        }
+        Rule::graph_op => {
+            let inner = inner.into_inner().next().unwrap();
+            let is_export = inner.as_rule() == Rule::graph_export;
+            let mut inner = inner.into_inner();
+            let relation = if is_export {
+                Some(SmartString::from(inner.next().unwrap().as_str()))
+            } else {
+                None
+            };
+            let path_expr = build_expr(inner.next().unwrap(), param_pool)?;
+            let path = match path_expr.eval_to_const()? {
+                DataValue::Str(s) => s,
+                v => bail!("The path of the graph file must be a string, got {v}"),
+            };
+            let path = path
+                .strip_prefix("file://")
+                .unwrap_or(path.as_str())
+                .to_string();
+            let mut opts = GraphOpts::default();
+            if let Some(opts_p) = inner.next() {
+                parse_graph_opts(opts_p.into_inner(), param_pool, &mut opts)?;
+            }
+            let format = match opts.format {
+                Some(format) => format,
+                None => Path::new(&path)
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .and_then(|ext| GraphFormat::from_name(&ext.to_lowercase()))
+                    .ok_or_else(|| {
+                        miette!("Cannot tell the format of the graph file {path}, give it with the `format` option")
+                    })?,
+            };
+            match relation {
+                Some(relation) => {
+                    ensure!(
+                        opts.relation.is_none(),
+                        "Unknown option relation for graph export"
+                    );
+                    SysOp::ExportGraph(GraphExportConfig {
+                        relation,
+                        path,
+                        format,
+                        nodes: opts.nodes,
+                        directed: opts.directed.unwrap_or(true),
+                    })
+                }
+                None => {
+                    ensure!(
+                        matches!(format, GraphFormat::GraphMl | GraphFormat::EdgeList),
+                        "Only GraphML files and edge lists can be imported"
+                    );
+                    ensure!(
+                        opts.directed.is_none(),
+                        "Unknown option directed for graph import"
+                    );
+                    let relation = match opts.relation {
+                        Some(relation) => relation,
+                        None => SmartString::from(sanitize_name(
+                            Path::new(&path)
+                                .file_stem()
+                                .and_then(|stem| stem.to_str())
+                                .unwrap_or_default(),
+                            'r',
+                        )),
+                    };
+                    SysOp::ImportGraph(GraphImportConfig {
+                        path,
+                        format,
+                        relation,
+                        nodes: opts.nodes,
+                    })
+                }
+            }
+        }
+        Rule::rdf_op => {
+            let mut inner = inner.into_inner();
+            let path_expr = build_expr(inner.next().unwrap(), param_pool)?;
+            let path = match path_expr.eval_to_const()? {
+                DataValue::Str(s) => s,
+                v => bail!("The path of the RDF file must be a string, got {v}"),
+            };
+            let path = path
+                .strip_prefix("file://")
+                .unwrap_or(path.as_str())
+                .to_string();
+            let mut relation = None;
+            let mut terms = None;
+            if let Some(opts_p) = inner.next() {
+                for opt_pair in opts_p.into_inner() {
+                    let mut opt_inner = opt_pair.into_inner();
+                    let opt_name = opt_inner.next().unwrap();
+                    let opt_val = opt_inner.next().unwrap();
+                    let opt_val_str = opt_val.as_str();
+                    let v = build_expr(opt_val, param_pool)?.eval_to_const()?;
+                    let name = v
+                        .get_str()
+                        .map(SmartString::from)
+                        .ok_or_else(|| miette!("Invalid {}: {}", opt_name.as_str(), opt_val_str))?;
+                    match opt_name.as_str() {
+                        "relation" => relation = Some(name),
+                        "terms" => terms = Some(name),
+                        _ => bail!("Unknown option {} for rdf import", opt_name.as_str()),
+                    }
+                }
+            }
+            let relation = match relation {
+                Some(relation) => relation,
+                None => SmartString::from(sanitize_name(
+                    Path::new(&path)
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .unwrap_or_default(),
+                    'r',
+                )),
+            };
+            let terms = terms.unwrap_or_else(|| SmartString::from(format!("{relation}_terms")));
+            SysOp::ImportRdf(RdfImportConfig {
+                path,
+                relation,
+                terms,
+            })
+        }
+        Rule::encrypt_op => {
+            let mut inner = inner.into_inner();
+            let rel_p = inner.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let mut columns = BTreeMap::new();
+            for opt_pair in inner.next().unwrap().into_inner() {
+                let mut opt_inner = opt_pair.into_inner();
+                let col = SmartString::from(opt_inner.next().unwrap().as_str());
+                let opt_val = opt_inner.next().unwrap();
+                let opt_val_str = opt_val.as_str();
+                let key = match build_expr(opt_val, param_pool)?.eval_to_const()? {
+                    DataValue::Null => None,
+                    DataValue::Str(key) => Some(key),
+                    _ => bail!("Invalid key for the column {col}: {opt_val_str}"),
+                };
+                columns.insert(col, key);
+            }
+            SysOp::EncryptColumns(rel, columns)
+        }
+        Rule::mask_op => {
+            let mut inner = inner.into_inner();
+            let rel_p = inner.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            match inner.next() {
+                None => SysOp::ListMasks(rel),
+                Some(opts_p) => {
+                    let mut columns = BTreeMap::new();
+                    for opt_pair in opts_p.into_inner() {
+                        let mut opt_inner = opt_pair.into_inner();
+                        let col = SmartString::from(opt_inner.next().unwrap().as_str());
+                        let opt_val = opt_inner.next().unwrap();
+                        let opt_val_str = opt_val.as_str();
+                        let mask = match build_expr(opt_val, param_pool)?.eval_to_const()? {
+                            DataValue::Null => None,
+                            DataValue::Str(mask) => Some(MaskPolicy::parse(&mask)?),
+                            _ => bail!("Invalid mask for the column {col}: {opt_val_str}"),
+                        };
+                        columns.insert(col, mask);
+                    }
+                    SysOp::SetMasks(rel, columns)
+                }
+            }
+        }
+        Rule::diff_op => {
+            let mut inner = inner.into_inner();
+            let path_expr = build_expr(inner.next().unwrap(), param_pool)?;
+            let path = match path_expr.eval_to_const()? {
+                DataValue::Str(s) => s,
+                v => bail!("The path of the database to compare with must be a string, got {v}"),
+            };
+            let mut config = DiffConfig {
+                path: path
+                    .strip_prefix("file://")
+                    .unwrap_or(path.as_str())
+                    .to_string(),
+                rows: 0,
+                relations: None,
+            };
+            if let Some(opts) = inner.next() {
+                parse_diff_opts(opts.into_inner(), param_pool, &mut config)?;
+            }
+            SysOp::Diff(config)
+        }
+        Rule::test_op => {
+            let mut stmts = vec![];
+            for stmt in inner.into_inner() {
+                let rule = stmt.as_rule();
+                let mut parts = stmt.into_inner().peekable();
+                let name = match parts.peek() {
+                    Some(p) if p.as_rule() != Rule::query_script_inner_no_bracket => {
+                        Some(parse_string(parts.next().unwrap())?.to_string())
+                    }
+                    _ => None,
+                };
+                let mut queries = vec![];
+                while let Some(p) =
+                    parts.next_if(|p| p.as_rule() == Rule::query_script_inner_no_bracket)
+                {
+                    queries.push(parse_query(
+                        p.into_inner(),
+                        param_pool,
+                        algorithms,
+                        cur_vld,
+                    )?);
+                }
+                let mut queries = queries.into_iter();
+                let mut query = || queries.next().unwrap();
+                stmts.push(match rule {
+                    Rule::test_setup => TestStmt::Setup(query()),
+                    Rule::test_assert_eq => TestStmt::AssertEq(name, query(), query()),
+                    Rule::test_assert_rows => {
+                        let prog = query();
+                        let expected = build_expr(parts.next().unwrap(), param_pool)?;
+                        let span = expected.span();
+                        let rows = match expected.eval_to_const()? {
+                            DataValue::List(rows) => rows
+                                .into_iter()
+                                .map(|row| match row {
+                                    DataValue::List(row) => Ok(row),
+                                    _ => Err(ExpectedRowsError(span)),
+                                })
+                                .collect::<Result<_, _>>()?,
+                            _ => bail!(ExpectedRowsError(span)),
+                        };
+                        TestStmt::AssertRows(name, prog, rows)
+                    }
+                    r => unreachable!("{:?}", r),
+                });
+            }
+            SysOp::Test(stmts)
+        }
         r => unreachable!("{:?}", r),
     })
 }
+
+fn parse_partition_opts(
+    src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<PartitionScheme> {
+    let mut by = None;
+    let mut partitions = None;
+    let mut bounds = None;
+    for opt_pair in src {
+        let mut opt_inner = opt_pair.into_inner();
+        let opt_name = opt_inner.next().unwrap();
+        let opt_val = opt_inner.next().unwrap();
+        let opt_val_str = opt_val.as_str();
+        let v = build_expr(opt_val, param_pool)?.eval_to_const()?;
+        match opt_name.as_str() {
+            "by" => {
+                by = Some(
+                    v.get_str()
+                        .map(SmartString::<LazyCompact>::from)
+                        .ok_or_else(|| miette!("Invalid by: {}", opt_val_str))?,
+                );
+            }
+            "partitions" => {
+                let n = v
+                    .get_non_neg_int()
+                    .ok_or_else(|| miette!("Invalid partitions: {}", opt_val_str))?;
+                ensure!(
+                    (2..=1024).contains(&n),
+                    "The number of partitions must be between 2 and 1024, got {}",
+                    n
+                );
+                partitions = Some(n as usize);
+            }
+            "bounds" => match v {
+                DataValue::List(l) if !l.is_empty() && l.len() < 1024 => bounds = Some(l),
+                _ => bail!(
+                    "Invalid bounds: {}, must be a list of 1 to 1023 values",
+                    opt_val_str
+                ),
+            },
+            _ => bail!("Unknown option {} for partition", opt_name.as_str()),
+        }
+    }
+    match (by.as_deref(), partitions, bounds) {
+        (Some("hash"), Some(n), None) => Ok(PartitionScheme::Hash(n)),
+        (Some("range"), None, Some(bounds)) => Ok(PartitionScheme::Range(bounds)),
+        (Some("hash"), ..) => bail!("Partitioning by hash takes 'partitions' only"),
+        (Some("range"), ..) => bail!("Partitioning by range takes 'bounds' only"),
+        _ => bail!("Partitions must be given 'by' either 'hash' or 'range'"),
+    }
+}
+
+fn parse_pin_opts(src: Pairs<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<usize> {
+    let mut max_bytes = DEFAULT_MAX_PINNED_BYTES;
+    for opt_pair in src {
+        let mut opt_inner = opt_pair.into_inner();
+        let opt_name = opt_inner.next().unwrap();
+        let opt_val = opt_inner.next().unwrap();
+        let opt_val_str = opt_val.as_str();
+        let v = build_expr(opt_val, param_pool)?.eval_to_const()?;
+        match opt_name.as_str() {
+            "max_bytes" => {
+                max_bytes = v
+                    .get_non_neg_int()
+                    .ok_or_else(|| miette!("Invalid max_bytes: {}", opt_val_str))?
+                    as usize;
+            }
+            _ => bail!("Unknown option {} for pin", opt_name.as_str()),
+        }
+    }
+    Ok(max_bytes)
+}
+
+fn parse_diff_opts(
+    src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    config: &mut DiffConfig,
+) -> Result<()> {
+    for opt_pair in src {
+        let mut opt_inner = opt_pair.into_inner();
+        let opt_name = opt_inner.next().unwrap();
+        let opt_val = opt_inner.next().unwrap();
+        let opt_val_str = opt_val.as_str();
+        let v = build_expr(opt_val, param_pool)?.eval_to_const()?;
+        match opt_name.as_str() {
+            "rows" => {
+                config.rows = v
+                    .get_non_neg_int()
+                    .ok_or_else(|| miette!("Invalid rows: {}", opt_val_str))?
+                    as usize;
+            }
+            "relations" => {
+                let relations = match v {
+                    DataValue::List(l) => l
+                        .iter()
+                        .map(|r| r.get_str().map(SmartString::from))
+                        .collect::<Option<Vec<_>>>(),
+                    _ => None,
+                };
+                config.relations =
+                    Some(relations.ok_or_else(|| miette!("Invalid relations: {}", opt_val_str))?);
+            }
+            _ => bail!("Unknown option {} for diff", opt_name.as_str()),
+        }
+    }
+    Ok(())
+}
+
+fn parse_export_opts(
+    src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    config: &mut ChunkedExportConfig,
+) -> Result<()> {
+    for opt_pair in src {
+        let mut opt_inner = opt_pair.into_inner();
+        let opt_name = opt_inner.next().unwrap();
+        let opt_val = opt_inner.next().unwrap();
+        let opt_val_str = opt_val.as_str();
+        let v = build_expr(opt_val, param_pool)?.eval_to_const()?;
+        match opt_name.as_str() {
+            "rows_per_chunk" => {
+                let n = v
+                    .get_int()
+                    .ok_or_else(|| miette!("Invalid rows_per_chunk: {}", opt_val_str))?;
+                ensure!(n > 0, "rows_per_chunk must be positive");
+                config.rows_per_chunk = n as usize;
+            }
+            "restart" => {
+                config.restart = v
+                    .get_bool()
+                    .ok_or_else(|| miette!("Invalid restart: {}", opt_val_str))?;
+            }
+            _ => bail!("Unknown option {} for chunked export", opt_name.as_str()),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct GraphOpts {
+    format: Option<GraphFormat>,
+    relation: Option<SmartString<LazyCompact>>,
+    nodes: Option<SmartString<LazyCompact>>,
+    directed: Option<bool>,
+}
+
+fn parse_graph_opts(
+    src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    opts: &mut GraphOpts,
+) -> Result<()> {
+    for opt_pair in src {
+        let mut opt_inner = opt_pair.into_inner();
+        let opt_name = opt_inner.next().unwrap();
+        let opt_val = opt_inner.next().unwrap();
+        let opt_val_str = opt_val.as_str();
+        let v = build_expr(opt_val, param_pool)?.eval_to_const()?;
+        match opt_name.as_str() {
+            "format" => {
+                opts.format = Some(
+                    v.get_str()
+                        .and_then(GraphFormat::from_name)
+                        .ok_or_else(|| miette!("Invalid format: {}", opt_val_str))?,
+                )
+            }
+            "relation" => {
+                let name = v
+                    .get_str()
+                    .ok_or_else(|| miette!("Invalid relation: {}", opt_val_str))?;
+                opts.relation = Some(SmartString::from(name));
+            }
+            "nodes" => {
+                let name = v
+                    .get_str()
+                    .ok_or_else(|| miette!("Invalid nodes: {}", opt_val_str))?;
+                opts.nodes = Some(SmartString::from(name));
+            }
+            "directed" => {
+                opts.directed = Some(
+                    v.get_bool()
+                        .ok_or_else(|| miette!("Invalid directed: {}", opt_val_str))?,
+                );
+            }
+            _ => bail!("Unknown option {} for graph", opt_name.as_str()),
+        }
+    }
+    Ok(())
+}
+
+fn parse_import_opts(
+    src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    config: &mut ImportInferConfig,
+) -> Result<()> {
+    for opt_pair in src {
+        let mut opt_inner = opt_pair.into_inner();
+        let opt_name = opt_inner.next().unwrap();
+        let opt_val = opt_inner.next().unwrap();
+        let opt_val_str = opt_val.as_str();
+        let v = build_expr(opt_val, param_pool)?.eval_to_const()?;
+        match opt_name.as_str() {
+            "format" => {
+                config.format = Some(match v.get_str() {
+                    Some("csv") => ImportFormat::Csv(b','),
+                    Some("tsv") => ImportFormat::Csv(b'\t'),
+                    Some("ndjson" | "jsonl") => ImportFormat::Ndjson,
+                    Some("parquet") => ImportFormat::Parquet,
+                    _ => bail!("Invalid format: {}", opt_val_str),
+                })
+            }
+            "relation" => {
+                let name = v
+                    .get_str()
+                    .ok_or_else(|| miette!("Invalid relation: {}", opt_val_str))?;
+                config.relation = SmartString::from(name);
+            }
+            "keys" => {
+                let keys = match v {
+                    DataValue::List(l) => l
+                        .iter()
+                        .map(|k| k.get_str().map(SmartString::from))
+                        .collect::<Option<Vec<_>>>(),
+                    _ => None,
+                };
+                config.keys = Some(keys.ok_or_else(|| miette!("Invalid keys: {}", opt_val_str))?);
+            }
+            "sample" => {
+                let n = v
+                    .get_int()
+                    .ok_or_else(|| miette!("Invalid sample: {}", opt_val_str))?;
+                ensure!(n > 0, "sample must be positive");
+                config.sample = n as usize;
+            }
+            "create" => {
+                config.create = v
+                    .get_bool()
+                    .ok_or_else(|| miette!("Invalid create: {}", opt_val_str))?;
+            }
+            _ => bail!("Unknown option {} for import", opt_name.as_str()),
+        }
+    }
+    Ok(())
+}
+
+fn parse_retention_opts(
+    src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<RetentionPolicy> {
+    let mut policy = RetentionPolicy::default();
+    for opt_pair in src {
+        let mut opt_inner = opt_pair.into_inner();
+        let opt_name = opt_inner.next().unwrap();
+        let opt_val = opt_inner.next().unwrap();
+        let opt_val_str = opt_val.as_str();
+        let v = build_expr(opt_val, param_pool)?.eval_to_const()?;
+        match opt_name.as_str() {
+            "keep_versions" => {
+                let n = v
+                    .get_int()
+                    .ok_or_else(|| miette!("Invalid keep_versions: {}", opt_val_str))?;
+                ensure!(n > 0, "keep_versions must be positive");
+                policy.keep_versions = Some(n as usize);
+            }
+            "keep_days" => {
+                let d = v
+                    .get_float()
+                    .ok_or_else(|| miette!("Invalid keep_days: {}", opt_val_str))?;
+                ensure!(d >= 0., "keep_days must not be negative");
+                policy.keep_micros = Some((d * MICROS_PER_DAY) as i64);
+            }
+            "expire_to" => {
+                let rel = v
+                    .get_str()
+                    .ok_or_else(|| miette!("Invalid expire_to: {}", opt_val_str))?;
+                policy.expire_to = Some(SmartString::from(rel));
+            }
+            "notify" => {
+                policy.notify = v
+                    .get_bool()
+                    .ok_or_else(|| miette!("Invalid notify: {}", opt_val_str))?;
+            }
+            _ => bail!("Unknown option {} for retention policy", opt_name.as_str()),
+        }
+    }
+    ensure!(
+        !policy.is_empty() || (policy.expire_to.is_none() && !policy.notify),
+        "expire_to and notify require keep_versions or keep_days to be given"
+    );
+    Ok(policy)
+}
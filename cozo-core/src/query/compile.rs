@@ -15,14 +15,14 @@ use thiserror::Error;
 use crate::data::aggr::Aggregation;
 use crate::data::expr::Expr;
 use crate::data::program::{
-    MagicAtom, MagicFixedRuleApply, MagicInlineRule, MagicRulesOrFixed, MagicSymbol,
-    StratifiedMagicProgram,
+    IndexHint, MagicAtom, MagicFixedRuleApply, MagicInlineRule, MagicRelationApplyAtom,
+    MagicRulesOrFixed, MagicSymbol, StratifiedMagicProgram,
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
 use crate::parse::SourceSpan;
 use crate::query::ra::RelAlgebra;
-use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle};
 use crate::runtime::transact::SessionTx;
 
 pub(crate) type CompiledProgram = BTreeMap<MagicSymbol, CompiledRuleSet>;
@@ -88,6 +88,8 @@ pub(crate) struct CompiledRule {
     pub(crate) aggr: Vec<Option<(Aggregation, Vec<DataValue>)>>,
     pub(crate) relation: RelAlgebra,
     pub(crate) contained_rules: BTreeMap<MagicSymbol, ContainedRuleMultiplicity>,
+    /// The hints given to stored relations, for `::explain`
+    pub(crate) hints: Vec<String>,
 }
 
 #[derive(Debug, Error, Diagnostic)]
@@ -108,6 +110,33 @@ pub(crate) enum IndexPositionUse {
     Ignored,
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("A negated relation cannot be broadcast")]
+#[diagnostic(code(eval::negated_broadcast))]
+#[diagnostic(help("Negation already reads the relation for each row, remove the hint"))]
+struct NegatedBroadcast(#[label] SourceSpan);
+
+/// The index to read a stored relation application through, as hinted or else as chosen
+/// by the planner
+fn index_for(
+    store: &RelationHandle,
+    rel_app: &MagicRelationApplyAtom,
+    join_indices: &[IndexPositionUse],
+) -> Result<Option<(RelationHandle, Vec<usize>, bool)>> {
+    Ok(match &rel_app.hints.index {
+        IndexHint::UseIndex(idx) => Some(store.hinted_index(
+            idx,
+            join_indices,
+            rel_app.valid_at.is_some(),
+            rel_app.tx_at.is_some(),
+        )?),
+        IndexHint::NoIndex => None,
+        // indices do not carry the transaction time column
+        IndexHint::Auto if rel_app.tx_at.is_some() || rel_app.hints.broadcast => None,
+        IndexHint::Auto => store.choose_index(join_indices, rel_app.valid_at.is_some()),
+    })
+}
+
 impl<'a> SessionTx<'a> {
     pub(crate) fn stratified_magic_compile(
         &mut self,
@@ -146,6 +175,7 @@ impl<'a> SessionTx<'a> {
                                         aggr: rule.aggr.clone(),
                                         relation,
                                         contained_rules: rule.contained_rules(),
+                                        hints: rule.hints(),
                                     })
                                 }
                                 Ok((k, CompiledRuleSet::Rules(collected)))
@@ -217,7 +247,7 @@ impl<'a> SessionTx<'a> {
                     ret = ret.join(right, prev_joiner_vars, right_joiner_vars, rule_app.span);
                 }
                 MagicAtom::Relation(rel_app) => {
-                    let store = self.get_relation(&rel_app.name, false)?;
+                    let store = self.get_relation_for_read(&rel_app.name)?;
                     if store.access_level < AccessLevel::ReadOnly {
                         bail!(InsufficientAccessLevel(
                             store.name.to_string(),
@@ -267,8 +297,12 @@ impl<'a> SessionTx<'a> {
                         }
                     }
 
-                    let chosen_index =
-                        store.choose_index(&join_indices, rel_app.valid_at.is_some());
+                    let chosen_index = index_for(&store, rel_app, &join_indices)?
+                        .filter(|(idx, _, _)| !self.read_around(&idx.name));
+                    self.record_read(&store.name);
+                    if let Some((idx, _, _)) = &chosen_index {
+                        self.record_read(&idx.name);
+                    }
 
                     match chosen_index {
                         None => {
@@ -278,10 +312,19 @@ impl<'a> SessionTx<'a> {
                                 store,
                                 rel_app.span,
                                 rel_app.valid_at,
+                                rel_app.tx_at,
                             )?;
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
-                            ret =
-                                ret.join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
+                            ret = if rel_app.hints.broadcast {
+                                ret.broadcast_join(
+                                    right,
+                                    prev_joiner_vars,
+                                    right_joiner_vars,
+                                    rel_app.span,
+                                )
+                            } else {
+                                ret.join(right, prev_joiner_vars, right_joiner_vars, rel_app.span)
+                            };
                         }
                         Some((chosen_index, mapper, false)) => {
                             // index-only
@@ -294,6 +337,7 @@ impl<'a> SessionTx<'a> {
                                 chosen_index,
                                 rel_app.span,
                                 rel_app.valid_at,
+                                rel_app.tx_at,
                             )?;
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                             ret =
@@ -326,6 +370,7 @@ impl<'a> SessionTx<'a> {
                                     chosen_index,
                                     rel_app.span,
                                     rel_app.valid_at,
+                                    rel_app.tx_at,
                                 )?;
                                 ret = ret.join(
                                     index,
@@ -351,6 +396,7 @@ impl<'a> SessionTx<'a> {
                                     store,
                                     rel_app.span,
                                     rel_app.valid_at,
+                                    rel_app.tx_at,
                                 )?;
                                 ret = ret.join(
                                     relation,
@@ -412,7 +458,7 @@ impl<'a> SessionTx<'a> {
                     ret = ret.neg_join(right, prev_joiner_vars, right_joiner_vars, rule_app.span);
                 }
                 MagicAtom::NegatedRelation(rel_app) => {
-                    let store = self.get_relation(&rel_app.name, false)?;
+                    let store = self.get_relation_for_read(&rel_app.name)?;
                     ensure!(
                         store.arity() == rel_app.args.len(),
                         ArityMismatch(
@@ -453,8 +499,13 @@ impl<'a> SessionTx<'a> {
                         }
                     }
 
-                    let chosen_index =
-                        store.choose_index(&join_indices, rel_app.valid_at.is_some());
+                    ensure!(!rel_app.hints.broadcast, NegatedBroadcast(rel_app.span));
+                    let chosen_index = index_for(&store, rel_app, &join_indices)?
+                        .filter(|(idx, _, _)| !self.read_around(&idx.name));
+                    self.record_read(&store.name);
+                    if let Some((idx, _, _)) = &chosen_index {
+                        self.record_read(&idx.name);
+                    }
 
                     match chosen_index {
                         None | Some((_, _, true)) => {
@@ -463,6 +514,7 @@ impl<'a> SessionTx<'a> {
                                 store,
                                 rel_app.span,
                                 rel_app.valid_at,
+                                rel_app.tx_at,
                             )?;
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                             ret = ret.neg_join(
@@ -483,6 +535,7 @@ impl<'a> SessionTx<'a> {
                                 chosen_index,
                                 rel_app.span,
                                 rel_app.valid_at,
+                                rel_app.tx_at,
                             )?;
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                             ret = ret.neg_join(
@@ -526,6 +579,12 @@ impl<'a> SessionTx<'a> {
                             own_bindings.push(var.clone());
                         }
                     }
+                    let mut s = s.clone();
+                    s.scan_base = self.read_around(&s.idx_handle.name);
+                    self.record_read(&s.base_handle.name);
+                    if !s.scan_base {
+                        self.record_read(&s.idx_handle.name);
+                    }
                     ret = ret.hnsw_search(s.clone(), own_bindings)?;
                     if !post_filters.is_empty() {
                         ret = ret.filter(Expr::build_and(post_filters, s.span))?;
@@ -560,6 +619,12 @@ impl<'a> SessionTx<'a> {
                             own_bindings.push(var.clone());
                         }
                     }
+                    let mut s = s.clone();
+                    s.scan_base = self.read_around(&s.idx_handle.name);
+                    self.record_read(&s.base_handle.name);
+                    if !s.scan_base {
+                        self.record_read(&s.idx_handle.name);
+                    }
                     ret = ret.fts_search(s.clone(), own_bindings)?;
                     if !post_filters.is_empty() {
                         ret = ret.filter(Expr::build_and(post_filters, s.span))?;
@@ -594,6 +659,12 @@ impl<'a> SessionTx<'a> {
                             own_bindings.push(var.clone());
                         }
                     }
+                    let mut s = s.clone();
+                    s.scan_base = self.read_around(&s.idx_handle.name);
+                    self.record_read(&s.base_handle.name);
+                    if !s.scan_base {
+                        self.record_read(&s.idx_handle.name);
+                    }
                     ret = ret.lsh_search(s.clone(), own_bindings)?;
                     if !post_filters.is_empty() {
                         ret = ret.filter(Expr::build_and(post_filters, s.span))?;
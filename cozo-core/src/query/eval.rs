@@ -12,14 +12,15 @@ use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use itertools::Itertools;
 use log::{debug, trace};
-use miette::Result;
+use miette::{bail, Diagnostic, Result};
 #[cfg(not(target_arch = "wasm32"))]
 use rayon::prelude::*;
+use thiserror::Error;
 
 use crate::data::aggr::Aggregation;
-use crate::data::program::{MagicSymbol, NoEntryError};
+use crate::data::program::{IterationLimit, MagicSymbol, NoEntryError};
 use crate::data::symb::{Symbol, PROG_ENTRY};
-use crate::data::tuple::Tuple;
+use crate::data::tuple::{Tuple, TupleIter};
 use crate::data::value::DataValue;
 use crate::fixed_rule::FixedRulePayload;
 use crate::parse::SourceSpan;
@@ -30,6 +31,14 @@ use crate::runtime::db::Poison;
 use crate::runtime::temp_store::{EpochStore, MeetAggrStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Recursive rules did not reach their fixpoint within {0} iterations")]
+#[diagnostic(code(eval::max_iterations_reached))]
+#[diagnostic(help(
+    "Raise `:max_iterations`, or set `:on_max_iterations 'partial'` to get the rows derived so far"
+))]
+struct MaxIterationsReached(usize);
+
 pub(crate) struct QueryLimiter {
     total: Option<usize>,
     skip: Option<usize>,
@@ -62,6 +71,12 @@ impl QueryLimiter {
 }
 
 impl<'a> SessionTx<'a> {
+    /// When `fixpoint` is given, rules found in it resume from the stored tuples instead of
+    /// starting empty, and on return it holds the final tuples of every rule.
+    /// This is only correct for programs without negation or aggregation, reading
+    /// relations that have not lost any rows since the stored tuples were computed.
+    ///
+    /// When `max_iterations` allows partial results and is reached, `incomplete` is set.
     pub(crate) fn stratified_magic_evaluate(
         &self,
         strata: &[CompiledProgram],
@@ -69,6 +84,9 @@ impl<'a> SessionTx<'a> {
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
         poison: Poison,
+        mut fixpoint: Option<&mut BTreeMap<MagicSymbol, RegularTempStore>>,
+        max_iterations: Option<IterationLimit>,
+        incomplete: &mut bool,
     ) -> Result<(EpochStore, bool)> {
         let mut stores: BTreeMap<MagicSymbol, EpochStore> = BTreeMap::new();
         let mut early_return = false;
@@ -82,8 +100,13 @@ impl<'a> SessionTx<'a> {
                 trace!("{:?}", stores);
             }
             for (rule_name, rule_set) in cur_prog {
+                let resumed = fixpoint.as_mut().and_then(|f| f.remove(rule_name));
                 let store = match rule_set.aggr_kind() {
-                    AggrKind::None | AggrKind::Normal => EpochStore::new_normal(rule_set.arity()),
+                    AggrKind::None => match resumed {
+                        Some(total) => EpochStore::new_resumed(rule_set.arity(), total),
+                        None => EpochStore::new_normal(rule_set.arity()),
+                    },
+                    AggrKind::Normal => EpochStore::new_normal(rule_set.arity()),
                     AggrKind::Meet => {
                         let rs = match rule_set {
                             CompiledRuleSet::Rules(rs) => rs,
@@ -101,7 +124,16 @@ impl<'a> SessionTx<'a> {
                 total_num_to_take,
                 num_to_skip,
                 poison.clone(),
+                max_iterations,
+                incomplete,
             )?;
+            if let Some(fixpoint) = fixpoint.as_mut() {
+                for rule_name in cur_prog.keys() {
+                    if let Some(total) = stores[rule_name].normal_total() {
+                        fixpoint.insert(rule_name.clone(), total);
+                    }
+                }
+            }
         }
         let entry_symbol = MagicSymbol::Muggle {
             inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
@@ -117,6 +149,8 @@ impl<'a> SessionTx<'a> {
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
         poison: Poison,
+        max_iterations: Option<IterationLimit>,
+        incomplete: &mut bool,
     ) -> Result<bool> {
         let limiter = QueryLimiter {
             total: total_num_to_take,
@@ -169,13 +203,22 @@ impl<'a> SessionTx<'a> {
                         },
                         CompiledRuleSet::Fixed(fixed) => {
                             let fixed_impl = fixed.fixed_impl.as_ref();
-                            let mut out = RegularTempStore::default();
                             let payload = FixedRulePayload {
                                 manifest: &fixed,
                                 stores: borrowed_stores,
                                 tx: self,
                             };
-                            fixed_impl.run(payload, &mut out, poison.clone())?;
+                            let run = |out: &mut RegularTempStore| {
+                                fixed_impl.run(payload, out, poison.clone())
+                            };
+                            let out = match &fixed.cache {
+                                Some(spec) => self.run_cached_fixed_rule(spec, run)?,
+                                None => {
+                                    let mut out = RegularTempStore::default();
+                                    run(&mut out)?;
+                                    out
+                                }
+                            };
                             out.wrap()
                         }
                     };
@@ -298,6 +341,16 @@ impl<'a> SessionTx<'a> {
             if !changed {
                 break;
             }
+            // the first epoch is not an iteration, as every rule is evaluated once anyway
+            if let Some(limit) = max_iterations {
+                if epoch as usize >= limit.max {
+                    if !limit.partial {
+                        bail!(MaxIterationsReached(limit.max))
+                    }
+                    *incomplete = true;
+                    break;
+                }
+            }
         }
         Ok(used_limiter.load(Ordering::Acquire))
     }
@@ -339,6 +392,23 @@ impl<'a> SessionTx<'a> {
 
         Ok((should_check_limit, out_store))
     }
+    /// The tuples of the body of a rule with aggregations, which may depend on the order
+    /// they see tuples in: sorted if the evaluation is deterministic
+    fn aggr_body_iter<'b>(
+        &'b self,
+        rule: &'b CompiledRule,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'b BTreeMap<MagicSymbol, EpochStore>,
+    ) -> Result<TupleIter<'b>> {
+        let iter = rule.relation.iter(self, delta_rule, stores)?;
+        if !self.deterministic {
+            return Ok(iter);
+        }
+        let mut tuples: Vec<Tuple> = iter.try_collect()?;
+        tuples.sort();
+        Ok(Box::new(tuples.into_iter().map(Ok)))
+    }
+
     fn initial_rule_meet_eval(
         &self,
         rule_symb: &MagicSymbol,
@@ -354,7 +424,7 @@ impl<'a> SessionTx<'a> {
             for (aggr, args) in aggr.iter_mut().flatten() {
                 aggr.meet_init(args)?;
             }
-            for item_res in rule.relation.iter(self, None, stores)? {
+            for item_res in self.aggr_body_iter(rule, None, stores)? {
                 let item = item_res?;
                 trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
                 out_store.meet_put(item)?;
@@ -388,6 +458,24 @@ impl<'a> SessionTx<'a> {
     ) -> Result<(bool, RegularTempStore)> {
         let mut out_store = RegularTempStore::default();
         let should_check_limit = limiter.total.is_some() && rule_symb.is_prog_entry();
+
+        // counting the tuples of a whole stored relation needs no decoding of them. The read
+        // is recorded in the usage stats when the rule is compiled, and `count_all` counts
+        // the pinned rows of the relation if there are any
+        if let [rule] = ruleset {
+            let counts_only = rule
+                .aggr
+                .iter()
+                .all(|a| matches!(a, Some((aggr, _)) if aggr.is_count()));
+            if counts_only && !should_check_limit {
+                if let Some(handle) = rule.relation.as_plain_stored_scan() {
+                    let count = DataValue::from(handle.count_all(self)? as i64);
+                    out_store.put(vec![count; rule.aggr.len()]);
+                    return Ok((false, out_store));
+                }
+            }
+        }
+
         let mut aggr_work: BTreeMap<Vec<DataValue>, Vec<Aggregation>> = BTreeMap::new();
 
         for (rule_n, rule) in ruleset.iter().enumerate() {
@@ -414,7 +502,7 @@ impl<'a> SessionTx<'a> {
                 .filter_map(|(i, a)| a.as_ref().map(|aggr| (i, aggr.clone())))
                 .collect_vec();
 
-            for item_res in rule.relation.iter(self, None, stores)? {
+            for item_res in self.aggr_body_iter(rule, None, stores)? {
                 let item = item_res?;
                 trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
 
@@ -645,7 +733,7 @@ impl<'a> SessionTx<'a> {
 
             if need_complete_run {
                 debug!("complete run for rule {:?}.{}", rule_symb, rule_n);
-                for item_res in rule.relation.iter(self, None, stores)? {
+                for item_res in self.aggr_body_iter(rule, None, stores)? {
                     out_store.meet_put(item_res?)?;
                 }
                 poison.check()?;
@@ -658,7 +746,7 @@ impl<'a> SessionTx<'a> {
                         "with delta {:?} for rule {:?}.{}",
                         delta_key, rule_symb, rule_n
                     );
-                    for item_res in rule.relation.iter(self, Some(delta_key), stores)? {
+                    for item_res in self.aggr_body_iter(rule, Some(delta_key), stores)? {
                         out_store.meet_put(item_res?)?;
                     }
                     poison.check()?;
@@ -140,6 +140,8 @@ impl InputAtom {
             name,
             mut args,
             valid_at,
+            tx_at,
+            hints,
             span,
         }: InputNamedFieldRelationApplyAtom,
         gen: &mut TempSymbGen,
@@ -177,6 +179,8 @@ impl InputAtom {
             args: new_args,
             span,
             valid_at,
+            tx_at,
+            hints,
         })
     }
 
@@ -342,6 +346,8 @@ impl InputRelationApplyAtom {
                 name: self.name,
                 args,
                 valid_at: self.valid_at,
+                tx_at: self.tx_at,
+                hints: self.hints,
                 span: self.span,
             })
         } else {
@@ -349,6 +355,8 @@ impl InputRelationApplyAtom {
                 name: self.name,
                 args,
                 valid_at: self.valid_at,
+                tx_at: self.tx_at,
+                hints: self.hints,
                 span: self.span,
             })
         });
@@ -6,7 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::mem;
 
 use itertools::Itertools;
@@ -27,22 +27,54 @@ use crate::query::logical::NamedFieldNotFound;
 use crate::query::ra::InvalidTimeTravelScanning;
 use crate::runtime::transact::SessionTx;
 
+/// Why the magic set rewrite is not applied to a rule, in which case the rule is computed in full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum MagicExemption {
+    Entry,
+    Disabled,
+    Aggregation,
+    NeededInFull,
+}
+
+impl MagicExemption {
+    pub(crate) fn reason(&self) -> &'static str {
+        match self {
+            MagicExemption::Entry => "entry rule",
+            MagicExemption::Disabled => "disabled by :disable_magic_rewrite",
+            MagicExemption::Aggregation => {
+                "rule has aggregations, use :force_magic_rewrite to rewrite it"
+            }
+            MagicExemption::NeededInFull => {
+                "used behind a negation, an aggregation or a fixed rule in a later stratum"
+            }
+        }
+    }
+}
+
 impl NormalFormProgram {
-    pub(crate) fn exempt_aggr_rules_for_magic_sets(&self, exempt_rules: &mut BTreeSet<Symbol>) {
+    pub(crate) fn exempt_aggr_rules_for_magic_sets(
+        &self,
+        exempt_rules: &mut BTreeMap<Symbol, MagicExemption>,
+    ) {
         for (name, rule_set) in self.prog.iter() {
             if self.disable_magic_rewrite {
-                exempt_rules.insert(name.clone());
+                exempt_rules
+                    .entry(name.clone())
+                    .or_insert(MagicExemption::Disabled);
+                continue;
+            }
+            if self.force_magic_rewrite {
                 continue;
             }
             match rule_set {
                 NormalFormRulesOrFixed::Rules { rules: rule_set } => {
-                    'outer: for rule in rule_set.iter() {
-                        for aggr in rule.aggr.iter() {
-                            if aggr.is_some() {
-                                exempt_rules.insert(name.clone());
-                                continue 'outer;
-                            }
-                        }
+                    if rule_set
+                        .iter()
+                        .any(|rule| rule.aggr.iter().any(|aggr| aggr.is_some()))
+                    {
+                        exempt_rules
+                            .entry(name.clone())
+                            .or_insert(MagicExemption::Aggregation);
                     }
                 }
                 NormalFormRulesOrFixed::Fixed { fixed: _ } => {}
@@ -52,20 +84,77 @@ impl NormalFormProgram {
 }
 
 impl StratifiedNormalFormProgram {
+    /// The rules that the magic set rewrite leaves alone, and why
+    pub(crate) fn magic_exemptions(&self) -> BTreeMap<Symbol, MagicExemption> {
+        let mut exempt_rules = BTreeMap::from([(
+            Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+            MagicExemption::Entry,
+        )]);
+        for prog in self.0.iter() {
+            prog.exempt_aggr_rules_for_magic_sets(&mut exempt_rules);
+            for name in prog.get_downstream_rules() {
+                exempt_rules
+                    .entry(name)
+                    .or_insert(MagicExemption::NeededInFull);
+            }
+        }
+        exempt_rules
+    }
+    /// The rules that depend on themselves
+    pub(crate) fn recursive_rules(&self) -> BTreeSet<Symbol> {
+        self.0
+            .iter()
+            .flat_map(|prog| {
+                prog.prog
+                    .keys()
+                    .filter(|name| prog.depends_on(name, name))
+                    .cloned()
+            })
+            .collect()
+    }
     pub(crate) fn magic_sets_rewrite(self, tx: &SessionTx<'_>) -> Result<StratifiedMagicProgram> {
-        let mut exempt_rules = BTreeSet::from([Symbol::new(PROG_ENTRY, SourceSpan(0, 0))]);
+        let exempt_rules = self.magic_exemptions();
         let mut collected = vec![];
         for prog in self.0 {
-            prog.exempt_aggr_rules_for_magic_sets(&mut exempt_rules);
-            let down_stream_rules = prog.get_downstream_rules();
             let adorned = prog.adorn(&exempt_rules, tx)?;
             collected.push(adorned.magic_rewrite());
-            exempt_rules.extend(down_stream_rules);
         }
         Ok(StratifiedMagicProgram(collected))
     }
 }
 
+/// How the magic set rewrite applied to a rule, as shown by `::explain`
+pub(crate) fn explain_magic(
+    name: &MagicSymbol,
+    exemptions: &BTreeMap<Symbol, MagicExemption>,
+    recursive: &BTreeSet<Symbol>,
+) -> String {
+    let inner = name.as_plain_symbol();
+    let reason = match name {
+        MagicSymbol::Muggle { .. } => exemptions
+            .get(inner)
+            .map(|exemption| exemption.reason())
+            .unwrap_or("not rewritten"),
+        MagicSymbol::Magic { adornment, .. } => {
+            if adornment.iter().any(|bound| *bound) {
+                let adornment: String = adornment
+                    .iter()
+                    .map(|bound| if *bound { 'b' } else { 'f' })
+                    .collect();
+                return format!("rewritten for bound arguments {adornment}");
+            }
+            "no argument is bound where the rule is applied"
+        }
+        MagicSymbol::Input { .. } => return "bound arguments of applications".to_string(),
+        MagicSymbol::Sup { .. } => return "supplementary rule".to_string(),
+    };
+    if recursive.contains(inner) {
+        format!("recursive rule fully materialized: {reason}")
+    } else {
+        format!("computed in full: {reason}")
+    }
+}
+
 impl MagicProgram {
     fn magic_rewrite(self) -> MagicProgram {
         let mut ret_prog = MagicProgram {
@@ -301,12 +390,28 @@ impl NormalFormProgram {
         }
         downstream_rules
     }
-    fn adorn(self, upstream_rules: &BTreeSet<Symbol>, tx: &SessionTx<'_>) -> Result<MagicProgram> {
-        let rules_to_rewrite: BTreeSet<_> = self
+    fn adorn(
+        self,
+        upstream_rules: &BTreeMap<Symbol, MagicExemption>,
+        tx: &SessionTx<'_>,
+    ) -> Result<MagicProgram> {
+        // rules to rewrite, with the positions of their heads holding aggregations,
+        // which must not be bound by the rewrite
+        let rules_to_rewrite: BTreeMap<_, _> = self
             .prog
-            .keys()
-            .filter(|k| !upstream_rules.contains(k))
-            .cloned()
+            .iter()
+            .filter(|(k, _)| !upstream_rules.contains_key(k))
+            .map(|(k, rules)| {
+                let aggregated = match rules {
+                    NormalFormRulesOrFixed::Rules { rules } => rules[0]
+                        .aggr
+                        .iter()
+                        .map(|aggr| aggr.is_some())
+                        .collect_vec(),
+                    NormalFormRulesOrFixed::Fixed { .. } => vec![],
+                };
+                (k.clone(), aggregated)
+            })
             .collect();
 
         let mut pending_adornment = vec![];
@@ -315,7 +420,7 @@ impl NormalFormProgram {
         };
 
         for (rule_name, rules) in &self.prog {
-            if rules_to_rewrite.contains(rule_name) {
+            if rules_to_rewrite.contains_key(rule_name) {
                 // processing starts with the sets of rules NOT subject to rewrite
                 continue;
             }
@@ -329,6 +434,7 @@ impl NormalFormProgram {
                             fixed: MagicFixedRuleApply {
                                 span: fixed.span,
                                 fixed_handle: fixed.fixed_handle.clone(),
+                                head: fixed.head.clone(),
                                 fixed_impl: fixed.fixed_impl.clone(),
                                 rule_args: fixed
                                     .rule_args
@@ -449,6 +555,7 @@ impl NormalFormProgram {
                                     .try_collect()?,
                                 options: fixed.options.clone(),
                                 arity: fixed.arity,
+                                cache: fixed.cache.clone(),
                             },
                         },
                     );
@@ -514,7 +621,7 @@ impl NormalFormAtom {
         &self,
         pending: &mut Vec<MagicSymbol>,
         seen_bindings: &mut BTreeSet<Symbol>,
-        rules_to_rewrite: &BTreeSet<Symbol>,
+        rules_to_rewrite: &BTreeMap<Symbol, Vec<bool>>,
     ) -> MagicAtom {
         match self {
             NormalFormAtom::Relation(v) => {
@@ -522,6 +629,8 @@ impl NormalFormAtom {
                     name: v.name.clone(),
                     args: v.args.clone(),
                     valid_at: v.valid_at,
+                    tx_at: v.tx_at,
+                    hints: v.hints.clone(),
                     span: v.span,
                 };
                 for arg in v.args.iter() {
@@ -561,12 +670,14 @@ impl NormalFormAtom {
                 MagicAtom::Predicate(p.clone())
             }
             NormalFormAtom::Rule(rule) => {
-                if rules_to_rewrite.contains(&rule.name) {
+                if let Some(aggregated) = rules_to_rewrite.get(&rule.name) {
                     // first mark adorned rules
                     // then
                     let mut adornment = SmallVec::new();
-                    for arg in rule.args.iter() {
-                        adornment.push(!seen_bindings.insert(arg.clone()));
+                    for (i, arg) in rule.args.iter().enumerate() {
+                        let is_bound = !seen_bindings.insert(arg.clone());
+                        let is_aggregated = aggregated.get(i).copied().unwrap_or(false);
+                        adornment.push(is_bound && !is_aggregated);
                     }
                     let name = MagicSymbol::Magic {
                         inner: rule.name.clone(),
@@ -602,6 +713,8 @@ impl NormalFormAtom {
                     name: nv.name.clone(),
                     args: nv.args.clone(),
                     valid_at: nv.valid_at,
+                    tx_at: nv.tx_at,
+                    hints: nv.hints.clone(),
                     span: nv.span,
                 })
             }
@@ -617,7 +730,7 @@ impl NormalFormInlineRule {
     fn adorn(
         &self,
         pending: &mut Vec<MagicSymbol>,
-        rules_to_rewrite: &BTreeSet<Symbol>,
+        rules_to_rewrite: &BTreeMap<Symbol, Vec<bool>>,
         mut seen_bindings: BTreeSet<Symbol>,
     ) -> MagicInlineRule {
         let mut ret_body = Vec::with_capacity(self.body.len());
@@ -656,4 +769,56 @@ mod tests {
         let res = db.run_default(query).unwrap().into_json();
         assert_eq!(res["rows"], json!([[0], [1]]));
     }
+
+    fn explain_magic(db: &DbInstance, query: &str, rule: &str) -> String {
+        let res = db.run_default(&format!("::explain {{ {query} }}")).unwrap();
+        let row = res
+            .rows
+            .iter()
+            .find(|row| row[2].get_str() == Some(rule) && row[4].get_str() == Some("out"))
+            .unwrap();
+        row[9].get_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn magic_rewrite_options() {
+        let db = DbInstance::default();
+        let edges = "e[a, b] <- [[1, 2], [1, 3], [2, 3]]";
+
+        let reach = format!(
+            "{edges}
+            r[a, b] := e[a, b]
+            r[a, b] := r[a, c], e[c, b]
+            ?[b] := r[1, b]"
+        );
+        let res = db.run_default(&reach).unwrap().into_json();
+        assert_eq!(res["rows"], json!([[2], [3]]));
+        assert_eq!(
+            explain_magic(&db, &reach, "r|Mbf"),
+            "rewritten for bound arguments bf"
+        );
+        let disabled = format!("{reach} :disable_magic_rewrite true");
+        let res = db.run_default(&disabled).unwrap().into_json();
+        assert_eq!(res["rows"], json!([[2], [3]]));
+        assert!(explain_magic(&db, &disabled, "r").starts_with("recursive rule fully materialized"));
+
+        let counts = format!(
+            "{edges}
+            c[a, count(b)] := e[a, b]
+            ?[n] := c[1, n]"
+        );
+        assert!(explain_magic(&db, &counts, "c").contains("rule has aggregations"));
+        let forced = format!("{counts} :force_magic_rewrite true");
+        let res = db.run_default(&forced).unwrap().into_json();
+        assert_eq!(res["rows"], json!([[2]]));
+        // aggregated positions are never bound
+        assert_eq!(
+            explain_magic(&db, &forced, "c|Mbf"),
+            "rewritten for bound arguments bf"
+        );
+
+        assert!(db
+            .run_default(&format!("{forced} :disable_magic_rewrite true"))
+            .is_err());
+    }
 }
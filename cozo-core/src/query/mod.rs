@@ -11,6 +11,7 @@ pub(crate) mod eval;
 pub(crate) mod graph;
 pub(crate) mod logical;
 pub(crate) mod magic;
+pub(crate) mod optimize;
 pub(crate) mod ra;
 pub(crate) mod reorder;
 pub(crate) mod sort;
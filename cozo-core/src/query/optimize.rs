@@ -0,0 +1,556 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+
+use crate::data::expr::Expr;
+use crate::data::program::{
+    FixedRuleArg, NormalFormAtom, NormalFormInlineRule, NormalFormProgram, NormalFormRulesOrFixed,
+    Unification,
+};
+use crate::data::symb::Symbol;
+
+/// An application of a rule in the body of another one
+struct RuleUse {
+    consumer: Symbol,
+    rule_idx: usize,
+    atom_idx: usize,
+    negated: bool,
+}
+
+impl NormalFormProgram {
+    /// Rewrite the rules of the program to produce narrower intermediate relations:
+    ///
+    /// * rules defined by a single non-recursive clause and applied only once are inlined
+    ///   into the rule applying them,
+    /// * columns of rules that are not used by any of their applications are dropped.
+    ///
+    /// Rules that are applied in rules with aggregations, or passed to fixed rules, are kept as
+    /// they are, since removing duplicate rows from them changes the result.
+    pub(crate) fn optimize_rules(&mut self) {
+        let mut inlined = 0;
+        while self.inline_one_rule(inlined) {
+            inlined += 1;
+        }
+        while self.prune_columns() {}
+    }
+
+    fn rules(&self, name: &Symbol) -> Option<&[NormalFormInlineRule]> {
+        self.prog
+            .get(name)
+            .and_then(|rules_or_fixed| rules_or_fixed.rules())
+    }
+
+    fn consumer(&self, rule_use: &RuleUse) -> &NormalFormInlineRule {
+        &self.rules(&rule_use.consumer).unwrap()[rule_use.rule_idx]
+    }
+
+    /// The applications of each rule in the bodies of rules,
+    /// and the set of rules given as input to fixed rules
+    fn rule_uses(&self) -> (BTreeMap<Symbol, Vec<RuleUse>>, BTreeSet<Symbol>) {
+        let mut uses: BTreeMap<Symbol, Vec<RuleUse>> = BTreeMap::new();
+        let mut fixed_inputs = BTreeSet::new();
+        for (name, rules_or_fixed) in &self.prog {
+            match rules_or_fixed {
+                NormalFormRulesOrFixed::Rules { rules } => {
+                    for (rule_idx, rule) in rules.iter().enumerate() {
+                        for (atom_idx, atom) in rule.body.iter().enumerate() {
+                            let (applied, negated) = match atom {
+                                NormalFormAtom::Rule(r) => (r, false),
+                                NormalFormAtom::NegatedRule(r) => (r, true),
+                                _ => continue,
+                            };
+                            uses.entry(applied.name.clone()).or_default().push(RuleUse {
+                                consumer: name.clone(),
+                                rule_idx,
+                                atom_idx,
+                                negated,
+                            });
+                        }
+                    }
+                }
+                NormalFormRulesOrFixed::Fixed { fixed } => {
+                    for arg in &fixed.rule_args {
+                        if let FixedRuleArg::InMem { name, .. } = arg {
+                            fixed_inputs.insert(name.clone());
+                        }
+                    }
+                }
+            }
+        }
+        (uses, fixed_inputs)
+    }
+
+    /// Whether `target` is reachable from the rules applied in the definition of `name`
+    pub(crate) fn depends_on(&self, name: &Symbol, target: &Symbol) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![name];
+        while let Some(cur) = stack.pop() {
+            let applied: Vec<&Symbol> = match self.prog.get(cur) {
+                Some(NormalFormRulesOrFixed::Rules { rules }) => rules
+                    .iter()
+                    .flat_map(|rule| rule.body.iter())
+                    .filter_map(|atom| match atom {
+                        NormalFormAtom::Rule(r) | NormalFormAtom::NegatedRule(r) => Some(&r.name),
+                        _ => None,
+                    })
+                    .collect(),
+                Some(NormalFormRulesOrFixed::Fixed { fixed }) => fixed
+                    .rule_args
+                    .iter()
+                    .filter_map(|arg| match arg {
+                        FixedRuleArg::InMem { name, .. } => Some(name),
+                        _ => None,
+                    })
+                    .collect(),
+                None => vec![],
+            };
+            for next in applied {
+                if next == target {
+                    return true;
+                }
+                if seen.insert(next) {
+                    stack.push(next);
+                }
+            }
+        }
+        false
+    }
+
+    fn inline_one_rule(&mut self, inlined: usize) -> bool {
+        let (uses, fixed_inputs) = self.rule_uses();
+        let found = uses.into_iter().find(|(name, rule_uses)| {
+            let [rule_use] = &rule_uses[..] else {
+                return false;
+            };
+            let Some([rule]) = self.rules(name) else {
+                return false;
+            };
+            if name.is_prog_entry()
+                || fixed_inputs.contains(name)
+                || rule_use.negated
+                || rule_use.consumer == *name
+                || has_aggr(rule)
+                || has_search(rule)
+                // a repeated head variable equates the arguments it takes, which renaming
+                // the variable to the arguments cannot express
+                || !rule.head.iter().all_unique()
+            {
+                return false;
+            }
+            let consumer = self.consumer(rule_use);
+            let NormalFormAtom::Rule(applied) = &consumer.body[rule_use.atom_idx] else {
+                unreachable!()
+            };
+            applied.args.len() == rule.head.len()
+                && !has_aggr(consumer)
+                && !self.depends_on(name, name)
+        });
+        let Some((name, mut rule_uses)) = found else {
+            return false;
+        };
+        let rule_use = rule_uses.pop().unwrap();
+        let Some(NormalFormRulesOrFixed::Rules {
+            rules: mut inlined_rules,
+        }) = self.prog.remove(&name)
+        else {
+            unreachable!()
+        };
+        let rule = inlined_rules.pop().unwrap();
+        let Some(NormalFormRulesOrFixed::Rules { rules }) = self.prog.get_mut(&rule_use.consumer)
+        else {
+            unreachable!()
+        };
+        let consumer = &mut rules[rule_use.rule_idx];
+        let NormalFormAtom::Rule(applied) = consumer.body.remove(rule_use.atom_idx) else {
+            unreachable!()
+        };
+
+        // the head of the inlined rule takes the arguments of the application,
+        // and its other variables are renamed apart
+        let mut renames = BTreeMap::new();
+        for (head, arg) in rule.head.iter().zip(applied.args) {
+            if !arg.is_generated_ignored_symbol() {
+                renames.insert(head.clone(), arg);
+            }
+        }
+        let rename = |symb: &Symbol| match renames.get(symb) {
+            Some(renamed) => renamed.clone(),
+            None if symb.is_ignored_symbol() => symb.clone(),
+            None => Symbol::new(&format!("{}#{inlined}", symb.name) as &str, symb.span),
+        };
+        let rest = consumer.body.split_off(rule_use.atom_idx);
+        consumer
+            .body
+            .extend(rule.body.into_iter().map(|atom| rename_atom(atom, &rename)));
+        consumer.body.extend(rest);
+        true
+    }
+
+    fn prune_columns(&mut self) -> bool {
+        let (uses, fixed_inputs) = self.rule_uses();
+        let mut pruned = vec![];
+        for (name, rule_uses) in uses {
+            let Some(rules) = self.rules(&name) else {
+                continue;
+            };
+            if name.is_prog_entry() || fixed_inputs.contains(&name) || rules.iter().any(has_aggr) {
+                continue;
+            }
+            let arity = rules[0].head.len();
+            if rules.iter().any(|rule| rule.head.len() != arity) {
+                continue;
+            }
+            let mut unused = vec![true; arity];
+            for rule_use in &rule_uses {
+                let consumer = self.consumer(rule_use);
+                let (NormalFormAtom::Rule(applied) | NormalFormAtom::NegatedRule(applied)) =
+                    &consumer.body[rule_use.atom_idx]
+                else {
+                    unreachable!()
+                };
+                if has_aggr(consumer) || has_search(consumer) || applied.args.len() != arity {
+                    unused.fill(false);
+                    break;
+                }
+                for (is_unused, arg) in unused.iter_mut().zip(applied.args.iter()) {
+                    *is_unused = *is_unused && occurrences(consumer, arg) == 1;
+                }
+            }
+            if unused.iter().all(|u| *u) {
+                // keep a column, so that the rule can still be used as a condition
+                unused[0] = false;
+            }
+            if unused.contains(&true) {
+                pruned.push((name, rule_uses, unused));
+            }
+        }
+
+        let changed = !pruned.is_empty();
+        for (name, rule_uses, unused) in pruned {
+            if let Some(NormalFormRulesOrFixed::Rules { rules }) = self.prog.get_mut(&name) {
+                for rule in rules {
+                    rule.head = keep_used(&rule.head, &unused);
+                    rule.aggr = keep_used(&rule.aggr, &unused);
+                }
+            }
+            for rule_use in rule_uses {
+                if let Some(NormalFormRulesOrFixed::Rules { rules }) =
+                    self.prog.get_mut(&rule_use.consumer)
+                {
+                    if let NormalFormAtom::Rule(applied) | NormalFormAtom::NegatedRule(applied) =
+                        &mut rules[rule_use.rule_idx].body[rule_use.atom_idx]
+                    {
+                        applied.args = keep_used(&applied.args, &unused);
+                    }
+                }
+            }
+        }
+        changed
+    }
+}
+
+fn has_aggr(rule: &NormalFormInlineRule) -> bool {
+    rule.aggr.iter().any(|aggr| aggr.is_some())
+}
+
+fn has_search(rule: &NormalFormInlineRule) -> bool {
+    rule.body.iter().any(|atom| {
+        matches!(
+            atom,
+            NormalFormAtom::HnswSearch(_)
+                | NormalFormAtom::FtsSearch(_)
+                | NormalFormAtom::LshSearch(_)
+        )
+    })
+}
+
+fn keep_used<T: Clone>(items: &[T], unused: &[bool]) -> Vec<T> {
+    items
+        .iter()
+        .zip(unused)
+        .filter(|(_, unused)| !**unused)
+        .map(|(item, _)| item.clone())
+        .collect()
+}
+
+/// The number of atoms of `rule` (counting the head as one) in which `symb` occurs.
+/// Must not be called on rules containing search atoms.
+fn occurrences(rule: &NormalFormInlineRule, symb: &Symbol) -> usize {
+    let in_head = rule.head.contains(symb) as usize;
+    let in_body = rule
+        .body
+        .iter()
+        .filter(|atom| match atom {
+            NormalFormAtom::Rule(r) | NormalFormAtom::NegatedRule(r) => r.args.contains(symb),
+            NormalFormAtom::Relation(r) | NormalFormAtom::NegatedRelation(r) => {
+                r.args.contains(symb)
+            }
+            NormalFormAtom::Predicate(p) => mentions(p, symb),
+            NormalFormAtom::Unification(u) => u.binding == *symb || mentions(&u.expr, symb),
+            NormalFormAtom::HnswSearch(_)
+            | NormalFormAtom::FtsSearch(_)
+            | NormalFormAtom::LshSearch(_) => unreachable!(),
+        })
+        .count();
+    in_head + in_body
+}
+
+fn rename_atom(atom: NormalFormAtom, rename: &impl Fn(&Symbol) -> Symbol) -> NormalFormAtom {
+    match atom {
+        NormalFormAtom::Rule(mut r) => {
+            r.args = r.args.iter().map(rename).collect();
+            NormalFormAtom::Rule(r)
+        }
+        NormalFormAtom::NegatedRule(mut r) => {
+            r.args = r.args.iter().map(rename).collect();
+            NormalFormAtom::NegatedRule(r)
+        }
+        NormalFormAtom::Relation(mut r) => {
+            r.args = r.args.iter().map(rename).collect();
+            NormalFormAtom::Relation(r)
+        }
+        NormalFormAtom::NegatedRelation(mut r) => {
+            r.args = r.args.iter().map(rename).collect();
+            NormalFormAtom::NegatedRelation(r)
+        }
+        NormalFormAtom::Predicate(mut p) => {
+            rename_expr(&mut p, rename);
+            NormalFormAtom::Predicate(p)
+        }
+        NormalFormAtom::Unification(mut u) => {
+            u.binding = rename(&u.binding);
+            rename_expr(&mut u.expr, rename);
+            NormalFormAtom::Unification(u)
+        }
+        NormalFormAtom::HnswSearch(_)
+        | NormalFormAtom::FtsSearch(_)
+        | NormalFormAtom::LshSearch(_) => unreachable!(),
+    }
+}
+
+fn rename_expr(expr: &mut Expr, rename: &impl Fn(&Symbol) -> Symbol) {
+    match expr {
+        Expr::Binding { var, .. } => *var = rename(var),
+        Expr::Apply { args, .. } | Expr::UnboundApply { args, .. } => {
+            for arg in args.iter_mut() {
+                rename_expr(arg, rename);
+            }
+        }
+        Expr::Cond { clauses, .. } => {
+            for (cond, val) in clauses.iter_mut() {
+                rename_expr(cond, rename);
+                rename_expr(val, rename);
+            }
+        }
+        Expr::Const { .. } => {}
+    }
+}
+
+fn mentions(expr: &Expr, symb: &Symbol) -> bool {
+    match expr {
+        Expr::Binding { var, .. } => var == symb,
+        Expr::Apply { args, .. } | Expr::UnboundApply { args, .. } => {
+            args.iter().any(|arg| mentions(arg, symb))
+        }
+        Expr::Cond { clauses, .. } => clauses
+            .iter()
+            .any(|(cond, val)| mentions(cond, symb) || mentions(val, symb)),
+        Expr::Const { .. } => false,
+    }
+}
+
+impl NormalFormInlineRule {
+    /// Simplify the expressions in the body of the rule before it is ordered:
+    ///
+    /// * subexpressions of unifications that do not depend on any variable are evaluated once,
+    ///   at compile time (predicates are already evaluated this way when normalized),
+    /// * subexpressions that occur several times in the body are computed once, by a new
+    ///   unification binding a fresh variable, and the occurrences replaced by that variable.
+    ///
+    /// Only subexpressions that some unification evaluates unconditionally (i.e. not inside a
+    /// `cond` or `if`) are shared, so that no expression is evaluated where it was not before.
+    /// The rewritten rule is what `::explain` shows.
+    pub(crate) fn optimize_exprs(mut self) -> Self {
+        for atom in self.body.iter_mut() {
+            if let NormalFormAtom::Unification(u) = atom {
+                // an expression that fails to evaluate is left in place, to raise
+                // its error at runtime, only if the unification is reached
+                let _ = u.expr.partial_eval();
+            }
+        }
+        self.share_common_subexprs();
+        self
+    }
+
+    fn share_common_subexprs(&mut self) {
+        let mut candidates = vec![];
+        for atom in &self.body {
+            if let NormalFormAtom::Unification(u) = atom {
+                if !u.one_many_unif {
+                    collect_shareable(&u.expr, &mut candidates);
+                }
+            }
+        }
+        // larger expressions first: their subexpressions are then only shared
+        // if they also occur elsewhere
+        candidates.sort_by_key(|e| Reverse(expr_size(e)));
+
+        let mut counter = 0;
+        for candidate in candidates {
+            let occurrences: usize = self
+                .body
+                .iter()
+                .map(|atom| match atom {
+                    NormalFormAtom::Unification(u) => count_occurrences(&u.expr, &candidate),
+                    NormalFormAtom::Predicate(p) => count_occurrences(p, &candidate),
+                    _ => 0,
+                })
+                .sum();
+            if occurrences < 2 {
+                continue;
+            }
+            let pos = self
+                .body
+                .iter()
+                .position(|atom| {
+                    matches!(atom, NormalFormAtom::Unification(u) if count_occurrences(&u.expr, &candidate) > 0)
+                })
+                .unwrap();
+            let span = candidate.span();
+            // a prefix of its own: `**` is taken by the variables joining atoms
+            let binding = Symbol::new(&format!("*cse*{counter}") as &str, span);
+            counter += 1;
+            for atom in self.body.iter_mut() {
+                match atom {
+                    NormalFormAtom::Unification(u) => {
+                        replace_occurrences(&mut u.expr, &candidate, &binding)
+                    }
+                    NormalFormAtom::Predicate(p) => replace_occurrences(p, &candidate, &binding),
+                    _ => {}
+                }
+            }
+            self.body.insert(
+                pos,
+                NormalFormAtom::Unification(Unification {
+                    binding,
+                    expr: candidate,
+                    one_many_unif: false,
+                    span,
+                }),
+            );
+        }
+    }
+}
+
+/// Collect the function applications evaluated whenever `expr` is
+fn collect_shareable(expr: &Expr, coll: &mut Vec<Expr>) {
+    if let Expr::Apply { args, .. } = expr {
+        if is_deterministic(expr) {
+            coll.push(expr.clone());
+        }
+        for arg in args.iter() {
+            collect_shareable(arg, coll);
+        }
+    }
+}
+
+pub(crate) fn is_deterministic(expr: &Expr) -> bool {
+    match expr {
+        Expr::Binding { .. } | Expr::Const { .. } => true,
+        Expr::Apply { op, args, .. } => {
+            !op.is_nondeterministic() && args.iter().all(is_deterministic)
+        }
+        Expr::Cond { clauses, .. } => clauses
+            .iter()
+            .all(|(cond, val)| is_deterministic(cond) && is_deterministic(val)),
+        Expr::UnboundApply { .. } => false,
+    }
+}
+
+fn expr_size(expr: &Expr) -> usize {
+    match expr {
+        Expr::Binding { .. } | Expr::Const { .. } | Expr::UnboundApply { .. } => 1,
+        Expr::Apply { args, .. } => 1 + args.iter().map(expr_size).sum::<usize>(),
+        Expr::Cond { clauses, .. } => {
+            1 + clauses
+                .iter()
+                .map(|(cond, val)| expr_size(cond) + expr_size(val))
+                .sum::<usize>()
+        }
+    }
+}
+
+/// Structural equality, ignoring source spans
+fn same_expr(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Binding { var: v1, .. }, Expr::Binding { var: v2, .. }) => v1 == v2,
+        (Expr::Const { val: v1, .. }, Expr::Const { val: v2, .. }) => v1 == v2,
+        (
+            Expr::Apply {
+                op: op1, args: a1, ..
+            },
+            Expr::Apply {
+                op: op2, args: a2, ..
+            },
+        ) => {
+            op1 == op2
+                && a1.len() == a2.len()
+                && a1.iter().zip(a2.iter()).all(|(x, y)| same_expr(x, y))
+        }
+        (Expr::Cond { clauses: c1, .. }, Expr::Cond { clauses: c2, .. }) => {
+            c1.len() == c2.len()
+                && c1
+                    .iter()
+                    .zip(c2.iter())
+                    .all(|((x1, y1), (x2, y2))| same_expr(x1, x2) && same_expr(y1, y2))
+        }
+        _ => false,
+    }
+}
+
+fn count_occurrences(expr: &Expr, target: &Expr) -> usize {
+    if same_expr(expr, target) {
+        return 1;
+    }
+    match expr {
+        Expr::Apply { args, .. } => args.iter().map(|arg| count_occurrences(arg, target)).sum(),
+        Expr::Cond { clauses, .. } => clauses
+            .iter()
+            .map(|(cond, val)| count_occurrences(cond, target) + count_occurrences(val, target))
+            .sum(),
+        Expr::Binding { .. } | Expr::Const { .. } | Expr::UnboundApply { .. } => 0,
+    }
+}
+
+fn replace_occurrences(expr: &mut Expr, target: &Expr, binding: &Symbol) {
+    if same_expr(expr, target) {
+        *expr = Expr::Binding {
+            var: binding.clone(),
+            tuple_pos: None,
+        };
+        return;
+    }
+    match expr {
+        Expr::Apply { args, .. } => {
+            for arg in args.iter_mut() {
+                replace_occurrences(arg, target, binding);
+            }
+        }
+        Expr::Cond { clauses, .. } => {
+            for (cond, val) in clauses.iter_mut() {
+                replace_occurrences(cond, target, binding);
+                replace_occurrences(val, target, binding);
+            }
+        }
+        Expr::Binding { .. } | Expr::Const { .. } | Expr::UnboundApply { .. } => {}
+    }
+}
@@ -6,6 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cmp::Reverse;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter, Write};
 use std::iter;
@@ -17,12 +18,14 @@ use miette::{bail, Diagnostic, Result};
 use smartstring::SmartString;
 use thiserror::Error;
 
+use crate::data::batch::{batched, Bitmap};
 use crate::data::expr::{compute_bounds, eval_bytecode, eval_bytecode_pred, Bytecode, Expr};
+use crate::data::functions::OP_GE;
 use crate::data::program::{FtsSearch, HnswSearch, MagicSymbol};
 use crate::data::relation::{ColType, NullableColType};
 use crate::data::symb::Symbol;
 use crate::data::tuple::{Tuple, TupleIter};
-use crate::data::value::{DataValue, ValidityTs};
+use crate::data::value::{DataValue, Validity, ValidityTs};
 use crate::parse::SourceSpan;
 use crate::runtime::minhash_lsh::LshSearch;
 use crate::runtime::relation::RelationHandle;
@@ -305,6 +308,7 @@ impl Debug for RelAlgebra {
                 .field(&r.storage.name)
                 .field(&r.filters)
                 .field(&r.valid_at)
+                .field(&r.tx_at.map(|(_, ts)| ts))
                 .finish(),
             RelAlgebra::Join(r) => {
                 if r.left.is_unit() {
@@ -355,6 +359,14 @@ impl Debug for RelAlgebra {
 ))]
 pub(crate) struct InvalidTimeTravelScanning(pub(crate) String, #[label] pub(crate) SourceSpan);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation {0} does not record transaction time")]
+#[diagnostic(code(eval::no_tx_time))]
+#[diagnostic(help(
+    "Transaction time scanning requires the relation to have a non-key column of type 'TxTime'"
+))]
+pub(crate) struct RelationHasNoTxTime(pub(crate) String, #[label] pub(crate) SourceSpan);
+
 impl RelAlgebra {
     pub(crate) fn fill_binding_indices_and_compile(&mut self) -> Result<()> {
         match self {
@@ -401,6 +413,18 @@ impl RelAlgebra {
     pub(crate) fn unit(span: SourceSpan) -> Self {
         Self::Fixed(InlineFixedRA::unit(span))
     }
+    /// The stored relation this relation reads in full, if it does nothing else.
+    /// Each of its rows is then a tuple of the stored relation.
+    pub(crate) fn as_plain_stored_scan(&self) -> Option<&RelationHandle> {
+        match self {
+            RelAlgebra::Stored(s) if s.filters.is_empty() => Some(&s.storage),
+            RelAlgebra::Join(j) if j.left.is_unit() && j.joiner.left_keys.is_empty() => {
+                j.right.as_plain_stored_scan()
+            }
+            RelAlgebra::Reorder(r) => r.relation.as_plain_stored_scan(),
+            _ => None,
+        }
+    }
     pub(crate) fn is_unit(&self) -> bool {
         if let RelAlgebra::Fixed(r) = self {
             r.bindings.is_empty() && r.data.len() == 1
@@ -429,15 +453,47 @@ impl RelAlgebra {
         storage: RelationHandle,
         span: SourceSpan,
         validity: Option<ValidityTs>,
+        tx_time: Option<ValidityTs>,
     ) -> Result<Self> {
+        let tx_at = match tx_time {
+            None => None,
+            Some(ts) => match storage.tx_time_col() {
+                None => bail!(RelationHasNoTxTime(storage.name.to_string(), span)),
+                Some(idx) => Some((idx, ts)),
+            },
+        };
         match validity {
-            None => Ok(Self::Stored(StoredRA {
-                bindings,
-                storage,
-                filters: vec![],
-                filters_bytecodes: vec![],
-                span,
-            })),
+            None => {
+                let mut filters = vec![];
+                if let Some((idx, ts)) = tx_at {
+                    // validities sort newest first, so this keeps rows recorded at or before `ts`
+                    filters.push(Expr::Apply {
+                        op: &OP_GE,
+                        args: [
+                            Expr::Binding {
+                                var: bindings[idx].clone(),
+                                tuple_pos: None,
+                            },
+                            Expr::Const {
+                                val: DataValue::Validity(Validity {
+                                    timestamp: ts,
+                                    is_assert: Reverse(true),
+                                }),
+                                span,
+                            },
+                        ]
+                        .into(),
+                        span,
+                    })
+                }
+                Ok(Self::Stored(StoredRA {
+                    bindings,
+                    storage,
+                    filters,
+                    filters_bytecodes: vec![],
+                    span,
+                }))
+            }
             Some(vld) => {
                 if storage.metadata.keys.last().unwrap().typing
                     != (NullableColType {
@@ -453,6 +509,7 @@ impl RelAlgebra {
                     filters: vec![],
                     filters_bytecodes: vec![],
                     valid_at: vld,
+                    tx_at,
                     span,
                 }))
             }
@@ -537,6 +594,7 @@ impl RelAlgebra {
                 filters_bytecodes: filter_bytecodes,
                 span,
                 valid_at,
+                tx_at,
             }) => {
                 filters.push(filter);
                 RelAlgebra::StoredWithValidity(StoredWithValidityRA {
@@ -545,6 +603,7 @@ impl RelAlgebra {
                     filters,
                     span,
                     valid_at,
+                    tx_at,
                     filters_bytecodes: filter_bytecodes,
                 })
             }
@@ -563,8 +622,8 @@ impl RelAlgebra {
                     mut right,
                     joiner,
                     to_eliminate,
+                    broadcast,
                     span,
-                    ..
                 } = *inner;
                 for filter in filters {
                     let f_bindings = filter.bindings()?;
@@ -581,6 +640,7 @@ impl RelAlgebra {
                     right,
                     joiner,
                     to_eliminate,
+                    broadcast,
                     span,
                 }));
                 if !remaining.is_empty() {
@@ -664,9 +724,24 @@ impl RelAlgebra {
                 right_keys,
             },
             to_eliminate: Default::default(),
+            broadcast: false,
             span,
         }))
     }
+    /// A join reading the right side once in full, as told by a `broadcast` hint
+    pub(crate) fn broadcast_join(
+        self,
+        right: RelAlgebra,
+        left_keys: Vec<Symbol>,
+        right_keys: Vec<Symbol>,
+        span: SourceSpan,
+    ) -> Self {
+        let mut ret = self.join(right, left_keys, right_keys, span);
+        if let RelAlgebra::Join(inner) = &mut ret {
+            inner.broadcast = true;
+        }
+        ret
+    }
     pub(crate) fn neg_join(
         self,
         right: RelAlgebra,
@@ -869,6 +944,25 @@ fn filter_iter(
     .map(flatten_err)
 }
 
+/// Like [filter_iter], but the rows are grouped into columnar batches first, so that
+/// ops with a batched implementation are evaluated over whole columns, and only the
+/// rows passing all filters are turned back into tuples.
+fn filter_batches<'a>(
+    filters: &'a [Expr],
+    it: impl Iterator<Item = Result<Tuple>> + 'a,
+) -> impl Iterator<Item = Result<Tuple>> + 'a {
+    batched(it)
+        .map_ok(move |batch| -> Result<Vec<Tuple>> {
+            let mut selection = Bitmap::new(batch.len(), true);
+            for filter in filters {
+                filter.eval_batch_pred(&batch, &mut selection)?;
+            }
+            Ok(batch.into_selected(&selection))
+        })
+        .map(flatten_err)
+        .flatten_ok()
+}
+
 fn get_eliminate_indices(bindings: &[Symbol], eliminate: &BTreeSet<Symbol>) -> BTreeSet<usize> {
     bindings
         .iter()
@@ -1053,6 +1147,10 @@ impl FtsSearchRA {
                     &mut stack,
                     &mut idf_cache,
                 )?;
+                let res = match &config.rerank {
+                    Some(rerank) => rerank.apply(&tuple[bind_idx], res)?,
+                    None => res,
+                };
                 Ok(res.into_iter().map(move |t| {
                     let mut r = tuple.clone();
                     r.extend(t);
@@ -1109,6 +1207,10 @@ impl HnswSearchRA {
                 };
 
                 let res = tx.hnsw_knn(v, &config, &filter_code, &mut stack)?;
+                let res = match &config.rerank {
+                    Some(rerank) => rerank.apply(&tuple[bind_idx], res)?,
+                    None => res,
+                };
                 Ok(res.into_iter().map(move |t| {
                     let mut r = tuple.clone();
                     r.extend(t);
@@ -1128,9 +1230,50 @@ pub(crate) struct StoredWithValidityRA {
     pub(crate) filters: Vec<Expr>,
     pub(crate) filters_bytecodes: Vec<(Vec<Bytecode>, SourceSpan)>,
     pub(crate) valid_at: ValidityTs,
+    /// Position of the transaction time column and the transaction time to slice at
+    pub(crate) tx_at: Option<(usize, ValidityTs)>,
     pub(crate) span: SourceSpan,
 }
 
+/// Keep, for each key, the newest version valid at `valid_at` that was recorded at or
+/// before `tx_at`, dropping it if it is a retraction.
+///
+/// The input must be sorted by key, with the versions of each key newest first.
+fn bitemporal_slice<'a>(
+    it: impl Iterator<Item = Result<Tuple>> + 'a,
+    n_keys: usize,
+    valid_at: ValidityTs,
+    (tx_idx, tx_at): (usize, ValidityTs),
+) -> impl Iterator<Item = Result<Tuple>> + 'a {
+    let mut done_prefix: Option<Vec<DataValue>> = None;
+    it.filter_map(move |res| -> Option<Result<Tuple>> {
+        let tuple = match res {
+            Ok(t) => t,
+            Err(e) => return Some(Err(e)),
+        };
+        if done_prefix.as_deref() == Some(&tuple[..n_keys - 1]) {
+            return None;
+        }
+        let vld = match &tuple[n_keys - 1] {
+            DataValue::Validity(v) => *v,
+            _ => return None,
+        };
+        let recorded_in_time = match &tuple[tx_idx] {
+            DataValue::Validity(t) => t.timestamp.0 .0 <= tx_at.0 .0,
+            _ => false,
+        };
+        if vld.timestamp.0 .0 > valid_at.0 .0 || !recorded_in_time {
+            return None;
+        }
+        done_prefix = Some(tuple[..n_keys - 1].to_vec());
+        if vld.is_assert.0 {
+            Some(Ok(tuple))
+        } else {
+            None
+        }
+    })
+}
+
 impl StoredWithValidityRA {
     fn fill_binding_indices_and_compile(&mut self) -> Result<()> {
         let bindings: BTreeMap<_, _> = self
@@ -1147,7 +1290,15 @@ impl StoredWithValidityRA {
         Ok(())
     }
     fn iter<'a>(&'a self, tx: &'a SessionTx<'_>) -> Result<TupleIter<'a>> {
-        let it = self.storage.skip_scan_all(tx, self.valid_at);
+        let it: TupleIter<'a> = match self.tx_at {
+            None => Box::new(self.storage.skip_scan_all(tx, self.valid_at)),
+            Some(tx_at) => Box::new(bitemporal_slice(
+                self.storage.scan_all(tx),
+                self.storage.metadata.keys.len(),
+                self.valid_at,
+                tx_at,
+            )),
+        };
         Ok(if self.filters.is_empty() {
             Box::new(it)
         } else {
@@ -1177,7 +1328,7 @@ impl StoredWithValidityRA {
                     .map(|i| tuple[*i].clone())
                     .collect_vec();
 
-                if !skip_range_check && !self.filters.is_empty() {
+                if !skip_range_check && !self.filters.is_empty() && self.tx_at.is_none() {
                     let other_bindings = &self.bindings[right_join_indices.len()..];
                     let (l_bound, u_bound) = match compute_bounds(&self.filters, other_bindings) {
                         Ok(b) => b,
@@ -1213,9 +1364,17 @@ impl StoredWithValidityRA {
                 }
                 skip_range_check = true;
                 let mut stack = vec![];
+                let found_it: TupleIter<'a> = match self.tx_at {
+                    None => Box::new(self.storage.skip_scan_prefix(tx, &prefix, self.valid_at)),
+                    Some(tx_at) => Box::new(bitemporal_slice(
+                        self.storage.scan_prefix(tx, &prefix),
+                        self.storage.metadata.keys.len(),
+                        self.valid_at,
+                        tx_at,
+                    )),
+                };
                 Right(
-                    self.storage
-                        .skip_scan_prefix(tx, &prefix, self.valid_at)
+                    found_it
                         .map(move |res_found| -> Result<Option<Tuple>> {
                             let found = res_found?;
                             for (p, span) in self.filters_bytecodes.iter() {
@@ -1500,6 +1659,8 @@ impl StoredRA {
         let it = self.storage.scan_all(tx);
         Ok(if self.filters.is_empty() {
             Box::new(it)
+        } else if self.filters.iter().any(|f| f.has_batched_op()) {
+            Box::new(filter_batches(&self.filters, it))
         } else {
             Box::new(filter_iter(self.filters_bytecodes.clone(), it))
         })
@@ -2030,6 +2191,8 @@ pub(crate) struct InnerJoin {
     pub(crate) right: RelAlgebra,
     pub(crate) joiner: Joiner,
     pub(crate) to_eliminate: BTreeSet<Symbol>,
+    /// Materialize the right side even when rows could be looked up in it
+    pub(crate) broadcast: bool,
     pub(crate) span: SourceSpan,
 }
 
@@ -2088,7 +2251,7 @@ impl InnerJoin {
                         &self.right.bindings_after_eliminate(),
                     )
                     .unwrap();
-                if join_is_prefix(&join_indices.1) {
+                if !self.broadcast && join_is_prefix(&join_indices.1) {
                     "stored_prefix_join"
                 } else {
                     "stored_mat_join"
@@ -2105,7 +2268,7 @@ impl InnerJoin {
                         &self.right.bindings_after_eliminate(),
                     )
                     .unwrap();
-                if join_is_prefix(&join_indices.1) {
+                if !self.broadcast && join_is_prefix(&join_indices.1) {
                     "stored_prefix_join"
                 } else {
                     "stored_mat_join"
@@ -2173,7 +2336,7 @@ impl InnerJoin {
                         &self.right.bindings_after_eliminate(),
                     )
                     .unwrap();
-                if join_is_prefix(&join_indices.1) {
+                if !self.broadcast && join_is_prefix(&join_indices.1) {
                     r.prefix_join(
                         tx,
                         self.left.iter(tx, delta_rule, stores)?,
@@ -2192,7 +2355,7 @@ impl InnerJoin {
                         &self.right.bindings_after_eliminate(),
                     )
                     .unwrap();
-                if join_is_prefix(&join_indices.1) {
+                if !self.broadcast && join_is_prefix(&join_indices.1) {
                     r.prefix_join(
                         tx,
                         self.left.iter(tx, delta_rule, stores)?,
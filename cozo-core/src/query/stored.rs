@@ -6,32 +6,36 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cmp::Reverse;
 use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
 use itertools::Itertools;
-use miette::{bail, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result, WrapErr};
 use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::expr::{Bytecode, Expr};
 use crate::data::program::{FixedRuleApply, InputInlineRulesOrFixed, InputProgram, RelationOp};
-use crate::data::relation::{ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
 use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN};
-use crate::data::value::{DataValue, ValidityTs};
+use crate::data::value::{DataValue, Validity, ValidityTs};
 use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::FixedRuleHandle;
 use crate::fts::tokenizer::TextAnalyzer;
 use crate::parse::expr::build_expr;
 use crate::parse::{parse_script, CozoScriptParser, Rule};
 use crate::runtime::callback::{CallbackCollector, CallbackOp};
+use crate::runtime::history::BitemporalRowImmutable;
+use crate::runtime::materialized::{RelationReadByViews, ViewNotWritable};
 use crate::runtime::minhash_lsh::HashPermutations;
 use crate::runtime::relation::{
     extend_tuple_from_v, AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
 };
 use crate::runtime::transact::SessionTx;
+use crate::runtime::trigger::{Trigger, TriggerKind};
 use crate::storage::Storage;
 use crate::{Db, NamedRows, SourceSpan, StoreTx};
 
@@ -54,6 +58,8 @@ impl<'a> SessionTx<'a> {
         propagate_triggers: bool,
         force_collect: &str,
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.writes_started += 1;
+        let writes = self.writes_started;
         let mut to_clear = vec![];
         let mut replaced_old_triggers = None;
         if op == RelationOp::Replace {
@@ -72,6 +78,15 @@ impl<'a> SessionTx<'a> {
                     struct ReplaceRelationWithIndices(String);
                     bail!(ReplaceRelationWithIndices(old_handle.name.to_string()))
                 }
+                if !old_handle.views.is_empty() {
+                    bail!(RelationReadByViews(
+                        old_handle.name.to_string(),
+                        old_handle.views.iter().join(", ")
+                    ))
+                }
+                if old_handle.materialized.is_some() {
+                    bail!(ViewNotWritable(old_handle.name.to_string()))
+                }
                 if old_handle.access_level < AccessLevel::Normal {
                     bail!(InsufficientAccessLevel(
                         old_handle.name.to_string(),
@@ -79,35 +94,25 @@ impl<'a> SessionTx<'a> {
                         old_handle.access_level
                     ));
                 }
-                if old_handle.has_triggers() {
-                    replaced_old_triggers = Some((old_handle.put_triggers, old_handle.rm_triggers))
-                }
-                for trigger in &old_handle.replace_triggers {
-                    let program = parse_script(
-                        trigger,
-                        &Default::default(),
-                        &db.fixed_rules.read().unwrap(),
+                for trigger in old_handle.enabled_triggers(TriggerKind::Replace) {
+                    self.run_trigger(
+                        db,
+                        &old_handle.name,
+                        &trigger,
+                        vec![],
                         cur_vld,
-                    )?
-                    .get_single_program()?;
-
-                    let (_, cleanups) = db
-                        .run_query(
-                            self,
-                            program,
-                            cur_vld,
-                            callback_targets,
-                            callback_collector,
-                            false,
-                        )
-                        .map_err(|err| {
-                            if err.source_code().is_some() {
-                                err
-                            } else {
-                                err.with_source_code(format!("{trigger}"))
-                            }
-                        })?;
-                    to_clear.extend(cleanups);
+                        callback_targets,
+                        callback_collector,
+                        &mut to_clear,
+                    )?;
+                }
+                if old_handle.has_triggers() {
+                    replaced_old_triggers = Some((
+                        old_handle.put_triggers,
+                        old_handle.put_trigger_options,
+                        old_handle.rm_triggers,
+                        old_handle.rm_trigger_options,
+                    ))
                 }
                 let destroy_res = self.destroy_relation(&meta.name)?;
                 if !meta.name.is_temp_store_name() {
@@ -120,19 +125,39 @@ impl<'a> SessionTx<'a> {
         } else {
             self.get_relation(&meta.name, false)?
         };
-        if let Some((old_put, old_retract)) = replaced_old_triggers {
+        if let Some((old_put, old_put_options, old_retract, old_retract_options)) =
+            replaced_old_triggers
+        {
             relation_store.put_triggers = old_put;
+            relation_store.put_trigger_options = old_put_options;
             relation_store.rm_triggers = old_retract;
+            relation_store.rm_trigger_options = old_retract_options;
         }
         let InputRelationHandle {
             metadata,
             key_bindings,
             dep_bindings,
+            expected_version,
             span,
             ..
         } = meta;
 
-        match op {
+        let is_write = !matches!(op, RelationOp::Ensure | RelationOp::EnsureNot);
+        if is_write && relation_store.materialized.is_some() {
+            bail!(ViewNotWritable(relation_store.name.to_string()))
+        }
+        if is_write {
+            // rows are only ever added by insertions, and by puts into relations without
+            // value columns, as an existing row is then written again unchanged
+            let only_added = match op {
+                RelationOp::Create | RelationOp::Insert => true,
+                RelationOp::Put => relation_store.metadata.non_keys.is_empty(),
+                _ => false,
+            };
+            self.record_relation_write(&relation_store.name, only_added);
+        }
+
+        let changed = match op {
             RelationOp::Rm | RelationOp::Delete => self.remove_from_relation(
                 db,
                 res_iter,
@@ -149,24 +174,30 @@ impl<'a> SessionTx<'a> {
                 force_collect,
                 *span,
             )?,
-            RelationOp::Ensure => self.ensure_in_relation(
-                res_iter,
-                headers,
-                cur_vld,
-                &relation_store,
-                metadata,
-                key_bindings,
-                *span,
-            )?,
-            RelationOp::EnsureNot => self.ensure_not_in_relation(
-                res_iter,
-                headers,
-                cur_vld,
-                &relation_store,
-                metadata,
-                key_bindings,
-                *span,
-            )?,
+            RelationOp::Ensure => {
+                self.ensure_in_relation(
+                    res_iter,
+                    headers,
+                    cur_vld,
+                    &relation_store,
+                    metadata,
+                    key_bindings,
+                    *span,
+                )?;
+                vec![]
+            }
+            RelationOp::EnsureNot => {
+                self.ensure_not_in_relation(
+                    res_iter,
+                    headers,
+                    cur_vld,
+                    &relation_store,
+                    metadata,
+                    key_bindings,
+                    *span,
+                )?;
+                vec![]
+            }
             RelationOp::Update => self.update_in_relation(
                 db,
                 res_iter,
@@ -179,6 +210,7 @@ impl<'a> SessionTx<'a> {
                 &relation_store,
                 metadata,
                 key_bindings,
+                expected_version,
                 force_collect,
                 *span,
             )?,
@@ -201,10 +233,91 @@ impl<'a> SessionTx<'a> {
                     *span,
                 )?,
         };
+        if is_write {
+            self.maintain_views(
+                db,
+                &relation_store,
+                &changed,
+                writes,
+                cur_vld,
+                callback_targets,
+                callback_collector,
+                &mut to_clear,
+            )?;
+        }
 
         Ok(to_clear)
     }
 
+    /// Write the rows of a materialized view that changed, putting the rows of `put` and
+    /// removing those of `rm` as `:put` and `:rm` would. Returns the rows changed, as
+    /// [SessionTx::put_into_relation] does.
+    pub(crate) fn write_view_rows<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        view: &RelationHandle,
+        put: Vec<Tuple>,
+        rm: Vec<Tuple>,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        to_clear: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<Vec<Tuple>> {
+        let bindings = |cols: &[ColumnDef]| {
+            cols.iter()
+                .map(|col| Symbol::new(col.name.clone(), Default::default()))
+                .collect_vec()
+        };
+        let key_bindings = bindings(&view.metadata.keys);
+        let dep_bindings = bindings(&view.metadata.non_keys);
+        let headers = key_bindings
+            .iter()
+            .chain(dep_bindings.iter())
+            .cloned()
+            .collect_vec();
+        let mut changed = vec![];
+        if !rm.is_empty() {
+            changed.extend(self.remove_from_relation(
+                db,
+                rm.into_iter(),
+                &headers,
+                cur_vld,
+                callback_targets,
+                callback_collector,
+                true,
+                to_clear,
+                view,
+                &view.metadata,
+                &key_bindings,
+                false,
+                "",
+                Default::default(),
+            )?);
+        }
+        if !put.is_empty() {
+            changed.extend(self.put_into_relation(
+                db,
+                put.into_iter(),
+                &headers,
+                cur_vld,
+                callback_targets,
+                callback_collector,
+                true,
+                to_clear,
+                view,
+                &view.metadata,
+                &key_bindings,
+                &dep_bindings,
+                false,
+                "",
+                Default::default(),
+            )?);
+        }
+        Ok(changed)
+    }
+
+    /// Put the rows into the relation. Returns the rows changed, both as they were and as
+    /// they are written, when materialized views read the relation.
     fn put_into_relation<'s, S: Storage<'s>>(
         &mut self,
         db: &Db<S>,
@@ -222,7 +335,7 @@ impl<'a> SessionTx<'a> {
         is_insert: bool,
         force_collect: &str,
         span: SourceSpan,
-    ) -> Result<()> {
+    ) -> Result<Vec<Tuple>> {
         let is_callback_target = callback_targets.contains(&relation_store.name)
             || force_collect == relation_store.name;
 
@@ -239,6 +352,7 @@ impl<'a> SessionTx<'a> {
             &metadata.keys,
             key_bindings,
             headers,
+            self.tx_time,
         )?;
 
         let need_to_collect = !force_collect.is_empty()
@@ -249,8 +363,10 @@ impl<'a> SessionTx<'a> {
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
         let has_fts_indices = !relation_store.fts_indices.is_empty();
         let has_lsh_indices = !relation_store.lsh_indices.is_empty();
+        let has_views = !relation_store.views.is_empty();
         let mut new_tuples: Vec<DataValue> = vec![];
         let mut old_tuples: Vec<DataValue> = vec![];
+        let mut changed = vec![];
 
         let val_extractors = if metadata.non_keys.is_empty() {
             make_extractors(
@@ -258,6 +374,7 @@ impl<'a> SessionTx<'a> {
                 &metadata.keys,
                 key_bindings,
                 headers,
+                self.tx_time,
             )?
         } else {
             make_extractors(
@@ -265,9 +382,12 @@ impl<'a> SessionTx<'a> {
                 &metadata.non_keys,
                 dep_bindings,
                 headers,
+                self.tx_time,
             )?
         };
         key_extractors.extend(val_extractors);
+        stamp_tx_time(&mut key_extractors, relation_store, self.tx_time);
+        let is_bitemporal = relation_store.is_bitemporal();
         let mut stack = vec![];
         let hnsw_filters = Self::make_hnsw_filters(relation_store)?;
         let fts_lsh_processors = self.make_fts_lsh_processors(relation_store)?;
@@ -297,6 +417,10 @@ impl<'a> SessionTx<'a> {
                 }
             }
 
+            if is_bitemporal && self.store_tx.exists(&key, false)? {
+                bail!(BitemporalRowImmutable(relation_store.name.to_string()));
+            }
+
             let val = relation_store.encode_val_for_store(&extracted, span)?;
 
             if need_to_collect
@@ -304,16 +428,21 @@ impl<'a> SessionTx<'a> {
                 || has_hnsw_indices
                 || has_fts_indices
                 || has_lsh_indices
+                || has_views
             {
                 if let Some(existing) = self.store_tx.get(&key, false)? {
                     let mut tup = extracted[0..relation_store.metadata.keys.len()].to_vec();
                     extend_tuple_from_v(&mut tup, &existing);
+                    let tup = relation_store.decrypt_tuple(tup)?;
                     if has_indices && extracted != tup {
                         self.update_in_index(relation_store, &extracted, &tup)?;
                         self.del_in_fts(relation_store, &mut stack, &fts_lsh_processors, &tup)?;
                         self.del_in_lsh(relation_store, &tup)?;
                     }
 
+                    if has_views {
+                        changed.push(tup.clone());
+                    }
                     if need_to_collect {
                         old_tuples.push(DataValue::List(tup));
                     }
@@ -339,6 +468,9 @@ impl<'a> SessionTx<'a> {
                     &lsh_perms,
                 )?;
 
+                if has_views {
+                    changed.push(extracted.clone());
+                }
                 if need_to_collect {
                     new_tuples.push(DataValue::List(extracted));
                 }
@@ -365,7 +497,7 @@ impl<'a> SessionTx<'a> {
                 old_tuples,
             )?;
         }
-        Ok(())
+        Ok(changed)
     }
 
     fn put_in_fts(
@@ -517,6 +649,8 @@ impl<'a> SessionTx<'a> {
         Ok(hnsw_filters)
     }
 
+    /// Update the rows with the keys given. Returns the rows changed, as they were and as they
+    /// are written, when materialized views read the relation.
     fn update_in_relation<'s, S: Storage<'s>>(
         &mut self,
         db: &Db<S>,
@@ -530,9 +664,10 @@ impl<'a> SessionTx<'a> {
         relation_store: &RelationHandle,
         metadata: &StoredRelationMetadata,
         key_bindings: &[Symbol],
+        expected_version: &Option<(Symbol, Expr)>,
         force_collect: &str,
         span: SourceSpan,
-    ) -> Result<()> {
+    ) -> Result<Vec<Tuple>> {
         let is_callback_target = callback_targets.contains(&relation_store.name)
             || force_collect == relation_store.name;
 
@@ -543,12 +678,16 @@ impl<'a> SessionTx<'a> {
                 relation_store.access_level
             ));
         }
+        if relation_store.is_bitemporal() {
+            bail!(BitemporalRowImmutable(relation_store.name.to_string()));
+        }
 
         let key_extractors = make_extractors(
             &relation_store.metadata.keys,
             &metadata.keys,
             key_bindings,
             headers,
+            self.tx_time,
         )?;
 
         let need_to_collect = !force_collect.is_empty()
@@ -559,15 +698,55 @@ impl<'a> SessionTx<'a> {
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
         let has_fts_indices = !relation_store.fts_indices.is_empty();
         let has_lsh_indices = !relation_store.lsh_indices.is_empty();
+        let has_views = !relation_store.views.is_empty();
         let mut new_tuples: Vec<DataValue> = vec![];
         let mut old_tuples: Vec<DataValue> = vec![];
+        let mut changed = vec![];
 
-        let val_extractors = make_update_extractors(
+        let mut val_extractors = make_update_extractors(
             &relation_store.metadata.non_keys,
             &metadata.keys,
             key_bindings,
             headers,
+            self.tx_time,
         )?;
+        if let Some(idx) = relation_store.tx_time_col() {
+            val_extractors[idx - relation_store.metadata.keys.len()] =
+                Some(DataExtractor::TxTimeExtractor(self.tx_time));
+        }
+
+        // the position of the version column among the value columns,
+        // and the expression giving the version expected for each row
+        let version_check = match expected_version {
+            None => None,
+            Some((col, expr)) => {
+                let idx = relation_store
+                    .metadata
+                    .non_keys
+                    .iter()
+                    .position(|c| c.name == col.name)
+                    .ok_or_else(|| {
+                        VersionColumnNotFound(
+                            relation_store.name.to_string(),
+                            col.name.to_string(),
+                            col.span,
+                        )
+                    })?;
+                ensure!(
+                    val_extractors[idx].is_none(),
+                    VersionColumnUpdated(col.name.to_string(), col.span)
+                );
+                let binding_map = headers
+                    .iter()
+                    .cloned()
+                    .enumerate()
+                    .map(|(i, h)| (h, i))
+                    .collect();
+                let mut expr = expr.clone();
+                expr.fill_binding_indices(&binding_map)?;
+                Some((idx, expr))
+            }
+        };
 
         let mut stack = vec![];
         let hnsw_filters = Self::make_hnsw_filters(relation_store)?;
@@ -594,7 +773,30 @@ impl<'a> SessionTx<'a> {
                         notice: "key to update does not exist".to_string()
                     })
                 }
-                Some(v) => rmp_serde::from_slice(&v[ENCODED_KEY_MIN_LEN..]).unwrap(),
+                Some(v) => relation_store
+                    .decrypt_vals(rmp_serde::from_slice(&v[ENCODED_KEY_MIN_LEN..]).unwrap())?,
+            };
+            let new_version = match &version_check {
+                None => None,
+                Some((idx, expr)) => {
+                    let expected = expr.eval(&tuple)?;
+                    let found = &original_val[*idx];
+                    if *found != expected {
+                        bail!(VersionConflict {
+                            relation: relation_store.name.to_string(),
+                            key: new_kv,
+                            expected,
+                            found: found.clone(),
+                        })
+                    }
+                    match found.get_int() {
+                        Some(v) => Some((*idx, DataValue::from(v + 1))),
+                        None => bail!(VersionNotInteger(
+                            relation_store.name.to_string(),
+                            found.clone()
+                        )),
+                    }
+                }
             };
             let mut old_kv = Vec::with_capacity(relation_store.arity());
             old_kv.extend_from_slice(&new_kv);
@@ -611,6 +813,9 @@ impl<'a> SessionTx<'a> {
                     }
                 }
             }
+            if let Some((idx, version)) = new_version {
+                new_kv[relation_store.metadata.keys.len() + idx] = version;
+            }
             let new_val = relation_store.encode_val_for_store(&new_kv, span)?;
 
             if need_to_collect
@@ -618,11 +823,15 @@ impl<'a> SessionTx<'a> {
                 || has_hnsw_indices
                 || has_fts_indices
                 || has_lsh_indices
+                || has_views
             {
                 self.del_in_fts(relation_store, &mut stack, &fts_lsh_processors, &old_kv)?;
                 self.del_in_lsh(relation_store, &old_kv)?;
                 self.update_in_index(relation_store, &new_kv, &old_kv)?;
 
+                if has_views {
+                    changed.push(old_kv.clone());
+                }
                 if need_to_collect {
                     old_tuples.push(DataValue::List(old_kv));
                 }
@@ -637,6 +846,9 @@ impl<'a> SessionTx<'a> {
                     &lsh_perms,
                 )?;
 
+                if has_views {
+                    changed.push(new_kv.clone());
+                }
                 if need_to_collect {
                     new_tuples.push(DataValue::List(new_kv));
                 }
@@ -663,7 +875,74 @@ impl<'a> SessionTx<'a> {
                 old_tuples,
             )?;
         }
-        Ok(())
+        Ok(changed)
+    }
+
+    /// Parse a trigger script, or take it as already parsed in the transaction, so that
+    /// statements run again and again, as in loops, do not parse their triggers every time.
+    /// The queries of materialized views are parsed the same way.
+    pub(crate) fn trigger_program<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        trigger: &str,
+        cur_vld: ValidityTs,
+    ) -> Result<InputProgram> {
+        let key = (trigger.to_string(), cur_vld);
+        if let Some(program) = self.trigger_programs.get(&key) {
+            return Ok(program.clone());
+        }
+        let program = parse_script(
+            trigger,
+            &Default::default(),
+            &db.fixed_rules.read().unwrap(),
+            cur_vld,
+        )?
+        .get_single_program()?;
+        self.trigger_programs.insert(key, program.clone());
+        Ok(program)
+    }
+
+    /// Run a trigger of `relation`, with `consts` giving the rows of the mutation
+    /// as the rules `_new` and `_old`. Whether its failure fails the mutation is up to its policy.
+    fn run_trigger<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        relation: &str,
+        trigger: &Trigger<'_>,
+        consts: Vec<(&str, Vec<Symbol>, Vec<DataValue>)>,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        to_clear: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        let mut program = match self.trigger_program(db, trigger.script, cur_vld) {
+            Ok(program) => program,
+            Err(err) => return trigger.recover(relation, err),
+        };
+        for (name, bindings, data) in consts {
+            make_const_rule(&mut program, name, bindings, data);
+        }
+        match db.run_query(
+            self,
+            program,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            false,
+        ) {
+            Ok((_, cleanups)) => {
+                to_clear.extend(cleanups);
+                Ok(())
+            }
+            Err(err) => {
+                let err = if err.source_code().is_some() {
+                    err
+                } else {
+                    err.with_source_code(format!("{} ", trigger.script))
+                };
+                trigger.recover(relation, err)
+            }
+        }
     }
 
     fn collect_mutations<'s, S: Storage<'s>>(
@@ -694,45 +973,20 @@ impl<'a> SessionTx<'a> {
 
         let kv_bindings = bindings;
         if propagate_triggers {
-            for trigger in &relation_store.put_triggers {
-                let mut program = parse_script(
-                    trigger,
-                    &Default::default(),
-                    &db.fixed_rules.read().unwrap(),
+            for trigger in relation_store.enabled_triggers(TriggerKind::Put) {
+                self.run_trigger(
+                    db,
+                    &relation_store.name,
+                    &trigger,
+                    vec![
+                        ("_new", kv_bindings.clone(), new_tuples.to_vec()),
+                        ("_old", kv_bindings.clone(), old_tuples.to_vec()),
+                    ],
                     cur_vld,
-                )?
-                .get_single_program()?;
-
-                make_const_rule(
-                    &mut program,
-                    "_new",
-                    kv_bindings.clone(),
-                    new_tuples.to_vec(),
-                );
-                make_const_rule(
-                    &mut program,
-                    "_old",
-                    kv_bindings.clone(),
-                    old_tuples.to_vec(),
-                );
-
-                let (_, cleanups) = db
-                    .run_query(
-                        self,
-                        program,
-                        cur_vld,
-                        callback_targets,
-                        callback_collector,
-                        false,
-                    )
-                    .map_err(|err| {
-                        if err.source_code().is_some() {
-                            err
-                        } else {
-                            err.with_source_code(format!("{trigger} "))
-                        }
-                    })?;
-                to_clear.extend(cleanups);
+                    callback_targets,
+                    callback_collector,
+                    to_clear,
+                )?;
             }
         }
 
@@ -818,6 +1072,7 @@ impl<'a> SessionTx<'a> {
             &metadata.keys,
             key_bindings,
             headers,
+            self.tx_time,
         )?;
 
         for tuple in res_iter {
@@ -865,6 +1120,7 @@ impl<'a> SessionTx<'a> {
             &metadata.keys,
             key_bindings,
             headers,
+            self.tx_time,
         )?;
 
         let val_extractors = make_extractors(
@@ -872,6 +1128,7 @@ impl<'a> SessionTx<'a> {
             &metadata.keys,
             key_bindings,
             headers,
+            self.tx_time,
         )?;
         key_extractors.extend(val_extractors);
 
@@ -898,7 +1155,16 @@ impl<'a> SessionTx<'a> {
                     })
                 }
                 Some(v) => {
-                    if &v as &[u8] != &val as &[u8] {
+                    // encrypted values are compared decrypted, as their nonces differ
+                    let matches = if relation_store.ciphers.is_some() {
+                        let stored = relation_store.decrypt_vals(
+                            rmp_serde::from_slice(&v[ENCODED_KEY_MIN_LEN..]).unwrap(),
+                        )?;
+                        stored[..] == extracted[relation_store.metadata.keys.len()..]
+                    } else {
+                        &v as &[u8] == &val as &[u8]
+                    };
+                    if !matches {
                         bail!(TransactAssertionFailure {
                             relation: relation_store.name.to_string(),
                             key: extracted,
@@ -911,6 +1177,8 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
+    /// Remove the rows with the keys given. Returns the rows removed, when materialized views
+    /// read the relation.
     fn remove_from_relation<'s, S: Storage<'s>>(
         &mut self,
         db: &Db<S>,
@@ -927,7 +1195,7 @@ impl<'a> SessionTx<'a> {
         check_exists: bool,
         force_collect: &str,
         span: SourceSpan,
-    ) -> Result<()> {
+    ) -> Result<Vec<Tuple>> {
         let is_callback_target =
             callback_targets.contains(&relation_store.name) || force_collect == relation_store.name;
 
@@ -938,11 +1206,15 @@ impl<'a> SessionTx<'a> {
                 relation_store.access_level
             ));
         }
+        if relation_store.is_bitemporal() {
+            bail!(BitemporalRowImmutable(relation_store.name.to_string()));
+        }
         let key_extractors = make_extractors(
             &relation_store.metadata.keys,
             &metadata.keys,
             key_bindings,
             headers,
+            self.tx_time,
         )?;
 
         let need_to_collect = !force_collect.is_empty()
@@ -953,9 +1225,11 @@ impl<'a> SessionTx<'a> {
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
         let has_fts_indices = !relation_store.fts_indices.is_empty();
         let has_lsh_indices = !relation_store.lsh_indices.is_empty();
+        let has_views = !relation_store.views.is_empty();
         let fts_processors = self.make_fts_lsh_processors(relation_store)?;
         let mut new_tuples: Vec<DataValue> = vec![];
         let mut old_tuples: Vec<DataValue> = vec![];
+        let mut changed = vec![];
         let mut stack = vec![];
 
         for tuple in res_iter {
@@ -978,10 +1252,11 @@ impl<'a> SessionTx<'a> {
                     });
                 }
             }
-            if need_to_collect || has_indices || has_hnsw_indices || has_fts_indices || has_lsh_indices {
+            if need_to_collect || has_indices || has_hnsw_indices || has_fts_indices || has_lsh_indices || has_views {
                 if let Some(existing) = self.store_tx.get(&key, false)? {
                     let mut tup = extracted.clone();
                     extend_tuple_from_v(&mut tup, &existing);
+                    let tup = relation_store.decrypt_tuple(tup)?;
                     self.del_in_fts(relation_store, &mut stack, &fts_processors, &tup)?;
                     self.del_in_lsh(relation_store, &tup)?;
                     if has_indices {
@@ -997,6 +1272,9 @@ impl<'a> SessionTx<'a> {
                             self.hnsw_remove(relation_store, idx_handle, &extracted)?;
                         }
                     }
+                    if has_views {
+                        changed.push(tup.clone());
+                    }
                     if need_to_collect {
                         old_tuples.push(DataValue::List(tup));
                     }
@@ -1031,41 +1309,20 @@ impl<'a> SessionTx<'a> {
             let kv_bindings = kv_bindings;
 
             if propagate_triggers {
-                for trigger in &relation_store.rm_triggers {
-                    let mut program = parse_script(
-                        trigger,
-                        &Default::default(),
-                        &db.fixed_rules.read().unwrap(),
+                for trigger in relation_store.enabled_triggers(TriggerKind::Rm) {
+                    self.run_trigger(
+                        db,
+                        &relation_store.name,
+                        &trigger,
+                        vec![
+                            ("_new", k_bindings.clone(), new_tuples.clone()),
+                            ("_old", kv_bindings.clone(), old_tuples.clone()),
+                        ],
                         cur_vld,
-                    )?
-                    .get_single_program()?;
-
-                    make_const_rule(&mut program, "_new", k_bindings.clone(), new_tuples.clone());
-
-                    make_const_rule(
-                        &mut program,
-                        "_old",
-                        kv_bindings.clone(),
-                        old_tuples.clone(),
-                    );
-
-                    let (_, cleanups) = db
-                        .run_query(
-                            self,
-                            program,
-                            cur_vld,
-                            callback_targets,
-                            callback_collector,
-                            false,
-                        )
-                        .map_err(|err| {
-                            if err.source_code().is_some() {
-                                err
-                            } else {
-                                err.with_source_code(format!("{trigger} "))
-                            }
-                        })?;
-                    to_clear.extend(cleanups);
+                        callback_targets,
+                        callback_collector,
+                        to_clear,
+                    )?;
                 }
             }
 
@@ -1104,7 +1361,7 @@ impl<'a> SessionTx<'a> {
                 ))
             }
         }
-        Ok(())
+        Ok(changed)
     }
 }
 
@@ -1117,9 +1374,37 @@ struct TransactAssertionFailure {
     notice: String,
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Version conflict for {key:?} of {relation}: expected version {expected}, found {found}")]
+#[diagnostic(code(transact::version_conflict))]
+#[diagnostic(help("The row was changed since it was read, read it again and retry"))]
+struct VersionConflict {
+    relation: String,
+    key: Vec<DataValue>,
+    expected: DataValue,
+    found: DataValue,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation {0} has no value column {1} to hold versions")]
+#[diagnostic(code(eval::version_column_not_found))]
+struct VersionColumnNotFound(String, String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The version column {0} cannot be updated explicitly")]
+#[diagnostic(code(eval::version_column_updated))]
+#[diagnostic(help("Versions are incremented automatically by `:update ... expecting`"))]
+struct VersionColumnUpdated(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The version {1} stored in relation {0} is not an integer")]
+#[diagnostic(code(eval::version_not_integer))]
+struct VersionNotInteger(String, DataValue);
+
 enum DataExtractor {
     DefaultExtractor(Expr, NullableColType),
     IndexExtractor(usize, NullableColType),
+    TxTimeExtractor(ValidityTs),
 }
 
 impl DataExtractor {
@@ -1131,19 +1416,35 @@ impl DataExtractor {
             DataExtractor::IndexExtractor(i, typ) => typ
                 .coerce(tuple[*i].clone(), cur_vld)
                 .wrap_err_with(|| format!("when processing tuple {tuple:?}"))?,
+            DataExtractor::TxTimeExtractor(tx_time) => DataValue::Validity(Validity {
+                timestamp: *tx_time,
+                is_assert: Reverse(true),
+            }),
         })
     }
 }
 
+/// Writes always record the time of the current transaction, whatever the input says
+fn stamp_tx_time(
+    extractors: &mut [DataExtractor],
+    relation_store: &RelationHandle,
+    tx_time: ValidityTs,
+) {
+    if let Some(idx) = relation_store.tx_time_col() {
+        extractors[idx] = DataExtractor::TxTimeExtractor(tx_time);
+    }
+}
+
 fn make_extractors(
     stored: &[ColumnDef],
     input: &[ColumnDef],
     bindings: &[Symbol],
     tuple_headers: &[Symbol],
+    tx_time: ValidityTs,
 ) -> Result<Vec<DataExtractor>> {
     stored
         .iter()
-        .map(|s| make_extractor(s, input, bindings, tuple_headers))
+        .map(|s| make_extractor(s, input, bindings, tuple_headers, tx_time))
         .try_collect()
 }
 
@@ -1152,12 +1453,19 @@ fn make_update_extractors(
     input: &[ColumnDef],
     bindings: &[Symbol],
     tuple_headers: &[Symbol],
+    tx_time: ValidityTs,
 ) -> Result<Vec<Option<DataExtractor>>> {
     let input_keys: BTreeSet<_> = input.iter().map(|b| &b.name).collect();
     let mut extractors = Vec::with_capacity(stored.len());
     for col in stored.iter() {
         if input_keys.contains(&col.name) {
-            extractors.push(Some(make_extractor(col, input, bindings, tuple_headers)?));
+            extractors.push(Some(make_extractor(
+                col,
+                input,
+                bindings,
+                tuple_headers,
+                tx_time,
+            )?));
         } else {
             extractors.push(None);
         }
@@ -1170,6 +1478,7 @@ fn make_extractor(
     input: &[ColumnDef],
     bindings: &[Symbol],
     tuple_headers: &[Symbol],
+    tx_time: ValidityTs,
 ) -> Result<DataExtractor> {
     for (inp_col, inp_binding) in input.iter().zip(bindings.iter()) {
         if inp_col.name == stored.name {
@@ -1185,6 +1494,8 @@ fn make_extractor(
             expr.clone(),
             stored.typing.clone(),
         ))
+    } else if stored.typing.coltype == ColType::TxTime {
+        Ok(DataExtractor::TxTimeExtractor(tx_time))
     } else {
         #[derive(Debug, Error, Diagnostic)]
         #[error("cannot make extractor for column {0}")]
@@ -1194,7 +1505,7 @@ fn make_extractor(
     }
 }
 
-fn make_const_rule(
+pub(crate) fn make_const_rule(
     program: &mut InputProgram,
     rule_name: &str,
     bindings: Vec<Symbol>,
@@ -1223,6 +1534,7 @@ fn make_const_rule(
                 arity: bindings_arity,
                 span: Default::default(),
                 fixed_impl: Arc::new(Box::new(Constant)),
+                cache: None,
             },
         },
     );
@@ -267,6 +267,7 @@ impl NormalFormProgram {
             .map(|_| NormalFormProgram {
                 prog: BTreeMap::new(),
                 disable_magic_rewrite: self.disable_magic_rewrite,
+                force_magic_rewrite: self.force_magic_rewrite,
             })
             .collect_vec();
 
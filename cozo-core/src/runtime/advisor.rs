@@ -0,0 +1,442 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::functions::{OP_COS_DIST, OP_IP_DIST, OP_L2_DIST, OP_STR_INCLUDES};
+use crate::data::program::{InputAtom, InputInlineRulesOrFixed, InputProgram};
+use crate::data::relation::{ColType, ColumnDef, VecElementType};
+use crate::data::symb::Symbol;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::parse::sys::HnswDistance;
+use crate::parse::{parse_script, CozoScript, ImperativeStmt};
+use crate::runtime::relation::RelationHandle;
+use crate::runtime::replay::ReplayEntry;
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("No workload to advise on")]
+#[diagnostic(code(advise::no_workload))]
+#[diagnostic(help(
+    "Record the workload with 'Db::set_replay_log', or give the path of a replay log"
+))]
+struct NoWorkload;
+
+/// An index that the queries of the workload could use
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Suggestion {
+    relation: SmartString<LazyCompact>,
+    kind: &'static str,
+    columns: Vec<SmartString<LazyCompact>>,
+    /// The system op creating the index
+    command: String,
+}
+
+/// Finds the indices that the stored relation applications of a script could use
+struct Advisor<'a, 'b> {
+    tx: &'a SessionTx<'b>,
+    /// The stored relations seen so far, `None` for those that do not exist
+    handles: BTreeMap<SmartString<LazyCompact>, Option<RelationHandle>>,
+    found: BTreeSet<Suggestion>,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Suggest the indices that would serve the scripts recorded in the replay log at `path`,
+    /// or in the one being recorded if no path is given.
+    ///
+    /// Suggestions come from the shape of the queries only: a secondary index for a stored
+    /// relation read with columns bound that are not a prefix of its keys, a full-text index
+    /// for columns searched with `str_includes`, and a vector index for vector columns
+    /// compared with a distance function. A row is returned for each, with the number of
+    /// recorded scripts that would use it and the time they took, as the estimated benefit.
+    pub(crate) fn advise_indexes(
+        &self,
+        tx: &SessionTx<'_>,
+        path: Option<&str>,
+    ) -> Result<NamedRows> {
+        let path = match path {
+            Some(path) => path.to_string(),
+            None => match self.replay.path() {
+                Some(path) => path,
+                None => bail!(NoWorkload),
+            },
+        };
+        let file = File::open(path).into_diagnostic()?;
+        let mut advisor = Advisor {
+            tx,
+            handles: Default::default(),
+            found: Default::default(),
+        };
+        let mut benefits: BTreeMap<Suggestion, (i64, f64)> = BTreeMap::new();
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.into_diagnostic()?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = ReplayEntry::parse(idx, &line)?;
+            // scripts that no longer parse, such as those using dropped fixed rules, are skipped
+            let script = match parse_script(
+                &entry.script,
+                &entry.params,
+                &self.fixed_rules.read().unwrap(),
+                ValidityTs(Reverse(entry.now)),
+            ) {
+                Ok(script) => script,
+                Err(_) => continue,
+            };
+            match &script {
+                CozoScript::Single(prog) => advisor.program(prog),
+                CozoScript::Imperative(stmts) => advisor.statements(stmts),
+                CozoScript::Sys(_) => {}
+            }
+            for suggestion in std::mem::take(&mut advisor.found) {
+                let (queries, secs) = benefits.entry(suggestion).or_default();
+                *queries += 1;
+                *secs += entry.elapsed;
+            }
+        }
+        let rows = benefits
+            .into_iter()
+            .sorted_by(|(_, (_, a)), (_, (_, b))| b.total_cmp(a))
+            .map(|(suggestion, (queries, secs))| {
+                vec![
+                    DataValue::Str(suggestion.relation),
+                    DataValue::from(suggestion.kind),
+                    DataValue::List(suggestion.columns.into_iter().map(DataValue::Str).collect()),
+                    DataValue::from(queries),
+                    DataValue::from(secs),
+                    DataValue::from(suggestion.command),
+                ]
+            })
+            .collect_vec();
+        Ok(NamedRows::new(
+            vec![
+                "relation".to_string(),
+                "kind".to_string(),
+                "columns".to_string(),
+                "queries".to_string(),
+                "total_secs".to_string(),
+                "command".to_string(),
+            ],
+            rows,
+        ))
+    }
+}
+
+impl<'a, 'b> Advisor<'a, 'b> {
+    fn statements(&mut self, stmts: &[ImperativeStmt]) {
+        for stmt in stmts {
+            match stmt {
+                ImperativeStmt::Program { prog } | ImperativeStmt::IgnoreErrorProgram { prog } => {
+                    self.program(&prog.prog)
+                }
+                ImperativeStmt::Return { returns } => {
+                    for prog in returns.iter().filter_map(|ret| ret.as_ref().left()) {
+                        self.program(&prog.prog)
+                    }
+                }
+                ImperativeStmt::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    if let Some(prog) = condition.as_ref().right() {
+                        self.program(&prog.prog)
+                    }
+                    self.statements(then_branch);
+                    self.statements(else_branch);
+                }
+                ImperativeStmt::Loop { body, .. } => self.statements(body),
+                ImperativeStmt::Break { .. }
+                | ImperativeStmt::Continue { .. }
+                | ImperativeStmt::SysOp { .. }
+                | ImperativeStmt::TempSwap { .. }
+                | ImperativeStmt::TempDebug { .. } => {}
+            }
+        }
+    }
+
+    fn program(&mut self, prog: &InputProgram) {
+        for rules in prog.prog.values() {
+            if let InputInlineRulesOrFixed::Rules { rules } = rules {
+                for rule in rules {
+                    self.atoms(&rule.body, &mut Default::default(), &mut Default::default());
+                }
+            }
+        }
+    }
+
+    /// Go through the atoms of a rule body in order, keeping track of the variables bound,
+    /// and of the stored columns that variables were first bound to
+    fn atoms(
+        &mut self,
+        atoms: &[InputAtom],
+        bound: &mut BTreeSet<Symbol>,
+        origins: &mut BTreeMap<Symbol, (RelationHandle, usize)>,
+    ) {
+        for atom in atoms {
+            match atom {
+                InputAtom::Rule { inner } => {
+                    for arg in &inner.args {
+                        bind(arg, bound);
+                    }
+                }
+                InputAtom::Relation { inner } => {
+                    if let Some(handle) = self.handle(&inner.name) {
+                        let args = inner.args.iter().enumerate().collect_vec();
+                        self.stored(&handle, &args, bound, origins);
+                    }
+                    for arg in &inner.args {
+                        bind(arg, bound);
+                    }
+                }
+                InputAtom::NamedFieldRelation { inner } => {
+                    if let Some(handle) = self.handle(&inner.name) {
+                        let columns = all_columns(&handle).collect_vec();
+                        let args = inner
+                            .args
+                            .iter()
+                            .filter_map(|(name, arg)| {
+                                let pos = columns.iter().position(|col| col.name == *name)?;
+                                Some((pos, arg))
+                            })
+                            .collect_vec();
+                        self.stored(&handle, &args, bound, origins);
+                    }
+                    for arg in inner.args.values() {
+                        bind(arg, bound);
+                    }
+                }
+                InputAtom::Predicate { inner } => self.expr(inner, origins),
+                InputAtom::Unification { inner } => {
+                    self.expr(&inner.expr, origins);
+                    bound.insert(inner.binding.clone());
+                }
+                InputAtom::Negation { inner, .. } => self.atoms(
+                    std::slice::from_ref(inner.as_ref()),
+                    &mut bound.clone(),
+                    &mut origins.clone(),
+                ),
+                InputAtom::Conjunction { inner, .. } => self.atoms(inner, bound, origins),
+                InputAtom::Disjunction { inner, .. } => {
+                    // only what every branch binds is bound afterwards
+                    let mut common: Option<BTreeSet<Symbol>> = None;
+                    for branch in inner {
+                        let mut branch_bound = bound.clone();
+                        self.atoms(
+                            std::slice::from_ref(branch),
+                            &mut branch_bound,
+                            &mut origins.clone(),
+                        );
+                        common = Some(match common {
+                            None => branch_bound,
+                            Some(common) => &common & &branch_bound,
+                        });
+                    }
+                    bound.extend(common.unwrap_or_default());
+                }
+                InputAtom::Search { inner } => {
+                    for arg in inner.bindings.values() {
+                        bind(arg, bound);
+                    }
+                }
+            }
+        }
+    }
+
+    /// A stored relation read with the arguments given for the columns at their positions
+    fn stored(
+        &mut self,
+        handle: &RelationHandle,
+        args: &[(usize, &Expr)],
+        bound: &BTreeSet<Symbol>,
+        origins: &mut BTreeMap<Symbol, (RelationHandle, usize)>,
+    ) {
+        let columns = all_columns(handle).collect_vec();
+        let mut bound_cols = vec![];
+        for (pos, arg) in args {
+            if *pos >= columns.len() {
+                continue;
+            }
+            let vars = match arg.bindings() {
+                Ok(vars) => vars,
+                Err(_) => continue,
+            };
+            if vars.iter().all(|var| bound.contains(var)) {
+                bound_cols.push(*pos);
+            } else if let Some(var) = arg.get_binding() {
+                if !var.is_ignored_symbol() && !var.is_generated_ignored_symbol() {
+                    origins
+                        .entry(var.clone())
+                        .or_insert_with(|| (handle.clone(), *pos));
+                }
+            }
+        }
+        bound_cols.sort_unstable();
+        bound_cols.dedup();
+        // reads with the first key bound are already served by the relation itself
+        if bound_cols.is_empty() || bound_cols[0] == 0 {
+            return;
+        }
+        if handle
+            .indices
+            .values()
+            .any(|(_, mapping)| bound_cols.contains(&mapping[0]))
+        {
+            return;
+        }
+        let names = bound_cols
+            .iter()
+            .map(|pos| columns[*pos].name.clone())
+            .collect_vec();
+        let command = format!(
+            "::index create {}:by_{} {{{}}}",
+            handle.name,
+            names.iter().join("_"),
+            names.iter().join(", ")
+        );
+        self.found.insert(Suggestion {
+            relation: handle.name.clone(),
+            kind: "index",
+            columns: names,
+            command,
+        });
+    }
+
+    /// Look for text searches and vector distances on stored columns
+    fn expr(&mut self, expr: &Expr, origins: &BTreeMap<Symbol, (RelationHandle, usize)>) {
+        if let Expr::Apply { op, args, .. } = expr {
+            let origin = |i: usize| {
+                args.get(i)
+                    .and_then(|arg| arg.get_binding())
+                    .and_then(|var| origins.get(var))
+            };
+            if **op == OP_STR_INCLUDES {
+                if let Some((handle, pos)) = origin(0) {
+                    self.fts(handle, *pos);
+                }
+            } else {
+                let distance = if **op == OP_L2_DIST {
+                    Some(HnswDistance::L2)
+                } else if **op == OP_IP_DIST {
+                    Some(HnswDistance::InnerProduct)
+                } else if **op == OP_COS_DIST {
+                    Some(HnswDistance::Cosine)
+                } else {
+                    None
+                };
+                if let Some(distance) = distance {
+                    for (handle, pos) in [origin(0), origin(1)].into_iter().flatten() {
+                        self.hnsw(handle, *pos, distance);
+                    }
+                }
+            }
+            for arg in args.iter() {
+                self.expr(arg, origins);
+            }
+        }
+    }
+
+    fn fts(&mut self, handle: &RelationHandle, pos: usize) {
+        let col = all_columns(handle).nth(pos).unwrap();
+        if !matches!(col.typing.coltype, ColType::String | ColType::Any) {
+            return;
+        }
+        if handle
+            .fts_indices
+            .values()
+            .any(|(_, manifest)| manifest.extractor == col.name)
+        {
+            return;
+        }
+        self.found.insert(Suggestion {
+            relation: handle.name.clone(),
+            kind: "fts",
+            columns: vec![col.name.clone()],
+            command: format!(
+                "::fts create {}:fts_{} {{extractor: {}, tokenizer: Simple, filters: [Lowercase]}}",
+                handle.name, col.name, col.name
+            ),
+        });
+    }
+
+    fn hnsw(&mut self, handle: &RelationHandle, pos: usize, distance: HnswDistance) {
+        let col = all_columns(handle).nth(pos).unwrap();
+        let (eltype, len) = match &col.typing.coltype {
+            ColType::Vec { eltype, len } => (eltype, len),
+            _ => return,
+        };
+        if handle
+            .hnsw_indices
+            .values()
+            .any(|(_, manifest)| manifest.vec_fields.contains(&pos))
+        {
+            return;
+        }
+        let dtype = match eltype {
+            VecElementType::F32 => "F32",
+            VecElementType::F64 => "F64",
+        };
+        let distance = match distance {
+            HnswDistance::L2 => "L2",
+            HnswDistance::InnerProduct => "IP",
+            HnswDistance::Cosine => "Cosine",
+        };
+        self.found.insert(Suggestion {
+            relation: handle.name.clone(),
+            kind: "hnsw",
+            columns: vec![col.name.clone()],
+            command: format!(
+                "::hnsw create {}:hnsw_{} {{dim: {len}, dtype: {dtype}, fields: [{}], distance: {distance}, m: 32, ef_construction: 50}}",
+                handle.name, col.name, col.name
+            ),
+        });
+    }
+
+    fn handle(&mut self, name: &Symbol) -> Option<RelationHandle> {
+        // temporary relations are gone, and indices are not indexed further
+        if name.is_temp_store_name() || name.name.contains(':') {
+            return None;
+        }
+        let tx = self.tx;
+        self.handles
+            .entry(name.name.clone())
+            .or_insert_with(|| tx.get_relation(&name.name, false).ok())
+            .clone()
+    }
+}
+
+/// The keys of a stored relation followed by the rest of its columns
+fn all_columns(handle: &RelationHandle) -> impl Iterator<Item = &ColumnDef> {
+    handle
+        .metadata
+        .keys
+        .iter()
+        .chain(handle.metadata.non_keys.iter())
+}
+
+/// Add the variables of an argument to those bound
+fn bind(arg: &Expr, bound: &mut BTreeSet<Symbol>) {
+    if let Ok(vars) = arg.bindings() {
+        bound.extend(
+            vars.into_iter()
+                .filter(|var| !var.is_ignored_symbol() && !var.is_generated_ignored_symbol()),
+        );
+    }
+}
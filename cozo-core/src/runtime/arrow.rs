@@ -0,0 +1,572 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Writing rows as Arrow IPC streams or Parquet files, with the types of the columns
+//! mapped to Arrow types instead of going through JSON, and reading the rows of Parquet
+//! files for `::import infer`.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, TimestampMicrosecondType,
+    TimestampMillisecondType, TimestampNanosecondType, TimestampSecondType, UInt16Type, UInt32Type,
+    UInt64Type, UInt8Type,
+};
+use arrow_array::{
+    Array, ArrayRef, BinaryArray, BooleanArray, FixedSizeBinaryArray, FixedSizeListArray,
+    Float32Array, Float64Array, Int64Array, ListArray, RecordBatch, StringArray, StructArray,
+    TimestampMicrosecondArray,
+};
+use arrow_buffer::{NullBuffer, OffsetBuffer};
+use arrow_schema::{DataType, Field, FieldRef, Fields, Schema, SchemaRef, TimeUnit};
+use itertools::Itertools;
+use miette::{bail, IntoDiagnostic, Result};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde_json::Value as JsonValue;
+
+use crate::data::relation::{ColType, NullableColType, VecElementType};
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Num, UuidWrapper, Validity, ValidityTs, Vector};
+use crate::parse::parse_type;
+use crate::runtime::columns::values_type;
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+/// The number of rows in each record batch written
+const BATCH_ROWS: usize = 65536;
+
+/// The key of the field metadata holding the Cozo type of the column, e.g. `<F32;128>`
+const COZO_TYPE_KEY: &str = "cozo:type";
+const EXTENSION_NAME_KEY: &str = "ARROW:extension:name";
+
+/// The format rows are exported in by [crate::Db::export_relations_arrow] and
+/// [NamedRows::to_arrow]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ArrowFormat {
+    /// An Arrow IPC stream
+    Ipc,
+    /// A Parquet file
+    Parquet,
+}
+
+impl FromStr for ArrowFormat {
+    type Err = miette::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "ipc" | "arrow" => ArrowFormat::Ipc,
+            "parquet" => ArrowFormat::Parquet,
+            s => bail!("Unknown Arrow format '{s}', expected 'ipc' or 'parquet'"),
+        })
+    }
+}
+
+impl NamedRows {
+    /// Encode the rows in the given format. The types of the columns are taken from
+    /// [NamedRows::columns] if set, otherwise from the values.
+    /// Rows in [NamedRows::next] are not included.
+    pub fn to_arrow(&self, format: ArrowFormat) -> Result<Vec<u8>> {
+        let declared = if self.columns.len() == self.headers.len() {
+            self.columns
+                .iter()
+                .map(|col| parse_type(&col.typing).ok())
+                .collect_vec()
+        } else {
+            vec![None; self.headers.len()]
+        };
+        let mut out = vec![];
+        write_arrow(&self.headers, declared, &self.rows, format, &mut out)?;
+        Ok(out)
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Export a stored relation in the given format, with the declared types of its columns
+    pub(crate) fn export_relation_arrow(&self, name: &str, format: ArrowFormat) -> Result<Vec<u8>> {
+        let (handle, rows) = self.export_relation(name)?;
+        let declared = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .map(|col| Some(col.typing.clone()))
+            .collect_vec();
+        let mut out = vec![];
+        write_arrow(&rows.headers, declared, &rows.rows, format, &mut out)?;
+        Ok(out)
+    }
+}
+
+/// Write the rows in record batches. A column whose values do not all fit its declared
+/// type, which can happen with masked columns, gets the type of its values instead.
+pub(crate) fn write_arrow<W: Write + Send>(
+    headers: &[String],
+    declared: Vec<Option<NullableColType>>,
+    rows: &[Tuple],
+    format: ArrowFormat,
+    writer: W,
+) -> Result<()> {
+    let types = declared
+        .into_iter()
+        .enumerate()
+        .map(|(i, typing)| {
+            let mut values = rows.iter().map(|row| &row[i]);
+            match typing {
+                Some(typing) if values.all(|v| fits(&typing, v)) => typing,
+                _ => values_type(rows.iter().map(|row| &row[i])),
+            }
+        })
+        .collect_vec();
+    let schema: SchemaRef = Arc::new(Schema::new(
+        headers
+            .iter()
+            .zip(types.iter())
+            .map(|(name, typing)| column_field(name, typing))
+            .collect_vec(),
+    ));
+
+    let batches = rows.chunks(BATCH_ROWS).map(|chunk| {
+        let columns = types
+            .iter()
+            .enumerate()
+            .map(|(i, typing)| build_array(typing, &chunk.iter().map(|row| &row[i]).collect_vec()))
+            .try_collect()?;
+        RecordBatch::try_new(schema.clone(), columns).into_diagnostic()
+    });
+
+    match format {
+        ArrowFormat::Ipc => {
+            let mut writer =
+                arrow_ipc::writer::StreamWriter::try_new(writer, &schema).into_diagnostic()?;
+            for batch in batches {
+                writer.write(&batch?).into_diagnostic()?;
+            }
+            writer.finish().into_diagnostic()?;
+        }
+        ArrowFormat::Parquet => {
+            let mut writer = parquet::arrow::ArrowWriter::try_new(writer, schema.clone(), None)
+                .into_diagnostic()?;
+            for batch in batches {
+                writer.write(&batch?).into_diagnostic()?;
+            }
+            writer.close().into_diagnostic()?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the value can be written as the type
+fn fits(typing: &NullableColType, value: &DataValue) -> bool {
+    match (&typing.coltype, value) {
+        (_, DataValue::Null) => typing.nullable,
+        (ColType::Any, _) => true,
+        (ColType::Bool, DataValue::Bool(_)) => true,
+        (ColType::Int, DataValue::Num(Num::Int(_))) => true,
+        (ColType::Float, DataValue::Num(_)) => true,
+        (ColType::String, DataValue::Str(_)) => true,
+        (ColType::Bytes, DataValue::Bytes(_)) => true,
+        (ColType::Uuid, DataValue::Uuid(_)) => true,
+        (ColType::List { eltype, len }, DataValue::List(l)) => {
+            len.map_or(true, |n| n == l.len()) && l.iter().all(|v| fits(eltype, v))
+        }
+        (ColType::Vec { eltype, len }, DataValue::Vec(v)) => {
+            v.el_type() == *eltype && v.len() == *len
+        }
+        (ColType::Tuple(types), DataValue::List(l)) => {
+            types.len() == l.len() && types.iter().zip(l.iter()).all(|(t, v)| fits(t, v))
+        }
+        (ColType::Validity | ColType::TxTime, DataValue::Validity(_)) => true,
+        (ColType::Json, DataValue::Json(_)) => true,
+        _ => false,
+    }
+}
+
+/// The field of a column, carrying the Cozo type in its metadata
+fn column_field(name: &str, typing: &NullableColType) -> Field {
+    let mut field = value_field(name, typing);
+    let mut metadata = field.metadata().clone();
+    metadata.insert(COZO_TYPE_KEY.to_string(), typing.to_string());
+    field.set_metadata(metadata);
+    field
+}
+
+fn value_field(name: &str, typing: &NullableColType) -> Field {
+    let (data_type, extension) = arrow_type(&typing.coltype);
+    let nullable = typing.nullable || typing.coltype == ColType::Any;
+    let field = Field::new(name, data_type, nullable);
+    match extension {
+        None => field,
+        Some(ext) => field.with_metadata(HashMap::from([(
+            EXTENSION_NAME_KEY.to_string(),
+            ext.to_string(),
+        )])),
+    }
+}
+
+/// The Arrow type of values of the Cozo type, and the name of the canonical extension
+/// type if any. Values of type `Any` are written as JSON.
+fn arrow_type(coltype: &ColType) -> (DataType, Option<&'static str>) {
+    match coltype {
+        ColType::Any | ColType::Json => (DataType::Utf8, Some("arrow.json")),
+        ColType::Bool => (DataType::Boolean, None),
+        ColType::Int => (DataType::Int64, None),
+        ColType::Float => (DataType::Float64, None),
+        ColType::String => (DataType::Utf8, None),
+        ColType::Bytes => (DataType::Binary, None),
+        ColType::Uuid => (DataType::FixedSizeBinary(16), Some("arrow.uuid")),
+        ColType::List { eltype, len } => {
+            let item: FieldRef = Arc::new(value_field("item", eltype));
+            match len {
+                None => (DataType::List(item), None),
+                Some(n) => (DataType::FixedSizeList(item, *n as i32), None),
+            }
+        }
+        ColType::Vec { eltype, len } => {
+            let item = match eltype {
+                VecElementType::F32 => Field::new("item", DataType::Float32, false),
+                VecElementType::F64 => Field::new("item", DataType::Float64, false),
+            };
+            (DataType::FixedSizeList(Arc::new(item), *len as i32), None)
+        }
+        ColType::Tuple(types) => (DataType::Struct(tuple_fields(types)), None),
+        ColType::Validity | ColType::TxTime => (DataType::Struct(validity_fields()), None),
+    }
+}
+
+fn tuple_fields(types: &[NullableColType]) -> Fields {
+    types
+        .iter()
+        .enumerate()
+        .map(|(i, t)| value_field(&format!("_{i}"), t))
+        .collect()
+}
+
+fn validity_fields() -> Fields {
+    Fields::from(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("is_assert", DataType::Boolean, false),
+    ])
+}
+
+fn null_buffer(values: &[&DataValue]) -> Option<NullBuffer> {
+    if values.iter().any(|v| matches!(v, DataValue::Null)) {
+        Some(NullBuffer::from(
+            values
+                .iter()
+                .map(|v| !matches!(v, DataValue::Null))
+                .collect_vec(),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Build the array of the values, which must fit the type. Nested arrays are built
+/// from the flattened values, with nulls standing in for the elements of null entries
+/// of fixed size.
+fn build_array(typing: &NullableColType, values: &[&DataValue]) -> Result<ArrayRef> {
+    Ok(match &typing.coltype {
+        ColType::Any => Arc::new(StringArray::from(
+            values
+                .iter()
+                .map(|v| match v {
+                    DataValue::Null => None,
+                    v => Some(JsonValue::from((*v).clone()).to_string()),
+                })
+                .collect_vec(),
+        )),
+        ColType::Json => Arc::new(StringArray::from(
+            values
+                .iter()
+                .map(|v| match v {
+                    DataValue::Json(j) => Some(j.0.to_string()),
+                    _ => None,
+                })
+                .collect_vec(),
+        )),
+        ColType::Bool => Arc::new(BooleanArray::from(
+            values.iter().map(|v| v.get_bool()).collect_vec(),
+        )),
+        ColType::Int => Arc::new(Int64Array::from(
+            values.iter().map(|v| v.get_int()).collect_vec(),
+        )),
+        ColType::Float => Arc::new(Float64Array::from(
+            values.iter().map(|v| v.get_float()).collect_vec(),
+        )),
+        ColType::String => Arc::new(StringArray::from(
+            values.iter().map(|v| v.get_str()).collect_vec(),
+        )),
+        ColType::Bytes => Arc::new(BinaryArray::from(
+            values
+                .iter()
+                .map(|v| match v {
+                    DataValue::Bytes(b) => Some(b.as_slice()),
+                    _ => None,
+                })
+                .collect_vec(),
+        )),
+        ColType::Uuid => Arc::new(
+            FixedSizeBinaryArray::try_from_sparse_iter_with_size(
+                values.iter().map(|v| match v {
+                    DataValue::Uuid(u) => Some(*u.0.as_bytes()),
+                    _ => None,
+                }),
+                16,
+            )
+            .into_diagnostic()?,
+        ),
+        ColType::List { eltype, len: None } => {
+            let mut items = vec![];
+            let mut lengths = vec![];
+            for v in values {
+                match v {
+                    DataValue::List(l) => {
+                        items.extend(l.iter());
+                        lengths.push(l.len());
+                    }
+                    _ => lengths.push(0),
+                }
+            }
+            Arc::new(
+                ListArray::try_new(
+                    Arc::new(value_field("item", eltype)),
+                    OffsetBuffer::from_lengths(lengths),
+                    build_array(eltype, &items)?,
+                    null_buffer(values),
+                )
+                .into_diagnostic()?,
+            )
+        }
+        ColType::List {
+            eltype,
+            len: Some(n),
+        } => {
+            let mut items = vec![];
+            for v in values {
+                match v {
+                    DataValue::List(l) => items.extend(l.iter()),
+                    _ => items.extend((0..*n).map(|_| &DataValue::Null)),
+                }
+            }
+            Arc::new(
+                FixedSizeListArray::try_new(
+                    Arc::new(value_field("item", eltype)),
+                    *n as i32,
+                    build_array(eltype, &items)?,
+                    null_buffer(values),
+                )
+                .into_diagnostic()?,
+            )
+        }
+        ColType::Vec { eltype, len } => {
+            let items: ArrayRef = match eltype {
+                VecElementType::F32 => {
+                    let mut items = Vec::with_capacity(values.len() * *len);
+                    for v in values {
+                        match v {
+                            DataValue::Vec(Vector::F32(a)) => items.extend(a.iter()),
+                            _ => items.extend((0..*len).map(|_| 0.)),
+                        }
+                    }
+                    Arc::new(Float32Array::from(items))
+                }
+                VecElementType::F64 => {
+                    let mut items = Vec::with_capacity(values.len() * *len);
+                    for v in values {
+                        match v {
+                            DataValue::Vec(Vector::F64(a)) => items.extend(a.iter()),
+                            _ => items.extend((0..*len).map(|_| 0.)),
+                        }
+                    }
+                    Arc::new(Float64Array::from(items))
+                }
+            };
+            let (DataType::FixedSizeList(item, _), _) = arrow_type(&typing.coltype) else {
+                unreachable!()
+            };
+            Arc::new(
+                FixedSizeListArray::try_new(item, *len as i32, items, null_buffer(values))
+                    .into_diagnostic()?,
+            )
+        }
+        ColType::Tuple(types) => {
+            let columns = types
+                .iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    let items = values
+                        .iter()
+                        .map(|v| match v {
+                            DataValue::List(l) => &l[i],
+                            _ => &DataValue::Null,
+                        })
+                        .collect_vec();
+                    build_array(t, &items)
+                })
+                .try_collect()?;
+            Arc::new(
+                StructArray::try_new(tuple_fields(types), columns, null_buffer(values))
+                    .into_diagnostic()?,
+            )
+        }
+        ColType::Validity | ColType::TxTime => {
+            let (timestamps, asserts): (Vec<_>, Vec<_>) = values
+                .iter()
+                .map(|v| match v {
+                    DataValue::Validity(vld) => (vld.timestamp.0 .0, vld.is_assert.0),
+                    _ => (0, false),
+                })
+                .unzip();
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(TimestampMicrosecondArray::from(timestamps).with_timezone("UTC")),
+                Arc::new(BooleanArray::from(asserts)),
+            ];
+            Arc::new(
+                StructArray::try_new(validity_fields(), columns, null_buffer(values))
+                    .into_diagnostic()?,
+            )
+        }
+    })
+}
+
+/// Call `f` with the row number, from 1, and the fields of each row of the Parquet file,
+/// until it returns `false`
+pub(crate) fn scan_parquet(
+    file: File,
+    mut f: impl FnMut(u64, Vec<(String, DataValue)>) -> Result<bool>,
+) -> Result<()> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+        .into_diagnostic()?
+        .build()
+        .into_diagnostic()?;
+    let mut row_no = 0;
+    for batch in reader {
+        let batch = batch.into_diagnostic()?;
+        let schema = batch.schema();
+        for i in 0..batch.num_rows() {
+            row_no += 1;
+            let fields = schema
+                .fields()
+                .iter()
+                .zip(batch.columns())
+                .map(|(field, column)| Ok((field.name().clone(), read_value(field, column, i)?)))
+                .collect::<Result<Vec<_>>>()?;
+            if !f(row_no, fields)? {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The value at `i` of the array of the field. Columns written by [write_arrow] are read
+/// back as the values written, others by their Arrow types: timestamps as seconds since
+/// the epoch, and structs as lists of the values of their fields.
+fn read_value(field: &Field, array: &dyn Array, i: usize) -> Result<DataValue> {
+    if array.is_null(i) {
+        return Ok(DataValue::Null);
+    }
+    let extension = field
+        .metadata()
+        .get(EXTENSION_NAME_KEY)
+        .map(|ext| ext.as_str());
+    Ok(match array.data_type() {
+        DataType::Boolean => DataValue::from(array.as_boolean().value(i)),
+        DataType::Int8 => DataValue::from(array.as_primitive::<Int8Type>().value(i) as i64),
+        DataType::Int16 => DataValue::from(array.as_primitive::<Int16Type>().value(i) as i64),
+        DataType::Int32 => DataValue::from(array.as_primitive::<Int32Type>().value(i) as i64),
+        DataType::Int64 => DataValue::from(array.as_primitive::<Int64Type>().value(i)),
+        DataType::UInt8 => DataValue::from(array.as_primitive::<UInt8Type>().value(i) as i64),
+        DataType::UInt16 => DataValue::from(array.as_primitive::<UInt16Type>().value(i) as i64),
+        DataType::UInt32 => DataValue::from(array.as_primitive::<UInt32Type>().value(i) as i64),
+        DataType::UInt64 => {
+            let v = array.as_primitive::<UInt64Type>().value(i);
+            match i64::try_from(v) {
+                Ok(v) => DataValue::from(v),
+                Err(_) => DataValue::from(v as f64),
+            }
+        }
+        DataType::Float32 => DataValue::from(array.as_primitive::<Float32Type>().value(i) as f64),
+        DataType::Float64 => DataValue::from(array.as_primitive::<Float64Type>().value(i)),
+        DataType::Utf8 | DataType::LargeUtf8 => {
+            let s = match array.data_type() {
+                DataType::Utf8 => array.as_string::<i32>().value(i),
+                _ => array.as_string::<i64>().value(i),
+            };
+            match extension {
+                Some("arrow.json") => {
+                    DataValue::from(serde_json::from_str::<JsonValue>(s).into_diagnostic()?)
+                }
+                _ => DataValue::from(s),
+            }
+        }
+        DataType::Binary => DataValue::Bytes(array.as_binary::<i32>().value(i).to_vec()),
+        DataType::LargeBinary => DataValue::Bytes(array.as_binary::<i64>().value(i).to_vec()),
+        DataType::FixedSizeBinary(_) => {
+            let bytes = array.as_fixed_size_binary().value(i);
+            match extension {
+                Some("arrow.uuid") => DataValue::Uuid(UuidWrapper(
+                    uuid::Uuid::from_slice(bytes).into_diagnostic()?,
+                )),
+                _ => DataValue::Bytes(bytes.to_vec()),
+            }
+        }
+        DataType::Timestamp(unit, _) => DataValue::from(match unit {
+            TimeUnit::Second => array.as_primitive::<TimestampSecondType>().value(i) as f64,
+            TimeUnit::Millisecond => {
+                array.as_primitive::<TimestampMillisecondType>().value(i) as f64 / 1e3
+            }
+            TimeUnit::Microsecond => {
+                array.as_primitive::<TimestampMicrosecondType>().value(i) as f64 / 1e6
+            }
+            TimeUnit::Nanosecond => {
+                array.as_primitive::<TimestampNanosecondType>().value(i) as f64 / 1e9
+            }
+        }),
+        DataType::List(item) => read_list(item, &array.as_list::<i32>().value(i))?,
+        DataType::LargeList(item) => read_list(item, &array.as_list::<i64>().value(i))?,
+        DataType::FixedSizeList(item, _) => read_list(item, &array.as_fixed_size_list().value(i))?,
+        DataType::Struct(fields) if *fields == validity_fields() => {
+            let columns = array.as_struct().columns();
+            DataValue::Validity(Validity {
+                timestamp: ValidityTs(Reverse(
+                    columns[0]
+                        .as_primitive::<TimestampMicrosecondType>()
+                        .value(i),
+                )),
+                is_assert: Reverse(columns[1].as_boolean().value(i)),
+            })
+        }
+        DataType::Struct(fields) => DataValue::List(
+            fields
+                .iter()
+                .zip(array.as_struct().columns())
+                .map(|(field, column)| read_value(field, column, i))
+                .try_collect()?,
+        ),
+        t => bail!("Cannot read the column {} of type {t}", field.name()),
+    })
+}
+
+fn read_list(item: &Field, values: &ArrayRef) -> Result<DataValue> {
+    Ok(DataValue::List(
+        (0..values.len())
+            .map(|j| read_value(item, values, j))
+            .try_collect()?,
+    ))
+}
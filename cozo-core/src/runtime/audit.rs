@@ -0,0 +1,331 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::iter;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use itertools::Itertools;
+use miette::Result;
+use sha2::digest::FixedOutput;
+use sha2::{Digest, Sha256};
+use smartstring::SmartString;
+
+use crate::data::program::{InputProgram, RelationOp};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::parse::sys::SysOp;
+use crate::parse::{CozoScript, SourceSpan};
+use crate::runtime::callback::CallbackOp;
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::runtime::relation::{AccessLevel, InputRelationHandle};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+/// Name of the stored relation holding the audit log
+pub(crate) const AUDIT_RELATION: &str = "cozo.audit";
+
+/// Runtime state of the audit subsystem, shared by all clones of a database
+#[derive(Default)]
+pub(crate) struct AuditState {
+    pub(crate) enabled: AtomicBool,
+    seq: AtomicU64,
+}
+
+/// A single statement to be recorded in the audit log
+pub(crate) struct AuditEntry<'a> {
+    pub(crate) actor: Option<&'a str>,
+    pub(crate) kind: &'static str,
+    pub(crate) statement: &'a str,
+    pub(crate) params: &'a BTreeMap<String, DataValue>,
+    pub(crate) error: Option<String>,
+}
+
+/// Classify a parsed script for auditing, returning `None` if it neither changes
+/// the schema nor mutates stored data.
+pub(crate) fn audit_kind(script: &CozoScript) -> Option<&'static str> {
+    match script {
+        CozoScript::Single(p) => program_audit_kind(p),
+        CozoScript::Imperative(ps) => {
+            let mut write_locks = Default::default();
+            for p in ps {
+                p.needs_write_locks(&mut write_locks);
+            }
+            if write_locks.is_empty() {
+                None
+            } else {
+                Some("mutation")
+            }
+        }
+        CozoScript::Sys(op) => match op {
+            SysOp::RemoveRelation(_)
+            | SysOp::RenameRelation(_)
+            | SysOp::CloneRelation(..)
+            | SysOp::SetTriggers(..)
+            | SysOp::SetTriggerEnabled(..)
+            | SysOp::SetAccessLevel(..)
+            | SysOp::CreateIndex(..)
+            | SysOp::CreateVectorIndex(_)
+            | SysOp::CreateFtsIndex(_)
+            | SysOp::CreateMinHashLshIndex(_)
+            | SysOp::RemoveIndex(..)
+            | SysOp::RebuildIndex(..)
+            | SysOp::DescribeRelation(..)
+            | SysOp::DescribeColumn(..)
+            | SysOp::SetRetention(..)
+            | SysOp::SetPartitions(..)
+            | SysOp::EncryptColumns(..)
+            | SysOp::SetMasks(..)
+            | SysOp::CreateMaterialized(..) => Some("ddl"),
+            SysOp::PruneHistory(..) | SysOp::MergeBranch(_) | SysOp::RefreshMaterialized(_) => {
+                Some("mutation")
+            }
+            SysOp::CreateBranch(_) | SysOp::DropBranch(_) => Some("config"),
+            SysOp::SetConfig(..)
+            | SysOp::ReloadConfig
+            | SysOp::LogLevel(Some(_))
+            | SysOp::PinRelation(..)
+            | SysOp::UnpinRelation(_) => Some("config"),
+            SysOp::InferImport(config) => config.create.then_some("ddl"),
+            SysOp::ImportGraph(_) | SysOp::ImportRdf(_) => Some("ddl"),
+            SysOp::Compact
+            | SysOp::Scrub
+            | SysOp::ListConfig
+            | SysOp::LogLevel(None)
+            | SysOp::ListPartitions
+            | SysOp::ListPinned
+            | SysOp::ListColumns(_)
+            | SysOp::ListIndices(_)
+            | SysOp::ListRelations
+            | SysOp::ListUsage
+            | SysOp::AdviseIndexes(_)
+            | SysOp::ListRunning
+            | SysOp::ListFixedRules
+            | SysOp::ListFixedRuleCache
+            | SysOp::DropFixedRuleCache
+            | SysOp::ListJobs
+            | SysOp::SubmitJob(..)
+            | SysOp::JobResult(_)
+            | SysOp::KillJob(_)
+            | SysOp::KillRunning(_)
+            | SysOp::Explain(_)
+            | SysOp::Test(_)
+            | SysOp::Diff(_)
+            | SysOp::ExportChunked(_)
+            | SysOp::ExportGraph(_)
+            | SysOp::ShowTrigger(_)
+            | SysOp::ListMasks(_)
+            | SysOp::ListBranches
+            | SysOp::ListTriggers => None,
+        },
+    }
+}
+
+pub(crate) fn program_audit_kind(p: &InputProgram) -> Option<&'static str> {
+    p.needs_write_lock()?;
+    match &p.out_opts.store_relation {
+        Some((_, RelationOp::Create | RelationOp::Replace, _)) => Some("ddl"),
+        Some(_) => Some("mutation"),
+        None => None,
+    }
+}
+
+/// Parameters are recorded as a hash only, so that the log does not leak their values
+fn hash_params(params: &BTreeMap<String, DataValue>) -> Option<String> {
+    if params.is_empty() {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    for (k, v) in params {
+        hasher.update(k.as_bytes());
+        hasher.update(rmp_serde::to_vec(v).unwrap());
+    }
+    let hash = hasher.finalize_fixed();
+    Some(hash.iter().map(|b| format!("{b:02x}")).join(""))
+}
+
+fn audit_metadata() -> StoredRelationMetadata {
+    let col = |name: &str, coltype: ColType, nullable: bool| ColumnDef {
+        name: SmartString::from(name),
+        typing: NullableColType { coltype, nullable },
+        default_gen: None,
+    };
+    StoredRelationMetadata {
+        keys: vec![
+            col("at", ColType::Float, false),
+            col("seq", ColType::Int, false),
+        ],
+        non_keys: vec![
+            col("actor", ColType::String, true),
+            col("kind", ColType::String, false),
+            col("statement", ColType::String, false),
+            col("params_hash", ColType::String, true),
+            col("error", ColType::String, true),
+        ],
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Append a row to the audit log, creating the log relation on first use.
+    pub(crate) fn append_audit(&mut self, row: Vec<DataValue>) -> Result<()> {
+        self.append_system_row(AUDIT_RELATION, audit_metadata, row)
+    }
+
+    /// Append a row to a relation kept by the system, creating it with the given columns
+    /// on first use.
+    ///
+    /// The relation is created read-only, so that it cannot be altered by queries.
+    pub(crate) fn append_system_row(
+        &mut self,
+        relation: &str,
+        metadata: fn() -> StoredRelationMetadata,
+        row: Vec<DataValue>,
+    ) -> Result<()> {
+        let name = Symbol::new(relation, SourceSpan(0, 0));
+        let handle = if self.relation_exists(&name)? {
+            self.get_relation(&name, false)?
+        } else {
+            let metadata = metadata();
+            let key_bindings = metadata
+                .keys
+                .iter()
+                .map(|c| Symbol::new(c.name.clone(), SourceSpan(0, 0)))
+                .collect();
+            let dep_bindings = metadata
+                .non_keys
+                .iter()
+                .map(|c| Symbol::new(c.name.clone(), SourceSpan(0, 0)))
+                .collect();
+            self.create_relation(InputRelationHandle {
+                name: name.clone(),
+                metadata,
+                key_bindings,
+                dep_bindings,
+                expected_version: None,
+                span: SourceSpan(0, 0),
+            })?;
+            self.set_access_level(&name, AccessLevel::ReadOnly)?;
+            self.get_relation(&name, false)?
+        };
+        let key = handle.encode_key_for_store(&row, Default::default())?;
+        let val = handle.encode_val_for_store(&row, Default::default())?;
+        self.store_tx.put(&key, &val)?;
+        Ok(())
+    }
+
+    /// The sequence number following the largest one in the audit log, for the numbering
+    /// to carry on when the database is opened again
+    pub(crate) fn next_audit_seq(&self) -> Result<u64> {
+        if !self.relation_exists(AUDIT_RELATION)? {
+            return Ok(0);
+        }
+        let handle = self.get_relation(AUDIT_RELATION, false)?;
+        let mut next = 0;
+        for tuple in handle.scan_all(self) {
+            if let Some(seq) = tuple?[1].get_int() {
+                next = next.max(seq as u64 + 1);
+            }
+        }
+        Ok(next)
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Turn the audit log on or off.
+    ///
+    /// While on, every statement that changes the schema or mutates stored data is recorded
+    /// in the read-only relation `cozo.audit`, together with its outcome. Parameters are
+    /// recorded as a hash. Register a callback on `cozo.audit` to export entries as they are
+    /// written.
+    pub fn set_audit_log(&self, enabled: bool) {
+        self.audit.enabled.store(enabled, Ordering::Release);
+    }
+
+    pub(crate) fn audit_enabled(&self) -> bool {
+        self.audit.enabled.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn seed_audit_seq(&self, next: u64) {
+        self.audit.seq.store(next, Ordering::Release);
+    }
+
+    pub(crate) fn make_audit_row(&self, entry: &AuditEntry<'_>) -> Result<Vec<DataValue>> {
+        let seq = self.audit.seq.fetch_add(1, Ordering::AcqRel);
+        Ok(vec![
+            DataValue::from(seconds_since_the_epoch()?),
+            DataValue::from(seq as i64),
+            entry.actor.map(DataValue::from).unwrap_or(DataValue::Null),
+            DataValue::from(entry.kind),
+            DataValue::from(entry.statement),
+            hash_params(entry.params)
+                .map(DataValue::from)
+                .unwrap_or(DataValue::Null),
+            entry
+                .error
+                .as_deref()
+                .map(DataValue::from)
+                .unwrap_or(DataValue::Null),
+        ])
+    }
+
+    /// Record an entry in its own transaction
+    pub(crate) fn record_audit(&'s self, entry: AuditEntry<'_>) -> Result<()> {
+        let row = self.make_audit_row(&entry)?;
+        let name = SmartString::from(AUDIT_RELATION);
+        let locks = self.obtain_relation_locks(iter::once(&name));
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+        let mut tx = self.transact_write()?;
+        tx.append_audit(row.clone())?;
+        tx.commit_tx()?;
+        self.send_audit_callbacks(row);
+        Ok(())
+    }
+
+    /// Record the outcome `res` of a statement, and return it. A failed statement keeps its
+    /// own error if the entry cannot be recorded, the failure to record it is logged instead.
+    pub(crate) fn record_audit_outcome<T>(
+        &'s self,
+        entry: AuditEntry<'_>,
+        res: Result<T>,
+    ) -> Result<T> {
+        match (self.record_audit(entry), res) {
+            (Ok(()), res) => res,
+            (Err(err), Ok(_)) => Err(err),
+            (Err(audit_err), Err(err)) => {
+                log::error!("cannot record a failed statement in the audit log: {audit_err:?}");
+                Err(err)
+            }
+        }
+    }
+
+    pub(crate) fn send_audit_callbacks(&'s self, row: Vec<DataValue>) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.current_callback_targets().contains(AUDIT_RELATION) {
+            let metadata = audit_metadata();
+            let headers = metadata
+                .keys
+                .iter()
+                .chain(metadata.non_keys.iter())
+                .map(|col| col.name.to_string())
+                .collect_vec();
+            let mut collector = BTreeMap::new();
+            collector.insert(
+                SmartString::from(AUDIT_RELATION),
+                vec![(
+                    CallbackOp::Put,
+                    NamedRows::new(headers.clone(), vec![row]),
+                    NamedRows::new(headers, vec![]),
+                )],
+            );
+            self.send_callbacks(collector)
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = row;
+    }
+}
@@ -0,0 +1,289 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::db::{OK_STR, STATUS_STR};
+use crate::runtime::diff::{relation_fingerprint, stored_relations};
+use crate::runtime::relation::{
+    AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle, RelationId,
+};
+use crate::runtime::transact::SessionTx;
+use crate::storage::mem::MemStorage;
+use crate::{Db, NamedRows, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Branch '{0}' not found")]
+#[diagnostic(code(branch::not_found))]
+struct BranchNotFound(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Branch '{0}' already exists")]
+#[diagnostic(code(branch::exists))]
+#[diagnostic(help("Merge or drop the branch first with '::branch merge' or '::branch drop'"))]
+struct BranchExists(String);
+
+/// A copy of the database made by `::branch create`, kept in memory
+pub(crate) struct Branch {
+    db: Db<MemStorage>,
+    /// The fingerprints of the stored relations when the branch was created or last merged,
+    /// telling which side has changed a relation since
+    base: BTreeMap<SmartString<LazyCompact>, String>,
+}
+
+/// The branches of a database by name, shared by all clones of the database
+#[derive(Default)]
+pub(crate) struct Branches {
+    state: Mutex<BTreeMap<SmartString<LazyCompact>, Branch>>,
+}
+
+fn fingerprints(tx: &SessionTx<'_>) -> Result<BTreeMap<SmartString<LazyCompact>, String>> {
+    stored_relations(tx)?
+        .into_iter()
+        .map(|(name, handle)| Ok((name, relation_fingerprint(tx, &handle)?)))
+        .collect()
+}
+
+fn status_ok() -> NamedRows {
+    NamedRows::new(
+        vec![STATUS_STR.to_string()],
+        vec![vec![DataValue::from(OK_STR)]],
+    )
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// The database of a branch made with `::branch create`, to run queries against.
+    /// Its stored relations can be brought back with `::branch merge`.
+    pub fn branch(&self, name: &str) -> Result<Db<MemStorage>> {
+        let branches = self.branches.state.lock().unwrap();
+        match branches.get(name) {
+            Some(branch) => Ok(branch.db.clone()),
+            None => bail!(BranchNotFound(name.to_string())),
+        }
+    }
+
+    pub(crate) fn list_branches(&self) -> NamedRows {
+        let branches = self.branches.state.lock().unwrap();
+        NamedRows::new(
+            vec!["name".to_string(), "relations".to_string()],
+            branches
+                .iter()
+                .map(|(name, branch)| {
+                    vec![
+                        DataValue::Str(name.clone()),
+                        DataValue::from(branch.base.len() as i64),
+                    ]
+                })
+                .collect(),
+        )
+    }
+
+    /// Copy everything the transaction sees into a new in-memory branch
+    pub(crate) fn create_branch(&self, tx: &SessionTx<'_>, name: &str) -> Result<NamedRows> {
+        let mut branches = self.branches.state.lock().unwrap();
+        if branches.contains_key(name) {
+            bail!(BranchExists(name.to_string()))
+        }
+        let db = Db::new(MemStorage::default())?;
+        db.db.batch_put(tx.store_tx.range_scan(&[], &[0xFF]))?;
+        db.initialize()?;
+        let base = fingerprints(tx)?;
+        branches.insert(SmartString::from(name), Branch { db, base });
+        Ok(status_ok())
+    }
+
+    pub(crate) fn drop_branch(&self, name: &str) -> Result<NamedRows> {
+        match self.branches.state.lock().unwrap().remove(name) {
+            Some(_) => Ok(status_ok()),
+            None => bail!(BranchNotFound(name.to_string())),
+        }
+    }
+
+    /// Bring the stored relations changed in the branch since it was created or last merged
+    /// into the database, unless they have also been changed in the database. A relation is
+    /// the unit of merging: when both sides have changed it, it is left alone as a conflict.
+    pub(crate) fn merge_branch(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        name: &str,
+        skip_locking: bool,
+    ) -> Result<NamedRows> {
+        let mut branches = self.branches.state.lock().unwrap();
+        let branch = match branches.get_mut(name) {
+            Some(branch) => branch,
+            None => bail!(BranchNotFound(name.to_string())),
+        };
+        let other = branch.db.transact()?;
+        let here = stored_relations(tx)?;
+        let there = stored_relations(&other)?;
+        let names: BTreeSet<_> = branch
+            .base
+            .keys()
+            .chain(here.keys())
+            .chain(there.keys())
+            .cloned()
+            .collect();
+        let locks = if skip_locking {
+            vec![]
+        } else {
+            self.obtain_relation_locks(names.iter())
+        };
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+
+        let mut rows = vec![];
+        for name in names {
+            let here_handle = here.get(&name);
+            let there_handle = there.get(&name);
+            let here_fp = here_handle
+                .map(|h| relation_fingerprint(tx, h))
+                .transpose()?;
+            let there_fp = there_handle
+                .map(|h| relation_fingerprint(&other, h))
+                .transpose()?;
+            let base_fp = branch.base.get(&name).cloned();
+            if there_fp == base_fp {
+                continue;
+            }
+            let outcome = if here_fp == there_fp {
+                "same"
+            } else if here_fp != base_fp {
+                "conflict"
+            } else if tx.take_branch_relation(&other, here_handle, there_handle)? {
+                "merged"
+            } else {
+                "unsupported"
+            };
+            if outcome == "same" || outcome == "merged" {
+                match there_fp {
+                    Some(fp) => branch.base.insert(name.clone(), fp),
+                    None => branch.base.remove(&name),
+                };
+            }
+            rows.push(vec![DataValue::Str(name), DataValue::from(outcome)]);
+        }
+        Ok(NamedRows::new(
+            vec!["relation".to_string(), "outcome".to_string()],
+            rows,
+        ))
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Make a stored relation what it is in the branch: replace its rows, create it or remove
+    /// it. Returns `false` without doing anything for changes that cannot be merged: changes
+    /// of the columns or indices, and relations with vector, FTS or LSH indices.
+    fn take_branch_relation(
+        &mut self,
+        other: &SessionTx<'_>,
+        here: Option<&RelationHandle>,
+        there: Option<&RelationHandle>,
+    ) -> Result<bool> {
+        let plain = |h: &RelationHandle| {
+            h.hnsw_indices.is_empty() && h.fts_indices.is_empty() && h.lsh_indices.is_empty()
+        };
+        match (here, there) {
+            (Some(h), Some(t)) => {
+                if !plain(h)
+                    || !plain(t)
+                    || h.metadata != t.metadata
+                    || !h.indices.keys().eq(t.indices.keys())
+                {
+                    return Ok(false);
+                }
+                if h.access_level < AccessLevel::Protected {
+                    bail!(InsufficientAccessLevel(
+                        h.name.to_string(),
+                        "merging a branch".to_string(),
+                        h.access_level
+                    ))
+                }
+                self.clear_stored_rows(h)?;
+                self.copy_branch_rows(other, t, h)?;
+                for (idx_name, (idx_handle, _)) in &h.indices {
+                    self.clear_stored_rows(idx_handle)?;
+                    self.copy_branch_rows(other, &t.indices[idx_name].0, idx_handle)?;
+                }
+                self.record_relation_write(&h.name, false);
+            }
+            (None, Some(t)) => {
+                if !plain(t) {
+                    return Ok(false);
+                }
+                let created =
+                    self.create_relation(InputRelationHandle::with_columns(&t.name, &t.metadata))?;
+                self.copy_branch_rows(other, t, &created)?;
+                let mut handle = t.clone();
+                handle.id = created.id;
+                for (idx_name, (idx_handle, _)) in handle.indices.iter_mut() {
+                    let new_idx = self.create_relation(InputRelationHandle::with_columns(
+                        &format!("{}:{}", t.name, idx_name),
+                        &idx_handle.metadata,
+                    ))?;
+                    self.copy_branch_rows(other, idx_handle, &new_idx)?;
+                    *idx_handle = new_idx;
+                }
+                self.record_relation_write(&handle.name, true);
+                let name_key =
+                    vec![DataValue::Str(handle.name.clone())].encode_as_key(RelationId::SYSTEM);
+                let mut meta_val = vec![];
+                handle
+                    .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+                    .unwrap();
+                self.store_tx.put(&name_key, &meta_val)?;
+            }
+            (Some(h), None) => {
+                if !h.has_no_index() {
+                    return Ok(false);
+                }
+                self.destroy_relation(&h.name)?;
+                self.clear_stored_rows(h)?;
+            }
+            (None, None) => {}
+        }
+        Ok(true)
+    }
+
+    fn clear_stored_rows(&mut self, handle: &RelationHandle) -> Result<()> {
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let keys: Vec<_> = self
+            .store_tx
+            .range_scan(&lower, &upper)
+            .map_ok(|(k, _)| k)
+            .try_collect()?;
+        for key in keys {
+            self.store_tx.del(&key)?;
+        }
+        Ok(())
+    }
+
+    fn copy_branch_rows(
+        &mut self,
+        other: &SessionTx<'_>,
+        from: &RelationHandle,
+        to: &RelationHandle,
+    ) -> Result<()> {
+        let lower = Tuple::default().encode_as_key(from.id);
+        let upper = Tuple::default().encode_as_key(from.id.next());
+        for kv in other.store_tx.range_scan(&lower, &upper) {
+            let (k, v) = to.adopt_stored_row(kv?);
+            self.store_tx.put(&k, &v)?;
+        }
+        Ok(())
+    }
+}
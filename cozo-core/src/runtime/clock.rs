@@ -0,0 +1,41 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Reverse;
+
+use crate::data::functions::current_validity;
+use crate::data::value::ValidityTs;
+use crate::{Db, Storage};
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Fix the clock of the database at `now`, in seconds since the epoch, for all scripts
+    /// run from now on. Pass `None` to go back to the system clock.
+    ///
+    /// While the clock is fixed, `now()` returns `now`, and mutations of relations with
+    /// validity, as well as `'NOW'` in time travel queries, take `now` as the current time.
+    /// This makes scripts depending on the time reproducible, for example in tests.
+    /// A single query can fix its own clock with the option `:fixed_now`.
+    ///
+    /// The fixed clock does not apply to transaction time: columns of type `TxTime` are
+    /// always stamped with the time the transaction started at by the system clock, so that
+    /// they record when rows were really written.
+    pub fn set_fixed_now(&self, now: Option<f64>) {
+        *self.fixed_now.lock().unwrap() =
+            now.map(|secs| ValidityTs(Reverse((secs * 1e6).round() as i64)));
+    }
+
+    /// The time fixed by [Db::set_fixed_now], if any
+    pub(crate) fn fixed_now(&self) -> Option<ValidityTs> {
+        *self.fixed_now.lock().unwrap()
+    }
+
+    /// The current time, as given by the clock of the database
+    pub(crate) fn current_validity(&self) -> ValidityTs {
+        self.fixed_now().unwrap_or_else(current_validity)
+    }
+}
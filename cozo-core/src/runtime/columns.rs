@@ -0,0 +1,192 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::program::{InputAtom, InputInlineRulesOrFixed, InputProgram};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, VecElementType};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Num, Vector};
+use crate::parse::SourceSpan;
+use crate::runtime::transact::SessionTx;
+
+/// Type and origin of a column of query results
+#[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ColumnInfo {
+    /// The type of the values, written as in schemas, e.g. `Int`, `Bytes?` or `<F32;128>`.
+    /// Taken from the schema if the column comes from a stored relation, otherwise from
+    /// the values returned, which are of type `Any?` if they have different types.
+    #[serde(rename = "type")]
+    pub typing: String,
+    /// The stored relation the values are read from, if known
+    pub relation: Option<String>,
+    /// The column of `relation` the values are read from
+    pub column: Option<String>,
+    /// The description of `column`, set with `::describe set`, left out of JSON if none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// A stored relation, one of its columns and the description of the column
+pub(crate) type ColumnSource = (
+    SmartString<LazyCompact>,
+    ColumnDef,
+    Option<SmartString<LazyCompact>>,
+);
+
+/// The stored column each output column of the entry rule is bound to,
+/// if the same in all the rules making up the entry
+pub(crate) fn entry_column_sources(
+    tx: &SessionTx<'_>,
+    program: &InputProgram,
+) -> Vec<Option<ColumnSource>> {
+    let rules = match program.prog.get(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0))) {
+        Some(InputInlineRulesOrFixed::Rules { rules }) => rules,
+        _ => return vec![],
+    };
+    let arity = rules.last().unwrap().head.len();
+    (0..arity)
+        .map(|i| {
+            let mut sources = rules
+                .iter()
+                .map(|rule| match (rule.head.get(i), rule.aggr.get(i)) {
+                    (Some(var), Some(None)) => rule
+                        .body
+                        .iter()
+                        .find_map(|atom| bound_column(tx, atom, var)),
+                    _ => None,
+                });
+            let first = sources.next()??;
+            if sources.all(
+                |s| matches!(s, Some((rel, col, _)) if rel == first.0 && col.name == first.1.name),
+            ) {
+                Some(first)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The stored column bound to `var` by the atom, if it applies a stored relation
+fn bound_column(tx: &SessionTx<'_>, atom: &InputAtom, var: &Symbol) -> Option<ColumnSource> {
+    let (name, col) = match atom {
+        InputAtom::Relation { inner } => {
+            let pos = inner
+                .args
+                .iter()
+                .position(|arg| arg.get_binding() == Some(var))?;
+            (&inner.name, Err(pos))
+        }
+        InputAtom::NamedFieldRelation { inner } => {
+            let (col, _) = inner
+                .args
+                .iter()
+                .find(|(_, arg)| arg.get_binding() == Some(var))?;
+            (&inner.name, Ok(col))
+        }
+        _ => return None,
+    };
+    let handle = tx.get_relation(name, false).ok()?;
+    let mut cols = handle
+        .metadata
+        .keys
+        .iter()
+        .chain(handle.metadata.non_keys.iter());
+    let def = match col {
+        Ok(col) => cols.find(|def| def.name == *col)?,
+        Err(pos) => cols.nth(pos)?,
+    };
+    let description = handle.column_descriptions.get(&def.name).cloned();
+    Some((handle.name, def.clone(), description))
+}
+
+/// Describe the columns of the rows, using the known sources of the columns if any
+pub(crate) fn column_infos(
+    sources: Vec<Option<ColumnSource>>,
+    arity: usize,
+    rows: &[Tuple],
+) -> Vec<ColumnInfo> {
+    let mut sources = sources.into_iter();
+    (0..arity)
+        .map(|i| match sources.next().flatten() {
+            Some((relation, def, description)) => ColumnInfo {
+                typing: def.typing.to_string(),
+                relation: Some(relation.to_string()),
+                column: Some(def.name.to_string()),
+                description: description.map(|d| d.to_string()),
+            },
+            None => ColumnInfo {
+                typing: values_type(rows.iter().filter_map(|row| row.get(i))).to_string(),
+                relation: None,
+                column: None,
+                description: None,
+            },
+        })
+        .collect()
+}
+
+/// The narrowest type of the values
+pub(crate) fn values_type<'a>(values: impl Iterator<Item = &'a DataValue>) -> NullableColType {
+    let mut coltype = None;
+    let mut nullable = false;
+    for value in values {
+        match value_type(value) {
+            None => nullable = true,
+            Some(t) => match &coltype {
+                None => coltype = Some(t),
+                Some(seen) if *seen == t => {}
+                Some(_) => {
+                    return NullableColType {
+                        coltype: ColType::Any,
+                        nullable: true,
+                    }
+                }
+            },
+        }
+    }
+    NullableColType {
+        coltype: coltype.unwrap_or(ColType::Any),
+        nullable,
+    }
+}
+
+fn value_type(value: &DataValue) -> Option<ColType> {
+    Some(match value {
+        DataValue::Null => return None,
+        DataValue::Bool(_) => ColType::Bool,
+        DataValue::Num(Num::Int(_)) => ColType::Int,
+        DataValue::Num(Num::Float(_)) => ColType::Float,
+        DataValue::Str(_) => ColType::String,
+        DataValue::Bytes(_) => ColType::Bytes,
+        DataValue::Uuid(_) => ColType::Uuid,
+        DataValue::List(_) => ColType::List {
+            eltype: Box::new(NullableColType {
+                coltype: ColType::Any,
+                nullable: true,
+            }),
+            len: None,
+        },
+        DataValue::Vec(Vector::F32(v)) => ColType::Vec {
+            eltype: VecElementType::F32,
+            len: v.len(),
+        },
+        DataValue::Vec(Vector::F64(v)) => ColType::Vec {
+            eltype: VecElementType::F64,
+            len: v.len(),
+        },
+        DataValue::Json(_) => ColType::Json,
+        DataValue::Validity(_) => ColType::Validity,
+        DataValue::Regex(_)
+        | DataValue::Set(_)
+        | DataValue::Mat(_)
+        | DataValue::Sparse(_)
+        | DataValue::Bot => ColType::Any,
+    })
+}
@@ -11,6 +11,7 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::default::Default;
 use std::fmt::{Debug, Formatter};
 use std::iter;
+use std::mem;
 use std::path::Path;
 #[allow(unused_imports)]
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
@@ -28,10 +29,13 @@ use itertools::Itertools;
 use miette::Report;
 #[allow(unused_imports)]
 use miette::{bail, ensure, miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::builder::Mutation;
 use crate::data::functions::current_validity;
 use crate::data::json::JsonValue;
 use crate::data::program::{InputProgram, QueryAssertion, RelationOp, ReturnMutation};
@@ -41,23 +45,50 @@ use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
 use crate::fixed_rule::DEFAULT_FIXED_RULES;
 use crate::fts::TokenizerCache;
 use crate::parse::sys::SysOp;
-use crate::parse::{parse_expressions, parse_script, CozoScript, SourceSpan};
+use crate::parse::{
+    matches_rule, parse_expressions, parse_script, CozoScript, Rule, SourceSpan,
+};
 use crate::query::compile::{CompiledProgram, CompiledRule, CompiledRuleSet};
+use crate::query::magic::{explain_magic, MagicExemption};
 use crate::query::ra::{
     FilteredRA, FtsSearchRA, HnswSearchRA, InnerJoin, LshSearchRA, NegJoin, RelAlgebra, ReorderRA,
     StoredRA, StoredWithValidityRA, TempStoreRA, UnificationRA,
 };
+#[cfg(feature = "arrow")]
+use crate::runtime::arrow::ArrowFormat;
 #[allow(unused_imports)]
 use crate::runtime::callback::{
     CallbackCollector, CallbackDeclaration, CallbackOp, EventCallbackRegistry,
 };
+use crate::runtime::columns::{column_infos, entry_column_sources, ColumnInfo};
+use crate::runtime::audit::{
+    audit_kind, program_audit_kind, AuditEntry, AuditState, AUDIT_RELATION,
+};
+use crate::runtime::branch::Branches;
+use crate::runtime::degraded::{inconsistent_index, DegradedIndices};
+use crate::runtime::encryption::{KeyProvider, KeyRing};
+use crate::runtime::fixed_rule_cache::PendingFixedRuleOutputs;
+use crate::runtime::fixpoint_cache::{fixpoint_key, FixpointCache};
+use crate::runtime::pinned::PinnedRelations;
+use crate::runtime::history::NoRetentionPolicy;
+use crate::runtime::jobs::JobQueue;
+use crate::runtime::lifecycle::{DbClosed, DbReadOnly, Lifecycle};
+use crate::runtime::limits::ResultLimits;
+use crate::runtime::logging::QueryLog;
+use crate::runtime::profile::ProfileState;
 use crate::runtime::relation::{
     extend_tuple_from_v, AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId,
 };
+use crate::runtime::replay::ReplayLog;
+use crate::runtime::rerank::{Reranker, RerankerRegistry};
+use crate::runtime::scrub::SCRUB_RELATION;
+use crate::runtime::stream::RowSink;
 use crate::runtime::transact::SessionTx;
+use crate::runtime::trigger::trigger_columns;
+use crate::runtime::usage::UsageStats;
 use crate::storage::temp::TempStorage;
-use crate::storage::Storage;
-use crate::{decode_tuple_from_kv, FixedRule, Symbol};
+use crate::storage::{Storage, StorageStats};
+use crate::{FixedRule, Symbol};
 
 pub(crate) struct RunningQueryHandle {
     pub(crate) started_at: f64,
@@ -73,7 +104,7 @@ impl Drop for RunningQueryCleanup {
     fn drop(&mut self) {
         let mut map = self.running_queries.lock().unwrap();
         if let Some(handle) = map.remove(&self.id) {
-            handle.poison.0.store(true, Ordering::Relaxed);
+            handle.poison.kill();
         }
     }
 }
@@ -102,11 +133,46 @@ pub struct Db<S> {
     pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
     pub(crate) fixed_rules: Arc<ShardedLock<BTreeMap<String, Arc<Box<dyn FixedRule>>>>>,
     pub(crate) tokenizers: Arc<TokenizerCache>,
+    pub(crate) rerankers: Arc<RerankerRegistry>,
+    /// Set by [Db::set_key_provider]
+    pub(crate) keys: Arc<KeyRing>,
     #[cfg(not(target_arch = "wasm32"))]
     callback_count: Arc<AtomicU32>,
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) event_callbacks: Arc<ShardedLock<EventCallbackRegistry>>,
     relation_locks: Arc<ShardedLock<BTreeMap<SmartString<LazyCompact>, Arc<ShardedLock<()>>>>>,
+    pub(crate) audit: Arc<AuditState>,
+    pub(crate) lifecycle: Arc<Lifecycle>,
+    pub(crate) result_limits: Arc<ResultLimits>,
+    /// Set by [Db::set_fixed_now]
+    pub(crate) fixed_now: Arc<Mutex<Option<ValidityTs>>>,
+    pub(crate) fixpoint_cache: Arc<FixpointCache>,
+    pub(crate) pinned: Arc<PinnedRelations>,
+    /// Set by [Db::set_resource_profile]
+    pub(crate) profile: Arc<ProfileState>,
+    /// Set by [Db::set_replay_log]
+    pub(crate) replay: Arc<ReplayLog>,
+    /// Set by [Db::set_storage_stats]
+    storage_stats: Arc<AtomicBool>,
+    /// Set by [Db::set_deterministic]
+    deterministic: Arc<AtomicBool>,
+    /// Set by [Db::set_config_reloader]
+    config_reloader: Arc<Mutex<Option<ConfigReloader>>>,
+    /// Shown by `::usage`
+    pub(crate) usage: Arc<UsageStats>,
+    pub(crate) pending_fixed_rule_outputs: PendingFixedRuleOutputs,
+    pub(crate) jobs: Arc<JobQueue>,
+    /// Set by [Db::set_index_fallback]
+    pub(crate) degraded: Arc<DegradedIndices>,
+    pub(crate) branches: Arc<Branches>,
+    /// Set on the clone a job is run with
+    pub(crate) job: Option<Poison>,
+    /// Set on the clone a script is streamed with, see [Db::run_script_streaming]
+    pub(crate) row_sink: Option<RowSink>,
+    /// Set by [Db::with_unmask]
+    unmask: bool,
+    /// Set by [Db::with_session]
+    pub(crate) session: Option<Arc<str>>,
 }
 
 impl<S> Debug for Db<S> {
@@ -125,8 +191,50 @@ pub(crate) struct BadDbInit(#[help] pub(crate) String);
 #[diagnostic(code(tx::import_into_index))]
 pub(crate) struct ImportIntoIndex(pub(crate) String);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot deserialize row {0}: {1}")]
+#[diagnostic(code(eval::row_deserialization))]
+#[diagnostic(help("Fields are matched to the columns by name"))]
+pub(crate) struct RowDeserializationError(usize, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot serialize {0} into {1}: {2}")]
+#[diagnostic(code(eval::row_serialization))]
+pub(crate) struct RowSerializationError(String, &'static str, String);
+
+/// The fields of `value`, which must serialize as a map or struct
+fn serialize_fields<T: Serialize>(
+    value: &T,
+    what: String,
+    into: &'static str,
+) -> Result<serde_json::Map<String, JsonValue>> {
+    match serde_json::to_value(value) {
+        Ok(JsonValue::Object(fields)) => Ok(fields),
+        Ok(_) => bail!(RowSerializationError(
+            what,
+            into,
+            "only maps and structs can be serialized".to_string()
+        )),
+        Err(err) => bail!(RowSerializationError(what, into, err.to_string())),
+    }
+}
+
+/// Turn a map or struct into parameters of a script, one for each of its fields.
+/// Values are converted as JSON parameters are.
+pub fn to_params<T: Serialize>(value: &T) -> Result<BTreeMap<String, DataValue>> {
+    let fields = serialize_fields(value, "the value".to_string(), "parameters")?;
+    Ok(fields
+        .into_iter()
+        .map(|(k, v)| (k, DataValue::from(v)))
+        .collect())
+}
+
 #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, Clone, Default)]
 /// Rows in a relation, together with headers for the fields.
+///
+/// Build it with [NamedRows::new] and set the other fields afterwards: fields are added as
+/// the results of scripts carry more information.
+#[non_exhaustive]
 pub struct NamedRows {
     /// The headers
     pub headers: Vec<String>,
@@ -134,6 +242,26 @@ pub struct NamedRows {
     pub rows: Vec<Tuple>,
     /// Contains the next named rows, if exists
     pub next: Option<Box<NamedRows>>,
+    /// Whether rows were left out because the result exceeded the limits set by
+    /// [Db::set_result_limits]
+    #[serde(default)]
+    pub truncated: bool,
+    /// Whether recursive rules were stopped by `:max_iterations` before reaching their
+    /// fixpoint, so that the rows may be missing some results
+    #[serde(default)]
+    pub incomplete: bool,
+    /// The work done by the storage engine for the script, if counted as set by
+    /// [Db::set_storage_stats]
+    #[serde(default)]
+    pub storage_stats: Option<StorageStats>,
+    /// The type and origin of each column, in the order of the headers.
+    /// Only set for the results of queries.
+    #[serde(default)]
+    pub columns: Vec<ColumnInfo>,
+    /// Problems that did not stop the script, such as indices read around as set by
+    /// [Db::set_index_fallback]
+    #[serde(default)]
+    pub warnings: Vec<String>,
 }
 
 impl IntoIterator for NamedRows {
@@ -152,6 +280,11 @@ impl NamedRows {
             headers,
             rows,
             next: None,
+            truncated: false,
+            incomplete: false,
+            storage_stats: None,
+            columns: vec![],
+            warnings: vec![],
         }
     }
 
@@ -187,11 +320,27 @@ impl NamedRows {
             .into_iter()
             .map(|row| row.into_iter().map(JsonValue::from).collect::<JsonValue>())
             .collect::<JsonValue>();
-        json!({
+        let mut ret = json!({
             "headers": self.headers,
             "rows": rows,
             "next": nxt,
-        })
+        });
+        if self.truncated {
+            ret["truncated"] = json!(true);
+        }
+        if self.incomplete {
+            ret["incomplete"] = json!(true);
+        }
+        if let Some(stats) = self.storage_stats {
+            ret["storage_stats"] = json!(stats);
+        }
+        if !self.columns.is_empty() {
+            ret["columns"] = json!(self.columns);
+        }
+        if !self.warnings.is_empty() {
+            ret["warnings"] = json!(self.warnings);
+        }
+        ret
     }
     /// Make named rows from JSON
     pub fn from_json(value: &JsonValue) -> Result<Self> {
@@ -225,11 +374,63 @@ impl NamedRows {
                 Ok(row.iter().map(DataValue::from).collect_vec())
             })
             .try_collect()?;
-        Ok(Self {
-            headers,
-            rows,
-            next: None,
-        })
+        let mut ret = Self::new(headers, rows);
+        if let Some(columns) = value.get("columns") {
+            ret.columns = serde_json::from_value(columns.clone())
+                .map_err(|err| miette!("bad 'columns' field: {err}"))?;
+        }
+        Ok(ret)
+    }
+
+    /// Deserialize each row into a `T`, whose fields are matched to the columns by name.
+    /// Values are converted as by [NamedRows::into_json], so that e.g. bytes are read
+    /// as base64-encoded strings.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                let fields = self
+                    .headers
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().map(|v| JsonValue::from(v.clone())))
+                    .collect();
+                serde_json::from_value(JsonValue::Object(fields))
+                    .map_err(|err| RowDeserializationError(i, err.to_string()).into())
+            })
+            .collect()
+    }
+
+    /// Make named rows from maps or structs, one row for each, with their fields as columns.
+    /// All items must have the same fields, the columns are in the order of their names.
+    pub fn serialize<T: Serialize>(items: &[T]) -> Result<Self> {
+        let mut headers: Option<Vec<String>> = None;
+        let mut rows = Vec::with_capacity(items.len());
+        for (i, item) in items.iter().enumerate() {
+            let fields = serialize_fields(item, format!("item {i}"), "a row")?;
+            match &headers {
+                None => headers = Some(fields.keys().cloned().collect()),
+                Some(expected) => ensure!(
+                    fields.keys().eq(expected.iter()),
+                    RowSerializationError(
+                        format!("item {i}"),
+                        "a row",
+                        format!(
+                            "its fields differ from the columns [{}]",
+                            expected.join(", ")
+                        )
+                    )
+                ),
+            }
+            rows.push(
+                fields
+                    .into_iter()
+                    .map(|(_, v)| DataValue::from(v))
+                    .collect(),
+            );
+        }
+        Ok(Self::new(headers.unwrap_or_default(), rows))
     }
 
     /// Create a query and parameters to apply an operation (insert, put, delete, rm) to a stored
@@ -242,8 +443,11 @@ impl NamedRows {
     }
 }
 
-const STATUS_STR: &str = "status";
-const OK_STR: &str = "OK";
+pub(crate) const STATUS_STR: &str = "status";
+pub(crate) const OK_STR: &str = "OK";
+
+/// What `::config reload` runs, see [Db::set_config_reloader]
+pub type ConfigReloader = Arc<dyn Fn() -> Result<NamedRows> + Send + Sync>;
 
 /// The query and parameters.
 pub type Payload = (String, BTreeMap<String, DataValue>);
@@ -257,6 +461,13 @@ pub enum TransactionPayload {
     Abort,
     /// Run a query inside the transaction
     Query(Payload),
+    /// Take a savepoint with the name. A name already taken refers to the new savepoint.
+    Savepoint(String),
+    /// Undo what was done after the savepoint with the name was taken. The savepoint is kept,
+    /// the ones taken after it are released.
+    RollbackToSavepoint(String),
+    /// Forget the savepoint with the name and the ones taken after it, keeping what was done
+    ReleaseSavepoint(String),
 }
 
 impl<'s, S: Storage<'s>> Db<S> {
@@ -264,6 +475,7 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// You must call [`initialize`](Self::initialize) immediately after creation.
     /// Due to lifetime restrictions we are not able to call that for you automatically.
     pub fn new(storage: S) -> Result<Self> {
+        let lifecycle = Arc::new(Lifecycle::new(storage.storage_kind()));
         let ret = Self {
             db: storage,
             temp_db: Default::default(),
@@ -272,19 +484,71 @@ impl<'s, S: Storage<'s>> Db<S> {
             running_queries: Default::default(),
             fixed_rules: Arc::new(ShardedLock::new(DEFAULT_FIXED_RULES.clone())),
             tokenizers: Arc::new(Default::default()),
+            rerankers: Default::default(),
+            keys: Default::default(),
             #[cfg(not(target_arch = "wasm32"))]
             callback_count: Default::default(),
             // callback_receiver: Arc::new(receiver),
             #[cfg(not(target_arch = "wasm32"))]
             event_callbacks: Default::default(),
             relation_locks: Default::default(),
+            audit: Default::default(),
+            lifecycle,
+            result_limits: Default::default(),
+            fixed_now: Default::default(),
+            fixpoint_cache: Default::default(),
+            pinned: Default::default(),
+            profile: Default::default(),
+            replay: Default::default(),
+            storage_stats: Default::default(),
+            deterministic: Default::default(),
+            config_reloader: Default::default(),
+            usage: Default::default(),
+            pending_fixed_rule_outputs: Default::default(),
+            jobs: Default::default(),
+            degraded: Default::default(),
+            branches: Default::default(),
+            job: None,
+            row_sink: None,
+            unmask: false,
+            session: None,
         };
         Ok(ret)
     }
 
+    /// Count the work done by the storage engine for each script run from now on, and return
+    /// it with the results as [NamedRows::storage_stats]: keys read, seeks, and for RocksDB,
+    /// blocks found in the block cache or read from disk and bloom filter hits.
+    ///
+    /// Only RocksDB counts for now, and counting slows reads down a little.
+    pub fn set_storage_stats(&self, enabled: bool) {
+        self.storage_stats.store(enabled, Ordering::Release);
+    }
+
+    /// Make the results of scripts run from now on independent of the order the engine
+    /// happens to find tuples in, so that they can be compared across runs, query plans
+    /// and versions, e.g. against golden files.
+    ///
+    /// Aggregations such as `collect` and `choice` then see the tuples of each group in
+    /// sorted order, and `:limit` and `:offset` without `:order` apply to the sorted result
+    /// instead of stopping evaluation at the first tuples found, which costs evaluating
+    /// the query fully. Random functions such as `rand_float` stay random.
+    /// A single query can set this with the option `:deterministic`.
+    pub fn set_deterministic(&self, enabled: bool) {
+        self.deterministic.store(enabled, Ordering::Release);
+    }
+
+    /// Start counting the work of the storage engine for `tx`, if set by [Db::set_storage_stats]
+    pub(crate) fn collect_storage_stats(&self, tx: &mut SessionTx<'_>) {
+        if self.storage_stats.load(Ordering::Acquire) {
+            tx.store_tx.collect_stats();
+        }
+    }
+
     /// Must be called after creation of the database to initialize the runtime state.
     pub fn initialize(&'s self) -> Result<()> {
         self.load_last_ids()?;
+        self.lifecycle.mark_clean();
         Ok(())
     }
 
@@ -293,6 +557,8 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// or when a query is not successful. After a transaction ends, sending / receiving from
     /// the channels will fail.
     ///
+    /// Savepoints can be taken within the transaction and rolled back to, undoing only part of it.
+    ///
     /// Write transactions _may_ block other reads, but we guarantee that this does not happen
     /// for the RocksDB backend.
     pub fn run_multi_transaction(
@@ -307,18 +573,19 @@ impl<'s, S: Storage<'s>> Db<S> {
             self.transact()
         };
         let mut cleanups: Vec<(Vec<u8>, Vec<u8>)> = vec![];
-        let mut tx = match tx {
-            Ok(tx) => tx,
+        let (mut tx, mut savepoints) = match tx {
+            Ok(tx) => tx.with_savepoints(),
             Err(err) => {
                 let _ = results.send(Err(err));
                 return;
             }
         };
 
-        let ts = current_validity();
+        let ts = self.current_validity();
         let callback_targets = self.current_callback_targets();
         let mut callback_collector = BTreeMap::new();
         let mut write_locks = BTreeMap::new();
+        let mut audit_rows = vec![];
 
         for payload in payloads {
             match payload {
@@ -334,6 +601,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                     if !callback_collector.is_empty() {
                         self.send_callbacks(callback_collector)
                     }
+                    for row in audit_rows {
+                        self.send_audit_callbacks(row);
+                    }
 
                     break;
                 }
@@ -341,6 +611,39 @@ impl<'s, S: Storage<'s>> Db<S> {
                     let _ = results.send(Ok(NamedRows::default()));
                     break;
                 }
+                TransactionPayload::Savepoint(name) => {
+                    savepoints.create(
+                        &name,
+                        &tx,
+                        (cleanups.len(), audit_rows.len(), callback_collector.clone()),
+                    );
+                    if results.send(Ok(NamedRows::default())).is_err() {
+                        break;
+                    }
+                }
+                TransactionPayload::RollbackToSavepoint(name) => {
+                    let res = savepoints.rollback_to(&name, &mut tx).map(
+                        |(n_cleanups, n_audit_rows, callbacks)| {
+                            cleanups.truncate(n_cleanups);
+                            audit_rows.truncate(n_audit_rows);
+                            callback_collector = callbacks;
+                            NamedRows::default()
+                        },
+                    );
+                    if results.send(res).is_err() {
+                        break;
+                    }
+                }
+                TransactionPayload::ReleaseSavepoint(name) => {
+                    let res = savepoints.release(&name).map(|_| NamedRows::default());
+                    if results.send(res).is_err() {
+                        break;
+                    }
+                }
+                TransactionPayload::Query(_) if self.is_closed() => {
+                    let _ = results.send(Err(DbClosed.into()));
+                    break;
+                }
                 TransactionPayload::Query((script, params)) => {
                     let p =
                         match parse_script(&script, &params, &self.fixed_rules.read().unwrap(), ts)
@@ -378,6 +681,21 @@ impl<'s, S: Storage<'s>> Db<S> {
                         }
                     }
 
+                    let audit_kind = if is_write && self.audit_enabled() {
+                        program_audit_kind(&p)
+                    } else {
+                        None
+                    };
+                    if audit_kind.is_some() {
+                        let audit_name = SmartString::from(AUDIT_RELATION);
+                        if let Entry::Vacant(e) = write_locks.entry(audit_name) {
+                            let lock = self
+                                .obtain_relation_locks(iter::once(e.key()))
+                                .pop()
+                                .unwrap();
+                            e.insert(lock);
+                        }
+                    }
                     let res = self.execute_single_program(
                         p,
                         &mut tx,
@@ -386,6 +704,34 @@ impl<'s, S: Storage<'s>> Db<S> {
                         &callback_targets,
                         &mut callback_collector,
                     );
+                    // recorded within the transaction, so the entry is kept only on commit
+                    if let Some(kind) = audit_kind {
+                        let entry = AuditEntry {
+                            actor: None,
+                            kind,
+                            statement: &script,
+                            params: &params,
+                            error: res.as_ref().err().map(|err| err.to_string()),
+                        };
+                        let audited = self
+                            .make_audit_row(&entry)
+                            .and_then(|row| tx.append_audit(row.clone()).map(|_| row));
+                        match audited {
+                            Ok(row) => audit_rows.push(row),
+                            Err(err) if res.is_err() => {
+                                log::error!(
+                                    "cannot record a failed statement in the audit log: {err:?}"
+                                );
+                            }
+                            Err(err) => {
+                                if results.send(Err(err)).is_err() {
+                                    break;
+                                } else {
+                                    continue;
+                                }
+                            }
+                        }
+                    }
                     if results.send(res).is_err() {
                         break;
                     }
@@ -401,12 +747,34 @@ impl<'s, S: Storage<'s>> Db<S> {
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
     ) -> Result<NamedRows> {
-        let cur_vld = current_validity();
-        self.do_run_script(
+        let cur_vld = self.current_validity();
+        self.run_script_recorded(
+            payload,
+            &params,
+            cur_vld,
+            mutability == ScriptMutability::Immutable,
+            None,
+        )
+    }
+
+    /// Run the CozoScript passed in on behalf of `actor`.
+    ///
+    /// This is the same as [`run_script`](Self::run_script), except that `actor` is recorded
+    /// in the audit log as the originator of the script.
+    pub fn run_script_as(
+        &'s self,
+        actor: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        let cur_vld = self.current_validity();
+        self.run_script_recorded(
             payload,
             &params,
             cur_vld,
             mutability == ScriptMutability::Immutable,
+            Some(actor),
         )
     }
 
@@ -416,8 +784,49 @@ impl<'s, S: Storage<'s>> Db<S> {
         payload: &str,
         params: BTreeMap<String, DataValue>,
     ) -> Result<NamedRows> {
-        let cur_vld = current_validity();
-        self.do_run_script(payload, &params, cur_vld, true)
+        let cur_vld = self.current_validity();
+        self.run_script_recorded(payload, &params, cur_vld, true, None)
+    }
+
+    /// Run the CozoScript passed in, after checking that it cannot change the database
+    /// and that its result depends only on the stored data and on `params`.
+    ///
+    /// Scripts that mutate stored relations (temp relations are allowed), run system ops,
+    /// call nondeterministic functions such as `rand_float` or `now`, or use fixed rules
+    /// reading external data or relying on randomness, are rejected before anything is run.
+    /// This makes it suitable for running untrusted queries against production data.
+    pub fn run_readonly(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let cur_vld = self.current_validity();
+        let script = parse_script(
+            payload,
+            &params,
+            &self.fixed_rules.read().unwrap(),
+            cur_vld,
+        )?;
+        script.check_sandboxed()?;
+        self.run_script_recorded(payload, &params, cur_vld, true, None)
+    }
+
+    /// Whether the CozoScript passed in contains destructive operations, such as `::remove`,
+    /// `::compact` or `:replace`, that should be reserved for administrators.
+    ///
+    /// The script is parsed but not run.
+    pub fn script_requires_admin(
+        &'s self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+    ) -> Result<bool> {
+        let script = parse_script(
+            payload,
+            params,
+            &self.fixed_rules.read().unwrap(),
+            self.current_validity(),
+        )?;
+        Ok(script.requires_admin())
     }
 
     /// Export relations to JSON data.
@@ -431,43 +840,32 @@ impl<'s, S: Storage<'s>> Db<S> {
         let tx = self.transact()?;
         let mut ret: BTreeMap<String, NamedRows> = BTreeMap::new();
         for rel in relations {
-            let handle = tx.get_relation(rel.as_ref(), false)?;
-            let size_hint = handle.metadata.keys.len() + handle.metadata.non_keys.len();
-
-            if handle.access_level < AccessLevel::ReadOnly {
-                bail!(InsufficientAccessLevel(
-                    handle.name.to_string(),
-                    "data export".to_string(),
-                    handle.access_level
-                ));
-            }
-
-            let mut cols = handle
-                .metadata
-                .keys
-                .iter()
-                .map(|col| col.name.clone())
-                .collect_vec();
-            cols.extend(
-                handle
-                    .metadata
-                    .non_keys
-                    .iter()
-                    .map(|col| col.name.clone())
-                    .collect_vec(),
-            );
-
-            let start = Tuple::default().encode_as_key(handle.id);
-            let end = Tuple::default().encode_as_key(handle.id.next());
-
-            let mut rows = vec![];
-            for data in tx.store_tx.range_scan(&start, &end) {
-                let (k, v) = data?;
-                let tuple = decode_tuple_from_kv(&k, &v, Some(size_hint));
-                rows.push(tuple);
-            }
-            let headers = cols.iter().map(|col| col.to_string()).collect_vec();
-            ret.insert(rel.as_ref().to_string(), NamedRows::new(headers, rows));
+            let (_, rows) = tx.export_relation(rel.as_ref())?;
+            ret.insert(rel.as_ref().to_string(), rows);
+        }
+        Ok(ret)
+    }
+    /// Export relations as Arrow IPC streams or Parquet files, all read in one transaction.
+    ///
+    /// The columns keep the types declared for them: vectors become fixed size lists,
+    /// UUIDs and JSON use the canonical extension types, validities become structs
+    /// of a timestamp and a boolean. The Cozo type of each column is kept in the
+    /// `cozo:type` metadata of its field.
+    #[cfg(feature = "arrow")]
+    pub fn export_relations_arrow<I, T>(
+        &'s self,
+        relations: I,
+        format: ArrowFormat,
+    ) -> Result<BTreeMap<String, Vec<u8>>>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        let tx = self.transact()?;
+        let mut ret = BTreeMap::new();
+        for rel in relations {
+            let data = tx.export_relation_arrow(rel.as_ref(), format)?;
+            ret.insert(rel.as_ref().to_string(), data);
         }
         Ok(ret)
     }
@@ -478,7 +876,83 @@ impl<'s, S: Storage<'s>> Db<S> {
     ///
     /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
     /// If you need to activate triggers or callbacks, use queries with parameters.
+    /// Materialized views reading the relations catch up with `::refresh materialized`.
     pub fn import_relations(&'s self, data: BTreeMap<String, NamedRows>) -> Result<()> {
+        self.audited_import_relations(data, None).map(|_| ())
+    }
+
+    /// Apply a mutation to a stored relation with the rows given, whose headers name the
+    /// columns. The name of the relation and the rows are passed to the script run as
+    /// parameters, so they need no escaping. Unlike [Self::import_relations], triggers run.
+    pub fn mutate_relation(
+        &'s self,
+        relation: &str,
+        mutation: Mutation,
+        rows: NamedRows,
+    ) -> Result<NamedRows> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("'{0}' is not a valid column name")]
+        #[diagnostic(code(eval::bad_column_name))]
+        struct BadColumnName(String);
+
+        for col in &rows.headers {
+            ensure!(matches_rule(col, Rule::ident), BadColumnName(col.clone()));
+        }
+        let cols = rows.headers.join(", ");
+        let script = format!(
+            "?[{cols}] <- $data {} $relation {{{cols}}}",
+            mutation.as_str()
+        );
+        let data = DataValue::List(rows.rows.into_iter().map(DataValue::List).collect());
+        let params = BTreeMap::from([
+            ("data".to_string(), data),
+            ("relation".to_string(), DataValue::from(relation)),
+        ]);
+        self.run_script(&script, params, ScriptMutability::Mutable)
+    }
+
+    /// Import relations as with [Self::import_relations], as a batch identified by
+    /// `idempotency_key`. The key is recorded in the same transaction as the data,
+    /// so that retrying a batch whose outcome is unknown never imports it twice.
+    ///
+    /// Returns `false` if a batch with the same key has already been imported,
+    /// in which case nothing is written. Keys are kept indefinitely.
+    pub fn ingest_relations(
+        &'s self,
+        idempotency_key: &str,
+        data: BTreeMap<String, NamedRows>,
+    ) -> Result<bool> {
+        self.audited_import_relations(data, Some(idempotency_key))
+    }
+
+    fn audited_import_relations(
+        &'s self,
+        data: BTreeMap<String, NamedRows>,
+        idempotency_key: Option<&str>,
+    ) -> Result<bool> {
+        if !self.audit_enabled() {
+            return self.do_import_relations(data, idempotency_key);
+        }
+        let statement = match idempotency_key {
+            None => format!("import_relations: {}", data.keys().join(", ")),
+            Some(key) => format!("ingest_relations {key:?}: {}", data.keys().join(", ")),
+        };
+        let res = self.do_import_relations(data, idempotency_key);
+        let entry = AuditEntry {
+            actor: None,
+            kind: "mutation",
+            statement: &statement,
+            params: &Default::default(),
+            error: res.as_ref().err().map(|err| err.to_string()),
+        };
+        self.record_audit_outcome(entry, res)
+    }
+
+    fn do_import_relations(
+        &'s self,
+        data: BTreeMap<String, NamedRows>,
+        idempotency_key: Option<&str>,
+    ) -> Result<bool> {
         #[derive(Debug, Diagnostic, Error)]
         #[error("cannot import data for relation '{0}': {1}")]
         #[diagnostic(code(import::bad_data))]
@@ -488,10 +962,17 @@ impl<'s, S: Storage<'s>> Db<S> {
         let locks = self.obtain_relation_locks(rel_names.iter());
         let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
 
-        let cur_vld = current_validity();
+        let cur_vld = self.current_validity();
 
         let mut tx = self.transact_write()?;
 
+        if let Some(key) = idempotency_key {
+            if tx.is_ingested(key)? {
+                return Ok(false);
+            }
+            tx.record_ingested(key)?;
+        }
+
         for (relation_op, in_data) in data {
             let is_delete;
             let relation: &str = match relation_op.strip_prefix('-') {
@@ -509,6 +990,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
             let handle = tx.get_relation(relation, false)?;
             let has_indices = !handle.indices.is_empty();
+            tx.record_relation_write(relation, !is_delete && handle.metadata.non_keys.is_empty());
 
             if handle.access_level < AccessLevel::Protected {
                 bail!(InsufficientAccessLevel(
@@ -576,6 +1058,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                     if let Some(existing) = tx.store_tx.get(&k_store, false)? {
                         let mut old = keys.clone();
                         extend_tuple_from_v(&mut old, &existing);
+                        let old = handle.decrypt_tuple(old)?;
                         if is_delete || old != row {
                             for (idx_rel, extractor) in handle.indices.values() {
                                 let idx_tup =
@@ -615,7 +1098,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         }
         tx.commit_tx()?;
-        Ok(())
+        Ok(true)
     }
     /// Backup the running database into an Sqlite file
     #[allow(unused_variables)]
@@ -640,7 +1123,7 @@ impl<'s, S: Storage<'s>> Db<S> {
     pub fn restore_backup(&'s self, in_file: impl AsRef<Path>) -> Result<()> {
         #[cfg(feature = "storage-sqlite")]
         {
-            let sqlite_db = crate::new_cozo_sqlite(in_file)?;
+            let sqlite_db = crate::storage::sqlite::open_cozo_sqlite(in_file, true)?;
             let mut s_tx = sqlite_db.transact()?;
             {
                 let mut tx = self.transact()?;
@@ -684,7 +1167,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             let locks = self.obtain_relation_locks(rel_names.iter());
             let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
 
-            let source_db = crate::new_cozo_sqlite(in_file)?;
+            let source_db = crate::storage::sqlite::open_cozo_sqlite(in_file, true)?;
             let mut src_tx = source_db.transact()?;
             let mut dst_tx = self.transact_write()?;
 
@@ -694,6 +1177,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 }
                 let src_handle = src_tx.get_relation(relation, false)?;
                 let dst_handle = dst_tx.get_relation(relation, false)?;
+                dst_tx.record_relation_write(relation, false);
 
                 if !dst_handle.indices.is_empty() {
                     #[derive(Debug, Error, Diagnostic)]
@@ -761,6 +1245,64 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(self.fixed_rules.write().unwrap().remove(name).is_some())
     }
 
+    /// Register a reranker, used by vector and full-text searches given its name
+    /// as their `rerank` parameter.
+    pub fn register_reranker<R>(&self, name: String, reranker: R) -> Result<()>
+    where
+        R: Reranker + 'static,
+    {
+        match self.rerankers.write().unwrap().entry(name) {
+            Entry::Vacant(ent) => {
+                ent.insert(Arc::new(reranker));
+                Ok(())
+            }
+            Entry::Occupied(ent) => {
+                bail!(
+                    "A reranker with the name {} is already registered",
+                    ent.key()
+                )
+            }
+        }
+    }
+
+    /// Unregister a reranker.
+    pub fn unregister_reranker(&self, name: &str) -> Result<bool> {
+        Ok(self.rerankers.write().unwrap().remove(name).is_some())
+    }
+
+    /// A handle to the same database for sessions with the `unmask` privilege, or without it.
+    ///
+    /// Columns masked with `::mask` are shown masked to the queries of sessions without
+    /// the privilege, which is the default.
+    pub fn with_unmask(&self, unmask: bool) -> Self {
+        Self {
+            unmask,
+            ..self.clone()
+        }
+    }
+
+    /// A handle to the same database whose queries are logged as run in the session `id`,
+    /// for the records of a client to be told apart from those of others.
+    pub fn with_session(&self, id: &str) -> Self {
+        Self {
+            session: Some(Arc::from(id)),
+            ..self.clone()
+        }
+    }
+
+    /// Set the provider of the keys of encrypted columns, or remove it with `None`.
+    /// Keys already fetched are forgotten.
+    pub fn set_key_provider(&self, provider: Option<Arc<dyn KeyProvider>>) {
+        self.keys.set_provider(provider)
+    }
+
+    /// Set what `::config reload` runs, usually a function of the program embedding the
+    /// database that reads its configuration again and applies it. The rows it returns
+    /// are the result of `::config reload`. Remove it with `None`.
+    pub fn set_config_reloader(&self, reloader: Option<ConfigReloader>) {
+        *self.config_reloader.lock().unwrap() = reloader;
+    }
+
     /// Register callback channel to receive changes when the requested relation are successfully committed.
     /// The returned ID can be used to unregister the callback channel.
     #[cfg(not(target_arch = "wasm32"))]
@@ -806,6 +1348,81 @@ impl<'s, S: Storage<'s>> Db<S> {
         ret.is_some()
     }
 
+    /// Prune the history of every stored relation that has a retention policy set.
+    /// Returns one row per relation with the number of versions scanned and pruned.
+    ///
+    /// This is what the background retention job runs periodically, but it can also be
+    /// called directly, e.g. from a scheduler of your own.
+    pub fn prune_history(&'s self) -> Result<NamedRows> {
+        let handles = {
+            let tx = self.transact()?;
+            self.relations_with_retention(&tx)?
+        };
+        let rel_names = handles
+            .iter()
+            .flat_map(|h| {
+                let archive = h.retention.as_ref().and_then(|p| p.expire_to.clone());
+                iter::once(h.name.clone()).chain(archive)
+            })
+            .collect_vec();
+        let locks = self.obtain_relation_locks(rel_names.iter());
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+
+        let now = self.current_validity();
+        let callback_targets = self.current_callback_targets();
+        let mut callback_collector = BTreeMap::new();
+        let mut tx = self.transact_write()?;
+        let mut rows = vec![];
+        for handle in handles {
+            // re-read under the lock, the relation may have changed in between
+            let handle = tx.get_relation(&handle.name, false)?;
+            let policy = match &handle.retention {
+                None => continue,
+                Some(p) => p.clone(),
+            };
+            let mut stats = tx.prune_history(&handle, &policy, now)?;
+            if callback_targets.contains(&handle.name) {
+                stats.notify_expired(&handle, &mut callback_collector);
+            }
+            rows.push(vec![
+                DataValue::from(&handle.name as &str),
+                DataValue::from(stats.scanned as i64),
+                DataValue::from(stats.pruned as i64),
+            ]);
+        }
+        tx.commit_tx()?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if !callback_collector.is_empty() {
+            self.send_callbacks(callback_collector)
+        }
+        Ok(NamedRows::new(
+            vec![
+                "relation".to_string(),
+                "scanned".to_string(),
+                "pruned".to_string(),
+            ],
+            rows,
+        ))
+    }
+
+    fn relations_with_retention(&'s self, tx: &SessionTx<'_>) -> Result<Vec<RelationHandle>> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut ret = vec![];
+        for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            let meta = RelationHandle::decode(&v_slice)?;
+            if meta.retention.is_some() {
+                ret.push(meta);
+            }
+        }
+        Ok(ret)
+    }
+
     pub(crate) fn obtain_relation_locks<'a, T: Iterator<Item = &'a SmartString<LazyCompact>>>(
         &'s self,
         rels: T,
@@ -841,29 +1458,87 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
 
     fn load_last_ids(&'s self) -> Result<()> {
-        let mut tx = self.transact_write()?;
+        // read-only databases are left as they are, another process may be writing them
+        let read_only = self.lifecycle.is_read_only();
+        let mut tx = if read_only {
+            self.transact()?
+        } else {
+            self.transact_write()?
+        };
         self.relation_store_id
-            .store(tx.init_storage()?.0, Ordering::Release);
-        tx.commit_tx()?;
+            .store(tx.init_storage(read_only)?.0, Ordering::Release);
+        self.seed_audit_seq(tx.next_audit_seq()?);
+        if !read_only {
+            tx.commit_tx()?;
+        }
         Ok(())
     }
     pub(crate) fn transact(&'s self) -> Result<SessionTx<'_>> {
+        if self.is_closed() {
+            bail!(DbClosed)
+        }
+        let (fixpoint_epoch, store_tx) =
+            self.fixpoint_cache.snapshot(|| self.db.transact(false))?;
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(false)?),
+            store_tx: Box::new(store_tx),
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            rerankers: self.rerankers.clone(),
+            keys: self.keys.clone(),
+            unmask: self.unmask,
+            deterministic: self.deterministic.load(Ordering::Acquire),
+            fixpoint_cache: self.fixpoint_cache.clone(),
+            fixpoint_epoch,
+            relation_writes: Default::default(),
+            writes_started: 0,
+            last_writes: Default::default(),
+            tx_time: current_validity(),
+            pinned: self.pinned.clone(),
+            pinned_views: Default::default(),
+            pending_fixed_rule_outputs: self.pending_fixed_rule_outputs.clone(),
+            trigger_programs: Default::default(),
+            usage: self.usage.clone(),
+            relations_read: Default::default(),
+            degraded: self.degraded.clone(),
+            warnings: vec![],
         };
         Ok(ret)
     }
     pub(crate) fn transact_write(&'s self) -> Result<SessionTx<'_>> {
+        if self.is_closed() {
+            bail!(DbClosed)
+        }
+        if self.lifecycle.is_read_only() {
+            bail!(DbReadOnly)
+        }
+        self.lifecycle.mark_dirty();
+        let (fixpoint_epoch, store_tx) = self.fixpoint_cache.snapshot(|| self.db.transact(true))?;
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(true)?),
+            store_tx: Box::new(store_tx),
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            rerankers: self.rerankers.clone(),
+            keys: self.keys.clone(),
+            unmask: self.unmask,
+            deterministic: self.deterministic.load(Ordering::Acquire),
+            fixpoint_cache: self.fixpoint_cache.clone(),
+            fixpoint_epoch,
+            relation_writes: Default::default(),
+            writes_started: 0,
+            last_writes: Default::default(),
+            tx_time: current_validity(),
+            pinned: self.pinned.clone(),
+            pinned_views: Default::default(),
+            pending_fixed_rule_outputs: self.pending_fixed_rule_outputs.clone(),
+            trigger_programs: Default::default(),
+            usage: self.usage.clone(),
+            relations_read: Default::default(),
+            degraded: self.degraded.clone(),
+            warnings: vec![],
         };
         Ok(ret)
     }
@@ -879,8 +1554,26 @@ impl<'s, S: Storage<'s>> Db<S> {
     ) -> Result<NamedRows> {
         #[allow(unused_variables)]
         let sleep_opt = p.out_opts.sleep;
-        let (q_res, q_cleanups) =
-            self.run_query(tx, p, cur_vld, callback_targets, callback_collector, true)?;
+        let mut p = p;
+        let (mut q_res, q_cleanups) = loop {
+            // an index found inconsistent is read around when the query is run again,
+            // unless the query has started writing, as its writes stay in the transaction
+            let retry = self.degraded.is_enabled().then(|| p.clone());
+            let writes_started = tx.writes_started;
+            match self.run_query(tx, p, cur_vld, callback_targets, callback_collector, true) {
+                Ok(res) => break res,
+                Err(err) => match (retry, inconsistent_index(&err)) {
+                    (Some(prog), Some(found))
+                        if self.degrade_index(&found.0, &found.1)
+                            && tx.writes_started == writes_started =>
+                    {
+                        p = prog
+                    }
+                    _ => return Err(err),
+                },
+            }
+        };
+        q_res.warnings.append(&mut tx.warnings);
         cleanups.extend(q_cleanups);
         #[cfg(not(target_arch = "wasm32"))]
         if let Some(secs) = sleep_opt {
@@ -889,28 +1582,62 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(q_res)
     }
 
-    fn do_run_script(
+    pub(crate) fn do_run_script(
         &'s self,
         payload: &str,
         param_pool: &BTreeMap<String, DataValue>,
         cur_vld: ValidityTs,
         read_only: bool,
+        actor: Option<&str>,
     ) -> Result<NamedRows> {
-        match parse_script(
+        let script = parse_script(
             payload,
             param_pool,
             &self.fixed_rules.read().unwrap(),
             cur_vld,
-        )? {
-            CozoScript::Single(p) => self.execute_single(cur_vld, p, read_only),
-            CozoScript::Imperative(ps) => self.execute_imperative(cur_vld, &ps, read_only),
-            CozoScript::Sys(op) => self.run_sys_op(op, read_only),
-        }
+        )?;
+        self.run_parsed_script(script, payload, param_pool, cur_vld, read_only, actor)
     }
 
-    fn execute_single(
+    pub(crate) fn run_parsed_script(
         &'s self,
-        cur_vld: ValidityTs,
+        script: CozoScript,
+        payload: &str,
+        param_pool: &BTreeMap<String, DataValue>,
+        cur_vld: ValidityTs,
+        read_only: bool,
+        actor: Option<&str>,
+    ) -> Result<NamedRows> {
+        let audit_kind = if self.audit_enabled() {
+            audit_kind(&script)
+        } else {
+            None
+        };
+        let res = match script {
+            CozoScript::Single(p) => self.execute_single(cur_vld, p, read_only),
+            CozoScript::Imperative(ps) => self.execute_imperative(cur_vld, &ps, read_only),
+            CozoScript::Sys(SysOp::Test(stmts)) => self.run_tests(cur_vld, &stmts, read_only),
+            CozoScript::Sys(op) => self.run_sys_op(op, read_only),
+        };
+        self.flush_fixed_rule_cache();
+        match audit_kind {
+            None => res,
+            Some(kind) => {
+                let entry = AuditEntry {
+                    actor,
+                    kind,
+                    statement: payload,
+                    params: param_pool,
+                    error: res.as_ref().err().map(|err| err.to_string()),
+                };
+                self.record_audit_outcome(entry, res)
+            }
+        }
+    }
+
+    fn execute_single(
+        &'s self,
+        cur_vld: ValidityTs,
         p: InputProgram,
         read_only: bool,
     ) -> Result<NamedRows, Report> {
@@ -932,13 +1659,17 @@ impl<'s, S: Storage<'s>> Db<S> {
             Default::default()
         };
         let mut cleanups = vec![];
-        let res;
+        let mut res;
         {
             let mut tx = if is_write {
                 self.transact_write()?
             } else {
                 self.transact()?
             };
+            self.collect_storage_stats(&mut tx);
+            if read_only {
+                tx.discard_fixed_rule_outputs();
+            }
 
             res = self.execute_single_program(
                 p,
@@ -952,6 +1683,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             for (lower, upper) in cleanups {
                 tx.store_tx.del_range_from_persisted(&lower, &upper)?;
             }
+            res.storage_stats = tx.store_tx.storage_stats();
 
             tx.commit_tx()?;
         }
@@ -962,7 +1694,12 @@ impl<'s, S: Storage<'s>> Db<S> {
 
         Ok(res)
     }
-    fn explain_compiled(&self, strata: &[CompiledProgram]) -> Result<NamedRows> {
+    fn explain_compiled(
+        &self,
+        strata: &[CompiledProgram],
+        exemptions: &BTreeMap<Symbol, MagicExemption>,
+        recursive: &BTreeSet<Symbol>,
+    ) -> Result<NamedRows> {
         let mut ret: Vec<JsonValue> = vec![];
         const STRATUM: &str = "stratum";
         const ATOM_IDX: &str = "atom_idx";
@@ -973,6 +1710,8 @@ impl<'s, S: Storage<'s>> Db<S> {
         const OUT_BINDINGS: &str = "out_relation";
         const JOINS_ON: &str = "joins_on";
         const FILTERS: &str = "filters/expr";
+        const MAGIC: &str = "magic";
+        const HINTS: &str = "hints";
 
         let headers = vec![
             STRATUM.to_string(),
@@ -984,6 +1723,8 @@ impl<'s, S: Storage<'s>> Db<S> {
             JOINS_ON.to_string(),
             FILTERS.to_string(),
             OUT_BINDINGS.to_string(),
+            MAGIC.to_string(),
+            HINTS.to_string(),
         ];
 
         for (stratum, p) in strata.iter().enumerate() {
@@ -991,7 +1732,13 @@ impl<'s, S: Storage<'s>> Db<S> {
             for (rule_name, v) in p {
                 match v {
                     CompiledRuleSet::Rules(rules) => {
-                        for CompiledRule { aggr, relation, .. } in rules.iter() {
+                        for CompiledRule {
+                            aggr,
+                            relation,
+                            hints,
+                            ..
+                        } in rules.iter()
+                        {
                             clause_idx += 1;
                             let mut ret_for_relation = vec![];
                             let mut rel_stack = vec![relation];
@@ -1013,7 +1760,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                                 OP: atom_type,
                                 RULE_IDX: clause_idx,
                                 RULE_NAME: rule_name.to_string(),
-                                OUT_BINDINGS: relation.bindings_after_eliminate().into_iter().map(|v| v.to_string()).collect_vec()
+                                OUT_BINDINGS: relation.bindings_after_eliminate().into_iter().map(|v| v.to_string()).collect_vec(),
+                                MAGIC: explain_magic(rule_name, exemptions, recursive),
+                                HINTS: if hints.is_empty() { json!(null) } else { json!(hints) },
                             }));
                             idx += 1;
 
@@ -1163,12 +1912,13 @@ impl<'s, S: Storage<'s>> Db<S> {
                             ret.extend(ret_for_relation)
                         }
                     }
-                    CompiledRuleSet::Fixed(_) => ret.push(json!({
+                    CompiledRuleSet::Fixed(fixed) => ret.push(json!({
                         STRATUM: stratum,
                         ATOM_IDX: 0,
                         OP: "algo",
                         RULE_IDX: 0,
                         RULE_NAME: rule_name.to_string(),
+                        OUT_BINDINGS: fixed.head.iter().map(|s| s.to_string()).collect_vec(),
                     })),
                 }
             }
@@ -1192,14 +1942,17 @@ impl<'s, S: Storage<'s>> Db<S> {
         op: &SysOp,
         read_only: bool,
         skip_locking: bool,
+        callback_collector: &mut CallbackCollector,
     ) -> Result<NamedRows> {
         match op {
             SysOp::Explain(prog) => {
                 let (normalized_program, _) = prog.clone().into_normalized_program(tx)?;
                 let (stratified_program, _) = normalized_program.into_stratified_program()?;
+                let exemptions = stratified_program.magic_exemptions();
+                let recursive = stratified_program.recursive_rules();
                 let program = stratified_program.magic_sets_rewrite(tx)?;
                 let compiled = tx.stratified_magic_compile(program)?;
-                self.explain_compiled(&compiled)
+                self.explain_compiled(&compiled, &exemptions, &recursive)
             }
             SysOp::Compact => {
                 if read_only {
@@ -1211,7 +1964,100 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::Scrub => {
+                if read_only {
+                    bail!("Cannot scrub in read-only mode");
+                }
+                let name = SmartString::from(SCRUB_RELATION);
+                let locks = if skip_locking {
+                    vec![]
+                } else {
+                    self.obtain_relation_locks(iter::once(&name))
+                };
+                let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+                self.scrub_with_tx(tx)
+            }
+            SysOp::ListConfig => {
+                let rows = self
+                    .db
+                    .config()?
+                    .into_iter()
+                    .map(|(name, value)| vec![DataValue::from(name), value])
+                    .collect_vec();
+                Ok(NamedRows::new(
+                    vec!["setting".to_string(), "value".to_string()],
+                    rows,
+                ))
+            }
+            SysOp::SetConfig(name, value) => {
+                self.db.set_config(name, value)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ReloadConfig => {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("There is no configuration to reload")]
+                #[diagnostic(code(eval::no_config_reloader))]
+                #[diagnostic(help("The program embedding the database must set a reloader"))]
+                struct NoConfigReloader;
+
+                // called without holding the lock, as the reloader may replace itself
+                let reloader = self.config_reloader.lock().unwrap().clone();
+                match reloader {
+                    None => bail!(NoConfigReloader),
+                    Some(reloader) => reloader(),
+                }
+            }
+            SysOp::LogLevel(level) => {
+                if let Some(level) = level {
+                    log::set_max_level(level);
+                }
+                Ok(NamedRows::new(
+                    vec!["level".to_string()],
+                    vec![vec![DataValue::from(log::max_level().as_str())]],
+                ))
+            }
+            SysOp::ListPartitions => self.list_partitions(tx),
+            SysOp::SetPartitions(rel_name, scheme) => {
+                if read_only {
+                    bail!("Cannot partition relations in read-only mode");
+                }
+                // rows move between partitions outside of the transaction, with writers locked out
+                if skip_locking {
+                    self.set_partitions(tx, rel_name, scheme.as_ref())?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&rel_name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    self.set_partitions(tx, rel_name, scheme.as_ref())?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListPinned => self.list_pinned(),
+            SysOp::PinRelation(rel_name, max_bytes) => {
+                self.pin_relation(tx, rel_name, *max_bytes)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::UnpinRelation(rel_name) => {
+                self.unpin_relation(rel_name)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
             SysOp::ListRelations => self.list_relations(tx),
+            SysOp::ListUsage => tx.list_usage(),
+            SysOp::AdviseIndexes(path) => self.advise_indexes(tx, path.as_deref()),
             SysOp::ListFixedRules => {
                 let rules = self.fixed_rules.read().unwrap();
                 Ok(NamedRows::new(
@@ -1249,12 +2095,25 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::DescribeRelation(rel_name, description) => {
+                if read_only {
+                    bail!("Cannot describe relation in read-only mode");
+                }
                 tx.describe_relation(rel_name, description)?;
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::DescribeColumn(rel_name, column, description) => {
+                if read_only {
+                    bail!("Cannot describe column in read-only mode");
+                }
+                tx.describe_column(rel_name, column, description)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
             SysOp::CreateIndex(rel_name, idx_name, cols) => {
                 if read_only {
                     bail!("Cannot create index in read-only mode");
@@ -1278,6 +2137,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 if read_only {
                     bail!("Cannot create vector index in read-only mode");
                 }
+                self.profile.ensure(|p| p.hnsw, "HNSW indices are")?;
                 if skip_locking {
                     tx.create_hnsw_index(config)?;
                 } else {
@@ -1297,6 +2157,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 if read_only {
                     bail!("Cannot create fts index in read-only mode");
                 }
+                self.profile.ensure(|p| p.fts, "FTS indices are")?;
                 if skip_locking {
                     tx.create_fts_index(config)?;
                 } else {
@@ -1332,6 +2193,29 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::RebuildIndex(rel_name, idx_name) => {
+                if read_only {
+                    bail!("Cannot rebuild index in read-only mode");
+                }
+                if skip_locking {
+                    tx.rebuild_index(rel_name, idx_name)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&rel_name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.rebuild_index(rel_name, idx_name)?;
+                }
+                // a query reading the index again before the transaction commits will find
+                // it inconsistent again and read around it anew
+                self.degraded
+                    .clear(&format!("{}:{}", rel_name.name, idx_name.name));
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
             SysOp::RemoveIndex(rel_name, idx_name) => {
                 if read_only {
                     bail!("Cannot remove index in read-only mode");
@@ -1376,6 +2260,31 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::CloneRelation(base, new) => {
+                if read_only {
+                    bail!("Cannot clone relations in read-only mode");
+                }
+                let locks = if skip_locking {
+                    vec![]
+                } else {
+                    self.obtain_relation_locks([&base.name, &new.name].into_iter())
+                };
+                let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+                tx.clone_relation(base, new)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListBranches => Ok(self.list_branches()),
+            SysOp::CreateBranch(name) => self.create_branch(tx, name),
+            SysOp::MergeBranch(name) => {
+                if read_only {
+                    bail!("Cannot merge branches in read-only mode");
+                }
+                self.merge_branch(tx, name, skip_locking)
+            }
+            SysOp::DropBranch(name) => self.drop_branch(name),
             SysOp::ListRunning => self.list_running(),
             SysOp::KillRunning(id) => {
                 let queries = self.running_queries.lock().unwrap();
@@ -1385,7 +2294,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         vec![vec![DataValue::from("NOT_FOUND")]],
                     ),
                     Some(handle) => {
-                        handle.poison.0.store(true, Ordering::Relaxed);
+                        handle.poison.kill();
                         NamedRows::new(
                             vec![STATUS_STR.to_string()],
                             vec![vec![DataValue::from("KILLING")]],
@@ -1395,30 +2304,243 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
             SysOp::ShowTrigger(name) => {
                 let rel = tx.get_relation(name, false)?;
-                let mut rows: Vec<Vec<JsonValue>> = vec![];
-                for (i, trigger) in rel.put_triggers.iter().enumerate() {
-                    rows.push(vec![json!("put"), json!(i), json!(trigger)])
+                let rows = rel.all_triggers().iter().map(|t| t.as_row()).collect_vec();
+                Ok(NamedRows::new(trigger_columns(), rows))
+            }
+            SysOp::ListTriggers => self.list_triggers(tx),
+            SysOp::Diff(config) => tx.diff_with(config),
+            SysOp::SetTriggers(name, triggers) => {
+                if read_only {
+                    bail!("Cannot set triggers in read-only mode");
                 }
-                for (i, trigger) in rel.rm_triggers.iter().enumerate() {
-                    rows.push(vec![json!("rm"), json!(i), json!(trigger)])
+                tx.set_relation_triggers(name, triggers)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SetTriggerEnabled(name, trigger, enabled) => {
+                if read_only {
+                    bail!("Cannot set triggers in read-only mode");
                 }
-                for (i, trigger) in rel.replace_triggers.iter().enumerate() {
-                    rows.push(vec![json!("replace"), json!(i), json!(trigger)])
+                tx.set_trigger_enabled(name, trigger, *enabled)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SetRetention(name, policy) => {
+                if read_only {
+                    bail!("Cannot set retention policy in read-only mode");
                 }
-                let rows = rows
-                    .into_iter()
-                    .map(|row| row.into_iter().map(DataValue::from).collect_vec())
-                    .collect_vec();
+                tx.set_retention_policy(name, policy.clone())?;
                 Ok(NamedRows::new(
-                    vec!["type".to_string(), "idx".to_string(), "trigger".to_string()],
-                    rows,
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
-            SysOp::SetTriggers(name, puts, rms, replaces) => {
+            SysOp::PruneHistory(name, policy) => {
                 if read_only {
-                    bail!("Cannot set triggers in read-only mode");
+                    bail!("Cannot prune history in read-only mode");
+                }
+                let handle = tx.get_relation(name, false)?;
+                let policy = match policy.as_ref().or(handle.retention.as_ref()) {
+                    Some(p) => p.clone(),
+                    None => bail!(NoRetentionPolicy(handle.name.to_string())),
+                };
+                let locks = if skip_locking {
+                    vec![]
+                } else {
+                    let archive = policy.expire_to.clone();
+                    self.obtain_relation_locks(iter::once(&name.name).chain(archive.as_ref()))
+                };
+                let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+                let handle = tx.get_relation(name, false)?;
+                let mut stats = tx.prune_history(&handle, &policy, self.current_validity())?;
+                if self.current_callback_targets().contains(&handle.name) {
+                    stats.notify_expired(&handle, callback_collector);
+                }
+                Ok(NamedRows::new(
+                    vec![
+                        "relation".to_string(),
+                        "scanned".to_string(),
+                        "pruned".to_string(),
+                    ],
+                    vec![vec![
+                        DataValue::from(&handle.name as &str),
+                        DataValue::from(stats.scanned as i64),
+                        DataValue::from(stats.pruned as i64),
+                    ]],
+                ))
+            }
+            SysOp::ListFixedRuleCache => tx.list_fixed_rule_cache(),
+            SysOp::DropFixedRuleCache => {
+                if read_only {
+                    bail!("Cannot drop the fixed rule cache in read-only mode");
+                }
+                let dropped = tx.drop_fixed_rule_cache()?;
+                Ok(NamedRows::new(
+                    vec!["dropped".to_string()],
+                    vec![vec![DataValue::from(dropped as i64)]],
+                ))
+            }
+            SysOp::ListJobs => Ok(self.jobs.list()),
+            #[cfg(target_arch = "wasm32")]
+            SysOp::SubmitJob(..) => bail!("Cannot submit jobs when threading is disallowed"),
+            #[cfg(not(target_arch = "wasm32"))]
+            SysOp::SubmitJob(script, params, _) => {
+                self.profile
+                    .ensure(|p| p.background_jobs, "Background jobs are")?;
+                if !self.jobs.is_accepting() {
+                    bail!("Nothing runs the jobs of this database, see Db::accept_jobs");
+                }
+                // the job is run with the permissions of the script submitting it
+                let id = self
+                    .jobs
+                    .submit(script.clone(), params.clone(), read_only)?;
+                Ok(NamedRows::new(
+                    vec!["id".to_string()],
+                    vec![vec![DataValue::from(id as i64)]],
+                ))
+            }
+            SysOp::JobResult(id) => self.jobs.result(*id),
+            SysOp::KillJob(id) => Ok(NamedRows::new(
+                vec![STATUS_STR.to_string()],
+                vec![vec![DataValue::from(
+                    self.jobs.kill(*id).unwrap_or("NOT_FOUND"),
+                )]],
+            )),
+            SysOp::Test(_) => {
+                bail!("'::test' runs in a transaction of its own and cannot be nested")
+            }
+            SysOp::InferImport(config) => {
+                if config.create && read_only {
+                    bail!("Cannot create relations in read-only mode");
+                }
+                if skip_locking || !config.create {
+                    tx.import_infer(config, self.current_validity())
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&config.relation))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.import_infer(config, self.current_validity())
+                }
+            }
+            SysOp::ExportChunked(config) => tx.export_chunked(config),
+            SysOp::ExportGraph(config) => tx.export_graph(config),
+            SysOp::ImportGraph(config) => {
+                if read_only {
+                    bail!("Cannot create relations in read-only mode");
+                }
+                let locks = if skip_locking {
+                    vec![]
+                } else {
+                    self.obtain_relation_locks(
+                        iter::once(&config.relation).chain(config.nodes.iter()),
+                    )
+                };
+                let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+                tx.import_graph(config)
+            }
+            SysOp::ImportRdf(config) => {
+                if read_only {
+                    bail!("Cannot create relations in read-only mode");
+                }
+                let locks = if skip_locking {
+                    vec![]
+                } else {
+                    self.obtain_relation_locks([&config.relation, &config.terms].into_iter())
+                };
+                let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+                tx.import_rdf(config)
+            }
+            SysOp::EncryptColumns(name, columns) => {
+                if read_only {
+                    bail!("Cannot encrypt columns in read-only mode");
+                }
+                let locks = if skip_locking {
+                    vec![]
+                } else {
+                    self.obtain_relation_locks(iter::once(&name.name))
+                };
+                let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+                let rewritten = tx.set_encrypted_columns(name, columns)?;
+                Ok(NamedRows::new(
+                    vec!["relation".to_string(), "rewritten".to_string()],
+                    vec![vec![
+                        DataValue::from(&name.name as &str),
+                        DataValue::from(rewritten as i64),
+                    ]],
+                ))
+            }
+            SysOp::ListMasks(name) => tx.list_masks(name),
+            SysOp::SetMasks(name, columns) => {
+                if read_only {
+                    bail!("Cannot mask columns in read-only mode");
+                }
+                let locks = if skip_locking {
+                    vec![]
+                } else {
+                    self.obtain_relation_locks(iter::once(&name.name))
+                };
+                let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+                tx.set_masks(name, columns)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::CreateMaterialized(name, script) => {
+                if read_only {
+                    bail!("Cannot create materialized views in read-only mode");
+                }
+                let locks = if skip_locking {
+                    vec![]
+                } else {
+                    self.obtain_relation_locks(iter::once(&name.name))
+                };
+                let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+                let to_clear = tx.create_materialized_view(
+                    self,
+                    name,
+                    script,
+                    self.current_validity(),
+                    &self.current_callback_targets(),
+                    callback_collector,
+                )?;
+                for (lower, upper) in to_clear {
+                    tx.store_tx.del_range_from_persisted(&lower, &upper)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RefreshMaterialized(name) => {
+                if read_only {
+                    bail!("Cannot refresh materialized views in read-only mode");
+                }
+                let locks = if skip_locking {
+                    vec![]
+                } else {
+                    self.obtain_relation_locks(iter::once(&name.name))
+                };
+                let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+                let view = tx.get_relation(name, true)?;
+                let mut to_clear = vec![];
+                tx.refresh_view(
+                    self,
+                    &view,
+                    self.current_validity(),
+                    &self.current_callback_targets(),
+                    callback_collector,
+                    &mut to_clear,
+                )?;
+                for (lower, upper) in to_clear {
+                    tx.store_tx.del_range_from_persisted(&lower, &upper)?;
                 }
-                tx.set_relation_triggers(name, puts, rms, replaces)?;
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
@@ -1444,20 +2566,38 @@ impl<'s, S: Storage<'s>> Db<S> {
         } else {
             self.transact_write()?
         };
-        let res = self.run_sys_op_with_tx(&mut tx, &op, read_only, false)?;
+        let mut callback_collector = BTreeMap::new();
+        let res =
+            self.run_sys_op_with_tx(&mut tx, &op, read_only, false, &mut callback_collector)?;
         tx.commit_tx()?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if !callback_collector.is_empty() {
+            self.send_callbacks(callback_collector)
+        }
         Ok(res)
     }
     /// This is the entry to query evaluation
     pub(crate) fn run_query(
         &self,
         tx: &mut SessionTx<'_>,
-        input_program: InputProgram,
-        cur_vld: ValidityTs,
+        mut input_program: InputProgram,
+        mut cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
         top_level: bool,
     ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
+        // with a fixed clock, `now()` is a constant and rows written are valid from the fixed
+        // time by default, but transaction time columns are still stamped with the system
+        // clock, see `SessionTx::tx_time`, so that they cannot be backdated
+        let fixed_now = match input_program.out_opts.fixed_now {
+            None => self.fixed_now(),
+            now => now,
+        };
+        if let Some(now) = fixed_now {
+            cur_vld = now;
+            input_program.fix_now(now);
+        }
+
         // cleanups contain stored relations that should be deleted at the end of query
         let mut clean_ups = vec![];
 
@@ -1495,13 +2635,51 @@ impl<'s, S: Storage<'s>> Db<S> {
 
         // query compilation
         let entry_head_or_default = input_program.get_entry_out_head_or_default()?;
+        let column_sources = if input_program.out_opts.store_relation.is_none() {
+            entry_column_sources(tx, &input_program)
+        } else {
+            vec![]
+        };
+        let unlimited =
+            input_program.out_opts.limit.is_none() && input_program.out_opts.offset.is_none();
+        let deterministic = input_program
+            .out_opts
+            .deterministic
+            .unwrap_or(tx.deterministic);
+        // existence checks need no more than the first tuple found: a query returning no
+        // columns has no other tuple, and a query asserted to return none fails with it
+        let exists_only = unlimited
+            && !deterministic
+            && input_program.out_opts.sorters.is_empty()
+            && input_program.out_opts.store_relation.is_none()
+            && (entry_head_or_default.is_empty()
+                || matches!(
+                    input_program.out_opts.assertion,
+                    Some(QueryAssertion::AssertNone(_))
+                ));
+        let unlimited = unlimited && !exists_only;
+        // the bodies of triggers hold the rows of each mutation as constants: they never
+        // run twice the same, and keying them would format every one of these rows
+        let fixpoint_epoch = tx.fixpoint_epoch().filter(|_| unlimited && top_level);
+        let cache_key = fixpoint_epoch.map(|_| fixpoint_key(&input_program, tx.unmask));
         let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
         let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
+        // resume from the fixpoint of a previous run of the same query, if still valid
+        let resumable = match (fixpoint_epoch, cache_key) {
+            (Some(epoch), Some(key)) => stratified_program
+                .resumable_relations()
+                .map(|relations| (epoch, key, relations)),
+            _ => None,
+        };
+        let resumable = resumable.filter(|_| self.profile.get().fixpoint_cache);
+        let mut fixpoint = resumable
+            .as_ref()
+            .map(|(epoch, key, _)| self.fixpoint_cache.take(key, *epoch).unwrap_or_default());
         let program = stratified_program.magic_sets_rewrite(tx)?;
         let compiled = tx.stratified_magic_compile(program)?;
 
         // poison is used to terminate queries early
-        let poison = Poison::default();
+        let poison = self.query_poison();
         if let Some(secs) = out_opts.timeout {
             poison.set_timeout(secs)?;
         }
@@ -1522,27 +2700,58 @@ impl<'s, S: Storage<'s>> Db<S> {
             id,
             running_queries: self.running_queries.clone(),
         };
+        // logged when the query is done
+        let mut query_log = QueryLog {
+            id,
+            session: self.session.clone(),
+            started_at: since_the_epoch,
+            relations: mem::take(&mut tx.relations_read),
+            rows: None,
+        };
+        if let Some((meta, _, _)) = &out_opts.store_relation {
+            if !meta.name.is_temp_store_name() {
+                query_log.relations.insert(meta.name.name.clone());
+            }
+        }
 
-        let total_num_to_take = if out_opts.sorters.is_empty() {
+        // a deterministic result is cut from the sorted result, not from the tuples found first
+        let total_num_to_take = if deterministic {
+            None
+        } else if exists_only {
+            Some(1)
+        } else if out_opts.sorters.is_empty() {
             out_opts.num_to_take()
         } else {
             None
         };
 
-        let num_to_skip = if out_opts.sorters.is_empty() {
+        let num_to_skip = if out_opts.sorters.is_empty() && !deterministic {
             out_opts.offset
         } else {
             None
         };
 
         // the real evaluation
-        let (result_store, early_return) = tx.stratified_magic_evaluate(
+        let mut incomplete = false;
+        let outer_deterministic = mem::replace(&mut tx.deterministic, deterministic);
+        let evaluated = tx.stratified_magic_evaluate(
             &compiled,
             store_lifetimes,
             total_num_to_take,
             num_to_skip,
-            poison,
-        )?;
+            poison.clone(),
+            fixpoint.as_mut(),
+            out_opts.max_iterations,
+            &mut incomplete,
+        );
+        tx.deterministic = outer_deterministic;
+        let (result_store, early_return) = evaluated?;
+        if let (Some((epoch, key, relations)), Some(stores)) = (resumable, fixpoint) {
+            // a partial fixpoint cannot be resumed from
+            if !incomplete {
+                self.fixpoint_cache.put(key, epoch, relations, stores);
+            }
+        }
 
         // deal with assertions
         if let Some(assertion) = &out_opts.assertion {
@@ -1604,22 +2813,25 @@ impl<'s, S: Storage<'s>> Db<S> {
                     )
                     .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
                 clean_ups.extend(to_clear);
-                let returned_rows =
+                let mut returned_rows =
                     tx.get_returning_rows(callback_collector, &meta.name, returning)?;
+                returned_rows.incomplete = incomplete;
+                query_log.rows = Some(returned_rows.rows.len());
                 Ok((returned_rows, clean_ups))
             } else {
                 // not sorting outputs
-                let rows: Vec<Tuple> = sorted_iter.collect_vec();
-                Ok((
-                    NamedRows::new(
-                        entry_head_or_default
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect_vec(),
-                        rows,
-                    ),
-                    clean_ups,
-                ))
+                let headers = entry_head_or_default
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect_vec();
+                let (rows, truncated) =
+                    self.collect_result(top_level, &headers, sorted_iter, &poison)?;
+                let mut ret = NamedRows::new(headers, rows);
+                ret.truncated = truncated;
+                ret.incomplete = incomplete;
+                ret.columns = column_infos(column_sources, ret.headers.len(), &ret.rows);
+                query_log.rows = Some(ret.rows.len());
+                Ok((ret, clean_ups))
             }
         } else {
             let scan = if early_return {
@@ -1660,23 +2872,24 @@ impl<'s, S: Storage<'s>> Db<S> {
                     )
                     .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
                 clean_ups.extend(to_clear);
-                let returned_rows =
+                let mut returned_rows =
                     tx.get_returning_rows(callback_collector, &meta.name, returning)?;
-
+                returned_rows.incomplete = incomplete;
+                query_log.rows = Some(returned_rows.rows.len());
                 Ok((returned_rows, clean_ups))
             } else {
-                let rows: Vec<Tuple> = scan.collect_vec();
-
-                Ok((
-                    NamedRows::new(
-                        entry_head_or_default
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect_vec(),
-                        rows,
-                    ),
-                    clean_ups,
-                ))
+                let headers = entry_head_or_default
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect_vec();
+                let (rows, truncated) =
+                    self.collect_result(top_level, &headers, scan, &poison)?;
+                let mut ret = NamedRows::new(headers, rows);
+                ret.truncated = truncated;
+                ret.incomplete = incomplete;
+                ret.columns = column_infos(column_sources, ret.headers.len(), &ret.rows);
+                query_log.rows = Some(ret.rows.len());
+                Ok((ret, clean_ups))
             }
         }
     }
@@ -1786,6 +2999,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 json!(col.typing.to_string()),
                 json!(col.default_gen.is_some()),
                 json!(default_expr),
+                json!(handle.column_descriptions.get(&col.name)),
             ]);
             idx += 1;
         }
@@ -1799,6 +3013,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 json!(col.typing.to_string()),
                 json!(col.default_gen.is_some()),
                 json!(default_expr),
+                json!(handle.column_descriptions.get(&col.name)),
             ]);
             idx += 1;
         }
@@ -1814,10 +3029,32 @@ impl<'s, S: Storage<'s>> Db<S> {
                 "type".to_string(),
                 "has_default".to_string(),
                 "default_expr".to_string(),
+                "description".to_string(),
             ],
             rows,
         ))
     }
+    fn list_triggers(&'s self, tx: &SessionTx<'_>) -> Result<NamedRows> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut rows = vec![];
+        for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            let meta = RelationHandle::decode(&v_slice)?;
+            for trigger in meta.all_triggers() {
+                let mut row = vec![DataValue::from(meta.name.as_str())];
+                row.extend(trigger.as_row());
+                rows.push(row);
+            }
+        }
+        let mut headers = vec!["relation".to_string()];
+        headers.extend(trigger_columns());
+        Ok(NamedRows::new(headers, rows))
+    }
     fn list_relations(&'s self, tx: &SessionTx<'_>) -> Result<NamedRows> {
         let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
         let upper =
@@ -1918,9 +3155,25 @@ fn _get_variables(src: &str, params: &BTreeMap<String, DataValue>) -> Result<BTr
     expr.get_variables()
 }
 
+/// Progress reported by a long-running fixed rule, see [`Poison::report_progress`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgressReport {
+    /// Fraction of the work completed, between 0 and 1
+    pub fraction: f64,
+    /// What the computation is doing
+    pub message: String,
+}
+
 /// Used for user-initiated termination of running queries
 #[derive(Clone, Default)]
-pub struct Poison(pub(crate) Arc<AtomicBool>);
+pub struct Poison {
+    killed: Arc<AtomicBool>,
+    /// Number of checks passed, a rough measure of the work done
+    progress: Arc<AtomicU64>,
+    report: Arc<Mutex<Option<ProgressReport>>>,
+    /// Set for queries run by a job
+    job: Option<Box<Poison>>,
+}
 
 impl Poison {
     /// Will return `Err` if user has initiated termination.
@@ -1932,11 +3185,51 @@ impl Poison {
         #[diagnostic(help("A query may be killed by timeout, or explicit command"))]
         struct ProcessKilled;
 
-        if self.0.load(Ordering::Relaxed) {
+        if self.is_killed() {
             bail!(ProcessKilled)
         }
+        self.progress.fetch_add(1, Ordering::Relaxed);
+        if let Some(job) = &self.job {
+            job.progress.fetch_add(1, Ordering::Relaxed);
+        }
         Ok(())
     }
+    /// How many times [`check`](Self::check) has succeeded
+    pub fn progress(&self) -> u64 {
+        self.progress.load(Ordering::Relaxed)
+    }
+    /// Report how far a long-running computation has got, `fraction` is clamped between 0 and 1.
+    /// Only the latest report is kept. For queries run as jobs, it is shown by `::jobs`.
+    pub fn report_progress(&self, fraction: f64, message: impl Into<String>) {
+        let report = ProgressReport {
+            fraction: fraction.clamp(0., 1.),
+            message: message.into(),
+        };
+        if let Some(job) = &self.job {
+            *job.report.lock().unwrap() = Some(report.clone());
+        }
+        *self.report.lock().unwrap() = Some(report);
+    }
+    /// The latest report made with [`report_progress`](Self::report_progress)
+    pub fn progress_report(&self) -> Option<ProgressReport> {
+        self.report.lock().unwrap().clone()
+    }
+    pub(crate) fn for_job(job: Option<Poison>) -> Self {
+        Self {
+            job: job.map(Box::new),
+            ..Default::default()
+        }
+    }
+    pub(crate) fn kill(&self) {
+        self.killed.store(true, Ordering::Relaxed);
+    }
+    pub(crate) fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Relaxed)
+            || match &self.job {
+                None => false,
+                Some(job) => job.is_killed(),
+            }
+    }
     #[cfg(target_arch = "wasm32")]
     pub(crate) fn set_timeout(&self, _secs: f64) -> Result<()> {
         bail!("Cannot set timeout when threading is disallowed");
@@ -1946,7 +3239,7 @@ impl Poison {
         let pill = self.clone();
         thread::spawn(move || {
             thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
-            pill.0.store(true, Ordering::Relaxed);
+            pill.kill();
         });
         Ok(())
     }
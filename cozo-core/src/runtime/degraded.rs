@@ -0,0 +1,209 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Report, Result};
+use pest::Parser;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Bytecode;
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::parse::expr::build_expr;
+use crate::parse::{CozoScriptParser, Rule};
+use crate::runtime::relation::RelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::utils::TempCollector;
+use crate::{Db, Storage};
+
+/// Raised when an index is found not to agree with its relation while it is read
+#[derive(Debug, Error, Diagnostic)]
+#[error("The index {0} does not agree with its relation: {1}")]
+#[diagnostic(code(eval::inconsistent_index))]
+#[diagnostic(help(
+    "Rebuild it with `::index rebuild`, or enable index fallback for queries to read around it"
+))]
+pub(crate) struct InconsistentIndex(pub(crate) String, pub(crate) String);
+
+/// Indices found not to agree with their relations, by their full name `relation:index`,
+/// with what was found. Shared by all clones of a database.
+///
+/// Only kept while index fallback is enabled by [Db::set_index_fallback].
+#[derive(Default)]
+pub(crate) struct DegradedIndices {
+    enabled: AtomicBool,
+    indices: Mutex<BTreeMap<SmartString<LazyCompact>, String>>,
+}
+
+impl DegradedIndices {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+    /// Whether queries must read around the index
+    pub(crate) fn is_degraded(&self, name: &str) -> bool {
+        self.is_enabled() && self.indices.lock().unwrap().contains_key(name)
+    }
+    /// Returns `true` if the index was not already degraded
+    fn mark(&self, name: &str, detail: &str) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let mut indices = self.indices.lock().unwrap();
+        if indices.contains_key(name) {
+            return false;
+        }
+        indices.insert(SmartString::from(name), detail.to_string());
+        true
+    }
+    /// The index has been rebuilt and can be used again
+    pub(crate) fn clear(&self, name: &str) {
+        self.indices.lock().unwrap().remove(name);
+    }
+}
+
+/// The inconsistent index an error was raised for, if any
+pub(crate) fn inconsistent_index(err: &Report) -> Option<&InconsistentIndex> {
+    err.chain()
+        .find_map(|err| err.downcast_ref::<InconsistentIndex>())
+}
+
+/// Compile the extractor or the filter of an index against the columns of its relation
+pub(crate) fn compile_index_expr(rel: &RelationHandle, code: &str) -> Result<Vec<Bytecode>> {
+    let parsed = CozoScriptParser::parse(Rule::expr, code)
+        .into_diagnostic()?
+        .next()
+        .unwrap();
+    let mut expr = build_expr(parsed, &Default::default())?;
+    expr.fill_binding_indices(&rel.raw_binding_map())?;
+    expr.compile()
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Set whether queries read around indices found not to agree with their relations,
+    /// instead of failing. Off by default.
+    ///
+    /// When on, an index found inconsistent while a query reads it, or by [Db::scrub], is no
+    /// longer used: the query is run again scanning the relation instead, and the results
+    /// carry a warning in [NamedRows::warnings](crate::NamedRows::warnings). A job rebuilding
+    /// the index is submitted, after which the index is used again.
+    pub fn set_index_fallback(&self, enabled: bool) {
+        self.degraded.enabled.store(enabled, Ordering::Release);
+        if !enabled {
+            self.degraded.indices.lock().unwrap().clear();
+        }
+    }
+
+    /// Stop using the index `name` if index fallback is on, and submit a job rebuilding it.
+    /// Returns `true` if the index was not already degraded.
+    pub(crate) fn degrade_index(&self, name: &str, detail: &str) -> bool {
+        if !self.degraded.mark(name, detail) {
+            return false;
+        }
+        log::warn!("index {name} does not agree with its relation ({detail}), rebuilding it");
+        let script = match name.split_once(':') {
+            Some((rel, idx)) => format!("::index rebuild {rel}:{idx}"),
+            None => return true,
+        };
+        if let Err(err) = self.jobs.submit(script, Default::default(), false) {
+            log::error!("cannot submit the rebuilding of index {name}: {err}");
+        }
+        true
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Whether the index must not be read, as it does not agree with its relation.
+    /// The results of the query are then warned about it.
+    pub(crate) fn read_around(&mut self, name: &str) -> bool {
+        if !self.degraded.is_degraded(name) {
+            return false;
+        }
+        let warning =
+            format!("the index {name} is being rebuilt, its relation was scanned instead");
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+        true
+    }
+
+    fn clear_relation_rows(&mut self, handle: &RelationHandle) -> Result<()> {
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let mut keys = vec![];
+        for kv in self.store_tx.range_scan(&lower, &upper) {
+            keys.push(kv?.0);
+        }
+        for key in keys {
+            self.store_tx.del(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Empty an index of any kind, and fill it again from the rows of its relation
+    pub(crate) fn rebuild_index(&mut self, rel_name: &Symbol, idx_name: &Symbol) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("index {0} for relation {1} not found")]
+        #[diagnostic(code(tx::idx_not_found))]
+        struct IndexNotFound(String, String);
+
+        let rel = self.get_relation(rel_name, true)?;
+        let mut rows = TempCollector::default();
+        for tuple in rel.scan_all(self) {
+            rows.push(tuple?);
+        }
+        let mut stack = vec![];
+        if let Some((idx, extraction)) = rel.indices.get(&idx_name.name) {
+            self.clear_relation_rows(idx)?;
+            for tuple in rows.into_iter() {
+                let extracted = extraction.iter().map(|i| tuple[*i].clone()).collect_vec();
+                let key = idx.encode_key_for_store(&extracted, Default::default())?;
+                self.store_tx.put(&key, &[])?;
+            }
+        } else if let Some((idx, manifest)) = rel.hnsw_indices.get(&idx_name.name) {
+            self.clear_relation_rows(idx)?;
+            let filter = match &manifest.index_filter {
+                None => None,
+                Some(code) => Some(compile_index_expr(&rel, code)?),
+            };
+            for tuple in rows.into_iter() {
+                self.hnsw_put(manifest, &rel, idx, filter.as_ref(), &mut stack, &tuple)?;
+            }
+        } else if let Some((idx, manifest)) = rel.fts_indices.get(&idx_name.name) {
+            self.clear_relation_rows(idx)?;
+            let tokenizer =
+                self.tokenizers
+                    .get(&idx.name, &manifest.tokenizer, &manifest.filters)?;
+            let extractor = compile_index_expr(&rel, &manifest.extractor)?;
+            for tuple in rows.into_iter() {
+                self.put_fts_index_item(&tuple, &extractor, &mut stack, &tokenizer, &rel, idx)?;
+            }
+        } else if let Some((idx, inv_idx, manifest)) = rel.lsh_indices.get(&idx_name.name) {
+            self.clear_relation_rows(idx)?;
+            self.clear_relation_rows(inv_idx)?;
+            let tokenizer =
+                self.tokenizers
+                    .get(&idx.name, &manifest.tokenizer, &manifest.filters)?;
+            let extractor = compile_index_expr(&rel, &manifest.extractor)?;
+            let perms = manifest.get_hash_perms();
+            for tuple in rows.into_iter() {
+                self.put_lsh_index_item(
+                    &tuple, &extractor, &mut stack, &tokenizer, &rel, idx, inv_idx, manifest,
+                    &perms,
+                )?;
+            }
+        } else {
+            bail!(IndexNotFound(idx_name.to_string(), rel_name.to_string()));
+        }
+        Ok(())
+    }
+}
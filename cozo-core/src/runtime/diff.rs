@@ -0,0 +1,329 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use sha2::digest::FixedOutput;
+use sha2::{Digest, Sha256};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::relation::ColumnDef;
+use crate::data::tuple::{Tuple, TupleT, ENCODED_KEY_MIN_LEN};
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::parse::sys::DiffConfig;
+use crate::runtime::relation::{decode_tuple_from_kv, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot compare with the remote database at {0}")]
+#[diagnostic(code(diff::remote_unsupported))]
+#[diagnostic(help(
+    "Back the remote database up into a file with 'backup_db', and compare with it"
+))]
+struct RemoteDiffUnsupported(String);
+
+/// The stored relations of the transaction by name, indices left out
+pub(crate) fn stored_relations(
+    tx: &SessionTx<'_>,
+) -> Result<BTreeMap<SmartString<LazyCompact>, RelationHandle>> {
+    let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+    let upper =
+        vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+    let mut ret = BTreeMap::new();
+    for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+        let (k_slice, v_slice) = kv_res?;
+        if upper <= k_slice {
+            break;
+        }
+        let handle = RelationHandle::decode(&v_slice)?;
+        if !handle.name.contains(':') {
+            ret.insert(handle.name.clone(), handle);
+        }
+    }
+    Ok(ret)
+}
+
+fn describe_schema(handle: &RelationHandle) -> String {
+    let cols = |cols: &[ColumnDef]| {
+        cols.iter()
+            .map(|c| format!("{}: {}", c.name, c.typing))
+            .join(", ")
+    };
+    let mut ret = format!("{{{}", cols(&handle.metadata.keys));
+    if !handle.metadata.non_keys.is_empty() {
+        ret.push_str(" => ");
+        ret.push_str(&cols(&handle.metadata.non_keys));
+    }
+    ret.push('}');
+    let indices = index_names(handle);
+    if !indices.is_empty() {
+        ret.push_str(&format!(" indices: [{}]", indices.iter().join(", ")));
+    }
+    ret
+}
+
+fn index_names(handle: &RelationHandle) -> BTreeSet<&str> {
+    handle
+        .indices
+        .keys()
+        .chain(handle.hnsw_indices.keys())
+        .chain(handle.fts_indices.keys())
+        .chain(handle.lsh_indices.keys())
+        .map(|name| name.as_str())
+        .collect()
+}
+
+/// Digest of the rows of a relation, which does not depend on where the relation is stored
+#[derive(Default)]
+struct ContentDigest {
+    hasher: Sha256,
+    n_rows: usize,
+}
+
+impl ContentDigest {
+    fn update(&mut self, key: &[u8], val: &[u8]) {
+        for part in [
+            &key[ENCODED_KEY_MIN_LEN..],
+            val.get(ENCODED_KEY_MIN_LEN..).unwrap_or(&[]),
+        ] {
+            self.hasher.update((part.len() as u64).to_be_bytes());
+            self.hasher.update(part);
+        }
+        self.n_rows += 1;
+    }
+    /// Digest a row as it is read, not as it is stored: encrypted values are stored
+    /// with a nonce of their own
+    fn update_tuple(&mut self, tuple: &Tuple) {
+        let encoded = tuple.encode_as_key(RelationId::SYSTEM);
+        let part = &encoded[ENCODED_KEY_MIN_LEN..];
+        self.hasher.update((part.len() as u64).to_be_bytes());
+        self.hasher.update(part);
+        self.n_rows += 1;
+    }
+    fn finish(self) -> (usize, String) {
+        let hash = self.hasher.finalize_fixed();
+        (
+            self.n_rows,
+            hash.iter().map(|b| format!("{b:02x}")).join(""),
+        )
+    }
+}
+
+/// A digest of the schema and the rows of a stored relation, the same wherever it is stored,
+/// telling whether the relation has changed
+pub(crate) fn relation_fingerprint(tx: &SessionTx<'_>, handle: &RelationHandle) -> Result<String> {
+    let mut digest = ContentDigest::default();
+    digest.hasher.update(describe_schema(handle).as_bytes());
+    for kv in scan_rows(tx, handle) {
+        let (k, v) = kv?;
+        digest.update(&k, &v);
+    }
+    Ok(digest.finish().1)
+}
+
+fn scan_rows<'t>(
+    tx: &'t SessionTx<'_>,
+    handle: &RelationHandle,
+) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 't> {
+    let lower = Tuple::default().encode_as_key(handle.id);
+    let upper = Tuple::default().encode_as_key(handle.id.next());
+    tx.store_tx.range_scan(&lower, &upper)
+}
+
+#[cfg(feature = "storage-sqlite")]
+fn diff_row(relation: &str, kind: &str, here: Option<Tuple>, there: Option<Tuple>) -> Tuple {
+    vec![
+        DataValue::from(relation),
+        DataValue::from(kind),
+        here.map(DataValue::List).unwrap_or(DataValue::Null),
+        there.map(DataValue::List).unwrap_or(DataValue::Null),
+    ]
+}
+
+fn ensure_local(config: &DiffConfig) -> Result<()> {
+    if config.path.starts_with("http://") || config.path.starts_with("https://") {
+        bail!(RemoteDiffUnsupported(config.path.to_string()))
+    }
+    Ok(())
+}
+
+impl<'a> SessionTx<'a> {
+    /// Compare the stored relations with those of another database, kept in a file in the
+    /// format of backups.
+    ///
+    /// Each difference gives a row of the relation, the kind of difference, and what is found
+    /// here and there: relations found on one side only, schemas, which include indices,
+    /// numbers of rows, and digests of the content. Up to `rows` differing rows are given
+    /// for each relation, by their full tuple. Contents are not compared when the columns differ.
+    #[cfg(feature = "storage-sqlite")]
+    pub(crate) fn diff_with(&self, config: &DiffConfig) -> Result<NamedRows> {
+        ensure_local(config)?;
+        let other = crate::storage::sqlite::open_cozo_sqlite(&config.path, true)?;
+        let other_tx = other.transact()?;
+        self.diff_relations(&other_tx, config)
+    }
+    /// Databases are compared with backups, which are kept by SQLite
+    #[cfg(not(feature = "storage-sqlite"))]
+    pub(crate) fn diff_with(&self, config: &DiffConfig) -> Result<NamedRows> {
+        ensure_local(config)?;
+        bail!("comparing databases requires the 'storage-sqlite' feature to be enabled")
+    }
+
+    #[cfg(feature = "storage-sqlite")]
+    fn diff_relations(&self, other: &SessionTx<'_>, config: &DiffConfig) -> Result<NamedRows> {
+        let mut here = stored_relations(self)?;
+        let mut there = stored_relations(other)?;
+        if let Some(relations) = &config.relations {
+            here.retain(|name, _| relations.contains(name));
+            there.retain(|name, _| relations.contains(name));
+        }
+        let names: BTreeSet<_> = here.keys().chain(there.keys()).cloned().collect();
+
+        let mut rows = vec![];
+        for name in names {
+            let (h, t) = match (here.get(&name), there.get(&name)) {
+                (Some(h), Some(t)) => (h, t),
+                (h, _) => {
+                    let kind = if h.is_some() {
+                        "only_here"
+                    } else {
+                        "only_there"
+                    };
+                    rows.push(vec![
+                        DataValue::from(name.as_str()),
+                        DataValue::from(kind),
+                        DataValue::Null,
+                        DataValue::Null,
+                    ]);
+                    continue;
+                }
+            };
+            if h.metadata != t.metadata || index_names(h) != index_names(t) {
+                rows.push(vec![
+                    DataValue::from(name.as_str()),
+                    DataValue::from("schema"),
+                    DataValue::from(describe_schema(h)),
+                    DataValue::from(describe_schema(t)),
+                ]);
+                if h.metadata != t.metadata {
+                    continue;
+                }
+            }
+            self.diff_content(other, &name, t, config.rows, &mut rows)?;
+        }
+        Ok(NamedRows::new(
+            vec![
+                "relation".to_string(),
+                "difference".to_string(),
+                "here".to_string(),
+                "there".to_string(),
+            ],
+            rows,
+        ))
+    }
+
+    /// Walk the rows of both relations in key order, digesting them and collecting
+    /// the first `max_rows` differing ones
+    #[cfg(feature = "storage-sqlite")]
+    fn diff_content(
+        &self,
+        other: &SessionTx<'_>,
+        name: &str,
+        there: &RelationHandle,
+        max_rows: usize,
+        out: &mut Vec<Tuple>,
+    ) -> Result<()> {
+        // rows are read as queries read them, masked unless the session may unmask
+        let here = self.get_relation_for_read(name)?;
+        // the keys of both sides are looked for here, as a backup is made with the same ones,
+        // and the masks of the database apply to its backup too
+        let mut there = there.clone();
+        self.attach_ciphers(&mut there)?;
+        there.active_masks = here.active_masks.clone();
+        // rows are compared decrypted, by their raw keys for the order
+        let read = |handle: &RelationHandle, kv: Option<Result<(Vec<u8>, Vec<u8>)>>| {
+            kv.map(|kv| -> Result<(Vec<u8>, Tuple)> {
+                let (k, v) = kv?;
+                let tuple = handle.decrypt_tuple(decode_tuple_from_kv(&k, &v, None))?;
+                Ok((k, handle.mask_tuple(tuple, false)))
+            })
+            .transpose()
+        };
+        let mut here_iter = scan_rows(self, &here);
+        let mut there_iter = scan_rows(other, &there);
+        let mut here_digest = ContentDigest::default();
+        let mut there_digest = ContentDigest::default();
+        let mut row_diffs = vec![];
+        let mut h = read(&here, here_iter.next())?;
+        let mut t = read(&there, there_iter.next())?;
+        loop {
+            let ord = match (&h, &t) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((hk, _)), Some((tk, _))) => {
+                    hk[ENCODED_KEY_MIN_LEN..].cmp(&tk[ENCODED_KEY_MIN_LEN..])
+                }
+            };
+            match ord {
+                Ordering::Less => {
+                    let (_, tuple) = h.take().unwrap();
+                    here_digest.update_tuple(&tuple);
+                    if row_diffs.len() < max_rows {
+                        row_diffs.push(diff_row(name, "row_only_here", Some(tuple), None));
+                    }
+                    h = read(&here, here_iter.next())?;
+                }
+                Ordering::Greater => {
+                    let (_, tuple) = t.take().unwrap();
+                    there_digest.update_tuple(&tuple);
+                    if row_diffs.len() < max_rows {
+                        row_diffs.push(diff_row(name, "row_only_there", None, Some(tuple)));
+                    }
+                    t = read(&there, there_iter.next())?;
+                }
+                Ordering::Equal => {
+                    let (_, h_tuple) = h.take().unwrap();
+                    let (_, t_tuple) = t.take().unwrap();
+                    here_digest.update_tuple(&h_tuple);
+                    there_digest.update_tuple(&t_tuple);
+                    if h_tuple != t_tuple && row_diffs.len() < max_rows {
+                        row_diffs.push(diff_row(name, "row_changed", Some(h_tuple), Some(t_tuple)));
+                    }
+                    h = read(&here, here_iter.next())?;
+                    t = read(&there, there_iter.next())?;
+                }
+            }
+        }
+        let (here_rows, here_hash) = here_digest.finish();
+        let (there_rows, there_hash) = there_digest.finish();
+        if here_rows != there_rows {
+            out.push(vec![
+                DataValue::from(name),
+                DataValue::from("row_count"),
+                DataValue::from(here_rows as i64),
+                DataValue::from(there_rows as i64),
+            ]);
+        }
+        if here_hash != there_hash {
+            out.push(vec![
+                DataValue::from(name),
+                DataValue::from("content"),
+                DataValue::from(here_hash),
+                DataValue::from(there_hash),
+            ]);
+        }
+        out.extend(row_diffs);
+        Ok(())
+    }
+}
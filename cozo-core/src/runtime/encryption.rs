@@ -0,0 +1,383 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use crossbeam::sync::ShardedLock;
+use miette::{bail, ensure, Diagnostic, Result};
+use rand::RngCore;
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+
+/// Supplies the keys of encrypted columns.
+///
+/// A provider is set with [crate::Db::set_key_provider] when the database is opened.
+/// Columns are encrypted with AES-256-GCM under the named key given to `::encrypt`.
+/// Sessions of a database whose provider has no key for a column read the ciphertext,
+/// as bytes, and cannot write to the relation.
+pub trait KeyProvider: Send + Sync {
+    /// The 32 bytes of the key with the given name, or `None` if this database
+    /// is not authorized to use it
+    fn key(&self, name: &str) -> Result<Option<Vec<u8>>>;
+}
+
+impl<F> KeyProvider for F
+where
+    F: Fn(&str) -> Result<Option<Vec<u8>>> + Send + Sync,
+{
+    fn key(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        self(name)
+    }
+}
+
+/// Fetches keys from a key management service over HTTP: the key named `name` is
+/// the base64 body of the response to `GET {url}/{name}`. A response of 403 or 404
+/// means the key is not available to this database.
+#[cfg(feature = "requests")]
+pub struct HttpKeyProvider {
+    url: String,
+    token: Option<String>,
+}
+
+#[cfg(feature = "requests")]
+impl HttpKeyProvider {
+    /// The `token`, if given, is sent as a bearer token with every request
+    pub fn new(url: &str, token: Option<&str>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            token: token.map(|t| t.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "requests")]
+impl KeyProvider for HttpKeyProvider {
+    fn key(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        use base64::Engine;
+        use miette::IntoDiagnostic;
+
+        let mut req = minreq::get(format!("{}/{name}", self.url));
+        if let Some(token) = &self.token {
+            req = req.with_header("Authorization", format!("Bearer {token}"));
+        }
+        let resp = req.send().into_diagnostic()?;
+        match resp.status_code {
+            200 => {
+                let body = resp.as_str().into_diagnostic()?;
+                let key = base64::engine::general_purpose::STANDARD
+                    .decode(body.trim())
+                    .into_diagnostic()?;
+                Ok(Some(key))
+            }
+            403 | 404 => Ok(None),
+            code => bail!("The key service answered {code} for the key {name}"),
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The key {0} of the encrypted column {1} is not available")]
+#[diagnostic(code(eval::encryption_key_unavailable))]
+#[diagnostic(help("Keys are supplied by the key provider set when the database is opened"))]
+struct KeyUnavailable(String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The value of the encrypted column {0} cannot be decrypted")]
+#[diagnostic(code(eval::bad_ciphertext))]
+#[diagnostic(help("The value was changed outside of Cozo, or encrypted with another key"))]
+struct BadCiphertext(String);
+
+/// The keys of a database, fetched from its provider when first used
+#[derive(Default)]
+pub(crate) struct KeyRing {
+    provider: ShardedLock<Option<Arc<dyn KeyProvider>>>,
+    ciphers: Mutex<BTreeMap<String, Option<Arc<Aes256Gcm>>>>,
+}
+
+impl KeyRing {
+    pub(crate) fn set_provider(&self, provider: Option<Arc<dyn KeyProvider>>) {
+        *self.provider.write().unwrap() = provider;
+        self.ciphers.lock().unwrap().clear();
+    }
+    fn cipher(&self, name: &str) -> Result<Option<Arc<Aes256Gcm>>> {
+        if let Some(found) = self.ciphers.lock().unwrap().get(name) {
+            return Ok(found.clone());
+        }
+        let provider = self.provider.read().unwrap().clone();
+        let cipher = match provider {
+            None => None,
+            Some(provider) => match provider.key(name)? {
+                None => None,
+                Some(key) => {
+                    ensure!(
+                        key.len() == 32,
+                        "The key {name} has {} bytes, AES-256 requires 32",
+                        key.len()
+                    );
+                    Some(Arc::new(Aes256Gcm::new_from_slice(&key).unwrap()))
+                }
+            },
+        };
+        self.ciphers
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), cipher.clone());
+        Ok(cipher)
+    }
+}
+
+/// An encrypted column of a relation, with its cipher if the key is available
+#[derive(Clone)]
+struct EncryptedColumn {
+    /// The position of the column in the tuple
+    pos: usize,
+    name: SmartString<LazyCompact>,
+    key: SmartString<LazyCompact>,
+    cipher: Option<Arc<Aes256Gcm>>,
+}
+
+/// The ciphers of the encrypted columns of a relation handle, made when the handle is loaded
+#[derive(Clone, Default)]
+pub(crate) struct ColumnCiphers {
+    columns: Vec<EncryptedColumn>,
+    n_keys: usize,
+}
+
+impl PartialEq for ColumnCiphers {
+    /// The ciphers are given by the keys, compared by their names
+    fn eq(&self, other: &Self) -> bool {
+        self.n_keys == other.n_keys
+            && self.columns.len() == other.columns.len()
+            && self
+                .columns
+                .iter()
+                .zip(&other.columns)
+                .all(|(a, b)| a.pos == b.pos && a.key == b.key)
+    }
+}
+
+const CIPHERTEXT_VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+
+impl ColumnCiphers {
+    /// Encrypt the values of the non-key columns given.
+    /// Nulls are left alone, so that nullability is kept.
+    pub(crate) fn encrypt_vals(&self, vals: &mut [DataValue]) -> Result<()> {
+        for col in &self.columns {
+            let val = &mut vals[col.pos - self.n_keys];
+            if *val == DataValue::Null {
+                continue;
+            }
+            let cipher = col
+                .cipher
+                .as_ref()
+                .ok_or_else(|| KeyUnavailable(col.key.to_string(), col.name.to_string()))?;
+            let mut plain = vec![];
+            val.serialize(&mut Serializer::new(&mut plain)).unwrap();
+            let mut nonce = [0u8; NONCE_LEN];
+            rand::thread_rng().fill_bytes(&mut nonce);
+            let sealed = cipher
+                .encrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: &plain,
+                        aad: col.name.as_bytes(),
+                    },
+                )
+                .unwrap();
+            let mut out = Vec::with_capacity(1 + NONCE_LEN + sealed.len());
+            out.push(CIPHERTEXT_VERSION);
+            out.extend_from_slice(&nonce);
+            out.extend_from_slice(&sealed);
+            *val = DataValue::Bytes(out);
+        }
+        Ok(())
+    }
+    /// Decrypt the values of a whole tuple, or of its non-key columns if `vals_only`.
+    /// Columns whose key is not available keep their ciphertext.
+    pub(crate) fn decrypt(&self, tuple: &mut [DataValue], vals_only: bool) -> Result<()> {
+        let offset = if vals_only { self.n_keys } else { 0 };
+        for col in &self.columns {
+            let cipher = match &col.cipher {
+                Some(cipher) => cipher,
+                None => continue,
+            };
+            let val = match tuple.get_mut(col.pos - offset) {
+                Some(val) => val,
+                None => continue,
+            };
+            let sealed = match val {
+                DataValue::Null => continue,
+                DataValue::Bytes(b) if b.len() > NONCE_LEN && b[0] == CIPHERTEXT_VERSION => b,
+                _ => bail!(BadCiphertext(col.name.to_string())),
+            };
+            let plain = cipher
+                .decrypt(
+                    Nonce::from_slice(&sealed[1..1 + NONCE_LEN]),
+                    Payload {
+                        msg: &sealed[1 + NONCE_LEN..],
+                        aad: col.name.as_bytes(),
+                    },
+                )
+                .map_err(|_| BadCiphertext(col.name.to_string()))?;
+            *val =
+                rmp_serde::from_slice(&plain).map_err(|_| BadCiphertext(col.name.to_string()))?;
+        }
+        Ok(())
+    }
+    /// Whether the key of any column is available
+    pub(crate) fn decrypts_any(&self) -> bool {
+        self.columns.iter().any(|col| col.cipher.is_some())
+    }
+}
+
+impl RelationHandle {
+    pub(crate) fn decrypt_tuple(&self, mut tuple: Tuple) -> Result<Tuple> {
+        if let Some(ciphers) = &self.ciphers {
+            ciphers.decrypt(&mut tuple, false)?;
+        }
+        Ok(tuple)
+    }
+    pub(crate) fn decrypt_vals(&self, mut vals: Vec<DataValue>) -> Result<Vec<DataValue>> {
+        if let Some(ciphers) = &self.ciphers {
+            ciphers.decrypt(&mut vals, true)?;
+        }
+        Ok(vals)
+    }
+    /// Decrypt the tuples of a scan when any key is available
+    pub(crate) fn decrypting<'a>(
+        &self,
+        iter: Box<dyn Iterator<Item = Result<Tuple>> + 'a>,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        match &self.ciphers {
+            Some(ciphers) if ciphers.decrypts_any() => {
+                let ciphers = ciphers.clone();
+                Box::new(iter.map(move |tuple| {
+                    let mut tuple = tuple?;
+                    ciphers.decrypt(&mut tuple, false)?;
+                    Ok(tuple)
+                }))
+            }
+            _ => iter,
+        }
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Give the handle of a relation with encrypted columns the ciphers available
+    pub(crate) fn attach_ciphers(&self, handle: &mut RelationHandle) -> Result<()> {
+        if handle.encrypted.is_empty() {
+            return Ok(());
+        }
+        let n_keys = handle.metadata.keys.len();
+        let mut columns = vec![];
+        for (i, col) in handle.metadata.non_keys.iter().enumerate() {
+            if let Some(key) = handle.encrypted.get(&col.name) {
+                columns.push(EncryptedColumn {
+                    pos: n_keys + i,
+                    name: col.name.clone(),
+                    key: key.clone(),
+                    cipher: self.keys.cipher(key)?,
+                });
+            }
+        }
+        handle.ciphers = Some(ColumnCiphers { columns, n_keys });
+        Ok(())
+    }
+
+    /// Encrypt the columns given with the named keys, or decrypt those given with `None`,
+    /// rewriting the rows already stored
+    pub(crate) fn set_encrypted_columns(
+        &mut self,
+        rel: &Symbol,
+        columns: &BTreeMap<SmartString<LazyCompact>, Option<SmartString<LazyCompact>>>,
+    ) -> Result<usize> {
+        let mut handle = self.get_relation(rel, true)?;
+        if handle.is_temp {
+            bail!("Cannot encrypt columns of temp relations")
+        }
+        if handle.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "encrypt columns".to_string(),
+                handle.access_level
+            ))
+        }
+        for (name, key) in columns {
+            let pos = match handle
+                .metadata
+                .non_keys
+                .iter()
+                .position(|c| c.name == *name)
+            {
+                Some(pos) => pos + handle.metadata.keys.len(),
+                None => {
+                    if handle.metadata.keys.iter().any(|c| c.name == *name) {
+                        bail!("The key column {name} cannot be encrypted")
+                    }
+                    bail!("The relation {} has no column {name}", handle.name)
+                }
+            };
+            if key.is_some() {
+                ensure!(
+                    handle
+                        .indices
+                        .values()
+                        .all(|(_, cols)| !cols.contains(&pos))
+                        && handle.hnsw_indices.is_empty()
+                        && handle.fts_indices.is_empty()
+                        && handle.lsh_indices.is_empty(),
+                    "The column {name} is indexed, and indices would keep its values unencrypted"
+                );
+            }
+        }
+        // all rows are read with the current keys, so these must be available
+        if let Some(ciphers) = &handle.ciphers {
+            for col in &ciphers.columns {
+                ensure!(
+                    col.cipher.is_some(),
+                    KeyUnavailable(col.key.to_string(), col.name.to_string())
+                );
+            }
+        }
+        let rows: Vec<Tuple> = handle.scan_all(self).collect::<Result<_>>()?;
+
+        for (name, key) in columns {
+            match key {
+                Some(key) => handle.encrypted.insert(name.clone(), key.clone()),
+                None => handle.encrypted.remove(name),
+            };
+        }
+        handle.ciphers = None;
+        self.attach_ciphers(&mut handle)?;
+        for row in &rows {
+            let key = handle.encode_key_for_store(row, rel.span)?;
+            let val = handle.encode_val_for_store(row, rel.span)?;
+            self.store_tx.put(&key, &val)?;
+        }
+
+        let name_key = vec![DataValue::Str(handle.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+        Ok(rows.len())
+    }
+}
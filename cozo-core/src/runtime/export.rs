@@ -0,0 +1,272 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use serde_json::Value as JsonValue;
+use sha2::digest::FixedOutput;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::parse::sys::ChunkedExportConfig;
+use crate::runtime::relation::{
+    decode_tuple_from_kv, AccessLevel, InsufficientAccessLevel, RelationHandle,
+};
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+/// The name of the manifest in the directory of a chunked export
+const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The export in {0} does not match the one asked for: {1}")]
+#[diagnostic(code(export::manifest_mismatch))]
+#[diagnostic(help("Export into another directory, or give 'restart: true' to start over"))]
+struct ManifestMismatch(String, String);
+
+/// The manifest of a chunked export, rewritten after each chunk so that an interrupted
+/// export can resume from the last chunk written
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+struct ExportManifest {
+    relation: String,
+    headers: Vec<String>,
+    rows_per_chunk: usize,
+    chunks: Vec<ChunkEntry>,
+    /// Whether all rows have been exported
+    complete: bool,
+}
+
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+struct ChunkEntry {
+    file: String,
+    rows: usize,
+    /// The SHA-256 of the file, in hex
+    sha256: String,
+    /// The stored key of the last row in the chunk, in base64
+    last_key: String,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    let hash = hasher.finalize_fixed();
+    hash.iter().map(|b| format!("{b:02x}")).join("")
+}
+
+/// Write a file under a temporary name first, so that it is either absent or complete
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)
+        .and_then(|_| fs::rename(&tmp, path))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("cannot write {}", path.display()))
+}
+
+impl ExportManifest {
+    fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read(&path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("cannot read {}", path.display()))?;
+        let manifest = serde_json::from_slice(&text)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("invalid manifest {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+    fn save(&self, dir: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).unwrap();
+        write_atomically(&dir.join(MANIFEST_FILE), &data)
+    }
+    /// Write the next chunk, then record it in the manifest
+    fn add_chunk(&mut self, dir: &Path, text: &str, rows: usize, last_key: &[u8]) -> Result<()> {
+        let file = format!("chunk-{:06}.jsonl", self.chunks.len());
+        write_atomically(&dir.join(&file), text.as_bytes())?;
+        self.chunks.push(ChunkEntry {
+            file,
+            rows,
+            sha256: sha256_hex(text.as_bytes()),
+            last_key: STANDARD.encode(last_key),
+        });
+        self.save(dir)
+    }
+    /// Drop the chunks after the first one missing or not matching its checksum
+    fn keep_verified(&mut self, dir: &Path) {
+        let verified = self
+            .chunks
+            .iter()
+            .take_while(|chunk| match fs::read(dir.join(&chunk.file)) {
+                Ok(data) => sha256_hex(&data) == chunk.sha256,
+                Err(_) => false,
+            })
+            .count();
+        if verified < self.chunks.len() {
+            self.chunks.truncate(verified);
+            self.complete = false;
+        }
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Read all rows of a stored relation, decrypted and masked, together with its handle
+    pub(crate) fn export_relation(&self, name: &str) -> Result<(RelationHandle, NamedRows)> {
+        let handle = self.get_relation_for_read(name)?;
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "data export".to_string(),
+                handle.access_level
+            ))
+        }
+        let headers = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .map(|col| col.name.to_string())
+            .collect_vec();
+
+        let start = Tuple::default().encode_as_key(handle.id);
+        let end = Tuple::default().encode_as_key(handle.id.next());
+        let mut rows = vec![];
+        for kv in self.store_tx.range_scan(&start, &end) {
+            let (k, v) = kv?;
+            let tuple = decode_tuple_from_kv(&k, &v, Some(headers.len()));
+            rows.push(handle.mask_tuple(handle.decrypt_tuple(tuple)?, false));
+        }
+        Ok((handle, NamedRows::new(headers, rows)))
+    }
+
+    /// Export a stored relation into a directory as numbered chunks of JSON lines,
+    /// one array of values per row, with a manifest holding the checksum of each chunk.
+    ///
+    /// All chunks written by one call come from the snapshot of its transaction. When
+    /// an earlier export was interrupted, the chunks it completed are verified and kept,
+    /// and the export continues after the last row they hold.
+    pub(crate) fn export_chunked(&self, config: &ChunkedExportConfig) -> Result<NamedRows> {
+        let handle = self.get_relation_for_read(&config.relation)?;
+        if handle.is_temp {
+            bail!("Cannot export temp relations in chunks")
+        }
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "data export".to_string(),
+                handle.access_level
+            ))
+        }
+        let headers = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .map(|col| col.name.to_string())
+            .collect_vec();
+
+        let dir = PathBuf::from(&config.dir);
+        fs::create_dir_all(&dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("cannot create {}", config.dir))?;
+        let mut manifest = match ExportManifest::load(&dir)? {
+            Some(mut manifest) if !config.restart => {
+                if manifest.relation != handle.name.as_str() {
+                    bail!(ManifestMismatch(
+                        config.dir.clone(),
+                        format!("it holds the relation '{}'", manifest.relation)
+                    ))
+                }
+                if manifest.headers != headers {
+                    bail!(ManifestMismatch(
+                        config.dir.clone(),
+                        "the columns of the relation have changed".to_string()
+                    ))
+                }
+                if manifest.rows_per_chunk != config.rows_per_chunk {
+                    bail!(ManifestMismatch(
+                        config.dir.clone(),
+                        format!("it has {} rows per chunk", manifest.rows_per_chunk)
+                    ))
+                }
+                manifest.keep_verified(&dir);
+                manifest
+            }
+            _ => ExportManifest {
+                relation: handle.name.to_string(),
+                headers: headers.clone(),
+                rows_per_chunk: config.rows_per_chunk,
+                chunks: vec![],
+                complete: false,
+            },
+        };
+        let kept = manifest.chunks.len();
+
+        if !manifest.complete {
+            let lower = match manifest.chunks.last() {
+                None => Tuple::default().encode_as_key(handle.id),
+                Some(chunk) => {
+                    let mut key = STANDARD
+                        .decode(&chunk.last_key)
+                        .into_diagnostic()
+                        .wrap_err("invalid key in the manifest")?;
+                    // the smallest key after the last one exported
+                    key.push(0);
+                    key
+                }
+            };
+            let upper = Tuple::default().encode_as_key(handle.id.next());
+
+            let mut text = String::new();
+            let mut rows = 0;
+            let mut last_key = vec![];
+            for kv in self.store_tx.range_scan(&lower, &upper) {
+                let (k, v) = kv?;
+                let tuple = decode_tuple_from_kv(&k, &v, Some(headers.len()));
+                let tuple = handle.mask_tuple(handle.decrypt_tuple(tuple)?, false);
+                let row = JsonValue::Array(tuple.into_iter().map(JsonValue::from).collect());
+                text.push_str(&row.to_string());
+                text.push('\n');
+                rows += 1;
+                last_key = k;
+                if rows == config.rows_per_chunk {
+                    manifest.add_chunk(&dir, &text, rows, &last_key)?;
+                    text.clear();
+                    rows = 0;
+                }
+            }
+            if rows > 0 {
+                manifest.add_chunk(&dir, &text, rows, &last_key)?;
+            }
+            manifest.complete = true;
+            manifest.save(&dir)?;
+        }
+
+        Ok(NamedRows::new(
+            vec![
+                "chunks".to_string(),
+                "rows".to_string(),
+                "kept".to_string(),
+                "manifest".to_string(),
+            ],
+            vec![vec![
+                DataValue::from(manifest.chunks.len() as i64),
+                DataValue::from(manifest.chunks.iter().map(|c| c.rows).sum::<usize>() as i64),
+                DataValue::from(kept as i64),
+                DataValue::from(dir.join(MANIFEST_FILE).to_string_lossy().to_string()),
+            ]],
+        ))
+    }
+}
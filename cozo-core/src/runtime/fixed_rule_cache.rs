@@ -0,0 +1,284 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::FixedRuleArg;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::relation::RelationId;
+use crate::runtime::temp_store::RegularTempStore;
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+/// The option of fixed rule applications asking for their output to be cached
+pub(crate) const CACHE_OPTION: &str = "cache";
+
+const RELATION_VERSION: &str = "RELATION_VERSION";
+const FIXED_RULE_CACHE: &str = "FIXED_RULE_CACHE";
+
+#[derive(Debug, Error, Diagnostic)]
+#[error(
+    "The output of fixed rule {0} can only be cached if all of its inputs are stored relations"
+)]
+#[diagnostic(code(parser::uncacheable_fixed_rule))]
+#[diagnostic(help("Inline rules and temp relations are computed anew by every query"))]
+struct UncacheableFixedRule(String, #[label] SourceSpan);
+
+/// Outputs of fixed rules computed by queries, waiting to be written into the database.
+/// Shared by all clones of a database.
+pub(crate) type PendingFixedRuleOutputs = Arc<Mutex<Vec<CachedFixedRuleOutput>>>;
+
+/// A fixed rule application whose output is kept in the database, until one of the
+/// stored relations it reads is written to
+#[derive(Clone, Debug)]
+pub(crate) struct FixedRuleCacheSpec {
+    /// The application as written, with options filled in: identifies the output
+    pub(crate) rule: String,
+    pub(crate) relations: BTreeSet<SmartString<LazyCompact>>,
+}
+
+impl FixedRuleCacheSpec {
+    pub(crate) fn new(
+        name: &str,
+        rule_args: &[FixedRuleArg],
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        span: SourceSpan,
+    ) -> Result<Self> {
+        let mut relations = BTreeSet::new();
+        let mut args = vec![];
+        for arg in rule_args {
+            let (rel, valid_at) = match arg {
+                FixedRuleArg::InMem { .. } => bail!(UncacheableFixedRule(name.to_string(), span)),
+                FixedRuleArg::Stored { name, valid_at, .. }
+                | FixedRuleArg::NamedStored { name, valid_at, .. } => (name, valid_at),
+            };
+            if rel.is_temp_store_name() {
+                bail!(UncacheableFixedRule(name.to_string(), span))
+            }
+            // writes into an index are writes into the relation it belongs to
+            let base = rel.name.split(':').next().unwrap_or_default();
+            relations.insert(SmartString::from(base));
+            match valid_at {
+                None => args.push(arg.to_string()),
+                Some(vld) => args.push(format!("{arg} @ {}", vld.0 .0)),
+            }
+        }
+        args.extend(options.iter().map(|(k, v)| format!("{k}: {v}")));
+        Ok(Self {
+            rule: format!("{name}({})", args.join(", ")),
+            relations,
+        })
+    }
+}
+
+/// The output of a fixed rule application, together with the versions of the stored
+/// relations it was computed from
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct CachedFixedRuleOutput {
+    rule: String,
+    versions: BTreeMap<String, u64>,
+    rows: Vec<Tuple>,
+}
+
+fn relation_version_key(name: &str) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from(RELATION_VERSION),
+        DataValue::from(name),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+fn cached_output_key(rule: &str) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from(FIXED_RULE_CACHE),
+        DataValue::from(rule),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl<'a> SessionTx<'a> {
+    /// How many transactions writing into the stored relation have been committed.
+    /// Persisted, so that cached outputs can be checked across restarts.
+    pub(crate) fn relation_version(&self, name: &str) -> Result<u64> {
+        Ok(
+            match self.store_tx.get(&relation_version_key(name), false)? {
+                None => 0,
+                Some(v) => u64::from_be_bytes(v.as_slice().try_into().unwrap_or_default()),
+            },
+        )
+    }
+    /// Called before committing the writes recorded in the transaction
+    pub(crate) fn bump_relation_versions(&mut self) -> Result<()> {
+        for name in self.relation_writes.keys() {
+            let version = self.relation_version(name)? + 1;
+            self.store_tx
+                .put(&relation_version_key(name), &version.to_be_bytes())?;
+        }
+        Ok(())
+    }
+    /// Use the cached output of a fixed rule application if the relations it reads are
+    /// unchanged, otherwise `run` it and queue the output to be cached
+    pub(crate) fn run_cached_fixed_rule(
+        &self,
+        spec: &FixedRuleCacheSpec,
+        run: impl FnOnce(&mut RegularTempStore) -> Result<()>,
+    ) -> Result<RegularTempStore> {
+        let mut out = RegularTempStore::default();
+        // the output would reflect writes that are not committed yet
+        if spec
+            .relations
+            .iter()
+            .any(|rel| self.relation_writes.contains_key(rel))
+        {
+            run(&mut out)?;
+            return Ok(out);
+        }
+        // masked columns make the output depend on the session
+        let rule = if self.unmask {
+            format!("{} unmasked", spec.rule)
+        } else {
+            spec.rule.clone()
+        };
+        let versions: BTreeMap<_, _> = spec
+            .relations
+            .iter()
+            .map(|rel| -> Result<_> { Ok((rel.to_string(), self.relation_version(rel)?)) })
+            .try_collect()?;
+        if let Some(found) = self.store_tx.get(&cached_output_key(&rule), false)? {
+            // an undecodable entry is treated as missing and overwritten
+            if let Ok(cached) = rmp_serde::from_slice::<CachedFixedRuleOutput>(&found) {
+                if cached.versions == versions {
+                    for row in cached.rows {
+                        out.put(row);
+                    }
+                    return Ok(out);
+                }
+            }
+        }
+        run(&mut out)?;
+        self.pending_fixed_rule_outputs
+            .lock()
+            .unwrap()
+            .push(CachedFixedRuleOutput {
+                rule,
+                versions,
+                rows: out.tuples().cloned().collect(),
+            });
+        Ok(out)
+    }
+    /// Keep the outputs of fixed rules computed in the transaction from being written into the
+    /// database, as immutable scripts must not write
+    pub(crate) fn discard_fixed_rule_outputs(&mut self) {
+        self.pending_fixed_rule_outputs = Default::default();
+    }
+    fn put_cached_fixed_rule_output(&mut self, output: &CachedFixedRuleOutput) -> Result<()> {
+        let mut val = vec![];
+        output
+            .serialize(&mut Serializer::new(&mut val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&cached_output_key(&output.rule), &val)
+    }
+    fn cached_fixed_rule_outputs(&self) -> Result<Vec<(Vec<u8>, CachedFixedRuleOutput)>> {
+        let lower = vec![
+            DataValue::Null,
+            DataValue::from(FIXED_RULE_CACHE),
+            DataValue::from(""),
+        ]
+        .encode_as_key(RelationId::SYSTEM);
+        let upper = vec![
+            DataValue::Null,
+            DataValue::from(FIXED_RULE_CACHE),
+            DataValue::Bot,
+        ]
+        .encode_as_key(RelationId::SYSTEM);
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k, v) = kv_res?;
+            if let Ok(output) = rmp_serde::from_slice(&v) {
+                ret.push((k, output));
+            }
+        }
+        Ok(ret)
+    }
+    /// One row for each cached output: the fixed rule application, the number of rows,
+    /// the relations read, and whether none of them has been written to since
+    pub(crate) fn list_fixed_rule_cache(&self) -> Result<NamedRows> {
+        let mut rows = vec![];
+        for (_, output) in self.cached_fixed_rule_outputs()? {
+            let mut fresh = true;
+            for (rel, version) in &output.versions {
+                fresh &= self.relation_version(rel)? == *version;
+            }
+            rows.push(vec![
+                DataValue::from(output.rule),
+                DataValue::from(output.rows.len() as i64),
+                DataValue::List(output.versions.into_keys().map(DataValue::from).collect()),
+                DataValue::from(fresh),
+            ]);
+        }
+        Ok(NamedRows::new(
+            vec![
+                "rule".to_string(),
+                "rows".to_string(),
+                "relations".to_string(),
+                "fresh".to_string(),
+            ],
+            rows,
+        ))
+    }
+    /// Remove all cached outputs, returning how many there were
+    pub(crate) fn drop_fixed_rule_cache(&mut self) -> Result<usize> {
+        let outputs = self.cached_fixed_rule_outputs()?;
+        for (k, _) in &outputs {
+            self.store_tx.del(k)?;
+        }
+        Ok(outputs.len())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Write the outputs of fixed rules computed by queries into the database,
+    /// unless the relations they were computed from have been written to in the meantime
+    pub(crate) fn flush_fixed_rule_cache(&'s self) {
+        let pending = mem::take(&mut *self.pending_fixed_rule_outputs.lock().unwrap());
+        if pending.is_empty() || self.lifecycle.is_read_only() {
+            return;
+        }
+        let res = (|| -> Result<()> {
+            let mut tx = self.transact_write()?;
+            for output in &pending {
+                let mut unchanged = true;
+                for (rel, version) in &output.versions {
+                    unchanged &= tx.relation_version(rel)? == *version;
+                }
+                if unchanged {
+                    tx.put_cached_fixed_rule_output(output)?;
+                }
+            }
+            tx.commit_tx()
+        })();
+        // the query has already succeeded, its output is simply not cached
+        if let Err(err) = res {
+            log::warn!("cannot cache the output of fixed rules: {err}");
+        }
+    }
+}
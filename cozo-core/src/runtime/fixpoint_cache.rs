@@ -0,0 +1,231 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Mutex;
+
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::program::{
+    InputProgram, MagicSymbol, NormalFormAtom, NormalFormRulesOrFixed, StratifiedNormalFormProgram,
+};
+use crate::query::optimize::is_deterministic;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// How many fixpoints are kept at most, the least recently computed is dropped first
+const MAX_ENTRIES: usize = 16;
+/// Fixpoints with more tuples than this in total are not kept
+const MAX_TUPLES: usize = 1_000_000;
+
+/// Fixpoints of recursive queries, shared by all clones of a database.
+///
+/// When a query is run again and the stored relations it reads have only gained rows since,
+/// its previous fixpoint is still part of the new one. Evaluation then resumes from it and
+/// semi-naive evaluation only propagates what the new rows derive.
+///
+/// Every commit writing into stored relations advances the epoch. Commits that may have
+/// removed or changed rows drop the fixpoints reading the affected relations.
+#[derive(Default)]
+pub(crate) struct FixpointCache {
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    epoch: u64,
+    /// Number of commits that have started but not yet advanced the epoch
+    committing: usize,
+    /// The last epoch at which rows may have been removed from or changed in a relation
+    invalidated_at: BTreeMap<SmartString<LazyCompact>, u64>,
+    entries: VecDeque<(String, CachedFixpoint)>,
+}
+
+struct CachedFixpoint {
+    epoch: u64,
+    relations: BTreeSet<SmartString<LazyCompact>>,
+    stores: BTreeMap<MagicSymbol, RegularTempStore>,
+}
+
+impl CachedFixpoint {
+    fn is_valid(&self, invalidated_at: &BTreeMap<SmartString<LazyCompact>, u64>) -> bool {
+        self.relations
+            .iter()
+            .all(|rel| match invalidated_at.get(rel) {
+                None => true,
+                Some(at) => *at <= self.epoch,
+            })
+    }
+}
+
+impl FixpointCache {
+    /// Start a transaction with `begin`, returning it together with the epoch its snapshot
+    /// corresponds to. The epoch is unknown if a commit happened while the transaction
+    /// was starting, in which case the transaction does not use the cache.
+    pub(crate) fn snapshot<T>(
+        &self,
+        begin: impl FnOnce() -> Result<T>,
+    ) -> Result<(Option<u64>, T)> {
+        let before = self.state.lock().unwrap().epoch;
+        let tx = begin()?;
+        let state = self.state.lock().unwrap();
+        let epoch = (state.epoch == before && state.committing == 0).then_some(before);
+        Ok((epoch, tx))
+    }
+    /// Commit a transaction with `commit`. `writes` maps the stored relations written
+    /// by the transaction to whether rows were only ever added to them.
+    pub(crate) fn commit(
+        &self,
+        writes: &BTreeMap<SmartString<LazyCompact>, bool>,
+        commit: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        if writes.is_empty() {
+            return commit();
+        }
+        self.state.lock().unwrap().committing += 1;
+        let res = commit();
+        let mut state = self.state.lock().unwrap();
+        state.committing -= 1;
+        state.epoch += 1;
+        let epoch = state.epoch;
+        for (rel, only_added) in writes {
+            // a failed commit may still have been partially applied
+            if !only_added || res.is_err() {
+                state.invalidated_at.insert(rel.clone(), epoch);
+            }
+        }
+        let CacheState {
+            invalidated_at,
+            entries,
+            ..
+        } = &mut *state;
+        entries.retain(|(_, entry)| entry.is_valid(invalidated_at));
+        res
+    }
+    /// Take out the fixpoint stored for `key`, if it can be used by a transaction
+    /// started at `epoch`
+    pub(crate) fn take(
+        &self,
+        key: &str,
+        epoch: u64,
+    ) -> Option<BTreeMap<MagicSymbol, RegularTempStore>> {
+        let mut state = self.state.lock().unwrap();
+        let pos = state
+            .entries
+            .iter()
+            .position(|(k, entry)| k == key && entry.epoch <= epoch)?;
+        state.entries.remove(pos).map(|(_, entry)| entry.stores)
+    }
+    /// Keep the fixpoint computed by a transaction started at `epoch`
+    pub(crate) fn put(
+        &self,
+        key: String,
+        epoch: u64,
+        relations: BTreeSet<SmartString<LazyCompact>>,
+        stores: BTreeMap<MagicSymbol, RegularTempStore>,
+    ) {
+        if stores.values().map(|s| s.len()).sum::<usize>() > MAX_TUPLES {
+            return;
+        }
+        let entry = CachedFixpoint {
+            epoch,
+            relations,
+            stores,
+        };
+        let mut state = self.state.lock().unwrap();
+        // rows may have been removed while the fixpoint was computed
+        if !entry.is_valid(&state.invalidated_at) {
+            return;
+        }
+        state.entries.retain(|(k, _)| *k != key);
+        if state.entries.len() >= MAX_ENTRIES {
+            state.entries.pop_front();
+        }
+        state.entries.push_back((key, entry));
+    }
+    /// Drop all fixpoints kept
+    pub(crate) fn clear(&self) {
+        self.state.lock().unwrap().entries.clear();
+    }
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+}
+
+/// Identifies a program across runs: parameters are already substituted as constants.
+/// Masked columns make the fixpoint depend on whether the session may `unmask`.
+pub(crate) fn fixpoint_key(program: &InputProgram, unmask: bool) -> String {
+    format!(
+        "{:?}{}{}{}",
+        program.prog,
+        program.disable_magic_rewrite,
+        program.force_magic_rewrite,
+        if unmask { " unmasked" } else { "" }
+    )
+}
+
+impl StratifiedNormalFormProgram {
+    /// The stored relations read by the program, if its fixpoint may be resumed after rows
+    /// are added to them.
+    ///
+    /// This requires a recursive program that is monotone in the relations it reads:
+    /// no negation, aggregation, fixed rules or index searches, no time travel or temp
+    /// relations, and no functions returning different results for the same arguments.
+    pub(crate) fn resumable_relations(&self) -> Option<BTreeSet<SmartString<LazyCompact>>> {
+        if self.recursive_rules().is_empty() {
+            return None;
+        }
+        let mut relations = BTreeSet::new();
+        for prog in &self.0 {
+            for rules in prog.prog.values() {
+                let rules = match rules {
+                    NormalFormRulesOrFixed::Rules { rules } => rules,
+                    NormalFormRulesOrFixed::Fixed { .. } => return None,
+                };
+                for rule in rules {
+                    if rule.aggr.iter().any(|a| a.is_some()) {
+                        return None;
+                    }
+                    for atom in &rule.body {
+                        match atom {
+                            NormalFormAtom::Rule(_) => {}
+                            NormalFormAtom::Relation(r) => {
+                                if r.valid_at.is_some()
+                                    || r.tx_at.is_some()
+                                    || r.name.is_temp_store_name()
+                                {
+                                    return None;
+                                }
+                                // indices change together with the relation they belong to
+                                let base = r.name.name.split(':').next().unwrap_or_default();
+                                relations.insert(SmartString::from(base));
+                            }
+                            NormalFormAtom::Predicate(expr) => {
+                                if !is_deterministic(expr) {
+                                    return None;
+                                }
+                            }
+                            NormalFormAtom::Unification(u) => {
+                                if !is_deterministic(&u.expr) {
+                                    return None;
+                                }
+                            }
+                            NormalFormAtom::NegatedRule(_)
+                            | NormalFormAtom::NegatedRelation(_)
+                            | NormalFormAtom::HnswSearch(_)
+                            | NormalFormAtom::FtsSearch(_)
+                            | NormalFormAtom::LshSearch(_) => return None,
+                        }
+                    }
+                }
+            }
+        }
+        Some(relations)
+    }
+}
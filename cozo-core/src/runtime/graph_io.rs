@@ -0,0 +1,691 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::sys::{GraphExportConfig, GraphFormat, GraphImportConfig};
+use crate::parse::SourceSpan;
+use crate::runtime::import::{csv_value, sanitize_name, unique_name};
+use crate::runtime::relation::InputRelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The relation {0} cannot be exported as a graph")]
+#[diagnostic(code(graph::not_an_edge_relation))]
+#[diagnostic(help("The first two columns of the relation must hold the nodes of each edge"))]
+struct NotAnEdgeRelation(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Malformed GraphML: {0}")]
+#[diagnostic(code(graph::bad_graphml))]
+struct BadGraphMl(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Line {0} of the edge list is not an edge")]
+#[diagnostic(code(graph::bad_edge_list))]
+#[diagnostic(help("Each line must hold two nodes, optionally followed by a numeric weight"))]
+struct BadEdgeList(usize);
+
+/// A graph as held in relations: nodes and edges, each followed by their attributes
+struct Graph {
+    node_attrs: Vec<ColumnDef>,
+    nodes: Vec<Tuple>,
+    edge_attrs: Vec<ColumnDef>,
+    edges: Vec<Tuple>,
+}
+
+/// The text of a value in a graph file, strings without their quotes
+fn text_of(val: &DataValue) -> String {
+    match val {
+        DataValue::Str(s) => s.to_string(),
+        v => v.to_string(),
+    }
+}
+
+fn column(name: &str, coltype: ColType, nullable: bool) -> ColumnDef {
+    ColumnDef {
+        name: SmartString::from(name),
+        typing: NullableColType { coltype, nullable },
+        default_gen: None,
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_dot(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Graph {
+    fn to_graphml(&self, directed: bool) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        for (prefix, target, attrs) in [
+            ("n", "node", &self.node_attrs),
+            ("e", "edge", &self.edge_attrs),
+        ] {
+            for (i, col) in attrs.iter().enumerate() {
+                let attr_type = match col.typing.coltype {
+                    ColType::Bool => "boolean",
+                    ColType::Int => "long",
+                    ColType::Float => "double",
+                    _ => "string",
+                };
+                writeln!(
+                    out,
+                    "  <key id=\"{prefix}{i}\" for=\"{target}\" attr.name=\"{}\" attr.type=\"{attr_type}\"/>",
+                    escape_xml(&col.name)
+                )
+                .unwrap();
+            }
+        }
+        let edge_default = if directed { "directed" } else { "undirected" };
+        writeln!(out, "  <graph id=\"G\" edgedefault=\"{edge_default}\">").unwrap();
+        let write_element =
+            |out: &mut String, tag: &str, ids: String, prefix: &str, attrs: &[DataValue]| {
+                if attrs.iter().all(|v| *v == DataValue::Null) {
+                    writeln!(out, "    <{tag} {ids}/>").unwrap();
+                    return;
+                }
+                writeln!(out, "    <{tag} {ids}>").unwrap();
+                for (i, val) in attrs.iter().enumerate() {
+                    if *val != DataValue::Null {
+                        writeln!(
+                            out,
+                            "      <data key=\"{prefix}{i}\">{}</data>",
+                            escape_xml(&text_of(val))
+                        )
+                        .unwrap();
+                    }
+                }
+                writeln!(out, "    </{tag}>").unwrap();
+            };
+        for node in &self.nodes {
+            let ids = format!("id=\"{}\"", escape_xml(&text_of(&node[0])));
+            write_element(&mut out, "node", ids, "n", &node[1..]);
+        }
+        for edge in &self.edges {
+            let ids = format!(
+                "source=\"{}\" target=\"{}\"",
+                escape_xml(&text_of(&edge[0])),
+                escape_xml(&text_of(&edge[1]))
+            );
+            write_element(&mut out, "edge", ids, "e", &edge[2..]);
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+    fn to_dot(&self, directed: bool) -> String {
+        let attrs = |cols: &[ColumnDef], vals: &[DataValue]| {
+            let attrs = cols
+                .iter()
+                .zip(vals)
+                .filter(|(_, val)| **val != DataValue::Null)
+                .map(|(col, val)| match val {
+                    DataValue::Num(n) => format!("{}={n}", escape_dot(&col.name)),
+                    val => format!("{}={}", escape_dot(&col.name), escape_dot(&text_of(val))),
+                })
+                .join(", ");
+            if attrs.is_empty() {
+                attrs
+            } else {
+                format!(" [{attrs}]")
+            }
+        };
+        let (keyword, arrow) = if directed {
+            ("digraph", "->")
+        } else {
+            ("graph", "--")
+        };
+        let mut out = format!("{keyword} {{\n");
+        for node in &self.nodes {
+            let node_attrs = attrs(&self.node_attrs, &node[1..]);
+            writeln!(out, "  {}{node_attrs};", escape_dot(&text_of(&node[0]))).unwrap();
+        }
+        for edge in &self.edges {
+            writeln!(
+                out,
+                "  {} {arrow} {}{};",
+                escape_dot(&text_of(&edge[0])),
+                escape_dot(&text_of(&edge[1])),
+                attrs(&self.edge_attrs, &edge[2..])
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+    /// In the JSON Graph Format, with the attributes as metadata
+    fn to_json(&self, directed: bool) -> String {
+        let metadata = |cols: &[ColumnDef], vals: &[DataValue]| {
+            let map: serde_json::Map<_, _> = cols
+                .iter()
+                .zip(vals)
+                .filter(|(_, val)| **val != DataValue::Null)
+                .map(|(col, val)| (col.name.to_string(), JsonValue::from(val.clone())))
+                .collect();
+            JsonValue::Object(map)
+        };
+        let nodes: serde_json::Map<_, _> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let attrs = serde_json::json!({"metadata": metadata(&self.node_attrs, &node[1..])});
+                (text_of(&node[0]), attrs)
+            })
+            .collect();
+        let edges = self
+            .edges
+            .iter()
+            .map(|edge| {
+                serde_json::json!({
+                    "source": text_of(&edge[0]),
+                    "target": text_of(&edge[1]),
+                    "metadata": metadata(&self.edge_attrs, &edge[2..])
+                })
+            })
+            .collect_vec();
+        let graph = serde_json::json!({
+            "graph": {"directed": directed, "nodes": nodes, "edges": edges}
+        });
+        serde_json::to_string_pretty(&graph).unwrap()
+    }
+    fn to_edge_list(&self) -> String {
+        let mut out = String::new();
+        for edge in &self.edges {
+            out.push_str(&edge.iter().map(text_of).join("\t"));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+enum XmlEvent<'a> {
+    Start {
+        name: &'a str,
+        attrs: BTreeMap<&'a str, String>,
+        empty: bool,
+    },
+    End(&'a str),
+    Text(String),
+}
+
+fn unescape_xml(s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        let end = rest
+            .find(';')
+            .ok_or_else(|| BadGraphMl("unterminated entity".to_string()))?;
+        let entity = &rest[..end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+            }
+            .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => out.push(c),
+            None => bail!(BadGraphMl(format!("unknown entity &{entity};"))),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Just enough of XML for GraphML: elements, their attributes, text and CDATA sections.
+/// Element names lose their namespace prefix, and declarations and comments are skipped.
+fn xml_events(src: &str) -> Result<Vec<XmlEvent<'_>>> {
+    let unterminated = || BadGraphMl("unterminated markup".to_string());
+    let mut events = vec![];
+    let mut rest = src;
+    while let Some(pos) = rest.find('<') {
+        if pos > 0 {
+            events.push(XmlEvent::Text(unescape_xml(&rest[..pos])?));
+        }
+        rest = &rest[pos..];
+        if let Some(body) = rest.strip_prefix("<!--") {
+            let end = body.find("-->").ok_or_else(unterminated)?;
+            rest = &body[end + 3..];
+        } else if let Some(body) = rest.strip_prefix("<![CDATA[") {
+            let end = body.find("]]>").ok_or_else(unterminated)?;
+            events.push(XmlEvent::Text(body[..end].to_string()));
+            rest = &body[end + 3..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            let end = rest.find('>').ok_or_else(unterminated)?;
+            rest = &rest[end + 1..];
+        } else if let Some(body) = rest.strip_prefix("</") {
+            let end = body.find('>').ok_or_else(unterminated)?;
+            let name = body[..end].trim();
+            events.push(XmlEvent::End(name.rsplit(':').next().unwrap()));
+            rest = &body[end + 1..];
+        } else {
+            let body = &rest[1..];
+            let mut quote = None;
+            let end = body
+                .find(|c: char| match quote {
+                    Some(q) => {
+                        if c == q {
+                            quote = None;
+                        }
+                        false
+                    }
+                    None if c == '"' || c == '\'' => {
+                        quote = Some(c);
+                        false
+                    }
+                    None => c == '>',
+                })
+                .ok_or_else(unterminated)?;
+            let (tag, empty) = match body[..end].strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (&body[..end], false),
+            };
+            let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+            let name = tag[..name_end].rsplit(':').next().unwrap();
+            let mut attrs = BTreeMap::new();
+            let mut attr_src = tag[name_end..].trim_start();
+            while !attr_src.is_empty() {
+                let (attr, val) = attr_src
+                    .split_once('=')
+                    .ok_or_else(|| BadGraphMl(format!("bad attributes of <{name}>")))?;
+                let val = val.trim_start();
+                let q = val
+                    .chars()
+                    .next()
+                    .filter(|c| *c == '"' || *c == '\'')
+                    .ok_or_else(|| BadGraphMl(format!("unquoted attribute of <{name}>")))?;
+                let val_end = val[1..].find(q).ok_or_else(unterminated)? + 1;
+                attrs.insert(attr.trim(), unescape_xml(&val[1..val_end])?);
+                attr_src = val[val_end + 1..].trim_start();
+            }
+            events.push(XmlEvent::Start { name, attrs, empty });
+            rest = &body[end + 1..];
+        }
+    }
+    Ok(events)
+}
+
+/// An attribute declared by a `<key>` of GraphML
+struct GraphMlKey {
+    name: String,
+    target: String,
+    coltype: ColType,
+    default: Option<String>,
+}
+
+fn graphml_value(text: &str, coltype: &ColType) -> Result<DataValue> {
+    let text = text.trim();
+    let val = match coltype {
+        ColType::Int => text.parse::<i64>().ok().map(DataValue::from),
+        ColType::Float => text.parse::<f64>().ok().map(DataValue::from),
+        ColType::Bool => match text.to_lowercase().as_str() {
+            "true" | "1" => Some(DataValue::from(true)),
+            "false" | "0" => Some(DataValue::from(false)),
+            _ => None,
+        },
+        _ => Some(DataValue::from(text)),
+    };
+    val.ok_or_else(|| BadGraphMl(format!("cannot read {text:?} as {coltype:?}")).into())
+}
+
+fn parse_graphml(src: &str) -> Result<Graph> {
+    let mut keys: BTreeMap<String, GraphMlKey> = BTreeMap::new();
+    let mut key_order = vec![];
+    // the nodes and edges being read, with their data by key
+    let mut open: Vec<(bool, Vec<DataValue>, BTreeMap<String, String>)> = vec![];
+    let mut nodes = vec![];
+    let mut edges = vec![];
+    let mut current_key = None;
+    let mut capture: Option<(Option<String>, String)> = None;
+    for event in xml_events(src)? {
+        match event {
+            XmlEvent::Start {
+                name,
+                mut attrs,
+                empty,
+            } => match name {
+                "key" => {
+                    let id = attrs
+                        .remove("id")
+                        .ok_or_else(|| BadGraphMl("a key has no id".to_string()))?;
+                    let coltype = match attrs.get("attr.type").map(|t| t.as_str()) {
+                        Some("boolean") => ColType::Bool,
+                        Some("int" | "long") => ColType::Int,
+                        Some("float" | "double") => ColType::Float,
+                        _ => ColType::String,
+                    };
+                    keys.insert(
+                        id.clone(),
+                        GraphMlKey {
+                            name: attrs.remove("attr.name").unwrap_or_else(|| id.clone()),
+                            target: attrs.remove("for").unwrap_or_else(|| "all".to_string()),
+                            coltype,
+                            default: None,
+                        },
+                    );
+                    key_order.push(id.clone());
+                    if !empty {
+                        current_key = Some(id);
+                    }
+                }
+                "default" if !empty => capture = Some((None, String::new())),
+                "node" | "edge" => {
+                    let ends = if name == "node" {
+                        vec!["id"]
+                    } else {
+                        vec!["source", "target"]
+                    };
+                    let ends: Vec<DataValue> = ends
+                        .into_iter()
+                        .map(|attr| {
+                            attrs
+                                .remove(attr)
+                                .map(DataValue::from)
+                                .ok_or_else(|| BadGraphMl(format!("a {name} has no {attr}")))
+                        })
+                        .try_collect()?;
+                    let is_node = name == "node";
+                    if empty {
+                        if is_node {
+                            nodes.push((ends, BTreeMap::new()));
+                        } else {
+                            edges.push((ends, BTreeMap::new()));
+                        }
+                    } else {
+                        open.push((is_node, ends, BTreeMap::new()));
+                    }
+                }
+                "data" if !empty => {
+                    let key = attrs
+                        .remove("key")
+                        .ok_or_else(|| BadGraphMl("a data has no key".to_string()))?;
+                    capture = Some((Some(key), String::new()));
+                }
+                _ => {}
+            },
+            XmlEvent::Text(text) => {
+                if let Some((_, captured)) = &mut capture {
+                    captured.push_str(&text);
+                }
+            }
+            XmlEvent::End(name) => match name {
+                "key" => current_key = None,
+                "default" => {
+                    if let (Some(id), Some((_, text))) = (&current_key, capture.take()) {
+                        keys.get_mut(id).unwrap().default = Some(text);
+                    }
+                }
+                "data" => {
+                    if let (Some((_, _, data)), Some((Some(key), text))) =
+                        (open.last_mut(), capture.take())
+                    {
+                        data.insert(key, text);
+                    }
+                }
+                "node" | "edge" => {
+                    let (is_node, ends, data) = open
+                        .pop()
+                        .ok_or_else(|| BadGraphMl(format!("unexpected </{name}>")))?;
+                    if is_node {
+                        nodes.push((ends, data));
+                    } else {
+                        edges.push((ends, data));
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    let build = |target: &str,
+                 ends: &[&str],
+                 elements: Vec<(Vec<DataValue>, BTreeMap<String, String>)>|
+     -> Result<(Vec<ColumnDef>, Vec<Tuple>)> {
+        let mut taken = ends.iter().map(|s| s.to_string()).collect();
+        let used = key_order
+            .iter()
+            .filter(|id| {
+                let key = &keys[*id];
+                key.target == target || key.target == "all"
+            })
+            .collect_vec();
+        let cols = used
+            .iter()
+            .map(|id| {
+                let key = &keys[*id];
+                let name = unique_name(sanitize_name(&key.name, 'c'), &mut taken);
+                column(&name, key.coltype.clone(), true)
+            })
+            .collect_vec();
+        let mut rows = vec![];
+        for (mut row, mut data) in elements {
+            for id in &used {
+                let key = &keys[*id];
+                let val = match data.remove(*id).or_else(|| key.default.clone()) {
+                    None => DataValue::Null,
+                    Some(text) => graphml_value(&text, &key.coltype)?,
+                };
+                row.push(val);
+            }
+            rows.push(row);
+        }
+        Ok((cols, rows))
+    };
+    let (node_attrs, nodes) = build("node", &["id"], nodes)?;
+    let (edge_attrs, edges) = build("edge", &["source", "target"], edges)?;
+    Ok(Graph {
+        node_attrs,
+        nodes,
+        edge_attrs,
+        edges,
+    })
+}
+
+fn parse_edge_list(src: &str) -> Result<Graph> {
+    let mut edges = vec![];
+    let mut weighted = false;
+    for (idx, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('%') {
+            continue;
+        }
+        let fields = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|f| !f.is_empty())
+            .collect_vec();
+        let weight = match fields.len() {
+            2 => DataValue::Null,
+            3 => match csv_value(fields[2]) {
+                DataValue::Num(n) => DataValue::from(n.get_float()),
+                _ => bail!(BadEdgeList(idx + 1)),
+            },
+            _ => bail!(BadEdgeList(idx + 1)),
+        };
+        weighted |= weight != DataValue::Null;
+        edges.push(vec![csv_value(fields[0]), csv_value(fields[1]), weight]);
+    }
+    if !weighted {
+        for edge in edges.iter_mut() {
+            edge.pop();
+        }
+    }
+    let nodes: BTreeSet<_> = edges.iter().flat_map(|e| e[..2].iter().cloned()).collect();
+    Ok(Graph {
+        node_attrs: vec![],
+        nodes: nodes.into_iter().map(|n| vec![n]).collect(),
+        edge_attrs: if weighted {
+            vec![column("weight", ColType::Float, true)]
+        } else {
+            vec![]
+        },
+        edges,
+    })
+}
+
+impl<'a> SessionTx<'a> {
+    /// Write the relation of edges, with the attributes in the relation of nodes if given,
+    /// into a graph file
+    pub(crate) fn export_graph(&self, config: &GraphExportConfig) -> Result<NamedRows> {
+        let columns = |name: &str| -> Result<Vec<ColumnDef>> {
+            let meta = self.get_relation(name, false)?.metadata;
+            Ok(meta.keys.into_iter().chain(meta.non_keys).collect())
+        };
+        let mut edge_attrs = columns(&config.relation)?;
+        ensure!(
+            edge_attrs.len() >= 2,
+            NotAnEdgeRelation(config.relation.to_string())
+        );
+        edge_attrs.drain(..2);
+        let edges: Vec<Tuple> = self
+            .get_relation_for_read(&config.relation)?
+            .scan_all(self)
+            .try_collect()?;
+
+        let mut node_attrs = vec![];
+        let mut nodes: BTreeMap<DataValue, Tuple> = BTreeMap::new();
+        if let Some(name) = &config.nodes {
+            node_attrs = columns(name)?.split_off(1);
+            for tuple in self.get_relation_for_read(name)?.scan_all(self) {
+                let tuple = tuple?;
+                nodes.insert(tuple[0].clone(), tuple);
+            }
+        }
+        for edge in &edges {
+            for node in &edge[..2] {
+                if !nodes.contains_key(node) {
+                    let mut row = vec![node.clone()];
+                    row.resize(node_attrs.len() + 1, DataValue::Null);
+                    nodes.insert(node.clone(), row);
+                }
+            }
+        }
+
+        let graph = Graph {
+            node_attrs,
+            nodes: nodes.into_values().collect(),
+            edge_attrs,
+            edges,
+        };
+        let text = match config.format {
+            GraphFormat::GraphMl => graph.to_graphml(config.directed),
+            GraphFormat::Dot => graph.to_dot(config.directed),
+            GraphFormat::Json => graph.to_json(config.directed),
+            GraphFormat::EdgeList => graph.to_edge_list(),
+        };
+        fs::write(&config.path, text)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("cannot write {}", config.path))?;
+        Ok(NamedRows::new(
+            vec!["path".to_string(), "nodes".to_string(), "edges".to_string()],
+            vec![vec![
+                DataValue::from(config.path.as_str()),
+                DataValue::from(graph.nodes.len() as i64),
+                DataValue::from(graph.edges.len() as i64),
+            ]],
+        ))
+    }
+    /// Create the relation of edges from a graph file, and the relation of nodes if asked to.
+    /// Edges repeated between the same nodes keep the attributes of the last one.
+    pub(crate) fn import_graph(&mut self, config: &GraphImportConfig) -> Result<NamedRows> {
+        let src = fs::read_to_string(&config.path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("cannot read {}", config.path))?;
+        let (graph, node_type) = match config.format {
+            GraphFormat::GraphMl => (parse_graphml(&src)?, ColType::String),
+            _ => (parse_edge_list(&src)?, ColType::Any),
+        };
+        let mut rows = vec![];
+        let loaded = self.create_and_load(
+            &config.relation,
+            vec![
+                column("source", node_type.clone(), false),
+                column("target", node_type.clone(), false),
+            ],
+            graph.edge_attrs,
+            graph.edges,
+        )?;
+        rows.push(vec![
+            DataValue::from(&config.relation as &str),
+            DataValue::from(loaded as i64),
+        ]);
+        if let Some(name) = &config.nodes {
+            let loaded = self.create_and_load(
+                name,
+                vec![column("id", node_type, false)],
+                graph.node_attrs,
+                graph.nodes,
+            )?;
+            rows.push(vec![
+                DataValue::from(name as &str),
+                DataValue::from(loaded as i64),
+            ]);
+        }
+        Ok(NamedRows::new(
+            vec!["relation".to_string(), "rows".to_string()],
+            rows,
+        ))
+    }
+    fn create_and_load(
+        &mut self,
+        name: &str,
+        keys: Vec<ColumnDef>,
+        non_keys: Vec<ColumnDef>,
+        rows: Vec<Tuple>,
+    ) -> Result<usize> {
+        let bindings = |cols: &[ColumnDef]| {
+            cols.iter()
+                .map(|c| Symbol::new(c.name.clone(), SourceSpan(0, 0)))
+                .collect_vec()
+        };
+        let handle = self.create_relation(InputRelationHandle {
+            name: Symbol::new(name, SourceSpan(0, 0)),
+            key_bindings: bindings(&keys),
+            dep_bindings: bindings(&non_keys),
+            metadata: StoredRelationMetadata { keys, non_keys },
+            expected_version: None,
+            span: SourceSpan(0, 0),
+        })?;
+        let mut written = BTreeSet::new();
+        for row in rows {
+            let key = handle.encode_key_for_store(&row, SourceSpan(0, 0))?;
+            let val = handle.encode_val_for_store(&row, SourceSpan(0, 0))?;
+            self.store_tx.put(&key, &val)?;
+            written.insert(key);
+        }
+        self.record_relation_write(name, true);
+        Ok(written.len())
+    }
+}
@@ -0,0 +1,326 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt::{Display, Formatter};
+use std::mem;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::relation::ColType;
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::{DataValue, ValidityTs};
+use crate::runtime::callback::{CallbackCollector, CallbackOp};
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+pub(crate) const MICROS_PER_DAY: f64 = 86_400_000_000.;
+
+/// Retention policy for the history of a relation keyed by `Validity`.
+///
+/// Both limits may be given at the same time, in which case a version is kept only
+/// if it satisfies both of them.
+#[derive(
+    Debug, Clone, Eq, PartialEq, Default, serde_derive::Serialize, serde_derive::Deserialize,
+)]
+pub(crate) struct RetentionPolicy {
+    /// Keep at most this many versions for each key
+    pub(crate) keep_versions: Option<usize>,
+    /// Drop versions that were superseded longer ago than this, in microseconds
+    pub(crate) keep_micros: Option<i64>,
+    /// A stored relation the dropped versions are put into
+    #[serde(default)]
+    pub(crate) expire_to: Option<SmartString<LazyCompact>>,
+    /// Whether the dropped versions are sent to the callbacks registered on the relation,
+    /// as removals
+    #[serde(default)]
+    pub(crate) notify: bool,
+}
+
+impl RetentionPolicy {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keep_versions.is_none() && self.keep_micros.is_none()
+    }
+}
+
+impl Display for RetentionPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some(n) = self.keep_versions {
+            parts.push(format!("keep_versions: {n}"));
+        }
+        if let Some(m) = self.keep_micros {
+            parts.push(format!("keep_days: {}", m as f64 / MICROS_PER_DAY));
+        }
+        if let Some(rel) = &self.expire_to {
+            parts.push(format!("expire_to: '{rel}'"));
+        }
+        if self.notify {
+            parts.push("notify: true".to_string());
+        }
+        write!(f, "{{{}}}", parts.join(", "))
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation '{0}' does not keep history: its last key column is not of type Validity")]
+#[diagnostic(code(tx::no_history))]
+pub(crate) struct RelationHasNoHistory(pub(crate) String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("No retention policy is set for relation '{0}'")]
+#[diagnostic(code(tx::no_retention_policy))]
+#[diagnostic(help("Set one with `::history retention` or give the limits explicitly"))]
+pub(crate) struct NoRetentionPolicy(pub(crate) String);
+
+/// Statistics returned from pruning the history of a single relation
+#[derive(Debug, Default, Clone)]
+pub(crate) struct PruneStats {
+    pub(crate) scanned: usize,
+    pub(crate) pruned: usize,
+    /// The versions pruned, kept if the policy asks for them to be notified
+    pub(crate) expired: Vec<Tuple>,
+}
+
+impl PruneStats {
+    /// Queue the expired versions for the callbacks registered on the relation,
+    /// in the shape of a removal of these rows
+    pub(crate) fn notify_expired(
+        &mut self,
+        handle: &RelationHandle,
+        collector: &mut CallbackCollector,
+    ) {
+        if self.expired.is_empty() {
+            return;
+        }
+        let n_keys = handle.metadata.keys.len();
+        let key_names = handle
+            .metadata
+            .keys
+            .iter()
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        let all_names = key_names
+            .iter()
+            .cloned()
+            .chain(
+                handle
+                    .metadata
+                    .non_keys
+                    .iter()
+                    .map(|col| col.name.to_string()),
+            )
+            .collect_vec();
+        let expired = mem::take(&mut self.expired);
+        let keys = expired.iter().map(|t| t[..n_keys].to_vec()).collect_vec();
+        collector.entry(handle.name.clone()).or_default().push((
+            CallbackOp::Rm,
+            NamedRows::new(key_names, keys),
+            NamedRows::new(all_names, expired),
+        ));
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Rows of bitemporal relation '{0}' cannot be modified once recorded")]
+#[diagnostic(code(tx::bitemporal_immutable))]
+#[diagnostic(help("Assert or retract a new version with a different validity instead"))]
+pub(crate) struct BitemporalRowImmutable(pub(crate) String);
+
+impl RelationHandle {
+    pub(crate) fn has_history(&self) -> bool {
+        matches!(
+            self.metadata.keys.last(),
+            Some(col) if col.typing.coltype == ColType::Validity
+        )
+    }
+    /// Position in the stored tuple of the column recording transaction time, if any
+    pub(crate) fn tx_time_col(&self) -> Option<usize> {
+        self.metadata
+            .non_keys
+            .iter()
+            .position(|col| col.typing.coltype == ColType::TxTime)
+            .map(|i| i + self.metadata.keys.len())
+    }
+    /// Whether recorded versions are immutable: the stored relation keeps history
+    /// and also records transaction time
+    pub(crate) fn is_bitemporal(&self) -> bool {
+        !self.is_temp && self.has_history() && self.tx_time_col().is_some()
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    pub(crate) fn set_retention_policy(
+        &mut self,
+        rel: &Symbol,
+        policy: Option<RetentionPolicy>,
+    ) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        if meta.is_temp {
+            bail!("Cannot set retention policy for temp store")
+        }
+        if !meta.has_history() {
+            bail!(RelationHasNoHistory(meta.name.to_string()))
+        }
+        if meta.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "set retention policy".to_string(),
+                meta.access_level
+            ))
+        }
+        meta.retention = policy.filter(|p| !p.is_empty());
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
+
+    /// Delete the versions of the rows in `handle` that fall outside `policy`.
+    ///
+    /// Rows are grouped by all key columns except the trailing validity, and each group
+    /// is visited from the newest version to the oldest. When a time limit is given,
+    /// the newest version older than the cutoff is kept if it is an assertion, so that
+    /// time travel to any point within the retained window still sees the right state.
+    pub(crate) fn prune_history(
+        &mut self,
+        handle: &RelationHandle,
+        policy: &RetentionPolicy,
+        now: ValidityTs,
+    ) -> Result<PruneStats> {
+        if handle.is_temp {
+            bail!("Cannot prune history of temp store")
+        }
+        if !handle.has_history() {
+            bail!(RelationHasNoHistory(handle.name.to_string()))
+        }
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "history pruning".to_string(),
+                handle.access_level
+            ))
+        }
+        if !handle.hnsw_indices.is_empty()
+            || !handle.fts_indices.is_empty()
+            || !handle.lsh_indices.is_empty()
+        {
+            bail!(
+                "Cannot prune history of relation '{}' with vector, FTS or LSH indices attached",
+                handle.name
+            )
+        }
+
+        let archive = match &policy.expire_to {
+            None => None,
+            Some(name) => {
+                let archive = self.get_relation(name, false)?;
+                ensure!(
+                    !archive.is_temp && archive.arity() == handle.arity(),
+                    "Relation '{}' cannot receive the expired rows of '{}': it must be a stored \
+                    relation with the same number of columns",
+                    archive.name,
+                    handle.name
+                );
+                ensure!(
+                    archive.hnsw_indices.is_empty()
+                        && archive.fts_indices.is_empty()
+                        && archive.lsh_indices.is_empty(),
+                    "Relation '{}' cannot receive expired rows, as it has vector, FTS or LSH indices",
+                    archive.name
+                );
+                if archive.access_level < AccessLevel::Protected {
+                    bail!(InsufficientAccessLevel(
+                        archive.name.to_string(),
+                        "receiving expired rows".to_string(),
+                        archive.access_level
+                    ))
+                }
+                self.record_relation_write(&archive.name, true);
+                Some(archive)
+            }
+        };
+
+        self.record_relation_write(&handle.name, false);
+        let n_keys = handle.metadata.keys.len();
+        let cutoff = policy.keep_micros.map(|m| now.0 .0.saturating_sub(m));
+        let mut stats = PruneStats::default();
+        let mut to_delete = vec![];
+
+        let mut cur_prefix: Option<Vec<DataValue>> = None;
+        let mut seen_in_group = 0;
+        let mut anchored = false;
+        for tuple in handle.scan_all(self) {
+            let tuple = tuple?;
+            stats.scanned += 1;
+            let prefix = &tuple[..n_keys - 1];
+            if cur_prefix.as_deref() != Some(prefix) {
+                cur_prefix = Some(prefix.to_vec());
+                seen_in_group = 0;
+                anchored = false;
+            }
+            let vld = match &tuple[n_keys - 1] {
+                DataValue::Validity(v) => *v,
+                v => bail!("Bad validity value {:?} in relation '{}'", v, handle.name),
+            };
+            seen_in_group += 1;
+
+            let mut keep = match policy.keep_versions {
+                Some(n) => seen_in_group <= n,
+                None => true,
+            };
+            if keep {
+                if let Some(cutoff) = cutoff {
+                    if vld.timestamp.0 .0 < cutoff {
+                        keep = !anchored && vld.is_assert.0;
+                        anchored = true;
+                    }
+                }
+            }
+            if !keep {
+                to_delete.push(tuple);
+            }
+        }
+
+        for tuple in to_delete {
+            for (idx_rel, extractor) in handle.indices.values() {
+                let idx_tup = extractor.iter().map(|i| tuple[*i].clone()).collect_vec();
+                let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                self.store_tx.del(&encoded)?;
+            }
+            let key = handle.encode_key_for_store(&tuple, Default::default())?;
+            self.store_tx.del(&key)?;
+            stats.pruned += 1;
+            if let Some(archive) = &archive {
+                let key = archive.encode_key_for_store(&tuple, Default::default())?;
+                let val = archive.encode_val_for_store(&tuple, Default::default())?;
+                self.store_tx.put(&key, &val)?;
+                for (idx_rel, extractor) in archive.indices.values() {
+                    let idx_tup = extractor.iter().map(|i| tuple[*i].clone()).collect_vec();
+                    let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                    self.store_tx.put(&encoded, &[])?;
+                }
+            }
+            if policy.notify {
+                stats.expired.push(tuple);
+            }
+        }
+
+        Ok(stats)
+    }
+}
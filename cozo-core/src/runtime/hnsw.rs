@@ -6,17 +6,19 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use crate::data::distance;
 use crate::data::expr::{eval_bytecode_pred, Bytecode};
 use crate::data::program::HnswSearch;
 use crate::data::relation::VecElementType;
 use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN};
 use crate::data::value::Vector;
 use crate::parse::sys::HnswDistance;
+use crate::runtime::degraded::{compile_index_expr, InconsistentIndex};
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, SourceSpan};
 use itertools::Itertools;
-use miette::{bail, miette, Result};
+use miette::{bail, Result};
 use ordered_float::OrderedFloat;
 use priority_queue::PriorityQueue;
 use rand::Rng;
@@ -57,6 +59,8 @@ type CompoundKey = (Tuple, usize, i32);
 struct VectorCache {
     cache: FxHashMap<CompoundKey, Vector>,
     distance: HnswDistance,
+    /// The name of the index, for reporting it inconsistent
+    index: SmartString<LazyCompact>,
 }
 
 impl VectorCache {
@@ -64,48 +68,17 @@ impl VectorCache {
         self.cache.insert(k, v);
     }
     fn dist(&self, v1: &Vector, v2: &Vector) -> f64 {
-        match self.distance {
-            HnswDistance::L2 => match (v1, v2) {
-                (Vector::F32(a), Vector::F32(b)) => {
-                    let diff = a - b;
-                    diff.dot(&diff) as f64
-                }
-                (Vector::F64(a), Vector::F64(b)) => {
-                    let diff = a - b;
-                    diff.dot(&diff)
-                }
-                _ => panic!("Cannot compute L2 distance between {:?} and {:?}", v1, v2),
-            },
-            HnswDistance::Cosine => match (v1, v2) {
-                (Vector::F32(a), Vector::F32(b)) => {
-                    let a_norm = a.dot(a) as f64;
-                    let b_norm = b.dot(b) as f64;
-                    let dot = a.dot(b) as f64;
-                    1.0 - dot / (a_norm * b_norm).sqrt()
-                }
-                (Vector::F64(a), Vector::F64(b)) => {
-                    let a_norm = a.dot(a);
-                    let b_norm = b.dot(b);
-                    let dot = a.dot(b);
-                    1.0 - dot / (a_norm * b_norm).sqrt()
-                }
-                _ => panic!(
-                    "Cannot compute cosine distance between {:?} and {:?}",
-                    v1, v2
-                ),
-            },
-            HnswDistance::InnerProduct => match (v1, v2) {
-                (Vector::F32(a), Vector::F32(b)) => {
-                    let dot = a.dot(b);
-                    1. - dot as f64
-                }
-                (Vector::F64(a), Vector::F64(b)) => {
-                    let dot = a.dot(b);
-                    1. - dot
-                }
-                _ => panic!("Cannot compute inner product between {:?} and {:?}", v1, v2),
-            },
-        }
+        let d = match self.distance {
+            HnswDistance::L2 => distance::l2_dist(v1, v2),
+            HnswDistance::Cosine => distance::cos_dist(v1, v2),
+            HnswDistance::InnerProduct => distance::ip_dist(v1, v2),
+        };
+        d.unwrap_or_else(|| {
+            panic!(
+                "Cannot compute {:?} distance between {:?} and {:?}",
+                self.distance, v1, v2
+            )
+        })
     }
     fn v_dist(&self, v: &Vector, key: &CompoundKey) -> f64 {
         let v2 = self.cache.get(key).unwrap();
@@ -144,7 +117,10 @@ impl VectorCache {
                         _ => bail!("Cannot interpret {} as vector", field),
                     }
                 }
-                None => bail!("Cannot find compound key for HNSW: {:?}", key),
+                None => bail!(InconsistentIndex(
+                    self.index.to_string(),
+                    format!("cannot find compound key {:?} in the relation", key)
+                )),
             }
         }
         Ok(())
@@ -710,6 +686,7 @@ impl<'a> SessionTx<'a> {
         let mut vec_cache = VectorCache {
             cache: FxHashMap::default(),
             distance: manifest.distance,
+            index: idx_table.name.clone(),
         };
         for (vec, idx, sub) in extracted_vectors {
             self.hnsw_put_vector(
@@ -883,9 +860,14 @@ impl<'a> SessionTx<'a> {
             (Vector::F64(v), VecElementType::F32) => Vector::F32(v.mapv(|x| x as f32)),
         };
 
+        if config.scan_base {
+            return self.hnsw_knn_by_scan(q, config, filter_bytecode, stack);
+        }
+
         let mut vec_cache = VectorCache {
             cache: Default::default(),
             distance: config.manifest.distance,
+            index: config.idx_handle.name.clone(),
         };
 
         let ep_res = config
@@ -955,44 +937,13 @@ impl<'a> SessionTx<'a> {
                     }
                 }
 
-                let mut cand_tuple = config
-                    .base_handle
-                    .get(self, &cand_key.0)?
-                    .ok_or_else(|| miette!("corrupted index"))?;
-
-                // make sure the order is the same as in all_bindings()!!!
-                if config.bind_field.is_some() {
-                    let field = if cand_key.1 < config.base_handle.metadata.keys.len() {
-                        config.base_handle.metadata.keys[cand_key.1].name.clone()
-                    } else {
-                        config.base_handle.metadata.non_keys
-                            [cand_key.1 - config.base_handle.metadata.keys.len()]
-                        .name
-                        .clone()
-                    };
-                    cand_tuple.push(DataValue::Str(field));
-                }
-                if config.bind_field_idx.is_some() {
-                    cand_tuple.push(if cand_key.2 < 0 {
-                        DataValue::Null
-                    } else {
-                        DataValue::from(cand_key.2 as i64)
-                    });
-                }
-                if config.bind_distance.is_some() {
-                    cand_tuple.push(DataValue::from(distance));
-                }
-                if config.bind_vector.is_some() {
-                    let vec = if cand_key.2 < 0 {
-                        cand_tuple[cand_key.1].clone()
-                    } else {
-                        match &cand_tuple[cand_key.1] {
-                            DataValue::List(v) => v[cand_key.2 as usize].clone(),
-                            v => bail!("corrupted index value {:?}", v),
-                        }
-                    };
-                    cand_tuple.push(vec);
-                }
+                let cand_tuple = config.base_handle.get(self, &cand_key.0)?.ok_or_else(|| {
+                    InconsistentIndex(
+                        config.idx_handle.name.to_string(),
+                        format!("cannot find the indexed row {:?}", cand_key.0),
+                    )
+                })?;
+                let cand_tuple = hnsw_result_tuple(config, cand_tuple, &cand_key, distance)?;
 
                 if let Some((code, span)) = filter_bytecode {
                     if !eval_bytecode_pred(code, &cand_tuple, stack, *span)? {
@@ -1010,6 +961,124 @@ impl<'a> SessionTx<'a> {
             Ok(vec![])
         }
     }
+
+    /// Search by computing the distances to all vectors in the relation, for an index
+    /// that cannot be read
+    fn hnsw_knn_by_scan(
+        &self,
+        q: Vector,
+        config: &HnswSearch,
+        filter_bytecode: &Option<(Vec<Bytecode>, SourceSpan)>,
+        stack: &mut Vec<DataValue>,
+    ) -> Result<Vec<Tuple>> {
+        let index_filter = match &config.manifest.index_filter {
+            None => None,
+            Some(code) => Some(compile_index_expr(&config.base_handle, code)?),
+        };
+        let vec_cache = VectorCache {
+            cache: Default::default(),
+            distance: config.manifest.distance,
+            index: config.idx_handle.name.clone(),
+        };
+        let mut found = vec![];
+        for tuple in config.base_handle.scan_all(self) {
+            let tuple = tuple?;
+            if let Some(code) = &index_filter {
+                if !eval_bytecode_pred(code, &tuple, stack, Default::default())? {
+                    continue;
+                }
+            }
+            for idx in &config.manifest.vec_fields {
+                match &tuple[*idx] {
+                    DataValue::Vec(v) => {
+                        let distance = vec_cache.dist(&q, v);
+                        found.push((OrderedFloat(distance), (tuple.clone(), *idx, -1)));
+                    }
+                    DataValue::List(l) => {
+                        for (sidx, v) in l.iter().enumerate() {
+                            if let DataValue::Vec(v) = v {
+                                let distance = vec_cache.dist(&q, v);
+                                found.push((
+                                    OrderedFloat(distance),
+                                    (tuple.clone(), *idx, sidx as i32),
+                                ));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        if config.filter.is_none() {
+            found.truncate(config.k);
+        }
+
+        let mut ret = vec![];
+        for (OrderedFloat(distance), cand_key) in found {
+            if ret.len() >= config.k {
+                break;
+            }
+            if let Some(r) = config.radius {
+                if distance > r {
+                    break;
+                }
+            }
+            // the whole row is kept in place of the key
+            let cand_tuple = hnsw_result_tuple(config, cand_key.0.clone(), &cand_key, distance)?;
+            if let Some((code, span)) = filter_bytecode {
+                if !eval_bytecode_pred(code, &cand_tuple, stack, *span)? {
+                    continue;
+                }
+            }
+            ret.push(cand_tuple);
+        }
+        Ok(ret)
+    }
+}
+
+/// The row bound by a search for a vector found at `cand_key` with `distance`
+fn hnsw_result_tuple(
+    config: &HnswSearch,
+    mut cand_tuple: Tuple,
+    cand_key: &CompoundKey,
+    distance: f64,
+) -> Result<Tuple> {
+    // make sure the order is the same as in all_bindings()!!!
+    if config.bind_field.is_some() {
+        let field = if cand_key.1 < config.base_handle.metadata.keys.len() {
+            config.base_handle.metadata.keys[cand_key.1].name.clone()
+        } else {
+            config.base_handle.metadata.non_keys
+                [cand_key.1 - config.base_handle.metadata.keys.len()]
+            .name
+            .clone()
+        };
+        cand_tuple.push(DataValue::Str(field));
+    }
+    if config.bind_field_idx.is_some() {
+        cand_tuple.push(if cand_key.2 < 0 {
+            DataValue::Null
+        } else {
+            DataValue::from(cand_key.2 as i64)
+        });
+    }
+    if config.bind_distance.is_some() {
+        cand_tuple.push(DataValue::from(distance));
+    }
+    if config.bind_vector.is_some() {
+        let vec = if cand_key.2 < 0 {
+            cand_tuple[cand_key.1].clone()
+        } else {
+            match &cand_tuple[cand_key.1] {
+                DataValue::List(v) => v[cand_key.2 as usize].clone(),
+                v => bail!("corrupted index value {:?}", v),
+            }
+        };
+        cand_tuple.push(vec);
+    }
+
+    Ok(cand_tuple)
 }
 
 #[cfg(test)]
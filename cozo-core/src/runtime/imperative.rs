@@ -120,7 +120,13 @@ impl<'s, S: Storage<'s>> Db<S> {
                     ret = NamedRows::default();
                 }
                 ImperativeStmt::SysOp { sysop, .. } => {
-                    ret = self.run_sys_op_with_tx(tx, &sysop.sysop, readonly, true)?;
+                    ret = self.run_sys_op_with_tx(
+                        tx,
+                        &sysop.sysop,
+                        readonly,
+                        true,
+                        callback_collector,
+                    )?;
                     if let Some(store_as) = &sysop.store_as {
                         tx.script_store_as_relation(self, store_as, &ret, cur_vld)?;
                     }
@@ -276,15 +282,19 @@ impl<'s, S: Storage<'s>> Db<S> {
             Default::default()
         };
         let mut cleanups: Vec<(Vec<u8>, Vec<u8>)> = vec![];
-        let ret;
+        let mut ret;
         {
             let mut tx = if is_write {
                 self.transact_write()?
             } else {
                 self.transact()?
             };
+            self.collect_storage_stats(&mut tx);
+            if readonly {
+                tx.discard_fixed_rule_outputs();
+            }
 
-            let poison = Poison::default();
+            let poison = self.query_poison();
             let qid = self.queries_count.fetch_add(1, Ordering::AcqRel);
             let since_the_epoch = seconds_since_the_epoch()?;
 
@@ -327,6 +337,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             for (lower, upper) in cleanups {
                 tx.store_tx.del_range_from_persisted(&lower, &upper)?;
             }
+            ret.storage_stats = tx.store_tx.storage_stats();
 
             tx.commit_tx()?;
         }
@@ -379,6 +390,7 @@ impl SessionTx<'_> {
             },
             key_bindings,
             dep_bindings: vec![],
+            expected_version: None,
             span: Default::default(),
         };
         let headers = meta.key_bindings.clone();
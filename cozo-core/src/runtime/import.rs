@@ -0,0 +1,510 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::mem;
+use std::path::Path;
+
+use csv::StringRecord;
+use itertools::Itertools;
+use miette::{bail, ensure, miette, IntoDiagnostic, Result, WrapErr};
+use smartstring::SmartString;
+
+use crate::data::json::JsonValue;
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::value::{DataValue, Num, ValidityTs};
+use crate::parse::sys::{ImportFormat, ImportInferConfig};
+use crate::parse::SourceSpan;
+use crate::runtime::relation::InputRelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+/// Name of the key column added when no column of the file has unique values
+const ROW_INDEX: &str = "row_idx";
+
+/// Make a header or field name, or the stem of a file name, into an identifier.
+/// Names not starting with a letter are given `prefix`.
+pub(crate) fn sanitize_name(raw: &str, prefix: char) -> String {
+    let mut name: String = raw
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !name.starts_with(char::is_alphabetic) {
+        name.insert(0, prefix);
+    }
+    name
+}
+
+pub(crate) fn unique_name(name: String, taken: &mut BTreeSet<String>) -> String {
+    let mut candidate = name.clone();
+    let mut n = 1;
+    while !taken.insert(candidate.clone()) {
+        n += 1;
+        candidate = format!("{name}_{n}");
+    }
+    candidate
+}
+
+fn open(path: &str) -> Result<File> {
+    File::open(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("cannot open {path}"))
+}
+
+/// Detect the format by the extension of the file, or else by its first bytes
+fn detect_format(path: &str) -> Result<ImportFormat> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("csv") => return Ok(ImportFormat::Csv(b',')),
+        Some("tsv") => return Ok(ImportFormat::Csv(b'\t')),
+        Some("ndjson" | "jsonl" | "json") => return Ok(ImportFormat::Ndjson),
+        Some("parquet") => return Ok(ImportFormat::Parquet),
+        _ => {}
+    }
+    let mut head = [0u8; 512];
+    let n = open(path)?.read(&mut head).into_diagnostic()?;
+    let head = &head[..n];
+    if head.starts_with(b"PAR1") {
+        return Ok(ImportFormat::Parquet);
+    }
+    Ok(match head.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'{') => ImportFormat::Ndjson,
+        _ => ImportFormat::Csv(b','),
+    })
+}
+
+enum Record {
+    Csv(StringRecord),
+    /// The fields of a JSON object, or of a row of a Parquet file
+    Fields(Vec<(String, DataValue)>),
+}
+
+/// The header line of a CSV file, empty for NDJSON and Parquet whose fields are found in
+/// the records
+fn read_headers(path: &str, format: ImportFormat) -> Result<Vec<String>> {
+    match format {
+        ImportFormat::Csv(delimiter) => {
+            let mut rdr = csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .from_reader(open(path)?);
+            let headers = rdr.headers().into_diagnostic()?;
+            Ok(headers.iter().map(|h| h.to_string()).collect())
+        }
+        ImportFormat::Ndjson | ImportFormat::Parquet => Ok(vec![]),
+    }
+}
+
+/// Call `f` with the line number and contents of each record of the file,
+/// until it returns `false`
+fn scan_records(
+    path: &str,
+    format: ImportFormat,
+    mut f: impl FnMut(u64, Record) -> Result<bool>,
+) -> Result<()> {
+    match format {
+        ImportFormat::Csv(delimiter) => {
+            let mut rdr = csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .from_reader(open(path)?);
+            for record in rdr.records() {
+                let record = record.into_diagnostic()?;
+                let line = record.position().map(|p| p.line()).unwrap_or_default();
+                if !f(line, Record::Csv(record))? {
+                    break;
+                }
+            }
+        }
+        ImportFormat::Ndjson => {
+            for (idx, line) in BufReader::new(open(path)?).lines().enumerate() {
+                let line_no = idx as u64 + 1;
+                let line = line.into_diagnostic()?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let fields = match serde_json::from_str(&line) {
+                    Ok(JsonValue::Object(fields)) => fields,
+                    _ => bail!("Line {line_no} of {path} is not a JSON object"),
+                };
+                let fields = fields
+                    .into_iter()
+                    .map(|(field, val)| (field, DataValue::from(val)))
+                    .collect_vec();
+                if !f(line_no, Record::Fields(fields))? {
+                    break;
+                }
+            }
+        }
+        #[cfg(feature = "arrow")]
+        ImportFormat::Parquet => {
+            crate::runtime::arrow::scan_parquet(open(path)?, |row, fields| {
+                f(row, Record::Fields(fields))
+            })?;
+        }
+        #[cfg(not(feature = "arrow"))]
+        ImportFormat::Parquet => {
+            #[derive(Debug, thiserror::Error, miette::Diagnostic)]
+            #[error("Parquet files cannot be imported without the `arrow` feature")]
+            #[diagnostic(code(import::parquet_unsupported))]
+            #[diagnostic(help(
+                "Build with the `arrow` feature, or convert the file to CSV or NDJSON"
+            ))]
+            struct ParquetUnsupported;
+            bail!(ParquetUnsupported)
+        }
+    }
+    Ok(())
+}
+
+/// The value of a CSV field, typed by how it looks. Empty fields are null.
+pub(crate) fn csv_value(field: &str) -> DataValue {
+    if field.is_empty() {
+        return DataValue::Null;
+    }
+    if let Ok(i) = field.parse::<i64>() {
+        return DataValue::from(i);
+    }
+    // words such as `inf` or `NaN` are taken as text
+    if field.bytes().any(|b| b.is_ascii_digit()) {
+        if let Ok(f) = field.parse::<f64>() {
+            return DataValue::from(f);
+        }
+    }
+    match field {
+        "true" => DataValue::from(true),
+        "false" => DataValue::from(false),
+        _ => DataValue::from(field),
+    }
+}
+
+fn value_type(val: &DataValue) -> Option<ColType> {
+    Some(match val {
+        DataValue::Null => return None,
+        DataValue::Bool(_) => ColType::Bool,
+        DataValue::Num(Num::Int(_)) => ColType::Int,
+        DataValue::Num(Num::Float(_)) => ColType::Float,
+        DataValue::Str(_) => ColType::String,
+        DataValue::List(_) | DataValue::Json(_) => ColType::Json,
+        _ => ColType::Any,
+    })
+}
+
+/// The narrowest type holding values of both types, `fallback` if there is none
+fn widen(a: ColType, b: ColType, fallback: &ColType) -> ColType {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (ColType::Int, ColType::Float) | (ColType::Float, ColType::Int) => ColType::Float,
+        (ColType::Json, _) | (_, ColType::Json) => ColType::Json,
+        _ => fallback.clone(),
+    }
+}
+
+struct ColumnGuess {
+    /// The header or field name in the file
+    field: String,
+    name: String,
+    coltype: Option<ColType>,
+    /// Number of sampled records with a value for the column
+    present: usize,
+    /// The values seen, as long as they are all distinct
+    distinct: Option<BTreeSet<DataValue>>,
+}
+
+impl ColumnGuess {
+    fn new(field: String) -> Self {
+        Self {
+            field,
+            name: String::new(),
+            coltype: None,
+            present: 0,
+            distinct: Some(BTreeSet::new()),
+        }
+    }
+    fn observe(&mut self, val: DataValue, fallback: &ColType) {
+        let coltype = match value_type(&val) {
+            None => return,
+            Some(t) => t,
+        };
+        self.present += 1;
+        self.coltype = Some(match self.coltype.take() {
+            None => coltype,
+            Some(prev) => widen(prev, coltype, fallback),
+        });
+        let unique = match &mut self.distinct {
+            None => true,
+            Some(distinct) => distinct.insert(val),
+        };
+        if !unique {
+            self.distinct = None;
+        }
+    }
+}
+
+struct InferredSchema {
+    format: ImportFormat,
+    /// Header or field names, in the order of the file
+    fields: Vec<String>,
+    metadata: StoredRelationMetadata,
+    /// Where each column of the relation, keys first, takes its values from:
+    /// a field of the file, or the row index when `None`
+    sources: Vec<Option<usize>>,
+}
+
+impl InferredSchema {
+    fn describe(&self) -> NamedRows {
+        let rows = self
+            .metadata
+            .keys
+            .iter()
+            .map(|c| (c, true))
+            .chain(self.metadata.non_keys.iter().map(|c| (c, false)))
+            .map(|(c, is_key)| {
+                vec![
+                    DataValue::from(&c.name as &str),
+                    DataValue::from(c.typing.to_string()),
+                    DataValue::from(is_key),
+                ]
+            })
+            .collect();
+        NamedRows::new(
+            vec!["column".to_string(), "type".to_string(), "key".to_string()],
+            rows,
+        )
+    }
+}
+
+fn infer_schema(config: &ImportInferConfig) -> Result<InferredSchema> {
+    let path = &config.path;
+    let format = match config.format {
+        Some(format) => format,
+        None => detect_format(path)?,
+    };
+    // CSV fields of mixed looks are all text, JSON values of mixed types are kept as they are
+    let fallback = match format {
+        ImportFormat::Csv(_) => ColType::String,
+        ImportFormat::Ndjson | ImportFormat::Parquet => ColType::Any,
+    };
+    let mut guesses = read_headers(path, format)?
+        .into_iter()
+        .map(ColumnGuess::new)
+        .collect_vec();
+    let mut positions: BTreeMap<String, usize> = BTreeMap::new();
+    let mut rows = 0;
+    scan_records(path, format, |_, record| {
+        match record {
+            Record::Csv(record) => {
+                for (guess, field) in guesses.iter_mut().zip(record.iter()) {
+                    guess.observe(csv_value(field), &fallback);
+                }
+            }
+            Record::Fields(fields) => {
+                for (field, val) in fields {
+                    let idx = *positions.entry(field.clone()).or_insert_with(|| {
+                        guesses.push(ColumnGuess::new(field));
+                        guesses.len() - 1
+                    });
+                    guesses[idx].observe(val, &fallback);
+                }
+            }
+        }
+        rows += 1;
+        Ok(rows < config.sample)
+    })?;
+    ensure!(rows > 0, "{path} has no records to infer a schema from");
+
+    let mut taken = BTreeSet::new();
+    for guess in &mut guesses {
+        guess.name = unique_name(sanitize_name(&guess.field, 'c'), &mut taken);
+    }
+    let key_idxs: Vec<usize> = match &config.keys {
+        Some(keys) => keys
+            .iter()
+            .map(|k| {
+                guesses
+                    .iter()
+                    .position(|g| g.name == k.as_str() || g.field == k.as_str())
+                    .ok_or_else(|| miette!("{path} has no column {k}"))
+            })
+            .try_collect()?,
+        None => {
+            let candidates = guesses
+                .iter()
+                .positions(|g| {
+                    g.present == rows
+                        && g.distinct.is_some()
+                        && matches!(g.coltype, Some(ColType::Int | ColType::String))
+                })
+                .collect_vec();
+            candidates
+                .iter()
+                .find(|i| guesses[**i].name.eq_ignore_ascii_case("id"))
+                .or(candidates.first())
+                .into_iter()
+                .copied()
+                .collect()
+        }
+    };
+
+    let col = |guess: &ColumnGuess| ColumnDef {
+        name: SmartString::from(guess.name.as_str()),
+        typing: NullableColType {
+            coltype: guess.coltype.clone().unwrap_or(ColType::Any),
+            nullable: guess.present < rows,
+        },
+        default_gen: None,
+    };
+    let mut keys = vec![];
+    let mut sources = vec![];
+    if key_idxs.is_empty() {
+        keys.push(ColumnDef {
+            name: SmartString::from(unique_name(ROW_INDEX.to_string(), &mut taken)),
+            typing: NullableColType {
+                coltype: ColType::Int,
+                nullable: false,
+            },
+            default_gen: None,
+        });
+        sources.push(None);
+    } else {
+        for &i in &key_idxs {
+            keys.push(col(&guesses[i]));
+            sources.push(Some(i));
+        }
+    }
+    let mut non_keys = vec![];
+    for (i, guess) in guesses.iter().enumerate() {
+        if !key_idxs.contains(&i) {
+            non_keys.push(col(guess));
+            sources.push(Some(i));
+        }
+    }
+    Ok(InferredSchema {
+        format,
+        fields: guesses.into_iter().map(|g| g.field).collect(),
+        metadata: StoredRelationMetadata { keys, non_keys },
+        sources,
+    })
+}
+
+impl<'a> SessionTx<'a> {
+    /// Propose a schema for the file from a sample of its records. If asked to,
+    /// create the relation and load the whole file into it.
+    pub(crate) fn import_infer(
+        &mut self,
+        config: &ImportInferConfig,
+        cur_vld: ValidityTs,
+    ) -> Result<NamedRows> {
+        let schema = infer_schema(config)?;
+        if !config.create {
+            return Ok(schema.describe());
+        }
+        let bindings = |cols: &[ColumnDef]| {
+            cols.iter()
+                .map(|c| Symbol::new(c.name.clone(), SourceSpan(0, 0)))
+                .collect_vec()
+        };
+        let handle = self.create_relation(InputRelationHandle {
+            name: Symbol::new(config.relation.clone(), SourceSpan(0, 0)),
+            metadata: schema.metadata.clone(),
+            key_bindings: bindings(&schema.metadata.keys),
+            dep_bindings: bindings(&schema.metadata.non_keys),
+            expected_version: None,
+            span: SourceSpan(0, 0),
+        })?;
+
+        let types = schema
+            .metadata
+            .keys
+            .iter()
+            .chain(schema.metadata.non_keys.iter())
+            .map(|c| &c.typing)
+            .collect_vec();
+        // CSV fields loaded as strings are not parsed
+        let mut as_text = vec![false; schema.fields.len()];
+        for (source, typing) in schema.sources.iter().zip(&types) {
+            if let Some(idx) = source {
+                as_text[*idx] = typing.coltype == ColType::String;
+            }
+        }
+        let positions: BTreeMap<&str, usize> = schema
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.as_str(), i))
+            .collect();
+        let path = &config.path;
+        let mut loaded = 0usize;
+        scan_records(path, schema.format, |line, record| {
+            let mut fields = vec![DataValue::Null; schema.fields.len()];
+            match record {
+                Record::Csv(record) => {
+                    for (idx, field) in record.iter().enumerate().take(fields.len()) {
+                        fields[idx] = if as_text[idx] && !field.is_empty() {
+                            DataValue::from(field)
+                        } else {
+                            csv_value(field)
+                        };
+                    }
+                }
+                Record::Fields(record) => {
+                    for (field, val) in record {
+                        let idx = *positions.get(field.as_str()).ok_or_else(|| {
+                            miette!(
+                                "Line {line} of {path} has the field {field} not found in the sample, \
+                                 sample more records"
+                            )
+                        })?;
+                        fields[idx] = val;
+                    }
+                }
+            }
+            let row: Vec<_> = schema
+                .sources
+                .iter()
+                .zip(&types)
+                .map(|(source, typing)| {
+                    let val = match source {
+                        None => DataValue::from(loaded as i64),
+                        Some(idx) => mem::replace(&mut fields[*idx], DataValue::Null),
+                    };
+                    typing.coerce(val, cur_vld)
+                })
+                .try_collect()
+                .wrap_err_with(|| format!("when loading line {line} of {path}"))?;
+            let key = handle.encode_key_for_store(&row, SourceSpan(0, 0))?;
+            ensure!(
+                !self.store_tx.exists(&key, false)?,
+                "Line {line} of {path} repeats a key, choose the key columns with the `keys` option"
+            );
+            let val = handle.encode_val_for_store(&row, SourceSpan(0, 0))?;
+            self.store_tx.put(&key, &val)?;
+            loaded += 1;
+            Ok(true)
+        })?;
+        self.record_relation_write(&config.relation, true);
+        Ok(NamedRows::new(
+            vec!["relation".to_string(), "rows".to_string()],
+            vec![vec![
+                DataValue::from(&config.relation as &str),
+                DataValue::from(loaded as i64),
+            ]],
+        ))
+    }
+}
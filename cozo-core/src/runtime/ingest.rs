@@ -0,0 +1,43 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use miette::Result;
+
+use crate::data::tuple::TupleT;
+use crate::data::value::DataValue;
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+
+const INGESTED: &str = "INGESTED";
+
+fn ingested_key(idempotency_key: &str) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from(INGESTED),
+        DataValue::from(idempotency_key),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl<'a> SessionTx<'a> {
+    /// Whether a batch with the key has been imported by a committed transaction
+    pub(crate) fn is_ingested(&self, idempotency_key: &str) -> Result<bool> {
+        // read for update, so that concurrent imports of the same batch conflict
+        Ok(self
+            .store_tx
+            .get(&ingested_key(idempotency_key), true)?
+            .is_some())
+    }
+    /// Record the key together with the time of the import
+    pub(crate) fn record_ingested(&mut self, idempotency_key: &str) -> Result<()> {
+        let at = seconds_since_the_epoch()?;
+        self.store_tx
+            .put(&ingested_key(idempotency_key), &at.to_be_bytes())
+    }
+}
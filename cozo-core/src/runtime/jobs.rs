@@ -0,0 +1,305 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use miette::{bail, Result};
+
+use crate::data::value::DataValue;
+use crate::runtime::db::{seconds_since_the_epoch, Poison};
+use crate::{Db, NamedRows, Storage};
+
+/// How many finished jobs are kept for their results to be fetched, the oldest is dropped first
+const MAX_FINISHED: usize = 64;
+
+/// Scripts submitted with `::job submit`, shared by all clones of a database.
+///
+/// Jobs are run one after another by a single worker, independently of the session
+/// that submitted them. Their state lives in memory and is lost when the process exits.
+#[derive(Default)]
+pub(crate) struct JobQueue {
+    state: Mutex<JobsState>,
+    /// Set while a worker is taking jobs off the queue, there is never more than one
+    worker_running: AtomicBool,
+    /// Set by [Db::accept_jobs]: without it nothing would ever run submitted scripts
+    accepting: AtomicBool,
+}
+
+#[derive(Default)]
+struct JobsState {
+    next_id: u64,
+    queued: VecDeque<u64>,
+    jobs: BTreeMap<u64, Job>,
+    finished: VecDeque<u64>,
+}
+
+struct Job {
+    script: String,
+    params: BTreeMap<String, DataValue>,
+    read_only: bool,
+    poison: Poison,
+    submitted_at: f64,
+    started_at: Option<f64>,
+    finished_at: Option<f64>,
+    outcome: Option<Result<NamedRows, String>>,
+}
+
+impl Job {
+    fn status(&self) -> &'static str {
+        match &self.outcome {
+            None if self.poison.is_killed() => "killing",
+            None if self.started_at.is_none() => "queued",
+            None => "running",
+            Some(_) if self.poison.is_killed() => "killed",
+            Some(Ok(_)) => "done",
+            Some(Err(_)) => "failed",
+        }
+    }
+}
+
+/// A job taken off the queue by the worker
+pub(crate) struct StartedJob {
+    pub(crate) id: u64,
+    pub(crate) script: String,
+    pub(crate) params: BTreeMap<String, DataValue>,
+    pub(crate) read_only: bool,
+    pub(crate) poison: Poison,
+}
+
+impl JobQueue {
+    pub(crate) fn submit(
+        &self,
+        script: String,
+        params: BTreeMap<String, DataValue>,
+        read_only: bool,
+    ) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.jobs.insert(
+            id,
+            Job {
+                script,
+                params,
+                read_only,
+                poison: Poison::default(),
+                submitted_at: seconds_since_the_epoch()?,
+                started_at: None,
+                finished_at: None,
+                outcome: None,
+            },
+        );
+        state.queued.push_back(id);
+        Ok(id)
+    }
+    pub(crate) fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::Acquire)
+    }
+    /// Returns `true` if there are queued jobs and no worker to run them,
+    /// in which case the caller has become the worker and must call [JobQueue::release_worker]
+    /// when it is done
+    pub(crate) fn claim_worker(&self) -> bool {
+        if self.state.lock().unwrap().queued.is_empty() {
+            return false;
+        }
+        self.worker_running
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+    /// Returns `true` if jobs were queued while the worker was stopping,
+    /// in which case the caller is the worker again
+    pub(crate) fn release_worker(&self) -> bool {
+        self.worker_running.store(false, Ordering::SeqCst);
+        self.claim_worker()
+    }
+    /// Take the next job off the queue
+    pub(crate) fn start_next(&self) -> Option<StartedJob> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.queued.pop_front()?;
+        let job = state.jobs.get_mut(&id).unwrap();
+        job.started_at = seconds_since_the_epoch().ok();
+        Some(StartedJob {
+            id,
+            script: job.script.clone(),
+            params: job.params.clone(),
+            read_only: job.read_only,
+            poison: job.poison.clone(),
+        })
+    }
+    pub(crate) fn finish(&self, id: u64, outcome: Result<NamedRows, String>) {
+        let mut state = self.state.lock().unwrap();
+        let job = match state.jobs.get_mut(&id) {
+            None => return,
+            Some(job) => job,
+        };
+        job.finished_at = seconds_since_the_epoch().ok();
+        job.outcome = Some(outcome);
+        state.finished.push_back(id);
+        if state.finished.len() > MAX_FINISHED {
+            if let Some(oldest) = state.finished.pop_front() {
+                state.jobs.remove(&oldest);
+            }
+        }
+    }
+    /// Queued jobs are dropped at once, running ones stop at their next check of the poison
+    pub(crate) fn kill(&self, id: u64) -> Option<&'static str> {
+        let mut state = self.state.lock().unwrap();
+        let job = state.jobs.get(&id)?;
+        if job.outcome.is_some() {
+            return Some(job.status());
+        }
+        job.poison.kill();
+        if job.started_at.is_some() {
+            return Some("killing");
+        }
+        state.queued.retain(|queued| *queued != id);
+        drop(state);
+        self.finish(id, Err("killed before it started".to_string()));
+        Some("killed")
+    }
+    pub(crate) fn kill_all(&self) {
+        let ids = self
+            .state
+            .lock()
+            .unwrap()
+            .jobs
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        for id in ids {
+            self.kill(id);
+        }
+    }
+    pub(crate) fn list(&self) -> NamedRows {
+        let state = self.state.lock().unwrap();
+        let time = |t: Option<f64>| t.map(DataValue::from).unwrap_or(DataValue::Null);
+        let rows = state
+            .jobs
+            .iter()
+            .map(|(id, job)| {
+                let report = job.poison.progress_report();
+                vec![
+                    DataValue::from(*id as i64),
+                    DataValue::from(job.status()),
+                    DataValue::from(job.poison.progress() as i64),
+                    report
+                        .as_ref()
+                        .map(|r| DataValue::from(r.fraction))
+                        .unwrap_or(DataValue::Null),
+                    report
+                        .map(|r| DataValue::from(r.message))
+                        .unwrap_or(DataValue::Null),
+                    DataValue::from(job.submitted_at),
+                    time(job.started_at),
+                    time(job.finished_at),
+                    match &job.outcome {
+                        Some(Err(err)) => DataValue::from(err.as_str()),
+                        _ => DataValue::Null,
+                    },
+                ]
+            })
+            .collect();
+        NamedRows::new(
+            vec![
+                "id".to_string(),
+                "status".to_string(),
+                "progress".to_string(),
+                "fraction".to_string(),
+                "message".to_string(),
+                "submitted_at".to_string(),
+                "started_at".to_string(),
+                "finished_at".to_string(),
+                "error".to_string(),
+            ],
+            rows,
+        )
+    }
+    pub(crate) fn result(&self, id: u64) -> Result<NamedRows> {
+        let state = self.state.lock().unwrap();
+        let job = match state.jobs.get(&id) {
+            None => bail!("Job {id} not found, it may have been dropped after finishing"),
+            Some(job) => job,
+        };
+        match &job.outcome {
+            None => bail!("Job {id} has not finished, it is {}", job.status()),
+            Some(Err(err)) => bail!("Job {id} failed: {err}"),
+            Some(Ok(rows)) => Ok(rows.clone()),
+        }
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// The poison for a new query: it is also killed when the job the query runs in is killed,
+    /// and reports progress to the job
+    pub(crate) fn query_poison(&self) -> Poison {
+        Poison::for_job(self.job.clone())
+    }
+    /// A clone whose queries are killed along with `poison`
+    pub(crate) fn with_job(&self, poison: Poison) -> Self {
+        let mut db = self.clone();
+        db.job = Some(poison);
+        db
+    }
+}
+
+impl<S> Db<S>
+where
+    S: for<'s> Storage<'s> + 'static,
+{
+    /// Let scripts submit jobs with `::job submit`, which are otherwise rejected on a `Db`
+    /// used directly: the caller takes it upon itself to call [Db::run_queued_jobs].
+    ///
+    /// [`DbInstance`](crate::DbInstance) does this itself and runs the jobs on a background thread.
+    pub fn accept_jobs(&self) {
+        self.jobs.accepting.store(true, Ordering::Release);
+    }
+    /// Run the jobs submitted with `::job submit` on the current thread, until none is left.
+    /// Returns at once if another thread is already running them.
+    ///
+    /// [`DbInstance`](crate::DbInstance) calls this on a background thread whenever a script
+    /// has submitted jobs. Users of `Db` directly have to arrange for it to be called.
+    pub fn run_queued_jobs(&self) {
+        if self.jobs.claim_worker() {
+            self.work_jobs();
+        }
+    }
+    /// Run jobs as the worker claimed with [JobQueue::claim_worker], then release it
+    pub(crate) fn work_jobs(&self) {
+        loop {
+            self.run_claimed_jobs();
+            if !self.jobs.release_worker() {
+                return;
+            }
+        }
+    }
+    fn run_claimed_jobs(&self) {
+        while let Some(job) = self.jobs.start_next() {
+            let mut db = self.clone();
+            db.job = Some(job.poison);
+            let outcome = db
+                .do_run_script(
+                    &job.script,
+                    &job.params,
+                    self.current_validity(),
+                    job.read_only,
+                    None,
+                )
+                .map_err(|err| err.to_string());
+            self.jobs.finish(job.id, outcome);
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn spawn_job_worker(&self) {
+        if self.jobs.claim_worker() {
+            let db = self.clone();
+            std::thread::spawn(move || db.work_jobs());
+        }
+    }
+}
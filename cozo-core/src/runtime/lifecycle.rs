@@ -0,0 +1,118 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+use miette::{Diagnostic, Result};
+use thiserror::Error;
+
+use crate::{Db, Storage};
+
+/// How long [Db::close] waits for killed queries to unwind
+#[cfg(not(target_arch = "wasm32"))]
+const CLOSE_WAIT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database has been closed")]
+#[diagnostic(code(db::closed))]
+pub(crate) struct DbClosed;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database has been opened read-only")]
+#[diagnostic(code(db::read_only))]
+pub(crate) struct DbReadOnly;
+
+/// Open/closed and read-only state of a database, shared by all of its clones.
+///
+/// When the last clone goes away without the database having been closed, and data was
+/// written to a storage engine that buffers writes in memory, a warning is logged.
+pub(crate) struct Lifecycle {
+    closed: AtomicBool,
+    dirty: AtomicBool,
+    read_only: AtomicBool,
+    storage_kind: &'static str,
+}
+
+impl Lifecycle {
+    pub(crate) fn new(storage_kind: &'static str) -> Self {
+        Self {
+            closed: AtomicBool::new(false),
+            dirty: AtomicBool::new(false),
+            read_only: AtomicBool::new(false),
+            storage_kind,
+        }
+    }
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+    pub(crate) fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+    pub(crate) fn mark_clean(&self) {
+        self.dirty.store(false, Ordering::Release);
+    }
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for Lifecycle {
+    fn drop(&mut self) {
+        let buffers_writes = matches!(self.storage_kind, "rocksdb" | "sled");
+        if buffers_writes && !self.is_closed() && self.dirty.load(Ordering::Acquire) {
+            log::warn!(
+                "{} database dropped without being closed: data written since the last flush \
+                 may not be durable. Call `close()` before exiting.",
+                self.storage_kind
+            );
+        }
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Close the database.
+    ///
+    /// From now on new transactions are refused, and background jobs started for the
+    /// database stop. Running queries are killed, and are given a few seconds to release
+    /// their iterators before the storage engine is flushed. The closed state is shared
+    /// by all clones of the database. Closing an already closed database only flushes again.
+    pub fn close(&'s self) -> Result<()> {
+        self.lifecycle.closed.store(true, Ordering::Release);
+        self.jobs.kill_all();
+        for handle in self.running_queries.lock().unwrap().values() {
+            handle.poison.kill();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let deadline = Instant::now() + CLOSE_WAIT;
+            while !self.running_queries.lock().unwrap().is_empty() {
+                if Instant::now() > deadline {
+                    log::warn!("closing database while queries are still running");
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        self.db.flush()?;
+        self.lifecycle.mark_clean();
+        Ok(())
+    }
+
+    /// Whether [close](Self::close) has been called on this database or one of its clones
+    pub fn is_closed(&self) -> bool {
+        self.lifecycle.is_closed()
+    }
+
+    /// Refuse all write transactions from now on. Used for databases opened without
+    /// taking the multi-process lock.
+    pub(crate) fn set_read_only(&self) {
+        self.lifecycle.read_only.store(true, Ordering::Release);
+    }
+}
@@ -0,0 +1,88 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Matrix, Vector};
+use crate::{Db, Storage};
+
+/// Limits on the size of the rows returned by a query, shared by all clones of a database.
+/// Zero means unlimited.
+#[derive(Default)]
+pub(crate) struct ResultLimits {
+    max_rows: AtomicUsize,
+    max_bytes: AtomicUsize,
+}
+
+impl ResultLimits {
+    /// Collect rows from `iter` until either limit is reached.
+    /// Returns the rows, and whether any were left out.
+    pub(crate) fn collect(&self, iter: impl Iterator<Item = Tuple>) -> (Vec<Tuple>, bool) {
+        let max_rows = self.max_rows.load(Ordering::Relaxed);
+        let max_bytes = self.max_bytes.load(Ordering::Relaxed);
+        if max_rows == 0 && max_bytes == 0 {
+            return (iter.collect(), false);
+        }
+        let mut rows = vec![];
+        let mut bytes = 0;
+        for tuple in iter {
+            if max_rows != 0 && rows.len() >= max_rows {
+                return (rows, true);
+            }
+            if max_bytes != 0 {
+                bytes += tuple.iter().map(estimated_size).sum::<usize>();
+                if bytes > max_bytes {
+                    return (rows, true);
+                }
+            }
+            rows.push(tuple);
+        }
+        (rows, false)
+    }
+}
+
+/// A rough estimate of the memory taken by a value once handed to the caller,
+/// e.g. as a JSON or Python object
+fn estimated_size(v: &DataValue) -> usize {
+    const OVERHEAD: usize = 8;
+    OVERHEAD
+        + match v {
+            DataValue::Null | DataValue::Bool(_) | DataValue::Bot => 0,
+            DataValue::Num(_) | DataValue::Validity(_) => 8,
+            DataValue::Str(s) => s.len(),
+            DataValue::Bytes(b) => b.len(),
+            DataValue::Uuid(_) => 16,
+            DataValue::Regex(r) => r.0.as_str().len(),
+            DataValue::List(l) => l.iter().map(estimated_size).sum(),
+            DataValue::Set(s) => s.iter().map(estimated_size).sum(),
+            DataValue::Vec(Vector::F32(a)) => a.len() * 4,
+            DataValue::Vec(Vector::F64(a)) => a.len() * 8,
+            DataValue::Json(j) => j.0.to_string().len(),
+            DataValue::Mat(Matrix::F32(m)) => m.len() * 4,
+            DataValue::Mat(Matrix::F64(m)) => m.len() * 8,
+            DataValue::Sparse(s) => s.nnz() * 8,
+        }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Limit the size of the rows returned by queries, so that a stray query over a huge
+    /// relation cannot exhaust the memory of the process consuming the result.
+    ///
+    /// Results over either limit are cut short and marked as `truncated`.
+    /// `max_bytes` is compared against an estimate of the size of the values.
+    /// Pass `None` to lift a limit. Mutations are not affected.
+    pub fn set_result_limits(&self, max_rows: Option<usize>, max_bytes: Option<usize>) {
+        self.result_limits
+            .max_rows
+            .store(max_rows.unwrap_or(0), Ordering::Relaxed);
+        self.result_limits
+            .max_bytes
+            .store(max_bytes.unwrap_or(0), Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use itertools::Itertools;
+use log::Level;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::NamedRows;
+
+/// The target of the records logged for every query and script run, with the key-values
+/// `session`, `query_id`, `relations`, `elapsed`, `rows` and `error` as they apply.
+/// The session is empty for queries not run in one.
+pub(crate) const QUERY_LOG_TARGET: &str = "cozo::query";
+
+/// How much of a failed script is logged
+const MAX_LOGGED_SCRIPT_LEN: usize = 256;
+
+/// Logs a query at the debug level when dropped, as failed unless its rows were counted
+pub(crate) struct QueryLog {
+    pub(crate) id: u64,
+    pub(crate) session: Option<Arc<str>>,
+    pub(crate) started_at: f64,
+    /// The stored relations and indices read or written
+    pub(crate) relations: BTreeSet<SmartString<LazyCompact>>,
+    pub(crate) rows: Option<usize>,
+}
+
+impl Drop for QueryLog {
+    fn drop(&mut self) {
+        if !log::log_enabled!(target: QUERY_LOG_TARGET, Level::Debug) {
+            return;
+        }
+        let elapsed = seconds_since_the_epoch().unwrap_or(self.started_at) - self.started_at;
+        let relations = self.relations.iter().join(",");
+        let session = self.session.as_deref().unwrap_or_default();
+        match self.rows {
+            Some(rows) => log::debug!(
+                target: QUERY_LOG_TARGET,
+                session = session,
+                query_id = self.id,
+                relations = relations.as_str(),
+                elapsed = elapsed,
+                rows = rows;
+                "query {} finished",
+                self.id
+            ),
+            None => log::debug!(
+                target: QUERY_LOG_TARGET,
+                session = session,
+                query_id = self.id,
+                relations = relations.as_str(),
+                elapsed = elapsed;
+                "query {} failed",
+                self.id
+            ),
+        }
+    }
+}
+
+/// Log a script that has been run: at the debug level if it succeeded, at the info level
+/// with the start of the script if it failed
+pub(crate) fn log_script(
+    session: Option<&str>,
+    script: &str,
+    elapsed: f64,
+    res: &Result<NamedRows>,
+) {
+    let session = session.unwrap_or_default();
+    match res {
+        Ok(rows) => log::debug!(
+            target: QUERY_LOG_TARGET,
+            session = session,
+            elapsed = elapsed,
+            rows = rows.rows.len();
+            "script finished"
+        ),
+        Err(err) => {
+            if !log::log_enabled!(target: QUERY_LOG_TARGET, Level::Info) {
+                return;
+            }
+            let script = match script.char_indices().nth(MAX_LOGGED_SCRIPT_LEN) {
+                None => script.trim(),
+                Some((idx, _)) => &script[..idx],
+            };
+            let error = err.to_string();
+            log::info!(
+                target: QUERY_LOG_TARGET,
+                session = session,
+                elapsed = elapsed,
+                error = error.as_str(),
+                script = script;
+                "script failed: {error}"
+            )
+        }
+    }
+}
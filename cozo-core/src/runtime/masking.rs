@@ -0,0 +1,234 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use itertools::Itertools;
+use miette::{bail, ensure, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use sha2::digest::FixedOutput;
+use sha2::{Digest, Sha256};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+/// How the values of a masked column are shown to sessions without the `unmask` privilege,
+/// see [crate::Db::with_unmask]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum MaskPolicy {
+    /// The SHA-256 of the value, in hex. Equal values have equal hashes,
+    /// so that masked columns can still be joined and grouped on.
+    Hash,
+    /// Only the last characters of the value, as a string
+    Partial,
+    /// Null
+    Null,
+}
+
+/// The number of characters kept by [MaskPolicy::Partial]
+const PARTIAL_KEPT: usize = 4;
+
+impl MaskPolicy {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "hash" => MaskPolicy::Hash,
+            "partial" => MaskPolicy::Partial,
+            "null" => MaskPolicy::Null,
+            s => bail!("Unknown mask '{s}', expected 'hash', 'partial' or 'null'"),
+        })
+    }
+    fn apply(self, val: &DataValue) -> DataValue {
+        if *val == DataValue::Null {
+            return DataValue::Null;
+        }
+        match self {
+            MaskPolicy::Hash => {
+                let mut bytes = vec![];
+                val.serialize(&mut Serializer::new(&mut bytes)).unwrap();
+                let mut hasher = Sha256::new();
+                hasher.update(&bytes);
+                let hash = hasher.finalize_fixed();
+                DataValue::from(hash.iter().map(|b| format!("{b:02x}")).join(""))
+            }
+            MaskPolicy::Partial => {
+                let s = match val {
+                    DataValue::Str(s) => s.to_string(),
+                    v => v.to_string(),
+                };
+                let n = s.chars().count();
+                let hidden = if n > PARTIAL_KEPT {
+                    n - PARTIAL_KEPT
+                } else {
+                    n
+                };
+                let masked: String = s
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| if i < hidden { '*' } else { c })
+                    .collect();
+                DataValue::from(masked)
+            }
+            MaskPolicy::Null => DataValue::Null,
+        }
+    }
+}
+
+impl Display for MaskPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaskPolicy::Hash => f.write_str("hash"),
+            MaskPolicy::Partial => f.write_str("partial"),
+            MaskPolicy::Null => f.write_str("null"),
+        }
+    }
+}
+
+impl RelationHandle {
+    /// Apply the masks of the relation to the rows read through this handle,
+    /// done for the relations read by queries of sessions without the `unmask` privilege
+    pub(crate) fn mask_reads(&mut self) {
+        let n_keys = self.metadata.keys.len();
+        let masks = self
+            .metadata
+            .non_keys
+            .iter()
+            .enumerate()
+            .filter_map(|(i, col)| self.masks.get(&col.name).map(|mask| (n_keys + i, *mask)))
+            .collect_vec();
+        self.active_masks = if masks.is_empty() { None } else { Some(masks) };
+    }
+    pub(crate) fn mask_tuple(&self, mut tuple: Tuple, vals_only: bool) -> Tuple {
+        if let Some(masks) = &self.active_masks {
+            let offset = if vals_only {
+                self.metadata.keys.len()
+            } else {
+                0
+            };
+            for (pos, mask) in masks {
+                if let Some(val) = tuple.get_mut(pos - offset) {
+                    *val = mask.apply(val);
+                }
+            }
+        }
+        tuple
+    }
+    pub(crate) fn masking<'a>(
+        &self,
+        iter: Box<dyn Iterator<Item = Result<Tuple>> + 'a>,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        match &self.active_masks {
+            None => iter,
+            Some(masks) => {
+                let masks = masks.clone();
+                Box::new(iter.map(move |tuple| {
+                    let mut tuple = tuple?;
+                    for (pos, mask) in &masks {
+                        tuple[*pos] = mask.apply(&tuple[*pos]);
+                    }
+                    Ok(tuple)
+                }))
+            }
+        }
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// The handle of a stored relation read by a query, masked unless the session may unmask
+    pub(crate) fn get_relation_for_read(&self, name: &str) -> Result<RelationHandle> {
+        let mut handle = self.get_relation(name, false)?;
+        if !self.unmask && !handle.masks.is_empty() {
+            handle.mask_reads();
+        }
+        Ok(handle)
+    }
+
+    pub(crate) fn list_masks(&self, rel: &Symbol) -> Result<NamedRows> {
+        let handle = self.get_relation(rel, false)?;
+        Ok(NamedRows::new(
+            vec!["column".to_string(), "mask".to_string()],
+            handle
+                .masks
+                .iter()
+                .map(|(col, mask)| {
+                    vec![
+                        DataValue::Str(col.clone()),
+                        DataValue::from(mask.to_string()),
+                    ]
+                })
+                .collect(),
+        ))
+    }
+
+    /// Mask the columns given, or unmask those given with `None`
+    pub(crate) fn set_masks(
+        &mut self,
+        rel: &Symbol,
+        columns: &BTreeMap<SmartString<LazyCompact>, Option<MaskPolicy>>,
+    ) -> Result<()> {
+        let mut handle = self.get_relation(rel, true)?;
+        if handle.is_temp {
+            bail!("Cannot mask columns of temp relations")
+        }
+        if handle.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "masking columns".to_string(),
+                handle.access_level
+            ))
+        }
+        for (name, mask) in columns {
+            let pos = match handle
+                .metadata
+                .non_keys
+                .iter()
+                .position(|c| c.name == *name)
+            {
+                Some(pos) => pos + handle.metadata.keys.len(),
+                None => {
+                    if handle.metadata.keys.iter().any(|c| c.name == *name) {
+                        bail!("The key column {name} cannot be masked")
+                    }
+                    bail!("The relation {} has no column {name}", handle.name)
+                }
+            };
+            match mask {
+                Some(mask) => {
+                    ensure!(
+                        handle
+                            .indices
+                            .values()
+                            .all(|(_, cols)| !cols.contains(&pos))
+                            && handle.hnsw_indices.is_empty()
+                            && handle.fts_indices.is_empty()
+                            && handle.lsh_indices.is_empty(),
+                        "The column {name} is indexed, and indices can be read unmasked"
+                    );
+                    handle.masks.insert(name.clone(), *mask);
+                }
+                None => {
+                    handle.masks.remove(name);
+                }
+            }
+        }
+
+        let name_key = vec![DataValue::Str(handle.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,634 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result, WrapErr};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::{
+    FixedRuleArg, InputAtom, InputInlineRulesOrFixed, InputProgram, InputRuleApplyAtom,
+};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::{DataValue, ValidityTs};
+use crate::query::stored::make_const_rule;
+use crate::runtime::callback::CallbackCollector;
+use crate::runtime::relation::{InputRelationHandle, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, Storage};
+
+/// Raised when rows are written into a materialized view other than by its query
+#[derive(Debug, Error, Diagnostic)]
+#[error("The relation {0} is a materialized view and cannot be written into")]
+#[diagnostic(code(eval::write_into_view))]
+#[diagnostic(help("Its rows follow from the relations its query reads, write into these instead"))]
+pub(crate) struct ViewNotWritable(pub(crate) String);
+
+/// Raised when a relation read by materialized views would be removed, renamed or replaced
+#[derive(Debug, Error, Diagnostic)]
+#[error("The relation {0} is read by the materialized views {1}")]
+#[diagnostic(code(eval::relation_read_by_views))]
+#[diagnostic(help("Remove the materialized views first"))]
+pub(crate) struct RelationReadByViews(pub(crate) String, pub(crate) String);
+
+/// The query of a materialized view, created by `::create materialized`
+#[derive(Clone, Debug, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct MaterializedView {
+    pub(crate) script: String,
+    /// The stored relations the query reads, indices given by their relation
+    pub(crate) sources: BTreeSet<SmartString<LazyCompact>>,
+}
+
+/// The columns of a materialized view: the variables of the head of the entry rule.
+/// The aggregated ones hold the values of the others, which make the keys. Without
+/// aggregations all columns are keys, as the rows are a set.
+fn view_columns(program: &InputProgram) -> Result<StoredRelationMetadata> {
+    let col = |symb: &Symbol| ColumnDef {
+        name: symb.name.clone(),
+        typing: NullableColType {
+            coltype: ColType::Any,
+            nullable: true,
+        },
+        default_gen: None,
+    };
+    let mut metadata = StoredRelationMetadata {
+        keys: vec![],
+        non_keys: vec![],
+    };
+    match program
+        .prog
+        .get(&Symbol::new(PROG_ENTRY, Default::default()))
+    {
+        Some(InputInlineRulesOrFixed::Rules { rules }) => {
+            let rule = rules.last().unwrap();
+            for (symb, aggr) in rule.head.iter().zip(rule.aggr.iter()) {
+                match aggr {
+                    None => metadata.keys.push(col(symb)),
+                    Some(_) => metadata.non_keys.push(col(symb)),
+                }
+            }
+        }
+        Some(InputInlineRulesOrFixed::Fixed { fixed }) => {
+            metadata.keys = fixed.head.iter().map(col).collect_vec();
+        }
+        None => bail!("The query of a materialized view must have an entry rule `?`"),
+    }
+    let mut seen = BTreeSet::new();
+    for col in metadata.keys.iter().chain(metadata.non_keys.iter()) {
+        if !seen.insert(&col.name) {
+            bail!(
+                "The column {} of the materialized view appears more than once in the head of its query",
+                col.name
+            )
+        }
+    }
+    if metadata.keys.is_empty() {
+        bail!("The head of the query of a materialized view must have a column that is not aggregated")
+    }
+    Ok(metadata)
+}
+
+/// The positions in the head of the entry rule of the columns of the view, in the order of
+/// [view_columns]: the columns not aggregated first
+fn view_column_positions(program: &InputProgram) -> Vec<usize> {
+    match program
+        .prog
+        .get(&Symbol::new(PROG_ENTRY, Default::default()))
+    {
+        Some(InputInlineRulesOrFixed::Rules { rules }) => {
+            let aggr = &rules.last().unwrap().aggr;
+            let (keys, non_keys): (Vec<_>, Vec<_>) =
+                (0..aggr.len()).partition(|i| aggr[*i].is_none());
+            keys.into_iter().chain(non_keys).collect_vec()
+        }
+        Some(InputInlineRulesOrFixed::Fixed { fixed }) => (0..fixed.head.len()).collect_vec(),
+        None => vec![],
+    }
+}
+
+/// The stored relations read by the program, once for each time they are read, with
+/// indices given by their relation
+fn read_relations(program: &InputProgram) -> Vec<SmartString<LazyCompact>> {
+    fn base(name: &Symbol) -> SmartString<LazyCompact> {
+        match name.name.split_once(':') {
+            Some((rel, _)) => SmartString::from(rel),
+            None => name.name.clone(),
+        }
+    }
+    fn atoms(atoms: &[InputAtom], found: &mut Vec<SmartString<LazyCompact>>) {
+        for atom in atoms {
+            match atom {
+                InputAtom::Relation { inner } => {
+                    found.push(base(&inner.name));
+                }
+                InputAtom::NamedFieldRelation { inner } => {
+                    found.push(base(&inner.name));
+                }
+                InputAtom::Search { inner } => {
+                    found.push(base(&inner.relation));
+                }
+                InputAtom::Negation { inner, .. } => {
+                    atoms(std::slice::from_ref(inner.as_ref()), found)
+                }
+                InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                    atoms(inner, found)
+                }
+                InputAtom::Rule { .. }
+                | InputAtom::Predicate { .. }
+                | InputAtom::Unification { .. } => {}
+            }
+        }
+    }
+
+    let mut found = vec![];
+    for rules in program.prog.values() {
+        match rules {
+            InputInlineRulesOrFixed::Rules { rules } => {
+                for rule in rules {
+                    atoms(&rule.body, &mut found);
+                }
+            }
+            InputInlineRulesOrFixed::Fixed { fixed } => {
+                for arg in &fixed.rule_args {
+                    match arg {
+                        FixedRuleArg::Stored { name, .. }
+                        | FixedRuleArg::NamedStored { name, .. } => {
+                            found.push(base(name));
+                        }
+                        FixedRuleArg::InMem { .. } => {}
+                    }
+                }
+            }
+        }
+    }
+    found
+}
+
+/// The name of the rule holding the rows of the relation written, for [changed_keys_program]
+const CHANGED_ROWS: &str = "*view*changed";
+/// The name of the rule holding the keys of the rows to compute, for [keyed_program]
+const CHANGED_KEYS: &str = "*view*keys";
+
+/// The query of a view made to give the keys of its rows that may change as the rows of
+/// `source` in `changed` are written: the atom reading `source` reads these rows instead,
+/// and the head keeps the columns making the keys of the view, without aggregations. As
+/// the other relations read are as before, the rows of the view with other keys are found
+/// in the same way as before.
+///
+/// `None` when the keys cannot be told this way: when `source` is read more than once,
+/// or other than by an atom of the body of the single rule of the query, as in negations,
+/// searches, through indices or at a time, and when the query limits, sorts or asserts its
+/// rows, or `source` has masked columns.
+fn changed_keys_program(
+    program: &InputProgram,
+    source: &RelationHandle,
+    changed: &[Tuple],
+) -> Option<InputProgram> {
+    let out_opts = &program.out_opts;
+    if out_opts.limit.is_some()
+        || out_opts.offset.is_some()
+        || !out_opts.sorters.is_empty()
+        || out_opts.assertion.is_some()
+        || !source.masks.is_empty()
+    {
+        return None;
+    }
+    let reads = read_relations(program)
+        .into_iter()
+        .filter(|name| *name == source.name)
+        .count();
+    if reads != 1 {
+        return None;
+    }
+    let mut program = program.clone();
+    let rule = match program
+        .prog
+        .get_mut(&Symbol::new(PROG_ENTRY, Default::default()))
+    {
+        Some(InputInlineRulesOrFixed::Rules { rules }) if rules.len() == 1 => &mut rules[0],
+        _ => return None,
+    };
+    let columns = source
+        .metadata
+        .keys
+        .iter()
+        .chain(source.metadata.non_keys.iter())
+        .collect_vec();
+    let (pos, args, span) = rule
+        .body
+        .iter()
+        .enumerate()
+        .find_map(|(pos, atom)| match atom {
+            InputAtom::Relation { inner }
+                if inner.name.name == source.name
+                    && inner.valid_at.is_none()
+                    && inner.tx_at.is_none() =>
+            {
+                Some((pos, inner.args.clone(), inner.span))
+            }
+            InputAtom::NamedFieldRelation { inner }
+                if inner.name.name == source.name
+                    && inner.valid_at.is_none()
+                    && inner.tx_at.is_none() =>
+            {
+                let args = columns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, col)| match inner.args.get(&col.name) {
+                        Some(arg) => arg.clone(),
+                        None => Expr::Binding {
+                            var: Symbol::new(format!("*view*{i}"), inner.span),
+                            tuple_pos: None,
+                        },
+                    })
+                    .collect_vec();
+                Some((pos, args, inner.span))
+            }
+            _ => None,
+        })?;
+    rule.body[pos] = InputAtom::Rule {
+        inner: InputRuleApplyAtom {
+            name: Symbol::new(CHANGED_ROWS, span),
+            args,
+            span,
+        },
+    };
+    rule.head = rule
+        .head
+        .iter()
+        .zip(rule.aggr.iter())
+        .filter(|(_, aggr)| aggr.is_none())
+        .map(|(symb, _)| symb.clone())
+        .collect_vec();
+    rule.aggr = rule.head.iter().map(|_| None).collect_vec();
+
+    let bindings = columns
+        .iter()
+        .map(|col| Symbol::new(col.name.clone(), Default::default()))
+        .collect_vec();
+    let rows = changed
+        .iter()
+        .map(|row| DataValue::List(row.clone()))
+        .collect_vec();
+    make_const_rule(&mut program, CHANGED_ROWS, bindings, rows);
+    Some(program)
+}
+
+/// The query of a view, taken by [changed_keys_program], made to give the rows with the
+/// keys in `keys` only
+fn keyed_program(mut program: InputProgram, keys: &[Tuple]) -> InputProgram {
+    let mut bindings = vec![];
+    if let Some(InputInlineRulesOrFixed::Rules { rules }) = program
+        .prog
+        .get_mut(&Symbol::new(PROG_ENTRY, Default::default()))
+    {
+        let rule = &mut rules[0];
+        bindings = rule
+            .head
+            .iter()
+            .zip(rule.aggr.iter())
+            .filter(|(_, aggr)| aggr.is_none())
+            .map(|(symb, _)| symb.clone())
+            .collect_vec();
+        let args = bindings
+            .iter()
+            .map(|var| Expr::Binding {
+                var: var.clone(),
+                tuple_pos: None,
+            })
+            .collect_vec();
+        rule.body.insert(
+            0,
+            InputAtom::Rule {
+                inner: InputRuleApplyAtom {
+                    name: Symbol::new(CHANGED_KEYS, rule.span),
+                    args,
+                    span: rule.span,
+                },
+            },
+        );
+    }
+    let rows = keys
+        .iter()
+        .map(|key| DataValue::List(key.clone()))
+        .collect_vec();
+    make_const_rule(&mut program, CHANGED_KEYS, bindings, rows);
+    program
+}
+
+impl<'a> SessionTx<'a> {
+    fn save_handle(&mut self, handle: &RelationHandle) -> Result<()> {
+        let name_key = vec![DataValue::Str(handle.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)
+    }
+
+    /// Create a stored relation holding the rows of the query in `script`, kept up to date
+    /// with the relations the query reads.
+    ///
+    /// Every statement writing into one of these relations computes again the rows of the
+    /// view with keys found from the rows written, and writes those that changed, running
+    /// the triggers and callbacks of the view for these. For queries reading the relation
+    /// written in ways not allowing this, see [SessionTx::maintain_views], the whole query
+    /// is evaluated again and the whole view read, which costs as much as the query at each
+    /// write. Reading the view then costs no more than reading any stored relation. Imports
+    /// and restores do not run the query, see [SessionTx::refresh_view].
+    pub(crate) fn create_materialized_view<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        name: &Symbol,
+        script: &str,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        if name.is_temp_store_name() {
+            bail!("A materialized view cannot be a temp relation")
+        }
+        let program = self.trigger_program(db, script, cur_vld)?;
+        if program.out_opts.store_relation.is_some() {
+            bail!("The query of a materialized view cannot write into relations")
+        }
+        let metadata = view_columns(&program)?;
+        let sources: BTreeSet<_> = read_relations(&program).into_iter().collect();
+        let mut source_handles = vec![];
+        for source in &sources {
+            if source.starts_with('_') {
+                bail!("The query of a materialized view cannot read the temp relation {source}")
+            }
+            source_handles.push(self.get_relation(source, true)?);
+        }
+
+        let mut view =
+            self.create_relation(InputRelationHandle::with_columns(&name.name, &metadata))?;
+        view.materialized = Some(MaterializedView {
+            script: script.to_string(),
+            sources,
+        });
+        self.save_handle(&view)?;
+        for mut source in source_handles {
+            source.views.insert(view.name.clone());
+            self.save_handle(&source)?;
+        }
+
+        let mut to_clear = vec![];
+        self.refresh_view(
+            db,
+            &view,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            &mut to_clear,
+        )?;
+        Ok(to_clear)
+    }
+
+    /// Remove the view from the relations its query reads, as it is being removed
+    pub(crate) fn unregister_view(&mut self, view: &RelationHandle) -> Result<()> {
+        if let Some(def) = &view.materialized {
+            for source in &def.sources {
+                let mut handle = self.get_relation(source, true)?;
+                handle.views.remove(&view.name);
+                self.save_handle(&handle)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Bring the materialized views reading `relation` up to date after it was written into.
+    /// `changed` holds the rows written, as they were and as they are, and `writes` the value
+    /// of `writes_started` when the write started.
+    ///
+    /// Only the rows of a view with keys found again from the rows written are computed
+    /// again, see [changed_keys_program]. The whole query is evaluated again for views not
+    /// allowing this, and for views reading other relations written since, by triggers, as
+    /// the rows written no longer tell all the rows that changed.
+    pub(crate) fn maintain_views<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        relation: &RelationHandle,
+        changed: &[Tuple],
+        writes: u64,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        to_clear: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        if changed.is_empty() {
+            return Ok(());
+        }
+        for name in &relation.views {
+            let view = self.get_relation(name, true)?;
+            self.maintain_view(
+                db,
+                &view,
+                relation,
+                changed,
+                writes,
+                cur_vld,
+                callback_targets,
+                callback_collector,
+                to_clear,
+            )
+            .wrap_err_with(|| format!("when maintaining the materialized view {name}"))?;
+        }
+        Ok(())
+    }
+
+    fn maintain_view<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        view: &RelationHandle,
+        source: &RelationHandle,
+        changed: &[Tuple],
+        writes: u64,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        to_clear: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        let def = match &view.materialized {
+            Some(def) => def,
+            None => bail!("The relation {} is not a materialized view", view.name),
+        };
+        let others_written = def.sources.iter().any(|other| {
+            *other != source.name
+                && matches!(self.last_writes.get(other), Some(last) if *last > writes)
+        });
+        let program = self.trigger_program(db, &def.script, cur_vld)?;
+        let keys_program = match changed_keys_program(&program, source, changed) {
+            Some(keys_program) if !others_written => keys_program,
+            _ => {
+                return self.refresh_view(
+                    db,
+                    view,
+                    cur_vld,
+                    callback_targets,
+                    callback_collector,
+                    to_clear,
+                )
+            }
+        };
+        let (keys, cleanups) = db.run_query(
+            self,
+            keys_program,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            false,
+        )?;
+        to_clear.extend(cleanups);
+        if keys.rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut stale = BTreeMap::new();
+        for key in &keys.rows {
+            if let Some(tuple) = view.get(self, key)? {
+                stale.insert(key.clone(), tuple);
+            }
+        }
+        let positions = view_column_positions(&program);
+        let program = keyed_program(program, &keys.rows);
+        let (rows, cleanups) = db.run_query(
+            self,
+            program,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            false,
+        )?;
+        to_clear.extend(cleanups);
+        self.write_view_changes(
+            db,
+            view,
+            stale,
+            rows.rows,
+            &positions,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            to_clear,
+        )
+    }
+
+    /// Evaluate the query of a materialized view, and write the rows that differ from those
+    /// stored. `::refresh materialized` runs it for views to catch up with imported rows.
+    pub(crate) fn refresh_view<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        view: &RelationHandle,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        to_clear: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        let def = match &view.materialized {
+            Some(def) => def,
+            None => bail!("The relation {} is not a materialized view", view.name),
+        };
+        let program = self.trigger_program(db, &def.script, cur_vld)?;
+        let positions = view_column_positions(&program);
+        let (rows, cleanups) = db.run_query(
+            self,
+            program,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            false,
+        )?;
+        to_clear.extend(cleanups);
+
+        let n_keys = view.metadata.keys.len();
+        let mut stale = BTreeMap::new();
+        for tuple in view.scan_all(self) {
+            let tuple = tuple?;
+            stale.insert(tuple[..n_keys].to_vec(), tuple);
+        }
+        self.write_view_changes(
+            db,
+            view,
+            stale,
+            rows.rows,
+            &positions,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            to_clear,
+        )
+    }
+
+    /// Write the rows of a view computed again in `rows`, given the rows they replace in
+    /// `stale` by their keys. The rows left in `stale` are removed. The rows of `rows` are in
+    /// the order of the head of the query, and are put in the order of the columns of the
+    /// view by `positions`, see [view_column_positions].
+    fn write_view_changes<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        view: &RelationHandle,
+        mut stale: BTreeMap<Tuple, Tuple>,
+        rows: Vec<Tuple>,
+        positions: &[usize],
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        to_clear: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        let n_keys = view.metadata.keys.len();
+        let mut put = vec![];
+        let mut only_added = true;
+        for row in rows {
+            let row = positions.iter().map(|i| row[*i].clone()).collect_vec();
+            match stale.remove(&row[..n_keys]) {
+                Some(old) if old == row => {}
+                Some(_) => {
+                    only_added = false;
+                    put.push(row);
+                }
+                None => put.push(row),
+            }
+        }
+        let rm = stale.into_values().collect_vec();
+        if put.is_empty() && rm.is_empty() {
+            return Ok(());
+        }
+        self.writes_started += 1;
+        let writes = self.writes_started;
+        self.record_relation_write(&view.name, only_added && rm.is_empty());
+        let changed = self.write_view_rows(
+            db,
+            view,
+            put,
+            rm,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            to_clear,
+        )?;
+        self.maintain_views(
+            db,
+            view,
+            &changed,
+            writes,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            to_clear,
+        )
+    }
+}
@@ -12,11 +12,12 @@ use crate::data::expr::{eval_bytecode, eval_bytecode_pred, Bytecode};
 use crate::data::tuple::Tuple;
 use crate::fts::tokenizer::TextAnalyzer;
 use crate::fts::TokenizerConfig;
+use crate::runtime::degraded::{compile_index_expr, InconsistentIndex};
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, Expr, SourceSpan, Symbol};
 use itertools::Itertools;
-use miette::{bail, miette, Result};
+use miette::{bail, Result};
 use quadrature::integrate;
 use rand::{thread_rng, RngCore};
 use rustc_hash::FxHashSet;
@@ -156,6 +157,17 @@ impl<'a> SessionTx<'a> {
             _ => bail!("Cannot search for value {:?} in a LSH index", q),
         };
         let chunk_size = config.manifest.n_rows_in_band * std::mem::size_of::<u32>();
+        if config.scan_base {
+            return self.lsh_search_by_scan(
+                &bytes,
+                chunk_size,
+                config,
+                stack,
+                filter_code,
+                perms,
+                tokenizer,
+            );
+        }
         let mut key_prefix = Vec::with_capacity(1);
         let mut found_tuples: FxHashSet<_> = FxHashSet::default();
         let early_stopper = if filter_code.is_some() {
@@ -181,10 +193,12 @@ impl<'a> SessionTx<'a> {
         }
         let mut ret = vec![];
         for key in found_tuples {
-            let orig_tuple = config
-                .base_handle
-                .get(self, &key)?
-                .ok_or_else(|| miette!("Tuple not found in base LSH relation"))?;
+            let orig_tuple = config.base_handle.get(self, &key)?.ok_or_else(|| {
+                InconsistentIndex(
+                    config.idx_handle.name.to_string(),
+                    format!("cannot find the indexed row {:?}", key),
+                )
+            })?;
             if let Some((filter_code, span)) = filter_code {
                 if !eval_bytecode_pred(filter_code, &orig_tuple, stack, *span)? {
                     continue;
@@ -199,6 +213,51 @@ impl<'a> SessionTx<'a> {
         }
         Ok(ret)
     }
+    /// Search by hashing all rows of the relation, for an index that cannot be read
+    #[allow(clippy::too_many_arguments)]
+    fn lsh_search_by_scan(
+        &self,
+        bytes: &[u8],
+        chunk_size: usize,
+        config: &LshSearch,
+        stack: &mut Vec<DataValue>,
+        filter_code: &Option<(Vec<Bytecode>, SourceSpan)>,
+        perms: &HashPermutations,
+        tokenizer: &TextAnalyzer,
+    ) -> Result<Vec<Tuple>> {
+        let extractor = compile_index_expr(&config.base_handle, &config.manifest.extractor)?;
+        let mut ret = vec![];
+        for tuple in config.base_handle.scan_all(self) {
+            let tuple = tuple?;
+            let min_hash = match eval_bytecode(&extractor, &tuple, stack)? {
+                DataValue::List(l) => HashValues::new(l.iter(), perms),
+                DataValue::Str(s) => {
+                    let n_grams = tokenizer.unique_ngrams(&s, config.manifest.n_gram);
+                    HashValues::new(n_grams.iter(), perms)
+                }
+                _ => continue,
+            };
+            let found = bytes
+                .chunks_exact(chunk_size)
+                .zip(min_hash.get_bytes().chunks_exact(chunk_size))
+                .any(|(q, v)| q == v);
+            if !found {
+                continue;
+            }
+            if let Some((filter_code, span)) = filter_code {
+                if !eval_bytecode_pred(filter_code, &tuple, stack, *span)? {
+                    continue;
+                }
+            }
+            ret.push(tuple);
+            if let Some(k) = config.k {
+                if ret.len() >= k {
+                    break;
+                }
+            }
+        }
+        Ok(ret)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -210,6 +269,9 @@ pub(crate) struct LshSearch {
     pub(crate) k: Option<usize>,
     pub(crate) query: Symbol,
     pub(crate) filter: Option<Expr>,
+    /// Set when the index is read around, the relation is scanned instead,
+    /// see [crate::Db::set_index_fallback]
+    pub(crate) scan_base: bool,
     pub(crate) span: SourceSpan,
 }
 
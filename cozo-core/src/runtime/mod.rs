@@ -6,12 +6,50 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub(crate) mod advisor;
+#[cfg(feature = "arrow")]
+pub(crate) mod arrow;
+pub(crate) mod audit;
+pub(crate) mod branch;
 pub(crate) mod callback;
+pub(crate) mod clock;
+pub(crate) mod columns;
 pub(crate) mod db;
+pub(crate) mod degraded;
+pub(crate) mod diff;
+pub(crate) mod encryption;
+pub(crate) mod export;
+pub(crate) mod fixed_rule_cache;
+pub(crate) mod fixpoint_cache;
+pub(crate) mod graph_io;
+pub(crate) mod history;
 pub(crate) mod imperative;
+pub(crate) mod import;
+pub(crate) mod ingest;
+pub(crate) mod jobs;
+pub(crate) mod lifecycle;
+pub(crate) mod limits;
+pub(crate) mod logging;
+pub(crate) mod masking;
+pub(crate) mod materialized;
+pub(crate) mod partition;
+pub(crate) mod pinned;
+pub(crate) mod prepared;
+pub(crate) mod profile;
+pub(crate) mod rdf;
 pub(crate) mod relation;
+pub(crate) mod replay;
+pub(crate) mod rerank;
+pub(crate) mod savepoint;
+pub(crate) mod scrub;
+pub(crate) mod stream;
 pub(crate) mod temp_store;
+pub(crate) mod test_runner;
 pub(crate) mod transact;
+pub(crate) mod trigger;
+pub(crate) mod usage;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod worker_pool;
 pub(crate) mod hnsw;
 pub(crate) mod minhash_lsh;
 #[cfg(test)]
@@ -0,0 +1,141 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::runtime::diff::stored_relations;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
+use crate::runtime::transact::SessionTx;
+use crate::storage::PartitionScheme;
+use crate::{Db, NamedRows, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' is partitioned")]
+#[diagnostic(code(tx::relation_partitioned))]
+#[diagnostic(help("Put its rows back together with '::partition drop {0}' first"))]
+pub(crate) struct RelationPartitioned(pub(crate) String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' is not partitioned")]
+#[diagnostic(code(tx::relation_not_partitioned))]
+struct RelationNotPartitioned(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot partition '{0}': {1}")]
+#[diagnostic(code(tx::bad_partitioning))]
+struct BadPartitioning(String, String);
+
+fn partition_columns() -> Vec<String> {
+    [
+        "relation",
+        "by",
+        "partition",
+        "column_family",
+        "lower",
+        "upper",
+    ]
+    .map(|s| s.to_string())
+    .to_vec()
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// The partitions of all partitioned relations, with the range of first key values
+    /// each holds for relations partitioned by range
+    pub(crate) fn list_partitions(&'s self, tx: &SessionTx<'_>) -> Result<NamedRows> {
+        let names: BTreeMap<_, _> = stored_relations(tx)?
+            .into_iter()
+            .map(|(name, handle)| (handle.id.0, name))
+            .collect();
+        let mut rows = vec![];
+        for (id, scheme, cfs) in self.db.partitions()? {
+            let relation = match names.get(&id) {
+                Some(name) => DataValue::from(name.as_str()),
+                // the relation was created or removed after the transaction started
+                None => continue,
+            };
+            for (idx, cf) in cfs.into_iter().enumerate() {
+                let (by, lower, upper) = match &scheme {
+                    PartitionScheme::Hash(_) => ("hash", DataValue::Null, DataValue::Null),
+                    PartitionScheme::Range(bounds) => (
+                        "range",
+                        idx.checked_sub(1)
+                            .map_or(DataValue::Null, |i| bounds[i].clone()),
+                        bounds.get(idx).cloned().unwrap_or(DataValue::Null),
+                    ),
+                };
+                rows.push(vec![
+                    relation.clone(),
+                    DataValue::from(by),
+                    DataValue::from(idx as i64),
+                    DataValue::from(cf),
+                    lower,
+                    upper,
+                ]);
+            }
+        }
+        Ok(NamedRows::new(partition_columns(), rows))
+    }
+
+    /// Spread the rows of a stored relation over partitions, or put them back together
+    /// if `scheme` is `None`. Writes to the relation must be locked out by the caller.
+    pub(crate) fn set_partitions(
+        &'s self,
+        tx: &SessionTx<'_>,
+        rel: &Symbol,
+        scheme: Option<&PartitionScheme>,
+    ) -> Result<()> {
+        let handle = tx.get_relation(rel, false)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "partitioning".to_string(),
+                handle.access_level
+            ))
+        }
+        let partitioned = tx.store_tx.is_partitioned(handle.id.0);
+        if scheme.is_some() && partitioned {
+            bail!(RelationPartitioned(handle.name.to_string()))
+        }
+        if scheme.is_none() && !partitioned {
+            bail!(RelationNotPartitioned(handle.name.to_string()))
+        }
+        let bad = |msg: &str| BadPartitioning(handle.name.to_string(), msg.to_string());
+        let scheme = match scheme {
+            Some(_) if handle.name.contains(':') => bail!(bad("indices cannot be partitioned")),
+            Some(_) if rel.is_temp_store_name() => {
+                bail!(bad("only stored relations can be partitioned"))
+            }
+            Some(PartitionScheme::Range(bounds)) => {
+                let col = match handle.metadata.keys.first() {
+                    Some(col) => col,
+                    None => bail!(bad("the relation has no key columns")),
+                };
+                // bounds are compared with the values stored, so must be of their type
+                let cur_vld = self.current_validity();
+                let mut coerced = vec![];
+                for bound in bounds {
+                    coerced.push(col.typing.coerce(bound.clone(), cur_vld)?);
+                }
+                if coerced.windows(2).any(|w| w[0] >= w[1]) {
+                    bail!(bad("the bounds of ranges must be ascending"))
+                }
+                Some(PartitionScheme::Range(coerced))
+            }
+            Some(PartitionScheme::Hash(_)) if handle.metadata.keys.is_empty() => {
+                bail!(bad("the relation has no key columns"))
+            }
+            scheme => scheme.cloned(),
+        };
+        self.db.set_partitions(handle.id.0, scheme.as_ref())
+    }
+}
@@ -0,0 +1,347 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::relation::{decode_tuple_from_kv, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+/// The memory a pinned relation may take when no limit is given
+pub(crate) const DEFAULT_MAX_PINNED_BYTES: usize = 16 << 20;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' is too large to be pinned: it takes more than {1} bytes")]
+#[diagnostic(code(pin::too_large))]
+#[diagnostic(help("Give a larger 'max_bytes' to '::pin', or leave the relation unpinned"))]
+struct PinnedTooLarge(String, usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot pin '{0}': {1}")]
+#[diagnostic(code(pin::bad_relation))]
+struct BadPin(String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' is not pinned")]
+#[diagnostic(code(pin::not_pinned))]
+struct NotPinned(String);
+
+/// Stored relations kept in memory in full, shared by all clones of a database.
+///
+/// Rows are loaded from a transaction and tagged with the version of the relation it saw.
+/// Every commit writing into a relation bumps its version, so a transaction seeing another
+/// version loads the rows again, and keeps them for the transactions that follow.
+/// A transaction that has written into a relation reads it from the storage instead.
+#[derive(Default)]
+pub(crate) struct PinnedRelations {
+    state: Mutex<BTreeMap<SmartString<LazyCompact>, PinnedRelation>>,
+}
+
+struct PinnedRelation {
+    max_bytes: usize,
+    rows: Option<Arc<PinnedRows>>,
+    /// The relation and version found larger than `max_bytes`, so not loaded again
+    too_large_at: Option<(RelationId, u64)>,
+    hits: Arc<AtomicU64>,
+    loads: u64,
+}
+
+/// The rows of a pinned relation at some version, by their encoded keys
+pub(crate) struct PinnedRows {
+    id: RelationId,
+    version: u64,
+    rows: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// The bytes taken by the keys and values
+    bytes: usize,
+    hits: Arc<AtomicU64>,
+}
+
+impl PinnedRows {
+    fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.hit();
+        self.rows.get(key).map(|v| v.as_slice())
+    }
+    pub(crate) fn range_count(&self, lower: &[u8], upper: &[u8]) -> usize {
+        self.hit();
+        if lower >= upper {
+            return 0;
+        }
+        self.rows
+            .range::<[u8], _>((Bound::Included(lower), Bound::Excluded(upper)))
+            .count()
+    }
+    pub(crate) fn range_scan_tuple(self: Arc<Self>, lower: &[u8], upper: &[u8]) -> PinnedScan {
+        self.hit();
+        PinnedScan {
+            rows: self,
+            last: None,
+            lower: lower.to_vec(),
+            upper: upper.to_vec(),
+        }
+    }
+}
+
+/// A scan over pinned rows, which keeps them alive while it goes on
+pub(crate) struct PinnedScan {
+    rows: Arc<PinnedRows>,
+    /// The last key returned, the scan goes on after it
+    last: Option<Vec<u8>>,
+    lower: Vec<u8>,
+    upper: Vec<u8>,
+}
+
+impl Iterator for PinnedScan {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.lower >= self.upper {
+            return None;
+        }
+        let start = match &self.last {
+            None => Bound::Included(self.lower.as_slice()),
+            Some(last) => Bound::Excluded(last.as_slice()),
+        };
+        let (k, v) = self
+            .rows
+            .rows
+            .range::<[u8], _>((start, Bound::Excluded(self.upper.as_slice())))
+            .next()?;
+        let tuple = decode_tuple_from_kv(k, v, None);
+        self.last = Some(k.clone());
+        Some(Ok(tuple))
+    }
+}
+
+fn pinned_columns() -> Vec<String> {
+    [
+        "relation",
+        "version",
+        "rows",
+        "bytes",
+        "max_bytes",
+        "hits",
+        "loads",
+    ]
+    .map(|s| s.to_string())
+    .to_vec()
+}
+
+impl PinnedRelations {
+    fn is_pinned(&self, name: &str) -> bool {
+        self.state.lock().unwrap().contains_key(name)
+    }
+    /// The rows of the relation at `version`, loaded with `load` if they are not in memory.
+    /// `None` if the relation is not pinned, or takes more memory than it may.
+    fn rows_at(
+        &self,
+        handle: &RelationHandle,
+        version: u64,
+        load: impl FnOnce(usize) -> Result<Option<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    ) -> Result<Option<Arc<PinnedRows>>> {
+        let (max_bytes, hits) = {
+            let state = self.state.lock().unwrap();
+            let pinned = match state.get(&handle.name) {
+                None => return Ok(None),
+                Some(pinned) => pinned,
+            };
+            if let Some(rows) = &pinned.rows {
+                if rows.id == handle.id && rows.version == version {
+                    return Ok(Some(rows.clone()));
+                }
+            }
+            if pinned.too_large_at == Some((handle.id, version)) {
+                return Ok(None);
+            }
+            (pinned.max_bytes, pinned.hits.clone())
+        };
+        // loading happens without the lock, other transactions may be loading as well
+        let loaded = load(max_bytes)?;
+        let mut state = self.state.lock().unwrap();
+        let pinned = match state.get_mut(&handle.name) {
+            // unpinned in the meantime
+            None => return Ok(None),
+            Some(pinned) => pinned,
+        };
+        let rows = match loaded {
+            None => {
+                pinned.too_large_at = Some((handle.id, version));
+                return Ok(None);
+            }
+            Some(rows) => rows,
+        };
+        let rows = Arc::new(PinnedRows {
+            id: handle.id,
+            version,
+            bytes: rows.iter().map(|(k, v)| k.len() + v.len()).sum(),
+            rows,
+            hits,
+        });
+        pinned.loads += 1;
+        // versions only go up, and a transaction started earlier must not replace newer rows
+        if !matches!(&pinned.rows, Some(r) if r.version >= version) {
+            pinned.rows = Some(rows.clone());
+        }
+        Ok(Some(rows))
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// The rows of a stored relation kept in memory, if it is pinned and this transaction
+    /// sees the same rows as are kept
+    pub(crate) fn pinned_rows(&self, handle: &RelationHandle) -> Result<Option<Arc<PinnedRows>>> {
+        if handle.is_temp || self.relation_writes.contains_key(&handle.name) {
+            return Ok(None);
+        }
+        if let Some(found) = self.pinned_views.lock().unwrap().get(&handle.name) {
+            return Ok(found.clone());
+        }
+        let found = if self.pinned.is_pinned(&handle.name) {
+            let version = self.relation_version(&handle.name)?;
+            self.pinned.rows_at(handle, version, |max_bytes| {
+                self.load_rows(handle, max_bytes)
+            })?
+        } else {
+            None
+        };
+        self.pinned_views
+            .lock()
+            .unwrap()
+            .insert(handle.name.clone(), found.clone());
+        Ok(found)
+    }
+    fn load_rows(
+        &self,
+        handle: &RelationHandle,
+        max_bytes: usize,
+    ) -> Result<Option<BTreeMap<Vec<u8>, Vec<u8>>>> {
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let mut rows = BTreeMap::new();
+        let mut bytes = 0;
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k, v) = kv_res?;
+            bytes += k.len() + v.len();
+            if bytes > max_bytes {
+                return Ok(None);
+            }
+            rows.insert(k, v);
+        }
+        Ok(Some(rows))
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// The pinned relations, with the memory taken by their rows and how often they were read
+    pub(crate) fn list_pinned(&'s self) -> Result<NamedRows> {
+        let state = self.pinned.state.lock().unwrap();
+        let rows = state
+            .iter()
+            .map(|(name, pinned)| {
+                let (version, n_rows, bytes) = match &pinned.rows {
+                    None => (DataValue::Null, DataValue::Null, DataValue::Null),
+                    Some(rows) => (
+                        DataValue::from(rows.version as i64),
+                        DataValue::from(rows.rows.len() as i64),
+                        DataValue::from(rows.bytes as i64),
+                    ),
+                };
+                vec![
+                    DataValue::from(name.as_str()),
+                    version,
+                    n_rows,
+                    bytes,
+                    DataValue::from(pinned.max_bytes as i64),
+                    DataValue::from(pinned.hits.load(Ordering::Relaxed) as i64),
+                    DataValue::from(pinned.loads as i64),
+                ]
+            })
+            .collect();
+        Ok(NamedRows::new(pinned_columns(), rows))
+    }
+
+    /// Keep the rows of a stored relation in memory, as long as they take at most
+    /// `max_bytes`. Pinning a pinned relation again changes its limit.
+    pub(crate) fn pin_relation(
+        &'s self,
+        tx: &SessionTx<'_>,
+        rel: &Symbol,
+        max_bytes: usize,
+    ) -> Result<()> {
+        let handle = tx.get_relation(rel, false)?;
+        if handle.name.contains(':') {
+            bail!(BadPin(
+                handle.name.to_string(),
+                "indices cannot be pinned".to_string()
+            ))
+        }
+        if handle.is_temp {
+            bail!(BadPin(
+                handle.name.to_string(),
+                "only stored relations can be pinned".to_string()
+            ))
+        }
+        let max_bytes = max_bytes.min(self.profile.get().max_pinned_bytes);
+        // load now, so that a relation too large is refused
+        let rows = match tx.load_rows(&handle, max_bytes)? {
+            None => bail!(PinnedTooLarge(handle.name.to_string(), max_bytes)),
+            Some(rows) => rows,
+        };
+        let version = tx.relation_version(&handle.name)?;
+        let mut state = self.pinned.state.lock().unwrap();
+        let pinned = state
+            .entry(handle.name.clone())
+            .or_insert_with(|| PinnedRelation {
+                max_bytes,
+                rows: None,
+                too_large_at: None,
+                hits: Default::default(),
+                loads: 0,
+            });
+        pinned.max_bytes = max_bytes;
+        pinned.too_large_at = None;
+        if !matches!(&pinned.rows, Some(r) if r.version > version) {
+            pinned.rows = Some(Arc::new(PinnedRows {
+                id: handle.id,
+                version,
+                bytes: rows.iter().map(|(k, v)| k.len() + v.len()).sum(),
+                rows,
+                hits: pinned.hits.clone(),
+            }));
+            pinned.loads += 1;
+        }
+        Ok(())
+    }
+
+    /// Stop keeping the rows of a relation in memory
+    pub(crate) fn unpin_relation(&'s self, rel: &Symbol) -> Result<()> {
+        if self
+            .pinned
+            .state
+            .lock()
+            .unwrap()
+            .remove(&rel.name)
+            .is_none()
+        {
+            bail!(NotPinned(rel.name.to_string()))
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, Result};
+
+use crate::data::program::InputProgram;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::parse::expr::ParamNotFoundError;
+use crate::parse::{parse_query_template, CozoScript, QueryTemplate};
+use crate::{Db, NamedRows, ScriptMutability, Storage};
+
+/// A script parsed once by [Db::prepare], to be run any number of times with different
+/// parameters by [Db::run_prepared].
+///
+/// Only parsing is saved, query plans are not cached: every run compiles, stratifies and
+/// plans the query again, against the relations and indices as they are then.
+///
+/// Only scripts consisting of a single query whose parameters are used as values in the
+/// bodies of rules are actually parsed once, as [PreparedQuery::is_parsed] tells. Other
+/// scripts, such as those using parameters in options like `:limit` or in time travel
+/// clauses, are parsed again on every run, as by [Db::run_script].
+#[derive(Clone)]
+pub struct PreparedQuery {
+    script: String,
+    template: Option<QueryTemplate>,
+}
+
+impl PreparedQuery {
+    /// The script that was prepared
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+    /// Whether the script is run without being parsed again
+    pub fn is_parsed(&self) -> bool {
+        self.template.is_some()
+    }
+}
+
+impl QueryTemplate {
+    /// The query with the parameters in `params` in place of the placeholders
+    fn bind(
+        &self,
+        params: &BTreeMap<String, DataValue>,
+        cur_vld: ValidityTs,
+    ) -> Result<InputProgram> {
+        let checked;
+        let params = if self.declared.is_empty() {
+            params
+        } else {
+            let mut with_declared = params.clone();
+            for decl in &self.declared {
+                with_declared.insert(decl.name.clone(), decl.check(params, cur_vld)?);
+            }
+            checked = with_declared;
+            &checked
+        };
+        let mut values = Vec::with_capacity(self.params.len());
+        for (span, name) in &self.params {
+            match params.get(name) {
+                Some(val) => values.push((*span, val)),
+                None => bail!(ParamNotFoundError(name.clone(), *span)),
+            }
+        }
+        let mut program = self.program.clone();
+        program.for_each_rule_const(&mut |val, span| {
+            if let Some((_, param)) = values.iter().find(|(s, _)| *s == span) {
+                *val = (*param).clone();
+            }
+        });
+        Ok(program)
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Parse the CozoScript passed in, for it to be run many times with different parameters
+    /// by [Db::run_prepared]. Parameters are referred to in the script as in
+    /// [Db::run_script], but are not given until the script is run.
+    ///
+    /// This only saves parsing the script: the query is still planned on every run,
+    /// see [PreparedQuery].
+    pub fn prepare(&self, payload: &str) -> Result<PreparedQuery> {
+        let template = parse_query_template(
+            payload,
+            &self.fixed_rules.read().unwrap(),
+            self.current_validity(),
+        )?;
+        Ok(PreparedQuery {
+            script: payload.to_string(),
+            template,
+        })
+    }
+
+    /// Run a script prepared by [Db::prepare], with the parameters `params`.
+    ///
+    /// This gives the same result as passing the script to [Db::run_script].
+    pub fn run_prepared(
+        &'s self,
+        prepared: &PreparedQuery,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        let template = match &prepared.template {
+            None => return self.run_script(&prepared.script, params, mutability),
+            Some(template) => template,
+        };
+        let cur_vld = self.current_validity();
+        let read_only = mutability == ScriptMutability::Immutable;
+        self.record_run(&prepared.script, &params, cur_vld, read_only, None, || {
+            let program = template.bind(&params, cur_vld)?;
+            self.run_parsed_script(
+                CozoScript::Single(program),
+                &prepared.script,
+                &params,
+                cur_vld,
+                read_only,
+                None,
+            )
+        })
+    }
+}
@@ -0,0 +1,126 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::Mutex;
+
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::{Db, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("{0} disabled by the resource profile of the database")]
+#[diagnostic(code(db::disabled_by_profile))]
+#[diagnostic(help("Use the 'full' profile, or enable it with 'Db::set_resource_profile'"))]
+pub(crate) struct DisabledByProfile(pub(crate) &'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unknown resource profile '{0}'")]
+#[diagnostic(code(db::unknown_profile))]
+#[diagnostic(help("The profiles are 'full' and 'lite'"))]
+struct UnknownProfile(String);
+
+/// What a database may use besides its storage, for deployments that need a predictable
+/// ceiling on memory and threads, such as embedded and IoT devices.
+///
+/// The default is [ResourceProfile::full], or [ResourceProfile::lite] when the crate is
+/// compiled with the `lite` feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResourceProfile {
+    /// Whether scripts may submit jobs with `::job submit`, which run on a background thread.
+    /// Background work started explicitly, such as
+    /// [crate::DbInstance::spawn_scrubbing], is not affected.
+    pub background_jobs: bool,
+    /// Whether the fixpoints of recursive queries are kept to resume from, which may
+    /// take up to a million tuples
+    pub fixpoint_cache: bool,
+    /// The most memory the rows of a relation pinned with `::pin` may take,
+    /// whatever `max_bytes` is given to it
+    pub max_pinned_bytes: usize,
+    /// Whether full-text search indices may be created
+    pub fts: bool,
+    /// Whether HNSW vector indices may be created
+    pub hnsw: bool,
+}
+
+impl ResourceProfile {
+    /// Everything enabled
+    pub fn full() -> Self {
+        Self {
+            background_jobs: true,
+            fixpoint_cache: true,
+            max_pinned_bytes: usize::MAX,
+            fts: true,
+            hnsw: true,
+        }
+    }
+    /// No background threads, no large caches and no FTS or HNSW indices
+    pub fn lite() -> Self {
+        Self {
+            background_jobs: false,
+            fixpoint_cache: false,
+            max_pinned_bytes: 1 << 20,
+            fts: false,
+            hnsw: false,
+        }
+    }
+    /// The profile named `full` or `lite`
+    pub fn by_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "full" => Self::full(),
+            "lite" => Self::lite(),
+            _ => bail!(UnknownProfile(name.to_string())),
+        })
+    }
+}
+
+impl Default for ResourceProfile {
+    fn default() -> Self {
+        if cfg!(feature = "lite") {
+            Self::lite()
+        } else {
+            Self::full()
+        }
+    }
+}
+
+/// The resource profile, shared by all clones of a database
+#[derive(Default)]
+pub(crate) struct ProfileState(Mutex<ResourceProfile>);
+
+impl ProfileState {
+    pub(crate) fn get(&self) -> ResourceProfile {
+        *self.0.lock().unwrap()
+    }
+    /// Fail with an error naming `what` if `enabled` is not set in the profile
+    pub(crate) fn ensure(
+        &self,
+        enabled: impl FnOnce(&ResourceProfile) -> bool,
+        what: &'static str,
+    ) -> Result<()> {
+        if !enabled(&self.get()) {
+            bail!(DisabledByProfile(what))
+        }
+        Ok(())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Set what the database may use besides its storage. Indices already created and jobs
+    /// already submitted are kept; the profile applies to what is done from now on.
+    pub fn set_resource_profile(&self, profile: ResourceProfile) {
+        *self.profile.0.lock().unwrap() = profile;
+        if !profile.fixpoint_cache {
+            self.fixpoint_cache.clear();
+        }
+    }
+    /// The profile set by [Db::set_resource_profile]
+    pub fn resource_profile(&self) -> ResourceProfile {
+        self.profile.get()
+    }
+}
@@ -0,0 +1,733 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use miette::{bail, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use thiserror::Error;
+
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::parse::sys::RdfImportConfig;
+use crate::parse::SourceSpan;
+use crate::runtime::relation::{InputRelationHandle, RelationHandle};
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XSD_NS: &str = "http://www.w3.org/2001/XMLSchema#";
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Malformed Turtle at line {0}: {1}")]
+#[diagnostic(code(rdf::bad_turtle))]
+struct BadTurtle(usize, String);
+
+/// An RDF term, or a variable in a pattern
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Term {
+    Iri(String),
+    Blank(String),
+    Literal {
+        lexical: String,
+        datatype: Option<String>,
+        lang: Option<String>,
+    },
+    Var(String),
+}
+
+impl Term {
+    /// The non-key columns of the term in the relation of terms:
+    /// `kind`, `value`, `datatype` and `lang`.
+    ///
+    /// Literals of the numeric and boolean XSD types are stored as numbers and booleans,
+    /// and strings lose the implicit `xsd:string` datatype.
+    pub(crate) fn columns(&self) -> Vec<DataValue> {
+        match self {
+            Term::Iri(iri) => vec![
+                DataValue::from("iri"),
+                DataValue::from(iri.as_str()),
+                DataValue::Null,
+                DataValue::Null,
+            ],
+            Term::Blank(label) | Term::Var(label) => vec![
+                DataValue::from("blank"),
+                DataValue::from(label.as_str()),
+                DataValue::Null,
+                DataValue::Null,
+            ],
+            Term::Literal {
+                lexical,
+                datatype,
+                lang,
+            } => {
+                let local = datatype
+                    .as_deref()
+                    .and_then(|dt| dt.strip_prefix(XSD_NS))
+                    .unwrap_or_default();
+                let value = match local {
+                    "integer" | "int" | "long" | "short" | "byte" | "nonNegativeInteger"
+                    | "positiveInteger" | "negativeInteger" | "nonPositiveInteger"
+                    | "unsignedInt" | "unsignedLong" | "unsignedShort" | "unsignedByte" => {
+                        lexical.trim().parse::<i64>().map(DataValue::from).ok()
+                    }
+                    "decimal" | "double" | "float" => {
+                        lexical.trim().parse::<f64>().map(DataValue::from).ok()
+                    }
+                    "boolean" => match lexical.trim() {
+                        "true" | "1" => Some(DataValue::from(true)),
+                        "false" | "0" => Some(DataValue::from(false)),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let datatype = match (local, datatype) {
+                    ("string", _) | (_, None) => DataValue::Null,
+                    (_, Some(dt)) => DataValue::from(dt.as_str()),
+                };
+                vec![
+                    DataValue::from("literal"),
+                    value.unwrap_or_else(|| DataValue::from(lexical.as_str())),
+                    datatype,
+                    match lang {
+                        Some(lang) => DataValue::from(lang.to_lowercase()),
+                        None => DataValue::Null,
+                    },
+                ]
+            }
+        }
+    }
+}
+
+/// The columns of the relation of triples and of the relation of terms
+pub(crate) fn rdf_metadata() -> (StoredRelationMetadata, StoredRelationMetadata) {
+    let col = |name: &str, coltype: ColType, nullable: bool| ColumnDef {
+        name: name.into(),
+        typing: NullableColType { coltype, nullable },
+        default_gen: None,
+    };
+    (
+        StoredRelationMetadata {
+            keys: vec![
+                col("s", ColType::Int, false),
+                col("p", ColType::Int, false),
+                col("o", ColType::Int, false),
+            ],
+            non_keys: vec![],
+        },
+        StoredRelationMetadata {
+            keys: vec![col("id", ColType::Int, false)],
+            non_keys: vec![
+                col("kind", ColType::String, false),
+                col("value", ColType::Any, false),
+                col("datatype", ColType::String, true),
+                col("lang", ColType::String, true),
+            ],
+        },
+    )
+}
+
+/// Parser of Turtle, and so of N-Triples. With `allow_vars`, the SPARQL variables
+/// `?x` and `$x` are accepted as terms, and the last `.` may be left out.
+pub(crate) struct TurtleParser<'a> {
+    src: &'a str,
+    pos: usize,
+    allow_vars: bool,
+    prefixes: BTreeMap<String, String>,
+    base: Option<String>,
+    generated_blanks: usize,
+    triples: Vec<[Term; 3]>,
+}
+
+impl<'a> TurtleParser<'a> {
+    pub(crate) fn new(src: &'a str, allow_vars: bool) -> Self {
+        let prefixes = [
+            ("rdf", RDF_NS),
+            ("rdfs", "http://www.w3.org/2000/01/rdf-schema#"),
+            ("xsd", XSD_NS),
+            ("owl", "http://www.w3.org/2002/07/owl#"),
+        ];
+        Self {
+            src,
+            pos: 0,
+            allow_vars,
+            prefixes: prefixes
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            base: None,
+            generated_blanks: 0,
+            triples: vec![],
+        }
+    }
+
+    pub(crate) fn parse(mut self) -> Result<Vec<[Term; 3]>> {
+        loop {
+            self.skip_ws();
+            if self.rest().is_empty() {
+                break;
+            }
+            if self.directive()? {
+                continue;
+            }
+            self.triples_stmt()?;
+            self.skip_ws();
+            if !self.eat(".") && !(self.allow_vars && self.rest().is_empty()) {
+                return Err(self.error("expected `.` after the triples"));
+            }
+        }
+        Ok(self.triples)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, msg: &str) -> miette::Report {
+        let line = self.src[..self.pos].matches('\n').count() + 1;
+        BadTurtle(line, msg.to_string()).into()
+    }
+
+    fn skip_ws(&mut self) {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('#') => while !matches!(self.bump(), None | Some('\n')) {},
+                _ => break,
+            }
+        }
+    }
+
+    /// A bare word such as a keyword or a prefixed name, without consuming it
+    fn peek_word(&self) -> &'a str {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| {
+                !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '%' | '\\' | '@'))
+            })
+            .unwrap_or(rest.len());
+        &rest[..end]
+    }
+
+    fn directive(&mut self) -> Result<bool> {
+        let word = self.peek_word();
+        let (is_prefix, sparql_style) = match word.to_ascii_lowercase().as_str() {
+            "@prefix" => (true, false),
+            "@base" => (false, false),
+            "prefix" => (true, true),
+            "base" => (false, true),
+            _ => return Ok(false),
+        };
+        if sparql_style && word.contains(':') {
+            return Ok(false);
+        }
+        self.pos += word.len();
+        self.skip_ws();
+        if is_prefix {
+            let name = self.peek_word();
+            let prefix = match name.strip_suffix(':') {
+                Some(prefix) if !prefix.contains(':') => prefix.to_string(),
+                _ => return Err(self.error("expected a prefix name ending with `:`")),
+            };
+            self.pos += name.len();
+            self.skip_ws();
+            let iri = self.iri_ref()?;
+            self.prefixes.insert(prefix, iri);
+        } else {
+            self.skip_ws();
+            self.base = Some(self.iri_ref()?);
+        }
+        if !sparql_style {
+            self.skip_ws();
+            if !self.eat(".") {
+                return Err(self.error("expected `.` after the directive"));
+            }
+        }
+        Ok(true)
+    }
+
+    fn new_blank(&mut self) -> Term {
+        self.generated_blanks += 1;
+        // `#` cannot appear in the labels of blank nodes written out
+        Term::Blank(format!("#{}", self.generated_blanks))
+    }
+
+    fn triples_stmt(&mut self) -> Result<()> {
+        if self.peek() == Some('[') {
+            let subject = self.blank_node_property_list()?;
+            self.skip_ws();
+            if !matches!(self.peek(), Some('.') | None) {
+                self.predicate_object_list(&subject)?;
+            }
+        } else {
+            let subject = self.term(false)?;
+            if matches!(subject, Term::Literal { .. }) {
+                return Err(self.error("a literal cannot be a subject"));
+            }
+            self.predicate_object_list(&subject)?;
+        }
+        Ok(())
+    }
+
+    fn predicate_object_list(&mut self, subject: &Term) -> Result<()> {
+        loop {
+            self.skip_ws();
+            let predicate = if self.peek_word() == "a" {
+                self.pos += 1;
+                Term::Iri(format!("{RDF_NS}type"))
+            } else {
+                match self.term(false)? {
+                    t @ (Term::Iri(_) | Term::Var(_)) => t,
+                    _ => return Err(self.error("a predicate must be an IRI")),
+                }
+            };
+            loop {
+                self.skip_ws();
+                let object = self.term(true)?;
+                self.triples
+                    .push([subject.clone(), predicate.clone(), object]);
+                self.skip_ws();
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.skip_ws();
+            if !self.eat(";") {
+                break;
+            }
+            // repeated and trailing semicolons are allowed
+            loop {
+                self.skip_ws();
+                if !self.eat(";") {
+                    break;
+                }
+            }
+            if matches!(self.peek(), Some('.') | Some(']') | None) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn blank_node_property_list(&mut self) -> Result<Term> {
+        self.eat("[");
+        let node = self.new_blank();
+        self.skip_ws();
+        if !self.eat("]") {
+            self.predicate_object_list(&node)?;
+            self.skip_ws();
+            if !self.eat("]") {
+                return Err(self.error("expected `]`"));
+            }
+        }
+        Ok(node)
+    }
+
+    fn collection(&mut self) -> Result<Term> {
+        self.eat("(");
+        let mut items = vec![];
+        loop {
+            self.skip_ws();
+            if self.eat(")") {
+                break;
+            }
+            if self.rest().is_empty() {
+                return Err(self.error("expected `)`"));
+            }
+            items.push(self.term(true)?);
+        }
+        let mut list = Term::Iri(format!("{RDF_NS}nil"));
+        for item in items.into_iter().rev() {
+            let node = self.new_blank();
+            self.triples
+                .push([node.clone(), Term::Iri(format!("{RDF_NS}first")), item]);
+            self.triples
+                .push([node.clone(), Term::Iri(format!("{RDF_NS}rest")), list]);
+            list = node;
+        }
+        Ok(list)
+    }
+
+    fn term(&mut self, as_object: bool) -> Result<Term> {
+        match self.peek() {
+            Some('<') => Ok(Term::Iri(self.iri_ref()?)),
+            Some('[') => self.blank_node_property_list(),
+            Some('(') => self.collection(),
+            Some('"') | Some('\'') if as_object => self.string_literal(),
+            Some('?') | Some('$') if self.allow_vars => {
+                self.bump();
+                let rest = self.rest();
+                let name = &rest[..rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len())];
+                if name.is_empty() {
+                    return Err(self.error("expected the name of a variable"));
+                }
+                self.pos += name.len();
+                Ok(Term::Var(name.to_string()))
+            }
+            Some(c) if as_object && (c.is_ascii_digit() || matches!(c, '+' | '-' | '.')) => {
+                self.numeric_literal()
+            }
+            _ => {
+                if self.eat("_:") {
+                    let label = self.peek_word().trim_end_matches('.');
+                    if label.is_empty() {
+                        return Err(self.error("expected the label of a blank node"));
+                    }
+                    self.pos += label.len();
+                    // blank nodes of a pattern are variables that are not returned
+                    return Ok(if self.allow_vars {
+                        Term::Var(format!("_:{label}"))
+                    } else {
+                        Term::Blank(label.to_string())
+                    });
+                }
+                let word = self.peek_word();
+                if as_object && (word == "true" || word == "false") {
+                    self.pos += word.len();
+                    return Ok(Term::Literal {
+                        lexical: word.to_string(),
+                        datatype: Some(format!("{XSD_NS}boolean")),
+                        lang: None,
+                    });
+                }
+                self.prefixed_name()
+            }
+        }
+    }
+
+    fn prefixed_name(&mut self) -> Result<Term> {
+        let word = self.peek_word().trim_end_matches('.');
+        let (prefix, local) = match word.split_once(':') {
+            Some(parts) => parts,
+            None => return Err(self.error("expected an IRI, a blank node or a literal")),
+        };
+        let namespace = match self.prefixes.get(prefix) {
+            Some(ns) => ns.clone(),
+            None => return Err(self.error(&format!("undeclared prefix `{prefix}`"))),
+        };
+        self.pos += word.len();
+        let mut iri = namespace;
+        let mut chars = local.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => iri.extend(chars.next()),
+                c => iri.push(c),
+            }
+        }
+        Ok(Term::Iri(iri))
+    }
+
+    fn iri_ref(&mut self) -> Result<String> {
+        if !self.eat("<") {
+            return Err(self.error("expected an IRI in angle brackets"));
+        }
+        let mut iri = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error("unterminated IRI")),
+                Some('>') => break,
+                Some('\\') => iri.push(self.unicode_escape()?),
+                Some(c) => iri.push(c),
+            }
+        }
+        Ok(self.resolve(iri))
+    }
+
+    /// Relative IRIs are resolved against the base, by plain concatenation
+    fn resolve(&self, iri: String) -> String {
+        let base = match &self.base {
+            Some(base) => base,
+            None => return iri,
+        };
+        let has_scheme = iri
+            .find(':')
+            .map(|i| !iri[..i].contains(['/', '?', '#']))
+            .unwrap_or(false);
+        if has_scheme {
+            return iri;
+        }
+        if iri.is_empty() {
+            base.clone()
+        } else if iri.starts_with('#') {
+            format!("{}{iri}", base.split('#').next().unwrap())
+        } else if let Some(path) = iri.strip_prefix('/') {
+            let authority_end = base
+                .find("://")
+                .and_then(|i| base[i + 3..].find('/').map(|j| i + 3 + j))
+                .unwrap_or(base.len());
+            format!("{}/{path}", &base[..authority_end])
+        } else {
+            let dir_end = base.rfind('/').map(|i| i + 1).unwrap_or(base.len());
+            format!("{}{iri}", &base[..dir_end])
+        }
+    }
+
+    fn unicode_escape(&mut self) -> Result<char> {
+        let len = match self.bump() {
+            Some('u') => 4,
+            Some('U') => 8,
+            _ => return Err(self.error("invalid escape")),
+        };
+        let rest = self.rest();
+        let hex = rest.get(..len).unwrap_or_default();
+        let c = u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += len;
+        Ok(c)
+    }
+
+    fn string_literal(&mut self) -> Result<Term> {
+        let quote = self.bump().unwrap();
+        let long = if self.rest().starts_with(quote) && self.rest()[1..].starts_with(quote) {
+            self.pos += 2;
+            true
+        } else {
+            false
+        };
+        let mut lexical = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error("unterminated string")),
+                Some('\\') => {
+                    let c = match self.peek() {
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('f') => '\u{c}',
+                        Some(c @ ('"' | '\'' | '\\')) => c,
+                        _ => {
+                            lexical.push(self.unicode_escape()?);
+                            continue;
+                        }
+                    };
+                    self.bump();
+                    lexical.push(c);
+                }
+                Some(c) if c == quote => {
+                    if !long {
+                        break;
+                    }
+                    let closing: String = [quote, quote].iter().collect();
+                    if self.eat(&closing) {
+                        // quotes just before the closing ones belong to the string
+                        while self.rest().starts_with(quote) {
+                            lexical.push(quote);
+                            self.pos += 1;
+                        }
+                        break;
+                    }
+                    lexical.push(c);
+                }
+                Some('\n') if !long => return Err(self.error("unterminated string")),
+                Some(c) => lexical.push(c),
+            }
+        }
+        let (datatype, lang) = if self.eat("@") {
+            let lang = self.peek_word().trim_end_matches('.');
+            self.pos += lang.len();
+            (None, Some(lang.to_string()))
+        } else if self.eat("^^") {
+            match self.term(false)? {
+                Term::Iri(dt) => (Some(dt), None),
+                _ => return Err(self.error("a datatype must be an IRI")),
+            }
+        } else {
+            (None, None)
+        };
+        Ok(Term::Literal {
+            lexical,
+            datatype,
+            lang,
+        })
+    }
+
+    fn numeric_literal(&mut self) -> Result<Term> {
+        let rest = self.rest();
+        let bytes = rest.as_bytes();
+        let mut end = 0;
+        if matches!(bytes.first(), Some(b'+' | b'-')) {
+            end += 1;
+        }
+        let digits = |from: usize| {
+            bytes[from..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count()
+        };
+        end += digits(end);
+        let mut kind = "integer";
+        // a dot followed by a digit is a decimal point, otherwise it ends the statement
+        if bytes.get(end) == Some(&b'.')
+            && matches!(bytes.get(end + 1), Some(b) if b.is_ascii_digit())
+        {
+            end += 1 + digits(end + 1);
+            kind = "decimal";
+        }
+        if matches!(bytes.get(end), Some(b'e' | b'E')) {
+            let mut exp_end = end + 1;
+            if matches!(bytes.get(exp_end), Some(b'+' | b'-')) {
+                exp_end += 1;
+            }
+            let exp_digits = digits(exp_end);
+            if exp_digits > 0 {
+                end = exp_end + exp_digits;
+                kind = "double";
+            }
+        }
+        let lexical = &rest[..end];
+        if !lexical.bytes().any(|b| b.is_ascii_digit()) {
+            return Err(self.error("expected a number"));
+        }
+        self.pos += end;
+        Ok(Term::Literal {
+            lexical: lexical.to_string(),
+            datatype: Some(format!("{XSD_NS}{kind}")),
+            lang: None,
+        })
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Load an N-Triples or Turtle file into the relation of triples, whose columns hold
+    /// the ids of the terms interned in the relation of terms.
+    ///
+    /// The relations are created if they do not exist. Otherwise the triples are added,
+    /// terms already present keeping their ids. Blank nodes of each file are distinct.
+    pub(crate) fn import_rdf(&mut self, config: &RdfImportConfig) -> Result<NamedRows> {
+        let src = fs::read_to_string(&config.path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("cannot read {}", config.path))?;
+        let triples = TurtleParser::new(&src, false).parse()?;
+
+        let (triple_meta, term_meta) = rdf_metadata();
+        let triple_handle = self.get_or_create_relation(&config.relation, triple_meta)?;
+        let term_handle = self.get_or_create_relation(&config.terms, term_meta)?;
+
+        let mut ids: BTreeMap<Vec<DataValue>, i64> = BTreeMap::new();
+        let mut next_id = 0;
+        for tuple in term_handle.scan_all(self) {
+            let tuple = tuple?;
+            let id = tuple[0].get_int().unwrap_or_default();
+            next_id = next_id.max(id + 1);
+            if tuple[1] != DataValue::from("blank") {
+                ids.insert(tuple[1..].to_vec(), id);
+            }
+        }
+
+        let mut blanks: BTreeMap<String, i64> = BTreeMap::new();
+        let mut new_terms = 0;
+        let mut written = 0;
+        for triple in triples {
+            let mut row = Vec::with_capacity(3);
+            for term in triple {
+                let columns = term.columns();
+                let id = match &term {
+                    Term::Blank(label) => blanks.get(label).copied(),
+                    _ => ids.get(&columns).copied(),
+                };
+                let id = match id {
+                    Some(id) => id,
+                    None => {
+                        let id = next_id;
+                        next_id += 1;
+                        let mut term_row = vec![DataValue::from(id)];
+                        term_row.extend_from_slice(&columns);
+                        let key = term_handle.encode_key_for_store(&term_row, SourceSpan(0, 0))?;
+                        let val = term_handle.encode_val_for_store(&term_row, SourceSpan(0, 0))?;
+                        self.store_tx.put(&key, &val)?;
+                        new_terms += 1;
+                        match term {
+                            Term::Blank(label) => blanks.insert(label, id),
+                            _ => ids.insert(columns, id),
+                        };
+                        id
+                    }
+                };
+                row.push(DataValue::from(id));
+            }
+            let key = triple_handle.encode_key_for_store(&row, SourceSpan(0, 0))?;
+            if !self.store_tx.exists(&key, false)? {
+                let val = triple_handle.encode_val_for_store(&row, SourceSpan(0, 0))?;
+                self.store_tx.put(&key, &val)?;
+                written += 1;
+            }
+        }
+        self.record_relation_write(&config.relation, true);
+        self.record_relation_write(&config.terms, true);
+        Ok(NamedRows::new(
+            vec![
+                "relation".to_string(),
+                "triples".to_string(),
+                "new_terms".to_string(),
+            ],
+            vec![vec![
+                DataValue::from(&config.relation as &str),
+                DataValue::from(written as i64),
+                DataValue::from(new_terms as i64),
+            ]],
+        ))
+    }
+
+    fn get_or_create_relation(
+        &mut self,
+        name: &str,
+        metadata: StoredRelationMetadata,
+    ) -> Result<RelationHandle> {
+        if self.relation_exists(name)? {
+            let handle = self.get_relation(name, false)?;
+            let names =
+                |cols: &[ColumnDef]| cols.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+            if names(&handle.metadata.keys) != names(&metadata.keys)
+                || names(&handle.metadata.non_keys) != names(&metadata.non_keys)
+            {
+                bail!("The relation {name} exists and does not have the columns of RDF data")
+            }
+            return Ok(handle);
+        }
+        let bindings = |cols: &[ColumnDef]| {
+            cols.iter()
+                .map(|c| Symbol::new(c.name.clone(), SourceSpan(0, 0)))
+                .collect()
+        };
+        self.create_relation(InputRelationHandle {
+            name: Symbol::new(name, SourceSpan(0, 0)),
+            key_bindings: bindings(&metadata.keys),
+            dep_bindings: bindings(&metadata.non_keys),
+            metadata,
+            expected_version: None,
+            span: SourceSpan(0, 0),
+        })
+    }
+}
@@ -6,8 +6,9 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
+use std::iter;
 use std::sync::atomic::Ordering;
 
 use itertools::Itertools;
@@ -19,6 +20,7 @@ use serde::Serialize;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::expr::Expr;
 use crate::data::memcmp::MemCmpEncoder;
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
@@ -29,9 +31,15 @@ use crate::parse::expr::build_expr;
 use crate::parse::sys::{FtsIndexConfig, HnswIndexConfig, MinHashLshConfig};
 use crate::parse::{CozoScriptParser, Rule, SourceSpan};
 use crate::query::compile::IndexPositionUse;
+use crate::runtime::encryption::ColumnCiphers;
+use crate::runtime::history::RetentionPolicy;
 use crate::runtime::hnsw::HnswIndexManifest;
+use crate::runtime::masking::MaskPolicy;
+use crate::runtime::materialized::{MaterializedView, RelationReadByViews};
 use crate::runtime::minhash_lsh::{HashPermutations, LshParams, MinHashLshIndexManifest, Weights};
+use crate::runtime::partition::RelationPartitioned;
 use crate::runtime::transact::SessionTx;
+use crate::runtime::trigger::{TriggerDef, TriggerOptions};
 use crate::utils::TempCollector;
 use crate::{NamedRows, StoreTx};
 
@@ -90,6 +98,35 @@ pub(crate) struct RelationHandle {
         (RelationHandle, RelationHandle, MinHashLshIndexManifest),
     >,
     pub(crate) description: SmartString<LazyCompact>,
+    #[serde(default)]
+    pub(crate) retention: Option<RetentionPolicy>,
+    #[serde(default)]
+    pub(crate) put_trigger_options: Vec<TriggerOptions>,
+    #[serde(default)]
+    pub(crate) rm_trigger_options: Vec<TriggerOptions>,
+    #[serde(default)]
+    pub(crate) replace_trigger_options: Vec<TriggerOptions>,
+    /// The encrypted columns, with the names of their keys
+    #[serde(default)]
+    pub(crate) encrypted: BTreeMap<SmartString<LazyCompact>, SmartString<LazyCompact>>,
+    /// The ciphers of the encrypted columns, attached when the handle is loaded
+    #[serde(skip)]
+    pub(crate) ciphers: Option<ColumnCiphers>,
+    /// The masked columns, shown masked to sessions without the `unmask` privilege
+    #[serde(default)]
+    pub(crate) masks: BTreeMap<SmartString<LazyCompact>, MaskPolicy>,
+    /// The masks applied to the rows read through this handle, by column position
+    #[serde(skip)]
+    pub(crate) active_masks: Option<Vec<(usize, MaskPolicy)>>,
+    /// The descriptions of the columns, set by `::describe set`
+    #[serde(default)]
+    pub(crate) column_descriptions: BTreeMap<SmartString<LazyCompact>, SmartString<LazyCompact>>,
+    /// The query giving the rows, for materialized views
+    #[serde(default)]
+    pub(crate) materialized: Option<MaterializedView>,
+    /// The materialized views whose queries read this relation, maintained as it is written
+    #[serde(default)]
+    pub(crate) views: BTreeSet<SmartString<LazyCompact>>,
 }
 
 impl RelationHandle {
@@ -138,6 +175,11 @@ impl Display for AccessLevel {
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot read stored relation '{0}' through index '{1}': {2}")]
+#[diagnostic(code(eval::unusable_index_hint))]
+struct UnusableIndexHint(String, String, String, #[label] SourceSpan);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Arity mismatch for stored relation {name}: expect {expect_arity}, got {actual_arity}")]
 #[diagnostic(code(eval::stored_rel_arity_mismatch))]
@@ -188,11 +230,21 @@ impl RelationHandle {
         );
         Ok(NamedRows::new(headers, rows))
     }
-    #[allow(dead_code)]
     pub(crate) fn amend_key_prefix(&self, data: &mut [u8]) {
         let prefix_bytes = self.id.0.to_be_bytes();
         data[0..8].copy_from_slice(&prefix_bytes);
     }
+    /// Make a stored row of a relation with the same columns into a row of this one
+    pub(crate) fn adopt_stored_row(
+        &self,
+        (mut k, mut v): (Vec<u8>, Vec<u8>),
+    ) -> (Vec<u8>, Vec<u8>) {
+        self.amend_key_prefix(&mut k);
+        if v.len() >= ENCODED_KEY_MIN_LEN {
+            self.amend_key_prefix(&mut v);
+        }
+        (k, v)
+    }
     pub(crate) fn choose_index(
         &self,
         arg_uses: &[IndexPositionUse],
@@ -244,6 +296,45 @@ impl RelationHandle {
         }
         chosen
     }
+    /// The index named by a `use_index` hint, with the same result as [Self::choose_index]
+    pub(crate) fn hinted_index(
+        &self,
+        index_name: &Symbol,
+        arg_uses: &[IndexPositionUse],
+        validity_query: bool,
+        tx_time_query: bool,
+    ) -> Result<(RelationHandle, Vec<usize>, bool)> {
+        let (manifest, mapper) = match self.indices.get(&index_name.name) {
+            Some(found) => found,
+            None => bail!(UnusableIndexHint(
+                self.name.to_string(),
+                index_name.to_string(),
+                "no such index".to_string(),
+                index_name.span
+            )),
+        };
+        if tx_time_query {
+            bail!(UnusableIndexHint(
+                self.name.to_string(),
+                index_name.to_string(),
+                "indices do not carry the transaction time".to_string(),
+                index_name.span
+            ))
+        }
+        if validity_query && *mapper.last().unwrap() != self.metadata.keys.len() - 1 {
+            bail!(UnusableIndexHint(
+                self.name.to_string(),
+                index_name.to_string(),
+                "the index does not end with the validity column".to_string(),
+                index_name.span
+            ))
+        }
+        let need_join = arg_uses
+            .iter()
+            .enumerate()
+            .any(|(i, pos_use)| *pos_use != IndexPositionUse::Ignored && !mapper.contains(&i));
+        Ok((manifest.clone(), mapper.clone(), need_join))
+    }
     pub(crate) fn encode_key_for_store(
         &self,
         tuple: &[DataValue],
@@ -280,6 +371,12 @@ impl RelationHandle {
         let start = self.metadata.keys.len();
         let len = self.metadata.non_keys.len();
         let mut ret = self.encode_key_prefix(len);
+        if let Some(ciphers) = &self.ciphers {
+            let mut vals = tuple[start..].to_vec();
+            ciphers.encrypt_vals(&mut vals)?;
+            vals.serialize(&mut Serializer::new(&mut ret)).unwrap();
+            return Ok(ret);
+        }
         tuple[start..]
             .serialize(&mut Serializer::new(&mut ret))
             .unwrap();
@@ -291,6 +388,12 @@ impl RelationHandle {
         _span: SourceSpan,
     ) -> Result<Vec<u8>> {
         let mut ret = self.encode_key_prefix(tuple.len());
+        if let Some(ciphers) = &self.ciphers {
+            let mut vals = tuple.to_vec();
+            ciphers.encrypt_vals(&mut vals)?;
+            vals.serialize(&mut Serializer::new(&mut ret)).unwrap();
+            return Ok(ret);
+        }
         tuple.serialize(&mut Serializer::new(&mut ret)).unwrap();
         Ok(ret)
     }
@@ -323,9 +426,32 @@ pub(crate) struct InputRelationHandle {
     pub(crate) metadata: StoredRelationMetadata,
     pub(crate) key_bindings: Vec<Symbol>,
     pub(crate) dep_bindings: Vec<Symbol>,
+    /// Set by `:update ... expecting version = expr`: the version column, and the version
+    /// the stored rows must have, computed from the output row
+    #[serde(default)]
+    pub(crate) expected_version: Option<(Symbol, Expr)>,
     pub(crate) span: SourceSpan,
 }
 
+impl InputRelationHandle {
+    /// The handle for creating a relation with the given columns
+    pub(crate) fn with_columns(name: &str, metadata: &StoredRelationMetadata) -> Self {
+        let bindings = |cols: &[ColumnDef]| {
+            cols.iter()
+                .map(|col| Symbol::new(col.name.clone(), Default::default()))
+                .collect_vec()
+        };
+        Self {
+            name: Symbol::new(name, Default::default()),
+            metadata: metadata.clone(),
+            key_bindings: bindings(&metadata.keys),
+            dep_bindings: bindings(&metadata.non_keys),
+            expected_version: None,
+            span: Default::default(),
+        }
+    }
+}
+
 impl Debug for RelationHandle {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Relation<{}>", self.name)
@@ -363,7 +489,35 @@ impl RelationHandle {
         if self.is_temp {
             tx.temp_store_tx.range_scan_tuple(&lower, &upper)
         } else {
-            tx.store_tx.range_scan_tuple(&lower, &upper)
+            self.scan_stored(tx, &lower, &upper)
+        }
+    }
+
+    /// Scan a stored relation, from memory if it is pinned
+    fn scan_stored<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        let rows: Box<dyn Iterator<Item = Result<Tuple>> + 'a> = match tx.pinned_rows(self) {
+            Ok(Some(pinned)) => Box::new(pinned.range_scan_tuple(lower, upper)),
+            Ok(None) => tx.store_tx.range_scan_tuple(lower, upper),
+            Err(err) => Box::new(iter::once(Err(err))),
+        };
+        self.masking(self.decrypting(rows))
+    }
+
+    /// The number of tuples in the relation, counted without decoding them
+    pub(crate) fn count_all(&self, tx: &SessionTx<'_>) -> Result<usize> {
+        let lower = Tuple::default().encode_as_key(self.id);
+        let upper = Tuple::default().encode_as_key(self.id.next());
+        if self.is_temp {
+            tx.temp_store_tx.range_count(&lower, &upper)
+        } else if let Some(pinned) = tx.pinned_rows(self)? {
+            Ok(pinned.range_count(&lower, &upper))
+        } else {
+            tx.store_tx.range_count(&lower, &upper)
         }
     }
 
@@ -378,7 +532,9 @@ impl RelationHandle {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&lower, &upper, valid_at)
         } else {
-            tx.store_tx.range_skip_scan_tuple(&lower, &upper, valid_at)
+            self.masking(
+                self.decrypting(tx.store_tx.range_skip_scan_tuple(&lower, &upper, valid_at)),
+            )
         }
     }
 
@@ -389,11 +545,20 @@ impl RelationHandle {
                 .temp_store_tx
                 .get(&key_data, false)?
                 .map(|val_data| decode_tuple_from_kv(&key_data, &val_data, Some(self.arity()))))
+        } else if let Some(pinned) = tx.pinned_rows(self)? {
+            pinned
+                .get(&key_data)
+                .map(|val_data| decode_tuple_from_kv(&key_data, val_data, Some(self.arity())))
+                .map(|tuple| self.decrypt_tuple(tuple))
+                .transpose()
+                .map(|found| found.map(|tuple| self.mask_tuple(tuple, false)))
         } else {
-            Ok(tx
-                .store_tx
+            tx.store_tx
                 .get(&key_data, false)?
-                .map(|val_data| decode_tuple_from_kv(&key_data, &val_data, Some(self.arity()))))
+                .map(|val_data| decode_tuple_from_kv(&key_data, &val_data, Some(self.arity())))
+                .map(|tuple| self.decrypt_tuple(tuple))
+                .transpose()
+                .map(|found| found.map(|tuple| self.mask_tuple(tuple, false)))
         }
     }
 
@@ -408,11 +573,20 @@ impl RelationHandle {
                 .temp_store_tx
                 .get(&key_data, false)?
                 .map(|val_data| rmp_serde::from_slice(&val_data[ENCODED_KEY_MIN_LEN..]).unwrap()))
+        } else if let Some(pinned) = tx.pinned_rows(self)? {
+            pinned
+                .get(&key_data)
+                .map(|val_data| rmp_serde::from_slice(&val_data[ENCODED_KEY_MIN_LEN..]).unwrap())
+                .map(|vals| self.decrypt_vals(vals))
+                .transpose()
+                .map(|found| found.map(|vals| self.mask_tuple(vals, true)))
         } else {
-            Ok(tx
-                .store_tx
+            tx.store_tx
                 .get(&key_data, false)?
-                .map(|val_data| rmp_serde::from_slice(&val_data[ENCODED_KEY_MIN_LEN..]).unwrap()))
+                .map(|val_data| rmp_serde::from_slice(&val_data[ENCODED_KEY_MIN_LEN..]).unwrap())
+                .map(|vals| self.decrypt_vals(vals))
+                .transpose()
+                .map(|found| found.map(|vals| self.mask_tuple(vals, true)))
         }
     }
 
@@ -420,6 +594,8 @@ impl RelationHandle {
         let key_data = key.encode_as_key(self.id);
         if self.is_temp {
             tx.temp_store_tx.exists(&key_data, false)
+        } else if let Some(pinned) = tx.pinned_rows(self)? {
+            Ok(pinned.get(&key_data).is_some())
         } else {
             tx.store_tx.exists(&key_data, false)
         }
@@ -440,8 +616,7 @@ impl RelationHandle {
             tx.temp_store_tx
                 .range_scan_tuple(&prefix_encoded, &upper_encoded)
         } else {
-            tx.store_tx
-                .range_scan_tuple(&prefix_encoded, &upper_encoded)
+            self.scan_stored(tx, &prefix_encoded, &upper_encoded)
         }
     }
 
@@ -461,8 +636,11 @@ impl RelationHandle {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
         } else {
-            tx.store_tx
-                .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
+            self.masking(self.decrypting(tx.store_tx.range_skip_scan_tuple(
+                &prefix_encoded,
+                &upper_encoded,
+                valid_at,
+            )))
         }
     }
 
@@ -484,7 +662,7 @@ impl RelationHandle {
             tx.temp_store_tx
                 .range_scan_tuple(&lower_encoded, &upper_encoded)
         } else {
-            tx.store_tx.range_scan_tuple(&lower_encoded, &upper_encoded)
+            self.scan_stored(tx, &lower_encoded, &upper_encoded)
         }
     }
     pub(crate) fn skip_scan_bounded_prefix<'a>(
@@ -506,8 +684,11 @@ impl RelationHandle {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
         } else {
-            tx.store_tx
-                .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
+            self.masking(self.decrypting(tx.store_tx.range_skip_scan_tuple(
+                &lower_encoded,
+                &upper_encoded,
+                valid_at,
+            )))
         }
     }
 }
@@ -553,9 +734,7 @@ impl<'a> SessionTx<'a> {
     pub(crate) fn set_relation_triggers(
         &mut self,
         name: &Symbol,
-        puts: &[String],
-        rms: &[String],
-        replaces: &[String],
+        triggers: &[TriggerDef],
     ) -> Result<()> {
         if name.name.starts_with('_') {
             bail!("Cannot set triggers for temp store")
@@ -568,9 +747,7 @@ impl<'a> SessionTx<'a> {
                 original.access_level
             ))
         }
-        original.put_triggers = puts.to_vec();
-        original.rm_triggers = rms.to_vec();
-        original.replace_triggers = replaces.to_vec();
+        original.set_triggers(triggers)?;
 
         let name_key =
             vec![DataValue::Str(original.name.clone())].encode_as_key(RelationId::SYSTEM);
@@ -601,6 +778,22 @@ impl<'a> SessionTx<'a> {
         }
 
         let metadata = input_meta.metadata.clone();
+        if metadata
+            .keys
+            .iter()
+            .any(|col| col.typing.coltype == ColType::TxTime)
+        {
+            bail!("Column of type TxTime cannot be part of the keys")
+        }
+        if metadata
+            .non_keys
+            .iter()
+            .filter(|col| col.typing.coltype == ColType::TxTime)
+            .count()
+            > 1
+        {
+            bail!("At most one column of type TxTime is allowed")
+        }
         let last_id = if is_temp {
             self.temp_store_id.fetch_add(1, Ordering::Relaxed) as u64
         } else {
@@ -620,6 +813,17 @@ impl<'a> SessionTx<'a> {
             fts_indices: Default::default(),
             lsh_indices: Default::default(),
             description: Default::default(),
+            retention: None,
+            put_trigger_options: vec![],
+            rm_trigger_options: vec![],
+            replace_trigger_options: vec![],
+            encrypted: Default::default(),
+            ciphers: None,
+            masks: Default::default(),
+            active_masks: None,
+            column_descriptions: Default::default(),
+            materialized: None,
+            views: Default::default(),
         };
 
         let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
@@ -659,7 +863,8 @@ impl<'a> SessionTx<'a> {
                 .get(&encoded, lock)?
                 .ok_or_else(|| StoredRelationNotFoundError(name.to_string()))?
         };
-        let metadata = RelationHandle::decode(&found)?;
+        let mut metadata = RelationHandle::decode(&found)?;
+        self.attach_ciphers(&mut metadata)?;
         Ok(metadata)
     }
     pub(crate) fn describe_relation(&mut self, name: &str, description: &str) -> Result<()> {
@@ -678,6 +883,42 @@ impl<'a> SessionTx<'a> {
 
         Ok(())
     }
+    /// Set the description of a column, or remove it if empty
+    pub(crate) fn describe_column(
+        &mut self,
+        name: &str,
+        column: &Symbol,
+        description: &str,
+    ) -> Result<()> {
+        let mut meta = self.get_relation(name, true)?;
+
+        if !meta
+            .metadata
+            .keys
+            .iter()
+            .chain(meta.metadata.non_keys.iter())
+            .any(|col| col.name == column.name)
+        {
+            bail!("The relation {} has no column {}", meta.name, column.name)
+        }
+        if description.is_empty() {
+            meta.column_descriptions.remove(&column.name);
+        } else {
+            meta.column_descriptions
+                .insert(column.name.clone(), SmartString::from(description));
+        }
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        if meta.is_temp {
+            self.temp_store_tx.put(&name_key, &meta_val)?;
+        } else {
+            self.store_tx.put(&name_key, &meta_val)?;
+        }
+
+        Ok(())
+    }
     pub(crate) fn destroy_relation(&mut self, name: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
         let is_temp = name.starts_with('_');
         let mut to_clean = vec![];
@@ -699,6 +940,16 @@ impl<'a> SessionTx<'a> {
                 store.access_level
             ))
         }
+        if !is_temp && self.store_tx.is_partitioned(store.id.0) {
+            bail!(RelationPartitioned(store.name.to_string()))
+        }
+        if !store.views.is_empty() {
+            bail!(RelationReadByViews(
+                store.name.to_string(),
+                store.views.iter().join(", ")
+            ))
+        }
+        self.unregister_view(&store)?;
 
         for k in store.indices.keys() {
             let more_to_clean = self.destroy_relation(&format!("{name}:{k}"))?;
@@ -710,6 +961,7 @@ impl<'a> SessionTx<'a> {
             to_clean.extend(more_to_clean);
         }
 
+        self.record_relation_write(name, false);
         let key = DataValue::from(name);
         let encoded = vec![key].encode_as_key(RelationId::SYSTEM);
         if is_temp {
@@ -747,6 +999,12 @@ impl<'a> SessionTx<'a> {
                 config.index_name.to_string()
             ));
         }
+        ensure!(
+            rel_handle.encrypted.is_empty() && rel_handle.masks.is_empty(),
+            "Cannot create the index {} on the relation {} with encrypted or masked columns",
+            config.index_name,
+            rel_handle.name
+        );
 
         let inv_idx_keys = rel_handle.metadata.keys.clone();
         let inv_idx_vals = vec![ColumnDef {
@@ -877,6 +1135,12 @@ impl<'a> SessionTx<'a> {
                 config.index_name.to_string()
             ));
         }
+        ensure!(
+            rel_handle.encrypted.is_empty() && rel_handle.masks.is_empty(),
+            "Cannot create the index {} on the relation {} with encrypted or masked columns",
+            config.index_name,
+            rel_handle.name
+        );
 
         // Build key columns definitions
         let mut idx_keys: Vec<ColumnDef> = vec![ColumnDef {
@@ -1018,6 +1282,12 @@ impl<'a> SessionTx<'a> {
                 config.index_name.to_string()
             ));
         }
+        ensure!(
+            rel_handle.encrypted.is_empty() && rel_handle.masks.is_empty(),
+            "Cannot create the index {} on the relation {} with encrypted or masked columns",
+            config.index_name,
+            rel_handle.name
+        );
 
         // Check that what we are indexing are really vectors
         if config.vec_fields.is_empty() {
@@ -1223,6 +1493,7 @@ impl<'a> SessionTx<'a> {
             },
             key_bindings,
             dep_bindings,
+            expected_version: None,
             span: Default::default(),
         };
         let idx_handle = self.create_relation(idx_handle)?;
@@ -1245,6 +1516,15 @@ impl<'a> SessionTx<'a> {
                 rel_name.name.to_string()
             ));
         }
+        if let Some(col) = cols.iter().find(|col| {
+            rel_handle.encrypted.contains_key(&col.name) || rel_handle.masks.contains_key(&col.name)
+        }) {
+            bail!(
+                "Cannot index the encrypted or masked column {} of the relation {}",
+                col.name,
+                rel_name.name
+            )
+        }
 
         // Build column definitions
         let mut col_defs = vec![];
@@ -1300,6 +1580,7 @@ impl<'a> SessionTx<'a> {
             metadata: idx_meta,
             key_bindings,
             dep_bindings: vec![],
+            expected_version: None,
             span: Default::default(),
         };
 
@@ -1431,7 +1712,18 @@ impl<'a> SessionTx<'a> {
                 rel.access_level
             ));
         }
+        if !rel.views.is_empty() {
+            bail!(RelationReadByViews(
+                rel.name.to_string(),
+                rel.views.iter().join(", ")
+            ))
+        }
+        if rel.materialized.is_some() {
+            bail!("Cannot rename the materialized view {}", rel.name)
+        }
         rel.name = new.name.clone();
+        self.record_relation_write(&old.name, false);
+        self.record_relation_write(&new.name, false);
 
         let mut meta_val = vec![];
         rel.serialize(&mut Serializer::new(&mut meta_val)).unwrap();
@@ -1440,6 +1732,88 @@ impl<'a> SessionTx<'a> {
 
         Ok(())
     }
+    /// Create a stored relation holding a copy of the rows of another, with the same
+    /// columns, plain indices, descriptions, encryption, masks and retention policy.
+    /// Triggers are not copied, so that writing into the copy has no effect elsewhere.
+    ///
+    /// The rows are copied as they are stored, without decoding or re-encrypting them.
+    /// This is not copy-on-write: none of the storage engines can share data between
+    /// relations, so the copy takes time and space in proportion to the rows of the
+    /// relation and its indices, and is made within the transaction, holding its locks.
+    pub(crate) fn clone_relation(&mut self, base: &Symbol, new: &Symbol) -> Result<()> {
+        if base.name.starts_with('_') || new.name.starts_with('_') {
+            bail!("Cannot clone temp relations");
+        }
+        let new_encoded = vec![DataValue::Str(new.name.clone())].encode_as_key(RelationId::SYSTEM);
+        if self.store_tx.exists(&new_encoded, true)? {
+            bail!(RelNameConflictError(new.name.to_string()))
+        };
+        let base_handle = self.get_relation(base, false)?;
+        if base_handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                base_handle.name.to_string(),
+                "cloning relation".to_string(),
+                base_handle.access_level
+            ));
+        }
+        if !base_handle.hnsw_indices.is_empty()
+            || !base_handle.fts_indices.is_empty()
+            || !base_handle.lsh_indices.is_empty()
+        {
+            bail!(
+                "Cannot clone relation '{}' with vector, FTS or LSH indices attached",
+                base_handle.name
+            )
+        }
+
+        let mut handle = self.create_relation(InputRelationHandle::with_columns(
+            &new.name,
+            &base_handle.metadata,
+        ))?;
+        self.copy_stored_rows(&base_handle, &handle)?;
+        for (idx_name, (idx_handle, extractor)) in &base_handle.indices {
+            let new_idx = self.create_relation(InputRelationHandle::with_columns(
+                &format!("{}:{}", new.name, idx_name),
+                &idx_handle.metadata,
+            ))?;
+            self.copy_stored_rows(idx_handle, &new_idx)?;
+            handle
+                .indices
+                .insert(idx_name.clone(), (new_idx, extractor.clone()));
+        }
+        handle.description = base_handle.description.clone();
+        handle.column_descriptions = base_handle.column_descriptions.clone();
+        handle.encrypted = base_handle.encrypted.clone();
+        handle.masks = base_handle.masks.clone();
+        handle.retention = base_handle.retention.clone();
+        self.record_relation_write(&new.name, true);
+
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&new_encoded, &meta_val)?;
+        Ok(())
+    }
+    /// Copy the stored rows of a relation into another with the same columns,
+    /// only changing the prefix of their keys and values
+    fn copy_stored_rows(&mut self, from: &RelationHandle, to: &RelationHandle) -> Result<()> {
+        let lower = Tuple::default().encode_as_key(from.id);
+        let upper = Tuple::default().encode_as_key(from.id.next());
+        if self.store_tx.supports_par_put() {
+            for kv in self.store_tx.range_scan(&lower, &upper) {
+                let (k, v) = to.adopt_stored_row(kv?);
+                self.store_tx.par_put(&k, &v)?;
+            }
+        } else {
+            let rows: Vec<_> = self.store_tx.range_scan(&lower, &upper).try_collect()?;
+            for kv in rows {
+                let (k, v) = to.adopt_stored_row(kv);
+                self.store_tx.put(&k, &v)?;
+            }
+        }
+        Ok(())
+    }
     pub(crate) fn rename_temp_relation(&mut self, old: Symbol, new: Symbol) -> Result<()> {
         let new_key = DataValue::Str(new.name.clone());
         let new_encoded = vec![new_key].encode_as_key(RelationId::SYSTEM);
@@ -0,0 +1,218 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::runtime::logging::log_script;
+use crate::{Db, NamedRows, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Line {0} of the replay log is not a recorded script: {1}")]
+#[diagnostic(code(replay::invalid_entry))]
+struct InvalidReplayEntry(usize, String);
+
+/// The replay log of a database, shared by all its clones
+#[derive(Default)]
+pub(crate) struct ReplayLog {
+    file: Mutex<Option<File>>,
+    /// Where the file is, for reading back what was recorded
+    path: Mutex<Option<String>>,
+}
+
+/// A script as recorded in the replay log, one JSON object per line
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct ReplayEntry {
+    /// When the script started, in seconds since the epoch
+    at: f64,
+    /// The clock of the database when the script started, in microseconds
+    pub(crate) now: i64,
+    pub(crate) script: String,
+    pub(crate) params: BTreeMap<String, DataValue>,
+    read_only: bool,
+    actor: Option<String>,
+    /// How long the script ran, in seconds
+    pub(crate) elapsed: f64,
+    rows: Option<usize>,
+    error: Option<String>,
+}
+
+fn replay_columns() -> Vec<String> {
+    [
+        "line",
+        "script",
+        "recorded_secs",
+        "replayed_secs",
+        "recorded_rows",
+        "replayed_rows",
+        "recorded_error",
+        "replayed_error",
+    ]
+    .map(|s| s.to_string())
+    .to_vec()
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Record every script run from now on, with its parameters, clock, timing and outcome,
+    /// by appending a line to the file at `path`. Pass `None` to stop recording.
+    ///
+    /// The file can be given to [Db::replay] to run the same scripts again, usually against
+    /// a copy of the database taken when recording started. Parameters are recorded in full,
+    /// so the file should be kept as safe as the database itself.
+    pub fn set_replay_log(&self, path: Option<&str>) -> Result<()> {
+        let file = path
+            .map(|path| OpenOptions::new().create(true).append(true).open(path))
+            .transpose()
+            .into_diagnostic()?;
+        *self.replay.file.lock().unwrap() = file;
+        *self.replay.path.lock().unwrap() = path.map(|path| path.to_string());
+        Ok(())
+    }
+
+    /// Run the scripts of a replay log written by [Db::set_replay_log], one after another
+    /// and in the order they finished when recorded.
+    ///
+    /// Each script runs with the clock fixed at the time it started when recorded, so that
+    /// `now()` and mutations of relations with validity give the same results. The clock of
+    /// the database is fixed while the replay goes on, and set back when it is done.
+    /// A row is returned for each script, comparing timing, number of rows and error with
+    /// those recorded. Scripts that fail do not stop the replay.
+    pub fn replay(&'s self, path: impl AsRef<Path>) -> Result<NamedRows> {
+        let file = File::open(path).into_diagnostic()?;
+        let saved_now = self.fixed_now();
+        let res = self.replay_entries(BufReader::new(file));
+        *self.fixed_now.lock().unwrap() = saved_now;
+        Ok(NamedRows::new(replay_columns(), res?))
+    }
+
+    fn replay_entries(&'s self, reader: impl BufRead) -> Result<Vec<Tuple>> {
+        let mut rows = vec![];
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line.into_diagnostic()?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = ReplayEntry::parse(idx, &line)?;
+            let now = ValidityTs(Reverse(entry.now));
+            *self.fixed_now.lock().unwrap() = Some(now);
+            let started = seconds_since_the_epoch()?;
+            let res = self.do_run_script(
+                &entry.script,
+                &entry.params,
+                now,
+                entry.read_only,
+                entry.actor.as_deref(),
+            );
+            let elapsed = seconds_since_the_epoch()? - started;
+            let count = |rows: Option<usize>| match rows {
+                None => DataValue::Null,
+                Some(n) => DataValue::from(n as i64),
+            };
+            let error = |err: Option<String>| match err {
+                None => DataValue::Null,
+                Some(err) => DataValue::from(err),
+            };
+            rows.push(vec![
+                DataValue::from((idx + 1) as i64),
+                DataValue::from(entry.script),
+                DataValue::from(entry.elapsed),
+                DataValue::from(elapsed),
+                count(entry.rows),
+                count(res.as_ref().ok().map(|r| r.rows.len())),
+                error(entry.error),
+                error(res.err().map(|err| err.to_string())),
+            ]);
+        }
+        Ok(rows)
+    }
+
+    /// Run a script, recording it in the replay log if there is one
+    pub(crate) fn run_script_recorded(
+        &'s self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+        cur_vld: ValidityTs,
+        read_only: bool,
+        actor: Option<&str>,
+    ) -> Result<NamedRows> {
+        self.record_run(payload, params, cur_vld, read_only, actor, || {
+            self.do_run_script(payload, params, cur_vld, read_only, actor)
+        })
+    }
+
+    /// Call `run`, which runs the script `payload`, logging the outcome and recording
+    /// the script in the replay log if there is one
+    pub(crate) fn record_run(
+        &'s self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+        cur_vld: ValidityTs,
+        read_only: bool,
+        actor: Option<&str>,
+        run: impl FnOnce() -> Result<NamedRows>,
+    ) -> Result<NamedRows> {
+        let at = seconds_since_the_epoch()?;
+        let res = run();
+        let elapsed = seconds_since_the_epoch()? - at;
+        log_script(self.session.as_deref(), payload, elapsed, &res);
+        if self.replay.file.lock().unwrap().is_none() {
+            return res;
+        }
+        let entry = ReplayEntry {
+            at,
+            now: cur_vld.0 .0,
+            script: payload.to_string(),
+            params: params.clone(),
+            read_only,
+            actor: actor.map(|a| a.to_string()),
+            elapsed,
+            rows: res.as_ref().ok().map(|r| r.rows.len()),
+            error: res.as_ref().err().map(|err| err.to_string()),
+        };
+        // the script has already run, so failing to record it must not fail it
+        if let Err(err) = self.replay.append(&entry) {
+            log::error!("cannot write to the replay log: {err:?}");
+        }
+        res
+    }
+}
+
+impl ReplayEntry {
+    /// The entry recorded on the line with the index `idx`
+    pub(crate) fn parse(idx: usize, line: &str) -> Result<Self> {
+        match serde_json::from_str(line) {
+            Ok(entry) => Ok(entry),
+            Err(err) => bail!(InvalidReplayEntry(idx + 1, err.to_string())),
+        }
+    }
+}
+
+impl ReplayLog {
+    /// The path of the file being recorded to, if any
+    pub(crate) fn path(&self) -> Option<String> {
+        self.path.lock().unwrap().clone()
+    }
+    fn append(&self, entry: &ReplayEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry).into_diagnostic()?;
+        line.push(b'\n');
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            file.write_all(&line).into_diagnostic()?;
+        }
+        Ok(())
+    }
+}
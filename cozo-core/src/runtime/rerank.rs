@@ -0,0 +1,201 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use crossbeam::sync::ShardedLock;
+use miette::{bail, ensure, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::{NormalFormAtom, TempSymbGen, Unification};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::transact::SessionTx;
+
+/// Reorders the rows found by vector and full-text searches, for example by scoring
+/// them with a cross-encoder, before the rest of the query sees them.
+///
+/// A reranker is registered with [crate::Db::register_reranker], and used by giving its name
+/// as the `rerank` parameter of a search.
+pub trait Reranker: Send + Sync {
+    /// Score the rows found for `query`, a higher score meaning a more relevant row.
+    /// Each row holds the columns of the indexed relation, followed by the values bound
+    /// by the search, such as the distance. Exactly one score must be returned for each row.
+    fn rerank(&self, query: &DataValue, rows: &[Tuple]) -> Result<Vec<f64>>;
+}
+
+impl<F> Reranker for F
+where
+    F: Fn(&DataValue, &[Tuple]) -> Result<Vec<f64>> + Send + Sync,
+{
+    fn rerank(&self, query: &DataValue, rows: &[Tuple]) -> Result<Vec<f64>> {
+        self(query, rows)
+    }
+}
+
+pub(crate) type RerankerRegistry = ShardedLock<BTreeMap<String, Arc<dyn Reranker>>>;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("No reranker named '{0}' is registered")]
+#[diagnostic(code(eval::reranker_not_found))]
+struct RerankerNotFound(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The parameter '{0}' requires the 'rerank' parameter")]
+#[diagnostic(code(parser::reranker_missing))]
+struct RerankerMissing(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Reranker '{0}' returned {2} scores for {1} rows")]
+#[diagnostic(code(eval::reranker_bad_output))]
+struct RerankerBadOutput(String, usize, usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The filter of a search cannot use the score of its reranker")]
+#[diagnostic(code(eval::filter_uses_rerank_score))]
+#[diagnostic(help("The filter is applied before reranking, filter in the rule body instead"))]
+struct FilterUsesRerankScore(#[label] SourceSpan);
+
+/// The optional reranking stage of a vector or full-text search
+#[derive(Clone)]
+pub(crate) struct RerankStage {
+    pub(crate) name: String,
+    pub(crate) reranker: Arc<dyn Reranker>,
+    /// The number of rows given to the reranker at once
+    pub(crate) batch_size: usize,
+    /// The number of rows kept after reranking, all of them if `None`
+    pub(crate) k: Option<usize>,
+    pub(crate) bind_score: Option<Symbol>,
+}
+
+impl Debug for RerankStage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RerankStage")
+            .field("name", &self.name)
+            .field("batch_size", &self.batch_size)
+            .field("k", &self.k)
+            .field("bind_score", &self.bind_score)
+            .finish()
+    }
+}
+
+impl RerankStage {
+    /// Take the parameters `rerank`, `rerank_k`, `rerank_batch_size` and `bind_rerank_score`
+    /// of a search, if it is to be reranked
+    pub(crate) fn take_from_parameters(
+        parameters: &mut BTreeMap<SmartString<LazyCompact>, Expr>,
+        tx: &SessionTx<'_>,
+        gen: &mut TempSymbGen,
+        conj: &mut Vec<NormalFormAtom>,
+    ) -> Result<Option<Self>> {
+        let name_expr = match parameters.remove("rerank") {
+            None => {
+                for param in ["rerank_k", "rerank_batch_size", "bind_rerank_score"] {
+                    if let Some(expr) = parameters.get(param) {
+                        bail!(RerankerMissing(param.to_string(), expr.span()))
+                    }
+                }
+                return Ok(None);
+            }
+            Some(expr) => expr,
+        };
+        let span = name_expr.span();
+        let name = match name_expr.eval_to_const()? {
+            DataValue::Str(s) => s.to_string(),
+            v => bail!(
+                "The 'rerank' parameter must be the name of a reranker, got {}",
+                v
+            ),
+        };
+        let reranker = tx
+            .rerankers
+            .read()
+            .unwrap()
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| RerankerNotFound(name.clone(), span))?;
+        let mut pos_int = |param: &str| -> Result<Option<usize>> {
+            match parameters.remove(param) {
+                None => Ok(None),
+                Some(expr) => match expr.clone().eval_to_const()?.get_int() {
+                    Some(i) if i > 0 => Ok(Some(i as usize)),
+                    _ => bail!(
+                        "The '{}' parameter must be a positive integer, got {}",
+                        param,
+                        expr
+                    ),
+                },
+            }
+        };
+        let k = pos_int("rerank_k")?;
+        let batch_size = pos_int("rerank_batch_size")?.unwrap_or(64);
+        let bind_score = match parameters.remove("bind_rerank_score") {
+            None => None,
+            Some(Expr::Binding { var, .. }) => Some(var),
+            Some(expr) => {
+                let span = expr.span();
+                let kw = gen.next(span);
+                conj.push(NormalFormAtom::Unification(Unification {
+                    binding: kw.clone(),
+                    expr,
+                    one_many_unif: false,
+                    span,
+                }));
+                Some(kw)
+            }
+        };
+        Ok(Some(Self {
+            name,
+            reranker,
+            batch_size,
+            k,
+            bind_score,
+        }))
+    }
+    /// Filters run before the reranker, so they cannot use its score
+    pub(crate) fn check_filter(&self, filter: &Option<Expr>) -> Result<()> {
+        if let (Some(filter), Some(score)) = (filter, &self.bind_score) {
+            ensure!(
+                !filter.bindings()?.contains(score),
+                FilterUsesRerankScore(filter.span())
+            );
+        }
+        Ok(())
+    }
+    /// Rerank the rows found for `query`, best first
+    pub(crate) fn apply(&self, query: &DataValue, rows: Vec<Tuple>) -> Result<Vec<Tuple>> {
+        let mut scored = Vec::with_capacity(rows.len());
+        for batch in rows.chunks(self.batch_size) {
+            let scores = self.reranker.rerank(query, batch)?;
+            ensure!(
+                scores.len() == batch.len(),
+                RerankerBadOutput(self.name.clone(), batch.len(), scores.len())
+            );
+            scored.extend(scores.into_iter().zip(batch.iter().cloned()));
+        }
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        if let Some(k) = self.k {
+            scored.truncate(k);
+        }
+        Ok(scored
+            .into_iter()
+            .map(|(score, mut row)| {
+                if self.bind_score.is_some() {
+                    row.push(DataValue::from(score));
+                }
+                row
+            })
+            .collect())
+    }
+}
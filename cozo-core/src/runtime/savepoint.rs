@@ -0,0 +1,247 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use miette::{Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::runtime::transact::SessionTx;
+use crate::storage::temp::TempTx;
+use crate::storage::StoreTx;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("There is no savepoint named '{0}' in the transaction")]
+#[diagnostic(code(tx::savepoint_not_found))]
+struct SavepointNotFound(String);
+
+/// Writes into the store since the oldest savepoint, with the values they replaced
+#[derive(Default)]
+struct UndoLog {
+    recording: bool,
+    entries: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+/// A store transaction recording the values replaced by its writes while savepoints exist
+struct UndoLoggingTx<'s> {
+    inner: Box<dyn StoreTx<'s> + 's>,
+    log: Arc<Mutex<UndoLog>>,
+}
+
+impl<'s> UndoLoggingTx<'s> {
+    fn record(&self, key: &[u8]) -> Result<()> {
+        let mut log = self.log.lock().unwrap();
+        if log.recording {
+            let prev = self.inner.get(key, false)?;
+            log.entries.push((key.to_vec(), prev));
+        }
+        Ok(())
+    }
+}
+
+impl<'s> StoreTx<'s> for UndoLoggingTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record(key)?;
+        self.inner.put(key, val)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record(key)?;
+        self.inner.par_put(key, val)
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.record(key)?;
+        self.inner.del(key)
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.record(key)?;
+        self.inner.par_del(key)
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        {
+            let mut log = self.log.lock().unwrap();
+            if log.recording {
+                for kv in self.inner.range_scan(lower, upper) {
+                    let (k, v) = kv?;
+                    log.entries.push((k, Some(v)));
+                }
+            }
+        }
+        self.inner.del_range_from_persisted(lower, upper)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+
+    fn is_partitioned(&self, relation: u64) -> bool {
+        self.inner.is_partitioned(relation)
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_tuple(lower, upper)
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.inner.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan(lower, upper)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.total_scan()
+    }
+}
+
+struct Savepoint<T> {
+    name: String,
+    undo_len: usize,
+    temp_store: TempTx,
+    relation_writes: BTreeMap<SmartString<LazyCompact>, bool>,
+    state: T,
+}
+
+/// The savepoints of a multi-transaction, the latest last.
+///
+/// Together with each savepoint, the caller keeps a `state` of its own, for example
+/// the callbacks collected so far, which is given back when the savepoint is rolled back to.
+pub(crate) struct Savepoints<T> {
+    log: Arc<Mutex<UndoLog>>,
+    stack: Vec<Savepoint<T>>,
+}
+
+impl<'a> SessionTx<'a> {
+    /// Make the transaction able to roll back to savepoints, by recording
+    /// what its writes into the store replace once a savepoint is taken
+    pub(crate) fn with_savepoints<T>(self) -> (Self, Savepoints<T>) {
+        let log: Arc<Mutex<UndoLog>> = Default::default();
+        let tx = SessionTx {
+            store_tx: Box::new(UndoLoggingTx {
+                inner: self.store_tx,
+                log: log.clone(),
+            }),
+            ..self
+        };
+        let savepoints = Savepoints { log, stack: vec![] };
+        (tx, savepoints)
+    }
+}
+
+impl<T: Clone> Savepoints<T> {
+    fn position(&self, name: &str) -> Result<usize> {
+        self.stack
+            .iter()
+            .rposition(|s| s.name == name)
+            .ok_or_else(|| SavepointNotFound(name.to_string()).into())
+    }
+    /// Take a savepoint. A name already taken refers to the new savepoint from now on.
+    pub(crate) fn create(&mut self, name: &str, tx: &SessionTx<'_>, state: T) {
+        let mut log = self.log.lock().unwrap();
+        log.recording = true;
+        self.stack.push(Savepoint {
+            name: name.to_string(),
+            undo_len: log.entries.len(),
+            temp_store: tx.temp_store_tx.clone(),
+            relation_writes: tx.relation_writes.clone(),
+            state,
+        });
+    }
+    /// Undo everything the transaction did after the savepoint was taken, and return the
+    /// state kept with it. The savepoint is kept, the ones taken after it are released.
+    pub(crate) fn rollback_to(&mut self, name: &str, tx: &mut SessionTx<'_>) -> Result<T> {
+        let pos = self.position(name)?;
+        self.stack.truncate(pos + 1);
+        let savepoint = &self.stack[pos];
+        let undone = self
+            .log
+            .lock()
+            .unwrap()
+            .entries
+            .split_off(savepoint.undo_len);
+        for (key, prev) in undone.into_iter().rev() {
+            match prev {
+                Some(val) => tx.store_tx.put(&key, &val)?,
+                None => tx.store_tx.del(&key)?,
+            }
+        }
+        // the writes undoing the others have been recorded as well
+        self.log
+            .lock()
+            .unwrap()
+            .entries
+            .truncate(savepoint.undo_len);
+        tx.temp_store_tx = savepoint.temp_store.clone();
+        tx.relation_writes = savepoint.relation_writes.clone();
+        Ok(savepoint.state.clone())
+    }
+    /// Forget the savepoint and the ones taken after it, keeping what was done since
+    pub(crate) fn release(&mut self, name: &str) -> Result<()> {
+        let pos = self.position(name)?;
+        self.stack.truncate(pos);
+        if self.stack.is_empty() {
+            let mut log = self.log.lock().unwrap();
+            log.recording = false;
+            log.entries.clear();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::iter;
+
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::runtime::diff::stored_relations;
+use crate::runtime::relation::{decode_tuple_from_kv, RelationHandle};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+/// The read-only relation the findings of scrubbing are recorded in
+pub(crate) const SCRUB_RELATION: &str = "cozo.scrub";
+
+/// A problem found by scrubbing
+struct ScrubFinding {
+    kind: &'static str,
+    relation: Option<SmartString<LazyCompact>>,
+    /// The full name `relation:index` of the index found inconsistent
+    index: Option<SmartString<LazyCompact>>,
+    detail: String,
+}
+
+fn scrub_metadata() -> StoredRelationMetadata {
+    let col = |name: &str, coltype: ColType, nullable: bool| ColumnDef {
+        name: SmartString::from(name),
+        typing: NullableColType { coltype, nullable },
+        default_gen: None,
+    };
+    StoredRelationMetadata {
+        keys: vec![
+            col("at", ColType::Float, false),
+            col("seq", ColType::Int, false),
+        ],
+        non_keys: vec![
+            col("kind", ColType::String, false),
+            col("relation", ColType::String, true),
+            col("detail", ColType::String, false),
+        ],
+    }
+}
+
+/// Counts the problems of one kind found in an index, keeping the first as an example
+#[derive(Default)]
+struct IndexProblems {
+    count: usize,
+    example: Option<Tuple>,
+}
+
+impl IndexProblems {
+    fn add(&mut self, tuple: Tuple) {
+        self.count += 1;
+        if self.example.is_none() {
+            self.example = Some(tuple);
+        }
+    }
+    fn report(
+        self,
+        kind: &'static str,
+        what: &str,
+        relation: &RelationHandle,
+        index: &str,
+        findings: &mut Vec<ScrubFinding>,
+    ) {
+        if let Some(example) = self.example {
+            findings.push(ScrubFinding {
+                kind,
+                relation: Some(relation.name.clone()),
+                index: Some(SmartString::from(format!("{}:{index}", relation.name))),
+                detail: format!(
+                    "{} {what} in index {index}, e.g. {}",
+                    self.count,
+                    example.iter().join(", ")
+                ),
+            });
+        }
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Check that the rows of every plain index agree with the rows of its relation:
+    /// each row has its index row, and each index row belongs to a row with the same values.
+    /// Returns the number of relations checked.
+    fn check_indices(&self, findings: &mut Vec<ScrubFinding>) -> Result<usize> {
+        let relations = stored_relations(self)?;
+        for handle in relations.values() {
+            for (idx_name, (idx_handle, extractor)) in &handle.indices {
+                let mut missing = IndexProblems::default();
+                let lower = Tuple::default().encode_as_key(handle.id);
+                let upper = Tuple::default().encode_as_key(handle.id.next());
+                for kv in self.store_tx.range_scan(&lower, &upper) {
+                    let (k, v) = kv?;
+                    let tuple = decode_tuple_from_kv(&k, &v, Some(handle.arity()));
+                    let idx_tuple = extractor.iter().map(|i| tuple[*i].clone()).collect_vec();
+                    if !self
+                        .store_tx
+                        .exists(&idx_tuple.encode_as_key(idx_handle.id), false)?
+                    {
+                        missing.add(tuple[..handle.metadata.keys.len()].to_vec());
+                    }
+                }
+
+                // where the key columns of the relation are in the index rows
+                let key_positions = (0..handle.metadata.keys.len())
+                    .map(|j| extractor.iter().position(|i| *i == j).unwrap())
+                    .collect_vec();
+                let mut dangling = IndexProblems::default();
+                let mut stale = IndexProblems::default();
+                let lower = Tuple::default().encode_as_key(idx_handle.id);
+                let upper = Tuple::default().encode_as_key(idx_handle.id.next());
+                for kv in self.store_tx.range_scan(&lower, &upper) {
+                    let (k, v) = kv?;
+                    let idx_tuple = decode_tuple_from_kv(&k, &v, Some(idx_handle.arity()));
+                    let key = key_positions
+                        .iter()
+                        .map(|p| idx_tuple[*p].clone())
+                        .collect_vec();
+                    let key_data = key.encode_as_key(handle.id);
+                    match self.store_tx.get(&key_data, false)? {
+                        None => dangling.add(idx_tuple),
+                        Some(val_data) => {
+                            let tuple =
+                                decode_tuple_from_kv(&key_data, &val_data, Some(handle.arity()));
+                            if extractor
+                                .iter()
+                                .zip(idx_tuple.iter())
+                                .any(|(i, val)| tuple[*i] != *val)
+                            {
+                                stale.add(idx_tuple);
+                            }
+                        }
+                    }
+                }
+
+                missing.report(
+                    "missing_index_row",
+                    "rows have no index row",
+                    handle,
+                    idx_name,
+                    findings,
+                );
+                dangling.report(
+                    "dangling_index_row",
+                    "index rows have no row",
+                    handle,
+                    idx_name,
+                    findings,
+                );
+                stale.report(
+                    "stale_index_row",
+                    "index rows do not match their row",
+                    handle,
+                    idx_name,
+                    findings,
+                );
+            }
+        }
+        Ok(relations.len())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Check the stored data for corruption: verify it against the checksums kept by the
+    /// storage engine, and check that the indices of stored relations agree with their rows.
+    ///
+    /// Problems found are logged and recorded in the read-only relation `cozo.scrub`.
+    /// With index fallback on, indices found inconsistent are read around until rebuilt,
+    /// see [Db::set_index_fallback].
+    /// Returns the number of relations checked, whether checksums were verified (they are
+    /// only kept by RocksDB), and the number of problems found.
+    pub fn scrub(&'s self) -> Result<NamedRows> {
+        let name = SmartString::from(SCRUB_RELATION);
+        let locks = self.obtain_relation_locks(iter::once(&name));
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+        let mut tx = self.transact_write()?;
+        let res = self.scrub_with_tx(&mut tx)?;
+        tx.commit_tx()?;
+        Ok(res)
+    }
+
+    pub(crate) fn scrub_with_tx(&'s self, tx: &mut SessionTx<'_>) -> Result<NamedRows> {
+        let mut findings = vec![];
+        let checksums_verified = match self.db.verify_checksums() {
+            Ok(verified) => verified,
+            Err(err) => {
+                findings.push(ScrubFinding {
+                    kind: "checksum",
+                    relation: None,
+                    index: None,
+                    detail: format!("{err}"),
+                });
+                true
+            }
+        };
+        let checked = tx.check_indices(&mut findings)?;
+
+        let at = seconds_since_the_epoch()?;
+        for (seq, finding) in findings.iter().enumerate() {
+            log::error!(
+                "scrubbing found {} in {}: {}",
+                finding.kind,
+                finding.relation.as_deref().unwrap_or("storage"),
+                finding.detail
+            );
+            tx.append_system_row(
+                SCRUB_RELATION,
+                scrub_metadata,
+                vec![
+                    DataValue::from(at),
+                    DataValue::from(seq as i64),
+                    DataValue::from(finding.kind),
+                    finding
+                        .relation
+                        .clone()
+                        .map(DataValue::Str)
+                        .unwrap_or(DataValue::Null),
+                    DataValue::from(finding.detail.as_str()),
+                ],
+            )?;
+            if let Some(index) = &finding.index {
+                self.degrade_index(index, &finding.detail);
+            }
+        }
+
+        Ok(NamedRows::new(
+            vec![
+                "relations".to_string(),
+                "checksums_verified".to_string(),
+                "findings".to_string(),
+            ],
+            vec![vec![
+                DataValue::from(checked as i64),
+                DataValue::from(checksums_verified),
+                DataValue::from(findings.len() as i64),
+            ]],
+        ))
+    }
+}
@@ -0,0 +1,206 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::BTreeMap;
+
+use crossbeam::channel::{Receiver, Sender};
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::parse::{parse_script, CozoScript};
+use crate::runtime::db::Poison;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{DataValue, ScriptMutability};
+use crate::{Db, NamedRows, Storage};
+
+/// What the thread running a streamed script sends to its [QueryStream]
+pub(crate) enum StreamEvent {
+    Headers(Vec<String>),
+    Row(Tuple),
+    /// The outcome of the script. Its rows are those that were not streamed.
+    Done(Result<NamedRows>),
+}
+
+/// Where the rows of the top-level query of a streamed script are sent, instead of
+/// being collected
+#[derive(Clone)]
+pub(crate) struct RowSink(Sender<StreamEvent>);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The stream of rows has been dropped before the query finished")]
+#[diagnostic(code(eval::stream_dropped))]
+struct StreamDropped;
+
+impl RowSink {
+    fn send(&self, event: StreamEvent) -> Result<()> {
+        if self.0.send(event).is_err() {
+            bail!(StreamDropped)
+        }
+        Ok(())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// The rows of the result of a query, and whether they were cut short by the result limits.
+    ///
+    /// For the top-level query of a streamed script, the rows are sent to the stream
+    /// one by one and none is returned. Result limits do not apply to them.
+    pub(crate) fn collect_result(
+        &self,
+        top_level: bool,
+        headers: &[String],
+        rows: impl Iterator<Item = Tuple>,
+        poison: &Poison,
+    ) -> Result<(Vec<Tuple>, bool)> {
+        match &self.row_sink {
+            Some(sink) if top_level => {
+                sink.send(StreamEvent::Headers(headers.to_vec()))?;
+                for row in rows {
+                    poison.check()?;
+                    // blocks while the buffer of the stream is full
+                    sink.send(StreamEvent::Row(row))?;
+                }
+                Ok((vec![], false))
+            }
+            _ => Ok(self.result_limits.collect(rows)),
+        }
+    }
+}
+
+/// The rows of a script run with [Db::run_script_streaming], as they are produced.
+///
+/// The script runs on a thread of its own, which waits whenever the rows it has produced
+/// are not consumed quickly enough. Dropping the stream before it is exhausted kills the script.
+pub struct QueryStream {
+    headers: Vec<String>,
+    events: Receiver<StreamEvent>,
+    /// The rows of a script that could not be streamed
+    pending: std::vec::IntoIter<Tuple>,
+    poison: Poison,
+    done: bool,
+}
+
+impl QueryStream {
+    /// Wait for the script to produce its headers, or fail
+    fn start(events: Receiver<StreamEvent>, poison: Poison) -> Result<Self> {
+        let mut ret = Self {
+            headers: vec![],
+            events,
+            pending: vec![].into_iter(),
+            poison,
+            done: false,
+        };
+        match ret.events.recv() {
+            Ok(StreamEvent::Headers(headers)) => ret.headers = headers,
+            Ok(StreamEvent::Done(res)) => {
+                let rows = res?;
+                ret.headers = rows.headers;
+                ret.pending = rows.rows.into_iter();
+                ret.done = true;
+            }
+            Ok(StreamEvent::Row(_)) => unreachable!(),
+            Err(_) => bail!("the thread running the streamed script has panicked"),
+        }
+        Ok(ret)
+    }
+    /// The headers of the rows
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+    /// Take up to `n` rows at once
+    pub fn next_chunk(&mut self, n: usize) -> Result<Vec<Tuple>> {
+        self.by_ref().take(n).collect()
+    }
+}
+
+impl Iterator for QueryStream {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(row) = self.pending.next() {
+            return Some(Ok(row));
+        }
+        if self.done {
+            return None;
+        }
+        match self.events.recv() {
+            Ok(StreamEvent::Row(row)) => Some(Ok(row)),
+            Ok(StreamEvent::Done(res)) => {
+                self.done = true;
+                match res {
+                    Ok(rows) => {
+                        self.pending = rows.rows.into_iter();
+                        self.pending.next().map(Ok)
+                    }
+                    Err(err) => Some(Err(err)),
+                }
+            }
+            Ok(StreamEvent::Headers(_)) => unreachable!(),
+            Err(_) => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+impl Drop for QueryStream {
+    fn drop(&mut self) {
+        if !self.done {
+            self.poison.kill();
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S> Db<S>
+where
+    S: for<'s> Storage<'s> + 'static,
+{
+    /// Run the CozoScript passed in, returning its rows one by one as they are read out
+    /// of the result of the query, instead of all at once in a [NamedRows].
+    ///
+    /// At most `buffer` rows are held waiting to be consumed, the query is paused until there
+    /// is room for more. Only scripts consisting of a single query that does not mutate stored
+    /// relations are actually streamed: the rows of other scripts are all produced at the end.
+    /// The query keeps its transaction open until the stream is exhausted or dropped.
+    /// Streamed scripts are not recorded in the replay log.
+    pub fn run_script_streaming(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        buffer: usize,
+    ) -> Result<QueryStream> {
+        let (sender, receiver) = crossbeam::channel::bounded(buffer.max(1));
+        let poison = Poison::default();
+        let mut db = self.clone();
+        db.job = Some(poison.clone());
+        let payload = payload.to_string();
+        let read_only = mutability == ScriptMutability::Immutable;
+        let cur_vld = self.current_validity();
+        std::thread::spawn(move || {
+            let parsed = parse_script(&payload, &params, &db.fixed_rules.read().unwrap(), cur_vld);
+            let res = match parsed {
+                Ok(script) => {
+                    if matches!(script, CozoScript::Single(_)) {
+                        db.row_sink = Some(RowSink(sender.clone()));
+                    }
+                    db.run_parsed_script(script, &payload, &params, cur_vld, read_only, None)
+                }
+                Err(err) => Err(err),
+            };
+            // the receiving end may be gone already, there is no one left to tell then
+            let _ = sender.send(StreamEvent::Done(res));
+        });
+        QueryStream::start(receiver, poison)
+    }
+}
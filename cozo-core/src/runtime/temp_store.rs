@@ -23,7 +23,7 @@ use crate::data::value::DataValue;
 
 /// A store holding temp data during evaluation of queries.
 /// The public interface is used in custom implementations of algorithms/utilities.
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct RegularTempStore {
     inner: BTreeMap<Tuple, bool>,
 }
@@ -59,6 +59,12 @@ impl RegularTempStore {
     pub fn put(&mut self, tuple: Tuple) {
         self.inner.insert(tuple, false);
     }
+    pub(crate) fn len(&self) -> usize {
+        self.inner.len()
+    }
+    pub(crate) fn tuples(&self) -> impl Iterator<Item = &Tuple> {
+        self.inner.keys()
+    }
     pub(crate) fn put_with_skip(&mut self, tuple: Tuple) {
         self.inner.insert(tuple, true);
     }
@@ -265,6 +271,23 @@ impl EpochStore {
             arity,
         }
     }
+    /// A store resuming from a previously computed `total`: only tuples not already in it
+    /// count as delta once the first epoch is merged in
+    pub(crate) fn new_resumed(arity: usize, total: RegularTempStore) -> Self {
+        Self {
+            total: TempStore::Normal(total),
+            delta: TempStore::Normal(RegularTempStore::default()),
+            use_total_for_delta: false,
+            arity,
+        }
+    }
+    /// A copy of all tuples in a store without aggregations
+    pub(crate) fn normal_total(&self) -> Option<RegularTempStore> {
+        match &self.total {
+            TempStore::Normal(total) => Some(total.clone()),
+            TempStore::MeetAggr(_) => None,
+        }
+    }
     pub(crate) fn new_meet(aggrs: &[Option<(Aggregation, Vec<DataValue>)>]) -> Result<Self> {
         Ok(Self {
             total: TempStore::MeetAggr(MeetAggrStore::new(aggrs.to_vec())?),
@@ -0,0 +1,148 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::{bail, Result};
+
+use crate::data::program::InputProgram;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::parse::sys::TestStmt;
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+const PASS: &str = "pass";
+const FAIL: &str = "fail";
+const ERROR: &str = "error";
+
+fn rows_to_value(mut rows: Vec<Tuple>) -> DataValue {
+    rows.sort();
+    DataValue::List(rows.into_iter().map(DataValue::List).collect())
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Run the statements of a `::test` script in a single transaction which is never committed,
+    /// so that nothing done by the setups and assertions is visible afterwards.
+    ///
+    /// Each assertion gives a row of its name, its status, which is `pass`, `fail` or `error`,
+    /// and a message. Rows are compared regardless of their order. A failing setup gives
+    /// an `error` row and stops the script, since the assertions after it cannot be trusted.
+    pub(crate) fn run_tests(
+        &'s self,
+        cur_vld: ValidityTs,
+        stmts: &[TestStmt],
+        read_only: bool,
+    ) -> Result<NamedRows> {
+        let programs = stmts
+            .iter()
+            .flat_map(|stmt| match stmt {
+                TestStmt::Setup(p) | TestStmt::AssertRows(_, p, _) => vec![p],
+                TestStmt::AssertEq(_, l, r) => vec![l, r],
+            })
+            .collect_vec();
+        let write_lock_names: BTreeSet<_> = programs
+            .iter()
+            .filter_map(|p| p.needs_write_lock())
+            .collect();
+        let is_write = !write_lock_names.is_empty();
+        if read_only && is_write {
+            bail!("write lock required for read-only query");
+        }
+        let write_locks = self.obtain_relation_locks(write_lock_names.iter());
+        let _write_lock_guards = write_locks.iter().map(|l| l.read().unwrap()).collect_vec();
+        let mut tx = if is_write {
+            self.transact_write()?
+        } else {
+            self.transact()?
+        };
+
+        let mut rows = vec![];
+        let mut n_asserts = 0;
+        for stmt in stmts {
+            let (name, outcome) = match stmt {
+                TestStmt::Setup(p) => match self.run_test_program(&mut tx, p, cur_vld) {
+                    Ok(_) => continue,
+                    Err(err) => {
+                        rows.push(vec![
+                            DataValue::from("setup"),
+                            DataValue::from(ERROR),
+                            DataValue::from(err.to_string()),
+                        ]);
+                        break;
+                    }
+                },
+                TestStmt::AssertRows(name, p, expected) => {
+                    n_asserts += 1;
+                    let outcome = self
+                        .run_test_program(&mut tx, p, cur_vld)
+                        .map(|got| (rows_to_value(expected.clone()), rows_to_value(got.rows)));
+                    (name, outcome)
+                }
+                TestStmt::AssertEq(name, l, r) => {
+                    n_asserts += 1;
+                    let outcome = self.run_test_program(&mut tx, l, cur_vld).and_then(|got| {
+                        let expected = self.run_test_program(&mut tx, r, cur_vld)?;
+                        Ok((rows_to_value(expected.rows), rows_to_value(got.rows)))
+                    });
+                    (name, outcome)
+                }
+            };
+            let name = match name {
+                Some(name) => name.clone(),
+                None => format!("assertion {n_asserts}"),
+            };
+            let (status, message) = match outcome {
+                Ok((expected, got)) if expected == got => (PASS, DataValue::Null),
+                Ok((expected, got)) => (
+                    FAIL,
+                    DataValue::from(format!("expected {expected}, got {got}")),
+                ),
+                Err(err) => (ERROR, DataValue::from(err.to_string())),
+            };
+            rows.push(vec![
+                DataValue::from(name),
+                DataValue::from(status),
+                message,
+            ]);
+        }
+        // the transaction is dropped without being committed, which discards all writes
+        drop(tx);
+        Ok(NamedRows::new(
+            vec![
+                "test".to_string(),
+                "status".to_string(),
+                "message".to_string(),
+            ],
+            rows,
+        ))
+    }
+
+    fn run_test_program(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        p: &InputProgram,
+        cur_vld: ValidityTs,
+    ) -> Result<NamedRows> {
+        let mut cleanups = vec![];
+        let mut callback_collector = BTreeMap::new();
+        let res = self.execute_single_program(
+            p.clone(),
+            tx,
+            &mut cleanups,
+            cur_vld,
+            &Default::default(),
+            &mut callback_collector,
+        )?;
+        for (lower, upper) in cleanups {
+            tx.store_tx.del_range_from_persisted(&lower, &upper)?;
+        }
+        Ok(res)
+    }
+}
@@ -8,6 +8,8 @@
  */
 
 use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 use itertools::Itertools;
@@ -17,13 +19,18 @@ use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
 use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
 use crate::data::value::DataValue;
 use crate::fixed_rule::FixedRulePayload;
 use crate::fts::{TokenizerCache, TokenizerConfig};
 use crate::parse::SourceSpan;
 use crate::runtime::callback::CallbackOp;
 use crate::runtime::db::Poison;
-use crate::{DbInstance, FixedRule, RegularTempStore, ScriptMutability};
+use crate::runtime::degraded::inconsistent_index;
+use crate::{
+    to_params, DbInstance, FixedRule, NamedRows, RegularTempStore, ResourceProfile,
+    ScriptMutability, SimpleFixedRule,
+};
 
 #[test]
 fn test_limit_offset() {
@@ -667,6 +674,56 @@ fn test_multi_tx() {
     assert!(db.run_default("?[a] := *a[a]").is_err());
 }
 
+#[test]
+fn test_multi_tx_savepoints() {
+    let db = DbInstance::default();
+    let tx = db.multi_transaction(true);
+    let rows = |script: &str| {
+        tx.run_script(script, Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    tx.run_script(":create a {a}", Default::default()).unwrap();
+    tx.run_script("?[a] <- [[1], [2]] :put a {a}", Default::default())
+        .unwrap();
+    tx.savepoint("first").unwrap();
+    tx.run_script("?[a] <- [[3]] :put a {a}", Default::default())
+        .unwrap();
+    tx.run_script("?[a] <- [[1]] :rm a {a}", Default::default())
+        .unwrap();
+    tx.savepoint("second").unwrap();
+    tx.run_script(":create b {b}", Default::default()).unwrap();
+    tx.run_script("?[a] <- [[4]] :replace _tmp {a}", Default::default())
+        .unwrap();
+    assert_eq!(rows("?[a] := *a[a]"), json!([[2], [3]]));
+
+    tx.rollback_to_savepoint("second").unwrap();
+    assert!(tx.run_script("?[b] := *b[b]", Default::default()).is_err());
+    assert!(tx
+        .run_script("?[a] := *_tmp[a]", Default::default())
+        .is_err());
+    assert_eq!(rows("?[a] := *a[a]"), json!([[2], [3]]));
+
+    tx.rollback_to_savepoint("first").unwrap();
+    assert_eq!(rows("?[a] := *a[a]"), json!([[1], [2]]));
+    // a savepoint taken after the one rolled back to is gone
+    assert!(tx.rollback_to_savepoint("second").is_err());
+    // the one rolled back to is kept
+    tx.run_script("?[a] <- [[5]] :put a {a}", Default::default())
+        .unwrap();
+    tx.rollback_to_savepoint("first").unwrap();
+    tx.run_script("?[a] <- [[6]] :put a {a}", Default::default())
+        .unwrap();
+    tx.release_savepoint("first").unwrap();
+    assert!(tx.rollback_to_savepoint("first").is_err());
+    tx.commit().unwrap();
+    assert_eq!(
+        db.run_default("?[a] := *a[a]").unwrap().into_json()["rows"],
+        json!([[1], [2], [6]])
+    );
+}
+
 #[test]
 fn test_vec_types() {
     let db = DbInstance::new("mem", "", "").unwrap();
@@ -1574,3 +1631,4422 @@ fn fts_drop() {
         ::fts drop entity:fts_index
     "#).unwrap();
 }
+
+#[test]
+fn history_retention() {
+    let db = DbInstance::default();
+    db.run_default(":create hist {k: Int, vld: Validity => v: Int}")
+        .unwrap();
+    db.run_default(
+        r#"
+        ?[k, vld, v] <- [[1, [1, true], 1], [1, [2, true], 2], [1, [3, true], 3],
+                         [2, [1, true], 10], [2, [2, false], 0]]
+        :put hist {k, vld => v}
+        "#,
+    )
+    .unwrap();
+    assert!(db.run_default("::history prune hist").is_err());
+    db.run_default("::history retention hist {keep_versions: 2}")
+        .unwrap();
+    let res = db.run_default("::history prune hist").unwrap();
+    assert_eq!(res.rows[0][2], DataValue::from(1));
+    let res = db.run_default("?[k, v] := *hist{k, vld, v}").unwrap();
+    assert_eq!(res.rows.len(), 4);
+    let res = db
+        .run_default("?[k, v] := *hist{k, v @ 2}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, 2]]));
+
+    // everything is older than a day, only the assertion effective at the cutoff survives
+    let res = db
+        .run_default("::history prune hist {keep_days: 1}")
+        .unwrap();
+    assert_eq!(res.rows[0][2], DataValue::from(3));
+    let res = db
+        .run_default("?[k, v] := *hist{k, vld, v}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, 3]]));
+
+    let res = db.prune_history().unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("hist"));
+    assert!(db
+        .run_default(":create plain {k: Int => v: Int}")
+        .and_then(|_| db.run_default("::history retention plain {keep_versions: 1}"))
+        .is_err());
+}
+
+#[test]
+fn bitemporal_tx_time() {
+    let db = DbInstance::default();
+    db.run_default(":create bt {k: Int, vld: Validity => v: Int, tt: TxTime}")
+        .unwrap();
+    // the supplied transaction time is ignored
+    db.run_default("?[k, vld, v, tt] <- [[1, [10, true], 1, [0, true]]] :put bt {k, vld => v, tt}")
+        .unwrap();
+    let res = db.run_default("?[tt] := *bt{tt}").unwrap();
+    let t1 = match &res.rows[0][0] {
+        DataValue::Validity(v) => v.timestamp.0 .0,
+        v => panic!("unexpected transaction time {v:?}"),
+    };
+    assert!(t1 > 0);
+    std::thread::sleep(Duration::from_millis(2));
+
+    // a backdated correction recorded in a later transaction
+    db.run_default("?[k, vld, v] <- [[1, [20, true], 2]] :put bt {k, vld => v}")
+        .unwrap();
+    let res = db
+        .run_default("?[v] := *bt{k: 1, v @ 30}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[2]]));
+    let res = db
+        .run_default(&format!("?[v] := *bt{{k: 1, v @ 30 @@ {t1}}}"))
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1]]));
+    let res = db
+        .run_default(&format!("?[v] := *bt[_, _, v, _ @@ {t1}]"))
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1]]));
+    let res = db
+        .run_default("?[v] := *bt{v @@ 'NOW'}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1], [2]]));
+
+    // recorded versions are immutable
+    assert!(db
+        .run_default("?[k, vld, v] <- [[1, [10, true], 3]] :put bt {k, vld => v}")
+        .is_err());
+    assert!(db
+        .run_default("?[k, vld] <- [[1, [10, true]]] :rm bt {k, vld}")
+        .is_err());
+
+    db.run_default(":create plain {k: Int => v: Int}").unwrap();
+    assert!(db.run_default("?[v] := *plain{v @@ 'NOW'}").is_err());
+    assert!(db
+        .run_default(":create bad {k: Int, tt: TxTime => v: Int}")
+        .is_err());
+}
+
+#[test]
+fn tx_time_ignores_fixed_clock() {
+    let db = DbInstance::default();
+    db.run_default(":create bt {k: Int, vld: Validity => v: Int, tt: TxTime}")
+        .unwrap();
+    let before = crate::data::functions::current_validity().0 .0;
+    // neither a query nor the database fixing the clock dates the transaction time
+    db.run_default("?[k, vld, v] <- [[1, [10, true], 1]] :put bt {k, vld => v} :fixed_now 1000")
+        .unwrap();
+    db.set_fixed_now(Some(1000.));
+    db.run_default("?[k, vld, v] <- [[2, [10, true], 1]] :put bt {k, vld => v}")
+        .unwrap();
+    db.set_fixed_now(None);
+    let after = crate::data::functions::current_validity().0 .0;
+    let res = db.run_default("?[k, tt] := *bt{k, tt}").unwrap();
+    assert_eq!(res.rows.len(), 2);
+    for row in res.rows {
+        match &row[1] {
+            DataValue::Validity(v) => {
+                assert!(v.timestamp.0 .0 >= before && v.timestamp.0 .0 <= after)
+            }
+            v => panic!("unexpected transaction time {v:?}"),
+        }
+    }
+}
+
+#[test]
+fn audit_log() {
+    let db = DbInstance::default();
+    db.run_default(":create before_audit {a}").unwrap();
+    db.set_audit_log(true);
+    let (_id, receiver) = db.register_callback("cozo.audit", None);
+
+    db.run_script_as(
+        "alice",
+        ":create audited {a => b}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    db.run_script(
+        "?[a, b] <- [[$a, 2]] :put audited {a => b}",
+        BTreeMap::from([("a".to_string(), DataValue::from(1))]),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    // reads are not recorded
+    db.run_default("?[a, b] := *audited{a, b}").unwrap();
+    // failed attempts are
+    assert!(db.run_default("?[a] <- [[1]] :put nonexistent {a}").is_err());
+    db.run_default("::index create audited:idx {b}").unwrap();
+
+    let res = db
+        .run_default("?[seq, actor, kind, failed] := *cozo.audit{seq, actor, kind, error}, failed = !is_null(error)")
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            [0, "alice", "ddl", false],
+            [1, null, "mutation", false],
+            [2, null, "mutation", true],
+            [3, null, "ddl", false]
+        ])
+    );
+    let res = db
+        .run_default("?[h] := *cozo.audit{seq: 1, params_hash: h}")
+        .unwrap();
+    assert_eq!(res.rows[0][0].get_str().unwrap().len(), 64);
+
+    // the log cannot be tampered with
+    assert!(db.run_default("?[at, seq] <- [[0., 0]] :rm cozo.audit {at, seq}").is_err());
+    assert!(db.run_default("::remove cozo.audit").is_err());
+
+    let (op, new_rows, _) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(op, CallbackOp::Put);
+    assert_eq!(new_rows.rows[0][2], DataValue::from("alice"));
+
+    db.set_audit_log(false);
+    db.run_default("?[a, b] <- [[3, 4]] :put audited {a => b}")
+        .unwrap();
+    let res = db.run_default("?[count(seq)] := *cozo.audit{seq}").unwrap();
+    // the two failed tampering attempts above were recorded as well
+    assert_eq!(res.rows[0][0], DataValue::from(6));
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn audit_seq_survives_reopen() {
+    let path = std::env::temp_dir().join(format!("cozo-audit-test-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let db = DbInstance::new("sqlite", &path, "").unwrap();
+    db.set_audit_log(true);
+    db.run_default(":create a {x}").unwrap();
+    db.run_default("?[x] <- [[1]] :put a {x}").unwrap();
+    drop(db);
+
+    let db = DbInstance::new("sqlite", &path, "").unwrap();
+    db.set_audit_log(true);
+    db.run_default("?[x] <- [[2]] :put a {x}").unwrap();
+    let res = db
+        .run_default("?[seq] := *cozo.audit{seq}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[0], [1], [2]]));
+    drop(db);
+    for suffix in ["", ".lock", ".pid"] {
+        let mut p = path.clone().into_os_string();
+        p.push(suffix);
+        let _ = std::fs::remove_file(p);
+    }
+}
+
+#[test]
+fn admin_classification() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x => y}").unwrap();
+    let requires_admin =
+        |script: &str| db.script_requires_admin(script, &Default::default()).unwrap();
+
+    assert!(requires_admin("::remove a"));
+    assert!(requires_admin("::compact"));
+    assert!(requires_admin("::rename a -> b"));
+    assert!(requires_admin("?[x, y] <- [[1, 2]] :replace a {x => y}"));
+    assert!(requires_admin("{::remove a}"));
+    assert!(requires_admin("{?[x, y] <- [[1, 2]] :replace a {x => y}}"));
+
+    assert!(!requires_admin("?[x, y] := *a{x, y}"));
+    assert!(!requires_admin("?[x, y] <- [[1, 2]] :put a {x => y}"));
+    assert!(!requires_admin("?[x, y] <- [[1, 2]] :replace _tmp {x => y}"));
+    assert!(!requires_admin("::relations"));
+    assert!(!requires_admin("::explain { ?[x] := *a{x} }"));
+
+    // everything reading files on the server
+    assert!(requires_admin("::import infer '/tmp/a.csv'"));
+    assert!(requires_admin("::graph import '/tmp/a.graphml'"));
+    assert!(requires_admin("::rdf import '/tmp/a.nt'"));
+    assert!(requires_admin("::diff '/tmp/a.db'"));
+    assert!(requires_admin("{::import infer '/tmp/a.csv'}"));
+}
+
+#[cfg(feature = "serve")]
+#[test]
+fn serve_writer_server_files() {
+    use crate::serve::{run_query_payload, QueryPayload};
+    use crate::ServeRole;
+
+    let db = DbInstance::default();
+    let path = std::env::temp_dir().join(format!("cozo-writer-{}.csv", std::process::id()));
+    std::fs::write(&path, "id,name\n1,alice\n").unwrap();
+    let run = |role: ServeRole, script: &str| {
+        let payload: QueryPayload = serde_json::from_value(json!({
+            "script": script,
+            "params": {"path": path.to_str().unwrap()},
+        }))
+        .unwrap();
+        run_query_payload(&db, role, payload)
+    };
+
+    assert!(run(ServeRole::Writer, "::import infer $path").is_none());
+    assert!(run(ServeRole::Writer, "::diff $path").is_none());
+    assert!(run(ServeRole::Writer, "{::import infer $path}").is_none());
+    let res = run(ServeRole::Admin, "::import infer $path").unwrap();
+    assert_eq!(res["ok"], json!(true));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(all(feature = "serve", feature = "requests"))]
+#[test]
+fn serve_embedded() {
+    use crate::{ServeAddr, ServeOptions};
+
+    let db = DbInstance::default();
+    db.run_default(":create a {x => y}").unwrap();
+    let server = db
+        .serve(
+            ServeAddr::Tcp("127.0.0.1:0".parse().unwrap()),
+            ServeOptions {
+                auth_token: Some("secret".to_string()),
+                read_only: false,
+            },
+        )
+        .unwrap();
+    let url = format!("http://{}/text-query", server.local_addr().unwrap());
+    let body = json!({"script": "?[x, y] <- [[1, 2]] :put a {x => y}", "params": {}});
+
+    let post = |body: &serde_json::Value| {
+        minreq::post(&url)
+            .with_header("content-type", "application/json")
+            .with_body(body.to_string())
+    };
+
+    let res = post(&body).send().unwrap();
+    assert_eq!(res.status_code, 401);
+
+    let res = post(&body)
+        .with_header("x-cozo-auth", "secret")
+        .send()
+        .unwrap();
+    assert_eq!(res.status_code, 200);
+
+    // the application sees writes made through the server, and vice versa
+    let rows = db.run_default("?[x, y] := *a{x, y}").unwrap().into_json();
+    assert_eq!(rows["rows"], json!([[1, 2]]));
+    db.run_default("?[x, y] <- [[3, 4]] :put a {x => y}").unwrap();
+    let res = post(&json!({"script": "?[count(x)] := *a{x}", "params": {}}))
+        .with_header("x-cozo-auth", "secret")
+        .send()
+        .unwrap();
+    let res: serde_json::Value = serde_json::from_str(res.as_str().unwrap()).unwrap();
+    assert_eq!(res["rows"], json!([[2]]));
+
+    server.shutdown();
+}
+
+#[test]
+fn close_db() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x}").unwrap();
+    let clone = db.clone();
+    let tx = db.multi_transaction(false);
+    assert!(!db.is_closed());
+
+    db.close().unwrap();
+    assert!(clone.is_closed());
+    assert!(clone.run_default("?[x] := *a{x}").is_err());
+    assert!(db.run_default("?[x] <- [[1]] :put a {x}").is_err());
+    assert!(db.export_relations(["a"].iter()).is_err());
+    // transactions opened before closing are refused further work
+    assert!(tx.run_script("?[x] := *a{x}", Default::default()).is_err());
+    // closing twice is harmless
+    db.close().unwrap();
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn multi_process_lock() {
+    let path = std::env::temp_dir().join(format!("cozo-lock-test-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let db = DbInstance::new("sqlite", &path, "").unwrap();
+    db.run_default(":create a {x}").unwrap();
+
+    let err = DbInstance::new("sqlite", &path, "").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains(&format!("already opened by process {}", std::process::id())));
+
+    let reader = DbInstance::new("sqlite", &path, r#"{"read_only": true}"#).unwrap();
+    reader.run_default("?[x] := *a{x}").unwrap();
+    assert!(reader.run_default("?[x] <- [[1]] :put a {x}").is_err());
+
+    drop(db);
+    let db = DbInstance::new("sqlite", &path, "").unwrap();
+    db.run_default("?[x] <- [[1]] :put a {x}").unwrap();
+    drop(db);
+    drop(reader);
+    for suffix in ["", ".lock", ".pid"] {
+        let mut p = path.clone().into_os_string();
+        p.push(suffix);
+        let _ = std::fs::remove_file(p);
+    }
+
+    // a reader does not create the database it is pointed at
+    assert!(DbInstance::new("sqlite", &path, r#"{"read_only": true}"#).is_err());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(feature = "storage-rocksdb")]
+#[test]
+fn rocksdb_read_only() {
+    let path = std::env::temp_dir().join(format!("cozo-read-only-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let open_reader = || DbInstance::new("rocksdb", &path, r#"{"read_only": true}"#);
+
+    // a reader does not create the database it is pointed at
+    assert!(open_reader().is_err());
+    assert!(!path.exists());
+
+    let db = DbInstance::new("rocksdb", &path, "").unwrap();
+    db.run_default(":create a {x}").unwrap();
+    db.run_default("?[x] <- [[1]] :put a {x}").unwrap();
+    db.close().unwrap();
+    drop(db);
+    std::fs::remove_file(path.join("manifest")).unwrap();
+    assert!(open_reader().is_err());
+    assert!(!path.join("manifest").exists());
+
+    let db = DbInstance::new("rocksdb", &path, "").unwrap();
+    db.close().unwrap();
+    drop(db);
+    let reader = open_reader().unwrap();
+    let res = reader.run_default("?[x] := *a{x}").unwrap().into_json();
+    assert_eq!(res["rows"], json!([[1]]));
+    assert!(reader.run_default("?[x] <- [[2]] :put a {x}").is_err());
+    assert!(!path.join("cozo.pid").exists());
+    reader.close().unwrap();
+    drop(reader);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[test]
+fn result_limits() {
+    let db = DbInstance::new("mem", "", r#"{"max_result_rows": 10}"#).unwrap();
+    let res = db.run_default("?[a] := a in int_range(100)").unwrap();
+    assert_eq!(res.rows.len(), 10);
+    assert!(res.truncated);
+    assert_eq!(res.into_json()["truncated"], json!(true));
+
+    let res = db.run_default("?[a] := a in int_range(5)").unwrap();
+    assert!(!res.truncated);
+    assert_eq!(res.into_json().get("truncated"), None);
+
+    // mutations see all rows
+    db.run_default("?[a] := a in int_range(100) :create a {a}")
+        .unwrap();
+    let res = db.run_default("?[count(a)] := *a{a}").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(100));
+
+    db.set_result_limits(None, Some(100));
+    let res = db
+        .run_default("?[a, s] := a in int_range(100), s = 'some string value'")
+        .unwrap();
+    assert!(res.truncated);
+    assert!(!res.rows.is_empty() && res.rows.len() < 10);
+
+    db.set_result_limits(None, None);
+    let res = db.run_default("?[a] := a in int_range(100)").unwrap();
+    assert_eq!(res.rows.len(), 100);
+    assert!(!res.truncated);
+}
+
+#[test]
+fn batched_scan_filters() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[k, v, f, s] := k in int_range(3000), v = k % 100, f = k / 2, s = if(k % 2 == 0, 'even', 'odd')
+        :create r {k => v, f, s}
+    "#,
+    )
+    .unwrap();
+    let res = db
+        .run_default("?[count(k)] := *r{k, v, f, s}, v > 10, 20 >= v, f < 1000.5, s == 'even'")
+        .unwrap();
+    // k below 2001, k % 100 in 11..=20 and even
+    assert_eq!(res.rows[0][0], DataValue::from(100));
+
+    let res = db
+        .run_default("?[count(k)] := *r{k, v}, v != 0, k % 100 == 1")
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(30));
+
+    db.run_default("?[k, v, f, s] <- [[5000, null, 0, 'odd']] :put r {k => v, f, s}")
+        .unwrap();
+    assert!(db.run_default("?[k] := *r{k, v}, v > 10").is_err());
+    let res = db.run_default("?[k] := *r{k, v}, v == 99, k > 2900").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(2999)]]);
+}
+
+#[test]
+fn shared_subexprs() {
+    let db = DbInstance::default();
+    let query = r#"
+        ?[a, b, c] := x in [1, 2, 3], a = (x + 1) * 2, b = (x + 1) * 2 + 1, c = x + 1,
+                      (x + 1) * 2 > 4
+    "#;
+    let res = db.run_default(query).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[6, 7, 3], [8, 9, 4]]));
+
+    // `(x + 1) * 2` and `x + 1` are each computed once
+    let expl = db.run_default(&format!("::explain {{ {query} }}")).unwrap();
+    let shared = expl
+        .rows
+        .iter()
+        .filter(|row| {
+            row[4].get_str() == Some("unify") && row[5].get_str().unwrap().starts_with("*cse*")
+        })
+        .count();
+    assert_eq!(shared, 2);
+}
+
+#[test]
+fn shared_subexprs_with_repeated_vars() {
+    let db = DbInstance::default();
+    db.run_default(":create r {a, b}").unwrap();
+    db.run_default("?[a, b] <- [[1, 1], [2, 2], [3, 4]] :put r {a, b}")
+        .unwrap();
+    // the repeated `x` is joined through a generated variable, which the shared
+    // `x + 1` must not be confused with
+    let res = db
+        .run_default("?[x, a, b] := *r[x, x], a = x + 1, b = (x + 1) * 10")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 2, 20], [2, 3, 30]]));
+    let res = db
+        .run_default("?[x, z, a] := *r[x, y], *r[y, z], a = (y + 1) * (y + 1)")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 1, 4], [2, 2, 9]]));
+}
+
+#[test]
+fn folded_constants() {
+    let db = DbInstance::default();
+    let expl = db
+        .run_default(
+            "::explain { ?[x, t] := x in [1, 2], t = parse_timestamp('2024-01-01T00:00:00Z') + x }",
+        )
+        .unwrap();
+    let unified = expl
+        .rows
+        .iter()
+        .filter(|row| row[4].get_str() == Some("unify"))
+        .map(|row| row[7].get_str().unwrap().to_string())
+        .collect_vec();
+    assert_eq!(unified.len(), 1);
+    assert!(!unified[0].contains("parse_timestamp"));
+
+    // nondeterministic functions are still called once per row
+    let res = db
+        .run_default("?[count_unique(r)] := x in int_range(10), r = rand_float()")
+        .unwrap();
+    assert!(res.rows[0][0].get_int().unwrap() > 1);
+    let res = db
+        .run_default("?[count(x)] := x in int_range(1000), rand_float() < 0.5")
+        .unwrap();
+    let n = res.rows[0][0].get_int().unwrap();
+    assert!(n > 0 && n < 1000);
+}
+
+#[test]
+fn inlined_rules() {
+    let db = DbInstance::default();
+    let query = r#"
+        r[a, b] := a in [1, 2, 3], b = a * 10
+        s[a, c] := r[a, b], c = b + 1
+        ?[a, c] := s[a, c], a > 1
+    "#;
+    let res = db.run_default(query).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, 21], [3, 31]]));
+
+    let expl = db.run_default(&format!("::explain {{ {query} }}")).unwrap();
+    assert!(expl.rows.iter().all(|row| row[2] == DataValue::from("?")));
+
+    // recursive rules and rules used several times are kept
+    let res = db
+        .run_default(
+            r#"
+        r[a] := a in [1, 2]
+        r[b] := r[a], b = a + 1, b < 5
+        s[a] := r[a], a > 2
+        ?[a, b] := s[a], s[b], a < b
+    "#,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3, 4]]));
+
+    // as are rules repeating a variable in their heads
+    let query = r#"
+        r[x, x] := x in [1, 2]
+        ?[a, b] := r[a, b]
+    "#;
+    let res = db.run_default(query).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 1], [2, 2]]));
+}
+
+#[test]
+fn pruned_columns() {
+    let db = DbInstance::default();
+    let rules = r#"
+        r[a, b] := a in [1, 2], b in [1, 2]
+        r[a, b] := a = 5, b = 0
+    "#;
+    let query = format!("{rules} ?[a] := r[a, _]");
+    let res = db.run_default(&query).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2], [5]]));
+
+    let expl = db.run_default(&format!("::explain {{ {query} }}")).unwrap();
+    let out = expl
+        .rows
+        .iter()
+        .find(|row| row[2] == DataValue::from("r") && row[4] == DataValue::from("out"))
+        .unwrap();
+    assert_eq!(out[8], DataValue::List(vec![DataValue::from("a")]));
+
+    // aggregations see every row of the rules they use
+    let res = db
+        .run_default(&format!("{rules} ?[count(a)] := r[a, _]"))
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(5));
+}
+
+#[test]
+fn resumed_fixpoints() {
+    let db = crate::new_cozo_mem().unwrap();
+    let run = |script: &str| {
+        db.run_script(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    let query = r#"
+        reach[a, b] := *edge[a, b]
+        reach[a, c] := reach[a, b], *edge[b, c]
+        ?[a, b] := reach[a, b]
+    "#;
+    run(":create edge {fr: Int, to: Int}");
+    run("?[fr, to] <- [[1, 2], [2, 3]] :put edge {fr, to}");
+    assert_eq!(run(query), json!([[1, 2], [1, 3], [2, 3]]));
+    assert_eq!(db.fixpoint_cache.len(), 1);
+
+    // new edges only add paths, so the fixpoint is kept and evaluation resumes from it
+    run("?[fr, to] <- [[3, 4]] :put edge {fr, to}");
+    assert_eq!(db.fixpoint_cache.len(), 1);
+    assert_eq!(
+        run(query),
+        json!([[1, 2], [1, 3], [1, 4], [2, 3], [2, 4], [3, 4]])
+    );
+
+    // removing an edge drops it
+    run("?[fr, to] <- [[2, 3]] :rm edge {fr, to}");
+    assert_eq!(db.fixpoint_cache.len(), 0);
+    assert_eq!(run(query), json!([[1, 2], [3, 4]]));
+}
+
+#[test]
+fn cached_fixed_rules() {
+    struct CountedSum(Arc<AtomicUsize>);
+
+    impl FixedRule for CountedSum {
+        fn arity(
+            &self,
+            _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+            _rule_head: &[Symbol],
+            _span: SourceSpan,
+        ) -> miette::Result<usize> {
+            Ok(1)
+        }
+
+        fn run(
+            &self,
+            payload: FixedRulePayload<'_, '_>,
+            out: &'_ mut RegularTempStore,
+            _poison: Poison,
+        ) -> miette::Result<()> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            let mut sum = 0;
+            for row in payload.get_input(0)?.iter()? {
+                sum += row?[1].get_int().unwrap_or(0);
+            }
+            out.put(vec![DataValue::from(sum)]);
+            Ok(())
+        }
+    }
+
+    let db = DbInstance::default();
+    let runs = Arc::new(AtomicUsize::new(0));
+    db.register_fixed_rule("CountedSum".to_string(), CountedSum(runs.clone()))
+        .unwrap();
+    db.run_default(":create weight {k: Int => w: Int}").unwrap();
+    db.run_default("?[k, w] <- [[1, 10], [2, 20]] :put weight {k => w}")
+        .unwrap();
+
+    let query = "?[s] <~ CountedSum(*weight[k, w], cache: true)";
+    // immutable scripts do not write outputs into the database
+    db.run_script(query, Default::default(), ScriptMutability::Immutable)
+        .unwrap();
+    assert!(db.run_default("::cache").unwrap().rows.is_empty());
+    runs.store(0, Ordering::Relaxed);
+
+    let sum = |db: &DbInstance| db.run_default(query).unwrap().rows[0][0].clone();
+    assert_eq!(sum(&db), DataValue::from(30));
+    assert_eq!(sum(&db), DataValue::from(30));
+    assert_eq!(runs.load(Ordering::Relaxed), 1);
+    let listed = db.run_default("::cache").unwrap();
+    assert_eq!(listed.rows.len(), 1);
+    assert_eq!(listed.rows[0][1], DataValue::from(1));
+    assert_eq!(listed.rows[0][3], DataValue::from(true));
+
+    // writing into the input makes the output stale
+    db.run_default("?[k, w] <- [[3, 30]] :put weight {k => w}")
+        .unwrap();
+    assert_eq!(
+        db.run_default("::cache").unwrap().rows[0][3],
+        DataValue::from(false)
+    );
+    assert_eq!(sum(&db), DataValue::from(60));
+    assert_eq!(sum(&db), DataValue::from(60));
+    assert_eq!(runs.load(Ordering::Relaxed), 2);
+
+    assert_eq!(
+        db.run_default("::cache drop").unwrap().rows[0][0],
+        DataValue::from(1)
+    );
+    assert!(db.run_default("::cache").unwrap().rows.is_empty());
+    assert_eq!(sum(&db), DataValue::from(60));
+    assert_eq!(runs.load(Ordering::Relaxed), 3);
+
+    // outputs computed from inline rules are not kept
+    assert!(db
+        .run_default("r[k, w] := *weight[k, w] ?[s] <~ CountedSum(r[k, w], cache: true)")
+        .is_err());
+}
+
+#[test]
+fn background_jobs() {
+    struct Spin;
+
+    impl FixedRule for Spin {
+        fn arity(
+            &self,
+            _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+            _rule_head: &[Symbol],
+            _span: SourceSpan,
+        ) -> miette::Result<usize> {
+            Ok(1)
+        }
+
+        fn run(
+            &self,
+            _payload: FixedRulePayload<'_, '_>,
+            _out: &'_ mut RegularTempStore,
+            poison: Poison,
+        ) -> miette::Result<()> {
+            loop {
+                poison.report_progress(0.5, "spinning");
+                poison.check()?;
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    let db = DbInstance::default();
+    db.register_fixed_rule("Spin".to_string(), Spin).unwrap();
+    let submit = |script: &str| {
+        let params = BTreeMap::from([("script".to_string(), DataValue::from(script))]);
+        db.run_script("::job submit $script", params, ScriptMutability::Mutable)
+            .unwrap()
+            .rows[0][0]
+            .get_int()
+            .unwrap()
+    };
+    let job_row = |id: i64| {
+        db.run_default("::jobs")
+            .unwrap()
+            .rows
+            .into_iter()
+            .find(|row| row[0] == DataValue::from(id))
+            .unwrap()
+    };
+    let wait_for = |id: i64, status: &str| {
+        for _ in 0..1000 {
+            if job_row(id)[1] == DataValue::from(status) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("job {id} never became {status}");
+    };
+
+    let id = submit("?[x] := x in [1, 2, 3]");
+    wait_for(id, "done");
+    let res = db.run_default(&format!("::job result {id}")).unwrap();
+    assert_eq!(res.rows.len(), 3);
+
+    let id = submit("?[x] <- [[1]] :put not_there {x}");
+    wait_for(id, "failed");
+    assert!(!job_row(id)[8].get_str().unwrap().is_empty());
+    assert!(db.run_default(&format!("::job result {id}")).is_err());
+
+    // scripts are parsed when submitted
+    assert!(db
+        .run_script(
+            "::job submit $script",
+            BTreeMap::from([("script".to_string(), DataValue::from("?[x] :="))]),
+            ScriptMutability::Mutable,
+        )
+        .is_err());
+
+    let id = submit("?[x] <~ Spin()");
+    wait_for(id, "running");
+    while job_row(id)[2].get_int().unwrap() == 0 {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let row = job_row(id);
+    assert_eq!(row[3], DataValue::from(0.5));
+    assert_eq!(row[4], DataValue::from("spinning"));
+    assert!(db.run_default(&format!("::job result {id}")).is_err());
+    let res = db.run_default(&format!("::job kill {id}")).unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("killing"));
+    wait_for(id, "killed");
+    assert_eq!(
+        db.run_default("::job kill 1000").unwrap().rows[0][0],
+        DataValue::from("NOT_FOUND")
+    );
+}
+
+#[test]
+fn jobs_on_bare_db() {
+    let db = crate::new_cozo_mem().unwrap();
+    let submit = || {
+        let params = BTreeMap::from([("script".to_string(), DataValue::from("?[x] <- [[1]]"))]);
+        db.run_script("::job submit $script", params, ScriptMutability::Mutable)
+    };
+    let status = |i: usize| db.run_default("::jobs").unwrap().rows[i][1].clone();
+    // nothing would ever run the job
+    assert!(submit().is_err());
+
+    db.accept_jobs();
+    let id = submit().unwrap().rows[0][0].get_int().unwrap();
+    assert_eq!(status(0), DataValue::from("queued"));
+    db.run_queued_jobs();
+    let res = db.run_default(&format!("::job result {id}")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(1)]]);
+
+    // a worker running the jobs elsewhere is left alone
+    submit().unwrap();
+    assert!(db.jobs.claim_worker());
+    db.run_queued_jobs();
+    assert_eq!(status(1), DataValue::from("queued"));
+    // the queued job is found on the way out
+    assert!(db.jobs.release_worker());
+    db.work_jobs();
+    assert_eq!(status(1), DataValue::from("done"));
+}
+
+#[test]
+fn idempotent_ingestion() {
+    let db = DbInstance::default();
+    db.run_default(":create counter {k: Int => v: Int}")
+        .unwrap();
+    let batch = |v: i64| {
+        BTreeMap::from([(
+            "counter".to_string(),
+            NamedRows::new(
+                vec!["k".to_string(), "v".to_string()],
+                vec![vec![DataValue::from(v), DataValue::from(v)]],
+            ),
+        )])
+    };
+    let count = || db.run_default("?[count(k)] := *counter{k}").unwrap().rows[0][0].clone();
+
+    assert!(db.ingest_relations("batch-1", batch(1)).unwrap());
+    assert!(!db.ingest_relations("batch-1", batch(2)).unwrap());
+    assert_eq!(count(), DataValue::from(1));
+    assert!(db.ingest_relations("batch-2", batch(2)).unwrap());
+    assert_eq!(count(), DataValue::from(2));
+
+    // a failed batch records nothing, so that it can be retried
+    let mut bad = batch(3);
+    bad.insert("not_there".to_string(), NamedRows::default());
+    assert!(db.ingest_relations("batch-3", bad).is_err());
+    assert!(db.ingest_relations("batch-3", batch(3)).unwrap());
+    assert_eq!(count(), DataValue::from(3));
+
+    let res: serde_json::Value = serde_json::from_str(&db.ingest_relations_str(
+        "batch-3",
+        r#"{"counter": {"headers": ["k", "v"], "rows": [[4, 4]]}}"#,
+    ))
+    .unwrap();
+    assert_eq!(res, json!({"ok": true, "ingested": false}));
+}
+
+#[test]
+fn import_infer() {
+    let dir = std::env::temp_dir();
+    let csv_path = dir.join(format!("cozo-infer-{}.csv", std::process::id()));
+    std::fs::write(
+        &csv_path,
+        "Name,id,score,active\nalice,1,1.5,true\nbob,2,2,false\ncarol,3,,true\n",
+    )
+    .unwrap();
+    let ndjson_path = dir.join(format!("cozo-infer-{}.data", std::process::id()));
+    std::fs::write(
+        &ndjson_path,
+        "{\"user\": \"a\", \"n\": 1, \"tags\": [\"x\"]}\n\n{\"user\": \"a\", \"n\": 2.5}\n",
+    )
+    .unwrap();
+    let db = DbInstance::default();
+    let infer = |path: &std::path::Path, opts: &str| {
+        db.run_script(
+            &format!("::import infer $path {opts}"),
+            BTreeMap::from([("path".to_string(), DataValue::from(path.to_str().unwrap()))]),
+            ScriptMutability::Mutable,
+        )
+    };
+
+    let schema = infer(&csv_path, "").unwrap().into_json()["rows"].clone();
+    assert_eq!(
+        schema,
+        json!([
+            ["id", "Int", true],
+            ["Name", "String", false],
+            ["score", "Float?", false],
+            ["active", "Bool", false]
+        ])
+    );
+    let loaded = infer(&csv_path, "{relation: 'people', create: true}").unwrap();
+    assert_eq!(loaded.rows[0][1], DataValue::from(3));
+    let res = db
+        .run_default("?[Name, score] := *people{id: 2, Name, score}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["bob", 2.0]]));
+
+    // the file extension is unknown, so the format is sniffed; no field is unique
+    let schema = infer(&ndjson_path, "").unwrap().into_json()["rows"].clone();
+    assert_eq!(
+        schema,
+        json!([
+            ["row_idx", "Int", true],
+            ["n", "Float", false],
+            ["tags", "Json?", false],
+            ["user", "String", false]
+        ])
+    );
+    infer(&ndjson_path, "{relation: 'events', create: true}").unwrap();
+    let res = db
+        .run_default("?[row_idx, n] := *events{row_idx, n}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[0, 1.0], [1, 2.5]]));
+
+    // records beyond the sample must still fit the schema
+    std::fs::write(&csv_path, "id,v\n1,2\n2,x\n").unwrap();
+    assert!(infer(&csv_path, "{sample: 1, relation: 'mixed', create: true}").is_err());
+    assert!(infer(&csv_path, "{format: 'parquet'}").is_err());
+
+    std::fs::remove_file(csv_path).unwrap();
+    std::fs::remove_file(ndjson_path).unwrap();
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn import_infer_parquet() {
+    use crate::ArrowFormat;
+
+    let db = DbInstance::default();
+    let data = db
+        .run_default(
+            "?[id, name, score, tags] <- [[1, 'alice', 1.5, ['x']], [2, 'bob', null, ['y', 'z']]]",
+        )
+        .unwrap()
+        .to_arrow(ArrowFormat::Parquet)
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("cozo-infer-{}.parquet", std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    let infer = |opts: &str| {
+        db.run_script(
+            &format!("::import infer $path {opts}"),
+            BTreeMap::from([("path".to_string(), DataValue::from(path.to_str().unwrap()))]),
+            ScriptMutability::Mutable,
+        )
+    };
+
+    let schema = infer("").unwrap().into_json()["rows"].clone();
+    assert_eq!(
+        schema,
+        json!([
+            ["id", "Int", true],
+            ["name", "String", false],
+            ["score", "Float?", false],
+            ["tags", "Json", false]
+        ])
+    );
+    infer("{relation: 'people', create: true}").unwrap();
+    let res = db
+        .run_default("?[id, name, score] := *people{id, name, score}")
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "alice", 1.5], [2, "bob", null]])
+    );
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn convert_offsets() {
+    let db = DbInstance::default();
+    let convert = |offsets: &str, opts: &str| {
+        db.run_script(
+            &format!(
+                r#"
+                pos[path, offset] <- [{offsets}]
+                files[path, content] <- [['a.rs', $content]]
+                ?[path, offset, utf8, utf16, line, col8, col16] <~
+                    ConvertOffsets(pos[], files[]{opts})
+                "#
+            ),
+            BTreeMap::from([("content".to_string(), DataValue::from("é x\n😀 y"))]),
+            ScriptMutability::Immutable,
+        )
+    };
+    let res = convert("['a.rs', 0], ['a.rs', 9], ['a.rs', 11]", "").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["a.rs", 0, 0, 0, 0, 0, 0],
+            ["a.rs", 9, 9, 6, 1, 4, 2],
+            ["a.rs", 11, 11, 8, 1, 6, 4]
+        ])
+    );
+    let res = convert("['a.rs', 6]", ", from: 'utf16'").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a.rs", 6, 9, 6, 1, 4, 2]]));
+    // inside the emoji, past the end, and a file without content
+    assert!(convert("['a.rs', 6]", "").is_err());
+    assert!(convert("['a.rs', 12]", "").is_err());
+    assert!(convert("['b.rs', 0]", "").is_err());
+}
+
+#[test]
+fn fixed_rule_output_columns() {
+    let db = DbInstance::default();
+    let query = r#"
+        pos[path, offset] <- [['a.rs', 2]]
+        files[path, content] <- [['a.rs', 'x\ny']]
+        ?[] <~ ConvertOffsets(pos[], files[])
+    "#;
+    let columns = json!([
+        "path",
+        "offset",
+        "utf8",
+        "utf16",
+        "line",
+        "col_utf8",
+        "col_utf16"
+    ]);
+    let res = db.run_default(query).unwrap().into_json();
+    assert_eq!(res["headers"], columns);
+    assert_eq!(res["rows"], json!([["a.rs", 2, 2, 2, 1, 0, 0]]));
+
+    let expl = db
+        .run_default(&format!("::explain {{ {query} }}"))
+        .unwrap()
+        .into_json();
+    let algo = expl["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|row| row[4] == "algo")
+        .unwrap();
+    assert_eq!(algo[8], columns);
+
+    db.run_default(&format!(
+        "{query}\n:create offsets {{path, offset => utf8, utf16, line, col_utf8, col_utf16}}"
+    ))
+    .unwrap();
+    let res = db.run_default("::columns offsets").unwrap().into_json();
+    let names: Vec<_> = res["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row[0].clone())
+        .collect();
+    assert_eq!(json!(names), columns);
+
+    // a head given in the query takes precedence
+    let res = db
+        .run_default(&query.replace("?[]", "?[a, b, c, d, e, f, g]"))
+        .unwrap();
+    assert_eq!(res.headers, vec!["a", "b", "c", "d", "e", "f", "g"]);
+
+    let rule = SimpleFixedRule::new(2, |_, _| {
+        Ok(NamedRows::new(
+            vec![],
+            vec![vec![DataValue::from(1), DataValue::from(2)]],
+        ))
+    })
+    .with_output_columns(vec!["x".to_string(), "y".to_string()]);
+    db.register_fixed_rule("Pair".to_string(), rule).unwrap();
+    let res = db.run_default("?[] <~ Pair()").unwrap().into_json();
+    assert_eq!(res["headers"], json!(["x", "y"]));
+    assert_eq!(res["rows"], json!([[1, 2]]));
+}
+
+#[test]
+fn expand_recurrences() {
+    let db = DbInstance::default();
+    // 2024-03-01T00:00:00Z to 2024-04-01T00:00:00Z, across the DST change in New York
+    let res = db
+        .run_default(
+            r#"
+        defs[id, rule, tz, start, end] <- [[
+            'standup',
+            'FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4',
+            'America/New_York',
+            parse_timestamp('2024-03-04T09:00:00-05:00'),
+            parse_timestamp('2024-03-04T09:15:00-05:00')
+        ]]
+        occ[] <~ ExpandRecurrences(defs[], from: 1709251200, to: 1711929600)
+        ?[id, start, len] := occ[id, s, e],
+                             start = format_timestamp(s, 'America/New_York'),
+                             len = e - s
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["standup", "2024-03-04T09:00:00-05:00", 900.0],
+            ["standup", "2024-03-06T09:00:00-05:00", 900.0],
+            ["standup", "2024-03-11T09:00:00-04:00", 900.0],
+            ["standup", "2024-03-13T09:00:00-04:00", 900.0]
+        ])
+    );
+
+    // every other day from 2024-01-01, in UTC, only the part of the window before `until`
+    let res = db
+        .run_default(
+            r#"
+        defs[id, rule, tz, start, end, until] <- [[
+            1,
+            'FREQ=DAILY;INTERVAL=2',
+            null,
+            parse_timestamp('2024-01-01T22:00:00Z'),
+            parse_timestamp('2024-01-02T01:00:00Z'),
+            parse_timestamp('2024-03-04T00:00:00Z')
+        ]]
+        occ[] <~ ExpandRecurrences(defs[], from: 1709251200, to: 1711929600)
+        ?[id, start] := occ[id, s, _], start = format_timestamp(s)
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            [1, "2024-03-01T22:00:00+00:00"],
+            [1, "2024-03-03T22:00:00+00:00"]
+        ])
+    );
+
+    assert!(db
+        .run_default(
+            r#"
+        defs[id, rule, tz, start, end] <- [[1, 'FREQ=HOURLY', null, 0, 1]]
+        ?[] <~ ExpandRecurrences(defs[], from: 0, to: 100)
+        "#
+        )
+        .is_err());
+}
+
+#[test]
+fn test_script_assertions() {
+    let db = DbInstance::default();
+    db.run_default(":create existing {k: Int => v: String}")
+        .unwrap();
+    let res = db
+        .run_default(
+            r#"
+        ::test {
+            { ?[k, v] <- [[1, 'a'], [2, 'b']] :put existing {k => v} }
+            { ?[x] <- [[10], [20]] :create scratch {x} }
+            assert_rows 'count' { ?[count(k)] := *existing[k, _] } [[2]]
+            assert_rows { ?[x] := *scratch[x] } [[20], [10]]
+            assert_eq 'same' { ?[x] := *scratch[x] } { ?[x] := x in [10, 20] }
+            assert_rows 'wrong' { ?[x] := *scratch[x] } [[10]]
+            assert_rows 'broken' { ?[x] := *missing[x] } []
+        }
+        "#,
+        )
+        .unwrap();
+    let rows = res.into_json()["rows"].clone();
+    assert_eq!(rows[0], json!(["count", "pass", null]));
+    assert_eq!(rows[1], json!(["assertion 2", "pass", null]));
+    assert_eq!(rows[2], json!(["same", "pass", null]));
+    assert_eq!(rows[3][0], json!("wrong"));
+    assert_eq!(rows[3][1], json!("fail"));
+    assert_eq!(rows[3][2], json!("expected [[10]], got [[10], [20]]"));
+    assert_eq!(rows[4][0], json!("broken"));
+    assert_eq!(rows[4][1], json!("error"));
+
+    // nothing done by the test is kept
+    assert!(db.run_default("?[x] := *scratch[x]").is_err());
+    let res = db.run_default("?[k] := *existing[k, _]").unwrap();
+    assert_eq!(res.rows.len(), 0);
+
+    // a failing setup stops the script
+    let res = db
+        .run_default(
+            r#"
+        ::test {
+            { ?[x] <- [[1]] :replace existing {x} }
+            { ?[x] := *missing[x] }
+            assert_rows { ?[x] <- [[1]] } [[1]]
+        }
+        "#,
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 1);
+    assert_eq!(res.rows[0][0], DataValue::from("setup"));
+    assert_eq!(res.rows[0][1], DataValue::from("error"));
+}
+
+#[test]
+fn fixed_now() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default("?[t] := t = now() :fixed_now 1700000000")
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(1700000000.));
+    let res = db
+        .run_default("?[t] := t = now() :fixed_now '2024-01-01T00:00:00Z'")
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(1704067200.));
+    assert!(db.run_default("?[t] := t = now() :fixed_now [1]").is_err());
+
+    // the clock of the database applies to all queries, including mutations
+    db.run_default(":create hist {k: Int, vld: Validity => v: String}")
+        .unwrap();
+    db.set_fixed_now(Some(1000.));
+    db.run_default("?[k, vld, v] <- [[1, 'ASSERT', 'a']] :put hist {k, vld => v}")
+        .unwrap();
+    let res = db
+        .run_default("?[t, n] := *hist{vld}, t = to_int(vld), n = now()")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1000000000, 1000.0]]));
+
+    db.set_fixed_now(Some(2000.));
+    db.run_default("?[k, vld, v] <- [[1, 'ASSERT', 'b']] :put hist {k, vld => v}")
+        .unwrap();
+    let res = db.run_default("?[v] := *hist{k: 1, v @ 'NOW'}").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("b"));
+    // a query fixing its own clock wins over the database
+    let res = db
+        .run_default("?[v] := *hist{k: 1, v @ 'NOW'} :fixed_now 1500")
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("a"));
+
+    db.set_fixed_now(None);
+    let res = db.run_default("?[t] := t = now()").unwrap();
+    assert!(res.rows[0][0].get_float().unwrap() > 1700000000.);
+}
+
+#[test]
+fn count_and_exists_fast_paths() {
+    let db = DbInstance::default();
+    db.run_default(":create counted {a: Int, b: Int => c: String}")
+        .unwrap();
+    let res = db.run_default("?[count(a)] := *counted[a, _, _]").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(0)]]);
+
+    db.run_default(
+        "?[a, b, c] := a in int_range(100), b = a % 3, c = 'x' :put counted {a, b => c}",
+    )
+    .unwrap();
+    let res = db
+        .run_default("?[count(a), count(c)] := *counted[a, b, c]")
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from(100), DataValue::from(100)]]
+    );
+    let res = db.run_default("?[count(a)] := *counted{a}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(100)]]);
+    // these are not a scan of the whole relation
+    let res = db
+        .run_default("?[count(a)] := *counted{a, b}, b == 0")
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(34)]]);
+    let res = db.run_default("?[count(a)] := *counted{a, b: 1}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(33)]]);
+    let res = db.run_default("?[b, count(a)] := *counted{a, b}").unwrap();
+    assert_eq!(res.rows.len(), 3);
+
+    // existence checks
+    let res = db.run_default("?[] := *counted{b: 2}").unwrap();
+    assert_eq!(res.rows, vec![Vec::<DataValue>::new()]);
+    let res = db.run_default("?[] := *counted{b: 5}").unwrap();
+    assert!(res.rows.is_empty());
+    assert!(db
+        .run_default("?[a] := *counted{a, b: 1} :assert none")
+        .is_err());
+    let res = db
+        .run_default("?[a] := *counted{a, b: 5} :assert none")
+        .unwrap();
+    assert!(res.rows.is_empty());
+
+    // counting is a read like any other, and is served by pinned rows
+    let reads = |db: &DbInstance| {
+        let res = db.run_default("::usage").unwrap().into_json();
+        res["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|row| row[0] == "counted")
+            .unwrap()[2]
+            .as_i64()
+            .unwrap()
+    };
+    let hits = |db: &DbInstance| {
+        db.run_default("::pin").unwrap().rows[0][5]
+            .get_int()
+            .unwrap()
+    };
+    db.run_default("::pin counted").unwrap();
+    let (reads_before, hits_before) = (reads(&db), hits(&db));
+    let res = db.run_default("?[count(a)] := *counted{a}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(100)]]);
+    assert_eq!(reads(&db), reads_before + 1);
+    assert!(hits(&db) > hits_before);
+    let res = db
+        .run_default(
+            r#"
+            {?[a, b, c] <- [[100, 0, 'y']] :put counted {a, b => c}}
+            {?[count(a)] := *counted{a}}
+            "#,
+        )
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(101)]]);
+}
+
+#[test]
+fn triggers_on_bulk_mutations() {
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_default(":create edge {fr: Int, to: Int}").unwrap();
+    db.run_default(":create reached {fr: Int, to: Int}")
+        .unwrap();
+    db.run_default(
+        r#"
+        ::set_triggers edge
+        on put {
+            reach[a, b] := _new[a, b]
+            reach[a, c] := reach[a, b], *edge[b, c]
+            ?[fr, to] := reach[fr, to]
+            :put reached {fr, to}
+        }
+        "#,
+    )
+    .unwrap();
+
+    db.run_default("?[fr, to] := fr in int_range(50), to = fr + 1 :put edge {fr, to}")
+        .unwrap();
+    db.run_default("?[fr, to] <- [[50, 51]] :put edge {fr, to}")
+        .unwrap();
+    let res = db.run_default("?[count(fr)] := *reached{fr}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(1276)]]);
+    // the bodies of triggers do not take the place of the fixpoints of queries
+    assert_eq!(db.fixpoint_cache.len(), 0);
+}
+
+#[test]
+fn trigger_options() {
+    let db = DbInstance::default();
+    db.run_default(":create src {k: Int => v: Any}").unwrap();
+    db.run_default(":create seen {k: Int => n: Int}").unwrap();
+    db.run_default(
+        r#"
+        ::set_triggers src
+
+        on put second order 2 {
+            ?[k, n] := _new[k, _], *seen[k, m], n = m * 10
+            :put seen {k => n}
+        }
+        on put broken order 3 on_error log {
+            ?[k, n] := _new[k, _], n = 0
+            :insert seen {k => n}
+        }
+        on put first order 1 {
+            ?[k, n] := _new[k, _], n = 1
+            :put seen {k => n}
+        }
+        "#,
+    )
+    .unwrap();
+
+    // triggers run by order, and the failure of the last one is only logged
+    db.run_default("?[k, v] <- [[1, null]] :put src {k => v}")
+        .unwrap();
+    let res = db.run_default("?[k, n] := *seen[k, n]").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 10]]));
+
+    db.run_default("::trigger disable src second").unwrap();
+    db.run_default("?[k, v] <- [[2, null]] :put src {k => v}")
+        .unwrap();
+    let res = db.run_default("?[k, n] := *seen[k, n]").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 10], [2, 1]]));
+
+    let res = db.run_default("::triggers").unwrap();
+    let summary = res
+        .rows
+        .iter()
+        .map(|row| [0, 4, 6, 7].map(|i| row[i].clone()))
+        .collect_vec();
+    let expected: Vec<[DataValue; 4]> = vec![
+        ["src".into(), "first".into(), true.into(), "abort".into()],
+        ["src".into(), "second".into(), false.into(), "abort".into()],
+        ["src".into(), "broken".into(), true.into(), "log".into()],
+    ];
+    assert_eq!(summary, expected);
+    assert!(db.run_default("::trigger enable src third").is_err());
+    db.run_default("::trigger enable src second").unwrap();
+    let res = db.run_default("::show_triggers src").unwrap();
+    assert!(res.rows.iter().all(|row| row[5] == DataValue::from(true)));
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn diff_with_backup() {
+    let path = std::env::temp_dir().join(format!("cozo-diff-test-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let db = DbInstance::default();
+    db.run_default(":create a {k: Int => v: String}").unwrap();
+    db.run_default("?[k, v] <- [[1, 'x'], [2, 'y']] :put a {k => v}")
+        .unwrap();
+    db.run_default(":create b {x: Int}").unwrap();
+    db.backup_db(&path).unwrap();
+
+    let diff = format!("::diff '{}' {{rows: 10}}", path.display());
+    let res = db.run_default(&diff).unwrap();
+    assert!(res.rows.is_empty());
+
+    db.run_default("?[k, v] <- [[2, 'z'], [3, 'w']] :put a {k => v}")
+        .unwrap();
+    db.run_default("::remove b").unwrap();
+    db.run_default(":create c {y: Int}").unwrap();
+    let res = db.run_default(&diff).unwrap();
+    let kinds = res.rows.iter().map(|row| row[1].clone()).collect_vec();
+    assert_eq!(
+        kinds,
+        [
+            "row_count",
+            "content",
+            "row_changed",
+            "row_only_here",
+            "only_there",
+            "only_here"
+        ]
+        .map(DataValue::from)
+    );
+    assert_eq!(
+        res.into_json()["rows"][2],
+        json!(["a", "row_changed", [2, "z"], [2, "y"]])
+    );
+
+    let res = db
+        .run_default(&format!("::diff '{}' {{relations: ['b']}}", path.display()))
+        .unwrap();
+    assert_eq!(res.rows.len(), 1);
+    assert!(db.run_default("::diff 'https://example.com'").is_err());
+    for suffix in ["", ".lock", ".pid"] {
+        let mut p = path.clone().into_os_string();
+        p.push(suffix);
+        let _ = std::fs::remove_file(p);
+    }
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn diff_with_encrypted_backup() {
+    let path = std::env::temp_dir().join(format!(
+        "cozo-diff-encrypted-test-{}.db",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    let db = DbInstance::default();
+    let key: Arc<dyn crate::KeyProvider> =
+        Arc::new(|name: &str| -> miette::Result<Option<Vec<u8>>> {
+            Ok((name == "pii").then(|| vec![7u8; 32]))
+        });
+    db.set_key_provider(Some(key));
+    db.run_default(":create users {id: Int => email: String}")
+        .unwrap();
+    db.run_default("?[id, email] <- [[1, 'a@x.org'], [2, 'b@x.org']] :put users {id => email}")
+        .unwrap();
+    db.run_default("::encrypt users {email: 'pii'}").unwrap();
+    db.backup_db(&path).unwrap();
+
+    // the same value is encrypted with a new nonce each time it is written
+    db.run_default("?[id, email] <- [[1, 'a@x.org']] :put users {id => email}")
+        .unwrap();
+    let diff = format!("::diff '{}' {{rows: 10}}", path.display());
+    assert!(db.run_default(&diff).unwrap().rows.is_empty());
+
+    db.run_default("?[id, email] <- [[2, 'c@x.org']] :put users {id => email}")
+        .unwrap();
+    let res = db.run_default(&diff).unwrap().into_json();
+    assert_eq!(res["rows"][0][1], json!("content"));
+    assert_eq!(
+        res["rows"][1],
+        json!(["users", "row_changed", [2, "c@x.org"], [2, "b@x.org"]])
+    );
+    for suffix in ["", ".lock", ".pid"] {
+        let mut p = path.clone().into_os_string();
+        p.push(suffix);
+        let _ = std::fs::remove_file(p);
+    }
+}
+
+#[test]
+fn replay_log() {
+    let path = std::env::temp_dir().join(format!("cozo-replay-test-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let log = path.to_str().unwrap();
+
+    let db = DbInstance::default();
+    db.set_replay_log(Some(log)).unwrap();
+    db.run_default(":create seen {k: Int => at: Float}")
+        .unwrap();
+    db.run_script(
+        "?[k, at] := k = $k, at = now() :put seen {k => at}",
+        BTreeMap::from([("k".to_string(), DataValue::from(7))]),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    db.run_default("?[k] := *seen{k}").unwrap();
+    assert!(db.run_default("?[k] := *nowhere{k}").is_err());
+    db.set_replay_log(None).unwrap();
+    db.run_default("::remove seen").unwrap();
+
+    let copy = DbInstance::default();
+    let res = copy.replay(&path).unwrap();
+    assert_eq!(res.rows.len(), 4);
+    for row in &res.rows {
+        assert_eq!(row[4], row[5]);
+        assert_eq!(row[6] == DataValue::Null, row[7] == DataValue::Null);
+    }
+    assert_ne!(res.rows[3][7], DataValue::Null);
+    let recorded = std::fs::read_to_string(&path).unwrap();
+    let entry: serde_json::Value = serde_json::from_str(recorded.lines().nth(1).unwrap()).unwrap();
+    let at = copy.run_default("?[at] := *seen{k: 7, at}").unwrap();
+    assert_eq!(
+        at.rows[0][0].get_float().unwrap(),
+        entry["now"].as_i64().unwrap() as f64 / 1e6
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn storage_stats() {
+    let db = DbInstance::default();
+    db.set_storage_stats(true);
+    db.run_default(":create a {k: Int}").unwrap();
+    let res = db.run_default("?[k] := *a{k}").unwrap();
+    // the in-memory storage does not count
+    assert_eq!(res.storage_stats, None);
+    assert!(res.into_json().get("storage_stats").is_none());
+}
+
+#[cfg(feature = "storage-rocksdb")]
+#[test]
+fn rocksdb_storage_stats() {
+    let path = std::env::temp_dir().join(format!("cozo-stats-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let db = DbInstance::new("rocksdb", &path, "").unwrap();
+    db.run_default(":create a {k: Int => v: String}").unwrap();
+    db.run_default("?[k, v] := k in int_range(100), v = 'x' :put a {k => v}")
+        .unwrap();
+
+    let res = db.run_default("?[k, v] := *a{k, v}").unwrap();
+    assert_eq!(res.storage_stats, None);
+
+    db.set_storage_stats(true);
+    let res = db.run_default("?[k, v] := *a{k, v}").unwrap();
+    let stats = res.storage_stats.clone().unwrap();
+    // the rows, and the metadata of the relation
+    assert!(stats.keys_read > 100);
+    assert!(stats.seeks >= 1);
+    assert!(stats.bytes_read > 0);
+    assert!(res.into_json()["storage_stats"]["keys_read"].is_u64());
+
+    let res = db.run_default("?[v] := *a{k: 5, v}").unwrap();
+    assert!(res.storage_stats.unwrap().keys_read < stats.keys_read);
+    db.close().unwrap();
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[test]
+fn storage_config() {
+    let db = DbInstance::default();
+    let res = db.run_default("::config").unwrap();
+    assert_eq!(res.headers, ["setting", "value"]);
+    assert!(res.rows.is_empty());
+    assert!(db
+        .run_default("::config set write_buffer_size 1048576")
+        .is_err());
+}
+
+#[cfg(feature = "storage-rocksdb")]
+#[test]
+fn rocksdb_storage_config() {
+    let path = std::env::temp_dir().join(format!("cozo-config-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let db = DbInstance::new("rocksdb", &path, "").unwrap();
+    let setting = |name: &str| {
+        let res = db.run_default("::config").unwrap();
+        res.rows
+            .into_iter()
+            .find(|row| row[0] == DataValue::from(name))
+            .map(|row| row[1].clone())
+            .unwrap()
+    };
+
+    db.run_default("::config set write_buffer_size 8388608")
+        .unwrap();
+    assert_eq!(setting("write_buffer_size"), DataValue::from(8388608));
+    db.run_default("::config set block_cache_capacity 1048576")
+        .unwrap();
+    assert_eq!(setting("block_cache_capacity"), DataValue::from(1048576));
+    db.run_default("::config set disable_auto_compactions true")
+        .unwrap();
+    assert_eq!(setting("disable_auto_compactions"), DataValue::from(true));
+    db.run_default("::config set max_background_jobs 2")
+        .unwrap();
+    assert_eq!(setting("max_background_jobs"), DataValue::from(2));
+
+    assert!(db.run_default("::config set write_buffer_size 1").is_err());
+    assert!(db
+        .run_default("::config set max_background_jobs 'many'")
+        .is_err());
+    assert!(db
+        .run_default("::config set compaction_style 'universal'")
+        .is_err());
+    assert!(db.run_default("::config set no_such_option 1").is_err());
+    db.close().unwrap();
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[test]
+fn partitions_unsupported() {
+    let db = DbInstance::default();
+    db.run_default(":create part {k: Int => v: String}")
+        .unwrap();
+    let res = db.run_default("::partition").unwrap();
+    assert_eq!(
+        res.headers,
+        [
+            "relation",
+            "by",
+            "partition",
+            "column_family",
+            "lower",
+            "upper"
+        ]
+    );
+    assert!(res.rows.is_empty());
+    let err = db
+        .run_default("::partition create part {by: 'hash', partitions: 4}")
+        .unwrap_err();
+    assert!(err.to_string().contains("cannot partition"));
+    assert!(db.run_default("::partition drop part").is_err());
+    assert!(db
+        .run_default("::partition create part {by: 'hash', partitions: 1}")
+        .is_err());
+    assert!(db
+        .run_default("::partition create part {by: 'hash', bounds: [1]}")
+        .is_err());
+    assert!(db
+        .run_default("::partition create part {by: 'list', partitions: 4}")
+        .is_err());
+}
+
+#[cfg(feature = "storage-rocksdb")]
+#[test]
+fn rocksdb_partitions() {
+    let path = std::env::temp_dir().join(format!("cozo-partition-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let db = DbInstance::new("rocksdb", &path, "").unwrap();
+    db.run_default(":create hashed {k: Int, j: Int => v: String}")
+        .unwrap();
+    db.run_default(":create ranged {k: Int => v: String}")
+        .unwrap();
+    db.run_default(":create plain {k: Int => v: String}")
+        .unwrap();
+    db.run_default("?[k, j, v] := k in int_range(100), j in int_range(3), v = to_string(k) :put hashed {k, j => v}")
+        .unwrap();
+    db.run_default("?[k, v] := k in int_range(100), v = to_string(k) :put ranged {k => v}")
+        .unwrap();
+    db.run_default("?[k, v] := k in int_range(10), v = to_string(k) :put plain {k => v}")
+        .unwrap();
+
+    db.run_default("::partition create hashed {by: 'hash', partitions: 4}")
+        .unwrap();
+    db.run_default("::partition create ranged {by: 'range', bounds: [25, 50, 75]}")
+        .unwrap();
+    assert!(db
+        .run_default("::partition create ranged {by: 'hash', partitions: 2}")
+        .is_err());
+    assert!(db
+        .run_default("::partition create plain {by: 'range', bounds: [5, 1]}")
+        .is_err());
+    let res = db.run_default("::partition").unwrap();
+    assert_eq!(res.rows.len(), 8);
+    assert_eq!(res.rows[4][4..], [DataValue::Null, DataValue::from(25)]);
+
+    let check = |db: &DbInstance| {
+        let count = |script: &str| db.run_default(script).unwrap().rows[0][0].clone();
+        assert_eq!(count("?[count(k)] := *hashed{k}"), DataValue::from(300));
+        assert_eq!(
+            count("?[count(j)] := *hashed{k: 42, j}"),
+            DataValue::from(3)
+        );
+        assert_eq!(
+            count("?[v] := *hashed{k: 42, j: 1, v}"),
+            DataValue::from("42")
+        );
+        assert_eq!(count("?[count(k)] := *ranged{k}"), DataValue::from(100));
+        assert_eq!(
+            count("?[count(k)] := *ranged{k}, k >= 20, k < 60"),
+            DataValue::from(40)
+        );
+        assert_eq!(count("?[v] := *ranged{k: 75, v}"), DataValue::from("75"));
+        assert_eq!(count("?[count(k)] := *plain{k}"), DataValue::from(10));
+    };
+    check(&db);
+
+    db.run_default("?[k, j, v] <- [[42, 1, 'changed']] :put hashed {k, j => v}")
+        .unwrap();
+    db.run_default("?[k] <- [[42]] :rm ranged {k}").unwrap();
+    db.run_default("?[k, v] <- [[42, '42']] :put ranged {k => v}")
+        .unwrap();
+    db.run_default("?[k, j, v] <- [[42, 1, '42']] :put hashed {k, j => v}")
+        .unwrap();
+    assert!(db.run_default("::remove ranged").is_err());
+
+    db.close().unwrap();
+    drop(db);
+    let db = DbInstance::new("rocksdb", &path, "").unwrap();
+    assert_eq!(db.run_default("::partition").unwrap().rows.len(), 8);
+    check(&db);
+
+    db.run_default("::partition drop ranged").unwrap();
+    db.run_default("::partition drop hashed").unwrap();
+    assert!(db.run_default("::partition").unwrap().rows.is_empty());
+    check(&db);
+    db.run_default("::remove ranged").unwrap();
+    db.close().unwrap();
+    drop(db);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[cfg(feature = "storage-rocksdb")]
+#[test]
+fn rocksdb_interrupted_partitioning() {
+    use crate::data::tuple::Tuple;
+
+    let path =
+        std::env::temp_dir().join(format!("cozo-partition-crash-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    type RocksDb = crate::Db<crate::RocksDbStorage>;
+    let run = |db: &RocksDb, script: &str| {
+        db.run_script(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap()
+    };
+    let db = crate::new_cozo_rocksdb(&path).unwrap();
+    run(&db, ":create r {k: Int => v: String}");
+    run(
+        &db,
+        "?[k, v] := k in int_range(4), v = to_string(k) :put r {k => v}",
+    );
+    let id = db.transact().unwrap().get_relation("r", false).unwrap().id;
+    // scans across relations, as those of backups, also look in the default column family:
+    // the upper bound is just past the start of the next relation for the scan to be one
+    let lower = Tuple::default().encode_as_key(id);
+    let mut upper = Tuple::default().encode_as_key(id.next());
+    upper.push(0);
+    let raw_rows = |db: &RocksDb| {
+        db.transact()
+            .unwrap()
+            .store_tx
+            .range_scan(&lower, &upper)
+            .count()
+    };
+    let count = |db: &RocksDb| run(db, "?[count(k)] := *r{k}").rows[0][0].clone();
+    // the rows a move interrupted after the partitions were saved leaves behind,
+    // one of which is then removed from the partitions
+    let interrupted_partitioning = |db: &RocksDb| {
+        run(db, "::partition create r {by: 'hash', partitions: 2}");
+        let tx = db.transact().unwrap();
+        let stale = tx
+            .store_tx
+            .range_scan(&lower, &upper)
+            .collect::<miette::Result<Vec<_>>>()
+            .unwrap();
+        drop(tx);
+        run(db, "?[k] <- [[0]] :rm r {k}");
+        for (k, v) in &stale {
+            db.db.put_unrouted(k, v).unwrap();
+        }
+    };
+
+    interrupted_partitioning(&db);
+    assert_eq!(raw_rows(&db), 4);
+    assert_eq!(count(&db), DataValue::from(3));
+    // the removed row does not come back with the others
+    run(&db, "::partition drop r");
+    assert_eq!(raw_rows(&db), 3);
+    assert_eq!(count(&db), DataValue::from(3));
+
+    run(&db, "?[k, v] <- [[0, '0']] :put r {k => v}");
+    interrupted_partitioning(&db);
+    assert_eq!(raw_rows(&db), 4);
+    db.close().unwrap();
+    drop(db);
+    // the rows left behind are cleared when the database is opened
+    let db = crate::new_cozo_rocksdb(&path).unwrap();
+    assert_eq!(raw_rows(&db), 3);
+    assert_eq!(count(&db), DataValue::from(3));
+    db.close().unwrap();
+    drop(db);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[test]
+fn pinned_relations() {
+    let db = DbInstance::default();
+    db.run_default(":create dim {k: Int => v: String}").unwrap();
+    db.run_default(":create fact {id: Int => k: Int}").unwrap();
+    db.run_default("?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']] :put dim {k => v}")
+        .unwrap();
+    db.run_default("?[id, k] := id in int_range(30), k = 1 + id % 3 :put fact {id => k}")
+        .unwrap();
+    db.run_default("::pin dim").unwrap();
+    let res = db.run_default("::pin").unwrap();
+    assert_eq!(
+        res.headers,
+        [
+            "relation",
+            "version",
+            "rows",
+            "bytes",
+            "max_bytes",
+            "hits",
+            "loads"
+        ]
+    );
+    assert_eq!(res.rows.len(), 1);
+    assert_eq!(res.rows[0][0], DataValue::from("dim"));
+    assert_eq!(res.rows[0][2], DataValue::from(3));
+    assert_eq!(res.rows[0][6], DataValue::from(1));
+
+    let join = "?[v, count(id)] := *fact{id, k}, *dim{k, v}";
+    let res = db.run_default(join).unwrap();
+    assert_eq!(res.rows.len(), 3);
+    assert_eq!(res.rows[0], vec![DataValue::from("a"), DataValue::from(10)]);
+    let res = db.run_default("::pin").unwrap();
+    assert!(res.rows[0][5].get_int().unwrap() > 0);
+    assert_eq!(res.rows[0][6], DataValue::from(1));
+
+    // a commit changes the version, and the rows are loaded again when next read
+    db.run_default("?[k, v] <- [[1, 'x']] :put dim {k => v}")
+        .unwrap();
+    let res = db.run_default(join).unwrap();
+    assert_eq!(res.rows[2], vec![DataValue::from("x"), DataValue::from(10)]);
+    let res = db.run_default("::pin").unwrap();
+    assert_eq!(res.rows[0][6], DataValue::from(2));
+
+    // a transaction sees its own writes
+    let res = db
+        .run_default(
+            r#"
+            {?[k, v] <- [[4, 'd']] :put dim {k => v}}
+            {?[v] := *dim{k: 4, v}}
+            "#,
+        )
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from("d")]]);
+    let res = db.run_default("?[count(k)] := *dim{k}").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(4));
+
+    let err = db.run_default("::pin dim {max_bytes: 10}").unwrap_err();
+    assert!(err.to_string().contains("too large"));
+    assert!(db.run_default("::pin nope").is_err());
+    assert!(db.run_default("::pin dim {min_bytes: 10}").is_err());
+    db.run_default("::unpin dim").unwrap();
+    assert!(db.run_default("::unpin dim").is_err());
+    assert!(db.run_default("::pin").unwrap().rows.is_empty());
+    let res = db.run_default(join).unwrap();
+    assert_eq!(res.rows.len(), 3);
+}
+
+#[test]
+fn join_hints() {
+    let db = DbInstance::default();
+    db.run_default(":create friends {fr: Int, to: Int => data: Int}")
+        .unwrap();
+    db.run_default(
+        "?[fr, to, data] <- [[1, 2, 3], [4, 5, 6], [1, 5, 7]] :put friends {fr, to => data}",
+    )
+    .unwrap();
+    db.run_default("::index create friends:rev {to, fr}")
+        .unwrap();
+    let explain = |query: &str| {
+        db.run_default(&format!("::explain {{ {query} }}"))
+            .unwrap()
+            .rows
+    };
+    let refs = |rows: &[Vec<DataValue>]| {
+        rows.iter()
+            .filter_map(|row| row[5].get_str().map(|s| s.to_string()))
+            .collect_vec()
+    };
+
+    // the index is chosen when the second key column is bound, unless told otherwise
+    let query = "?[fr, data] := *friends{to: 5, fr, data}";
+    assert!(refs(&explain(query)).contains(&":friends:rev".to_string()));
+    let hinted = "?[fr, data] := *friends{to: 5, fr, data} /*+ no_index */";
+    let rows = explain(hinted);
+    assert!(!refs(&rows).contains(&":friends:rev".to_string()));
+    let out = rows.iter().find(|row| row[4] == "out".into()).unwrap();
+    assert_eq!(out[10], DataValue::List(vec![":friends no_index".into()]));
+    assert_eq!(
+        db.run_default(hinted).unwrap().rows,
+        db.run_default(query).unwrap().rows
+    );
+
+    // and not when the first one is, unless told to
+    let query = "?[to, data] := *friends{fr: 1, to, data}";
+    assert!(!refs(&explain(query)).contains(&":friends:rev".to_string()));
+    let hinted = "?[to, data] := *friends[1, to, data] /*+ use_index(rev) */";
+    assert!(refs(&explain(hinted)).contains(&":friends:rev".to_string()));
+    assert_eq!(
+        db.run_default(hinted).unwrap().rows,
+        db.run_default(query).unwrap().rows
+    );
+
+    let query = "?[a, to] := a in [1, 4], *friends{fr: a, to}";
+    let ops = |rows: &[Vec<DataValue>]| {
+        rows.iter()
+            .map(|row| row[4].get_str().unwrap().to_string())
+            .collect_vec()
+    };
+    assert!(ops(&explain(query)).contains(&"stored_prefix_join".to_string()));
+    let hinted = "?[a, to] := a in [1, 4], *friends{fr: a, to} /*+ broadcast */";
+    assert!(ops(&explain(hinted)).contains(&"stored_mat_join".to_string()));
+    assert_eq!(
+        db.run_default(hinted).unwrap().rows,
+        db.run_default(query).unwrap().rows
+    );
+
+    // ordinary comments are still comments
+    db.run_default("?[fr] := *friends{fr} /* not a hint */")
+        .unwrap();
+    for bad in [
+        "?[fr] := *friends{fr} /*+ use_index(nope) */",
+        "?[fr] := *friends{fr} /*+ frobnicate */",
+        "?[fr] := *friends{fr} /*+ use_index(rev), broadcast */",
+        "?[fr] := *friends{fr} /*+ no_index, use_index(rev) */",
+        "?[fr] := fr in [1], not *friends{fr} /*+ broadcast */",
+    ] {
+        assert!(db.run_default(bad).is_err(), "{bad}");
+    }
+}
+
+#[test]
+fn serde_rows() {
+    #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq)]
+    struct Person {
+        name: String,
+        age: i64,
+        email: Option<String>,
+    }
+    #[derive(serde_derive::Serialize)]
+    struct Filter {
+        min_age: i64,
+    }
+
+    let db = DbInstance::default();
+    db.run_default(":create person {name: String => age: Int, email: String?}")
+        .unwrap();
+    let mut people = vec![
+        Person {
+            name: "alice".to_string(),
+            age: 31,
+            email: Some("alice@example.com".to_string()),
+        },
+        Person {
+            name: "bob".to_string(),
+            age: 25,
+            email: None,
+        },
+        Person {
+            name: "carol".to_string(),
+            age: 40,
+            email: None,
+        },
+    ];
+    let data = NamedRows::serialize(&people).unwrap();
+    assert_eq!(data.headers, ["age", "email", "name"]);
+    db.import_relations(BTreeMap::from([("person".to_string(), data)]))
+        .unwrap();
+
+    let res = db
+        .run_script(
+            "?[name, age, email] := *person{name, age, email}, age >= $min_age",
+            to_params(&Filter { min_age: 30 }).unwrap(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    let found: Vec<Person> = res.deserialize().unwrap();
+    people.remove(1);
+    assert_eq!(found, people);
+
+    // columns without fields are left out, fields without columns are errors
+    #[derive(serde_derive::Deserialize, Debug, PartialEq)]
+    struct Name {
+        name: String,
+    }
+    let names: Vec<Name> = res.deserialize().unwrap();
+    assert_eq!(names[1].name, "carol");
+    let res = db.run_default("?[name] := *person{name}").unwrap();
+    assert!(res.deserialize::<Person>().is_err());
+
+    assert!(to_params(&1).is_err());
+    assert!(NamedRows::serialize(&[json!({"a": 1}), json!({"b": 2})]).is_err());
+    assert!(NamedRows::serialize(&["a"]).is_err());
+}
+
+#[test]
+fn query_builder() {
+    use crate::builder::{Atom, Comparison, Mutation, QueryBuilder, RuleBuilder, Term};
+
+    let db = DbInstance::default();
+    db.run_default(":create friends {fr: String, to: String}")
+        .unwrap();
+    let evil = "x'}, *friends{fr} :rm friends {fr} \"";
+    let (script, params) = QueryBuilder::new()
+        .rule(RuleBuilder::entry(["fr", "to"]).rows(vec![
+            vec!["a".into(), "b".into()],
+            vec!["b".into(), "c".into()],
+            vec!["c".into(), evil.into()],
+        ]))
+        .mutate(Mutation::Put, "friends", ["fr", "to"], Vec::<String>::new())
+        .build()
+        .unwrap();
+    db.run_script(&script, params, ScriptMutability::Mutable)
+        .unwrap();
+
+    // friends of friends of 'a', excluding 'a' itself, by a recursive rule
+    let (script, params) = QueryBuilder::new()
+        .rule(
+            RuleBuilder::new("reach", ["x"]).atom(
+                Atom::stored("friends")
+                    .bind("fr", Term::param("start"))
+                    .bind("to", Term::var("x")),
+            ),
+        )
+        .rule(
+            RuleBuilder::new("reach", ["x"])
+                .atom(Atom::rule("reach", [Term::var("y")]))
+                .atom(
+                    Atom::stored("friends")
+                        .bind("fr", Term::var("y"))
+                        .bind("to", Term::var("x")),
+                ),
+        )
+        .rule(
+            RuleBuilder::entry(["x"])
+                .atom(Atom::rule("reach", [Term::var("x")]))
+                .atom(Atom::compare(
+                    Term::var("x"),
+                    Comparison::Neq,
+                    Term::value("b"),
+                )),
+        )
+        .param("start", "a")
+        .sort("x", true)
+        .build()
+        .unwrap();
+    let res = db
+        .run_script(&script, params, ScriptMutability::Immutable)
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[evil], ["c"]]), "{script}");
+
+    let (script, params) = QueryBuilder::new()
+        .rule(
+            RuleBuilder::entry(["x"])
+                .atom(Atom::stored("friends").bind("to", Term::var("x")))
+                .atom(Atom::not(
+                    Atom::stored("friends").bind("fr", Term::var("x")),
+                )),
+        )
+        .build()
+        .unwrap();
+    let res = db
+        .run_script(&script, params, ScriptMutability::Immutable)
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[evil]]), "{script}");
+
+    // values without literals are passed as parameters
+    let (script, params) = QueryBuilder::new()
+        .rule(
+            RuleBuilder::entry(["x"])
+                .aggr("count", "y")
+                .atom(Atom::or([
+                    vec![Atom::unify("x", Term::value(DataValue::Bytes(vec![1])))],
+                    vec![Atom::unify("x", Term::value(f64::NAN))],
+                ]))
+                .atom(Atom::member("y", Term::value(vec![1, 2])))
+                .atom(Atom::compare(
+                    Term::var("y"),
+                    Comparison::Ge,
+                    Term::value(i64::MIN),
+                )),
+        )
+        .build()
+        .unwrap();
+    assert_eq!(params.len(), 3, "{script}");
+    let res = db
+        .run_script(&script, params, ScriptMutability::Immutable)
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+    assert_eq!(res.rows[0][1], DataValue::from(2));
+
+    let entry = || RuleBuilder::entry(["x"]).atom(Atom::unify("x", Term::value(1)));
+    for bad in [
+        QueryBuilder::new(),
+        QueryBuilder::new().rule(RuleBuilder::entry(["x]"])),
+        QueryBuilder::new().rule(RuleBuilder::entry(["null"])),
+        QueryBuilder::new().rule(entry().atom(Atom::stored("a{}").bind("x", Term::var("x")))),
+        QueryBuilder::new().rule(entry().atom(Atom::stored("a").bind("x: 1", Term::var("x")))),
+        QueryBuilder::new().rule(entry().atom(Atom::unify("y", Term::param("$p")))),
+        QueryBuilder::new().rule(entry()).param("__0", 1),
+        QueryBuilder::new().rule(entry()).sort("x, y", false),
+        QueryBuilder::new().rule(entry()).mutate(
+            Mutation::Rm,
+            "friends; ::remove friends",
+            ["x"],
+            ["y"],
+        ),
+    ] {
+        assert!(bad.build().is_err());
+    }
+}
+
+#[test]
+fn relation_params() {
+    use crate::builder::Mutation;
+
+    let db = DbInstance::default();
+    let params = |pairs: &[(&str, DataValue)]| -> BTreeMap<String, DataValue> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    };
+    let rel = DataValue::from("r");
+    db.run_script(
+        ":create $rel {a: Int => b: Int}",
+        params(&[("rel", rel.clone())]),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    db.run_script(
+        "?[a, b] <- $rows :put $rel {a => b}",
+        params(&[
+            ("rel", rel.clone()),
+            ("rows", json!([[1, 10], [2, 20], [3, 20]]).into()),
+        ]),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            "?[a, b] := *$rel{a, b} :limit $n",
+            params(&[("rel", rel.clone()), ("n", DataValue::from(2))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 10], [2, 20]]));
+    let res = db
+        .run_script(
+            "?[b, a] := *$rel[a, b], a > 1",
+            params(&[("rel", rel.clone())]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[20, 2], [20, 3]]));
+
+    // index names are given as 'relation:index'
+    db.run_default("::index create r:by_b {b}").unwrap();
+    let res = db
+        .run_script(
+            "?[a] := *$idx{b: 20, a}",
+            params(&[("idx", DataValue::from("r:by_b"))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [3]]));
+
+    // so are validity timestamps
+    db.run_default(":create h {k: Int, at: Validity => v: Int}")
+        .unwrap();
+    db.run_default("?[k, at, v] <- [[1, 100, 1], [1, 200, 2]] :put h {k, at => v}")
+        .unwrap();
+    let res = db
+        .run_script(
+            "?[v] := *$rel{k: 1, v @ $t}",
+            params(&[("rel", DataValue::from("h")), ("t", DataValue::from(150))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+
+    // parameters can only hold names
+    for bad in [
+        DataValue::from("r{a, b}, *r{a} :rm r {a}"),
+        DataValue::from("r; ::remove r"),
+        DataValue::from(1),
+    ] {
+        let err = db
+            .run_script(
+                "?[a, b] := *$rel{a, b}",
+                params(&[("rel", bad)]),
+                ScriptMutability::Immutable,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.code().unwrap().to_string(),
+            "parser::bad_relation_param"
+        );
+    }
+    assert!(db
+        .run_script(
+            "?[a] := *$rel{a}",
+            Default::default(),
+            ScriptMutability::Immutable
+        )
+        .is_err());
+
+    db.mutate_relation(
+        "r",
+        Mutation::Put,
+        NamedRows::new(
+            vec!["a".to_string(), "b".to_string()],
+            vec![vec![DataValue::from(4), DataValue::from(40)]],
+        ),
+    )
+    .unwrap();
+    db.mutate_relation(
+        "r",
+        Mutation::Rm,
+        NamedRows::new(vec!["a".to_string()], vec![vec![DataValue::from(1)]]),
+    )
+    .unwrap();
+    let res = db.run_default("?[a] := *r{a}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [3], [4]]));
+    assert!(db
+        .mutate_relation(
+            "r",
+            Mutation::Put,
+            NamedRows::new(vec!["a} :rm r {a".to_string()], vec![]),
+        )
+        .is_err());
+    assert!(db
+        .mutate_relation("r; ::remove r", Mutation::Put, NamedRows::default())
+        .is_err());
+}
+
+#[test]
+fn sandboxed_scripts() {
+    let db = DbInstance::default();
+    db.run_default(":create r {a => b}").unwrap();
+    db.run_default("?[a, b] <- [[1, 10], [2, 20]] :put r {a => b}")
+        .unwrap();
+
+    let res = db
+        .run_readonly(
+            "?[a, b] := *r{a, b}, a > $min",
+            BTreeMap::from([("min".to_string(), DataValue::from(1))]),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, 20]]));
+    let res = db
+        .run_readonly(
+            "{?[a] := *r{a} :replace _t {a}} {?[n] := *_t{a}, n = a * 2}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [4]]));
+
+    for script in [
+        "?[a, b] <- [[3, 30]] :put r {a => b}",
+        ":create s {a}",
+        "{?[a] <- [[1]]} {?[a] := *r{a} :rm r {a}}",
+        "::relations",
+        "?[x] := x = rand_float()",
+        "?[x] := *r{a}, x = if(a > 1, now(), 0)",
+        "?[x] := x in [1, 2], not *r{a: x, b: rand_int(0, 10)}",
+        "r[a, b] := *r{a, b} ?[] <~ RandomWalk(r[], r[], r[], steps: 2)",
+        "?[] <~ Constant(data: [[rand_float()]])",
+    ] {
+        let err = db.run_readonly(script, Default::default()).unwrap_err();
+        assert_eq!(err.code().unwrap().to_string(), "eval::sandbox_violation");
+    }
+    let res = db.run_default("?[a] := *r{a}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2]]));
+}
+
+#[test]
+fn result_columns() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k: Int => b: Bytes?, v: <F32; 2>}")
+        .unwrap();
+    db.run_default("?[k, b, v] <- [[1, null, vec([1, 2])]] :put r {k => b, v}")
+        .unwrap();
+
+    let res = db
+        .run_default("?[k, v, x, n] := *r[k, b, v], x = k + 0.5, n = null")
+        .unwrap();
+    let columns = res
+        .columns
+        .iter()
+        .map(|c| {
+            (
+                c.typing.as_str(),
+                c.relation.as_deref(),
+                c.column.as_deref(),
+            )
+        })
+        .collect_vec();
+    assert_eq!(
+        columns,
+        [
+            ("Int", Some("r"), Some("k")),
+            ("<F32;2>", Some("r"), Some("v")),
+            ("Float", None, None),
+            ("Any?", None, None),
+        ]
+    );
+    let res = db.run_default("?[y] := *r{b: y}").unwrap();
+    assert_eq!(res.columns[0].typing, "Bytes?");
+    assert_eq!(res.columns[0].column.as_deref(), Some("b"));
+    assert_eq!(
+        res.into_json()["columns"],
+        json!([{"type": "Bytes?", "relation": "r", "column": "b"}])
+    );
+
+    // all the rules of the entry must read the column from the same place
+    let res = db.run_default("?[k] := *r{k} ?[k] := k = 2").unwrap();
+    assert_eq!(res.columns[0].typing, "Int");
+    assert_eq!(res.columns[0].relation, None);
+    let res = db.run_default("?[k] <- [[1], ['a'], [null]]").unwrap();
+    assert_eq!(res.columns[0].typing, "Any?");
+    let res = db.run_default("?[count(k)] := *r{k}").unwrap();
+    assert_eq!(res.columns[0].typing, "Int");
+    assert_eq!(res.columns[0].relation, None);
+
+    let res = db
+        .run_default("?[k, b, v] <- [[2, null, vec([0, 0])]] :put r {k => b, v}")
+        .unwrap();
+    assert!(res.columns.is_empty());
+}
+
+#[cfg(feature = "onnx")]
+#[test]
+fn onnx_score_errors() {
+    let db = DbInstance::default();
+    let res = db.run_default(
+        r#"
+        f[k, v] <- [[1, [1.0, 2.0]]]
+        ?[k, p] <~ OnnxScore(f[k, v], model: decode_base64('AAAA'))
+    "#,
+    );
+    assert!(res.unwrap_err().to_string().contains("ONNX"));
+    let res = db.run_default(
+        r#"
+        f[k, v] <- [[1, [1.0, 2.0]]]
+        ?[k, p] <~ OnnxScore(f[k, v], model: 1)
+    "#,
+    );
+    assert!(res.is_err());
+    let res = db.run_default(
+        r#"
+        f[k, v] <- [[1, [1.0, 2.0]], [2, [1.0]]]
+        m[b] <- [[decode_base64('AAAA')]]
+        ?[k, p] <~ OnnxScore(f[k, v], m[b])
+    "#,
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn sparse_search() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r#"
+        docs[k, v] <- [['a', sparse_vec([[0, 1.0], [3, 1.0]])],
+                       ['b', sparse_vec([[3, 2.0]])],
+                       ['c', sparse_vec([[7, 5.0]])]]
+        queries[q, v] <- [[1, sparse_vec([[3, 1.0]])], [2, [[0, 2.0]]], [3, [[9, 1.0]]]]
+        ?[q, k, score] <~ SparseSearch(docs[], queries[], k: 1)
+        :order q
+    "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "b", 2.0], [2, "a", 2.0]])
+    );
+    let res = db.run_default(
+        r#"
+        docs[k, v] <- [['a', 'not a vector']]
+        ?[q, k, score] <~ SparseSearch(docs[], docs[])
+    "#,
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn hybrid_search() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        ?[k, v, t] <- [['a', [1, 0], 'apple pie'],
+                       ['b', [0.9, 0.1], 'banana split'],
+                       ['c', [0, 1], 'apple crumble']]
+        :create docs {k: String => v: <F32; 2>, t: String}
+    ",
+    )
+    .unwrap();
+    db.run_default(
+        r"::hnsw create docs:vec {dim: 2, m: 16, dtype: F32, fields: [v], distance: L2, ef_construction: 20}",
+    )
+    .unwrap();
+    db.run_default(
+        r"::fts create docs:fts {extractor: t, tokenizer: Simple, filters: [Lowercase]}",
+    )
+    .unwrap();
+
+    let res = db
+        .run_default(
+            r"
+        vec_hits[k, d] := ~docs:vec{k | query: q, k: 3, ef: 20, bind_distance: d}, q = vec([1, 0])
+        text_hits[k, s] := ~docs:fts{k | query: 'apple', k: 3, bind_score: s}
+        ?[k, score] <~ HybridSearch(vec_hits[], text_hits[], k: 2)
+        :order -score
+    ",
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows.iter().map(|r| r[0].clone()).collect_vec(),
+        vec![DataValue::from("a"), DataValue::from("c")]
+    );
+
+    let res = db
+        .run_default(
+            r"
+        vec_hits[k, d] <- [['x', 0.0], ['y', 1.0]]
+        text_hits[k, s] <- [['y', 3.0], ['z', 1.0]]
+        ?[k, score] <~ HybridSearch(vec_hits[], text_hits[], method: 'weighted', vector_weight: 2)
+        :order -score
+    ",
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["x", 2.0], ["y", 1.0], ["z", 0.0]])
+    );
+
+    let res = db.run_default(
+        r"
+        hits[k, d] <- [['x', 0.0]]
+        ?[k, score] <~ HybridSearch(hits[], hits[], method: 'max')
+    ",
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn rerank_search_results() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        ?[k, v, t] <- [['a', [1, 0], 'apple pie'],
+                       ['b', [0.9, 0.1], 'banana split'],
+                       ['c', [0, 1], 'apple crumble']]
+        :create docs {k: String => v: <F32; 2>, t: String}
+    ",
+    )
+    .unwrap();
+    db.run_default(
+        r"::hnsw create docs:vec {dim: 2, m: 16, dtype: F32, fields: [v], distance: L2, ef_construction: 20}",
+    )
+    .unwrap();
+    db.run_default(
+        r"::fts create docs:fts {extractor: t, tokenizer: Simple, filters: [Lowercase]}",
+    )
+    .unwrap();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls_ = calls.clone();
+    db.register_reranker(
+        "by_length".to_string(),
+        move |_query: &DataValue, rows: &[Vec<DataValue>]| -> miette::Result<Vec<f64>> {
+            calls_.fetch_add(1, Ordering::Relaxed);
+            Ok(rows
+                .iter()
+                .map(|row| row[2].get_str().unwrap().len() as f64)
+                .collect())
+        },
+    )
+    .unwrap();
+    db.register_reranker(
+        "broken".to_string(),
+        |_query: &DataValue, _rows: &[Vec<DataValue>]| -> miette::Result<Vec<f64>> { Ok(vec![]) },
+    )
+    .unwrap();
+    assert!(db
+        .register_reranker(
+            "broken".to_string(),
+            |_query: &DataValue, _rows: &[Vec<DataValue>]| -> miette::Result<Vec<f64>> {
+                Ok(vec![])
+            },
+        )
+        .is_err());
+
+    let res = db
+        .run_default(
+            r"
+        ?[k, s] := ~docs:vec{k | query: q, k: 3, ef: 20, rerank: 'by_length', rerank_k: 2,
+                                 rerank_batch_size: 2, bind_rerank_score: s}, q = vec([1, 0])
+    ",
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["b", 12.0], ["c", 13.0]]));
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    let res = db
+        .run_default(
+            r"
+        ?[k, score, s] := ~docs:fts{k | query: 'apple', k: 3, bind_score: score,
+                                        rerank: 'by_length', rerank_k: 1, bind_rerank_score: s}
+    ",
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 1);
+    assert_eq!(res.rows[0][0], DataValue::from("c"));
+    assert_eq!(res.rows[0][2], DataValue::from(13.0));
+
+    for script in [
+        r"?[k] := ~docs:fts{k | query: 'apple', k: 3, rerank: 'missing'}",
+        r"?[k] := ~docs:fts{k | query: 'apple', k: 3, rerank_k: 1}",
+        r"?[k] := ~docs:fts{k | query: 'apple', k: 3, rerank: 'broken'}",
+        r"?[k] := ~docs:fts{k | query: 'apple', k: 3, rerank: 'by_length', bind_rerank_score: s, filter: s > 1}",
+    ] {
+        assert!(db.run_default(script).is_err(), "{script}");
+    }
+
+    assert!(db.unregister_reranker("broken").unwrap());
+    assert!(!db.unregister_reranker("broken").unwrap());
+}
+
+#[test]
+fn chunk_text_rule() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r"
+        texts[k, t] <- [[1, 'a b c d e'], [2, 'Hi there. How are you? Fine.']]
+        ?[k, i, s, e, c] <~ ChunkText(texts[], max_tokens: 3, overlap: 1)
+    ",
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            [1, 0, 0, 5, "a b c"],
+            [1, 1, 4, 9, "c d e"],
+            [2, 0, 0, 13, "Hi there. How"],
+            [2, 1, 10, 22, "How are you?"],
+            [2, 2, 18, 28, "you? Fine."]
+        ])
+    );
+
+    let res = db
+        .run_default(
+            r"
+        texts[k, t] <- [[2, 'Hi there. How are you? Fine.']]
+        ?[k, i, s, e, c] <~ ChunkText(texts[], max_tokens: 5, by: 'sentences')
+    ",
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            [2, 0, 0, 22, "Hi there. How are you?"],
+            [2, 1, 23, 28, "Fine."]
+        ])
+    );
+
+    for script in [
+        r"texts[k, t] <- [[1, 'a b']] ?[k, i, s, e, c] <~ ChunkText(texts[], max_tokens: 2, overlap: 2)",
+        r"texts[k, t] <- [[1, 'a b']] ?[k, i, s, e, c] <~ ChunkText(texts[], by: 'words')",
+        r"texts[k, t] <- [[1, 2]] ?[k, i, s, e, c] <~ ChunkText(texts[])",
+    ] {
+        assert!(db.run_default(script).is_err(), "{script}");
+    }
+}
+
+#[test]
+fn trigram_search() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r"
+        names[k, n] <- [[1, 'John Smith'], [2, 'Jane Doe'], [3, 'Jon Smyth']]
+        queries[q, n] <- [['q', 'Jon Smith']]
+        ?[q, k, s] <~ TrigramSearch(names[], queries[])
+    ",
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["q", 1, 8. / 13.], ["q", 3, 7. / 13.]])
+    );
+
+    let res = db
+        .run_default(
+            r"
+        names[k, n] <- [[1, 'John Smith'], [2, 'Jane Doe'], [3, 'Jon Smyth']]
+        queries[q, n] <- [['q', 'Jon Smith']]
+        ?[q, k, s] <~ TrigramSearch(names[], queries[], k: 1, threshold: 0)
+    ",
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 1);
+    assert_eq!(res.rows[0][1], DataValue::from(1));
+
+    let res = db.run_default(
+        r"
+        names[k, n] <- [[1, 2]]
+        ?[q, k, s] <~ TrigramSearch(names[], names[])
+    ",
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn update_expecting_version() {
+    let db = DbInstance::default();
+    db.run_default(":create users {id: Int => name: String, version: Int default 0}")
+        .unwrap();
+    db.run_default("?[id, name] <- [[1, 'a'], [2, 'b']] :put users {id => name}")
+        .unwrap();
+
+    let update = |name: &str, version: i64| {
+        db.run_script(
+            "?[id, name] <- [[1, $name]] :update users {id => name} expecting version = $v",
+            BTreeMap::from([
+                ("name".to_string(), DataValue::from(name)),
+                ("v".to_string(), DataValue::from(version)),
+            ]),
+            ScriptMutability::Mutable,
+        )
+    };
+    update("x", 0).unwrap();
+    let err = update("y", 0).unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "transact::version_conflict"
+    );
+    update("y", 1).unwrap();
+    let res = db
+        .run_default("?[id, name, version] := *users{id, name, version}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "y", 2], [2, "b", 0]]));
+
+    // the expected versions may come from the rows themselves
+    db.run_default(
+        r"
+        ?[id, name, v] <- [[1, 'p', 2], [2, 'q', 0]]
+        :update users {id => name} expecting version = v
+    ",
+    )
+    .unwrap();
+    let res = db
+        .run_default("?[id, name, version] := *users{id, name, version}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "p", 3], [2, "q", 1]]));
+    // a single conflict aborts the whole update
+    assert!(db
+        .run_default(
+            r"
+        ?[id, name, v] <- [[1, 'r', 3], [2, 's', 0]]
+        :update users {id => name} expecting version = v
+    ",
+        )
+        .is_err());
+    let res = db.run_default("?[name] := *users{name}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["p"], ["q"]]));
+
+    for script in [
+        "?[id, name] <- [[1, 'a']] :put users {id => name} expecting version = 0",
+        "?[id, version] <- [[1, 5]] :update users {id => version} expecting version = 3",
+        "?[id, name] <- [[1, 'a']] :update users {id => name} expecting revision = 3",
+    ] {
+        assert!(db.run_default(script).is_err(), "{script}");
+    }
+}
+
+#[test]
+fn max_iterations() {
+    let db = DbInstance::default();
+    let reach = r"
+        edge[a, b] := a in int_range(20), b = a + 1
+        reach[a, b] := edge[a, b]
+        reach[a, c] := reach[a, b], edge[b, c]
+        ?[count(b)] := reach[0, b]
+    ";
+
+    let res = db.run_default(reach).unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(20));
+    assert!(!res.incomplete);
+    let res = db
+        .run_default(&format!("{reach} :max_iterations 100"))
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(20));
+    assert!(!res.incomplete);
+
+    let err = db
+        .run_default(&format!("{reach} :max_iterations 5"))
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::max_iterations_reached"
+    );
+
+    let res = db
+        .run_default(&format!(
+            "{reach} :max_iterations 5 :on_max_iterations 'partial'"
+        ))
+        .unwrap();
+    assert!(res.incomplete);
+    let found = res.rows[0][0].get_int().unwrap();
+    assert!(found > 0 && found < 20, "{found}");
+    assert_eq!(res.into_json()["incomplete"], json!(true));
+
+    // non-recursive queries never iterate
+    let res = db
+        .run_default("?[a] := a in [1, 2, 3] :max_iterations 1")
+        .unwrap();
+    assert_eq!(res.rows.len(), 3);
+
+    for script in [
+        "?[a] := a = 1 :on_max_iterations 'partial'",
+        "?[a] := a = 1 :max_iterations 3 :on_max_iterations 'maybe'",
+        "?[a] := a = 1 :max_iterations 0",
+    ] {
+        assert!(db.run_default(script).is_err(), "{script}");
+    }
+}
+
+#[test]
+fn graph_export_import() {
+    let dir = std::env::temp_dir();
+    let file = |ext: &str| {
+        dir.join(format!("cozo-graph-{}.{ext}", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+    let db = DbInstance::default();
+    let run = |script: &str, path: &str| {
+        db.run_script(
+            script,
+            BTreeMap::from([("path".to_string(), DataValue::from(path))]),
+            ScriptMutability::Mutable,
+        )
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    db.run_default(":create dep {fr: String, to: String => weight: Float}")
+        .unwrap();
+    db.run_default(":create pkg {name: String => label: String?}")
+        .unwrap();
+    db.run_default(
+        "?[fr, to, weight] <- [['a', 'b', 1.5], ['b', 'c', 2]] :put dep {fr, to => weight}",
+    )
+    .unwrap();
+    db.run_default("?[name, label] <- [['a', 'A & co'], ['d', null]] :put pkg {name => label}")
+        .unwrap();
+
+    let graphml = file("graphml");
+    assert_eq!(
+        run("::graph export dep $path {nodes: 'pkg'}", &graphml),
+        json!([[graphml, 4, 2]])
+    );
+    let written = std::fs::read_to_string(&graphml).unwrap();
+    assert!(written.contains(r#"<key id="e0" for="edge" attr.name="weight" attr.type="double"/>"#));
+    assert!(written.contains(r#"<data key="n0">A &amp; co</data>"#));
+    assert!(written.contains(r#"<node id="d"/>"#));
+
+    let dot = file("dot");
+    run("::graph export dep $path {directed: false}", &dot);
+    let written = std::fs::read_to_string(&dot).unwrap();
+    assert!(written.starts_with("graph {"));
+    assert!(written.contains(r#""a" -- "b" ["weight"=1.5];"#));
+
+    let json_path = file("json");
+    run("::graph export dep $path", &json_path);
+    let written: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json_path).unwrap()).unwrap();
+    assert_eq!(
+        written["graph"]["edges"][1],
+        json!({"source": "b", "target": "c", "metadata": {"weight": 2.0}})
+    );
+
+    // GraphML read back keeps the attributes and their types
+    assert_eq!(
+        run(
+            "::graph import $path {relation: 'dep2', nodes: 'pkg2'}",
+            &graphml
+        ),
+        json!([["dep2", 2], ["pkg2", 4]])
+    );
+    assert_eq!(
+        db.run_default("?[s, t, w] := *dep2[s, t, w]")
+            .unwrap()
+            .into_json()["rows"],
+        json!([["a", "b", 1.5], ["b", "c", 2.0]])
+    );
+    assert_eq!(
+        db.run_default("?[id, label] := *pkg2[id, label]")
+            .unwrap()
+            .into_json()["rows"],
+        json!([["a", "A & co"], ["b", null], ["c", null], ["d", null]])
+    );
+
+    let edges = file("edges");
+    std::fs::write(&edges, "# comment\n1 2\n2,3, 0.5\n\n").unwrap();
+    assert_eq!(run("::graph import $path", &edges)[0][1], json!(2));
+    let name = format!("cozo_graph_{}", std::process::id());
+    assert_eq!(
+        db.run_default(&format!("?[s, t, w] := *{name}[s, t, w]"))
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1, 2, null], [2, 3, 0.5]])
+    );
+    std::fs::write(&edges, "1 2 3 4\n").unwrap();
+    assert!(db
+        .run_script(
+            "::graph import $path {relation: 'bad'}",
+            BTreeMap::from([("path".to_string(), DataValue::from(edges.as_str()))]),
+            ScriptMutability::Mutable,
+        )
+        .is_err());
+
+    for path in [graphml, dot, json_path, edges] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn rdf_import_and_match() {
+    let path = std::env::temp_dir()
+        .join(format!("cozo-rdf-{}.ttl", std::process::id()))
+        .to_str()
+        .unwrap()
+        .to_string();
+    std::fs::write(
+        &path,
+        r#"
+        @prefix foaf: <http://xmlns.com/foaf/0.1/> .
+        @prefix ex: <http://example.org/> .
+        # people
+        ex:alice a foaf:Person ; foaf:name "Alice" ; foaf:age 30 ;
+            foaf:knows ex:bob, [ foaf:name "Anon" ] .
+        <http://example.org/bob> a foaf:Person ; foaf:name "Bob"@en .
+        "#,
+    )
+    .unwrap();
+    let db = DbInstance::default();
+    let import = || {
+        db.run_script(
+            "::rdf import $path {relation: 'social'}",
+            BTreeMap::from([("path".to_string(), DataValue::from(path.as_str()))]),
+            ScriptMutability::Mutable,
+        )
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(import(), json!([["social", 8, 12]]));
+    // importing again only adds the triples of the blank node, which is a new one
+    assert_eq!(import(), json!([["social", 2, 1]]));
+
+    let prefix = "PREFIX foaf: <http://xmlns.com/foaf/0.1/>";
+    let run = |pattern: &str| {
+        db.run_default(&format!(
+            "?[a, b] <~ RdfMatch(*social[], *social_terms[], pattern: '{prefix} {pattern}')"
+        ))
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(
+        run("?p a foaf:Person ; foaf:name ?name"),
+        json!([
+            ["http://example.org/alice", "Alice"],
+            ["http://example.org/bob", "Bob"]
+        ])
+    );
+    assert_eq!(
+        run("?x foaf:knows [ foaf:name ?n ]"),
+        json!([
+            ["http://example.org/alice", "Anon"],
+            ["http://example.org/alice", "Bob"]
+        ])
+    );
+    assert_eq!(
+        run("?p foaf:age 30 . ?p foaf:name ?name"),
+        json!([["http://example.org/alice", "Alice"]])
+    );
+    assert_eq!(run("?p foaf:age 31 . ?p foaf:name ?name"), json!([]));
+}
+
+#[test]
+fn encrypted_columns() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let allow: Arc<dyn crate::KeyProvider> =
+        Arc::new(|name: &str| -> miette::Result<Option<Vec<u8>>> {
+            Ok((name == "pii").then(|| vec![7u8; 32]))
+        });
+    let deny: Arc<dyn crate::KeyProvider> =
+        Arc::new(|_: &str| -> miette::Result<Option<Vec<u8>>> { Ok(None) });
+    db.set_key_provider(Some(allow.clone()));
+    db.run_default(":create users {id: Int => name: String, email: String?}")
+        .unwrap();
+    db.run_default("?[id, name, email] <- [[1, 'a', 'a@x.org'], [2, 'b', null]] :put users {id => name, email}")
+        .unwrap();
+    assert_eq!(
+        db.run_default("::encrypt users {email: 'pii'}")
+            .unwrap()
+            .into_json()["rows"],
+        json!([["users", 2]])
+    );
+    assert!(db.run_default("::encrypt users {nope: 'pii'}").is_err());
+    assert!(db.run_default("::encrypt users {id: 'pii'}").is_err());
+    assert!(db
+        .run_default("::index create users:by_email {email}")
+        .is_err());
+    let query = "?[id, name, email] := *users{id, name, email}";
+    let plain = json!([[1, "a", "a@x.org"], [2, "b", null]]);
+    assert_eq!(db.run_default(query).unwrap().into_json()["rows"], plain);
+    db.run_default("?[id, name, email] <- [[3, 'c', 'c@x.org']] :put users {id => name, email}")
+        .unwrap();
+
+    db.set_key_provider(Some(deny));
+    let rows = db.run_default(query).unwrap().rows;
+    assert!(matches!(rows[0][2], DataValue::Bytes(_)));
+    assert_eq!(rows[1][2], DataValue::Null);
+    assert!(db
+        .run_default("?[id, name, email] <- [[4, 'd', 'd@x.org']] :put users {id => name, email}")
+        .is_err());
+
+    db.set_key_provider(Some(allow));
+    db.run_default("?[id, name] <- [[1, 'A']] :update users {id => name}")
+        .unwrap();
+    assert_eq!(
+        db.run_default(query).unwrap().into_json()["rows"],
+        json!([[1, "A", "a@x.org"], [2, "b", null], [3, "c", "c@x.org"]])
+    );
+    db.run_default("::encrypt users {email: null}").unwrap();
+    db.set_key_provider(None);
+    assert_eq!(
+        db.run_default(query).unwrap().into_json()["rows"][0],
+        json!([1, "A", "a@x.org"])
+    );
+}
+
+#[test]
+fn masked_columns() {
+    let admin = DbInstance::new("mem", "", "").unwrap().with_unmask(true);
+    let analyst = admin.with_unmask(false);
+    admin
+        .run_default(
+            ":create people {id: Int => name: String, ssn: String, email: String?, age: Int}",
+        )
+        .unwrap();
+    admin
+        .run_default(r#"?[id, name, ssn, email, age] <- [[1, "a", "123-45-6789", "a@x.org", 30], [2, "b", "987-65-4321", null, 40]] :put people {id => name, ssn, email, age}"#)
+        .unwrap();
+    assert!(admin.run_default("::mask people {id: 'hash'}").is_err());
+    assert!(admin.run_default("::mask people {age: 'blur'}").is_err());
+    assert!(analyst
+        .script_requires_admin("::mask people {ssn: 'partial'}", &Default::default())
+        .unwrap());
+    admin
+        .run_default("::mask people {ssn: 'partial', email: 'hash', age: 'null'}")
+        .unwrap();
+    assert_eq!(
+        admin.run_default("::mask people").unwrap().into_json()["rows"],
+        json!([["age", "null"], ["email", "hash"], ["ssn", "partial"]])
+    );
+    assert!(admin
+        .run_default("::index create people:by_ssn {ssn}")
+        .is_err());
+
+    let query = "?[id, ssn, email, age] := *people{id, ssn, email, age}";
+    assert_eq!(
+        admin.run_default(query).unwrap().into_json()["rows"],
+        json!([
+            [1, "123-45-6789", "a@x.org", 30],
+            [2, "987-65-4321", null, 40]
+        ])
+    );
+    let rows = analyst.run_default(query).unwrap().rows;
+    assert_eq!(rows[0][1], DataValue::from("*******6789"));
+    assert_eq!(rows[1][1], DataValue::from("*******4321"));
+    let hashed = rows[0][2].get_str().unwrap().to_string();
+    assert_eq!(hashed.len(), 64);
+    assert_eq!(rows[1][2], DataValue::Null);
+    assert_eq!(rows[0][3], DataValue::Null);
+    // the masked values are the ones filtered and joined on
+    assert_eq!(
+        analyst
+            .run_default("?[id] := *people{id, email: 'a@x.org'}")
+            .unwrap()
+            .rows
+            .len(),
+        0
+    );
+    let found = analyst
+        .run_script(
+            "?[id] := *people{id, email: $h}",
+            BTreeMap::from([("h".to_string(), DataValue::from(hashed))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(found.into_json()["rows"], json!([[1]]));
+    // writes through updates keep the stored values
+    analyst
+        .run_default("?[id, name] <- [[1, 'A']] :update people {id => name}")
+        .unwrap();
+    assert_eq!(
+        admin.run_default(query).unwrap().into_json()["rows"][0],
+        json!([1, "123-45-6789", "a@x.org", 30])
+    );
+    assert_eq!(
+        analyst.export_relations(["people"].iter()).unwrap()["people"].rows[0][2],
+        DataValue::from("*******6789")
+    );
+    let graph = std::env::temp_dir().join(format!("cozo-masked-graph-{}.json", std::process::id()));
+    let params = BTreeMap::from([("path".to_string(), DataValue::from(graph.to_str().unwrap()))]);
+    analyst
+        .run_script(
+            "::graph export people $path",
+            params,
+            ScriptMutability::Mutable,
+        )
+        .unwrap();
+    let written = std::fs::read_to_string(&graph).unwrap();
+    assert!(written.contains("*******6789"));
+    assert!(!written.contains("123-45-6789"));
+    std::fs::remove_file(graph).unwrap();
+    #[cfg(feature = "storage-sqlite")]
+    {
+        let path = std::env::temp_dir().join(format!("cozo-masked-diff-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        admin.backup_db(&path).unwrap();
+        admin
+            .run_default("?[id, ssn] <- [[2, '555-55-5555']] :update people {id => ssn}")
+            .unwrap();
+        let diff = format!("::diff '{}' {{rows: 10}}", path.display());
+        let res = analyst.run_default(&diff).unwrap().into_json();
+        assert_eq!(res["rows"][1][2][2], json!("*******5555"));
+        assert_eq!(res["rows"][1][3][2], json!("*******4321"));
+        assert!(!res.to_string().contains("987-65-4321"));
+        for suffix in ["", ".lock", ".pid"] {
+            let mut p = path.clone().into_os_string();
+            p.push(suffix);
+            let _ = std::fs::remove_file(p);
+        }
+    }
+
+    admin
+        .run_default("::mask people {ssn: null, email: null, age: null}")
+        .unwrap();
+    assert_eq!(
+        analyst.run_default(query).unwrap().into_json()["rows"][0],
+        json!([1, "123-45-6789", "a@x.org", 30])
+    );
+}
+
+#[test]
+fn history_expiry_notifications() {
+    let db = DbInstance::default();
+    db.run_default(":create hist {k: Int, vld: Validity => v: Int}")
+        .unwrap();
+    db.run_default(":create hist_old {k: Int, vld: Validity => v: Int}")
+        .unwrap();
+    db.run_default(
+        r#"
+        ?[k, vld, v] <- [[1, [1, true], 1], [1, [2, true], 2], [1, [3, true], 3]]
+        :put hist {k, vld => v}
+        "#,
+    )
+    .unwrap();
+    assert!(db
+        .run_default("::history retention hist {notify: true}")
+        .is_err());
+    assert!(db
+        .run_default("::history prune hist {keep_versions: 1, expire_to: 'nowhere'}")
+        .is_err());
+
+    let (_id, receiver) = db.register_callback("hist", None);
+    db.run_default(
+        "::history retention hist {keep_versions: 1, expire_to: 'hist_old', notify: true}",
+    )
+    .unwrap();
+    let res = db.prune_history().unwrap();
+    assert_eq!(res.rows[0][2], DataValue::from(2));
+    let res = db
+        .run_default("?[k, v] := *hist_old{k, v}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, 1], [1, 2]]));
+    let res = db
+        .run_default("?[k, v] := *hist{k, v}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, 3]]));
+
+    std::thread::sleep(Duration::from_secs_f64(0.01));
+    let (op, keys, old) = receiver.try_recv().unwrap();
+    assert_eq!(op, CallbackOp::Rm);
+    assert_eq!(keys.headers, vec!["k", "vld"]);
+    assert_eq!(keys.rows.len(), 2);
+    assert_eq!(old.rows.len(), 2);
+    assert!(old.rows.iter().any(|row| row[2] == DataValue::from(2)));
+}
+
+#[test]
+fn chunked_export() {
+    let dir = std::env::temp_dir().join(format!("cozo-export-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let db = DbInstance::default();
+    db.run_default(":create big {k: Int => v: String}").unwrap();
+    db.run_default("?[k, v] := k in int_range(25), v = to_string(k) :put big {k => v}")
+        .unwrap();
+    let script = format!(
+        "::export chunked big '{}' {{rows_per_chunk: 10}}",
+        dir.display()
+    );
+
+    let res = db.run_default(&script).unwrap().into_json();
+    assert_eq!(res["rows"][0][0], json!(3));
+    assert_eq!(res["rows"][0][1], json!(25));
+    assert_eq!(res["rows"][0][2], json!(0));
+    let last = std::fs::read_to_string(dir.join("chunk-000002.jsonl")).unwrap();
+    assert_eq!(last.lines().count(), 5);
+    assert_eq!(last.lines().next(), Some(r#"[20,"20"]"#));
+
+    // an interrupted export resumes after the last chunk that checks out
+    std::fs::write(dir.join("chunk-000001.jsonl"), "[]\n").unwrap();
+    std::fs::remove_file(dir.join("chunk-000002.jsonl")).unwrap();
+    db.run_default("?[k, v] <- [[100, 'new']] :put big {k => v}")
+        .unwrap();
+    let res = db.run_default(&script).unwrap().into_json();
+    assert_eq!(
+        res["rows"][0],
+        json!([3, 26, 1, dir.join("manifest.json").to_string_lossy()])
+    );
+    let second = std::fs::read_to_string(dir.join("chunk-000001.jsonl")).unwrap();
+    assert!(second.starts_with(r#"[10,"10"]"#));
+
+    assert!(db
+        .run_default(&format!("::export chunked big '{}'", dir.display()))
+        .is_err());
+    let res = db
+        .run_default(&format!(
+            "::export chunked big '{}' {{rows_per_chunk: 100, restart: true}}",
+            dir.display()
+        ))
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"][0][0], json!(1));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn clone_relation() {
+    let db = DbInstance::default();
+    db.run_default(":create base {k: Int => v: String}")
+        .unwrap();
+    db.run_default("::index create base:by_v {v}").unwrap();
+    db.run_default("?[k, v] <- [[1, 'a'], [2, 'b']] :put base {k => v}")
+        .unwrap();
+    db.run_default("::clone base branch").unwrap();
+    assert!(db.run_default("::clone base branch").is_err());
+
+    db.run_default("?[k, v] <- [[2, 'c'], [3, 'd']] :put branch {k => v}")
+        .unwrap();
+    let res = db
+        .run_default("?[k, v] := *base{k, v}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, "a"], [2, "b"]]));
+    let res = db
+        .run_default("?[k, v] := *branch{k, v}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, "a"], [2, "c"], [3, "d"]]));
+    let res = db
+        .run_default("?[v, k] := *branch:by_v{v, k}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([["a", 1], ["c", 2], ["d", 3]]));
+    let res = db
+        .run_default("?[v, k] := *base:by_v{v, k}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([["a", 1], ["b", 2]]));
+}
+
+#[test]
+fn branch_and_merge() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k: Int => v: Int}").unwrap();
+    db.run_default(":create b {k: Int => v: Int}").unwrap();
+    db.run_default(":create c {k: Int => v: Int}").unwrap();
+    db.run_default("::branch create dev").unwrap();
+    assert!(db.run_default("::branch create dev").is_err());
+    let res = db.run_default("::branch").unwrap().into_json();
+    assert_eq!(res["rows"], json!([["dev", 3]]));
+
+    let dev = db.branch("dev").unwrap();
+    dev.run_default("?[k, v] <- [[1, 1]] :put a {k => v}")
+        .unwrap();
+    dev.run_default("?[k, v] <- [[1, 1]] :put c {k => v}")
+        .unwrap();
+    dev.run_default(":create d {k: Int}").unwrap();
+    db.run_default("?[k, v] <- [[2, 2]] :put b {k => v}")
+        .unwrap();
+    db.run_default("?[k, v] <- [[2, 2]] :put c {k => v}")
+        .unwrap();
+    assert!(db
+        .run_default("?[k, v] := *a{k, v}")
+        .unwrap()
+        .rows
+        .is_empty());
+
+    let res = db.run_default("::branch merge dev").unwrap().into_json();
+    assert_eq!(
+        res["rows"],
+        json!([["a", "merged"], ["c", "conflict"], ["d", "merged"]])
+    );
+    let res = db.run_default("?[k, v] := *a{k, v}").unwrap().into_json();
+    assert_eq!(res["rows"], json!([[1, 1]]));
+    let res = db.run_default("?[k, v] := *b{k, v}").unwrap().into_json();
+    assert_eq!(res["rows"], json!([[2, 2]]));
+    let res = db.run_default("?[k, v] := *c{k, v}").unwrap().into_json();
+    assert_eq!(res["rows"], json!([[2, 2]]));
+    db.run_default("?[k] <- [[3]] :put d {k}").unwrap();
+
+    // merged relations are not merged again
+    let res = db.run_default("::branch merge dev").unwrap().into_json();
+    assert_eq!(res["rows"], json!([["c", "conflict"]]));
+    db.run_default("::branch drop dev").unwrap();
+    assert!(db.branch("dev").is_err());
+}
+
+#[test]
+fn scrub_finds_index_corruption() {
+    let mem = crate::new_cozo_mem().unwrap();
+    let db = DbInstance::Mem(mem.clone());
+    db.run_default(":create friends {fr: Int, to: Int => data: Int}")
+        .unwrap();
+    db.run_default("::index create friends:rev {to, data}")
+        .unwrap();
+    db.run_default("?[fr, to, data] <- [[1, 2, 3], [4, 5, 6]] :put friends {fr, to => data}")
+        .unwrap();
+    let res = db.run_default("::scrub now").unwrap().into_json();
+    assert_eq!(res["rows"], json!([[1, false, 0]]));
+
+    // remove an index row behind the back of the database
+    {
+        let mut tx = mem.transact_write().unwrap();
+        let handle = tx.get_relation("friends:rev", false).unwrap();
+        let key = vec![DataValue::from(2), DataValue::from(3), DataValue::from(1)];
+        tx.store_tx.del(&key.encode_as_key(handle.id)).unwrap();
+        tx.commit_tx().unwrap();
+    }
+    let res = db.scrub().unwrap().into_json();
+    assert_eq!(res["rows"], json!([[1, false, 1]]));
+    let res = db
+        .run_default("?[kind, relation] := *cozo.scrub{kind, relation}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([["missing_index_row", "friends"]]));
+    assert!(db
+        .run_default("?[at, seq] <- [[0.0, 0]] :rm cozo.scrub {at, seq}")
+        .is_err());
+}
+
+#[test]
+fn index_fallback() {
+    let mem = crate::new_cozo_mem().unwrap();
+    // scripts are run on the `Db` for the rebuilding jobs to be run only when asked
+    let run = |script: &str| {
+        mem.run_script(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap()
+    };
+    run(":create friends {fr: Int, to: Int => data: Int}");
+    run("::index create friends:rev {to, data}");
+    run("?[fr, to, data] <- [[1, 2, 3], [4, 2, 6]] :put friends {fr, to => data}");
+    {
+        let mut tx = mem.transact_write().unwrap();
+        let handle = tx.get_relation("friends:rev", false).unwrap();
+        let key = vec![DataValue::from(2), DataValue::from(3), DataValue::from(1)];
+        tx.store_tx.del(&key.encode_as_key(handle.id)).unwrap();
+        tx.commit_tx().unwrap();
+    }
+    let query = "?[fr] := *friends{fr, to: 2}";
+    assert_eq!(run(query).rows, vec![vec![DataValue::from(4)]]);
+
+    mem.set_index_fallback(true);
+    run("::scrub now");
+    let res = run(query);
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from(1)], vec![DataValue::from(4)]]
+    );
+    assert_eq!(res.warnings.len(), 1);
+    assert!(res.warnings[0].contains("friends:rev"));
+    mem.run_queued_jobs();
+    let res = run(query);
+    assert_eq!(res.rows.len(), 2);
+    assert!(res.warnings.is_empty());
+
+    run(r"
+        ?[k, v] <- [['a', [1, 2]], ['b', [2, 3]], ['c', [30, 40]]]
+        :create a {k: String => v: <F32; 2>}
+    ");
+    run(r"
+        ::hnsw create a:vec {
+            dim: 2,
+            m: 50,
+            dtype: F32,
+            fields: [v],
+            distance: L2,
+            ef_construction: 20,
+        }");
+    {
+        let mut tx = mem.transact_write().unwrap();
+        let handle = tx.get_relation("a", false).unwrap();
+        let key = vec![DataValue::from("b")];
+        tx.store_tx.del(&key.encode_as_key(handle.id)).unwrap();
+        tx.commit_tx().unwrap();
+    }
+    let query = "?[k] := ~a:vec{k | query: vec([1, 2]), k: 2, ef: 20}";
+    mem.set_index_fallback(false);
+    let err = mem
+        .run_script(query, Default::default(), ScriptMutability::Immutable)
+        .unwrap_err();
+    assert!(inconsistent_index(&err).is_some());
+
+    mem.set_index_fallback(true);
+    let res = run(query);
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from("a")], vec![DataValue::from("c")]]
+    );
+    assert_eq!(res.warnings.len(), 1);
+    mem.run_queued_jobs();
+    let res = run(query);
+    assert_eq!(res.rows.len(), 2);
+    assert!(res.warnings.is_empty());
+}
+
+#[test]
+fn index_fallback_after_writes() {
+    let mem = crate::new_cozo_mem().unwrap();
+    let run = |script: &str| mem.run_script(script, Default::default(), ScriptMutability::Mutable);
+    run(":create a {k: String => v: <F32; 2>}").unwrap();
+    run("?[k, v] <- [['a', [1, 2]], ['b', [2, 3]], ['c', [30, 40]]] :put a {k => v}").unwrap();
+    run("::hnsw create a:vec {dim: 2, m: 50, dtype: F32, fields: [v], distance: L2, ef_construction: 20}")
+        .unwrap();
+    run(":create events {id: Int}").unwrap();
+    run(":create hits {id: Int, k: String}").unwrap();
+    // the index is read by the trigger, once the event is written
+    run(r"
+        ::set_triggers events
+        on put {
+            ?[id, k] := _new[id], ~a:vec{k | query: vec([1, 2]), k: 2, ef: 20}
+            :put hits {id, k}
+        }
+    ")
+    .unwrap();
+    {
+        let mut tx = mem.transact_write().unwrap();
+        let handle = tx.get_relation("a", false).unwrap();
+        let key = vec![DataValue::from("b")];
+        tx.store_tx.del(&key.encode_as_key(handle.id)).unwrap();
+        tx.commit_tx().unwrap();
+    }
+    mem.set_index_fallback(true);
+
+    // running the insertion again would find the row it has just inserted
+    let insert = "?[id] <- [[1]] :insert events {id}";
+    let err = run(insert).unwrap_err();
+    assert!(inconsistent_index(&err).is_some());
+    assert!(run("?[id] := *events{id}").unwrap().rows.is_empty());
+    // the index is read around from then on
+    run(insert).unwrap();
+    assert_eq!(
+        run("?[id, k] := *hits{id, k}").unwrap().into_json()["rows"],
+        json!([[1, "a"], [1, "c"]])
+    );
+}
+
+#[test]
+fn journaled_mem_storage() {
+    let mem = crate::new_cozo_mem_journaled(Default::default()).unwrap();
+    let db = DbInstance::Mem(mem.clone());
+    db.run_default(":create a {k: Int => v: Int}").unwrap();
+    db.run_default("?[k, v] <- [[1, 1], [2, 2]] :put a {k => v}")
+        .unwrap();
+    let mut saved = mem.snapshot();
+    assert!(mem.take_changes().is_empty());
+
+    db.run_default("?[k, v] <- [[3, 3]] :put a {k => v}")
+        .unwrap();
+    db.run_default("?[k] <- [[1]] :rm a {k}").unwrap();
+    let changes = mem.take_changes();
+    assert!(changes.values().any(|v| v.is_none()));
+    assert!(mem.take_changes().is_empty());
+    for (k, v) in changes {
+        match v {
+            Some(v) => saved.insert(k, v),
+            None => saved.remove(&k),
+        };
+    }
+
+    let reopened = DbInstance::Mem(crate::new_cozo_mem_journaled(saved).unwrap());
+    let res = reopened
+        .run_default("?[k, v] := *a{k, v}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[2, 2], [3, 3]]));
+    reopened.run_default(":create b {k: Int}").unwrap();
+    let res = reopened.run_default("::relations").unwrap();
+    assert_eq!(res.rows.len(), 2);
+}
+
+#[test]
+fn lite_resource_profile() {
+    let db = DbInstance::new("mem", "", r#"{"profile": "lite"}"#).unwrap();
+    db.run_default(":create a {k: String => v: String, vec: <F32; 2>}")
+        .unwrap();
+    let err = db
+        .run_default("::fts create a:fts {extractor: v, tokenizer: Simple}")
+        .unwrap_err();
+    assert!(err.to_string().contains("FTS indices are disabled"));
+    assert!(db
+        .run_default(
+            "::hnsw create a:vec {dim: 2, dtype: F32, fields: [vec], m: 8, ef_construction: 20}"
+        )
+        .is_err());
+    let params = BTreeMap::from([("script".to_string(), DataValue::from("?[x] <- [[1]]"))]);
+    assert!(db
+        .run_script("::job submit $script", params, ScriptMutability::Mutable)
+        .is_err());
+
+    let mut profile = ResourceProfile::lite();
+    profile.fts = true;
+    db.set_resource_profile(profile);
+    db.run_default("::fts create a:fts {extractor: v, tokenizer: Simple}")
+        .unwrap();
+    assert!(DbInstance::new("mem", "", r#"{"profile": "huge"}"#).is_err());
+}
+
+#[test]
+fn deterministic_results() {
+    let db = DbInstance::default();
+    let rows = |script: &str| db.run_default(script).unwrap().into_json()["rows"].clone();
+    assert_eq!(
+        rows("?[collect(x)] := x in [3, 1, 2]"),
+        json!([[[3, 1, 2]]])
+    );
+    assert_eq!(
+        rows("?[collect(x)] := x in [3, 1, 2] :deterministic true"),
+        json!([[[1, 2, 3]]])
+    );
+
+    db.set_deterministic(true);
+    assert_eq!(
+        rows("?[collect(x)] := x in [3, 1, 2]"),
+        json!([[[1, 2, 3]]])
+    );
+    assert_eq!(rows("?[choice(x)] := x in [3, 1, 2]"), json!([[1]]));
+    assert_eq!(rows("?[x] := x in [3, 1, 2] :limit 2"), json!([[1], [2]]));
+    assert_eq!(rows("?[x] := x in [3, 1, 2] :offset 1"), json!([[2], [3]]));
+    assert_eq!(
+        rows("?[collect(x)] := x in [3, 1, 2] :deterministic false"),
+        json!([[[3, 1, 2]]])
+    );
+}
+
+#[test]
+fn optional_atoms() {
+    let db = DbInstance::default();
+    db.run_default(":create r {a: Int}").unwrap();
+    db.run_default(":create s {a: Int => b: String}").unwrap();
+    db.run_default("?[a] <- [[1], [2], [3]] :put r {a}")
+        .unwrap();
+    db.run_default("?[a, b] <- [[1, 'x'], [3, 'z']] :put s {a => b}")
+        .unwrap();
+    let rows = |script: &str| db.run_default(script).unwrap().into_json()["rows"].clone();
+
+    assert_eq!(
+        rows("?[a, b] := *r{a}, optional *s{a, b}"),
+        json!([[1, "x"], [2, null], [3, "z"]])
+    );
+    assert_eq!(
+        rows("?[a, b] := *r{a}, optional *s[a, b] default {b: 'none'}"),
+        json!([[1, "x"], [2, "none"], [3, "z"]])
+    );
+    assert_eq!(
+        rows(
+            r"
+            f[a, c] <- [[2, 20]]
+            ?[a, c, n] := *r{a}, optional f[a, c] default {c: 0}, n = c + 1
+            "
+        ),
+        json!([[1, 0, 1], [2, 20, 21], [3, 0, 1]])
+    );
+    // the default of a variable bound elsewhere makes no sense
+    assert!(db
+        .run_default("?[a, b] := *r{a}, optional *s{a, b} default {a: 0}")
+        .is_err());
+    assert!(db
+        .run_default("?[a, b] := *r{a}, (optional *s{a, b} or b = 1)")
+        .is_err());
+}
+
+#[test]
+fn set_operators() {
+    let db = DbInstance::default();
+    db.run_default(":create cohort_a {id: Int}").unwrap();
+    db.run_default(":create cohort_b {id: Int => joined: Int}")
+        .unwrap();
+    db.run_default("?[id] <- [[1], [2], [3], [4]] :put cohort_a {id}")
+        .unwrap();
+    db.run_default(
+        "?[id, joined] <- [[2, 2020], [4, 2021], [5, 2022]] :put cohort_b {id => joined}",
+    )
+    .unwrap();
+    let rows = |script: &str| db.run_default(script).unwrap().into_json()["rows"].clone();
+
+    assert_eq!(
+        rows("?[id] := *cohort_a{id} intersect *cohort_b{id}"),
+        json!([[2], [4]])
+    );
+    assert_eq!(
+        rows("?[id] := *cohort_a{id} except *cohort_b{id}"),
+        json!([[1], [3]])
+    );
+    assert_eq!(
+        rows(
+            r"
+            recent[id] := *cohort_b{id, joined}, joined > 2020
+            ?[id] := *cohort_a[id] intersect *cohort_b{id} except recent[id]
+            "
+        ),
+        json!([[2]])
+    );
+    assert!(db
+        .run_default("?[id] := *cohort_a{id} except *cohort_b{id, joined}")
+        .is_err());
+}
+
+#[test]
+fn pivot_and_unpivot() {
+    let db = DbInstance::default();
+    db.run_default(":create eav {id: Int, attr: String => val: Any}")
+        .unwrap();
+    db.run_default(
+        r"?[id, attr, val] <- [[1, 'name', 'a'], [1, 'age', 30], [2, 'name', 'b'], [2, 'tag', 'x']]
+        :put eav {id, attr => val}",
+    )
+    .unwrap();
+    let rows = |script: &str| db.run_default(script).unwrap().into_json()["rows"].clone();
+
+    assert_eq!(
+        rows("?[id, name, age] <~ Pivot(*eav[], attributes: ['name', 'age'])"),
+        json!([[1, "a", 30], [2, "b", null]])
+    );
+    assert_eq!(
+        rows(
+            r"
+            wide[id, name, age] <~ Pivot(*eav[], attributes: ['name', 'age'], default: 0)
+            ?[id, attr, val] <~ Unpivot(wide[], attributes: ['name', 'age'])
+            "
+        ),
+        json!([
+            [1, "age", 30],
+            [1, "name", "a"],
+            [2, "age", 0],
+            [2, "name", "b"]
+        ])
+    );
+    assert_eq!(
+        rows(
+            r"
+            wide[id, name, age] <- [[3, 'c', null]]
+            ?[id, attr, val] <~ Unpivot(wide[], attributes: ['name', 'age'], keep_nulls: true)
+            "
+        ),
+        json!([[3, "age", null], [3, "name", "c"]])
+    );
+    assert!(db
+        .run_default(
+            r"
+            dup[id, attr, val] <- [[1, 'name', 'a'], [1, 'name', 'b']]
+            ?[id, name] <~ Pivot(dup[], attributes: ['name'])
+            "
+        )
+        .is_err());
+}
+
+#[test]
+fn closure_rules() {
+    let db = DbInstance::default();
+    db.run_default(":create follows {src: Int, dst: Int}")
+        .unwrap();
+    db.run_default(
+        r"?[src, dst] <- [[1, 2], [2, 3], [3, 1], [3, 4], [5, 6]]
+        :put follows {src, dst}",
+    )
+    .unwrap();
+    let rows = |script: &str| db.run_default(script).unwrap().into_json()["rows"].clone();
+
+    assert_eq!(
+        rows(
+            r"
+            reachable[node, depth] := closure(*follows{src: a, dst: b}, from: 1)
+            ?[node, depth] := reachable[node, depth]
+            "
+        ),
+        json!([[1, 3], [2, 1], [3, 2], [4, 3]])
+    );
+    assert_eq!(
+        rows("?[node] := closure(*follows[a, b], from: 1, max_depth: 2)"),
+        json!([[2], [3]])
+    );
+    assert_eq!(
+        rows(
+            r"
+            edge[a, b] := *follows{src: a, dst: b}
+            ?[start, node] := closure(edge[a, b])
+            :order start, node
+            "
+        ),
+        json!([
+            [1, 1],
+            [1, 2],
+            [1, 3],
+            [1, 4],
+            [2, 1],
+            [2, 2],
+            [2, 3],
+            [2, 4],
+            [3, 1],
+            [3, 2],
+            [3, 3],
+            [3, 4],
+            [5, 6]
+        ])
+    );
+    assert_eq!(
+        rows("?[start, node, depth] := closure(*follows[a, b], max_depth: 1)"),
+        json!([[1, 2, 1], [2, 3, 1], [3, 1, 1], [3, 4, 1], [5, 6, 1]])
+    );
+    assert!(db
+        .run_default("?[node] := closure(*follows[a, a], from: 1)")
+        .is_err());
+    assert!(db
+        .run_default("?[node] := closure(*follows[a, b], from: 1, depth: 2)")
+        .is_err());
+}
+
+#[test]
+fn declared_params() {
+    let db = DbInstance::default();
+    let run = |params: Vec<(&str, DataValue)>| {
+        db.run_script(
+            r"
+            :params {id: Int, tags: [String], limit: Int?}
+            ?[id, tags, limit] <- [[$id, $tags, $limit]]
+            ",
+            params
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+            ScriptMutability::Immutable,
+        )
+    };
+    let tags = DataValue::List(vec![DataValue::from("a"), DataValue::from("b")]);
+
+    assert_eq!(
+        run(vec![("id", DataValue::from(1.0)), ("tags", tags.clone())])
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1, ["a", "b"], null]])
+    );
+    assert!(run(vec![("id", DataValue::from("1")), ("tags", tags.clone())]).is_err());
+    assert!(run(vec![
+        ("id", DataValue::from(1)),
+        ("tags", DataValue::List(vec![DataValue::from(1)]))
+    ])
+    .is_err());
+    assert!(run(vec![("tags", tags)]).is_err());
+}
+
+#[test]
+fn column_descriptions() {
+    let db = DbInstance::default();
+    db.run_default(":create users {id: Int => email: String}")
+        .unwrap();
+    db.run_default("::describe users 'The users of the app'")
+        .unwrap();
+    db.run_default("::describe set users email 'Verified at signup'")
+        .unwrap();
+    let descriptions = || {
+        let res = db.run_default("::columns users").unwrap().into_json();
+        let pos = res["headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .position(|h| h == "description")
+            .unwrap();
+        res["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row[pos].clone())
+            .collect_vec()
+    };
+    assert_eq!(
+        descriptions(),
+        vec![json!(null), json!("Verified at signup")]
+    );
+
+    let res = db.run_default("::relations").unwrap().into_json();
+    assert!(res["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|row| row[0] == "users"
+            && row
+                .as_array()
+                .unwrap()
+                .contains(&json!("The users of the app"))));
+
+    let res = db.run_default("?[e] := *users{email: e}").unwrap();
+    assert_eq!(
+        res.columns[0].description.as_deref(),
+        Some("Verified at signup")
+    );
+
+    assert!(db
+        .run_default("::describe set users name 'Full name'")
+        .is_err());
+    db.run_default("::describe set users email").unwrap();
+    assert_eq!(descriptions(), vec![json!(null), json!(null)]);
+
+    // descriptions are writes, refused in read-only transactions
+    for script in [
+        "::describe set users email 'Read only'",
+        "::describe users 'Read only'",
+        "{::describe set users email 'Read only'}",
+    ] {
+        assert!(db
+            .run_script(script, Default::default(), ScriptMutability::Immutable)
+            .is_err());
+    }
+    assert_eq!(descriptions(), vec![json!(null), json!(null)]);
+}
+
+#[test]
+fn usage_stats() {
+    let db = DbInstance::default();
+    db.run_default(":create users {id: Int => email: String}")
+        .unwrap();
+    db.run_default(":create unused {id: Int}").unwrap();
+    db.run_default("::index create users:by_email {email}")
+        .unwrap();
+    db.run_default("?[id, email] <- [[1, 'a@x'], [2, 'b@x']] :put users {id => email}")
+        .unwrap();
+    db.run_default("?[id] := *users{id, email: 'a@x'} /*+ use_index(by_email) */")
+        .unwrap();
+    db.run_default("?[id] := *users{id}, id > 1").unwrap();
+
+    let res = db.run_default("::usage").unwrap().into_json();
+    let usage: BTreeMap<String, serde_json::Value> = res["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| (row[0].as_str().unwrap().to_string(), row.clone()))
+        .collect();
+    assert_eq!(usage["users"][1], json!("relation"));
+    assert_eq!(usage["users"][2], json!(2));
+    assert_eq!(usage["users"][3], json!(1));
+    assert!(usage["users"][4].is_f64());
+    assert!(usage["users"][5].is_f64());
+    assert_eq!(usage["users:by_email"][1], json!("index"));
+    assert_eq!(usage["users:by_email"][2], json!(1));
+    assert_eq!(usage["users:by_email"][3], json!(1));
+    assert_eq!(usage["unused"][2], json!(0));
+    assert_eq!(usage["unused"][3], json!(0));
+    assert_eq!(usage["unused"][4], json!(null));
+}
+
+#[test]
+fn advise_indexes() {
+    let path = std::env::temp_dir().join(format!("cozo-advise-test-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let log = path.to_str().unwrap();
+
+    let db = DbInstance::default();
+    assert!(db.run_default("::advise indexes").is_err());
+    db.run_default(":create users {id: Int => email: String, bio: String, emb: <F32; 2>}")
+        .unwrap();
+    db.set_replay_log(Some(log)).unwrap();
+    db.run_script(
+        "?[id] := *users{id, email: $email}",
+        BTreeMap::from([("email".to_string(), DataValue::from("a@b.c"))]),
+        ScriptMutability::Immutable,
+    )
+    .unwrap();
+    db.run_default("?[id] := *users{id, email: 'x@y.z'}")
+        .unwrap();
+    db.run_default("?[id] := *users{id, bio}, str_includes(bio, 'rust')")
+        .unwrap();
+    db.run_default("?[id, d] := *users{id, emb}, d = cos_dist(emb, vec([1, 0]))")
+        .unwrap();
+    db.run_default("?[email] := *users{id: 1, email}").unwrap();
+
+    let res = db.run_default("::advise indexes").unwrap();
+    assert_eq!(
+        res.headers,
+        [
+            "relation",
+            "kind",
+            "columns",
+            "queries",
+            "total_secs",
+            "command"
+        ]
+    );
+    assert_eq!(res.rows.len(), 3);
+    let by_kind = |kind: &str| {
+        res.rows
+            .iter()
+            .find(|row| row[1] == DataValue::from(kind))
+            .unwrap()
+            .clone()
+    };
+    let index = by_kind("index");
+    assert_eq!(index[2], DataValue::List(vec![DataValue::from("email")]));
+    assert_eq!(index[3], DataValue::from(2));
+    assert_eq!(by_kind("fts")[3], DataValue::from(1));
+    assert_eq!(by_kind("hnsw")[3], DataValue::from(1));
+    for row in &res.rows {
+        db.run_default(row[5].get_str().unwrap()).unwrap();
+    }
+    db.set_replay_log(None).unwrap();
+
+    let res = db
+        .run_default(&format!("::advise indexes '{log}'"))
+        .unwrap();
+    assert!(res.rows.is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn arrow_export() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float32Type, Int64Type};
+    use arrow_schema::DataType;
+
+    use crate::ArrowFormat;
+
+    let db = DbInstance::default();
+    db.run_default(
+        ":create r {k: Int, vld: Validity => id: Uuid, v: <F32; 2>, doc: Json, tags: [String]?}",
+    )
+    .unwrap();
+    db.run_default(
+        r#"
+        ?[k, vld, id, v, doc, tags] <- [
+            [9007199254740993, 'ASSERT', rand_uuid_v4(), vec([1, 2]), json([1, 2]), ['x']],
+            [2, 'ASSERT', rand_uuid_v4(), vec([3, 4]), json([]), null]
+        ]
+        :put r {k, vld => id, v, doc, tags}
+    "#,
+    )
+    .unwrap();
+
+    let exported = db
+        .export_relations_arrow(["r"].iter(), ArrowFormat::Ipc)
+        .unwrap();
+    let reader =
+        arrow_ipc::reader::StreamReader::try_new(std::io::Cursor::new(&exported["r"]), None)
+            .unwrap();
+    let schema = reader.schema();
+    assert_eq!(schema.field(0).data_type(), &DataType::Int64);
+    assert!(matches!(schema.field(1).data_type(), DataType::Struct(_)));
+    assert_eq!(schema.field(2).data_type(), &DataType::FixedSizeBinary(16));
+    assert!(matches!(
+        schema.field(3).data_type(),
+        DataType::FixedSizeList(_, 2)
+    ));
+    assert_eq!(schema.field(4).metadata()["ARROW:extension:name"], "arrow.json");
+    assert_eq!(schema.field(5).metadata()["cozo:type"], "[String]?");
+    assert!(schema.field(5).is_nullable());
+
+    let batches: Vec<_> = reader.map(|b| b.unwrap()).collect();
+    assert_eq!(batches.len(), 1);
+    let batch = &batches[0];
+    assert_eq!(batch.num_rows(), 2);
+    let keys = batch.column(0).as_primitive::<Int64Type>();
+    assert_eq!(keys.value(1), 9007199254740993);
+    let vecs = batch.column(3).as_fixed_size_list();
+    let first = vecs.value(0);
+    assert_eq!(first.as_primitive::<Float32Type>().values().to_vec(), vec![3., 4.]);
+    assert!(batch.column(5).is_null(0));
+
+    let res = db
+        .run_default("?[k, v, n] := *r{k, v}, n = null")
+        .unwrap();
+    let data = res.to_arrow(ArrowFormat::Parquet).unwrap();
+    assert_eq!(&data[..4], b"PAR1");
+
+    assert!(db
+        .export_relations_arrow(["nope"].iter(), ArrowFormat::Ipc)
+        .is_err());
+}
+
+#[test]
+fn config_reload() {
+    let db = DbInstance::default();
+    let err = db.run_default("::config reload").unwrap_err();
+    assert!(err.to_string().contains("no configuration to reload"));
+
+    let reloads = Arc::new(AtomicUsize::new(0));
+    let counter = reloads.clone();
+    db.set_config_reloader(Some(Arc::new(move || {
+        let n = counter.fetch_add(1, Ordering::Relaxed) + 1;
+        Ok(NamedRows::new(
+            vec!["reloads".to_string()],
+            vec![vec![DataValue::from(n as i64)]],
+        ))
+    })));
+    let res = db.run_default("::config reload").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(1)]]);
+    db.run_default("::config reload").unwrap();
+    assert_eq!(reloads.load(Ordering::Relaxed), 2);
+    assert!(db.script_requires_admin("::config reload", &Default::default()).unwrap());
+
+    db.set_config_reloader(None);
+    assert!(db.run_default("::config reload").is_err());
+}
+
+#[test]
+fn streaming_results() {
+    let db = DbInstance::default();
+    let mut stream = db
+        .run_script_streaming(
+            "?[x, y] := x in int_range(10000), y = x * 2",
+            Default::default(),
+            ScriptMutability::Immutable,
+            16,
+        )
+        .unwrap();
+    assert_eq!(stream.headers(), ["x", "y"]);
+    let first = stream.next_chunk(100).unwrap();
+    assert_eq!(first.len(), 100);
+    assert_eq!(first[0], vec![DataValue::from(0), DataValue::from(0)]);
+    let rest: Vec<_> = stream.map(|r| r.unwrap()).collect();
+    assert_eq!(rest.len(), 9900);
+    assert_eq!(
+        rest.last().unwrap(),
+        &vec![DataValue::from(9999), DataValue::from(19998)]
+    );
+
+    // dropping a stream kills its query
+    let stream = db
+        .run_script_streaming(
+            "?[x] := x in int_range(100000)",
+            Default::default(),
+            ScriptMutability::Immutable,
+            1,
+        )
+        .unwrap();
+    drop(stream);
+
+    // scripts that cannot be streamed give all their rows at the end
+    let stream = db
+        .run_script_streaming(
+            "{?[a] <- [[1], [2]] :create s {a}} {?[a] := *s{a}}",
+            Default::default(),
+            ScriptMutability::Mutable,
+            1,
+        )
+        .unwrap();
+    assert_eq!(stream.headers(), ["a"]);
+    assert_eq!(stream.map(|r| r.unwrap()).count(), 2);
+
+    assert!(db
+        .run_script_streaming(
+            "?[x] := y",
+            Default::default(),
+            ScriptMutability::Immutable,
+            1
+        )
+        .is_err());
+}
+
+#[test]
+fn prepared_queries() {
+    let db = DbInstance::default();
+    db.run_default(r"?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']] :create kv {k => v}")
+        .unwrap();
+    let k = |k: i64| BTreeMap::from([("k".to_string(), DataValue::from(k))]);
+
+    let q = db.prepare("?[v] := *kv{k: $k, v}").unwrap();
+    assert!(q.is_parsed());
+    for (key, v) in [(1, "a"), (3, "c")] {
+        let res = db
+            .run_prepared(&q, k(key), ScriptMutability::Immutable)
+            .unwrap();
+        assert_eq!(res.rows, vec![vec![DataValue::from(v)]]);
+    }
+    let err = db
+        .run_prepared(&q, Default::default(), ScriptMutability::Immutable)
+        .unwrap_err();
+    assert!(err.to_string().contains("Required parameter k not found"));
+
+    // declared parameters are checked on every run
+    let q = db
+        .prepare(":params {k: Int} ?[v] := *kv{k: key, v}, key > $k")
+        .unwrap();
+    assert!(q.is_parsed());
+    let res = db
+        .run_prepared(&q, k(1), ScriptMutability::Immutable)
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from("b")], vec![DataValue::from("c")]]
+    );
+    let bad = BTreeMap::from([("k".to_string(), DataValue::from("x"))]);
+    assert!(db
+        .run_prepared(&q, bad, ScriptMutability::Immutable)
+        .is_err());
+
+    // parameters used in options are substituted by parsing again
+    let q = db.prepare("?[k] := *kv{k} :limit $k").unwrap();
+    assert!(!q.is_parsed());
+    let res = db
+        .run_prepared(&q, k(2), ScriptMutability::Immutable)
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+
+    let q = db.prepare("?[k, v] <- [[$k, 'd']] :put kv {k => v}").unwrap();
+    assert!(db
+        .run_prepared(&q, k(4), ScriptMutability::Immutable)
+        .is_err());
+    db.run_prepared(&q, k(4), ScriptMutability::Mutable)
+        .unwrap();
+    assert_eq!(db.run_default("?[k] := *kv{k}").unwrap().rows.len(), 4);
+
+    assert!(db.prepare("?[k] := *kv{k").is_err());
+
+    // the queries of dashboards, filtering by parameters with a fixed limit, are parsed once
+    db.run_default("?[id, kind, ts] <- [[1, 'click', 10], [2, 'view', 20], [3, 'click', 30], [4, 'click', 90]] :create events {id => kind, ts}")
+        .unwrap();
+    let q = db
+        .prepare(
+            r"
+        :params {kinds: [String], from: Int, to: Int}
+        ?[kind, bucket, count(id)] := *events{id, kind, ts}, is_in(kind, $kinds),
+                                      ts >= $from, ts < $to, bucket = floor(ts / 50)
+        :order -bucket
+        :limit 100
+    ",
+        )
+        .unwrap();
+    assert!(q.is_parsed());
+    let kinds = DataValue::List(vec![DataValue::from("click")]);
+    let params = BTreeMap::from([
+        ("kinds".to_string(), kinds),
+        ("from".to_string(), DataValue::from(0)),
+        ("to".to_string(), DataValue::from(100)),
+    ]);
+    let res = db
+        .run_prepared(&q, params, ScriptMutability::Immutable)
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["click", 1.0, 1], ["click", 0.0, 2]])
+    );
+}
+
+#[test]
+fn log_level() {
+    let db = DbInstance::default();
+    let level = |db: &DbInstance, script: &str| {
+        let res = db.run_default(script).unwrap();
+        res.rows[0][0].get_str().unwrap().to_string()
+    };
+    let before = log::max_level();
+    assert_eq!(level(&db, "::log level"), before.as_str());
+    assert_eq!(level(&db, "::log level debug"), "DEBUG");
+    assert_eq!(log::max_level(), log::LevelFilter::Debug);
+    // queries of a session are logged with its id
+    db.with_session("s1").run_default("?[x] <- [[1]]").unwrap();
+    assert!(db.run_default("::log level loud").is_err());
+    assert_eq!(
+        level(&db, &format!("::log level {before}")),
+        before.as_str()
+    );
+
+    assert!(db
+        .script_requires_admin("::log level off", &Default::default())
+        .unwrap());
+    assert!(!db
+        .script_requires_admin("::log level", &Default::default())
+        .unwrap());
+}
+
+/// Wait for a future on the current thread, there being no async runtime in the tests
+fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark()
+        }
+    }
+    let waker = Arc::new(ThreadWaker(std::thread::current())).into();
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut fut = std::pin::pin!(fut);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            std::task::Poll::Ready(res) => return res,
+            std::task::Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[test]
+fn async_calls() {
+    let db = DbInstance::default();
+    let res = block_on(db.run_script_async(
+        "?[a] <- [[1], [2]] :create a {a}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    ))
+    .unwrap();
+    assert_eq!(res.headers, ["status"]);
+    let exported = block_on(db.export_relations_async(["a"].iter())).unwrap();
+    assert_eq!(exported["a"].rows.len(), 2);
+    block_on(db.run_script_async(
+        ":create b {a}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    ))
+    .unwrap();
+    block_on(db.import_relations_async(BTreeMap::from([("b".to_string(), exported["a"].clone())])))
+        .unwrap();
+    let prepared = db.prepare("?[a] := *b{a}, a > $min").unwrap();
+    let res = block_on(db.run_prepared_async(
+        &prepared,
+        BTreeMap::from([("min".to_string(), DataValue::from(1))]),
+        ScriptMutability::Immutable,
+    ))
+    .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+
+    // many calls are made at once
+    let futures = (0..20)
+        .map(|i| {
+            db.run_script_async(
+                "?[x] := x = $i * 2",
+                BTreeMap::from([("i".to_string(), DataValue::from(i))]),
+                ScriptMutability::Immutable,
+            )
+        })
+        .collect_vec();
+    for (i, fut) in futures.into_iter().enumerate() {
+        let res = block_on(fut).unwrap();
+        assert_eq!(res.rows, vec![vec![DataValue::from(i as i64 * 2)]]);
+    }
+
+    // cancelling kills the script
+    let fut = db.run_script_async(
+        "r[x] := x = 0
+         r[y] := r[x], y = x + 1, y < 1000000000
+         ?[count(x)] := r[x]",
+        Default::default(),
+        ScriptMutability::Immutable,
+    );
+    while fut.poison().progress() == 0 {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    fut.cancel();
+    assert!(block_on(fut).is_err());
+}
+
+struct MemUnderTest;
+
+impl crate::conformance::StorageUnderTest for MemUnderTest {
+    type Storage = crate::MemStorage;
+
+    fn empty_storage(&self) -> miette::Result<Self::Storage> {
+        Ok(Default::default())
+    }
+}
+
+mod mem_conformance {
+    crate::storage_conformance_tests!(super::MemUnderTest);
+}
+
+#[cfg(feature = "storage-sqlite")]
+struct SqliteUnderTest;
+
+#[cfg(feature = "storage-sqlite")]
+impl crate::conformance::StorageUnderTest for SqliteUnderTest {
+    type Storage = crate::SqliteStorage;
+
+    fn empty_storage(&self) -> miette::Result<Self::Storage> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "cozo-conformance-test-{}-{}.db",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+        Ok(crate::new_cozo_sqlite(path)?.db.clone())
+    }
+}
+
+#[cfg(feature = "storage-sqlite")]
+mod sqlite_conformance {
+    crate::storage_conformance_tests!(super::SqliteUnderTest);
+}
+
+#[test]
+fn materialized_views() {
+    let db = DbInstance::default();
+    db.run_default(":create sales {id: Int => day: String, amount: Int}")
+        .unwrap();
+    db.run_default(
+        "?[id, day, amount] <- [[1, 'mon', 10], [2, 'mon', 5], [3, 'tue', 7]]
+         :put sales {id => day, amount}",
+    )
+    .unwrap();
+    db.run_default("::create materialized daily { ?[day, sum(amount)] := *sales{day, amount} }")
+        .unwrap();
+    db.run_default("::create materialized busy { ?[day] := *daily{day, amount}, amount > 8 }")
+        .unwrap();
+    let daily = || {
+        db.run_default("?[day, amount] := *daily{day, amount}")
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    let busy = || db.run_default("?[day] := *busy{day}").unwrap().into_json()["rows"].clone();
+    assert_eq!(daily(), json!([["mon", 15.0], ["tue", 7.0]]));
+    assert_eq!(busy(), json!([["mon"]]));
+
+    // views follow the writes into their relations, and the views they read
+    db.run_default("?[id, day, amount] <- [[4, 'tue', 3]] :put sales {id => day, amount}")
+        .unwrap();
+    assert_eq!(daily(), json!([["mon", 15.0], ["tue", 10.0]]));
+    assert_eq!(busy(), json!([["mon"], ["tue"]]));
+    db.run_default("?[id] <- [[1], [2]] :rm sales {id}")
+        .unwrap();
+    assert_eq!(daily(), json!([["tue", 10.0]]));
+    assert_eq!(busy(), json!([["tue"]]));
+
+    // views are only written by their queries
+    assert!(db
+        .run_default("?[day, amount] <- [['wed', 1]] :put daily {day => amount}")
+        .is_err());
+    assert!(db.run_default("::remove sales").is_err());
+    assert!(db.run_default("::rename sales -> receipts").is_err());
+
+    // imported rows are taken into account when the view is refreshed
+    db.import_relations_str_with_err(
+        r#"{"sales": {"headers": ["id", "day", "amount"], "rows": [[5, "wed", 20]]}}"#,
+    )
+    .unwrap();
+    assert_eq!(daily(), json!([["tue", 10.0]]));
+    db.run_default("::refresh materialized daily").unwrap();
+    assert_eq!(daily(), json!([["tue", 10.0], ["wed", 20.0]]));
+    assert_eq!(busy(), json!([["tue"], ["wed"]]));
+
+    db.run_default("::remove busy, daily, sales").unwrap();
+}
+
+#[test]
+fn materialized_views_follow_changed_rows() {
+    let db = DbInstance::default();
+    db.run_default(":create sales {id: Int => day: String, shop: String, amount: Int}")
+        .unwrap();
+    db.run_default(":create shops {shop: String => city: String}")
+        .unwrap();
+    db.run_default(
+        "?[id, day, shop, amount] <- [[1, 'mon', 'a', 10], [2, 'mon', 'b', 5], [3, 'tue', 'a', 7]]
+         :put sales {id => day, shop, amount}",
+    )
+    .unwrap();
+    db.run_default("?[shop, city] <- [['a', 'paris'], ['b', 'rome']] :put shops {shop => city}")
+        .unwrap();
+    // computed again for the keys of the rows written only
+    db.run_default(
+        "::create materialized by_city {
+            ?[city, day, sum(amount)] := *sales{day, shop, amount}, *shops{shop, city}
+        }",
+    )
+    .unwrap();
+    // evaluated again as a whole, as its rows depend on all the others
+    db.run_default(
+        "::create materialized top { ?[day, amount] := *sales{day, amount} :order -amount :limit 1 }",
+    )
+    .unwrap();
+    let by_city = || {
+        db.run_default("?[city, day, amount] := *by_city{city, day, amount}")
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    let top = || {
+        db.run_default("?[day, amount] := *top{day, amount}")
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(
+        by_city(),
+        json!([
+            ["paris", "mon", 10.0],
+            ["paris", "tue", 7.0],
+            ["rome", "mon", 5.0]
+        ])
+    );
+    assert_eq!(top(), json!([["mon", 10]]));
+
+    // a put moving a row to another key leaves the key it was at
+    db.run_default(
+        "?[id, day, shop, amount] <- [[3, 'wed', 'a', 7]] :put sales {id => day, shop, amount}",
+    )
+    .unwrap();
+    assert_eq!(
+        by_city(),
+        json!([
+            ["paris", "mon", 10.0],
+            ["paris", "wed", 7.0],
+            ["rome", "mon", 5.0]
+        ])
+    );
+    db.run_default("?[id, amount] <- [[1, 4]] :update sales {id => amount}")
+        .unwrap();
+    assert_eq!(
+        by_city(),
+        json!([
+            ["paris", "mon", 4.0],
+            ["paris", "wed", 7.0],
+            ["rome", "mon", 5.0]
+        ])
+    );
+    assert_eq!(top(), json!([["wed", 7]]));
+
+    // writes into the other relation read are followed as well
+    db.run_default("?[shop, city] <- [['b', 'paris']] :put shops {shop => city}")
+        .unwrap();
+    assert_eq!(
+        by_city(),
+        json!([["paris", "mon", 9.0], ["paris", "wed", 7.0]])
+    );
+    db.run_default("?[id] <- [[3]] :rm sales {id}").unwrap();
+    assert_eq!(by_city(), json!([["paris", "mon", 9.0]]));
+    assert_eq!(top(), json!([["mon", 5]]));
+
+    db.run_default("::remove top, by_city, sales, shops")
+        .unwrap();
+}
+
+#[test]
+fn materialized_view_aggregate_first() {
+    let db = DbInstance::default();
+    db.run_default(":create sales {id: Int => day: String, amount: Int}")
+        .unwrap();
+    db.run_default(
+        "?[id, day, amount] <- [[1, 'mon', 10], [2, 'mon', 5], [3, 'tue', 7]]
+         :put sales {id => day, amount}",
+    )
+    .unwrap();
+    // the columns of the view are the keys first, whatever the order of the head
+    db.run_default(
+        "::create materialized daily { ?[sum(amount), day, count(id)] := *sales{id, day, amount} }",
+    )
+    .unwrap();
+    let daily = || {
+        db.run_default("?[day, total, n] := *daily{day, amount: total, id: n}")
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(daily(), json!([["mon", 15.0, 2], ["tue", 7.0, 1]]));
+
+    db.run_default("?[id, day, amount] <- [[3, 'mon', 1]] :put sales {id => day, amount}")
+        .unwrap();
+    assert_eq!(daily(), json!([["mon", 16.0, 3]]));
+    db.run_default("?[id] <- [[1]] :rm sales {id}").unwrap();
+    assert_eq!(daily(), json!([["mon", 6.0, 2]]));
+    db.run_default("::refresh materialized daily").unwrap();
+    assert_eq!(daily(), json!([["mon", 6.0, 2]]));
+
+    db.run_default("::remove daily, sales").unwrap();
+}
@@ -6,18 +6,27 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::atomic::{AtomicU32, AtomicU64};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use miette::{bail, Result};
-use crate::data::program::ReturnMutation;
+use smartstring::{LazyCompact, SmartString};
+use crate::data::program::{InputProgram, ReturnMutation};
 
 use crate::data::tuple::TupleT;
-use crate::data::value::DataValue;
+use crate::data::value::{DataValue, ValidityTs};
 use crate::fts::TokenizerCache;
 use crate::{CallbackOp, NamedRows};
 use crate::runtime::callback::CallbackCollector;
+use crate::runtime::degraded::DegradedIndices;
+use crate::runtime::encryption::KeyRing;
+use crate::runtime::fixed_rule_cache::PendingFixedRuleOutputs;
+use crate::runtime::fixpoint_cache::FixpointCache;
+use crate::runtime::pinned::{PinnedRelations, PinnedRows};
 use crate::runtime::relation::RelationId;
+use crate::runtime::rerank::RerankerRegistry;
+use crate::runtime::usage::UsageStats;
 use crate::storage::temp::TempTx;
 use crate::storage::StoreTx;
 
@@ -27,6 +36,40 @@ pub struct SessionTx<'a> {
     pub(crate) relation_store_id: Arc<AtomicU64>,
     pub(crate) temp_store_id: AtomicU32,
     pub(crate) tokenizers: Arc<TokenizerCache>,
+    pub(crate) rerankers: Arc<RerankerRegistry>,
+    /// The keys of encrypted columns available to this database
+    pub(crate) keys: Arc<KeyRing>,
+    /// Whether masked columns are read unmasked
+    pub(crate) unmask: bool,
+    /// Whether evaluation that depends on the order tuples are found in follows the order
+    /// of the tuples instead, see [crate::Db::set_deterministic]
+    pub(crate) deterministic: bool,
+    pub(crate) fixpoint_cache: Arc<FixpointCache>,
+    /// The epoch of the fixpoint cache matching the snapshot of this transaction, if known
+    pub(crate) fixpoint_epoch: Option<u64>,
+    /// Stored relations written so far, and whether rows were only ever added to them
+    pub(crate) relation_writes: BTreeMap<SmartString<LazyCompact>, bool>,
+    /// The number of times queries have started writing into relations, stored or temp
+    pub(crate) writes_started: u64,
+    /// The value of `writes_started` at the last write into each stored relation, telling
+    /// whether the relations read by materialized views were written while maintaining them
+    pub(crate) last_writes: BTreeMap<SmartString<LazyCompact>, u64>,
+    /// The time the transaction started at by the system clock, stamped in the transaction
+    /// time columns of the rows written. Never taken from a fixed clock, so that these record
+    /// when rows were really written.
+    pub(crate) tx_time: ValidityTs,
+    pub(crate) pinned: Arc<PinnedRelations>,
+    /// The rows of pinned relations read so far, `None` for those read from the storage
+    pub(crate) pinned_views: Mutex<BTreeMap<SmartString<LazyCompact>, Option<Arc<PinnedRows>>>>,
+    pub(crate) pending_fixed_rule_outputs: PendingFixedRuleOutputs,
+    /// Trigger scripts parsed so far, by their source and the time they were parsed at
+    pub(crate) trigger_programs: BTreeMap<(String, ValidityTs), InputProgram>,
+    pub(crate) usage: Arc<UsageStats>,
+    /// Stored relations and indices read by the query being compiled, for logging
+    pub(crate) relations_read: BTreeSet<SmartString<LazyCompact>>,
+    pub(crate) degraded: Arc<DegradedIndices>,
+    /// Returned with the results, see [NamedRows::warnings]
+    pub(crate) warnings: Vec<String>,
 }
 
 pub const CURRENT_STORAGE_VERSION: [u8; 1] = [0x00];
@@ -94,12 +137,15 @@ impl<'a> SessionTx<'a> {
         Ok(returned_rows)
     }
 
-    pub(crate) fn init_storage(&mut self) -> Result<RelationId> {
+    /// Check the storage version and return the last relation id, setting up the storage
+    /// first if it is empty, unless `read_only` is set.
+    pub(crate) fn init_storage(&mut self, read_only: bool) -> Result<RelationId> {
         let tuple = vec![DataValue::Null];
         let t_encoded = tuple.encode_as_key(RelationId::SYSTEM);
         let found = self.store_tx.get(&t_encoded, false)?;
         let storage_version_key = storage_version_key();
         let ret = match found {
+            None if read_only => bail!("Storage is empty and cannot be set up in read-only mode"),
             None => {
                 self.store_tx
                     .put(&storage_version_key, &CURRENT_STORAGE_VERSION)?;
@@ -130,7 +176,42 @@ impl<'a> SessionTx<'a> {
     }
 
     pub fn commit_tx(&mut self) -> Result<()> {
-        self.store_tx.commit()?;
+        self.bump_relation_versions()?;
+        let store_tx = &mut self.store_tx;
+        self.fixpoint_cache
+            .commit(&self.relation_writes, || store_tx.commit())?;
+        self.usage.record_writes(self.relation_writes.keys());
+        self.relation_writes.clear();
         Ok(())
     }
+    /// Record that a query reads the stored relation or index `name`
+    pub(crate) fn record_read(&mut self, name: &str) {
+        if name.starts_with('_') {
+            return;
+        }
+        self.usage.record_read(name);
+        self.relations_read.insert(SmartString::from(name));
+    }
+    /// Record a write into a stored relation, for the invalidation of cached fixpoints
+    /// and the maintenance of materialized views
+    pub(crate) fn record_relation_write(&mut self, name: &str, only_added: bool) {
+        if name.starts_with('_') {
+            return;
+        }
+        self.last_writes
+            .insert(SmartString::from(name), self.writes_started);
+        *self
+            .relation_writes
+            .entry(SmartString::from(name))
+            .or_insert(true) &= only_added;
+    }
+    /// The epoch at which cached fixpoints may be used and stored by this transaction:
+    /// a transaction that has written into stored relations sees data not yet committed
+    pub(crate) fn fixpoint_epoch(&self) -> Option<u64> {
+        if self.relation_writes.is_empty() {
+            self.fixpoint_epoch
+        } else {
+            None
+        }
+    }
 }
@@ -0,0 +1,269 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Report, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
+use crate::data::value::DataValue;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' has no trigger named '{1}'")]
+#[diagnostic(code(tx::trigger_not_found))]
+#[diagnostic(help(
+    "Triggers given without a name are named after their kind and position, as in 'put_0'"
+))]
+struct TriggerNotFound(String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' would have more than one trigger named '{1}'")]
+#[diagnostic(code(tx::duplicate_trigger_name))]
+struct DuplicateTriggerName(String, String);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum TriggerKind {
+    Put,
+    Rm,
+    Replace,
+}
+
+impl Display for TriggerKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriggerKind::Put => f.write_str("put"),
+            TriggerKind::Rm => f.write_str("rm"),
+            TriggerKind::Replace => f.write_str("replace"),
+        }
+    }
+}
+
+/// What becomes of a mutation when one of its triggers fails
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize,
+)]
+pub(crate) enum TriggerErrorPolicy {
+    /// The mutation fails, and the transaction with it
+    #[default]
+    Abort,
+    /// The error is logged and the mutation goes on. Rows the trigger wrote before failing are kept.
+    Log,
+}
+
+impl Display for TriggerErrorPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriggerErrorPolicy::Abort => f.write_str("abort"),
+            TriggerErrorPolicy::Log => f.write_str("log"),
+        }
+    }
+}
+
+/// How a trigger runs, besides its script
+#[derive(Clone, Debug, Default, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct TriggerOptions {
+    /// Triggers given without a name are named after their kind and position, as in `put_0`
+    pub(crate) name: Option<SmartString<LazyCompact>>,
+    /// Triggers of a kind run from the lowest order up, and in the order given for equal orders
+    pub(crate) order: i64,
+    pub(crate) disabled: bool,
+    pub(crate) on_error: TriggerErrorPolicy,
+}
+
+/// A trigger as given to `::set_triggers`
+#[derive(Debug)]
+pub(crate) struct TriggerDef {
+    pub(crate) kind: TriggerKind,
+    pub(crate) script: String,
+    pub(crate) options: TriggerOptions,
+}
+
+/// A trigger of a stored relation, with its name and options
+pub(crate) struct Trigger<'a> {
+    pub(crate) kind: TriggerKind,
+    pub(crate) idx: usize,
+    pub(crate) name: SmartString<LazyCompact>,
+    pub(crate) script: &'a str,
+    pub(crate) options: TriggerOptions,
+}
+
+impl Trigger<'_> {
+    /// Let the mutation go on after the trigger failed with `err`, if its policy says so
+    pub(crate) fn recover(&self, relation: &str, err: Report) -> Result<()> {
+        match self.options.on_error {
+            TriggerErrorPolicy::Abort => Err(err),
+            TriggerErrorPolicy::Log => {
+                log::error!(
+                    "{} trigger '{}' of '{}' failed: {:?}",
+                    self.kind,
+                    self.name,
+                    relation,
+                    err
+                );
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn as_row(&self) -> Vec<DataValue> {
+        vec![
+            DataValue::from(self.kind.to_string()),
+            DataValue::from(self.idx as i64),
+            DataValue::from(self.script),
+            DataValue::from(self.name.as_str()),
+            DataValue::from(self.options.order),
+            DataValue::from(!self.options.disabled),
+            DataValue::from(self.options.on_error.to_string()),
+        ]
+    }
+}
+
+/// The columns of [Trigger::as_row]
+pub(crate) fn trigger_columns() -> Vec<String> {
+    [
+        "type", "idx", "trigger", "name", "order", "enabled", "on_error",
+    ]
+    .map(|s| s.to_string())
+    .to_vec()
+}
+
+impl RelationHandle {
+    fn trigger_parts(&self, kind: TriggerKind) -> (&[String], &[TriggerOptions]) {
+        let (scripts, options) = match kind {
+            TriggerKind::Put => (&self.put_triggers, &self.put_trigger_options),
+            TriggerKind::Rm => (&self.rm_triggers, &self.rm_trigger_options),
+            TriggerKind::Replace => (&self.replace_triggers, &self.replace_trigger_options),
+        };
+        (scripts, options)
+    }
+
+    /// The triggers of a kind, in the order they run, disabled ones included
+    pub(crate) fn triggers(&self, kind: TriggerKind) -> Vec<Trigger<'_>> {
+        let (scripts, options) = self.trigger_parts(kind);
+        scripts
+            .iter()
+            .enumerate()
+            .map(|(idx, script)| {
+                // relations stored before triggers had options hold none
+                let options = options.get(idx).cloned().unwrap_or_default();
+                let name = match &options.name {
+                    Some(name) => name.clone(),
+                    None => SmartString::from(format!("{kind}_{idx}")),
+                };
+                Trigger {
+                    kind,
+                    idx,
+                    name,
+                    script,
+                    options,
+                }
+            })
+            .collect()
+    }
+
+    /// The triggers of a kind which are enabled, in the order they run
+    pub(crate) fn enabled_triggers(&self, kind: TriggerKind) -> Vec<Trigger<'_>> {
+        let mut triggers = self.triggers(kind);
+        triggers.retain(|t| !t.options.disabled);
+        triggers
+    }
+
+    pub(crate) fn all_triggers(&self) -> Vec<Trigger<'_>> {
+        [TriggerKind::Put, TriggerKind::Rm, TriggerKind::Replace]
+            .into_iter()
+            .flat_map(|kind| self.triggers(kind))
+            .collect()
+    }
+
+    /// Replace all triggers by `defs`, putting each kind in the order they run
+    pub(crate) fn set_triggers(&mut self, defs: &[TriggerDef]) -> Result<()> {
+        let mut defs = defs.iter().collect_vec();
+        defs.sort_by_key(|def| def.options.order);
+        self.put_triggers.clear();
+        self.put_trigger_options.clear();
+        self.rm_triggers.clear();
+        self.rm_trigger_options.clear();
+        self.replace_triggers.clear();
+        self.replace_trigger_options.clear();
+        for def in defs {
+            let (scripts, options) = match def.kind {
+                TriggerKind::Put => (&mut self.put_triggers, &mut self.put_trigger_options),
+                TriggerKind::Rm => (&mut self.rm_triggers, &mut self.rm_trigger_options),
+                TriggerKind::Replace => (
+                    &mut self.replace_triggers,
+                    &mut self.replace_trigger_options,
+                ),
+            };
+            scripts.push(def.script.clone());
+            options.push(def.options.clone());
+        }
+        let mut seen = BTreeSet::new();
+        for trigger in self.all_triggers() {
+            if !seen.insert(trigger.name.clone()) {
+                bail!(DuplicateTriggerName(
+                    self.name.to_string(),
+                    trigger.name.to_string()
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Enable or disable the trigger of a stored relation with the given name
+    pub(crate) fn set_trigger_enabled(
+        &mut self,
+        rel: &Symbol,
+        name: &str,
+        enabled: bool,
+    ) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        if meta.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "set triggers".to_string(),
+                meta.access_level
+            ))
+        }
+        let found = meta
+            .all_triggers()
+            .into_iter()
+            .find(|t| t.name.as_str() == name)
+            .map(|t| (t.kind, t.idx));
+        let (kind, idx) = match found {
+            Some(found) => found,
+            None => bail!(TriggerNotFound(meta.name.to_string(), name.to_string())),
+        };
+        let (scripts, options) = match kind {
+            TriggerKind::Put => (&meta.put_triggers, &mut meta.put_trigger_options),
+            TriggerKind::Rm => (&meta.rm_triggers, &mut meta.rm_trigger_options),
+            TriggerKind::Replace => (&meta.replace_triggers, &mut meta.replace_trigger_options),
+        };
+        options.resize(scripts.len(), Default::default());
+        options[idx].disabled = !enabled;
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
+}
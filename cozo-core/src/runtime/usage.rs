@@ -0,0 +1,113 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::runtime::relation::{RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+/// How often a stored relation or index was used, and when last
+#[derive(Clone, Copy, Debug, Default)]
+struct Usage {
+    reads: u64,
+    writes: u64,
+    last_read: Option<f64>,
+    last_written: Option<f64>,
+}
+
+/// The usage of stored relations and indices since the database was opened, shown by
+/// `::usage`. The counts are kept in memory only, and are not shared between processes.
+#[derive(Default)]
+pub(crate) struct UsageStats(Mutex<BTreeMap<SmartString<LazyCompact>, Usage>>);
+
+impl UsageStats {
+    /// Record that a query reads the stored relation or index `name`
+    pub(crate) fn record_read(&self, name: &str) {
+        if name.starts_with('_') {
+            return;
+        }
+        let now = seconds_since_the_epoch().ok();
+        let mut stats = self.0.lock().unwrap();
+        let usage = stats.entry(SmartString::from(name)).or_default();
+        usage.reads += 1;
+        usage.last_read = now;
+    }
+    /// Record that a committed transaction wrote into the stored relations
+    pub(crate) fn record_writes<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a SmartString<LazyCompact>>,
+    ) {
+        let now = seconds_since_the_epoch().ok();
+        let mut stats = self.0.lock().unwrap();
+        for name in names {
+            let usage = stats.entry(name.clone()).or_default();
+            usage.writes += 1;
+            usage.last_written = now;
+        }
+    }
+    fn get(&self, name: &str) -> Usage {
+        self.0
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// The usage of all stored relations and indices, including those never used.
+    /// Indices are written whenever their relation is, so they show the writes of
+    /// their relation.
+    pub(crate) fn list_usage(&self) -> Result<NamedRows> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut rows = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            let meta = RelationHandle::decode(&v_slice)?;
+            let read = self.usage.get(&meta.name);
+            let (kind, written) = match meta.name.split_once(':') {
+                None => ("relation", read),
+                Some((base, _)) => ("index", self.usage.get(base)),
+            };
+            let time = |t: Option<f64>| t.map(DataValue::from).unwrap_or(DataValue::Null);
+            rows.push(vec![
+                DataValue::Str(meta.name.clone()),
+                DataValue::from(kind),
+                DataValue::from(read.reads as i64),
+                DataValue::from(written.writes as i64),
+                time(read.last_read),
+                time(written.last_written),
+            ]);
+        }
+        Ok(NamedRows::new(
+            vec![
+                "name".to_string(),
+                "kind".to_string(),
+                "reads".to_string(),
+                "writes".to_string(),
+                "last_read".to_string(),
+                "last_written".to_string(),
+            ],
+            rows,
+        ))
+    }
+}
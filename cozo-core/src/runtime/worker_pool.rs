@@ -0,0 +1,179 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use lazy_static::lazy_static;
+use miette::{miette, Result};
+
+use crate::runtime::db::Poison;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// The threads the calls of the async methods of [crate::DbInstance] are run on, shared
+/// by all databases. Threads are started while tasks are waiting, up to the number of CPUs.
+struct WorkerPool {
+    sender: Sender<Task>,
+    receiver: Receiver<Task>,
+    max_workers: usize,
+    workers: Mutex<WorkerCounts>,
+}
+
+#[derive(Default)]
+struct WorkerCounts {
+    started: usize,
+    idle: usize,
+}
+
+lazy_static! {
+    static ref WORKER_POOL: WorkerPool = {
+        let (sender, receiver) = unbounded();
+        WorkerPool {
+            sender,
+            receiver,
+            max_workers: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(4),
+            workers: Default::default(),
+        }
+    };
+}
+
+impl WorkerPool {
+    fn submit(&'static self, task: Task) {
+        // the receiving end is never dropped
+        self.sender.send(task).unwrap();
+        let mut workers = self.workers.lock().unwrap();
+        if workers.idle == 0 && workers.started < self.max_workers {
+            workers.started += 1;
+            std::thread::spawn(move || self.work());
+        }
+    }
+    fn work(&'static self) {
+        loop {
+            self.workers.lock().unwrap().idle += 1;
+            let task = self.receiver.recv().unwrap();
+            self.workers.lock().unwrap().idle -= 1;
+            // a panicking task fails its future when it is dropped, see `Completer`
+            let _ = catch_unwind(AssertUnwindSafe(task));
+        }
+    }
+}
+
+struct Shared<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// Sets the result of a [DbFuture], or an error if it is dropped without one
+struct Completer<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    done: bool,
+}
+
+impl<T> Completer<T> {
+    fn complete(&mut self, res: Result<T>) {
+        self.done = true;
+        let mut shared = self.shared.lock().unwrap();
+        shared.result = Some(res);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.complete(Err(miette!("the call panicked before it completed")));
+        }
+    }
+}
+
+/// The outcome of a call of an async method of [crate::DbInstance], such as
+/// [crate::DbInstance::run_script_async], made on a pool of worker threads.
+///
+/// The future works with any async runtime. Dropping it before it completes, or calling
+/// [DbFuture::cancel], kills the scripts it runs as `::kill` does: they fail at the next
+/// check of their [Poison]. Other calls, such as imports, run to completion regardless.
+pub struct DbFuture<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    poison: Poison,
+    finished: bool,
+}
+
+impl<T> DbFuture<T> {
+    /// Kill the call, which then completes with an error, unless it has completed already
+    pub fn cancel(&self) {
+        self.poison.kill();
+    }
+    /// The poison of the call, to follow its progress
+    pub fn poison(&self) -> &Poison {
+        &self.poison
+    }
+}
+
+impl<T> Future for DbFuture<T> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let res = {
+            let mut shared = self.shared.lock().unwrap();
+            match shared.result.take() {
+                Some(res) => res,
+                None => {
+                    shared.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        };
+        self.finished = true;
+        Poll::Ready(res)
+    }
+}
+
+impl<T> Drop for DbFuture<T> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.poison.kill();
+        }
+    }
+}
+
+/// Run `f` on the worker pool. It is not run at all if the poison is killed before it starts.
+pub(crate) fn spawn_on_pool<T: Send + 'static>(
+    poison: Poison,
+    f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> DbFuture<T> {
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+    let mut completer = Completer {
+        shared: shared.clone(),
+        done: false,
+    };
+    let pill = poison.clone();
+    WORKER_POOL.submit(Box::new(move || {
+        let res = match pill.check() {
+            Ok(()) => f(),
+            Err(err) => Err(err),
+        };
+        completer.complete(res);
+    }));
+    DbFuture {
+        shared,
+        poison,
+        finished: false,
+    }
+}
@@ -0,0 +1,554 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Serving an embedded database over HTTP, with the same protocol as the standalone server.
+//! The endpoints the standalone server shares are those of [protocol_routes].
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::routing::{get, post, put};
+use axum::{middleware, Extension, Json, Router};
+use itertools::Itertools;
+use miette::{IntoDiagnostic, Result};
+use serde_json::json;
+use tokio::sync::oneshot;
+use tokio::task::spawn_blocking;
+
+use crate::{
+    format_error_as_json, DataValue, DbInstance, MultiTransaction, NamedRows, ScriptMutability,
+};
+
+/// Where to serve the database
+#[derive(Debug, Clone)]
+pub enum ServeAddr {
+    /// A TCP address, use port `0` to pick a free port
+    Tcp(SocketAddr),
+    /// A unix domain socket, created at the path. An existing file at the path is replaced.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Options for [DbInstance::serve]
+#[derive(Debug, Clone, Default)]
+pub struct ServeOptions {
+    /// If set, clients must send this token in the `x-cozo-auth` header
+    pub auth_token: Option<String>,
+    /// If set, only immutable scripts are accepted, and importing data is not allowed
+    pub read_only: bool,
+}
+
+/// A running server, see [DbInstance::serve].
+/// The server stops when the handle is dropped or [ServerHandle::shutdown] is called.
+pub struct ServerHandle {
+    local_addr: Option<SocketAddr>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// The address actually bound, for TCP servers
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+    /// Stop accepting connections, and wait for in-flight requests to finish
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+    fn stop(&mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.stop()
+    }
+}
+
+/// What the client of a request is allowed to do. It is set as a request extension by the
+/// authentication in front of [protocol_routes].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ServeRole {
+    /// Immutable scripts only
+    Reader,
+    /// Mutations, but no destructive system operations
+    Writer,
+    /// Everything, including `::remove`, `::compact`, `:replace`, backups and the system
+    /// operations reading files on the server, such as `::import infer` and `::diff`
+    Admin,
+}
+
+impl ServeRole {
+    /// The mutability of the scripts run for the role
+    pub fn mutability(self) -> ScriptMutability {
+        match self {
+            ServeRole::Reader => ScriptMutability::Immutable,
+            ServeRole::Writer | ServeRole::Admin => ScriptMutability::Mutable,
+        }
+    }
+    /// Whether columns masked with `::mask` are shown unmasked
+    pub fn unmask(self) -> bool {
+        self == ServeRole::Admin
+    }
+}
+
+/// The session the queries of a request are logged in. It is set as a request extension
+/// by the authentication in front of [protocol_routes], queries are logged in no session
+/// without it.
+#[derive(Clone)]
+pub struct ServeSession(pub Arc<str>);
+
+/// Header marking an import as a batch that must be imported at most once
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+const ADMIN_REQUIRED: &str = "this operation requires an admin token";
+
+/// The state of the endpoints of [protocol_routes]: the database and its open transactions
+#[derive(Clone)]
+pub struct ServeState {
+    db: DbInstance,
+    tx_counter: Arc<AtomicU32>,
+    txs: Arc<Mutex<BTreeMap<u32, Arc<MultiTransaction>>>>,
+}
+
+impl ServeState {
+    /// The state of the endpoints serving `db`
+    pub fn new(db: DbInstance) -> Self {
+        Self {
+            db,
+            tx_counter: Default::default(),
+            txs: Default::default(),
+        }
+    }
+    fn session_db(&self, session: Option<Extension<ServeSession>>) -> DbInstance {
+        match session {
+            None => self.db.clone(),
+            Some(Extension(session)) => self.db.with_session(&session.0),
+        }
+    }
+}
+
+/// The endpoints of the protocol of the standalone server that [DbInstance::serve] has as
+/// well: `/text-query`, `/batch`, `/export/{relations}`, `/import` and `/transact`.
+///
+/// Every request must carry a [ServeRole] extension, and may carry a [ServeSession] one,
+/// set by the authentication layered over the routes.
+pub fn protocol_routes(state: ServeState) -> Router {
+    Router::new()
+        .route("/text-query", post(text_query))
+        .route("/batch", post(batch_query))
+        .route("/export/:relations", get(export_relations))
+        .route("/import", put(import_relations))
+        .route("/transact", post(start_transact))
+        .route("/transact/:id", post(transact_query).put(finish_query))
+        .with_state(state)
+}
+
+impl DbInstance {
+    /// Serve this database with the HTTP protocol of the standalone server, so that
+    /// clients and debugging tools can attach to it while the application keeps using it.
+    ///
+    /// The endpoints of [protocol_routes] are available. Clients are admins, or readers if
+    /// [ServeOptions::read_only] is set. Requests are served on a dedicated thread until the
+    /// returned handle is dropped.
+    pub fn serve(&self, addr: ServeAddr, options: ServeOptions) -> Result<ServerHandle> {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("cozo-serve")
+            .build()
+            .into_diagnostic()?;
+        let role = if options.read_only {
+            ServeRole::Reader
+        } else {
+            ServeRole::Admin
+        };
+        let token = options.auth_token.map(Arc::new);
+        let app = protocol_routes(ServeState::new(self.clone())).layer(middleware::from_fn(
+            move |mut req: Request, next: Next| {
+                let token = token.clone();
+                async move {
+                    if let Some(token) = token {
+                        match req.headers().get("x-cozo-auth") {
+                            Some(v) if v.as_bytes() == token.as_bytes() => {}
+                            _ => return StatusCode::UNAUTHORIZED.into_response(),
+                        }
+                    }
+                    req.extensions_mut().insert(role);
+                    next.run(req).await
+                }
+            },
+        ));
+        let (shutdown_send, shutdown_recv) = oneshot::channel::<()>();
+        let shutdown = async {
+            let _ = shutdown_recv.await;
+        };
+
+        let (local_addr, thread) = match addr {
+            ServeAddr::Tcp(addr) => {
+                let listener = rt
+                    .block_on(tokio::net::TcpListener::bind(addr))
+                    .into_diagnostic()?;
+                let local_addr = listener.local_addr().into_diagnostic()?;
+                let thread = std::thread::spawn(move || {
+                    rt.block_on(async move {
+                        if let Err(err) = axum::serve(listener, app)
+                            .with_graceful_shutdown(shutdown)
+                            .await
+                        {
+                            log::error!("serving database failed: {err}");
+                        }
+                    })
+                });
+                (Some(local_addr), thread)
+            }
+            #[cfg(unix)]
+            ServeAddr::Unix(path) => {
+                let _ = std::fs::remove_file(&path);
+                let listener = {
+                    let _guard = rt.enter();
+                    tokio::net::UnixListener::bind(&path).into_diagnostic()?
+                };
+                let thread = std::thread::spawn(move || {
+                    rt.block_on(serve_unix(listener, app, shutdown));
+                    let _ = std::fs::remove_file(&path);
+                });
+                (None, thread)
+            }
+        };
+        Ok(ServerHandle {
+            local_addr,
+            shutdown: Some(shutdown_send),
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(
+    listener: tokio::net::UnixListener,
+    app: Router,
+    shutdown: impl std::future::Future<Output = ()>,
+) {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use tower::Service;
+
+    tokio::pin!(shutdown);
+    loop {
+        let stream = tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    log::error!("accepting connection failed: {err}");
+                    continue;
+                }
+            }
+        };
+        let app = app.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |req: Request<hyper::body::Incoming>| {
+                app.clone().call(req)
+            });
+            if let Err(err) = Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                log::debug!("connection closed with error: {err}");
+            }
+        });
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+pub(crate) struct QueryPayload {
+    script: String,
+    #[serde(default)]
+    params: BTreeMap<String, serde_json::Value>,
+    immutable: Option<bool>,
+}
+
+fn convert_params(params: BTreeMap<String, serde_json::Value>) -> BTreeMap<String, DataValue> {
+    params
+        .into_iter()
+        .map(|(k, v)| (k, DataValue::from(v)))
+        .collect()
+}
+
+type Reply = (StatusCode, Json<serde_json::Value>);
+
+fn wrap_json(json: serde_json::Value) -> Reply {
+    let code = if let Some(serde_json::Value::Bool(true)) = json.get("ok") {
+        StatusCode::OK
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    (code, json.into())
+}
+
+fn error_reply(code: StatusCode, message: impl ToString) -> Reply {
+    (
+        code,
+        json!({"ok": false, "message": message.to_string()}).into(),
+    )
+}
+
+/// Run the script of a query as the role allows, returning `None` if it requires admin
+pub(crate) fn run_query_payload(
+    db: &DbInstance,
+    role: ServeRole,
+    payload: QueryPayload,
+) -> Option<serde_json::Value> {
+    let params = convert_params(payload.params);
+    let immutable = match role.mutability() {
+        ScriptMutability::Mutable => payload.immutable.unwrap_or(false),
+        ScriptMutability::Immutable => true,
+    };
+    if role < ServeRole::Admin
+        && db
+            .script_requires_admin(&payload.script, &params)
+            .unwrap_or(false)
+    {
+        return None;
+    }
+    Some(db.with_unmask(role.unmask()).run_script_fold_err(
+        &payload.script,
+        params,
+        if immutable {
+            ScriptMutability::Immutable
+        } else {
+            ScriptMutability::Mutable
+        },
+    ))
+}
+
+async fn text_query(
+    Extension(role): Extension<ServeRole>,
+    session: Option<Extension<ServeSession>>,
+    State(st): State<ServeState>,
+    Json(payload): Json<QueryPayload>,
+) -> Reply {
+    let db = st.session_db(session);
+    match spawn_blocking(move || run_query_payload(&db, role, payload)).await {
+        Ok(Some(res)) => wrap_json(res),
+        Ok(None) => error_reply(StatusCode::FORBIDDEN, ADMIN_REQUIRED),
+        Err(err) => error_reply(StatusCode::INTERNAL_SERVER_ERROR, err),
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+struct BatchPayload {
+    queries: Vec<QueryPayload>,
+}
+
+/// Run several independent queries, each in its own transaction, in the order given.
+/// A query failing does not stop the others: each result is reported as `/text-query` would.
+async fn batch_query(
+    Extension(role): Extension<ServeRole>,
+    session: Option<Extension<ServeSession>>,
+    State(st): State<ServeState>,
+    Json(payload): Json<BatchPayload>,
+) -> Reply {
+    let db = st.session_db(session);
+    let result = spawn_blocking(move || {
+        payload
+            .queries
+            .into_iter()
+            .map(|query| match run_query_payload(&db, role, query) {
+                Some(res) => res,
+                None => json!({"ok": false, "message": ADMIN_REQUIRED}),
+            })
+            .collect_vec()
+    })
+    .await;
+    match result {
+        Ok(results) => (
+            StatusCode::OK,
+            json!({"ok": true, "results": results}).into(),
+        ),
+        Err(err) => error_reply(StatusCode::INTERNAL_SERVER_ERROR, err),
+    }
+}
+
+async fn export_relations(
+    Extension(role): Extension<ServeRole>,
+    State(st): State<ServeState>,
+    Path(relations): Path<String>,
+) -> Reply {
+    let relations = relations
+        .split(',')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect_vec();
+    let result = spawn_blocking(move || {
+        st.db
+            .with_unmask(role.unmask())
+            .export_relations(relations.iter())
+    })
+    .await;
+    match result {
+        Ok(Ok(data)) => {
+            let data: serde_json::Map<_, _> =
+                data.into_iter().map(|(k, v)| (k, v.into_json())).collect();
+            (StatusCode::OK, json!({"ok": true, "data": data}).into())
+        }
+        Ok(Err(err)) => error_reply(StatusCode::BAD_REQUEST, err),
+        Err(err) => error_reply(StatusCode::INTERNAL_SERVER_ERROR, err),
+    }
+}
+
+async fn import_relations(
+    Extension(role): Extension<ServeRole>,
+    State(st): State<ServeState>,
+    headers: HeaderMap,
+    Json(payload): Json<serde_json::Value>,
+) -> Reply {
+    if role < ServeRole::Writer {
+        return error_reply(StatusCode::FORBIDDEN, "importing relations is not allowed");
+    }
+    let payload = match payload.as_object() {
+        None => return error_reply(StatusCode::BAD_REQUEST, "payload must be a JSON object"),
+        Some(obj) => {
+            let mut ret = BTreeMap::new();
+            for (k, v) in obj {
+                match NamedRows::from_json(v) {
+                    Ok(rows) => {
+                        ret.insert(k.to_string(), rows);
+                    }
+                    Err(err) => return error_reply(StatusCode::BAD_REQUEST, err),
+                }
+            }
+            ret
+        }
+    };
+    let idempotency_key = match headers.get(IDEMPOTENCY_KEY).map(|v| v.to_str()) {
+        None => None,
+        Some(Ok(key)) => Some(key.to_string()),
+        Some(Err(_)) => {
+            return error_reply(
+                StatusCode::BAD_REQUEST,
+                "idempotency key must be visible ASCII",
+            )
+        }
+    };
+    let result = spawn_blocking(move || match idempotency_key {
+        None => st.db.import_relations(payload).map(|_| json!({"ok": true})),
+        Some(key) => st
+            .db
+            .ingest_relations(&key, payload)
+            .map(|ingested| json!({"ok": true, "ingested": ingested})),
+    })
+    .await;
+    match result {
+        Ok(Ok(ret)) => (StatusCode::OK, ret.into()),
+        Ok(Err(err)) => error_reply(StatusCode::BAD_REQUEST, err),
+        Err(err) => error_reply(StatusCode::INTERNAL_SERVER_ERROR, err),
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+struct StartTransactPayload {
+    write: bool,
+}
+
+async fn start_transact(
+    Extension(role): Extension<ServeRole>,
+    session: Option<Extension<ServeSession>>,
+    State(st): State<ServeState>,
+    Query(payload): Query<StartTransactPayload>,
+) -> Reply {
+    if payload.write && role < ServeRole::Writer {
+        return error_reply(StatusCode::FORBIDDEN, "write transactions are not allowed");
+    }
+    let tx = st
+        .session_db(session)
+        .with_unmask(role.unmask())
+        .multi_transaction(payload.write);
+    let id = st.tx_counter.fetch_add(1, Ordering::SeqCst);
+    st.txs.lock().unwrap().insert(id, Arc::new(tx));
+    (StatusCode::OK, json!({"ok": true, "id": id}).into())
+}
+
+async fn transact_query(
+    Extension(role): Extension<ServeRole>,
+    State(st): State<ServeState>,
+    Path(id): Path<u32>,
+    Json(payload): Json<QueryPayload>,
+) -> Reply {
+    let tx = match st.txs.lock().unwrap().get(&id) {
+        None => return (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
+        Some(tx) => tx.clone(),
+    };
+    let src = payload.script.clone();
+    let result = spawn_blocking(move || {
+        let params = convert_params(payload.params);
+        let query = payload.script;
+        if role < ServeRole::Admin
+            && st
+                .db
+                .script_requires_admin(&query, &params)
+                .unwrap_or(false)
+        {
+            return Err(None);
+        }
+        tx.run_script(&query, params).map_err(Some)
+    })
+    .await;
+    match result {
+        Ok(Ok(res)) => (StatusCode::OK, res.into_json().into()),
+        Ok(Err(None)) => error_reply(StatusCode::FORBIDDEN, ADMIN_REQUIRED),
+        Ok(Err(Some(err))) => (
+            StatusCode::BAD_REQUEST,
+            format_error_as_json(err, Some(&src)).into(),
+        ),
+        Err(err) => error_reply(StatusCode::INTERNAL_SERVER_ERROR, err),
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+struct FinishTransactPayload {
+    abort: bool,
+}
+
+async fn finish_query(
+    State(st): State<ServeState>,
+    Path(id): Path<u32>,
+    Json(payload): Json<FinishTransactPayload>,
+) -> Reply {
+    let tx = match st.txs.lock().unwrap().remove(&id) {
+        None => return (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
+        Some(tx) => tx,
+    };
+    let res = spawn_blocking(move || {
+        if payload.abort {
+            tx.abort()
+        } else {
+            tx.commit()
+        }
+    })
+    .await;
+    match res {
+        Ok(Ok(())) => (StatusCode::OK, json!({"ok": true}).into()),
+        Ok(Err(err)) => error_reply(StatusCode::BAD_REQUEST, err),
+        Err(err) => error_reply(StatusCode::INTERNAL_SERVER_ERROR, err),
+    }
+}
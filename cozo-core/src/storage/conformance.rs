@@ -0,0 +1,405 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Checks that a storage engine has the semantics Cozo expects of implementations of
+//! [Storage] and [StoreTx], for authors of storage engines.
+//!
+//! Implement [StorageUnderTest] to make empty instances of the engine, then generate a test
+//! for every check with [storage_conformance_tests](crate::storage_conformance_tests):
+//!
+//! ```ignore
+//! struct MyEngine;
+//!
+//! impl cozo::conformance::StorageUnderTest for MyEngine {
+//!     type Storage = MyStorage;
+//!
+//!     fn empty_storage(&self) -> miette::Result<MyStorage> {
+//!         MyStorage::open_temporary()
+//!     }
+//! }
+//!
+//! cozo::storage_conformance_tests!(MyEngine);
+//! ```
+//!
+//! Each check opens one transaction at a time, so that engines serializing transactions
+//! with locks can be checked. Keys and values are encoded as those of stored relations.
+
+use std::cmp::Reverse;
+
+use itertools::Itertools;
+use miette::{ensure, Result};
+use serde::Serialize;
+
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::{DataValue, Validity, ValidityTs};
+use crate::runtime::relation::RelationId;
+use crate::storage::{Storage, StoreTx};
+
+/// A storage engine to be checked
+pub trait StorageUnderTest {
+    /// The storage engine
+    type Storage: for<'s> Storage<'s>;
+
+    /// A new instance of the storage engine, holding no data. It is made anew for every check.
+    fn empty_storage(&self) -> Result<Self::Storage>;
+
+    /// Whether [StoreTx::range_skip_scan_tuple] is implemented, for time travel.
+    /// The default is `true`.
+    fn supports_time_travel(&self) -> bool {
+        true
+    }
+}
+
+/// The relation the keys of the checks belong to
+const REL: u64 = 1000;
+
+fn key(k: i64) -> Vec<u8> {
+    vec![DataValue::from(k)].encode_as_key(RelationId::new(REL))
+}
+
+fn val(v: i64) -> Vec<u8> {
+    let mut ret = RelationId::new(REL).raw_encode().to_vec();
+    vec![DataValue::from(v)]
+        .serialize(&mut rmp_serde::Serializer::new(&mut ret))
+        .unwrap();
+    ret
+}
+
+fn row(k: i64) -> (Vec<u8>, Vec<u8>) {
+    (key(k), val(k * 10))
+}
+
+fn put_committed<S: for<'s> Storage<'s>>(
+    storage: &S,
+    keys: impl Iterator<Item = i64>,
+) -> Result<()> {
+    let mut tx = storage.transact(true)?;
+    for k in keys {
+        let (k, v) = row(k);
+        tx.put(&k, &v)?;
+    }
+    tx.commit()
+}
+
+/// The keys `k` of the rows of the relation in the range, checking their values
+fn scanned_keys<'s, T: StoreTx<'s>>(tx: &T, lower: i64, upper: i64) -> Result<Vec<i64>> {
+    let mut ret = vec![];
+    for kv in tx.range_scan(&key(lower), &key(upper)) {
+        let (k, v) = kv?;
+        let found = crate::decode_tuple_from_kv(&k, &v, None);
+        let n = found[0].get_int().unwrap();
+        ensure!(
+            v == val(n * 10),
+            "range_scan gave a wrong value for key {n}"
+        );
+        ret.push(n);
+    }
+    Ok(ret)
+}
+
+/// A write transaction sees its own puts and deletions, before and after committing
+pub fn reads_own_writes<T: StorageUnderTest>(target: &T) -> Result<()> {
+    let storage = target.empty_storage()?;
+    {
+        let mut tx = storage.transact(true)?;
+        for k in 1..=3 {
+            let (k, v) = row(k);
+            tx.put(&k, &v)?;
+        }
+        ensure!(
+            tx.get(&key(2), false)? == Some(val(20)),
+            "get does not find a key put in the transaction"
+        );
+        ensure!(
+            tx.exists(&key(2), false)?,
+            "exists does not find a key put in the transaction"
+        );
+        tx.put(&key(2), &val(200))?;
+        ensure!(
+            tx.get(&key(2), true)? == Some(val(200)),
+            "put does not overwrite the value of a key"
+        );
+        tx.del(&key(2))?;
+        ensure!(
+            tx.get(&key(2), false)?.is_none(),
+            "get finds a key deleted in the transaction"
+        );
+        ensure!(
+            !tx.exists(&key(2), false)?,
+            "exists finds a key deleted in the transaction"
+        );
+        ensure!(
+            scanned_keys(&tx, 0, 10)? == vec![1, 3],
+            "range_scan does not reflect the puts and deletions of the transaction"
+        );
+        tx.commit()?;
+    }
+    let tx = storage.transact(false)?;
+    ensure!(
+        tx.get(&key(1), false)? == Some(val(10)),
+        "a committed key is not found"
+    );
+    ensure!(
+        tx.get(&key(2), false)?.is_none(),
+        "a committed deletion is not applied"
+    );
+    ensure!(
+        scanned_keys(&tx, 0, 10)? == vec![1, 3],
+        "range_scan does not reflect the committed transaction"
+    );
+    Ok(())
+}
+
+/// The changes of a write transaction dropped without being committed are discarded
+pub fn discards_uncommitted_writes<T: StorageUnderTest>(target: &T) -> Result<()> {
+    let storage = target.empty_storage()?;
+    put_committed(&storage, 1..=2)?;
+    {
+        let mut tx = storage.transact(true)?;
+        tx.del(&key(1))?;
+        let (k, v) = row(3);
+        tx.put(&k, &v)?;
+    }
+    let tx = storage.transact(false)?;
+    ensure!(
+        tx.get(&key(1), false)?.is_some(),
+        "the deletion of a transaction that was not committed is applied"
+    );
+    ensure!(
+        !tx.exists(&key(3), false)?,
+        "the put of a transaction that was not committed is applied"
+    );
+    ensure!(
+        scanned_keys(&tx, 0, 10)? == vec![1, 2],
+        "range_scan sees the changes of a transaction that was not committed"
+    );
+    Ok(())
+}
+
+/// Scans give the keys in their range, lower bound included and upper bound excluded,
+/// in ascending order of bytes, merging the committed keys with those of the transaction
+pub fn scans_in_order<T: StorageUnderTest>(target: &T) -> Result<()> {
+    let storage = target.empty_storage()?;
+    // negative integers sort before positive ones in their encoding
+    put_committed(&storage, [7, -3, 12, 0, 5].into_iter())?;
+    let mut tx = storage.transact(true)?;
+    for k in [9, -8, 1, 15] {
+        let (k, v) = row(k);
+        tx.put(&k, &v)?;
+    }
+    tx.del(&key(12))?;
+    let expected = vec![-8, -3, 0, 1, 5, 7, 9, 15];
+    ensure!(
+        scanned_keys(&tx, -100, 100)? == expected,
+        "range_scan does not give all keys in ascending order"
+    );
+    ensure!(
+        scanned_keys(&tx, 0, 9)? == vec![0, 1, 5, 7],
+        "range_scan does not include its lower bound and exclude its upper bound"
+    );
+    ensure!(
+        tx.range_count(&key(-100), &key(100))? == expected.len(),
+        "range_count does not count the keys range_scan gives"
+    );
+    ensure!(
+        tx.range_count(&key(0), &key(9))? == 4,
+        "range_count does not include its lower bound and exclude its upper bound"
+    );
+    let tuples: Vec<Tuple> = tx.range_scan_tuple(&key(0), &key(9)).try_collect()?;
+    let expected_tuples = [0, 1, 5, 7]
+        .into_iter()
+        .map(|k| vec![DataValue::from(k), DataValue::from(k * 10)])
+        .collect_vec();
+    ensure!(
+        tuples == expected_tuples,
+        "range_scan_tuple does not decode the rows in the range"
+    );
+    let other = RelationId::new(REL + 1);
+    ensure!(
+        tx.range_scan(&Tuple::default().encode_as_key(other), &[0xFF])
+            .next()
+            .is_none(),
+        "range_scan gives keys outside its range"
+    );
+    tx.commit()?;
+
+    let tx = storage.transact(false)?;
+    let mut all = vec![];
+    for kv in tx.total_scan() {
+        all.push(kv?.0);
+    }
+    ensure!(
+        all.windows(2).all(|w| w[0] < w[1]),
+        "total_scan does not give keys in ascending order"
+    );
+    let ours = all
+        .iter()
+        .filter(|k| k.starts_with(&RelationId::new(REL).raw_encode()))
+        .cloned()
+        .collect_vec();
+    ensure!(
+        ours == expected.iter().map(|k| key(*k)).collect_vec(),
+        "total_scan does not give all committed keys"
+    );
+    Ok(())
+}
+
+/// `multi_get` gives what `get` gives for each key
+pub fn multi_get_matches_get<T: StorageUnderTest>(target: &T) -> Result<()> {
+    let storage = target.empty_storage()?;
+    put_committed(&storage, 1..=3)?;
+    let mut tx = storage.transact(true)?;
+    let (k, v) = row(4);
+    tx.put(&k, &v)?;
+    tx.del(&key(2))?;
+    let keys = (0..=5).map(key).collect_vec();
+    let expected: Vec<_> = keys.iter().map(|k| tx.get(k, false)).try_collect()?;
+    ensure!(
+        tx.multi_get(&keys, false)? == expected,
+        "multi_get does not give what get gives"
+    );
+    let merged = [None, Some(10), None, Some(30), Some(40), None].map(|v| v.map(val));
+    ensure!(
+        expected == merged,
+        "get does not merge the committed keys with those of the transaction"
+    );
+    Ok(())
+}
+
+/// `del_range_from_persisted` deletes the committed keys in its range, upper bound excluded
+pub fn deletes_persisted_ranges<T: StorageUnderTest>(target: &T) -> Result<()> {
+    let storage = target.empty_storage()?;
+    put_committed(&storage, 0..10)?;
+    {
+        let mut tx = storage.transact(true)?;
+        tx.del_range_from_persisted(&key(3), &key(7))?;
+        tx.commit()?;
+    }
+    let tx = storage.transact(false)?;
+    ensure!(
+        scanned_keys(&tx, 0, 10)? == vec![0, 1, 2, 7, 8, 9],
+        "del_range_from_persisted does not delete exactly the keys in its range"
+    );
+    Ok(())
+}
+
+/// The data given to `batch_put` is stored
+pub fn batch_puts<T: StorageUnderTest>(target: &T) -> Result<()> {
+    let storage = target.empty_storage()?;
+    storage.batch_put(Box::new((0..100).map(|k| Ok(row(k)))))?;
+    let tx = storage.transact(false)?;
+    ensure!(
+        scanned_keys(&tx, 0, 100)? == (0..100).collect_vec(),
+        "batch_put does not store the data given"
+    );
+    Ok(())
+}
+
+/// Skip scans give, for each key, the row with the latest validity at or before the time
+/// asked for, if it is an assertion. Only checked for engines supporting time travel.
+pub fn skip_scans_at_validity<T: StorageUnderTest>(target: &T) -> Result<()> {
+    if !target.supports_time_travel() {
+        return Ok(());
+    }
+    let storage = target.empty_storage()?;
+    let rel = RelationId::new(REL);
+    let vld_key = |k: i64, ts: i64, assert: bool| {
+        vec![
+            DataValue::from(k),
+            DataValue::Validity(Validity::from((ts, assert))),
+        ]
+        .encode_as_key(rel)
+    };
+    let history = [
+        (1, 10, true, 100),
+        (1, 20, true, 200),
+        (1, 30, false, 0),
+        (2, 5, true, 50),
+        (3, 25, true, 300),
+    ];
+    // some versions are committed, the others are seen within the transaction
+    let (committed, pending) = history.split_at(2);
+    {
+        let mut tx = storage.transact(true)?;
+        for (k, ts, assert, v) in committed {
+            tx.put(&vld_key(*k, *ts, *assert), &val(*v))?;
+        }
+        tx.commit()?;
+    }
+    let mut tx = storage.transact(true)?;
+    for (k, ts, assert, v) in pending {
+        tx.put(&vld_key(*k, *ts, *assert), &val(*v))?;
+    }
+    let lower = Tuple::default().encode_as_key(rel);
+    let upper = Tuple::default().encode_as_key(rel.next());
+    for (at, expected) in [
+        (1, vec![]),
+        (15, vec![(1, 10, 100), (2, 5, 50)]),
+        (25, vec![(1, 20, 200), (2, 5, 50), (3, 25, 300)]),
+        (35, vec![(2, 5, 50), (3, 25, 300)]),
+    ] {
+        let found: Vec<Tuple> = tx
+            .range_skip_scan_tuple(&lower, &upper, ValidityTs(Reverse(at)))
+            .try_collect()?;
+        let expected = expected
+            .into_iter()
+            .map(|(k, ts, v)| {
+                vec![
+                    DataValue::from(k),
+                    DataValue::Validity(Validity::from((ts, true))),
+                    DataValue::from(v),
+                ]
+            })
+            .collect_vec();
+        ensure!(
+            found == expected,
+            "range_skip_scan_tuple at {at} gives {found:?} instead of {expected:?}"
+        );
+    }
+    Ok(())
+}
+
+/// Run every check, stopping at the first failing
+pub fn check_all<T: StorageUnderTest>(target: &T) -> Result<()> {
+    reads_own_writes(target)?;
+    discards_uncommitted_writes(target)?;
+    scans_in_order(target)?;
+    multi_get_matches_get(target)?;
+    deletes_persisted_ranges(target)?;
+    batch_puts(target)?;
+    skip_scans_at_validity(target)
+}
+
+/// Generate a `#[test]` for every check of [conformance](crate::conformance), with the
+/// [StorageUnderTest](crate::conformance::StorageUnderTest) given, in the module it is
+/// called in. A list of checks may follow the target after a semicolon, to generate only those.
+#[macro_export]
+macro_rules! storage_conformance_tests {
+    ($target:expr) => {
+        $crate::storage_conformance_tests!(
+            $target;
+            reads_own_writes,
+            discards_uncommitted_writes,
+            scans_in_order,
+            multi_get_matches_get,
+            deletes_persisted_ranges,
+            batch_puts,
+            skip_scans_at_validity,
+        );
+    };
+    ($target:expr; $($check:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $check() {
+                if let Err(err) = $crate::conformance::$check(&$target) {
+                    panic!("{:?}", err);
+                }
+            }
+        )*
+    };
+}
@@ -0,0 +1,98 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use fs2::FileExt;
+use miette::{Diagnostic, IntoDiagnostic, Result, WrapErr};
+use thiserror::Error;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Database at {path} is already opened by {holder}")]
+#[diagnostic(code(db::locked))]
+#[diagnostic(help(
+    "Only one process may open a database for writing. Close the other process, \
+     or open this database with the option {{\"read_only\": true}}"
+))]
+pub(crate) struct DbLocked {
+    path: String,
+    holder: String,
+}
+
+/// An OS-level advisory lock guarding a database against being opened by several processes.
+///
+/// The lock is held on `<base>.lock`, and the PID of the holder is recorded in `<base>.pid`:
+/// on Windows a locked file cannot be read by other processes, so the PID lives in a separate file.
+/// The lock is released by the OS when the process exits, even if it crashes.
+pub(crate) struct ProcessLock {
+    _file: File,
+    pid_path: PathBuf,
+}
+
+impl Drop for ProcessLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.pid_path);
+    }
+}
+
+/// Take the lock for the database at `db_path`, with lock files placed at `base` plus suffixes.
+/// Pass `read_only` to skip locking altogether, for processes that will not write.
+pub(crate) fn lock_db(
+    db_path: &Path,
+    base: PathBuf,
+    read_only: bool,
+) -> Result<Option<Arc<ProcessLock>>> {
+    if read_only {
+        return Ok(None);
+    }
+    let lock_path = with_suffix(&base, "lock");
+    let pid_path = with_suffix(&base, "pid");
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("when opening lock file {}", lock_path.display()))?;
+    match file.try_lock_exclusive() {
+        Ok(()) => {}
+        Err(err) if err.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+            let holder = match fs::read_to_string(&pid_path) {
+                Ok(pid) if !pid.trim().is_empty() => format!("process {}", pid.trim()),
+                _ => "another process".to_string(),
+            };
+            return Err(DbLocked {
+                path: db_path.display().to_string(),
+                holder,
+            }
+            .into());
+        }
+        Err(err) => {
+            return Err(err)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("when locking {}", lock_path.display()))
+        }
+    }
+    fs::write(&pid_path, std::process::id().to_string())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("when writing {}", pid_path.display()))?;
+    Ok(Some(Arc::new(ProcessLock {
+        _file: file,
+        pid_path,
+    })))
+}
+
+fn with_suffix(base: &Path, suffix: &str) -> PathBuf {
+    let mut s = base.as_os_str().to_owned();
+    s.push(".");
+    s.push(suffix);
+    PathBuf::from(s)
+}
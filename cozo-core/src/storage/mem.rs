@@ -14,7 +14,7 @@ use std::default::Default;
 use std::iter::Fuse;
 use std::mem;
 use std::ops::Bound;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use itertools::Itertools;
 use miette::{bail, Result};
@@ -35,10 +35,67 @@ pub fn new_cozo_mem() -> Result<crate::Db<MemStorage>> {
     Ok(ret)
 }
 
+/// Create a database backed by memory, starting with the data given and keeping a journal
+/// of the changes made to it, so that they can be persisted elsewhere.
+/// See [MemStorage::journaled].
+pub fn new_cozo_mem_journaled(data: BTreeMap<Vec<u8>, Vec<u8>>) -> Result<crate::Db<MemStorage>> {
+    let ret = crate::Db::new(MemStorage::journaled(data))?;
+
+    ret.initialize()?;
+    Ok(ret)
+}
+
+/// The changes made to the data since they were last taken: the values put,
+/// or `None` for the keys deleted
+pub type MemChanges = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
 /// The non-persistent storage
 #[derive(Default, Clone)]
 pub struct MemStorage {
     store: Arc<ShardedLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    journal: Option<Arc<Mutex<MemChanges>>>,
+}
+
+impl MemStorage {
+    /// A storage starting with the data given, which keeps a journal of the changes made
+    /// to it. Used where memory is the only storage the database can use synchronously,
+    /// such as in browsers, to persist the data asynchronously: the changes are taken
+    /// in batches with [take_changes](Self::take_changes) and written out, and the data
+    /// is read back when the storage is created again.
+    pub fn journaled(data: BTreeMap<Vec<u8>, Vec<u8>>) -> Self {
+        Self {
+            store: Arc::new(ShardedLock::new(data)),
+            journal: Some(Default::default()),
+        }
+    }
+    /// The changes committed since they were last taken, each key with its latest value.
+    /// Always empty if the storage keeps no journal.
+    pub fn take_changes(&self) -> MemChanges {
+        match &self.journal {
+            None => Default::default(),
+            Some(journal) => mem::take(&mut *journal.lock().unwrap()),
+        }
+    }
+    /// All the data, with the changes up to now marked as taken, so that the data
+    /// can replace what was written out before
+    pub fn snapshot(&self) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let store = self.store.read().unwrap();
+        if let Some(journal) = &self.journal {
+            journal.lock().unwrap().clear();
+        }
+        store.clone()
+    }
+}
+
+impl crate::Db<MemStorage> {
+    /// See [MemStorage::take_changes].
+    pub fn take_changes(&self) -> MemChanges {
+        self.db.take_changes()
+    }
+    /// See [MemStorage::snapshot].
+    pub fn snapshot(&self) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        self.db.snapshot()
+    }
 }
 
 impl<'s> Storage<'s> for MemStorage {
@@ -51,7 +108,7 @@ impl<'s> Storage<'s> for MemStorage {
     fn transact(&'s self, write: bool) -> Result<Self::Tx> {
         Ok(if write {
             let wtr = self.store.write().unwrap();
-            MemTx::Writer(wtr, Default::default())
+            MemTx::Writer(wtr, Default::default(), self.journal.as_deref())
         } else {
             let rdr = self.store.read().unwrap();
             MemTx::Reader(rdr)
@@ -67,8 +124,12 @@ impl<'s> Storage<'s> for MemStorage {
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
     ) -> Result<()> {
         let mut store = self.store.write().unwrap();
+        let mut journal = self.journal.as_ref().map(|j| j.lock().unwrap());
         for pair in data {
             let (k, v) = pair?;
+            if let Some(journal) = &mut journal {
+                journal.insert(k.clone(), Some(v.clone()));
+            }
             store.insert(k, v);
         }
         Ok(())
@@ -80,6 +141,7 @@ pub enum MemTx<'s> {
     Writer(
         ShardedLockWriteGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>,
         BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        Option<&'s Mutex<MemChanges>>,
     ),
 }
 
@@ -87,7 +149,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
         Ok(match self {
             MemTx::Reader(rdr) => rdr.get(key).cloned(),
-            MemTx::Writer(wtr, cache) => match cache.get(key) {
+            MemTx::Writer(wtr, cache, _) => match cache.get(key) {
                 Some(r) => r.clone(),
                 None => wtr.get(key).cloned(),
             },
@@ -99,7 +161,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(_, cache) => {
+            MemTx::Writer(_, cache, _) => {
                 cache.insert(key.to_vec(), Some(val.to_vec()));
                 Ok(())
             }
@@ -119,7 +181,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(_, cache) => {
+            MemTx::Writer(_, cache, _) => {
                 cache.insert(key.to_vec(), None);
                 Ok(())
             }
@@ -131,7 +193,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(ref mut wtr, _) => {
+            MemTx::Writer(ref mut wtr, _, journal) => {
                 let keys = wtr
                     .range(lower.to_vec()..upper.to_vec())
                     .map(|kv| kv.0.clone())
@@ -139,6 +201,12 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 for k in keys.iter() {
                     wtr.remove(k);
                 }
+                if let Some(journal) = journal {
+                    let mut journal = journal.lock().unwrap();
+                    for k in keys {
+                        journal.insert(k, None);
+                    }
+                }
             }
         }
 
@@ -148,7 +216,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     fn exists(&self, key: &[u8], _for_update: bool) -> Result<bool> {
         Ok(match self {
             MemTx::Reader(rdr) => rdr.contains_key(key),
-            MemTx::Writer(wtr, cache) => match cache.get(key) {
+            MemTx::Writer(wtr, cache, _) => match cache.get(key) {
                 Some(r) => r.is_some(),
                 None => wtr.contains_key(key),
             },
@@ -158,9 +226,15 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     fn commit(&mut self) -> Result<()> {
         match self {
             MemTx::Reader(_) => Ok(()),
-            MemTx::Writer(wtr, cached) => {
+            MemTx::Writer(wtr, cached, journal) => {
                 let mut cache = BTreeMap::default();
                 mem::swap(&mut cache, cached);
+                if let Some(journal) = journal {
+                    let mut journal = journal.lock().unwrap();
+                    for (k, mv) in cache.iter() {
+                        journal.insert(k.clone(), mv.clone());
+                    }
+                }
                 for (k, mv) in cache {
                     match mv {
                         None => {
@@ -189,7 +263,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 rdr.range(lower.to_vec()..upper.to_vec())
                     .map(|(k, v)| Ok(decode_tuple_from_kv(k, v, None))),
             ),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIter {
+            MemTx::Writer(wtr, cache, _) => Box::new(CacheIter {
                 change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
                 db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
                 change_cache: None,
@@ -215,7 +289,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 }
                 .map(Ok),
             ),
-            MemTx::Writer(stored, delta) => Box::new(
+            MemTx::Writer(stored, delta, _) => Box::new(
                 SkipDualIterator {
                     stored,
                     delta,
@@ -241,7 +315,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 rdr.range(lower.to_vec()..upper.to_vec())
                     .map(|(k, v)| Ok((k.clone(), v.clone()))),
             ),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
+            MemTx::Writer(wtr, cache, _) => Box::new(CacheIterRaw {
                 change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
                 db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
                 change_cache: None,
@@ -256,7 +330,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     {
         Ok(match self {
             MemTx::Reader(rdr) => rdr.range(lower.to_vec()..upper.to_vec()).count(),
-            MemTx::Writer(wtr, cache) => (CacheIterRaw {
+            MemTx::Writer(wtr, cache, _) => (CacheIterRaw {
                 change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
                 db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
                 change_cache: None,
@@ -272,7 +346,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     {
         match self {
             MemTx::Reader(rdr) => Box::new(rdr.iter().map(|(k, v)| Ok((k.clone(), v.clone())))),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
+            MemTx::Writer(wtr, cache, _) => Box::new(CacheIterRaw {
                 change_iter: cache.iter().fuse(),
                 db_iter: wtr.iter().fuse(),
                 change_cache: None,
@@ -7,14 +7,24 @@
  */
 
 use itertools::Itertools;
-use miette::Result;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
 
 use crate::data::tuple::Tuple;
-use crate::data::value::ValidityTs;
+use crate::data::value::{DataValue, ValidityTs};
 use crate::decode_tuple_from_kv;
 
+pub mod conformance;
+#[cfg(any(
+    feature = "storage-rocksdb",
+    feature = "storage-sled",
+    feature = "storage-sqlite"
+))]
+pub(crate) mod lock;
 pub(crate) mod mem;
 #[cfg(feature = "storage-rocksdb")]
+pub(crate) mod partition;
+#[cfg(feature = "storage-rocksdb")]
 pub(crate) mod rocks;
 #[cfg(feature = "storage-sled")]
 pub(crate) mod sled;
@@ -25,6 +35,76 @@ pub(crate) mod temp;
 pub(crate) mod tikv;
 // pub(crate) mod re;
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The {0} storage engine has no setting '{1}' that can be changed at runtime")]
+#[diagnostic(code(storage::unknown_setting))]
+#[diagnostic(help("'::config' lists the settings that can be changed"))]
+pub(crate) struct UnknownStorageSetting(pub(crate) &'static str, pub(crate) String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The {0} storage engine cannot partition relations")]
+#[diagnostic(code(storage::partitioning_unsupported))]
+#[diagnostic(help("Relations can be partitioned with the RocksDB storage engine"))]
+pub(crate) struct PartitioningUnsupported(pub(crate) &'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid value {1} for the storage setting '{0}': {2}")]
+#[diagnostic(code(storage::invalid_setting))]
+pub(crate) struct InvalidStorageSetting(pub(crate) String, pub(crate) DataValue, pub(crate) String);
+
+/// The value of an integral storage setting, which must be at least `min`
+pub(crate) fn int_setting(name: &str, value: &DataValue, min: i64) -> Result<i64> {
+    match value.get_int() {
+        Some(i) if i >= min => Ok(i),
+        Some(_) => bail!(InvalidStorageSetting(
+            name.to_string(),
+            value.clone(),
+            format!("must be at least {min}")
+        )),
+        None => bail!(InvalidStorageSetting(
+            name.to_string(),
+            value.clone(),
+            "must be an integer".to_string()
+        )),
+    }
+}
+
+/// Counters of the work done by the storage engine for a transaction,
+/// telling queries waiting on reads from those busy computing
+#[derive(
+    Clone, Debug, Default, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize,
+)]
+pub struct StorageStats {
+    /// Keys found by lookups and scans
+    pub keys_read: u64,
+    /// Seeks of iterators, including the one starting each scan
+    pub seeks: u64,
+    /// Bytes of keys and values read
+    pub bytes_read: u64,
+    /// Blocks found in the block cache
+    pub block_cache_hits: u64,
+    /// Blocks read from disk, or from the page cache of the OS
+    pub block_reads: u64,
+    /// Bytes of the blocks read from disk
+    pub block_read_bytes: u64,
+    /// Lookups let into a file by its bloom filter
+    pub bloom_filter_hits: u64,
+    /// Lookups kept out of a file by its bloom filter
+    pub bloom_filter_misses: u64,
+}
+
+/// How the rows of a stored relation are spread over partitions, by the value
+/// of their first key column
+#[derive(Clone, Debug, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub enum PartitionScheme {
+    /// By a hash of the value, into the given number of partitions
+    Hash(usize),
+    /// By ranges of the value, given by ascending bounds. Partition `i` holds values
+    /// from bound `i - 1`, included, up to bound `i`, excluded, so that there is one
+    /// more partition than there are bounds.
+    Range(Vec<DataValue>),
+}
+
 /// Swappable storage trait for Cozo's storage engine
 pub trait Storage<'s>: Send + Sync + Clone {
     /// The associated transaction type used by this engine
@@ -40,6 +120,47 @@ pub trait Storage<'s>: Send + Sync + Clone {
     /// have the concept of compaction.
     fn range_compact(&'s self, lower: &[u8], upper: &[u8]) -> Result<()>;
 
+    /// Make all committed data durable, e.g. by flushing in-memory buffers to disk.
+    /// Called when the database is closed. The default implementation does nothing.
+    fn flush(&'s self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Read all stored data and verify it against the checksums kept by the storage engine,
+    /// returning an error describing any corruption found. Returns `false` if the engine
+    /// keeps no checksums, which is what the default implementation does.
+    fn verify_checksums(&'s self) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// The settings of the storage engine that can be changed at runtime by
+    /// [`set_config`](Self::set_config), with their current values.
+    /// The default implementation has none.
+    fn config(&'s self) -> Result<Vec<(&'static str, DataValue)>> {
+        Ok(vec![])
+    }
+
+    /// Change a setting of the storage engine until the database is closed, after
+    /// checking the value. The default implementation knows no settings.
+    fn set_config(&'s self, name: &str, _value: &DataValue) -> Result<()> {
+        bail!(UnknownStorageSetting(self.storage_kind(), name.to_string()))
+    }
+
+    /// Spread the rows of the stored relation with the given id over partitions according to
+    /// `scheme`, or put them back together if `scheme` is `None`. Rows keep being found by
+    /// their keys, and scans may skip the partitions they cannot find keys in.
+    /// There will be no writes to the relation while this function is running.
+    /// The default implementation cannot partition.
+    fn set_partitions(&'s self, _relation: u64, _scheme: Option<&PartitionScheme>) -> Result<()> {
+        bail!(PartitioningUnsupported(self.storage_kind()))
+    }
+
+    /// The partitioned relations by id, with their scheme and the names of their partitions.
+    /// The default implementation has none.
+    fn partitions(&'s self) -> Result<Vec<(u64, PartitionScheme, Vec<String>)>> {
+        Ok(vec![])
+    }
+
     /// Put multiple key-value pairs into the database.
     /// No duplicate data will be sent, and the order data come in is strictly ascending.
     /// There will be no other access to the database while this function is running.
@@ -101,6 +222,22 @@ pub trait StoreTx<'s>: Sync {
     /// and discard all changes introduced by this transaction.
     fn commit(&mut self) -> Result<()>;
 
+    /// Start counting the work done by the transaction, to be returned by
+    /// [`storage_stats`](Self::storage_stats). The default implementation counts nothing.
+    fn collect_stats(&mut self) {}
+
+    /// The work done by the transaction since [`collect_stats`](Self::collect_stats) was called,
+    /// or `None` if it was not called or the storage engine does not count.
+    fn storage_stats(&self) -> Option<StorageStats> {
+        None
+    }
+
+    /// Whether the rows of the stored relation with the given id are spread over partitions,
+    /// see [`Storage::set_partitions`]. The default implementation does not partition.
+    fn is_partitioned(&self, _relation: u64) -> bool {
+        false
+    }
+
     /// Scan on a range. `lower` is inclusive whereas `upper` is exclusive.
     /// The default implementation calls [`range_scan_owned`](Self::range_scan) and converts the results.
     ///
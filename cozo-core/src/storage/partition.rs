@@ -0,0 +1,119 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::hash::Hasher;
+use std::ops::RangeInclusive;
+
+use twox_hash::XxHash64;
+
+use crate::data::memcmp::MemCmpEncoder;
+use crate::data::tuple::ENCODED_KEY_MIN_LEN;
+use crate::data::value::DataValue;
+use crate::storage::PartitionScheme;
+
+impl PartitionScheme {
+    pub(crate) fn n_partitions(&self) -> usize {
+        match self {
+            PartitionScheme::Hash(n) => *n,
+            PartitionScheme::Range(bounds) => bounds.len() + 1,
+        }
+    }
+}
+
+/// Finds the partitions of keys of a relation, given without their relation id
+pub(crate) struct PartitionRouter {
+    scheme: PartitionScheme,
+    /// The bounds of a range scheme, encoded as keys are, so that they compare with them
+    encoded_bounds: Vec<Vec<u8>>,
+}
+
+/// The encoded first value of the key suffix, if there is one
+fn first_value(suffix: &[u8]) -> Option<&[u8]> {
+    if suffix.is_empty() {
+        return None;
+    }
+    let (_, rest) = DataValue::decode_from_key(suffix);
+    Some(&suffix[..suffix.len() - rest.len()])
+}
+
+fn hash_partition(value: &[u8], n: usize) -> usize {
+    // xxHash does not change between versions or platforms, unlike the hasher of std
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(value);
+    (hasher.finish() % n as u64) as usize
+}
+
+impl PartitionRouter {
+    pub(crate) fn new(scheme: PartitionScheme) -> Self {
+        let encoded_bounds = match &scheme {
+            PartitionScheme::Hash(_) => vec![],
+            PartitionScheme::Range(bounds) => bounds
+                .iter()
+                .map(|b| {
+                    let mut encoded = vec![];
+                    encoded.encode_datavalue(b);
+                    encoded
+                })
+                .collect(),
+        };
+        Self {
+            scheme,
+            encoded_bounds,
+        }
+    }
+
+    pub(crate) fn scheme(&self) -> &PartitionScheme {
+        &self.scheme
+    }
+
+    /// The partition of a key of the relation
+    pub(crate) fn partition_of(&self, key: &[u8]) -> usize {
+        let suffix = &key[ENCODED_KEY_MIN_LEN..];
+        match &self.scheme {
+            PartitionScheme::Hash(n) => first_value(suffix).map_or(0, |v| hash_partition(v, *n)),
+            PartitionScheme::Range(_) => self.range_partition(suffix),
+        }
+    }
+
+    fn range_partition(&self, suffix: &[u8]) -> usize {
+        // a key with the value of a bound starts with the encoded bound, so is not below it
+        self.encoded_bounds
+            .partition_point(|b| b.as_slice() <= suffix)
+    }
+
+    /// The partitions a scan of the relation from `lower`, included, to `upper`, excluded,
+    /// may find keys in. An `upper` of `None` scans to the end of the relation.
+    ///
+    /// Hash partitions are only told apart when all keys of the scan have the same
+    /// first value, range partitions whenever the bounds of the scan fall between theirs.
+    pub(crate) fn partitions_between(
+        &self,
+        lower: &[u8],
+        upper: Option<&[u8]>,
+    ) -> RangeInclusive<usize> {
+        let lower = &lower[ENCODED_KEY_MIN_LEN..];
+        let upper = upper.map(|u| &u[ENCODED_KEY_MIN_LEN..]);
+        match &self.scheme {
+            PartitionScheme::Hash(n) => match (first_value(lower), upper) {
+                (Some(v), Some(upper)) if upper.starts_with(v) => {
+                    let p = hash_partition(v, *n);
+                    p..=p
+                }
+                _ => 0..=n - 1,
+            },
+            PartitionScheme::Range(bounds) => {
+                let start = self.range_partition(lower);
+                let end = match upper {
+                    None => bounds.len(),
+                    Some(upper) => self.range_partition(upper),
+                };
+                start..=end
+            }
+        }
+    }
+}
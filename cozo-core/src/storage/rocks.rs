@@ -6,39 +6,65 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
 use std::fs;
+use std::iter;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 
 use log::info;
-use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use miette::{bail, miette, IntoDiagnostic, Result, WrapErr};
 
-use cozorocks::{DbBuilder, DbIter, RocksDb, Tx};
+use cozorocks::{perf_counters, DbBuilder, DbIter, PerfCounters, RocksDb, Tx};
 
-use crate::data::tuple::{check_key_for_validity, Tuple};
-use crate::data::value::ValidityTs;
+use crate::data::tuple::{check_key_for_validity, Tuple, ENCODED_KEY_MIN_LEN};
+use crate::data::value::{DataValue, ValidityTs};
 use crate::runtime::db::{BadDbInit, DbManifest};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
-use crate::storage::{Storage, StoreTx};
+use crate::storage::lock::{lock_db, ProcessLock};
+use crate::storage::partition::PartitionRouter;
+use crate::storage::{
+    int_setting, InvalidStorageSetting, PartitionScheme, Storage, StorageStats, StoreTx,
+    UnknownStorageSetting,
+};
 use crate::utils::swap_option_result;
 use crate::Db;
 
 const KEY_PREFIX_LEN: usize = 9;
 const CURRENT_STORAGE_VERSION: u64 = 3;
+/// The column family keeping the schemes of partitioned relations, by relation id
+const PARTITIONS_CF: &str = "cozo_partitions";
+/// The number of rows moved by each transaction when partitioning a relation
+const MOVE_BATCH_SIZE: usize = 10_000;
 
 /// Creates a RocksDB database object.
 /// This is currently the fastest persistent storage and it can
 /// sustain huge concurrency.
 /// Supports concurrent readers and writers.
 pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
+    open_cozo_rocksdb(path, false)
+}
+
+/// Open a RocksDB database. If `read_only` is true, the database must exist already, the
+/// lock guarding against other processes is not taken, and all write transactions are
+/// refused. RocksDB itself still opens the database for writing, locking it and keeping
+/// its own log files, so it cannot be opened while another process has it open.
+pub(crate) fn open_cozo_rocksdb(
+    path: impl AsRef<Path>,
+    read_only: bool,
+) -> Result<Db<RocksDbStorage>> {
     let builder = DbBuilder::default().path(path.as_ref());
-    fs::create_dir_all(path.as_ref()).map_err(|err| {
-        BadDbInit(format!(
-            "cannot create directory {}: {}",
-            path.as_ref().to_string_lossy(),
-            err
-        ))
-    })?;
+    if !read_only {
+        fs::create_dir_all(path.as_ref()).map_err(|err| {
+            BadDbInit(format!(
+                "cannot create directory {}: {}",
+                path.as_ref().to_string_lossy(),
+                err
+            ))
+        })?;
+    }
     let path_buf = PathBuf::from(path.as_ref());
+    let process_lock = lock_db(path.as_ref(), path_buf.join("cozo"), read_only)?;
 
     let is_new = {
         let mut manifest_path = path_buf.clone();
@@ -59,6 +85,11 @@ pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
             );
 
             false
+        } else if read_only {
+            bail!(
+                "no database at {} to open read-only",
+                path.as_ref().display()
+            )
         } else {
             fs::write(
                 manifest_path,
@@ -105,7 +136,10 @@ pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
 
     let db = db_builder.build()?;
 
-    let ret = Db::new(RocksDbStorage::new(db))?;
+    let ret = Db::new(RocksDbStorage::new(db, process_lock, read_only)?)?;
+    if read_only {
+        ret.set_read_only();
+    }
     ret.initialize()?;
     Ok(ret)
 }
@@ -114,11 +148,358 @@ pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
 #[derive(Clone)]
 pub struct RocksDbStorage {
     db: RocksDb,
+    partitions: Arc<RwLock<Partitions>>,
+    /// Held while the rows of a relation move between partitions
+    partitioning: Arc<Mutex<()>>,
+    _process_lock: Option<Arc<ProcessLock>>,
 }
 
 impl RocksDbStorage {
-    pub(crate) fn new(db: RocksDb) -> Self {
-        Self { db }
+    pub(crate) fn new(
+        db: RocksDb,
+        process_lock: Option<Arc<ProcessLock>>,
+        read_only: bool,
+    ) -> Result<Self> {
+        let partitions = Partitions::load(&db)?;
+        let relations = partitions.relations.keys().copied().collect::<Vec<_>>();
+        let ret = Self {
+            db,
+            partitions: Arc::new(RwLock::new(partitions)),
+            partitioning: Default::default(),
+            _process_lock: process_lock,
+        };
+        if !read_only {
+            // a partitioning interrupted after it was saved leaves rows behind, which scans
+            // across relations would find
+            for relation in relations {
+                ret.clear_default_rows(relation)?;
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Apply `op` to the rows of a column family, the default one if `None`, between the bounds,
+    /// in transactions of [MOVE_BATCH_SIZE] rows
+    fn rewrite_rows(
+        &self,
+        cf: Option<usize>,
+        lower: &[u8],
+        upper: &[u8],
+        op: impl Fn(&Tx, &[u8], &[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let reader = self.db.transact().start();
+        let builder = match cf {
+            None => reader.iterator(),
+            Some(cf) => reader.iterator_cf(cf),
+        };
+        let mut it = builder.upper_bound(upper).start();
+        it.seek(lower);
+        let mut writer = self.db.transact().start();
+        let mut n = 0;
+        while let Some((k, v)) = it.pair()? {
+            if k >= upper {
+                break;
+            }
+            op(&writer, k, v)?;
+            n += 1;
+            if n % MOVE_BATCH_SIZE == 0 {
+                writer.commit()?;
+                writer = self.db.transact().start();
+            }
+            it.next();
+        }
+        writer.commit()?;
+        Ok(())
+    }
+
+    fn partition_relation(&self, relation: u64, scheme: &PartitionScheme) -> Result<()> {
+        if self
+            .partitions
+            .read()
+            .unwrap()
+            .relations
+            .contains_key(&relation)
+        {
+            bail!("relation {relation} is already partitioned")
+        }
+        let existing = self.db.column_families();
+        let mut cfs = vec![];
+        let mut names = vec![];
+        for i in 0..scheme.n_partitions() {
+            let name = format!("cozo_rel{relation}_p{i}");
+            // left behind by an attempt that failed
+            if let Some(idx) = existing.iter().position(|n| *n == name) {
+                self.db.drop_column_family(idx)?;
+            }
+            cfs.push(self.db.create_column_family(&name)?);
+            names.push(name);
+        }
+        let part = PartitionedRelation {
+            router: PartitionRouter::new(scheme.clone()),
+            cfs,
+            names,
+        };
+        let lower = relation.to_be_bytes();
+        let upper = (relation + 1).to_be_bytes();
+        self.rewrite_rows(None, &lower, &upper, |tx, k, v| {
+            Ok(tx.put_cf(part.cf_of(k), k, v)?)
+        })?;
+        self.save_partition_meta(relation, Some(&part))?;
+        self.partitions
+            .write()
+            .unwrap()
+            .relations
+            .insert(relation, part);
+        // from now on the rows are only looked for in the partitions
+        self.clear_default_rows(relation)
+    }
+
+    /// Delete the rows of a partitioned relation from the default column family
+    fn clear_default_rows(&self, relation: u64) -> Result<()> {
+        let lower = relation.to_be_bytes();
+        let upper = (relation + 1).to_be_bytes();
+        self.rewrite_rows(None, &lower, &upper, |tx, k, _| Ok(tx.del(k)?))
+    }
+
+    fn unpartition_relation(&self, relation: u64) -> Result<()> {
+        let cfs = match self.partitions.read().unwrap().relations.get(&relation) {
+            None => bail!("relation {relation} is not partitioned"),
+            Some(part) => part.cfs.clone(),
+        };
+        // rows may have been left there by an interrupted move, and deleted since
+        self.clear_default_rows(relation)?;
+        let lower = relation.to_be_bytes();
+        let upper = (relation + 1).to_be_bytes();
+        for cf in &cfs {
+            self.rewrite_rows(Some(*cf), &lower, &upper, |tx, k, v| Ok(tx.put(k, v)?))?;
+        }
+        self.save_partition_meta(relation, None)?;
+        self.partitions.write().unwrap().relations.remove(&relation);
+        for cf in cfs {
+            self.db.drop_column_family(cf)?;
+        }
+        Ok(())
+    }
+
+    fn save_partition_meta(&self, relation: u64, part: Option<&PartitionedRelation>) -> Result<()> {
+        let meta_cf = self.meta_cf()?;
+        let key = relation.to_be_bytes();
+        let mut tx = self.db.transact().start();
+        match part {
+            Some(part) => {
+                let meta = PartitionMeta {
+                    scheme: part.router.scheme().clone(),
+                    column_families: part.names.clone(),
+                };
+                let val = rmp_serde::to_vec_named(&meta).into_diagnostic()?;
+                tx.put_cf(meta_cf, &key, &val)?;
+            }
+            None => tx.del_cf(meta_cf, &key)?,
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Write a row to the default column family, whatever the relation it belongs to
+    #[cfg(test)]
+    pub(crate) fn put_unrouted(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        let mut tx = self.db.transact().start();
+        tx.put(key, val)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn meta_cf(&self) -> Result<usize> {
+        if let Some(cf) = self.partitions.read().unwrap().meta_cf {
+            return Ok(cf);
+        }
+        let cf = self.db.create_column_family(PARTITIONS_CF)?;
+        self.partitions.write().unwrap().meta_cf = Some(cf);
+        Ok(cf)
+    }
+}
+
+/// The scheme of a partitioned relation as it is kept in [PARTITIONS_CF]
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+struct PartitionMeta {
+    scheme: PartitionScheme,
+    column_families: Vec<String>,
+}
+
+/// A partitioned relation, with the column families of its partitions
+struct PartitionedRelation {
+    router: PartitionRouter,
+    cfs: Vec<usize>,
+    names: Vec<String>,
+}
+
+impl PartitionedRelation {
+    fn cf_of(&self, key: &[u8]) -> usize {
+        self.cfs[self.router.partition_of(key)]
+    }
+}
+
+/// The partitioned relations of the database, shared by the storage and its transactions
+#[derive(Default)]
+struct Partitions {
+    relations: BTreeMap<u64, PartitionedRelation>,
+    /// The index of [PARTITIONS_CF], once it exists
+    meta_cf: Option<usize>,
+}
+
+fn relation_of(key: &[u8]) -> u64 {
+    u64::from_be_bytes(key[..ENCODED_KEY_MIN_LEN].try_into().unwrap())
+}
+
+impl Partitions {
+    fn load(db: &RocksDb) -> Result<Self> {
+        let names = db.column_families();
+        let meta_cf = match names.iter().position(|n| n == PARTITIONS_CF) {
+            None => return Ok(Self::default()),
+            Some(cf) => cf,
+        };
+        let tx = db.transact().start();
+        let mut it = tx.iterator_cf(meta_cf).start();
+        it.seek_to_start();
+        let mut relations = BTreeMap::new();
+        while let Some((k, v)) = it.pair()? {
+            let meta: PartitionMeta = rmp_serde::from_slice(v).into_diagnostic()?;
+            let cfs = meta
+                .column_families
+                .iter()
+                .map(|name| {
+                    names.iter().position(|n| n == name).ok_or_else(|| {
+                        miette!("the column family {name} of a partition is missing")
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            relations.insert(
+                relation_of(k),
+                PartitionedRelation {
+                    router: PartitionRouter::new(meta.scheme),
+                    cfs,
+                    names: meta.column_families,
+                },
+            );
+            it.next();
+        }
+        Ok(Self {
+            relations,
+            meta_cf: Some(meta_cf),
+        })
+    }
+
+    /// The column family of a key, if it is not the default one
+    #[inline]
+    fn cf_of(&self, key: &[u8]) -> Option<usize> {
+        if self.relations.is_empty() || key.len() < ENCODED_KEY_MIN_LEN {
+            return None;
+        }
+        self.relations
+            .get(&relation_of(key))
+            .map(|part| part.cf_of(key))
+    }
+
+    /// The column families a scan may find keys in, `None` standing for the default one
+    fn scan_cfs(&self, lower: &[u8], upper: &[u8]) -> Vec<Option<usize>> {
+        if self.relations.is_empty() {
+            return vec![None];
+        }
+        if lower.len() >= ENCODED_KEY_MIN_LEN && upper.len() >= ENCODED_KEY_MIN_LEN {
+            let relation = relation_of(lower);
+            let upper_relation = relation_of(upper);
+            // the scan ends either within the relation or where the next one starts
+            let to_end = upper.len() == ENCODED_KEY_MIN_LEN
+                && relation.checked_add(1) == Some(upper_relation);
+            if upper_relation == relation || to_end {
+                return match self.relations.get(&relation) {
+                    None => vec![None],
+                    Some(part) => {
+                        let upper = if to_end { None } else { Some(upper) };
+                        part.router
+                            .partitions_between(lower, upper)
+                            .map(|i| Some(part.cfs[i]))
+                            .collect()
+                    }
+                };
+            }
+        }
+        // scans across relations, as those of backups, look everywhere
+        iter::once(None)
+            .chain(
+                self.relations
+                    .values()
+                    .flat_map(|part| part.cfs.iter().map(|cf| Some(*cf))),
+            )
+            .collect()
+    }
+}
+
+/// Iterates over the column families a scan may find keys in, in the order of keys
+pub(crate) struct PartIter(Vec<DbIter>);
+
+impl PartIter {
+    #[inline]
+    fn seek(&mut self, key: &[u8]) {
+        for it in &mut self.0 {
+            it.seek(key)
+        }
+    }
+
+    /// The iterator at the smallest key, if any is not exhausted
+    #[inline]
+    fn current(&self) -> Result<Option<&DbIter>> {
+        if let [it] = self.0.as_slice() {
+            return Ok(Some(it));
+        }
+        let mut found: Option<(&DbIter, &[u8])> = None;
+        for it in &self.0 {
+            if let Some(k) = it.key()? {
+                let smaller = match found {
+                    None => true,
+                    Some((_, min)) => k < min,
+                };
+                if smaller {
+                    found = Some((it, k));
+                }
+            }
+        }
+        Ok(found.map(|(it, _)| it))
+    }
+
+    #[inline]
+    fn next(&mut self) -> Result<()> {
+        if let [it] = self.0.as_mut_slice() {
+            it.next();
+            return Ok(());
+        }
+        let min = match self.current()? {
+            None => return Ok(()),
+            Some(it) => it.key()?.unwrap_or_default().to_vec(),
+        };
+        // a key is only found twice while a relation is being partitioned
+        for it in &mut self.0 {
+            if it.key()? == Some(min.as_slice()) {
+                it.next();
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn key(&self) -> Result<Option<&[u8]>> {
+        match self.current()? {
+            None => Ok(None),
+            Some(it) => Ok(it.key()?),
+        }
+    }
+
+    #[inline]
+    fn pair(&self) -> Result<Option<(&[u8], &[u8])>> {
+        match self.current()? {
+            None => Ok(None),
+            Some(it) => Ok(it.pair()?),
+        }
     }
 }
 
@@ -131,13 +512,113 @@ impl Storage<'_> for RocksDbStorage {
 
     fn transact(&self, _write: bool) -> Result<Self::Tx> {
         let db_tx = self.db.transact().set_snapshot(true).start();
-        Ok(RocksDbTx { db_tx })
+        Ok(RocksDbTx {
+            db_tx,
+            partitions: self.partitions.clone(),
+            stats: None,
+        })
     }
 
     fn range_compact(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
         self.db.range_compact(lower, upper).into_diagnostic()
     }
 
+    fn flush(&self) -> Result<()> {
+        self.db.flush().into_diagnostic()
+    }
+
+    fn verify_checksums(&self) -> Result<bool> {
+        self.db.verify_checksum().into_diagnostic()?;
+        Ok(true)
+    }
+
+    fn config(&self) -> Result<Vec<(&'static str, DataValue)>> {
+        let config = self.db.config();
+        Ok(vec![
+            (
+                "block_cache_capacity",
+                DataValue::from(config.block_cache_capacity as i64),
+            ),
+            (
+                "write_buffer_size",
+                DataValue::from(config.write_buffer_size as i64),
+            ),
+            (
+                "max_write_buffer_number",
+                DataValue::from(config.max_write_buffer_number as i64),
+            ),
+            (
+                "max_background_jobs",
+                DataValue::from(config.max_background_jobs as i64),
+            ),
+            (
+                "level0_file_num_compaction_trigger",
+                DataValue::from(config.level0_file_num_compaction_trigger as i64),
+            ),
+            (
+                "disable_auto_compactions",
+                DataValue::from(config.disable_auto_compactions),
+            ),
+            ("compaction_style", DataValue::from(config.compaction_style)),
+        ])
+    }
+
+    fn set_config(&self, name: &str, value: &DataValue) -> Result<()> {
+        // settings are named as the options of RocksDB, some of which are db-wide
+        let (val, db_wide) = match name {
+            "block_cache_capacity" => {
+                let capacity = int_setting(name, value, 0)?;
+                return self
+                    .db
+                    .set_block_cache_capacity(capacity as usize)
+                    .into_diagnostic();
+            }
+            // RocksDB raises smaller write buffers to 64 KiB
+            "write_buffer_size" => (int_setting(name, value, 1 << 16)?.to_string(), false),
+            "max_write_buffer_number" => (int_setting(name, value, 2)?.to_string(), false),
+            "level0_file_num_compaction_trigger" => {
+                (int_setting(name, value, 1)?.to_string(), false)
+            }
+            "max_background_jobs" => (int_setting(name, value, 1)?.to_string(), true),
+            "disable_auto_compactions" => match value.get_bool() {
+                Some(b) => (b.to_string(), false),
+                None => bail!(InvalidStorageSetting(
+                    name.to_string(),
+                    value.clone(),
+                    "must be a boolean".to_string()
+                )),
+            },
+            "compaction_style" => bail!(InvalidStorageSetting(
+                name.to_string(),
+                value.clone(),
+                "the compaction style can only be set in the options file, \
+                 and takes effect when the database is opened"
+                    .to_string()
+            )),
+            _ => bail!(UnknownStorageSetting("rocksdb", name.to_string())),
+        };
+        self.db.set_option(name, &val, db_wide).into_diagnostic()
+    }
+
+    fn set_partitions(&self, relation: u64, scheme: Option<&PartitionScheme>) -> Result<()> {
+        let _guard = self.partitioning.lock().unwrap();
+        match scheme {
+            Some(scheme) => self.partition_relation(relation, scheme),
+            None => self.unpartition_relation(relation),
+        }
+    }
+
+    fn partitions(&self) -> Result<Vec<(u64, PartitionScheme, Vec<String>)>> {
+        Ok(self
+            .partitions
+            .read()
+            .unwrap()
+            .relations
+            .iter()
+            .map(|(id, part)| (*id, part.router.scheme().clone(), part.names.clone()))
+            .collect())
+    }
+
     fn batch_put<'a>(
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
@@ -152,6 +633,81 @@ impl Storage<'_> for RocksDbStorage {
 
 pub struct RocksDbTx {
     db_tx: Tx,
+    partitions: Arc<RwLock<Partitions>>,
+    stats: Option<Arc<TxStats>>,
+}
+
+impl RocksDbTx {
+    #[inline]
+    fn cf_of(&self, key: &[u8]) -> Option<usize> {
+        self.partitions.read().unwrap().cf_of(key)
+    }
+
+    #[inline]
+    fn put_routed(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        match self.cf_of(key) {
+            None => Ok(self.db_tx.put(key, val)?),
+            Some(cf) => Ok(self.db_tx.put_cf(cf, key, val)?),
+        }
+    }
+
+    #[inline]
+    fn del_routed(&self, key: &[u8]) -> Result<()> {
+        match self.cf_of(key) {
+            None => Ok(self.db_tx.del(key)?),
+            Some(cf) => Ok(self.db_tx.del_cf(cf, key)?),
+        }
+    }
+
+    /// An iterator over the column families a scan between the bounds may find keys in,
+    /// not positioned yet
+    fn iter_between(&self, lower: &[u8], upper: &[u8]) -> PartIter {
+        let cfs = self.partitions.read().unwrap().scan_cfs(lower, upper);
+        PartIter(
+            cfs.into_iter()
+                .map(|cf| {
+                    let builder = match cf {
+                        None => self.db_tx.iterator(),
+                        Some(cf) => self.db_tx.iterator_cf(cf),
+                    };
+                    builder.upper_bound(upper).start()
+                })
+                .collect(),
+        )
+    }
+}
+
+/// The work done by a transaction, shared with its iterators
+#[derive(Default)]
+struct TxStats(Mutex<StorageStats>);
+
+/// The perf counters of the current thread before an operation, if the work is counted.
+///
+/// The perf context of RocksDB is local to each thread, and the parts of a query may run
+/// on several, so each operation is counted on the thread it runs on.
+struct Sample<'a>(Option<(&'a TxStats, PerfCounters)>);
+
+impl<'a> Sample<'a> {
+    #[inline]
+    fn start(stats: &'a Option<Arc<TxStats>>) -> Self {
+        Self(stats.as_deref().map(|stats| (stats, perf_counters())))
+    }
+
+    #[inline]
+    fn finish(self, seeks: u64, keys_read: u64) {
+        if let Some((stats, before)) = self.0 {
+            let delta = perf_counters().since(&before);
+            let mut stats = stats.0.lock().unwrap();
+            stats.keys_read += keys_read;
+            stats.seeks += seeks;
+            stats.bytes_read += delta.bytes_read;
+            stats.block_cache_hits += delta.block_cache_hits;
+            stats.block_reads += delta.block_reads;
+            stats.block_read_bytes += delta.block_read_bytes;
+            stats.bloom_filter_hits += delta.bloom_filter_hits;
+            stats.bloom_filter_misses += delta.bloom_filter_misses;
+        }
+    }
 }
 
 unsafe impl Sync for RocksDbTx {}
@@ -159,12 +715,19 @@ unsafe impl Sync for RocksDbTx {}
 impl<'s> StoreTx<'s> for RocksDbTx {
     #[inline]
     fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
-        Ok(self.db_tx.get(key, for_update)?.map(|v| v.to_vec()))
+        let sample = Sample::start(&self.stats);
+        let ret = match self.cf_of(key) {
+            None => self.db_tx.get(key, for_update)?,
+            Some(cf) => self.db_tx.get_cf(cf, key, for_update)?,
+        }
+        .map(|v| v.to_vec());
+        sample.finish(0, ret.is_some() as u64);
+        Ok(ret)
     }
 
     #[inline]
     fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
-        Ok(self.db_tx.put(key, val)?)
+        self.put_routed(key, val)
     }
 
     fn supports_par_put(&self) -> bool {
@@ -173,41 +736,69 @@ impl<'s> StoreTx<'s> for RocksDbTx {
 
     #[inline]
     fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
-        Ok(self.db_tx.put(key, val)?)
+        self.put_routed(key, val)
     }
 
     #[inline]
     fn del(&mut self, key: &[u8]) -> Result<()> {
-        Ok(self.db_tx.del(key)?)
+        self.del_routed(key)
     }
 
     #[inline]
     fn par_del(&self, key: &[u8]) -> Result<()> {
-        Ok(self.db_tx.del(key)?)
+        self.del_routed(key)
     }
 
     fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
-        let mut inner = self.db_tx.iterator().upper_bound(upper).start();
+        let sample = Sample::start(&self.stats);
+        let mut inner = self.iter_between(lower, upper);
         inner.seek(lower);
+        let mut count = 0;
         while let Some(key) = inner.key()? {
             if key >= upper {
                 break;
             }
-            self.db_tx.del(key)?;
-            inner.next();
+            self.del_routed(key)?;
+            count += 1;
+            inner.next()?;
         }
+        sample.finish(1, count);
         Ok(())
     }
 
     #[inline]
     fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
-        Ok(self.db_tx.exists(key, for_update)?)
+        let sample = Sample::start(&self.stats);
+        let ret = match self.cf_of(key) {
+            None => self.db_tx.exists(key, for_update)?,
+            Some(cf) => self.db_tx.exists_cf(cf, key, for_update)?,
+        };
+        sample.finish(0, ret as u64);
+        Ok(ret)
     }
 
     fn commit(&mut self) -> Result<()> {
         Ok(self.db_tx.commit()?)
     }
 
+    fn is_partitioned(&self, relation: u64) -> bool {
+        self.partitions
+            .read()
+            .unwrap()
+            .relations
+            .contains_key(&relation)
+    }
+
+    fn collect_stats(&mut self) {
+        self.stats = Some(Default::default());
+    }
+
+    fn storage_stats(&self) -> Option<StorageStats> {
+        self.stats
+            .as_ref()
+            .map(|stats| stats.0.lock().unwrap().clone())
+    }
+
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
@@ -216,12 +807,15 @@ impl<'s> StoreTx<'s> for RocksDbTx {
     where
         's: 'a,
     {
-        let mut inner = self.db_tx.iterator().upper_bound(upper).start();
+        let sample = Sample::start(&self.stats);
+        let mut inner = self.iter_between(lower, upper);
         inner.seek(lower);
+        sample.finish(1, 0);
         Box::new(RocksDbIterator {
             inner,
             started: false,
             upper_bound: upper.to_vec(),
+            stats: self.stats.clone(),
         })
     }
 
@@ -231,12 +825,13 @@ impl<'s> StoreTx<'s> for RocksDbTx {
         upper: &[u8],
         valid_at: ValidityTs,
     ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
-        let inner = self.db_tx.iterator().upper_bound(upper).start();
+        let inner = self.iter_between(lower, upper);
         Box::new(RocksDbSkipIterator {
             inner,
             upper_bound: upper.to_vec(),
             next_bound: lower.to_owned(),
             valid_at,
+            stats: self.stats.clone(),
         })
     }
 
@@ -248,12 +843,15 @@ impl<'s> StoreTx<'s> for RocksDbTx {
     where
         's: 'a,
     {
-        let mut inner = self.db_tx.iterator().upper_bound(upper).start();
+        let sample = Sample::start(&self.stats);
+        let mut inner = self.iter_between(lower, upper);
         inner.seek(lower);
+        sample.finish(1, 0);
         Box::new(RocksDbIteratorRaw {
             inner,
             started: false,
             upper_bound: upper.to_vec(),
+            stats: self.stats.clone(),
         })
     }
 
@@ -261,7 +859,8 @@ impl<'s> StoreTx<'s> for RocksDbTx {
     where
         's: 'a,
     {
-        let mut inner = self.db_tx.iterator().upper_bound(upper).start();
+        let sample = Sample::start(&self.stats);
+        let mut inner = self.iter_between(lower, upper);
         inner.seek(lower);
         let mut count = 0;
         while let Some(k) = inner.key()? {
@@ -269,8 +868,9 @@ impl<'s> StoreTx<'s> for RocksDbTx {
                 break;
             }
             count += 1;
-            inner.next();
+            inner.next()?;
         }
+        sample.finish(1, count as u64);
         Ok(count)
     }
 
@@ -283,20 +883,22 @@ impl<'s> StoreTx<'s> for RocksDbTx {
 }
 
 pub(crate) struct RocksDbIterator {
-    inner: DbIter,
+    inner: PartIter,
     started: bool,
     upper_bound: Vec<u8>,
+    stats: Option<Arc<TxStats>>,
 }
 
 impl RocksDbIterator {
     #[inline]
     fn next_inner(&mut self) -> Result<Option<Tuple>> {
+        let sample = Sample::start(&self.stats);
         if self.started {
-            self.inner.next()
+            self.inner.next()?
         } else {
             self.started = true;
         }
-        Ok(match self.inner.pair()? {
+        let ret = match self.inner.pair()? {
             None => None,
             Some((k_slice, v_slice)) => {
                 if self.upper_bound.as_slice() <= k_slice {
@@ -306,7 +908,9 @@ impl RocksDbIterator {
                     Some(decode_tuple_from_kv(k_slice, v_slice, None))
                 }
             }
-        })
+        };
+        sample.finish(0, ret.is_some() as u64);
+        Ok(ret)
     }
 }
 
@@ -319,18 +923,22 @@ impl Iterator for RocksDbIterator {
 }
 
 pub(crate) struct RocksDbSkipIterator {
-    inner: DbIter,
+    inner: PartIter,
     upper_bound: Vec<u8>,
     next_bound: Vec<u8>,
     valid_at: ValidityTs,
+    stats: Option<Arc<TxStats>>,
 }
 
 impl RocksDbSkipIterator {
     #[inline]
     fn next_inner(&mut self) -> Result<Option<Tuple>> {
         loop {
+            let sample = Sample::start(&self.stats);
             self.inner.seek(&self.next_bound);
-            match self.inner.pair()? {
+            let pair = self.inner.pair()?;
+            sample.finish(1, pair.is_some() as u64);
+            match pair {
                 None => return Ok(None),
                 Some((k_slice, v_slice)) => {
                     if self.upper_bound.as_slice() <= k_slice {
@@ -358,20 +966,22 @@ impl Iterator for RocksDbSkipIterator {
 }
 
 pub(crate) struct RocksDbIteratorRaw {
-    inner: DbIter,
+    inner: PartIter,
     started: bool,
     upper_bound: Vec<u8>,
+    stats: Option<Arc<TxStats>>,
 }
 
 impl RocksDbIteratorRaw {
     #[inline]
     fn next_inner(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let sample = Sample::start(&self.stats);
         if self.started {
-            self.inner.next()
+            self.inner.next()?
         } else {
             self.started = true;
         }
-        Ok(match self.inner.pair()? {
+        let ret = match self.inner.pair()? {
             None => None,
             Some((k_slice, v_slice)) => {
                 if self.upper_bound.as_slice() <= k_slice {
@@ -381,7 +991,9 @@ impl RocksDbIteratorRaw {
                     Some((k_slice.to_vec(), v_slice.to_vec()))
                 }
             }
-        })
+        };
+        sample.finish(0, ret.is_some() as u64);
+        Ok(ret)
     }
 }
 
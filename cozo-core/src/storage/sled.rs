@@ -10,14 +10,16 @@ use std::cmp::Ordering;
 use std::iter;
 use std::iter::Fuse;
 use std::path::Path;
+use std::sync::Arc;
 
 use itertools::Itertools;
-use miette::{miette, IntoDiagnostic, Result};
+use miette::{bail, miette, IntoDiagnostic, Result};
 use sled::{Batch, Config, Db, IVec, Iter, Mode};
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::decode_tuple_from_kv;
+use crate::storage::lock::{lock_db, ProcessLock};
 use crate::storage::{Storage, StoreTx};
 use crate::utils::{swap_option_result, TempCollector};
 
@@ -25,9 +27,37 @@ use crate::utils::{swap_option_result, TempCollector};
 /// You should use [`new_cozo_rocksdb`](crate::new_cozo_rocksdb) or
 /// [`new_cozo_sqlite`](crate::new_cozo_sqlite) instead.
 pub fn new_cozo_sled(path: impl AsRef<Path>) -> Result<crate::Db<SledStorage>> {
+    open_cozo_sled(path, false)
+}
+
+/// Open a Sled database. If `read_only` is true, the database must exist already, the
+/// lock guarding against other processes is not taken, and all write transactions are
+/// refused. Sled still locks the database itself, so it cannot be opened while another
+/// process has it open.
+pub(crate) fn open_cozo_sled(
+    path: impl AsRef<Path>,
+    read_only: bool,
+) -> Result<crate::Db<SledStorage>> {
+    if read_only {
+        if !path.as_ref().join("db").exists() {
+            bail!(
+                "no database at {} to open read-only",
+                path.as_ref().display()
+            )
+        }
+    } else {
+        std::fs::create_dir_all(path.as_ref()).into_diagnostic()?;
+    }
+    let process_lock = lock_db(path.as_ref(), path.as_ref().join("cozo"), read_only)?;
     let db = sled::open(path).into_diagnostic()?;
-    let ret = crate::Db::new(SledStorage { db })?;
+    let ret = crate::Db::new(SledStorage {
+        db,
+        _process_lock: process_lock,
+    })?;
 
+    if read_only {
+        ret.set_read_only();
+    }
     ret.initialize()?;
     Ok(ret)
 }
@@ -36,6 +66,7 @@ pub fn new_cozo_sled(path: impl AsRef<Path>) -> Result<crate::Db<SledStorage>> {
 #[derive(Clone)]
 pub struct SledStorage {
     db: Db,
+    _process_lock: Option<Arc<ProcessLock>>,
 }
 
 const PUT_MARKER: u8 = 1;
@@ -59,6 +90,11 @@ impl Storage<'_> for SledStorage {
         Ok(())
     }
 
+    fn flush(&self) -> Result<()> {
+        self.db.flush().into_diagnostic()?;
+        Ok(())
+    }
+
     fn batch_put<'a>(
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
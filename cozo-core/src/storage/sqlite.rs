@@ -18,6 +18,7 @@ use sqlite::{ConnectionThreadSafe, State, Statement};
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::storage::lock::{lock_db, ProcessLock};
 use crate::storage::{Storage, StoreTx};
 use crate::utils::swap_option_result;
 
@@ -27,6 +28,7 @@ pub struct SqliteStorage {
     lock: Arc<ShardedLock<()>>,
     name: PathBuf,
     pool: Arc<Mutex<Vec<ConnectionThreadSafe>>>,
+    _process_lock: Option<Arc<ProcessLock>>,
 }
 
 /// Create a sqlite backed database.
@@ -35,26 +37,52 @@ pub struct SqliteStorage {
 /// You must provide a disk-based path: `:memory:` is not OK.
 /// If you want a pure memory storage, use [`new_cozo_mem`](crate::new_cozo_mem).
 pub fn new_cozo_sqlite(path: impl AsRef<Path>) -> Result<crate::Db<SqliteStorage>> {
+    open_cozo_sqlite(path, false)
+}
+
+/// Open a sqlite backed database. If `read_only` is true, the lock guarding against other
+/// processes is not taken, and all write transactions are refused.
+pub(crate) fn open_cozo_sqlite(
+    path: impl AsRef<Path>,
+    read_only: bool,
+) -> Result<crate::Db<SqliteStorage>> {
     if path.as_ref().to_str() == Some("") {
         bail!("empty path for sqlite storage")
     }
+    let process_lock = lock_db(path.as_ref(), PathBuf::from(path.as_ref()), read_only)?;
     let conn = Connection::open_thread_safe(&path).into_diagnostic()?;
-    let query = r#"
-        create table if not exists cozo
-        (
-            k BLOB primary key,
-            v BLOB
-        );
-    "#;
-    let mut statement = conn.prepare(query).unwrap();
-    while statement.next().into_diagnostic()? != State::Done {}
+    if read_only {
+        // the file is left as it is, another process may be writing it
+        let query = "select 1 from sqlite_master where type = 'table' and name = 'cozo'";
+        let mut statement = conn.prepare(query).into_diagnostic()?;
+        if statement.next().into_diagnostic()? != State::Row {
+            bail!(
+                "no database at {} to open read-only",
+                path.as_ref().display()
+            )
+        }
+    } else {
+        let query = r#"
+            create table if not exists cozo
+            (
+                k BLOB primary key,
+                v BLOB
+            );
+        "#;
+        let mut statement = conn.prepare(query).unwrap();
+        while statement.next().into_diagnostic()? != State::Done {}
+    }
 
     let ret = crate::Db::new(SqliteStorage {
         lock: Default::default(),
         name: PathBuf::from(path.as_ref()),
         pool: Default::default(),
+        _process_lock: process_lock,
     })?;
 
+    if read_only {
+        ret.set_read_only();
+    }
     ret.initialize()?;
     Ok(ret)
 }
@@ -111,6 +139,13 @@ impl<'s> Storage<'s> for SqliteStorage {
         Ok(())
     }
 
+    fn flush(&'_ self) -> Result<()> {
+        // closing the pooled connections checkpoints the write-ahead log
+        let mut pool = self.pool.lock().unwrap();
+        while pool.pop().is_some() {}
+        Ok(())
+    }
+
     fn storage_kind(&self) -> &'static str {
         "sqlite"
     }
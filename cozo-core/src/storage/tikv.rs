@@ -6,24 +6,40 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::iter;
+//! Storage engine backed by a TiKV cluster, through its transactional API.
+//!
+//! Each Cozo transaction is one TiKV transaction, optimistic or pessimistic as chosen with
+//! [new_cozo_tikv], reading the snapshot at the timestamp it gets from the placement driver
+//! when it starts. Read transactions are started read-only: they take no locks, and their
+//! commit sends nothing. Reads `for_update` lock the key at once in pessimistic mode, and make
+//! the commit fail if the key was written meanwhile in optimistic mode. Writes are buffered
+//! by the client until the commit, which is the two-phase commit of TiKV. A write
+//! transaction dropped without being committed is rolled back, releasing its locks.
+//!
+//! Range scans are pushed down to TiKV in batches of [BATCH_SIZE] pairs, each batch one
+//! request to the regions holding the range. Counting rows only fetches keys, looking up
+//! several keys is one request, and skip scans for time travel seek past the versions of
+//! each key that are not needed instead of fetching them.
+
+use std::collections::HashMap;
 use std::ops::Bound::{Excluded, Included};
 use std::sync::{Arc, Mutex};
 
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use miette::{miette, IntoDiagnostic, Result};
-use tikv_client::{RawClient, Transaction, TransactionClient};
+use miette::{IntoDiagnostic, Result};
+use tikv_client::{CheckLevel, Transaction, TransactionClient, TransactionOptions};
 use tokio::runtime::Runtime;
 
-use crate::data::tuple::Tuple;
+use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
-use crate::runtime::relation::decode_tuple_from_kv;
+use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
 use crate::storage::{Storage, StoreTx};
 use crate::utils::{swap_option_result, TempCollector};
 use crate::Db;
 
-/// Connect to a Storage engine backed by TiKV.
+/// Connect to a Storage engine backed by TiKV, with the endpoints of its placement drivers.
+/// Transactions are optimistic if `optimistic` is true, and pessimistic otherwise.
 /// Experimental and very slow.
 pub fn new_cozo_tikv(pd_endpoints: Vec<String>, optimistic: bool) -> Result<Db<TiKvStorage>> {
     let client = RT
@@ -61,16 +77,22 @@ impl Storage<'_> for TiKvStorage {
         "tikv"
     }
 
-    fn transact(&self, _write: bool) -> Result<Self::Tx> {
-        let tx = if self.optimistic {
-            RT.block_on(self.client.begin_optimistic())
-                .into_diagnostic()?
+    fn transact(&self, write: bool) -> Result<Self::Tx> {
+        let options = if self.optimistic {
+            TransactionOptions::new_optimistic()
         } else {
-            RT.block_on(self.client.begin_pessimistic())
-                .into_diagnostic()?
+            TransactionOptions::new_pessimistic()
         };
+        // dropped transactions are rolled back by `TiKvTx`, and read-only ones need no rollback
+        let options = options.drop_check(CheckLevel::None);
+        let options = if write { options } else { options.read_only() };
+        let tx = RT
+            .block_on(self.client.begin_with_options(options))
+            .into_diagnostic()?;
         Ok(TiKvTx {
             tx: Arc::new(Mutex::new(tx)),
+            write,
+            committed: false,
         })
     }
 
@@ -94,6 +116,17 @@ impl Storage<'_> for TiKvStorage {
 
 pub struct TiKvTx {
     tx: Arc<Mutex<Transaction>>,
+    write: bool,
+    committed: bool,
+}
+
+impl Drop for TiKvTx {
+    fn drop(&mut self) {
+        if self.write && !self.committed {
+            // the locks left behind expire with their TTL if this fails
+            let _ = RT.block_on(self.tx.lock().unwrap().rollback());
+        }
+    }
 }
 
 impl<'s> StoreTx<'s> for TiKvTx {
@@ -107,6 +140,24 @@ impl<'s> StoreTx<'s> for TiKvTx {
         }
     }
 
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        let keys_owned = keys.to_vec();
+        let mut tx = self.tx.lock().unwrap();
+        let found: HashMap<Vec<u8>, Vec<u8>> = if for_update {
+            RT.block_on(tx.batch_get_for_update(keys_owned))
+                .into_diagnostic()?
+                .into_iter()
+                .map(|pair| (pair.0.into(), pair.1))
+                .collect()
+        } else {
+            RT.block_on(tx.batch_get(keys_owned))
+                .into_diagnostic()?
+                .map(|pair| (pair.0.into(), pair.1))
+                .collect()
+        };
+        Ok(keys.iter().map(|k| found.get(k).cloned()).collect())
+    }
+
     fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
         self.par_put(key, val)
     }
@@ -155,6 +206,7 @@ impl<'s> StoreTx<'s> for TiKvTx {
     fn commit(&mut self) -> Result<()> {
         RT.block_on(self.tx.lock().unwrap().commit())
             .into_diagnostic()?;
+        self.committed = true;
         Ok(())
     }
 
@@ -171,13 +223,16 @@ impl<'s> StoreTx<'s> for TiKvTx {
 
     fn range_skip_scan_tuple<'a>(
         &'a self,
-        _lower: &[u8],
-        _upper: &[u8],
-        _valid_at: ValidityTs,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
     ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
-        Box::new(iter::once(Err(miette!(
-            "TiKV backend does not support time travelling."
-        ))))
+        Box::new(SkipScanner {
+            tx: self.tx.clone(),
+            valid_at,
+            next_bound: lower.to_vec(),
+            upper: upper.to_vec(),
+        })
     }
 
     fn range_scan<'a>(
@@ -195,7 +250,18 @@ impl<'s> StoreTx<'s> for TiKvTx {
     where
         's: 'a,
     {
-        Ok(BatchScannerRaw::new(self.tx.clone(), lower, upper).count())
+        let mut count = 0;
+        let mut lower = Included(lower.to_vec());
+        loop {
+            let mut tx = self.tx.lock().unwrap();
+            let fut = tx.scan_keys((lower, Excluded(upper.to_vec())), BATCH_SIZE);
+            let keys = RT.block_on(fut).into_diagnostic()?.collect_vec();
+            count += keys.len();
+            if keys.len() < BATCH_SIZE as usize {
+                return Ok(count);
+            }
+            lower = Excluded(keys.into_iter().last().unwrap().into());
+        }
     }
 
     fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
@@ -226,6 +292,7 @@ impl BatchScannerRaw {
     }
 }
 
+/// The number of pairs fetched by each request of range scans
 const BATCH_SIZE: u32 = 100;
 
 impl BatchScannerRaw {
@@ -295,6 +362,50 @@ impl Iterator for BatchScannerRaw {
     }
 }
 
+/// Seeks the version of each key valid at a time, one request for each seek
+struct SkipScanner {
+    tx: Arc<Mutex<Transaction>>,
+    valid_at: ValidityTs,
+    next_bound: Vec<u8>,
+    upper: Vec<u8>,
+}
+
+impl SkipScanner {
+    fn next_inner(&mut self) -> Result<Option<Tuple>> {
+        loop {
+            let candidate = {
+                let mut tx = self.tx.lock().unwrap();
+                let fut = tx.scan(
+                    (
+                        Included(self.next_bound.clone()),
+                        Excluded(self.upper.clone()),
+                    ),
+                    1,
+                );
+                RT.block_on(fut).into_diagnostic()?.next()
+            };
+            let (k, v) = match candidate {
+                None => return Ok(None),
+                Some(pair) => (Vec::<u8>::from(pair.0), pair.1),
+            };
+            let (ret, next_bound) = check_key_for_validity(&k, self.valid_at, None);
+            self.next_bound = next_bound;
+            if let Some(mut tup) = ret {
+                extend_tuple_from_v(&mut tup, &v);
+                return Ok(Some(tup));
+            }
+        }
+    }
+}
+
+impl Iterator for SkipScanner {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        swap_option_result(self.next_inner())
+    }
+}
+
 struct BatchScanner {
     raw: BatchScannerRaw,
 }
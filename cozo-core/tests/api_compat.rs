@@ -0,0 +1,185 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Compatibility tests for `cozo::api`. Only items of that module are used here, and
+//! these tests must keep compiling and passing unchanged until the next major release.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde_json::json;
+
+use cozo::api::{ColumnInfo, DbInstance, Error, FromValue, QueryBuilder, Result, Row, Rows, Value};
+
+#[test]
+fn signatures() {
+    let _: fn(&str, PathBuf, &str) -> Result<DbInstance> = DbInstance::open;
+    let _: fn() -> Result<DbInstance> = DbInstance::open_mem;
+    let _: for<'a> fn(&'a DbInstance, &'a str) -> QueryBuilder<'a> = DbInstance::query;
+    let _: fn(&DbInstance, &str) -> Result<Rows> = DbInstance::run;
+    let _: fn(&DbInstance, &str) -> Result<Rows> = DbInstance::run_readonly;
+    let _: fn(&DbInstance, &[&str]) -> Result<BTreeMap<String, Rows>> =
+        DbInstance::export_relations;
+    let _: fn(&DbInstance, BTreeMap<String, Rows>) -> Result<()> = DbInstance::import_relations;
+    let _: fn(&DbInstance, PathBuf) -> Result<()> = DbInstance::backup;
+    let _: fn(&DbInstance, PathBuf) -> Result<()> = DbInstance::restore;
+    let _: fn(&DbInstance) -> Result<()> = DbInstance::close;
+    let _: fn(QueryBuilder<'static>, &str, i64) -> QueryBuilder<'static> = QueryBuilder::param;
+    let _: fn(QueryBuilder<'static>) -> QueryBuilder<'static> = QueryBuilder::immutable;
+    let _: fn(QueryBuilder<'static>) -> Result<Rows> = QueryBuilder::run;
+    let _: fn(QueryBuilder<'static>) -> Result<Rows> = QueryBuilder::run_readonly;
+    let _: fn(Vec<String>, Vec<Vec<Value>>) -> Rows = Rows::new;
+    let _: fn(&Rows) -> &[String] = Rows::headers;
+    let _: fn(&Rows) -> &[ColumnInfo] = Rows::columns;
+    let _: fn(&Rows) -> usize = Rows::len;
+    let _: fn(&Rows) -> bool = Rows::is_truncated;
+    let _: fn(&Rows) -> Option<&Rows> = Rows::next_result;
+    let _: fn(&Row) -> &[Value] = Row::values;
+    let _: fn(&Row, usize) -> Option<&Value> = Row::get;
+    let _: fn(&Row, &str) -> Option<&Value> = Row::by_name;
+    let _: fn(&Row, &str) -> Result<i64> = Row::get_as::<i64>;
+    let _: fn(&Error) -> String = Error::message;
+    let _: fn(&Error) -> Option<String> = Error::code;
+    let _: fn(&Value) -> Option<i64> = <i64 as FromValue>::from_value;
+}
+
+#[test]
+fn queries_with_params() {
+    let db = DbInstance::open_mem().unwrap();
+    let rows = db
+        .query("?[x, y, z] := x = $a, y = $b, z = $c")
+        .param("a", 1)
+        .param("b", "two")
+        .param("c", None::<i64>)
+        .immutable()
+        .run()
+        .unwrap();
+    assert_eq!(rows.headers(), ["x", "y", "z"]);
+    assert_eq!(rows.len(), 1);
+    let row = &rows[0];
+    assert_eq!(row[0], Value::Int(1));
+    assert_eq!(row.by_name("y"), Some(&Value::Str("two".to_string())));
+    assert!(row.get(2).unwrap().is_null());
+    assert_eq!(row.get(3), None);
+
+    let rows = db
+        .query("?[s] := s = $a + $b")
+        .params([("a", 1.5), ("b", 2.0)])
+        .run()
+        .unwrap();
+    assert_eq!(rows[0].get_as::<f64>("s").unwrap(), 3.5);
+}
+
+#[test]
+fn typed_reads() {
+    let db = DbInstance::open_mem().unwrap();
+    let rows = db
+        .run("?[i, f, s, b, l, n] <- [[1, 2.5, 'x', true, [1, 2], null]]")
+        .unwrap();
+    let row = rows.iter().next().unwrap();
+    assert_eq!(row.get_as::<i64>("i").unwrap(), 1);
+    assert_eq!(row.get_as::<f64>("i").unwrap(), 1.0);
+    assert_eq!(row.get_as::<f64>("f").unwrap(), 2.5);
+    assert_eq!(row.get_as::<String>("s").unwrap(), "x");
+    assert!(row.get_as::<bool>("b").unwrap());
+    assert_eq!(
+        row.get_as::<Vec<Value>>("l").unwrap(),
+        vec![Value::Int(1), Value::Int(2)]
+    );
+    assert_eq!(row.get_as::<Option<i64>>("n").unwrap(), None);
+    assert_eq!(row.get_as::<Option<i64>>("i").unwrap(), Some(1));
+
+    let err = row.get_as::<i64>("s").unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("api::type_mismatch"));
+    let err = row.get_as::<i64>("nope").unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("api::no_such_column"));
+}
+
+#[test]
+fn values_round_trip() {
+    let db = DbInstance::open_mem().unwrap();
+    let values = vec![
+        Value::Null,
+        Value::Bool(false),
+        Value::Int(-3),
+        Value::Float(0.25),
+        Value::Str("s".to_string()),
+        Value::Bytes(vec![1, 2, 3]),
+        Value::Uuid(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef),
+        Value::List(vec![Value::Int(1), Value::Str("a".to_string())]),
+        Value::Json(json!({"a": [1, 2]})),
+    ];
+    for value in values {
+        let rows = db
+            .query("?[v] := v = $v")
+            .param("v", value.clone())
+            .run()
+            .unwrap();
+        assert_eq!(rows[0][0], value);
+    }
+    let rows = db.run("?[v] := v = vec([1, 2])").unwrap();
+    assert_eq!(rows[0][0], Value::Vector(vec![1.0, 2.0]));
+}
+
+#[test]
+fn errors() {
+    let db = DbInstance::open_mem().unwrap();
+    let err = db.run("?[x] := ").unwrap_err();
+    assert_eq!(err.code().as_deref(), Some("parser::pest"));
+    assert!(!err.message().is_empty());
+    let _: &dyn std::error::Error = &err;
+
+    db.run(":create kv {k: Int => v: String}").unwrap();
+    assert!(db
+        .query("?[k, v] <- [[1, 'a']] :put kv {k => v}")
+        .immutable()
+        .run()
+        .is_err());
+}
+
+#[test]
+fn export_and_import() {
+    let db = DbInstance::open_mem().unwrap();
+    db.run(":create kv {k: Int => v: String}").unwrap();
+    db.run("?[k, v] <- [[1, 'a'], [2, 'b']] :put kv {k => v}")
+        .unwrap();
+    let exported = db.export_relations(&["kv"]).unwrap();
+    let kv = &exported["kv"];
+    assert_eq!(kv.headers(), ["k", "v"]);
+    assert_eq!(kv.len(), 2);
+
+    let other = DbInstance::open_mem().unwrap();
+    other.run(":create kv {k: Int => v: String}").unwrap();
+    let mut data = BTreeMap::new();
+    data.insert(
+        "kv".to_string(),
+        Rows::new(
+            vec!["k".to_string(), "v".to_string()],
+            vec![vec![Value::Int(3), Value::from("c")]],
+        ),
+    );
+    other.import_relations(data).unwrap();
+    let rows = other.run("?[v] := *kv{k: 3, v}").unwrap();
+    assert_eq!(rows[0].get_as::<String>("v").unwrap(), "c");
+    other.close().unwrap();
+}
+
+#[test]
+fn several_results() {
+    let db = DbInstance::open_mem().unwrap();
+    let rows = db.run("{?[a] <- [[1]]} {?[b] <- [[2]]}").unwrap();
+    let mut headers = vec![rows.headers().to_vec()];
+    let mut cur = rows.next_result();
+    while let Some(rows) = cur {
+        headers.push(rows.headers().to_vec());
+        cur = rows.next_result();
+    }
+    assert!(headers.contains(&vec!["b".to_string()]));
+    assert!(!rows.is_truncated());
+    assert_eq!(rows.iter().count(), rows.len());
+}
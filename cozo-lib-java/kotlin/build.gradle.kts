@@ -0,0 +1,21 @@
+plugins {
+    kotlin("jvm") version "1.9.23"
+    `java-library`
+}
+
+group = "org.cozodb"
+version = "0.7.6"
+
+repositories {
+    mavenCentral()
+}
+
+dependencies {
+    // Android ships its own `org.json`, exclude this one there
+    implementation("org.json:json:20240303")
+    api("org.jetbrains.kotlinx:kotlinx-coroutines-core:1.8.0")
+}
+
+kotlin {
+    jvmToolchain(11)
+}
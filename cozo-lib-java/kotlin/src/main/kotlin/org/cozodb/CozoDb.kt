@@ -0,0 +1,158 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+package org.cozodb
+
+import java.io.Closeable
+import kotlinx.coroutines.Dispatchers
+import kotlinx.coroutines.withContext
+import org.json.JSONArray
+import org.json.JSONObject
+
+/** A value passed to or returned by the database, with its type */
+sealed class CozoValue {
+    object Null : CozoValue()
+    data class Bool(val value: Boolean) : CozoValue()
+    data class Int(val value: Long) : CozoValue()
+    data class Double(val value: kotlin.Double) : CozoValue()
+    data class Str(val value: String) : CozoValue()
+    data class Array(val values: List<CozoValue>) : CozoValue()
+    data class Object(val fields: Map<String, CozoValue>) : CozoValue()
+
+    val boolValue: Boolean? get() = (this as? Bool)?.value
+    val longValue: Long? get() = (this as? Int)?.value
+    /** Integers are converted to doubles */
+    val doubleValue: kotlin.Double?
+        get() = when (this) {
+            is Int -> value.toDouble()
+            is Double -> value
+            else -> null
+        }
+    val stringValue: String? get() = (this as? Str)?.value
+    val arrayValue: List<CozoValue>? get() = (this as? Array)?.values
+    val isNull: Boolean get() = this == Null
+
+    /** The value as understood by `org.json` */
+    fun toJson(): Any = when (this) {
+        Null -> JSONObject.NULL
+        is Bool -> value
+        is Int -> value
+        is Double -> value
+        is Str -> value
+        is Array -> JSONArray(values.map { it.toJson() })
+        is Object -> JSONObject(fields.mapValues { it.value.toJson() })
+    }
+
+    companion object {
+        fun fromJson(json: Any?): CozoValue = when (json) {
+            null, JSONObject.NULL -> Null
+            is Boolean -> Bool(json)
+            // integers stay exact, other numbers become doubles
+            is kotlin.Int -> Int(json.toLong())
+            is Long -> Int(json)
+            is java.math.BigInteger -> Int(json.toLong())
+            is Number -> Double(json.toDouble())
+            is String -> Str(json)
+            is JSONArray -> Array((0 until json.length()).map { fromJson(json.get(it)) })
+            is JSONObject -> Object(json.keySet().associateWith { fromJson(json.get(it)) })
+            else -> Str(json.toString())
+        }
+    }
+}
+
+/** A failed query or operation, with the error as reported by the database */
+class CozoException(val error: JSONObject) :
+    Exception(error.optString("display", error.optString("message", error.toString())))
+
+/** The rows returned by a query */
+class NamedRows(val headers: List<String>, val rows: List<List<CozoValue>>) {
+    private val positions = headers.withIndex().associate { it.value to it.index }
+
+    /** The value of the column `name` in row `idx`, if the column exists */
+    fun get(idx: Int, name: String): CozoValue? = positions[name]?.let { rows[idx][it] }
+
+    /** Each row as a map from the name of a column to its value */
+    fun toMaps(): List<Map<String, CozoValue>> = rows.map { row -> headers.zip(row).toMap() }
+}
+
+/**
+ * A database, opened with the engine `mem`, `sqlite` or `rocksdb` (when it is compiled in).
+ *
+ * Every method blocks the thread it is called on. From the main thread of an Android app,
+ * use the suspending versions ending in `Async`, which run on [Dispatchers.IO].
+ */
+class CozoDb(engine: String = "mem", path: String = "", options: String = "{}") : Closeable {
+    private val id: Int = CozoJavaBridge.openDb(engine, path, options)
+
+    init {
+        if (id < 0) {
+            throw CozoException(JSONObject().put("message", "cannot open the database"))
+        }
+    }
+
+    private fun check(res: String): JSONObject {
+        val json = JSONObject(res)
+        if (!json.optBoolean("ok")) {
+            throw CozoException(json)
+        }
+        return json
+    }
+
+    fun run(script: String, params: Map<String, CozoValue> = emptyMap()): NamedRows {
+        val payload = JSONObject(params.mapValues { it.value.toJson() }).toString()
+        val json = check(CozoJavaBridge.runQuery(id, script, payload))
+        val headers = json.getJSONArray("headers").let { h -> (0 until h.length()).map { h.getString(it) } }
+        val rows = json.getJSONArray("rows").let { rs ->
+            (0 until rs.length()).map { i ->
+                val row = rs.getJSONArray(i)
+                (0 until row.length()).map { CozoValue.fromJson(row.get(it)) }
+            }
+        }
+        return NamedRows(headers, rows)
+    }
+
+    suspend fun runAsync(script: String, params: Map<String, CozoValue> = emptyMap()): NamedRows =
+        withContext(Dispatchers.IO) { run(script, params) }
+
+    /** The rows of the stored relations given, by relation */
+    fun exportRelations(relations: List<String>): JSONObject {
+        val payload = JSONObject().put("relations", JSONArray(relations)).toString()
+        return check(CozoJavaBridge.exportRelations(id, payload)).getJSONObject("data")
+    }
+
+    /**
+     * Import rows into stored relations, in the format returned by [exportRelations].
+     * Triggers are _not_ run for the relations.
+     */
+    fun importRelations(data: JSONObject) {
+        check(CozoJavaBridge.importRelations(id, data.toString()))
+    }
+
+    suspend fun importRelationsAsync(data: JSONObject) =
+        withContext(Dispatchers.IO) { importRelations(data) }
+
+    fun backup(path: String) {
+        check(CozoJavaBridge.backup(id, path))
+    }
+
+    suspend fun backupAsync(path: String) = withContext(Dispatchers.IO) { backup(path) }
+
+    fun restore(path: String) {
+        check(CozoJavaBridge.restore(id, path))
+    }
+
+    /** Import rows into the stored relations given from a backup. Triggers are _not_ run. */
+    fun importRelationsFromBackup(path: String, relations: List<String>) {
+        val payload = JSONObject().put("path", path).put("relations", JSONArray(relations))
+        check(CozoJavaBridge.importFromBackup(id, payload.toString()))
+    }
+
+    override fun close() {
+        CozoJavaBridge.closeDb(id)
+    }
+}
@@ -0,0 +1,43 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+package org.cozodb
+
+/**
+ * The native functions of the `cozo_java` library, the same as those of `CozoJavaBridge.java`.
+ * Results are JSON strings.
+ */
+internal object CozoJavaBridge {
+    init {
+        System.loadLibrary("cozo_java")
+    }
+
+    @JvmStatic
+    external fun openDb(engine: String, path: String, options: String): Int
+
+    @JvmStatic
+    external fun closeDb(id: Int): Boolean
+
+    @JvmStatic
+    external fun runQuery(id: Int, script: String, params: String): String
+
+    @JvmStatic
+    external fun exportRelations(id: Int, rel: String): String
+
+    @JvmStatic
+    external fun importRelations(id: Int, data: String): String
+
+    @JvmStatic
+    external fun backup(id: Int, file: String): String
+
+    @JvmStatic
+    external fun restore(id: Int, file: String): String
+
+    @JvmStatic
+    external fun importFromBackup(id: Int, data: String): String
+}
@@ -190,6 +190,9 @@ fn value2js<'a>(cx: &mut impl Context<'a>, val: &DataValue) -> JsResult<'a, JsVa
             target_l.as_value(cx)
         }
         DataValue::Json(JsonData(j)) => json2js(cx, j)?,
+        DataValue::Mat(_) | DataValue::Sparse(_) => {
+            json2js(cx, &serde_json::Value::from(val.clone()))?
+        }
     })
 }
 
@@ -173,6 +173,9 @@ fn value_to_py(val: DataValue, py: Python<'_>) -> PyObject {
             }
         },
         DataValue::Json(JsonData(j)) => json_to_py(j, py),
+        v @ (DataValue::Mat(_) | DataValue::Sparse(_)) => {
+            json_to_py(serde_json::Value::from(v), py)
+        }
     }
 }
 
@@ -191,11 +194,20 @@ fn rows_to_py_rows(rows: Vec<Vec<DataValue>>, py: Python<'_>) -> PyObject {
 fn named_rows_to_py(named_rows: NamedRows, py: Python<'_>) -> PyObject {
     let rows = rows_to_py_rows(named_rows.rows, py);
     let headers = named_rows.headers.into_py(py);
+    let truncated = named_rows.truncated.into_py(py);
+    let incomplete = named_rows.incomplete.into_py(py);
     let next = match named_rows.next {
         None => py.None(),
         Some(nxt) => named_rows_to_py(*nxt, py),
     };
-    BTreeMap::from([("rows", rows), ("headers", headers), ("next", next)]).into_py(py)
+    BTreeMap::from([
+        ("rows", rows),
+        ("headers", headers),
+        ("next", next),
+        ("truncated", truncated),
+        ("incomplete", incomplete),
+    ])
+    .into_py(py)
 }
 
 #[pyclass]
@@ -208,6 +220,11 @@ struct CozoDbMulTx {
     tx: MultiTransaction,
 }
 
+#[pyclass]
+struct CozoStream {
+    stream: QueryStream,
+}
+
 const DB_CLOSED_MSG: &str = r##"{"ok":false,"message":"database closed"}"##;
 
 #[pymethods]
@@ -253,6 +270,35 @@ impl CozoDbPy {
             Err(PyException::new_err(DB_CLOSED_MSG))
         }
     }
+    pub fn run_script_streaming(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: &PyDict,
+        immutable: bool,
+        buffer: usize,
+    ) -> PyResult<CozoStream> {
+        if let Some(db) = &self.db {
+            let params = convert_params(params)?;
+            let stream = py
+                .allow_threads(|| {
+                    db.run_script_streaming(
+                        query,
+                        params,
+                        if immutable {
+                            ScriptMutability::Immutable
+                        } else {
+                            ScriptMutability::Mutable
+                        },
+                        buffer,
+                    )
+                })
+                .map_err(report2py)?;
+            Ok(CozoStream { stream })
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG))
+        }
+    }
     pub fn register_callback(&self, rel: &str, callback: &PyAny) -> PyResult<u32> {
         if let Some(db) = &self.db {
             let cb: Py<PyAny> = callback.into();
@@ -333,6 +379,58 @@ impl CozoDbPy {
             Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
         }
     }
+    #[cfg(feature = "arrow")]
+    pub fn export_relations_arrow(
+        &self,
+        py: Python<'_>,
+        relations: Vec<String>,
+        format: &str,
+    ) -> PyResult<PyObject> {
+        if let Some(db) = &self.db {
+            let format = format.parse::<ArrowFormat>().map_err(report2py)?;
+            let res = py
+                .allow_threads(|| db.export_relations_arrow(relations.iter(), format))
+                .map_err(report2py)?;
+            let ret = PyDict::new(py);
+            for (k, v) in res {
+                ret.set_item(k, PyBytes::new(py, &v))?;
+            }
+            Ok(ret.into())
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
+        }
+    }
+    #[cfg(feature = "arrow")]
+    pub fn run_script_arrow(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: &PyDict,
+        immutable: bool,
+        format: &str,
+    ) -> PyResult<PyObject> {
+        if let Some(db) = &self.db {
+            let format = format.parse::<ArrowFormat>().map_err(report2py)?;
+            let params = convert_params(params)?;
+            let data = py
+                .allow_threads(|| {
+                    db.run_script(
+                        query,
+                        params,
+                        if immutable {
+                            ScriptMutability::Immutable
+                        } else {
+                            ScriptMutability::Mutable
+                        },
+                    )?
+                    .to_arrow(format)
+                })
+                .map_err(report2py)?;
+            Ok(PyBytes::new(py, &data).into())
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
+        }
+    }
     pub fn import_relations(&self, py: Python<'_>, data: &PyDict) -> PyResult<()> {
         if let Some(db) = &self.db {
             let mut arg = BTreeMap::new();
@@ -417,6 +515,36 @@ impl CozoDbMulTx {
     }
 }
 
+#[pymethods]
+impl CozoStream {
+    #[getter]
+    fn headers(&self) -> Vec<String> {
+        self.stream.headers().to_vec()
+    }
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        match py.allow_threads(|| self.stream.next()) {
+            None => Ok(None),
+            Some(row) => Ok(Some(
+                row.map_err(report2py)?
+                    .into_iter()
+                    .map(|val| value_to_py(val, py))
+                    .collect::<Vec<_>>()
+                    .into_py(py),
+            )),
+        }
+    }
+    /// Take up to `n` rows at once, fewer only when the stream is exhausted
+    pub fn next_chunk(&mut self, py: Python<'_>, n: usize) -> PyResult<PyObject> {
+        let rows = py
+            .allow_threads(|| self.stream.next_chunk(n))
+            .map_err(report2py)?;
+        Ok(rows_to_py_rows(rows, py))
+    }
+}
+
 #[pyfunction]
 fn eval_expressions(
     py: Python<'_>,
@@ -459,6 +587,7 @@ fn variables(py: Python<'_>, query: &str, params: &PyDict) -> PyResult<BTreeSet<
 fn cozo_embedded(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<CozoDbPy>()?;
     m.add_class::<CozoDbMulTx>()?;
+    m.add_class::<CozoStream>()?;
     m.add_function(wrap_pyfunction!(eval_expressions, m)?)?;
     m.add_function(wrap_pyfunction!(variables, m)?)?;
     Ok(())
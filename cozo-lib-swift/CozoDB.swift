@@ -14,6 +14,90 @@ public enum CozoError: Error {
     case query(JSON)
 }
 
+/// A value passed to or returned by the database, with its type
+public enum CozoValue: Equatable {
+    case null
+    case bool(Bool)
+    case int(Int64)
+    case double(Double)
+    case string(String)
+    case array([CozoValue])
+    case object([String: CozoValue])
+
+    public init(json: JSON) {
+        switch json.type {
+        case .bool:
+            self = .bool(json.boolValue)
+        case .number:
+            // integers stay exact, other numbers become doubles
+            if let n = json.number, !CFNumberIsFloatType(n) {
+                self = .int(n.int64Value)
+            } else {
+                self = .double(json.doubleValue)
+            }
+        case .string:
+            self = .string(json.stringValue)
+        case .array:
+            self = .array(json.arrayValue.map(CozoValue.init(json:)))
+        case .dictionary:
+            self = .object(json.dictionaryValue.mapValues(CozoValue.init(json:)))
+        case .null, .unknown:
+            self = .null
+        }
+    }
+
+    public var json: JSON {
+        switch self {
+        case .null:
+            return JSON(NSNull())
+        case .bool(let b):
+            return JSON(b)
+        case .int(let i):
+            return JSON(i)
+        case .double(let d):
+            return JSON(d)
+        case .string(let s):
+            return JSON(s)
+        case .array(let a):
+            return JSON(a.map { $0.json.object })
+        case .object(let o):
+            return JSON(o.mapValues { $0.json.object })
+        }
+    }
+
+    public var boolValue: Bool? {
+        if case .bool(let b) = self { return b }
+        return nil
+    }
+    public var intValue: Int64? {
+        if case .int(let i) = self { return i }
+        return nil
+    }
+    /// Integers are converted to doubles
+    public var doubleValue: Double? {
+        switch self {
+        case .int(let i):
+            return Double(i)
+        case .double(let d):
+            return d
+        default:
+            return nil
+        }
+    }
+    public var stringValue: String? {
+        if case .string(let s) = self { return s }
+        return nil
+    }
+    /// Vectors are returned as arrays of doubles
+    public var arrayValue: [CozoValue]? {
+        if case .array(let a) = self { return a }
+        return nil
+    }
+    public var isNull: Bool {
+        return self == .null
+    }
+}
+
 public class RowHeaders {
     public let headers: [String]
     init(headers: [String]) {
@@ -42,6 +126,12 @@ public struct NamedRow {
             return nil
         }
     }
+    public func value(idx: Int) -> CozoValue {
+        return CozoValue(json: self.fields[idx])
+    }
+    public func value(key: String) -> CozoValue? {
+        return self.get(key: key).map(CozoValue.init(json:))
+    }
 }
 
 extension [NamedRow] {
@@ -98,6 +188,30 @@ public class CozoDB {
     public func run(_ query: String) throws -> [NamedRow] {
         return try self.run(query, stringParams: "")
     }
+    public func run(_ query: String, params: [String: CozoValue]) throws -> [NamedRow] {
+        return try self.run(query, params: JSON(params.mapValues { $0.json.object }))
+    }
+    /// Run a query on `queue`, a global background queue by default,
+    /// and call `completion` with the result there
+    public func run(
+        _ query: String,
+        params: [String: CozoValue] = [:],
+        queue: DispatchQueue = .global(qos: .userInitiated),
+        completion: @escaping (Result<[NamedRow], Error>) -> Void
+    ) {
+        queue.async {
+            completion(Result { try self.run(query, params: params) })
+        }
+    }
+    /// Run a query on a background queue, so that the calling thread is not blocked
+    @available(macOS 10.15, iOS 13.0, *)
+    public func runAsync(_ query: String, params: [String: CozoValue] = [:]) async throws -> [NamedRow] {
+        return try await withCheckedThrowingContinuation { continuation in
+            self.run(query, params: params) { result in
+                continuation.resume(with: result)
+            }
+        }
+    }
     func run(_ query: String, stringParams: String) throws -> [NamedRow] {
         let resStr = self.db.run_script_str(query, stringParams, false).toString()
         let dataFromString = resStr.data(using: .utf8, allowLossyConversion: false)!
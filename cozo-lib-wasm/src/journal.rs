@@ -0,0 +1,96 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The binary format of the batches of changes persisted by JS code.
+//!
+//! A batch is a sequence of records, each a key and a value prefixed by their lengths
+//! as little-endian `u32`. A value length of `u32::MAX` marks a deleted key, and a key
+//! length of `u32::MAX` with nothing following clears all data, which starts a snapshot.
+//! Batches concatenated in the order they were taken replay to the current data.
+
+use std::collections::BTreeMap;
+
+use cozo::MemChanges;
+
+const DELETED: u32 = u32::MAX;
+const CLEAR: u32 = u32::MAX;
+
+fn push_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+pub(crate) fn encode_changes(changes: MemChanges) -> Vec<u8> {
+    let mut out = vec![];
+    for (k, v) in changes {
+        push_bytes(&mut out, &k);
+        match v {
+            Some(v) => push_bytes(&mut out, &v),
+            None => out.extend_from_slice(&DELETED.to_le_bytes()),
+        }
+    }
+    out
+}
+
+pub(crate) fn encode_snapshot(data: BTreeMap<Vec<u8>, Vec<u8>>) -> Vec<u8> {
+    let mut out = CLEAR.to_le_bytes().to_vec();
+    for (k, v) in data {
+        push_bytes(&mut out, &k);
+        push_bytes(&mut out, &v);
+    }
+    out
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn len(&mut self) -> Result<u32, String> {
+        match self.data.split_first_chunk::<4>() {
+            Some((len, rest)) => {
+                self.data = rest;
+                Ok(u32::from_le_bytes(*len))
+            }
+            None => Err("truncated batch of changes".to_string()),
+        }
+    }
+    fn bytes(&mut self, len: u32) -> Result<Vec<u8>, String> {
+        let len = len as usize;
+        if self.data.len() < len {
+            return Err("truncated batch of changes".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Replay concatenated batches into the data they describe
+pub(crate) fn decode_batches(data: &[u8]) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, String> {
+    let mut ret = BTreeMap::new();
+    let mut reader = Reader { data };
+    while !reader.data.is_empty() {
+        let key_len = reader.len()?;
+        if key_len == CLEAR {
+            ret.clear();
+            continue;
+        }
+        let key = reader.bytes(key_len)?;
+        match reader.len()? {
+            DELETED => {
+                ret.remove(&key);
+            }
+            val_len => {
+                let val = reader.bytes(val_len)?;
+                ret.insert(key, val);
+            }
+        }
+    }
+    Ok(ret)
+}
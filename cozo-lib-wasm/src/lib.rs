@@ -10,6 +10,7 @@ use wasm_bindgen::prelude::*;
 
 use cozo::*;
 
+mod journal;
 mod utils;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
@@ -26,6 +27,8 @@ extern "C" {
 #[wasm_bindgen]
 pub struct CozoDb {
     db: DbInstance,
+    /// The same database, if its changes are journaled to be persisted by the caller
+    journaled: Option<Db<MemStorage>>,
 }
 
 #[wasm_bindgen]
@@ -34,7 +37,37 @@ impl CozoDb {
     pub fn new() -> Self {
         utils::set_panic_hook();
         let db = DbInstance::new("mem", "", "").unwrap();
-        Self { db }
+        Self {
+            db,
+            journaled: None,
+        }
+    }
+    /// A database to be persisted by the caller, e.g. in IndexedDB or OPFS: `saved` holds
+    /// the batches returned by `take_changes` and `snapshot` so far, concatenated in the order
+    /// they were taken.
+    pub fn new_journaled(saved: &[u8]) -> Result<CozoDb, JsError> {
+        utils::set_panic_hook();
+        let data = journal::decode_batches(saved).map_err(|err| JsError::new(&err))?;
+        let db = new_cozo_mem_journaled(data).map_err(|err| JsError::new(&err.to_string()))?;
+        Ok(Self {
+            db: DbInstance::Mem(db.clone()),
+            journaled: Some(db),
+        })
+    }
+    /// The changes committed since they were last taken, as a batch to be saved.
+    /// Empty if the database is not journaled or nothing has changed.
+    pub fn take_changes(&self) -> Vec<u8> {
+        match &self.journaled {
+            Some(db) => journal::encode_changes(db.take_changes()),
+            None => vec![],
+        }
+    }
+    /// All the data as a batch that can replace all batches saved before
+    pub fn snapshot(&self) -> Vec<u8> {
+        match &self.journaled {
+            Some(db) => journal::encode_snapshot(db.snapshot()),
+            None => vec![],
+        }
     }
     pub fn run(&self, script: &str, params: &str, immutable: bool) -> String {
         self.db.run_script_str(script, params, immutable)
@@ -45,4 +78,8 @@ impl CozoDb {
     pub fn import_relations(&self, data: &str) -> String {
         self.db.import_relations_str(data)
     }
+    pub fn set_result_limits(&self, max_rows: Option<u32>, max_bytes: Option<u32>) {
+        self.db
+            .set_result_limits(max_rows.map(|n| n as usize), max_bytes.map(|n| n as usize))
+    }
 }
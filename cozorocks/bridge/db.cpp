@@ -124,11 +124,23 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
 
     db->db_path = convert_vec_to_string(opts.db_path);
 
+    // all column families must be opened, the default one is missing from a new database
+    std::vector<string> cf_names;
+    auto list_status = DB::ListColumnFamilies(options, db->db_path, &cf_names);
+    if (!list_status.ok() || cf_names.empty()) {
+        cf_names = {kDefaultColumnFamilyName};
+    }
+    std::vector<ColumnFamilyDescriptor> cf_descs;
+    for (auto &name: cf_names) {
+        cf_descs.emplace_back(name, ColumnFamilyOptions(options));
+    }
+
     TransactionDB *txn_db = nullptr;
     write_status(
-            TransactionDB::Open(options, TransactionDBOptions(), db->db_path, &txn_db),
+            TransactionDB::Open(options, TransactionDBOptions(), db->db_path, cf_descs, &db->cf_handles, &txn_db),
             status);
     db->db.reset(txn_db);
+    db->cf_dropped.assign(db->cf_handles.size(), false);
     db->destroy_on_exit = opts.destroy_on_exit;
 
 
@@ -136,6 +148,12 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
 }
 
 RocksDbBridge::~RocksDbBridge() {
+    if (db != nullptr) {
+        for (auto handle: cf_handles) {
+            db->DestroyColumnFamilyHandle(handle);
+        }
+    }
+    cf_handles.clear();
     if (destroy_on_exit && (db != nullptr)) {
         cerr << "destroying database on exit: " << db_path << endl;
         auto status = db->Close();
@@ -150,3 +168,93 @@ RocksDbBridge::~RocksDbBridge() {
         }
     }
 }
+
+static shared_ptr<Cache> block_cache_of(DB *db) {
+    auto options = db->GetOptions(db->DefaultColumnFamily());
+    auto table_options = options.table_factory->GetOptions<BlockBasedTableOptions>();
+    if (table_options == nullptr) {
+        return nullptr;
+    }
+    return table_options->block_cache;
+}
+
+void RocksDbBridge::set_block_cache_capacity(size_t capacity, RocksDbStatus &status) const {
+    auto cache = block_cache_of(&*db);
+    if (cache == nullptr) {
+        write_status(Status::NotSupported("the database has no block cache"), status);
+        return;
+    }
+    cache->SetCapacity(capacity);
+}
+
+RocksDbConfig RocksDbBridge::get_config() const {
+    auto options = db->GetOptions(db->DefaultColumnFamily());
+    RocksDbConfig ret{};
+    auto cache = block_cache_of(&*db);
+    ret.block_cache_capacity = cache == nullptr ? 0 : cache->GetCapacity();
+    ret.write_buffer_size = options.write_buffer_size;
+    ret.max_write_buffer_number = options.max_write_buffer_number;
+    ret.max_background_jobs = options.max_background_jobs;
+    ret.level0_file_num_compaction_trigger = options.level0_file_num_compaction_trigger;
+    ret.disable_auto_compactions = options.disable_auto_compactions;
+    switch (options.compaction_style) {
+        case kCompactionStyleLevel:
+            ret.compaction_style = "level";
+            break;
+        case kCompactionStyleUniversal:
+            ret.compaction_style = "universal";
+            break;
+        case kCompactionStyleFIFO:
+            ret.compaction_style = "fifo";
+            break;
+        default:
+            ret.compaction_style = "none";
+    }
+    return ret;
+}
+
+ColumnFamilyHandle *RocksDbBridge::get_cf(size_t idx) const {
+    std::shared_lock lock(cf_mutex);
+    if (idx >= cf_handles.size()) {
+        return nullptr;
+    }
+    return cf_handles[idx];
+}
+
+rust::Vec<rust::String> RocksDbBridge::column_families() const {
+    std::shared_lock lock(cf_mutex);
+    rust::Vec<rust::String> ret;
+    for (size_t i = 0; i < cf_handles.size(); ++i) {
+        ret.push_back(cf_dropped[i] ? rust::String() : rust::String(cf_handles[i]->GetName()));
+    }
+    return ret;
+}
+
+size_t RocksDbBridge::create_column_family(rust::Str name, RocksDbStatus &status) const {
+    // new column families are tuned as the default one
+    ColumnFamilyOptions options(db->GetOptions(db->DefaultColumnFamily()));
+    ColumnFamilyHandle *handle = nullptr;
+    auto s = db->CreateColumnFamily(options, string(name), &handle);
+    write_status(s, status);
+    if (!s.ok()) {
+        return 0;
+    }
+    std::unique_lock lock(cf_mutex);
+    cf_handles.push_back(handle);
+    cf_dropped.push_back(false);
+    return cf_handles.size() - 1;
+}
+
+void RocksDbBridge::drop_column_family(size_t idx, RocksDbStatus &status) const {
+    auto handle = get_cf(idx);
+    if (handle == nullptr || handle->GetID() == 0) {
+        write_status(Status::InvalidArgument("cannot drop column family " + std::to_string(idx)), status);
+        return;
+    }
+    auto s = db->DropColumnFamily(handle);
+    write_status(s, status);
+    if (s.ok()) {
+        std::unique_lock lock(cf_mutex);
+        cf_dropped[idx] = true;
+    }
+}
@@ -8,6 +8,7 @@
 #define COZOROCKS_DB_H
 
 #include <utility>
+#include <shared_mutex>
 
 #include "iostream"
 #include "common.h"
@@ -46,6 +47,11 @@ static WriteOptions DEFAULT_WRITE_OPTIONS = WriteOptions();
 
 struct RocksDbBridge {
     unique_ptr<TransactionDB> db;
+    // column families by index, the default one included. Dropped ones are kept until
+    // the database is closed, as iterators may still be reading them.
+    mutable vector<ColumnFamilyHandle *> cf_handles;
+    mutable vector<bool> cf_dropped;
+    mutable std::shared_mutex cf_mutex;
 
     bool destroy_on_exit;
     string db_path;
@@ -75,7 +81,7 @@ struct RocksDbBridge {
 
 
     [[nodiscard]] inline unique_ptr<TxBridge> transact() const {
-        auto ret = make_unique<TxBridge>(&*this->db, db->DefaultColumnFamily());
+        auto ret = make_unique<TxBridge>(&*this->db, db->DefaultColumnFamily(), this);
         return ret;
     }
 
@@ -110,6 +116,42 @@ struct RocksDbBridge {
         write_status(s, status);
     }
 
+    inline void set_option(rust::Str name, rust::Str value, bool db_wide, RocksDbStatus &status) const {
+        std::unordered_map<string, string> opts{{string(name), string(value)}};
+        if (db_wide) {
+            write_status(db->SetDBOptions(opts), status);
+        } else {
+            write_status(db->SetOptions(db->DefaultColumnFamily(), opts), status);
+        }
+    }
+
+    void set_block_cache_capacity(size_t capacity, RocksDbStatus &status) const;
+
+    [[nodiscard]] ColumnFamilyHandle *get_cf(size_t idx) const;
+
+    [[nodiscard]] rust::Vec<rust::String> column_families() const;
+
+    size_t create_column_family(rust::Str name, RocksDbStatus &status) const;
+
+    void drop_column_family(size_t idx, RocksDbStatus &status) const;
+
+    [[nodiscard]] RocksDbConfig get_config() const;
+
+    void flush(RocksDbStatus &status) const {
+        FlushOptions options;
+        options.wait = true;
+        auto s = db->Flush(options);
+        if (s.ok()) {
+            s = db->FlushWAL(true);
+        }
+        write_status(s, status);
+    }
+
+    void verify_checksum(RocksDbStatus &status) const {
+        auto s = db->VerifyChecksum(ReadOptions());
+        write_status(s, status);
+    }
+
     DB *get_base_db() const {
         return db->GetBaseDB();
     }
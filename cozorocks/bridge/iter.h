@@ -20,10 +20,14 @@ struct IterBridge {
     Slice lower_bound;
     Slice upper_bound;
     unique_ptr<ReadOptions> r_opts;
-
-    explicit IterBridge(Transaction *tx_) : db(nullptr), tx(tx_), iter(nullptr), lower_bound(),
-                                                                     upper_bound(),
-                                                                     r_opts(new ReadOptions) {
+    // the default column family if null
+    ColumnFamilyHandle *cf;
+
+    explicit IterBridge(Transaction *tx_, ColumnFamilyHandle *cf_ = nullptr) : db(nullptr), tx(tx_), iter(nullptr),
+                                                                               lower_bound(),
+                                                                               upper_bound(),
+                                                                               r_opts(new ReadOptions),
+                                                                               cf(cf_) {
         r_opts->ignore_range_deletions = true;
         r_opts->auto_prefix_mode = true;
     }
@@ -85,7 +89,11 @@ struct IterBridge {
 
     inline void start() {
         if (db == nullptr) {
-            iter.reset(tx->GetIterator(*r_opts));
+            if (cf == nullptr) {
+                iter.reset(tx->GetIterator(*r_opts));
+            } else {
+                iter.reset(tx->GetIterator(*r_opts, cf));
+            }
         } else {
             iter.reset(db->NewIterator(*r_opts));
         }
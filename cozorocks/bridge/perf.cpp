@@ -0,0 +1,24 @@
+// Copyright 2023, The Cozo Project Authors.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file,
+// You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#include "perf.h"
+#include "cozorocks/src/bridge/mod.rs.h"
+
+PerfCounters read_perf_counters() {
+    // the perf context and its level are both local to the calling thread
+    if (GetPerfLevel() < PerfLevel::kEnableCount) {
+        SetPerfLevel(PerfLevel::kEnableCount);
+    }
+    const PerfContext *ctx = get_perf_context();
+    PerfCounters ret{};
+    ret.bytes_read = ctx->get_read_bytes + ctx->iter_read_bytes;
+    ret.block_cache_hits = ctx->block_cache_hit_count;
+    ret.block_reads = ctx->block_read_count;
+    ret.block_read_bytes = ctx->block_read_byte;
+    ret.bloom_filter_hits = ctx->bloom_sst_hit_count;
+    ret.bloom_filter_misses = ctx->bloom_sst_miss_count;
+    return ret;
+}
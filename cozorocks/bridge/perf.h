@@ -0,0 +1,18 @@
+// Copyright 2023, The Cozo Project Authors.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file,
+// You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#ifndef COZOROCKS_PERF_H
+#define COZOROCKS_PERF_H
+
+#include "common.h"
+#include "rocksdb/perf_context.h"
+#include "rocksdb/perf_level.h"
+
+struct PerfCounters;
+
+PerfCounters read_perf_counters();
+
+#endif //COZOROCKS_PERF_H
@@ -16,4 +16,50 @@ void TxBridge::start() {
         tx.reset(txn);
     }
     assert(tx);
-}
\ No newline at end of file
+}
+static Status unknown_cf(size_t cf) {
+    return Status::InvalidArgument("unknown column family " + std::to_string(cf));
+}
+
+unique_ptr<PinnableSlice> TxBridge::get_cf(size_t cf, RustBytes key, bool for_update, RocksDbStatus &status) const {
+    auto ret = make_unique<PinnableSlice>();
+    auto handle = bridge->get_cf(cf);
+    if (handle == nullptr) {
+        write_status(unknown_cf(cf), status);
+        return ret;
+    }
+    Slice key_ = convert_slice(key);
+    if (for_update) {
+        write_status(tx->GetForUpdate(*r_opts, handle, key_, &*ret), status);
+    } else {
+        write_status(tx->Get(*r_opts, handle, key_, &*ret), status);
+    }
+    return ret;
+}
+
+void TxBridge::exists_cf(size_t cf, RustBytes key, bool for_update, RocksDbStatus &status) const {
+    get_cf(cf, key, for_update, status);
+}
+
+void TxBridge::put_cf(size_t cf, RustBytes key, RustBytes val, RocksDbStatus &status) const {
+    auto handle = bridge->get_cf(cf);
+    if (handle == nullptr) {
+        write_status(unknown_cf(cf), status);
+        return;
+    }
+    write_status(tx->Put(handle, convert_slice(key), convert_slice(val)), status);
+}
+
+void TxBridge::del_cf(size_t cf, RustBytes key, RocksDbStatus &status) const {
+    auto handle = bridge->get_cf(cf);
+    if (handle == nullptr) {
+        write_status(unknown_cf(cf), status);
+        return;
+    }
+    write_status(tx->Delete(handle, convert_slice(key)), status);
+}
+
+unique_ptr<IterBridge> TxBridge::iterator_cf(size_t cf) const {
+    // the column family is checked by the caller: a null one would read the default
+    return make_unique<IterBridge>(&*tx, bridge->get_cf(cf));
+}
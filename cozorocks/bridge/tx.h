@@ -12,6 +12,8 @@
 #include "status.h"
 #include "iter.h"
 
+struct RocksDbBridge;
+
 struct TxBridge {
     OptimisticTransactionDB *odb;
     TransactionDB *tdb;
@@ -21,8 +23,10 @@ struct TxBridge {
     unique_ptr<OptimisticTransactionOptions> o_tx_opts;
     unique_ptr<TransactionOptions> p_tx_opts;
     ColumnFamilyHandle * cf_handle;
+    // for the other column families
+    const RocksDbBridge *bridge;
 
-    explicit TxBridge(TransactionDB *tdb_, ColumnFamilyHandle * cf_handle_) :
+    explicit TxBridge(TransactionDB *tdb_, ColumnFamilyHandle * cf_handle_, const RocksDbBridge *bridge_) :
             odb(nullptr),
             tdb(tdb_),
             tx(),
@@ -30,7 +34,8 @@ struct TxBridge {
             r_opts(new ReadOptions),
             o_tx_opts(nullptr),
             p_tx_opts(new TransactionOptions),
-            cf_handle(cf_handle_) {
+            cf_handle(cf_handle_),
+            bridge(bridge_) {
         r_opts->ignore_range_deletions = true;
     }
 
@@ -113,6 +118,16 @@ struct TxBridge {
         write_status(tx->Delete(convert_slice(key)), status);
     }
 
+    unique_ptr<PinnableSlice> get_cf(size_t cf, RustBytes key, bool for_update, RocksDbStatus &status) const;
+
+    void exists_cf(size_t cf, RustBytes key, bool for_update, RocksDbStatus &status) const;
+
+    void put_cf(size_t cf, RustBytes key, RustBytes val, RocksDbStatus &status) const;
+
+    void del_cf(size_t cf, RustBytes key, RocksDbStatus &status) const;
+
+    [[nodiscard]] unique_ptr<IterBridge> iterator_cf(size_t cf) const;
+
     inline void commit(RocksDbStatus &status) {
         write_status(tx->Commit(), status);
     }
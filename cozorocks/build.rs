@@ -15,7 +15,12 @@ fn main() {
 
     let mut builder = cxx_build::bridge("src/bridge/mod.rs");
     builder
-        .files(["bridge/status.cpp", "bridge/db.cpp", "bridge/tx.cpp"])
+        .files([
+            "bridge/status.cpp",
+            "bridge/db.cpp",
+            "bridge/tx.cpp",
+            "bridge/perf.cpp",
+        ])
         .include(rocksdb_include_dir())
         .include("bridge");
     if target.contains("msvc") {
@@ -85,6 +90,8 @@ fn main() {
     println!("cargo:rerun-if-changed=bridge/iter.h");
     println!("cargo:rerun-if-changed=bridge/tx.h");
     println!("cargo:rerun-if-changed=bridge/tx.cpp");
+    println!("cargo:rerun-if-changed=bridge/perf.h");
+    println!("cargo:rerun-if-changed=bridge/perf.cpp");
 
     if !try_to_find_and_link_lib("ROCKSDB") {
         println!("cargo:rerun-if-changed=rocksdb/");
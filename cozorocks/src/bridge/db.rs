@@ -177,6 +177,78 @@ impl RocksDb {
             Err(status)
         }
     }
+    #[inline]
+    pub fn flush(&self) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.flush(&mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    /// Read all data in the database and verify it against the checksums of its blocks.
+    /// Corrupted blocks are reported as an error.
+    #[inline]
+    pub fn verify_checksum(&self) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.verify_checksum(&mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    /// Change an option of the open database, given by its name and value as in options files.
+    /// Options of the whole database, such as `max_background_jobs`, need `db_wide`,
+    /// those of column families do not.
+    pub fn set_option(&self, name: &str, value: &str, db_wide: bool) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.set_option(name, value, db_wide, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    pub fn set_block_cache_capacity(&self, capacity: usize) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.set_block_cache_capacity(capacity, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    pub fn config(&self) -> RocksDbConfig {
+        self.inner.get_config()
+    }
+    /// The names of the column families by index, empty for dropped ones.
+    /// The default column family is among them.
+    pub fn column_families(&self) -> Vec<String> {
+        self.inner.column_families()
+    }
+    /// Create a column family tuned as the default one, returning its index
+    pub fn create_column_family(&self, name: &str) -> Result<usize, RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        let ret = self.inner.create_column_family(name, &mut status);
+        if status.is_ok() {
+            Ok(ret)
+        } else {
+            Err(status)
+        }
+    }
+    /// Drop a column family by index. Its data can still be read until the database is closed,
+    /// but not written.
+    pub fn drop_column_family(&self, idx: usize) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.drop_column_family(idx, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
     pub fn get_sst_writer(&self, path: &str) -> Result<SstWriter, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
         let ret = self.inner.get_sst_writer(path, &mut status);
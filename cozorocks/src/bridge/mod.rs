@@ -15,6 +15,7 @@ use crate::StatusSeverity;
 
 pub(crate) mod db;
 pub(crate) mod iter;
+pub(crate) mod perf;
 pub(crate) mod tx;
 
 #[cxx::bridge]
@@ -43,6 +44,34 @@ pub(crate) mod ffi {
         pub block_cache_size: usize,
     }
 
+    /// Options of an open database that can be changed at runtime, and the compaction style
+    #[derive(Clone, Debug, Default)]
+    pub struct RocksDbConfig {
+        pub block_cache_capacity: usize,
+        pub write_buffer_size: usize,
+        pub max_write_buffer_number: i32,
+        pub max_background_jobs: i32,
+        pub level0_file_num_compaction_trigger: i32,
+        pub disable_auto_compactions: bool,
+        /// Fixed when the database is opened
+        pub compaction_style: String,
+    }
+
+    /// Counters of the RocksDB perf context of a thread, see [crate::perf_counters]
+    #[derive(Copy, Clone, Debug, Default)]
+    pub struct PerfCounters {
+        /// Bytes of keys and values read by gets and iterators
+        pub bytes_read: u64,
+        pub block_cache_hits: u64,
+        /// Blocks read from the files, that were not found in the block cache
+        pub block_reads: u64,
+        pub block_read_bytes: u64,
+        /// Times a bloom filter let a lookup go on into its file
+        pub bloom_filter_hits: u64,
+        /// Times a bloom filter spared a lookup the reading of its file
+        pub bloom_filter_misses: u64,
+    }
+
     #[derive(Clone, Debug, Eq, PartialEq)]
     pub struct RocksDbStatus {
         pub code: StatusCode,
@@ -114,6 +143,8 @@ pub(crate) mod ffi {
         type PinnableSlice;
         fn convert_pinnable_slice_back(s: &PinnableSlice) -> &[u8];
 
+        fn read_perf_counters() -> PerfCounters;
+
         fn set_w_opts_sync(o: Pin<&mut WriteOptions>, val: bool);
         fn set_w_opts_disable_wal(o: Pin<&mut WriteOptions>, val: bool);
         fn set_w_opts_no_slowdown(o: Pin<&mut WriteOptions>, val: bool);
@@ -128,6 +159,28 @@ pub(crate) mod ffi {
         fn transact(self: &RocksDbBridge) -> UniquePtr<TxBridge>;
         fn del_range(self: &RocksDbBridge, lower: &[u8], upper: &[u8], status: &mut RocksDbStatus);
         fn put(self: &RocksDbBridge, key: &[u8], val: &[u8], status: &mut RocksDbStatus);
+        fn flush(self: &RocksDbBridge, status: &mut RocksDbStatus);
+        fn verify_checksum(self: &RocksDbBridge, status: &mut RocksDbStatus);
+        fn set_option(
+            self: &RocksDbBridge,
+            name: &str,
+            value: &str,
+            db_wide: bool,
+            status: &mut RocksDbStatus,
+        );
+        fn set_block_cache_capacity(
+            self: &RocksDbBridge,
+            capacity: usize,
+            status: &mut RocksDbStatus,
+        );
+        fn get_config(self: &RocksDbBridge) -> RocksDbConfig;
+        fn column_families(self: &RocksDbBridge) -> Vec<String>;
+        fn create_column_family(
+            self: &RocksDbBridge,
+            name: &str,
+            status: &mut RocksDbStatus,
+        ) -> usize;
+        fn drop_column_family(self: &RocksDbBridge, idx: usize, status: &mut RocksDbStatus);
         fn compact_range(
             self: &RocksDbBridge,
             lower: &[u8],
@@ -173,6 +226,23 @@ pub(crate) mod ffi {
         fn pop_savepoint(self: Pin<&mut TxBridge>, status: &mut RocksDbStatus);
         fn set_savepoint(self: Pin<&mut TxBridge>);
         fn iterator(self: &TxBridge) -> UniquePtr<IterBridge>;
+        fn get_cf(
+            self: &TxBridge,
+            cf: usize,
+            key: &[u8],
+            for_update: bool,
+            status: &mut RocksDbStatus,
+        ) -> UniquePtr<PinnableSlice>;
+        fn exists_cf(
+            self: &TxBridge,
+            cf: usize,
+            key: &[u8],
+            for_update: bool,
+            status: &mut RocksDbStatus,
+        );
+        fn put_cf(self: &TxBridge, cf: usize, key: &[u8], val: &[u8], status: &mut RocksDbStatus);
+        fn del_cf(self: &TxBridge, cf: usize, key: &[u8], status: &mut RocksDbStatus);
+        fn iterator_cf(self: &TxBridge, cf: usize) -> UniquePtr<IterBridge>;
 
         type IterBridge;
         fn start(self: Pin<&mut IterBridge>);
@@ -0,0 +1,41 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::bridge::ffi::*;
+
+/// The counters of the RocksDB perf context of the current thread.
+///
+/// Counting starts on a thread the first time this is called on it, so the work done by
+/// an operation is the difference between the counters taken before and after it,
+/// on the thread it runs on, as given by [PerfCounters::since].
+#[inline]
+pub fn perf_counters() -> PerfCounters {
+    read_perf_counters()
+}
+
+impl PerfCounters {
+    /// The work counted since `earlier` was taken on the same thread
+    pub fn since(&self, earlier: &PerfCounters) -> PerfCounters {
+        PerfCounters {
+            bytes_read: self.bytes_read.saturating_sub(earlier.bytes_read),
+            block_cache_hits: self
+                .block_cache_hits
+                .saturating_sub(earlier.block_cache_hits),
+            block_reads: self.block_reads.saturating_sub(earlier.block_reads),
+            block_read_bytes: self
+                .block_read_bytes
+                .saturating_sub(earlier.block_read_bytes),
+            bloom_filter_hits: self
+                .bloom_filter_hits
+                .saturating_sub(earlier.bloom_filter_hits),
+            bloom_filter_misses: self
+                .bloom_filter_misses
+                .saturating_sub(earlier.bloom_filter_misses),
+        }
+    }
+}
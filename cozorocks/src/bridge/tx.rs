@@ -188,4 +188,62 @@ impl Tx {
         }
         .auto_prefix_mode(true)
     }
+    #[inline]
+    pub fn put_cf(&self, cf: usize, key: &[u8], val: &[u8]) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.put_cf(cf, key, val, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    #[inline]
+    pub fn del_cf(&self, cf: usize, key: &[u8]) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.del_cf(cf, key, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    #[inline]
+    pub fn get_cf(
+        &self,
+        cf: usize,
+        key: &[u8],
+        for_update: bool,
+    ) -> Result<Option<PinSlice>, RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        let ret = self.inner.get_cf(cf, key, for_update, &mut status);
+        match status.code {
+            StatusCode::kOk => Ok(Some(PinSlice { inner: ret })),
+            StatusCode::kNotFound => Ok(None),
+            _ => Err(status),
+        }
+    }
+    #[inline]
+    pub fn exists_cf(
+        &self,
+        cf: usize,
+        key: &[u8],
+        for_update: bool,
+    ) -> Result<bool, RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.exists_cf(cf, key, for_update, &mut status);
+        match status.code {
+            StatusCode::kOk => Ok(true),
+            StatusCode::kNotFound => Ok(false),
+            _ => Err(status),
+        }
+    }
+    /// An iterator over a column family, which must be one of the database
+    #[inline]
+    pub fn iterator_cf(&self, cf: usize) -> IterBuilder {
+        IterBuilder {
+            inner: self.inner.iterator_cf(cf),
+        }
+        .auto_prefix_mode(true)
+    }
 }
@@ -11,6 +11,8 @@
 
 pub use bridge::db::DbBuilder;
 pub use bridge::db::RocksDb;
+pub use bridge::ffi::PerfCounters;
+pub use bridge::ffi::RocksDbConfig;
 pub use bridge::ffi::RocksDbStatus;
 pub use bridge::ffi::SnapshotBridge;
 pub use bridge::ffi::StatusCode;
@@ -18,6 +20,7 @@ pub use bridge::ffi::StatusSeverity;
 pub use bridge::ffi::StatusSubCode;
 pub use bridge::iter::DbIter;
 pub use bridge::iter::IterBuilder;
+pub use bridge::perf::perf_counters;
 pub use bridge::tx::PinSlice;
 pub use bridge::tx::Tx;
 pub use bridge::tx::TxBuilder;